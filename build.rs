@@ -0,0 +1,28 @@
+//! Generates the C header of the `ffi` feature
+
+fn main() {
+    #[cfg(feature = "ffi")]
+    generate_header();
+}
+
+/// Writes the header to OUT_DIR; the committed `include/ffb_replay.h` is only
+/// replaced when FFB_REPLAY_WRITE_HEADER=1, so builds never touch the source tree
+#[cfg(feature = "ffi")]
+fn generate_header() {
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR is set by cargo");
+    let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR is set by cargo");
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=FFB_REPLAY_WRITE_HEADER");
+
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)).expect("cbindgen.toml is valid");
+    let bindings = cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("C header generation failed");
+    bindings.write_to_file(format!("{}/ffb_replay.h", out_dir));
+    if std::env::var("FFB_REPLAY_WRITE_HEADER").is_ok_and(|value| value == "1") {
+        bindings.write_to_file(format!("{}/include/ffb_replay.h", crate_dir));
+    }
+}
@@ -0,0 +1,147 @@
+#ifndef FFB_REPLAY_H
+#define FFB_REPLAY_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+// Result of an FFI call
+typedef enum FfbStatus {
+  FFB_STATUS_OK = 0,
+  // Compared runs differ
+  FFB_STATUS_MISMATCH = 1,
+  // Device, driver or environment setup failed
+  FFB_STATUS_SETUP_ERROR = 2,
+  // Scenario file is missing or invalid
+  FFB_STATUS_BAD_SCENARIO = 3,
+  // A null pointer, bad index or non-UTF-8 string was passed
+  FFB_STATUS_INVALID_ARGUMENT = 4,
+} FfbStatus;
+
+// An initialized driver
+typedef struct FfbDriverHandle FfbDriverHandle;
+
+// Step outputs of a played run or a capture file
+typedef struct FfbRun FfbRun;
+
+// A loaded scenario
+typedef struct FfbScenario FfbScenario;
+
+// How `ffb_compare` compares runs; NULL means exact packet comparison
+typedef struct FfbCompareOptions {
+  // Decode both sides to canonical commands first
+  bool normalize;
+  // Compare only the number of packets per step
+  bool counts_only;
+  // Allowed packet count difference per step with `counts_only`
+  size_t tolerance;
+} FfbCompareOptions;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Message of the last failed call on this thread, NULL if it succeeded
+// Valid until the next call on this thread.
+const char *ffb_last_error(void);
+
+// Create and initialize a driver by name ("sdl", "simagic")
+// With `capture`, the device's USB traffic is captured with default capture settings.
+//
+// # Safety
+// `name` must be a NUL-terminated string and `out` a valid pointer.
+enum FfbStatus ffb_driver_create(const char *name, bool capture, struct FfbDriverHandle **out);
+
+// Name of the driver
+//
+// # Safety
+// `driver` must come from `ffb_driver_create` and not be freed.
+const char *ffb_driver_name(const struct FfbDriverHandle *driver);
+
+// Shut the driver down and free it; NULL is ignored
+//
+// # Safety
+// `driver` must come from `ffb_driver_create` and not be used afterwards.
+enum FfbStatus ffb_driver_free(struct FfbDriverHandle *driver);
+
+// Load a scenario from a YAML file
+//
+// # Safety
+// `path` must be a NUL-terminated string and `out` a valid pointer.
+enum FfbStatus ffb_scenario_load(const char *path, struct FfbScenario **out);
+
+// Free a scenario; NULL is ignored
+//
+// # Safety
+// `scenario` must come from `ffb_scenario_load` and not be used afterwards.
+void ffb_scenario_free(struct FfbScenario *scenario);
+
+// Play `scenario` on `driver`, blocking until it finishes
+// Steps whose effect fails get no packets, as in the CLI.
+//
+// # Safety
+// `driver` and `scenario` must be live handles and `out` a valid pointer.
+enum FfbStatus ffb_play(struct FfbDriverHandle *driver,
+                        const struct FfbScenario *scenario,
+                        struct FfbRun **out);
+
+// Load a capture file written by `ffb_run_save` or the CLI's `record`
+//
+// # Safety
+// `path` must be a NUL-terminated string and `out` a valid pointer.
+enum FfbStatus ffb_run_load(const char *path, struct FfbRun **out);
+
+// Save a run as a capture file
+//
+// # Safety
+// `run` must be a live handle and `path` a NUL-terminated string.
+enum FfbStatus ffb_run_save(const struct FfbRun *run, const char *path);
+
+// Number of steps in the run, 0 for NULL
+//
+// # Safety
+// `run` must be a live handle or NULL.
+size_t ffb_run_step_count(const struct FfbRun *run);
+
+// Effect name of a step, NULL if `step` is out of range
+//
+// # Safety
+// `run` must be a live handle or NULL.
+const char *ffb_run_step_name(const struct FfbRun *run, size_t step);
+
+// Number of packets captured in a step, 0 if `step` is out of range
+//
+// # Safety
+// `run` must be a live handle or NULL.
+size_t ffb_run_packet_count(const struct FfbRun *run, size_t step);
+
+// A packet of a step as hex bytes ("01 0a ff"), NULL if out of range
+//
+// # Safety
+// `run` must be a live handle or NULL.
+const char *ffb_run_packet(const struct FfbRun *run, size_t step, size_t packet);
+
+// Free a run; NULL is ignored
+//
+// # Safety
+// `run` must come from `ffb_play` or `ffb_run_load` and not be used afterwards.
+void ffb_run_free(struct FfbRun *run);
+
+// Compare `actual` with `expected` step by step
+// Returns `FFB_STATUS_OK` if all steps match, `FFB_STATUS_MISMATCH` otherwise, with the number of
+// differing steps in `mismatched` unless it is NULL.
+//
+// # Safety
+// `expected` and `actual` must be live handles, `options` and `mismatched` valid or NULL.
+enum FfbStatus ffb_compare(const struct FfbRun *expected,
+                           const struct FfbRun *actual,
+                           const struct FfbCompareOptions *options,
+                           size_t *mismatched);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* FFB_REPLAY_H */
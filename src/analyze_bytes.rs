@@ -0,0 +1,314 @@
+//! Correlating byte changes with a parameter across runs
+//!
+//! The `analyze-bytes` command takes captures of the same scenario recorded while
+//! varying one parameter (a magnitude, a duration, ...) and finds the packet bytes
+//! that follow it. Varying bytes are read as little- or big-endian, signed or
+//! unsigned fields of 1, 2 or 4 bytes, and each field is fitted against the
+//! parameter with a straight line, which shows scalings like `magnitude / 3.28`
+//! directly.
+
+use crate::decode::resolve_file;
+use crate::normalize::decode_packet;
+use crate::{compare::parse_hex, parse_capture_file, StepOutput};
+use serde::Serialize;
+
+/// Fits at least this good are reported as a relation
+const MIN_R_SQUARED: f64 = 0.99;
+/// Fits closer than this count as equally good; the field covering more varying
+/// bytes wins, then the narrower one
+const R_SQUARED_TIE: f64 = 1e-6;
+
+/// A field that changes with the parameter
+#[derive(Debug, Serialize)]
+pub struct ByteFinding {
+    pub step: usize,
+    /// 1-based position of the packet in the step
+    pub packet: usize,
+    /// Decoded command of the packet in the first run
+    pub command: Option<String>,
+    /// 0-based offset of the field in the packet
+    pub offset: usize,
+    pub width: usize,
+    /// e.g. "u8", "i16le", "u32be"
+    pub encoding: String,
+    /// Field value in each run, in parameter order
+    pub values: Vec<i64>,
+    /// Field value ≈ slope × parameter + intercept
+    pub slope: f64,
+    pub intercept: f64,
+    pub r_squared: f64,
+}
+
+/// Result of an analysis, as listed in JSON output
+#[derive(Debug, Serialize)]
+pub struct Analysis {
+    /// Parameter value of each run, ascending
+    pub parameters: Vec<f64>,
+    pub findings: Vec<ByteFinding>,
+    /// Packets that could not be lined up across the runs
+    pub skipped: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Encoding {
+    width: usize,
+    big_endian: bool,
+    signed: bool,
+}
+
+impl Encoding {
+    fn name(&self) -> String {
+        let sign = if self.signed { 'i' } else { 'u' };
+        let endian = match (self.width, self.big_endian) {
+            (1, _) => "",
+            (_, true) => "be",
+            (_, false) => "le",
+        };
+        format!("{}{}{}", sign, self.width * 8, endian)
+    }
+
+    fn read(&self, bytes: &[u8]) -> i64 {
+        let value = if self.big_endian {
+            bytes.iter().fold(0u64, |v, &b| (v << 8) | b as u64)
+        } else {
+            bytes.iter().rev().fold(0u64, |v, &b| (v << 8) | b as u64)
+        };
+        let bits = self.width as u32 * 8;
+        if self.signed && value & (1 << (bits - 1)) != 0 {
+            value as i64 - (1i64 << bits)
+        } else {
+            value as i64
+        }
+    }
+}
+
+/// Encodings tried for a field, narrowest first
+fn encodings() -> impl Iterator<Item = Encoding> {
+    [1, 2, 4].into_iter().flat_map(|width| {
+        let endians: &[bool] = if width == 1 { &[false] } else { &[false, true] };
+        endians.iter().flat_map(move |&big_endian| {
+            [false, true].map(|signed| Encoding {
+                width,
+                big_endian,
+                signed,
+            })
+        })
+    })
+}
+
+/// Least-squares line through the points: (slope, intercept, R²)
+fn fit(xs: &[f64], ys: &[f64]) -> (f64, f64, f64) {
+    let n = xs.len() as f64;
+    let mean_x = xs.iter().sum::<f64>() / n;
+    let mean_y = ys.iter().sum::<f64>() / n;
+    let cov: f64 = xs.iter().zip(ys).map(|(x, y)| (x - mean_x) * (y - mean_y)).sum();
+    let var_x: f64 = xs.iter().map(|x| (x - mean_x).powi(2)).sum();
+    let var_y: f64 = ys.iter().map(|y| (y - mean_y).powi(2)).sum();
+    let slope = cov / var_x;
+    let r_squared = if var_y == 0.0 { 0.0 } else { cov * cov / (var_x * var_y) };
+    (slope, mean_y - slope * mean_x, r_squared)
+}
+
+/// Parse "VALUE=CAPTURE" run arguments and load the captures
+fn load_runs(runs: &[String]) -> anyhow::Result<Vec<(f64, Vec<StepOutput>)>> {
+    let mut loaded = Vec::new();
+    for run in runs {
+        let (value, capture) = run
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expected VALUE=CAPTURE, got '{}'", run))?;
+        let value: f64 = value
+            .trim()
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid parameter value '{}' in '{}'", value, run))?;
+        let path = resolve_file(capture).ok_or_else(|| anyhow::anyhow!("Capture file not found: {}", capture))?;
+        loaded.push((value, parse_capture_file(&path)?));
+    }
+    loaded.sort_by(|a, b| a.0.total_cmp(&b.0));
+    if loaded.windows(2).all(|pair| pair[0].0 == pair[1].0) {
+        anyhow::bail!("The runs need at least two different parameter values");
+    }
+    Ok(loaded)
+}
+
+/// Analyze the runs, print the findings and return them
+/// Only `step` is analyzed when given
+pub fn run(runs: &[String], step: Option<usize>) -> anyhow::Result<Analysis> {
+    let runs = load_runs(runs)?;
+    let parameters: Vec<f64> = runs.iter().map(|(value, _)| *value).collect();
+    let mut findings = Vec::new();
+    let mut skipped = Vec::new();
+
+    let (_, first) = &runs[0];
+    for first_step in first.iter().filter(|s| step.is_none_or(|n| s.step_index == n)) {
+        let steps: Vec<&StepOutput> = runs
+            .iter()
+            .filter_map(|(_, steps)| steps.iter().find(|s| s.step_index == first_step.step_index))
+            .collect();
+        if steps.len() < runs.len() {
+            skipped.push(format!("Step {}: missing in some runs", first_step.step_index));
+            continue;
+        }
+        let counts: Vec<usize> = steps.iter().map(|s| s.packets.len()).collect();
+        if counts.iter().any(|&count| count != counts[0]) {
+            skipped.push(format!(
+                "Step {}: packet counts differ ({}), comparing the first {}",
+                first_step.step_index,
+                counts.iter().map(usize::to_string).collect::<Vec<_>>().join(", "),
+                counts.iter().min().unwrap()
+            ));
+        }
+
+        for index in 0..*counts.iter().min().unwrap() {
+            let Some(packets) = steps
+                .iter()
+                .map(|s| parse_hex(&s.packets[index]))
+                .collect::<Option<Vec<_>>>()
+            else {
+                skipped.push(format!("Step {} packet {}: not plain hex", first_step.step_index, index + 1));
+                continue;
+            };
+            if packets.iter().any(|p| p.len() != packets[0].len()) {
+                skipped.push(format!("Step {} packet {}: lengths differ", first_step.step_index, index + 1));
+                continue;
+            }
+            let command = decode_packet(&packets[0]);
+            for field in analyze_packet(&parameters, &packets) {
+                findings.push(ByteFinding {
+                    step: first_step.step_index,
+                    packet: index + 1,
+                    command: command.clone(),
+                    ..field
+                });
+            }
+        }
+    }
+
+    print_analysis(&parameters, &findings, &skipped);
+    Ok(Analysis {
+        parameters,
+        findings,
+        skipped,
+    })
+}
+
+/// Fields of one packet position that differ across the runs
+/// Varying bytes are covered left to right by the best fitting field containing them
+fn analyze_packet(parameters: &[f64], packets: &[Vec<u8>]) -> Vec<ByteFinding> {
+    let len = packets[0].len();
+    let varies = |offset: usize| packets.iter().any(|p| p[offset] != packets[0][offset]);
+    let mut findings = Vec::new();
+    let mut covered = 0;
+
+    for offset in 0..len {
+        if offset < covered || !varies(offset) {
+            continue;
+        }
+        let best = encodings()
+            .flat_map(|encoding| {
+                let first_start = (offset + 1).saturating_sub(encoding.width).max(covered);
+                (first_start..=offset)
+                    .filter(move |start| start + encoding.width <= len)
+                    .map(move |start| (start, encoding))
+            })
+            .map(|(start, encoding)| {
+                let values: Vec<i64> = packets
+                    .iter()
+                    .map(|p| encoding.read(&p[start..start + encoding.width]))
+                    .collect();
+                let ys: Vec<f64> = values.iter().map(|&v| v as f64).collect();
+                let (slope, intercept, r_squared) = fit(parameters, &ys);
+                let varying = (start..start + encoding.width).filter(|&o| varies(o)).count();
+                let finding = ByteFinding {
+                    step: 0,
+                    packet: 0,
+                    command: None,
+                    offset: start,
+                    width: encoding.width,
+                    encoding: encoding.name(),
+                    values,
+                    slope,
+                    intercept,
+                    r_squared,
+                };
+                (finding, varying)
+            })
+            .fold(None::<(ByteFinding, usize)>, |best, candidate| match best {
+                Some(best)
+                    if best.0.r_squared > candidate.0.r_squared + R_SQUARED_TIE
+                        || (best.0.r_squared + R_SQUARED_TIE >= candidate.0.r_squared && best.1 >= candidate.1) =>
+                {
+                    Some(best)
+                }
+                _ => Some(candidate),
+            });
+        if let Some((best, _)) = best {
+            covered = best.offset + best.width;
+            findings.push(best);
+        }
+    }
+    findings
+}
+
+/// The fitted relation, e.g. "parameter / 3.28 + 12"
+fn relation(finding: &ByteFinding) -> String {
+    let mut text = if finding.slope.abs() < 1.0 && finding.slope != 0.0 {
+        format!("parameter / {}", number(1.0 / finding.slope))
+    } else {
+        format!("parameter × {}", number(finding.slope))
+    };
+    if finding.intercept.abs() >= 0.5 {
+        let sign = if finding.intercept < 0.0 { '-' } else { '+' };
+        text.push_str(&format!(" {} {}", sign, number(finding.intercept.abs())));
+    }
+    text
+}
+
+/// Up to 3 decimals, without trailing zeros
+fn number(value: f64) -> String {
+    let text = format!("{:.3}", value);
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+fn print_analysis(parameters: &[f64], findings: &[ByteFinding], skipped: &[String]) {
+    let values: Vec<String> = parameters.iter().map(|&p| number(p)).collect();
+    println!("Parameter: {}", values.join(", "));
+    for finding in findings {
+        let bytes = if finding.width == 1 {
+            format!("byte {}", finding.offset)
+        } else {
+            format!("bytes {}-{}", finding.offset, finding.offset + finding.width - 1)
+        };
+        let command = finding
+            .command
+            .as_deref()
+            .and_then(|c| c.split_whitespace().next())
+            .map(|name| format!(" ({})", name))
+            .unwrap_or_default();
+        let values: Vec<String> = finding.values.iter().map(i64::to_string).collect();
+        let relation = if finding.r_squared >= MIN_R_SQUARED {
+            format!("≈ {} (R² {:.4})", relation(finding), finding.r_squared)
+        } else {
+            format!("no linear relation (R² {:.4})", finding.r_squared)
+        };
+        println!(
+            "Step {} packet {}{} {} {}: {} {}",
+            finding.step,
+            finding.packet,
+            command,
+            bytes,
+            finding.encoding,
+            values.join(", "),
+            relation
+        );
+    }
+    if findings.is_empty() {
+        println!("No bytes change with the parameter");
+    }
+    for note in skipped {
+        println!("Skipped {}", note);
+    }
+    let distinct = parameters.windows(2).filter(|pair| pair[0] != pair[1]).count() + 1;
+    if distinct < 3 && !findings.is_empty() {
+        println!("Note: two parameter values fit any line; record a third to confirm the relations");
+    }
+}
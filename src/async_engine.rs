@@ -0,0 +1,278 @@
+//! Async playback on tokio (`async` feature)
+//!
+//! Drivers are not thread-safe (SDL's haptic handle stays on the thread that opened
+//! it), so an `AsyncDriver` owns its driver on a dedicated thread and runs every
+//! driver call there. Effect durations are awaited with `tokio::time` between
+//! `FfbDriver::begin_effect` and `finish_effect` instead of sleeping on a thread, so
+//! long effects don't block the runtime and several devices can be driven
+//! concurrently from one runtime:
+//!
+//! ```no_run
+//! # async fn run(scenario: ffb_replay::Scenario) -> anyhow::Result<()> {
+//! use ffb_replay::async_engine::AsyncDriver;
+//!
+//! let wheel = AsyncDriver::create("sdl", Some(Default::default())).await?;
+//! let reference = AsyncDriver::create("simagic", None).await?;
+//! wheel.initialize().await?;
+//! reference.initialize().await?;
+//! let (actual, expected) = tokio::join!(wheel.play(&scenario), reference.play(&scenario));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Capture backends keep their reader threads; `AsyncDriver::live_packets` forwards
+//! their packets to an async channel from a blocking task.
+
+use crate::cancel::CancellationToken;
+use crate::driver::{EffectProgress, FfbDriver};
+use crate::effects::Effect;
+use crate::error::{FFBError, FFBResult};
+use crate::schedule::{Event, Schedule, TimerResolution, TimingMode};
+use crate::usb_monitor::{CaptureConfig, UsbPacket};
+use crate::scenario::{send_step_commands, with_sent, HeldEffects};
+use crate::{cadence, create_driver, effect_label, Scenario, StepOutput};
+use std::sync::mpsc;
+use std::thread;
+use tokio::sync::{mpsc as async_mpsc, oneshot};
+
+/// A driver call queued for the driver thread
+type Job = Box<dyn FnOnce(&mut dyn FfbDriver) + Send>;
+
+/// Handle to a driver running on its own thread; dropping it shuts the thread down
+pub struct AsyncDriver {
+    jobs: mpsc::Sender<Job>,
+    name: String,
+}
+
+fn stopped() -> FFBError {
+    FFBError::DeviceError("Driver thread stopped".to_string())
+}
+
+impl AsyncDriver {
+    /// Start a driver thread with the driver built by `factory`, on that thread
+    pub async fn spawn<F>(factory: F) -> anyhow::Result<Self>
+    where
+        F: FnOnce() -> anyhow::Result<Box<dyn FfbDriver>> + Send + 'static,
+    {
+        let (jobs, queued) = mpsc::channel::<Job>();
+        let (ready, created) = oneshot::channel();
+        thread::Builder::new().name("ffb-driver".to_string()).spawn(move || {
+            let mut driver = match factory() {
+                Ok(driver) => driver,
+                Err(e) => {
+                    let _ = ready.send(Err(e));
+                    return;
+                }
+            };
+            let _ = ready.send(Ok(driver.name().to_string()));
+            for job in queued {
+                job(driver.as_mut());
+            }
+        })?;
+        let name = created.await.map_err(|_| stopped())??;
+        Ok(Self { jobs, name })
+    }
+
+    /// Driver by name, as with `create_driver`
+    pub async fn create(driver_name: &str, capture: Option<CaptureConfig>) -> anyhow::Result<Self> {
+        let driver_name = driver_name.to_string();
+        Self::spawn(move || create_driver(&driver_name, capture.as_ref())).await
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Run `call` with the driver on its thread
+    pub async fn call<R, F>(&self, call: F) -> FFBResult<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut dyn FfbDriver) -> R + Send + 'static,
+    {
+        let (reply, result) = oneshot::channel();
+        self.jobs
+            .send(Box::new(move |driver| {
+                let _ = reply.send(call(driver));
+            }))
+            .map_err(|_| stopped())?;
+        result.await.map_err(|_| stopped())
+    }
+
+    pub async fn initialize(&self) -> FFBResult<()> {
+        self.call(|driver| driver.initialize()).await?
+    }
+
+    /// Apply an effect, awaiting its duration and returning the captured packets
+    pub async fn apply_effect(&self, effect: &Effect) -> FFBResult<Vec<String>> {
+        let effect = effect.clone();
+        match self.call(move |driver| driver.begin_effect(&effect)).await?? {
+            EffectProgress::Done(packets) => Ok(packets),
+            EffectProgress::Wait(duration) => {
+                tokio::time::sleep(duration).await;
+                self.call(|driver| driver.finish_effect()).await?
+            }
+        }
+    }
+
+    /// Notes on how the driver changed effects since the last call, e.g. by a force
+    /// limit (see `FfbDriver::take_notes`)
+    pub async fn take_notes(&self) -> Vec<String> {
+        self.call(|driver| driver.take_notes()).await.unwrap_or_default()
+    }
+
+    pub async fn stop_all_effects(&self) -> FFBResult<()> {
+        self.call(|driver| driver.stop_all_effects()).await?
+    }
+
+    pub async fn shutdown(&self) -> FFBResult<()> {
+        self.call(|driver| driver.shutdown()).await?
+    }
+
+    /// Captured packets as they arrive, None if the driver does not capture
+    pub async fn live_packets(&self) -> FFBResult<Option<async_mpsc::UnboundedReceiver<UsbPacket>>> {
+        let Some(packets) = self.call(|driver| driver.live_packets()).await? else {
+            return Ok(None);
+        };
+        let (sender, receiver) = async_mpsc::unbounded_channel();
+        // Ends when the capture is dropped or the receiver is closed
+        tokio::task::spawn_blocking(move || {
+            for packet in packets {
+                if sender.send(packet).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Some(receiver))
+    }
+
+    /// Play `scenario` like `Scenario::play`, without console output
+    /// A step whose effect fails gets an empty output; a failed capture ends playback.
+    /// Steps follow a [`Schedule`] like synchronous playback, awaiting each event.
+    /// Dropping the future stops playback after the current driver call, with the
+    /// effect possibly still running, so call `stop_all_effects` afterwards.
+    pub async fn play(&self, scenario: &Scenario) -> anyhow::Result<Vec<StepOutput>> {
+        let iterations = if scenario.loop_forever {
+            u32::MAX
+        } else {
+            scenario.repeat_count
+        };
+
+        let mut all_outputs = Vec::new();
+        let mut schedule = Schedule::new();
+        if iterations > 0 {
+            schedule.at(schedule.origin(), Event::Start { iteration: 0, step: 0 });
+        }
+        let _resolution = (scenario.timing == TimingMode::Precise).then(TimerResolution::raise);
+        let mut sent_packets = Ok(Vec::new());
+        let mut held = HeldEffects::default();
+        while let Some((due, event)) = schedule.pop() {
+            let sleep_until = due.checked_sub(scenario.timing.margin()).unwrap_or(due);
+            tokio::time::sleep_until(sleep_until.into()).await;
+            if sleep_until < due {
+                // Spin off the runtime's thread
+                let spin = move || TimingMode::Precise.wait_until(due, &CancellationToken::new());
+                let _ = tokio::task::spawn_blocking(spin).await;
+            }
+
+            let (iteration, idx, result) = match event {
+                Event::Start { iteration, step: idx } => {
+                    let Some(step) = scenario.steps.get(idx) else {
+                        held = self.release(held, None).await?;
+                        if iteration + 1 < iterations {
+                            schedule.at(due, Event::Start { iteration: iteration + 1, step: 0 });
+                        }
+                        continue;
+                    };
+                    let step = step.clone();
+                    held = self.release(held, Some(idx + 1)).await?;
+                    let begin = move |driver: &mut dyn FfbDriver| {
+                        let progress = driver.begin_effect(&step.effect);
+                        (progress, send_step_commands(driver, &step))
+                    };
+                    match self.call(begin).await? {
+                        (Ok(EffectProgress::Wait(wait)), sent) => {
+                            sent_packets = sent;
+                            schedule.at(due + wait, Event::Finish { iteration, step: idx });
+                            continue;
+                        }
+                        (Ok(EffectProgress::Done(packets)), sent) => {
+                            (iteration, idx, with_sent(Ok(packets), sent))
+                        }
+                        (Err(e), _) => (iteration, idx, Err(e)),
+                    }
+                }
+                Event::Finish { iteration, step: idx } => {
+                    let packets = self.call(|driver| driver.finish_effect()).await?;
+                    let sent = std::mem::replace(&mut sent_packets, Ok(Vec::new()));
+                    (iteration, idx, with_sent(packets, sent))
+                }
+            };
+
+            let packets = match result {
+                Ok(packets) => packets,
+                Err(e @ FFBError::CaptureFailed { .. }) => {
+                    let _ = self.stop_all_effects().await;
+                    return Err(e.at_step(idx + 1).into());
+                }
+                Err(_) => Vec::new(),
+            };
+
+            let (latencies_us, wire_latency_us, timestamps_us, axis, devices) = self
+                .call(|driver| {
+                    let latencies_us: Vec<Option<u64>> = driver
+                        .packet_latencies()
+                        .iter()
+                        .map(|l| l.map(|d| d.as_micros() as u64))
+                        .collect();
+                    let wire_latency_us = driver.wire_latency().map(|d| d.as_micros() as u64);
+                    let timestamps_us = cadence::relative_us(&driver.packet_timestamps());
+                    let axis = driver.axis_samples();
+                    (latencies_us, wire_latency_us, timestamps_us, axis, driver.device_captures())
+                })
+                .await?;
+            let step = scenario.steps[idx].clone();
+            let mut step_held = std::mem::take(&mut held);
+            held = self
+                .call(move |driver| {
+                    step_held.finish_step(driver, &step);
+                    step_held
+                })
+                .await?;
+            let health = if scenario.health_check {
+                self.call(|driver| driver.check_health()).await?
+            } else {
+                None
+            };
+            let failed_health = health.clone().filter(|health| !health.responding);
+            all_outputs.push(StepOutput {
+                step_index: idx + 1,
+                step_name: effect_label(&scenario.steps[idx].effect).to_string(),
+                packets,
+                variants: Vec::new(),
+                latencies_us,
+                wire_latency_us,
+                timestamps_us,
+                axis,
+                devices,
+                health,
+            });
+            if let Some(health) = failed_health {
+                return Err(FFBError::not_responding(health.detail).at_step(idx + 1).into());
+            }
+            schedule.at(due, Event::Start { iteration, step: idx + 1 });
+        }
+        Ok(all_outputs)
+    }
+
+    /// `HeldEffects::release` on the driver's thread
+    async fn release(&self, mut held: HeldEffects, step: Option<usize>) -> FFBResult<HeldEffects> {
+        if held.is_empty() {
+            return Ok(held);
+        }
+        self.call(move |driver| {
+            held.release(driver, step);
+            held
+        })
+        .await
+    }
+}
@@ -0,0 +1,408 @@
+//! Unattended runs of many scenarios from a manifest
+//!
+//! Each manifest entry records a scenario to `runs/`, or compares it with a
+//! capture, on a freshly initialized driver. Failed attempts are retried, and
+//! the outcome of every entry is written to one JSON result file.
+//!
+//! Entries run one after another, or with `parallel` all at once, each on its own
+//! thread with its own driver and capture. Rigs with a wheelbase plus FFB pedals or
+//! a handbrake, or racks of test devices, give every entry a `device` so that each
+//! driver plays on and captures only that device.
+
+use crate::cancel::CancellationToken;
+use crate::compare::{self, CompareOptions, StepComparison};
+use crate::error::{ExitStatus, FFBError, ScenarioError};
+use crate::observer::{ConsoleObserver, PlaybackObserver};
+use crate::priority::{self, ThreadRole};
+use crate::usb_monitor::{CaptureConfig, DeviceFilter};
+use crate::runner::ScenarioRunner;
+use crate::{history, CaptureReader, Scenario, StepOutput};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Instant;
+
+/// Batch manifest file
+#[derive(Debug, Deserialize)]
+pub struct Manifest {
+    /// Extra attempts for entries that fail with an error
+    #[serde(default)]
+    pub retries: u32,
+    /// Run all entries at the same time instead of one after another
+    #[serde(default)]
+    pub parallel: bool,
+    pub runs: Vec<ManifestRun>,
+}
+
+/// One scenario run of a manifest
+#[derive(Debug, Clone, Deserialize)]
+pub struct ManifestRun {
+    pub scenario: PathBuf,
+    #[serde(default = "default_driver")]
+    pub driver: String,
+    /// Record to this file name in runs/
+    #[serde(default)]
+    pub output: Option<String>,
+    /// Compare with this capture file name in runs/
+    #[serde(default)]
+    pub compare: Option<String>,
+    /// Decode both sides to canonical commands before comparing
+    #[serde(default)]
+    pub normalize: bool,
+    /// Play on and capture only this device (VID:PID) instead of the --device ones
+    #[serde(default, deserialize_with = "deserialize_device")]
+    pub device: Option<DeviceFilter>,
+}
+
+impl ManifestRun {
+    /// `capture` limited to the entry's device, if it has one
+    pub fn capture_config(&self, capture: &CaptureConfig) -> CaptureConfig {
+        let mut capture = capture.clone();
+        if let Some(device) = self.device {
+            capture.devices = vec![device];
+        }
+        capture
+    }
+}
+
+fn default_driver() -> String {
+    "sdl".to_string()
+}
+
+fn deserialize_device<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DeviceFilter>, D::Error> {
+    let device: Option<String> = Option::deserialize(deserializer)?;
+    device.map(|device| device.parse().map_err(serde::de::Error::custom)).transpose()
+}
+
+/// Outcome of one manifest entry in the result file
+#[derive(Debug, Serialize)]
+pub struct RunResult {
+    pub scenario: String,
+    pub driver: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// record or compare
+    pub mode: &'static str,
+    /// pass, fail or error
+    pub status: &'static str,
+    pub attempts: u32,
+    pub total_steps: usize,
+    pub mismatched_steps: usize,
+    pub total_packets: usize,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl RunResult {
+    /// Result of `entry` before any attempt finished: an error without steps
+    fn new(entry: &ManifestRun) -> Self {
+        Self {
+            scenario: entry.scenario.display().to_string(),
+            driver: entry.driver.clone(),
+            device: entry.device.map(|device| device.to_string()),
+            mode: if entry.compare.is_some() { "compare" } else { "record" },
+            status: "error",
+            attempts: 0,
+            total_steps: 0,
+            mismatched_steps: 0,
+            total_packets: 0,
+            duration_ms: 0,
+            output: entry.output.clone(),
+            error: None,
+        }
+    }
+}
+
+/// Aggregate result file
+#[derive(Debug, Serialize)]
+pub struct BatchResults {
+    pub started: u64,
+    pub finished: u64,
+    pub passed: usize,
+    pub failed: usize,
+    pub errors: usize,
+    pub runs: Vec<RunResult>,
+}
+
+impl BatchResults {
+    pub fn exit_status(&self) -> ExitStatus {
+        if self.errors > 0 {
+            ExitStatus::SetupError
+        } else if self.failed > 0 {
+            ExitStatus::Mismatch
+        } else {
+            ExitStatus::Success
+        }
+    }
+}
+
+/// Outcome of one successful attempt
+pub struct Attempt {
+    pub scenario_name: String,
+    pub driver_name: String,
+    pub total_steps: usize,
+    pub mismatched_steps: usize,
+    pub total_packets: usize,
+    /// Step comparisons, empty when recording
+    pub results: Vec<StepComparison>,
+    /// Captured output of every played step
+    pub steps: Vec<StepOutput>,
+}
+
+pub fn load_manifest(path: &Path) -> anyhow::Result<Manifest> {
+    let content = fs::read_to_string(path).map_err(|source| ScenarioError::Read {
+        path: path.display().to_string(),
+        source,
+    })?;
+    let manifest: Manifest = serde_yaml::from_str(&content).map_err(|e| ScenarioError::Invalid {
+        path: path.display().to_string(),
+        message: e.to_string(),
+    })?;
+    if let Some(run) = manifest.runs.iter().find(|r| r.output.is_none() == r.compare.is_none()) {
+        return Err(ScenarioError::Invalid {
+            path: path.display().to_string(),
+            message: format!("run of {} needs exactly one of output or compare", run.scenario.display()),
+        }
+        .into());
+    }
+    if manifest.parallel {
+        check_parallel(&manifest.runs).map_err(|message| ScenarioError::Invalid {
+            path: path.display().to_string(),
+            message,
+        })?;
+    }
+    Ok(manifest)
+}
+
+/// Entries that play on a device need one each to run at the same time
+pub fn check_parallel(runs: &[ManifestRun]) -> Result<(), String> {
+    let on_device = runs.iter().filter(|r| r.driver.eq_ignore_ascii_case("sdl"));
+    let mut devices = Vec::new();
+    for run in on_device {
+        let Some(device) = run.device else {
+            return Err(format!("parallel run of {} needs a device", run.scenario.display()));
+        };
+        if devices.contains(&device) {
+            return Err(format!("device {} is used by several parallel runs", device));
+        }
+        devices.push(device);
+    }
+    let mut outputs: Vec<&String> = runs.iter().filter_map(|r| r.output.as_ref()).collect();
+    outputs.sort();
+    if let Some(pair) = outputs.windows(2).find(|pair| pair[0] == pair[1]) {
+        return Err(format!("output {} is written by several parallel runs", pair[0]));
+    }
+    Ok(())
+}
+
+/// Run every manifest entry and write the results; `retries` overrides the manifest's
+/// Once `cancel` is cancelled the current run stops and the rest are skipped
+pub fn run(
+    manifest: &Manifest,
+    retries: Option<u32>,
+    capture: &CaptureConfig,
+    results_path: &Path,
+    cancel: &CancellationToken,
+) -> anyhow::Result<BatchResults> {
+    let retries = retries.unwrap_or(manifest.retries);
+    let started = history::now_timestamp();
+    let total = manifest.runs.len();
+    let mut results = Vec::with_capacity(total);
+
+    if manifest.parallel {
+        println!("\n##### Running {} entries in parallel #####", total);
+        thread::scope(|scope| {
+            let handles: Vec<_> = manifest
+                .runs
+                .iter()
+                .enumerate()
+                .map(|(index, entry)| {
+                    scope.spawn(move || {
+                        if capture.realtime {
+                            priority::raise_or_warn(ThreadRole::Playback);
+                        }
+                        run_with_retries(index, total, entry, retries, capture, cancel)
+                    })
+                })
+                .collect();
+            results.extend(handles.into_iter().zip(&manifest.runs).map(|(handle, entry)| {
+                handle.join().unwrap_or_else(|_| RunResult {
+                    error: Some("run thread panicked".to_string()),
+                    ..RunResult::new(entry)
+                })
+            }));
+        });
+    } else {
+        for (index, entry) in manifest.runs.iter().enumerate() {
+            if cancel.is_cancelled() {
+                println!("\nCancelled, skipping the remaining {} runs", total - index);
+                break;
+            }
+            results.push(run_with_retries(index, total, entry, retries, capture, cancel));
+        }
+    }
+
+    let count = |status: &str| results.iter().filter(|r| r.status == status).count();
+    let batch = BatchResults {
+        started,
+        finished: history::now_timestamp(),
+        passed: count("pass"),
+        failed: count("fail"),
+        errors: count("error"),
+        runs: results,
+    };
+    if let Some(parent) = results_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(results_path, serde_json::to_string_pretty(&batch)?)?;
+
+    println!("\n=== Batch Results ===");
+    println!("{:<40} {:<8} {:<9} {:<6} {:>8}  Details", "Scenario", "Driver", "Device", "Result", "Attempts");
+    for run in &batch.runs {
+        let details = match &run.error {
+            Some(error) => error.clone(),
+            None => format!("{} of {} steps differ", run.mismatched_steps, run.total_steps),
+        };
+        println!(
+            "{:<40} {:<8} {:<9} {:<6} {:>8}  {}",
+            run.scenario,
+            run.driver,
+            run.device.as_deref().unwrap_or("-"),
+            run.status.to_uppercase(),
+            run.attempts,
+            details
+        );
+    }
+    println!(
+        "\n{} passed, {} failed, {} errors ({} total); results written to {}",
+        batch.passed,
+        batch.failed,
+        batch.errors,
+        batch.runs.len(),
+        results_path.display()
+    );
+    Ok(batch)
+}
+
+/// Run `entry`, retrying failed attempts, and summarize its outcome
+fn run_with_retries(
+    index: usize,
+    total: usize,
+    entry: &ManifestRun,
+    retries: u32,
+    capture: &CaptureConfig,
+    cancel: &CancellationToken,
+) -> RunResult {
+    let device = entry.device.map(|device| format!(" on {}", device)).unwrap_or_default();
+    println!(
+        "\n##### [{}/{}] {} ({}{}) #####",
+        index + 1,
+        total,
+        entry.scenario.display(),
+        entry.driver,
+        device
+    );
+    let started = Instant::now();
+    let mut attempts = 0;
+    let outcome = loop {
+        attempts += 1;
+        match run_entry(entry, capture, &mut ConsoleObserver::default(), cancel) {
+            Ok(attempt) => break Ok(attempt),
+            // A broken scenario fails the same way every time
+            Err(e) if e.downcast_ref::<ScenarioError>().is_some() => break Err(e),
+            Err(e) if attempts > retries || cancel.is_cancelled() => break Err(e),
+            Err(e) => println!("Attempt {} failed: {:#}; retrying", attempts, e),
+        }
+    };
+
+    let mut result = RunResult {
+        attempts,
+        duration_ms: started.elapsed().as_millis() as u64,
+        ..RunResult::new(entry)
+    };
+    match outcome {
+        Ok(attempt) => {
+            result.status = if attempt.mismatched_steps == 0 { "pass" } else { "fail" };
+            result.total_steps = attempt.total_steps;
+            result.mismatched_steps = attempt.mismatched_steps;
+            result.total_packets = attempt.total_packets;
+        }
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            result.error = Some(format!("{:#}", e));
+        }
+    }
+    result
+}
+
+/// One attempt on a fresh driver, shut down before returning
+pub fn run_entry(
+    entry: &ManifestRun,
+    capture: &CaptureConfig,
+    observer: &mut dyn PlaybackObserver,
+    cancel: &CancellationToken,
+) -> anyhow::Result<Attempt> {
+    let scenario = Scenario::load_from_file(&entry.scenario)?;
+    let expected_steps = match &entry.compare {
+        Some(compare) => {
+            let path = PathBuf::from("runs").join(compare);
+            if !path.exists() {
+                return Err(FFBError::comparison(format!("comparison file {} not found", path.display())).into());
+            }
+            Some(CaptureReader::open(&path)?)
+        }
+        None => None,
+    };
+
+    let options = CompareOptions {
+        normalize: entry.normalize,
+        ..CompareOptions::default()
+    };
+    let output_path = entry.output.as_ref().map(|output| PathBuf::from("runs").join(output));
+    if output_path.is_some() {
+        fs::create_dir_all("runs")?;
+    }
+    let mut runner = ScenarioRunner::new(&entry.driver)
+        .capture(entry.capture_config(capture))
+        .observer(observer)
+        .cancel_token(cancel.clone());
+    if let Some(expected_steps) = &expected_steps {
+        runner = runner.compare_with(expected_steps, options);
+    }
+    if let Some(output_path) = &output_path {
+        runner = runner.stream_to(output_path);
+    }
+    let report = runner.run(&scenario)?;
+
+    let total_packets = report.total_packets();
+    let mismatched_steps = match (&report.comparison, &output_path) {
+        (Some(results), _) => {
+            compare::print_comparison(results);
+            report.mismatched_steps()
+        }
+        (None, Some(output_path)) => {
+            println!("Saved {} packets to {}", total_packets, output_path.display());
+            report.expectations.print();
+            report.mismatched_steps()
+        }
+        (None, None) => 0,
+    };
+
+    let mut record = report.history_record(if expected_steps.is_some() { "compare" } else { "record" });
+    record.mismatched_steps = mismatched_steps;
+    history::append_or_warn(&record);
+
+    Ok(Attempt {
+        scenario_name: report.scenario,
+        driver_name: report.driver,
+        total_steps: record.total_steps,
+        mismatched_steps,
+        total_packets,
+        results: report.comparison.unwrap_or_default(),
+        steps: report.steps,
+    })
+}
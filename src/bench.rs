@@ -0,0 +1,164 @@
+//! FFB update rate benchmark
+//!
+//! The `bench` command updates a constant force as fast as the driver accepts and
+//! measures from the USB capture how many packets actually reach the device, and
+//! how evenly they are spaced.
+
+use crate::cancel::CancellationToken;
+use crate::driver::FfbDriver;
+use crate::drivers::sdl_driver::SdlDriver;
+use crate::usb_monitor::{CaptureConfig, PacketDirection, UsbPacket};
+use std::time::Duration;
+
+/// Packet rate and spacing of a benchmark run
+#[derive(Debug, Clone)]
+pub struct BenchReport {
+    /// Updates accepted by the driver
+    pub updates: usize,
+    /// Host-to-device packets captured
+    pub packets: usize,
+    pub duration: Duration,
+    /// Time from the first to the last captured packet
+    pub span: Duration,
+    /// Inter-packet intervals in microseconds, sorted
+    pub intervals_us: Vec<f64>,
+}
+
+impl BenchReport {
+    pub fn from_packets(updates: usize, duration: Duration, packets: &[UsbPacket]) -> Self {
+        let mut timestamps: Vec<Duration> = packets
+            .iter()
+            .filter(|p| p.direction == PacketDirection::HostToDevice)
+            .map(|p| p.timestamp)
+            .collect();
+        timestamps.sort();
+
+        let mut intervals_us: Vec<f64> = timestamps
+            .windows(2)
+            .map(|pair| (pair[1] - pair[0]).as_secs_f64() * 1e6)
+            .collect();
+        intervals_us.sort_by(f64::total_cmp);
+
+        let span = match (timestamps.first(), timestamps.last()) {
+            (Some(first), Some(last)) => *last - *first,
+            _ => Duration::ZERO,
+        };
+        Self {
+            updates,
+            packets: timestamps.len(),
+            duration,
+            span,
+            intervals_us,
+        }
+    }
+
+    /// Captured packets per second over the span of the capture
+    pub fn packet_rate(&self) -> f64 {
+        if self.span.is_zero() {
+            return 0.0;
+        }
+        self.intervals_us.len() as f64 / self.span.as_secs_f64()
+    }
+
+    fn mean_interval_us(&self) -> Option<f64> {
+        (!self.intervals_us.is_empty()).then(|| self.intervals_us.iter().sum::<f64>() / self.intervals_us.len() as f64)
+    }
+
+    /// Standard deviation of the inter-packet interval
+    pub fn jitter_us(&self) -> Option<f64> {
+        let mean = self.mean_interval_us()?;
+        let variance =
+            self.intervals_us.iter().map(|i| (i - mean).powi(2)).sum::<f64>() / self.intervals_us.len() as f64;
+        Some(variance.sqrt())
+    }
+
+    fn percentile_us(&self, percentile: f64) -> Option<f64> {
+        let last = self.intervals_us.len().checked_sub(1)?;
+        Some(self.intervals_us[(last as f64 * percentile).round() as usize])
+    }
+
+    /// Results in the form of JSON output; interval statistics are null without enough packets
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "updates": self.updates,
+            "packets": self.packets,
+            "duration_ms": self.duration.as_millis() as u64,
+            "packet_rate": self.packet_rate(),
+            "mean_interval_us": self.mean_interval_us(),
+            "median_interval_us": self.percentile_us(0.5),
+            "p99_interval_us": self.percentile_us(0.99),
+            "min_interval_us": self.intervals_us.first(),
+            "max_interval_us": self.intervals_us.last(),
+            "jitter_us": self.jitter_us(),
+        })
+    }
+
+    pub fn print(&self) {
+        println!("\n=== Benchmark Results ===");
+        println!(
+            "Updates sent: {} in {:.2} s ({:.0} updates/s)",
+            self.updates,
+            self.duration.as_secs_f64(),
+            self.updates as f64 / self.duration.as_secs_f64()
+        );
+        println!("Packets captured: {}", self.packets);
+        let (Some(mean), Some(jitter), Some(median), Some(p99)) = (
+            self.mean_interval_us(),
+            self.jitter_us(),
+            self.percentile_us(0.5),
+            self.percentile_us(0.99),
+        ) else {
+            println!("Not enough packets captured to measure the update rate");
+            return;
+        };
+        println!("Effective FFB update rate: {:.1} packets/s", self.packet_rate());
+        println!(
+            "Interval: mean {:.0} us, median {:.0} us, p99 {:.0} us, min {:.0} us, max {:.0} us",
+            mean,
+            median,
+            p99,
+            self.intervals_us[0],
+            self.intervals_us[self.intervals_us.len() - 1]
+        );
+        println!("Jitter (std dev): {:.0} us", jitter);
+        if self.updates > 0 {
+            println!(
+                "Packets per update: {:.2} (below 1 means the driver or device coalesces updates)",
+                self.packets as f64 / self.updates as f64
+            );
+        }
+    }
+}
+
+/// Stream constant-force updates on the SDL driver for `duration` and report the result
+pub fn run(
+    capture: CaptureConfig,
+    duration: Duration,
+    magnitude: i16,
+    cancel: &CancellationToken,
+) -> anyhow::Result<BenchReport> {
+    // The benchmark drives the SDL driver directly, past create_driver's limit
+    let magnitude = match capture.max_force {
+        Some(max) => {
+            let limited = max.limit_magnitude(magnitude);
+            if limited != magnitude {
+                println!("Force limit {}% ({}): magnitude {} -> {}", max.percent, max.mode.name(), magnitude, limited);
+            }
+            limited
+        }
+        None => magnitude,
+    };
+    let mut driver = SdlDriver::with_capture_config(capture);
+    driver.set_cancellation(cancel.clone());
+    driver.initialize()?;
+
+    println!(
+        "\nStreaming constant-force updates (up to {} magnitude) for {} s...",
+        magnitude,
+        duration.as_secs()
+    );
+    let (updates, packets) = driver.stream_constant_force(duration, magnitude)?;
+    driver.shutdown()?;
+
+    Ok(BenchReport::from_packets(updates, duration, &packets))
+}
@@ -0,0 +1,322 @@
+//! Guided scenario building from a menu, for testers who don't write YAML
+//!
+//! The `build` command offers every effect type with moderate starting values. The
+//! tester changes fields by number, plays the effect on the wheel to feel it, and
+//! keeps it as the next step or discards it. Kept steps are written as a scenario
+//! that `play`, `record` and `compare` take like any other. Values are checked by
+//! the scenario format itself: a field is edited as YAML and the effect is parsed
+//! again, so an out-of-range value is refused with the parser's message.
+
+use crate::cancel::CancellationToken;
+use crate::driver::FfbDriver;
+use crate::effects::*;
+use crate::scenario::EFFECT_LABELS;
+use crate::{effect_label, Scenario, ScenarioStep};
+use serde::Serialize;
+use serde_yaml::Value;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// Fields chosen through the effect menu rather than edited
+const FIXED_FIELDS: [&str; 3] = ["type", "wave_type", "condition_type"];
+
+/// Outcome of a building session
+#[derive(Debug, Serialize)]
+pub struct BuildSummary {
+    /// Scenario written, None if the session ended without saving
+    pub output: Option<PathBuf>,
+    pub steps: usize,
+    /// Effects played on the device to try them
+    pub played: usize,
+}
+
+/// Starting point of the effect type `EFFECT_LABELS[index]`, at a moderate force
+fn template(index: usize) -> Effect {
+    let params = EffectParams::default();
+    let periodic = |wave_type| Effect::Periodic {
+        params: params.clone(),
+        effect: PeriodicEffect {
+            wave_type,
+            magnitude: 3000,
+            offset: 0,
+            phase: 0,
+            period: 100,
+            direction: Direction::default(),
+            envelope: Envelope::default(),
+        },
+    };
+    let condition = |condition_type| Effect::Condition {
+        params: params.clone(),
+        effect: ConditionEffect {
+            condition_type,
+            x_axis: ConditionParams {
+                positive_coefficient: 5000,
+                negative_coefficient: 5000,
+                ..Default::default()
+            },
+        },
+    };
+    match index {
+        0 => Effect::Constant {
+            params: params.clone(),
+            force: ConstantForce {
+                magnitude: 3000,
+                direction: Direction::default(),
+                envelope: Envelope::default(),
+            },
+        },
+        1 => periodic(WaveType::Sine),
+        2 => periodic(WaveType::Square),
+        3 => periodic(WaveType::Triangle),
+        4 => periodic(WaveType::SawtoothUp),
+        5 => periodic(WaveType::SawtoothDown),
+        6 => Effect::Ramp {
+            params: params.clone(),
+            effect: RampEffect {
+                start_magnitude: 0,
+                end_magnitude: 3000,
+                direction: Direction::default(),
+                envelope: Envelope::default(),
+            },
+        },
+        7 => condition(ConditionType::Spring),
+        8 => condition(ConditionType::Damper),
+        9 => condition(ConditionType::Friction),
+        _ => condition(ConditionType::Inertia),
+    }
+}
+
+/// Editable fields of `effect` as dotted paths (`envelope.attack_time`) and values
+fn fields(effect: &Effect) -> Vec<(String, Value)> {
+    fn collect(prefix: &str, value: &Value, fields: &mut Vec<(String, Value)>) {
+        let Value::Mapping(mapping) = value else {
+            fields.push((prefix.to_string(), value.clone()));
+            return;
+        };
+        for (key, value) in mapping {
+            let Some(key) = key.as_str() else { continue };
+            if prefix.is_empty() && FIXED_FIELDS.contains(&key) {
+                continue;
+            }
+            let path = if prefix.is_empty() { key.to_string() } else { format!("{}.{}", prefix, key) };
+            collect(&path, value, fields);
+        }
+    }
+    let mut fields = Vec::new();
+    if let Ok(value) = serde_yaml::to_value(effect) {
+        collect("", &value, &mut fields);
+    }
+    fields
+}
+
+/// `effect` with the field at `path` set to `input` (a YAML scalar)
+fn edit(effect: &Effect, path: &str, input: &str) -> Result<Effect, String> {
+    let new_value: Value = serde_yaml::from_str(input).map_err(|e| e.to_string())?;
+    let mut value = serde_yaml::to_value(effect).map_err(|e| e.to_string())?;
+    let mut field = &mut value;
+    for key in path.split('.') {
+        field = field.get_mut(key).ok_or_else(|| format!("no field {}", path))?;
+    }
+    *field = new_value;
+    serde_yaml::from_value(value).map_err(|e| e.to_string())
+}
+
+fn show(value: &Value) -> String {
+    serde_yaml::to_string(value).map_or_else(|_| "?".to_string(), |s| s.trim().to_string())
+}
+
+/// Print `text` and read a trimmed line, None at the end of input
+fn prompt(input: &mut impl BufRead, text: &str) -> io::Result<Option<String>> {
+    print!("{}", text);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        println!();
+        return Ok(None);
+    }
+    Ok(Some(line.trim().to_string()))
+}
+
+/// What the tester did with an effect
+enum Decision {
+    Keep(Effect),
+    Discard,
+    /// Input ended or the run was interrupted
+    Stop,
+}
+
+/// Interactive building session
+struct Session<'a, R> {
+    driver: &'a mut dyn FfbDriver,
+    input: R,
+    cancel: &'a CancellationToken,
+    played: usize,
+}
+
+impl<R: BufRead> Session<'_, R> {
+    /// Play `effect` on the device until it ends, then stop it
+    fn play(&mut self, effect: &Effect) {
+        println!("  Playing {} for {} ms...", effect_label(effect), effect.end_time());
+        let result = self.driver.apply_effect(effect);
+        let _ = self.driver.stop_all_effects();
+        for note in self.driver.take_notes() {
+            println!("    {}", note);
+        }
+        self.played += 1;
+        match result {
+            Ok(packets) => println!("  Done ({} packets)", packets.len()),
+            Err(e) => println!("  Playing failed: {}", e),
+        }
+    }
+
+    /// Let the tester tweak and try `effect` until it is kept or discarded
+    fn tune(&mut self, mut effect: Effect) -> io::Result<Decision> {
+        loop {
+            let fields = fields(&effect);
+            println!("\n{}:", effect_label(&effect));
+            for (number, (path, value)) in fields.iter().enumerate() {
+                println!("  {:>2}) {}: {}", number + 1, path, show(value));
+            }
+            let text = "Field number to change, p: play, k: keep as step, d: discard > ";
+            let Some(choice) = prompt(&mut self.input, text)? else {
+                return Ok(Decision::Stop);
+            };
+            if self.cancel.is_cancelled() {
+                return Ok(Decision::Stop);
+            }
+            match choice.as_str() {
+                "p" => self.play(&effect),
+                "k" => return Ok(Decision::Keep(effect)),
+                "d" => return Ok(Decision::Discard),
+                _ => {
+                    let Some((path, value)) = choice
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|number| fields.get(number.wrapping_sub(1)))
+                    else {
+                        println!("  Unknown choice '{}'", choice);
+                        continue;
+                    };
+                    let text = format!("  {} [{}]: ", path, show(value));
+                    let Some(new_value) = prompt(&mut self.input, &text)? else {
+                        return Ok(Decision::Stop);
+                    };
+                    if new_value.is_empty() {
+                        continue;
+                    }
+                    match edit(&effect, path, &new_value) {
+                        Ok(edited) => effect = edited,
+                        Err(e) => println!("  Invalid value, kept {}: {}", show(value), e),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Run a building session on an initialized driver, reading choices from `input`,
+/// and write the kept steps to `path` as a scenario named `name`
+/// Interrupting `cancel` or ending the input saves the steps kept so far.
+pub fn run(
+    driver: &mut dyn FfbDriver,
+    input: impl BufRead,
+    path: &Path,
+    name: &str,
+    cancel: &CancellationToken,
+) -> anyhow::Result<BuildSummary> {
+    let mut session = Session {
+        driver,
+        input,
+        cancel,
+        played: 0,
+    };
+    let mut steps: Vec<ScenarioStep> = Vec::new();
+    println!("Pick an effect, change its values, play it to feel it, and keep it as the next step.");
+
+    let save = loop {
+        println!("\nEffects:");
+        for (number, label) in EFFECT_LABELS.iter().enumerate() {
+            println!("  {:>2}) {}", number + 1, label);
+        }
+        println!(
+            "{} step(s) kept. l: list steps, u: remove the last step, s: save and quit, q: quit without saving",
+            steps.len()
+        );
+        let Some(choice) = prompt(&mut session.input, "Choice > ")? else {
+            break true;
+        };
+        if cancel.is_cancelled() {
+            break true;
+        }
+        match choice.as_str() {
+            "s" => break true,
+            "q" => break false,
+            "l" => {
+                for (number, step) in steps.iter().enumerate() {
+                    println!("  Step {}: {} ({} ms)", number + 1, effect_label(&step.effect), step.effect.duration());
+                }
+            }
+            "u" => match steps.pop() {
+                Some(step) => println!("  Removed step {}: {}", steps.len() + 1, effect_label(&step.effect)),
+                None => println!("  No steps to remove"),
+            },
+            _ => {
+                let Some(index) = choice.parse::<usize>().ok().filter(|n| (1..=EFFECT_LABELS.len()).contains(n)) else {
+                    println!("  Unknown choice '{}'", choice);
+                    continue;
+                };
+                match session.tune(template(index - 1))? {
+                    Decision::Keep(effect) => {
+                        println!("  Kept as step {}", steps.len() + 1);
+                        steps.push(ScenarioStep {
+                            effect,
+                            expect: None,
+                            peripherals: Vec::new(),
+                            settings: Vec::new(),
+                            hold: false,
+                            stop_at: None,
+                        });
+                    }
+                    Decision::Discard => {}
+                    Decision::Stop => break true,
+                }
+            }
+        }
+    };
+
+    let played = session.played;
+    if !save || steps.is_empty() {
+        println!("\nNothing saved");
+        return Ok(BuildSummary {
+            output: None,
+            steps: steps.len(),
+            played,
+        });
+    }
+
+    let scenario = Scenario {
+        name: name.to_string(),
+        description: "Built with ffb_replay build".to_string(),
+        loop_forever: false,
+        repeat_count: 1,
+        timing: Default::default(),
+        health_check: false,
+        steps,
+    };
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, serde_yaml::to_string(&scenario)?)?;
+    println!(
+        "\nSaved {} step(s) to {}. Try:\n  ffb_replay play --scenario {}",
+        scenario.steps.len(),
+        path.display(),
+        path.display()
+    );
+    Ok(BuildSummary {
+        output: Some(path.to_path_buf()),
+        steps: scenario.steps.len(),
+        played,
+    })
+}
@@ -0,0 +1,211 @@
+//! Packet cadence: how regularly a step's packets go out
+//!
+//! A step's packet timestamps are stored in capture files as one comment line
+//! after its packets, in microseconds since the step's first packet:
+//! `# Timestamps us: 0 1000 2001 3000`. The intervals between consecutive packets
+//! are binned into a histogram with doubling bucket widths, so a steady update
+//! rate shows up as a single peak and jitter, bursts or stalls of a driver or
+//! firmware as extra buckets.
+//!
+//! [`TimingThresholds`] turn the intervals into a regression check: `compare`
+//! and `test` fail steps whose jitter or update rate is out of bounds even when
+//! their packets match, so a firmware that updates at half the rate is caught.
+
+use crate::StepOutput;
+use serde::Serialize;
+use std::time::Duration;
+
+/// Prefix of the timestamp line in capture files
+pub const TIMESTAMPS_PREFIX: &str = "# Timestamps us:";
+
+/// Lower bounds of the histogram buckets (us); the last bucket is open-ended
+/// Common update periods (1, 2, 4, 8 ms) fall mid-bucket, so jitter around them
+/// stays in one bucket
+const BUCKET_BOUNDS_US: [u64; 10] = [0, 250, 750, 1_500, 3_000, 6_000, 12_000, 24_000, 48_000, 96_000];
+
+/// Width of the longest bar of a printed histogram
+const BAR_WIDTH: usize = 30;
+
+/// Fewest intervals a step needs for its timing to be checked; shorter steps
+/// are bursts of setup packets without a steady update rate
+pub const MIN_CHECKED_INTERVALS: usize = 10;
+
+/// Timestamps relative to the first one (us)
+pub fn relative_us(timestamps: &[Duration]) -> Vec<u64> {
+    let Some(&first) = timestamps.first() else {
+        return Vec::new();
+    };
+    timestamps
+        .iter()
+        .map(|t| t.saturating_sub(first).as_micros() as u64)
+        .collect()
+}
+
+/// Format the timestamp line of a step
+pub fn format_line(timestamps_us: &[u64]) -> String {
+    let values: Vec<String> = timestamps_us.iter().map(u64::to_string).collect();
+    format!("{} {}", TIMESTAMPS_PREFIX, values.join(" "))
+}
+
+/// Parse a timestamp line, None if the line is not one
+pub fn parse_line(line: &str) -> Option<Vec<u64>> {
+    let values = line.strip_prefix(TIMESTAMPS_PREFIX)?;
+    values.split_whitespace().map(|v| v.parse().ok()).collect()
+}
+
+/// Intervals in [from_us, to_us)
+#[derive(Debug, Clone, Serialize)]
+pub struct IntervalBucket {
+    pub from_us: u64,
+    /// None for the open-ended last bucket
+    pub to_us: Option<u64>,
+    pub count: usize,
+}
+
+/// Histogram of the intervals between consecutive packets of a step
+#[derive(Debug, Clone, Serialize)]
+pub struct IntervalHistogram {
+    pub intervals: usize,
+    pub min_us: u64,
+    pub median_us: u64,
+    pub max_us: u64,
+    /// 95th percentile of the intervals' distance from the median
+    pub jitter_us: u64,
+    /// From the first to the last non-empty bucket
+    pub buckets: Vec<IntervalBucket>,
+}
+
+impl IntervalHistogram {
+    /// Histogram of the intervals between `timestamps_us`, None with fewer than two packets
+    pub fn of(timestamps_us: &[u64]) -> Option<Self> {
+        let mut intervals: Vec<u64> = timestamps_us
+            .windows(2)
+            .map(|pair| pair[1].saturating_sub(pair[0]))
+            .collect();
+        intervals.sort_unstable();
+        let min_us = *intervals.first()?;
+        let max_us = *intervals.last()?;
+
+        let mut buckets: Vec<IntervalBucket> = BUCKET_BOUNDS_US
+            .iter()
+            .enumerate()
+            .map(|(i, &from_us)| IntervalBucket {
+                from_us,
+                to_us: BUCKET_BOUNDS_US.get(i + 1).copied(),
+                count: 0,
+            })
+            .collect();
+        for &interval in &intervals {
+            let index = BUCKET_BOUNDS_US.partition_point(|&bound| bound <= interval) - 1;
+            buckets[index].count += 1;
+        }
+        let first = buckets.iter().position(|b| b.count > 0)?;
+        let last = buckets.iter().rposition(|b| b.count > 0)?;
+        buckets.truncate(last + 1);
+        buckets.drain(..first);
+
+        let median_us = intervals[intervals.len() / 2];
+        let mut deviations: Vec<u64> = intervals.iter().map(|&i| i.abs_diff(median_us)).collect();
+        deviations.sort_unstable();
+        // Nearest-rank percentile
+        let jitter_us = deviations[(deviations.len() * 95).div_ceil(100).saturating_sub(1)];
+
+        Some(Self {
+            intervals: intervals.len(),
+            min_us,
+            median_us,
+            max_us,
+            jitter_us,
+            buckets,
+        })
+    }
+
+    /// Packets per second at the median interval, None if it is zero
+    pub fn update_rate_hz(&self) -> Option<f64> {
+        (self.median_us > 0).then(|| 1_000_000.0 / self.median_us as f64)
+    }
+
+    /// One line summary, e.g. "99 intervals, min 0.98 ms, median 1.00 ms, max 4.10 ms, jitter 0.02 ms"
+    pub fn describe(&self) -> String {
+        format!(
+            "{} intervals, min {}, median {}, max {}, jitter {}",
+            self.intervals,
+            millis(self.min_us),
+            millis(self.median_us),
+            millis(self.max_us),
+            millis(self.jitter_us)
+        )
+    }
+
+    /// One line per bucket with a bar scaled to the fullest bucket
+    pub fn render(&self) -> Vec<String> {
+        let fullest = self.buckets.iter().map(|b| b.count).max().unwrap_or(0).max(1);
+        let labels: Vec<String> = self
+            .buckets
+            .iter()
+            .map(|b| match b.to_us {
+                Some(to_us) => format!("{}-{} ms", bound(b.from_us), bound(to_us)),
+                None => format!(">= {} ms", bound(b.from_us)),
+            })
+            .collect();
+        let label_width = labels.iter().map(String::len).max().unwrap_or(0);
+        self.buckets
+            .iter()
+            .zip(&labels)
+            .map(|(b, label)| {
+                let bar = "#".repeat((b.count * BAR_WIDTH).div_ceil(fullest));
+                format!("{:<label_width$}  {:<BAR_WIDTH$} {}", label, bar, b.count)
+            })
+            .collect()
+    }
+}
+
+/// Limits on the packet timing of a step, checked on steps with at least
+/// [`MIN_CHECKED_INTERVALS`] intervals
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TimingThresholds {
+    /// Largest allowed jitter (us), see [`IntervalHistogram::jitter_us`]
+    pub max_jitter_us: Option<u64>,
+    /// Lowest allowed update rate (Hz), see [`IntervalHistogram::update_rate_hz`]
+    pub min_update_rate_hz: Option<f64>,
+}
+
+impl TimingThresholds {
+    pub fn is_set(&self) -> bool {
+        self.max_jitter_us.is_some() || self.min_update_rate_hz.is_some()
+    }
+
+    /// Thresholds `step` exceeds, e.g. "update rate 500.0 Hz below 1000.0 Hz";
+    /// empty if it stays within them or is too short to check
+    pub fn check(&self, step: &StepOutput) -> Vec<String> {
+        if !self.is_set() || step.packets.len() <= MIN_CHECKED_INTERVALS {
+            return Vec::new();
+        }
+        let Some(histogram) = IntervalHistogram::of(&step.timestamps_us) else {
+            return vec!["no packet timestamps to check the timing against".to_string()];
+        };
+        let mut violations = Vec::new();
+        if histogram.intervals < MIN_CHECKED_INTERVALS {
+            return violations;
+        }
+        if let Some(max) = self.max_jitter_us.filter(|&max| histogram.jitter_us > max) {
+            violations.push(format!("jitter {} above {}", millis(histogram.jitter_us), millis(max)));
+        }
+        if let (Some(min), Some(rate)) = (self.min_update_rate_hz, histogram.update_rate_hz()) {
+            if rate < min {
+                violations.push(format!("update rate {:.1} Hz below {:.1} Hz", rate, min));
+            }
+        }
+        violations
+    }
+}
+
+fn millis(us: u64) -> String {
+    format!("{:.2} ms", us as f64 / 1000.0)
+}
+
+/// Bucket bound in ms without trailing zeros, e.g. "0.25", "1.5", "96"
+fn bound(us: u64) -> String {
+    let text = format!("{:.3}", us as f64 / 1000.0);
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
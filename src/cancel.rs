@@ -0,0 +1,118 @@
+//! Cooperative cancellation of runs
+//!
+//! A `CancellationToken` is shared between whoever may stop a run (Ctrl+C, the TUI,
+//! the HTTP API) and everything that waits during one: playback between and within
+//! steps, driver effect waits and the start of the USB capture. Waits go through
+//! `CancellationToken::sleep`, which wakes up as soon as the token is cancelled, so
+//! a long step or an endless loop stops promptly instead of at the next step.
+//!
+//! The token also carries a pause, set by an emergency stop from the keyboard:
+//! playback holds before its next step until the pause is lifted or the token is
+//! cancelled.
+
+use crate::error::{FFBError, FFBResult};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often channel waits check the token, which cannot wake them directly
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Shared flag that interrupts waits once cancelled; clones share the flag
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<Shared>);
+
+#[derive(Debug, Default)]
+struct Shared {
+    cancelled: Mutex<bool>,
+    wake: Condvar,
+    paused: AtomicBool,
+}
+
+impl CancellationToken {
+    /// A token that is neither cancelled nor paused
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel, waking every `sleep` on this token
+    pub fn cancel(&self) {
+        *self.0.cancelled.lock().unwrap() = true;
+        self.0.wake.notify_all();
+    }
+
+    /// Whether `cancel` was called on this token or a clone of it
+    pub fn is_cancelled(&self) -> bool {
+        *self.0.cancelled.lock().unwrap()
+    }
+
+    /// Hold playback before its next step until `resume`
+    pub fn pause(&self) {
+        self.0.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Let playback go on after `pause`
+    pub fn resume(&self) {
+        self.0.paused.store(false, Ordering::SeqCst);
+    }
+
+    /// Whether playback is held by `pause`
+    pub fn is_paused(&self) -> bool {
+        self.0.paused.load(Ordering::SeqCst)
+    }
+
+    /// Wait while paused
+    /// Returns false if cancelled meanwhile
+    pub fn wait_while_paused(&self) -> bool {
+        while self.is_paused() {
+            if !self.sleep(POLL_INTERVAL) {
+                return false;
+            }
+        }
+        !self.is_cancelled()
+    }
+
+    /// `FFBError::Cancelled` once cancelled
+    pub fn check(&self) -> FFBResult<()> {
+        if self.is_cancelled() {
+            Err(FFBError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Sleep for `duration` or until cancelled
+    /// Returns false if the sleep was cut short
+    pub fn sleep(&self, duration: Duration) -> bool {
+        self.sleep_until(Instant::now() + duration)
+    }
+
+    /// Sleep until `deadline` or until cancelled
+    /// Returns false if the sleep was cut short
+    pub fn sleep_until(&self, deadline: Instant) -> bool {
+        let mut cancelled = self.0.cancelled.lock().unwrap();
+        while !*cancelled {
+            let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+                return true;
+            };
+            cancelled = self.0.wake.wait_timeout(cancelled, remaining).unwrap().0;
+        }
+        false
+    }
+
+    /// `recv_timeout` that also returns early, as a timeout, once cancelled
+    pub fn recv_timeout<T>(&self, receiver: &Receiver<T>, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline
+                .checked_duration_since(Instant::now())
+                .filter(|_| !self.is_cancelled())
+                .ok_or(RecvTimeoutError::Timeout)?;
+            match receiver.recv_timeout(remaining.min(POLL_INTERVAL)) {
+                Err(RecvTimeoutError::Timeout) => continue,
+                result => return result,
+            }
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Pre-run check of a scenario against what the driver can play
+//!
+//! Before the first step, the runner asks the initialized driver for its
+//! [`Capabilities`] and checks the scenario's needs against them: the effect types
+//! it uses, its longest duration and start delay, and how many effects it keeps on
+//! the device at once (held effects play under the following steps). The result is
+//! a table printed before playback; with `--strict` an incompatibility ends the run
+//! before it starts instead of failing steps one by one mid-run.
+
+use crate::{effect_label, Scenario, ScenarioStep};
+
+/// What a driver's device can play, as far as the driver knows
+#[derive(Debug, Clone, Default)]
+pub struct Capabilities {
+    /// Supported effect types, as `effect_label` names them
+    pub effects: Vec<&'static str>,
+    /// Longest finite effect duration (ms), None if not limited
+    pub max_duration_ms: Option<u32>,
+    /// Longest start delay (ms), None if not limited
+    pub max_start_delay_ms: Option<u32>,
+    /// Effects the device plays at once, None if unknown
+    pub max_playing: Option<usize>,
+}
+
+/// One row of the compatibility table
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub requirement: String,
+    /// What the scenario needs, e.g. "steps 1, 4"
+    pub needed: String,
+    /// What the driver offers
+    pub available: String,
+    /// None if the driver doesn't know
+    pub supported: Option<bool>,
+}
+
+/// Scenario checked against a driver's capabilities
+#[derive(Debug, Clone)]
+pub struct CompatibilityReport {
+    pub driver: String,
+    pub checks: Vec<Check>,
+}
+
+impl CompatibilityReport {
+    /// Checks the driver is known to fail
+    pub fn unsupported(&self) -> Vec<&Check> {
+        self.checks.iter().filter(|c| c.supported == Some(false)).collect()
+    }
+
+    pub fn is_compatible(&self) -> bool {
+        self.unsupported().is_empty()
+    }
+
+    /// The table, one line per check
+    pub fn lines(&self) -> Vec<String> {
+        let width = |f: fn(&Check) -> &str, title: &str| {
+            self.checks.iter().map(|c| f(c).len()).max().unwrap_or(0).max(title.len())
+        };
+        let requirement = width(|c| &c.requirement, "Requirement");
+        let needed = width(|c| &c.needed, "Scenario");
+        let available = width(|c| &c.available, "Driver");
+        let mut lines = vec![format!(
+            "{:<requirement$}  {:<needed$}  {:<available$}  Status",
+            "Requirement", "Scenario", "Driver"
+        )];
+        for check in &self.checks {
+            let status = match check.supported {
+                Some(true) => "OK",
+                Some(false) => "UNSUPPORTED",
+                None => "unknown",
+            };
+            lines.push(format!(
+                "{:<requirement$}  {:<needed$}  {:<available$}  {}",
+                check.requirement, check.needed, check.available, status
+            ));
+        }
+        lines
+    }
+
+    pub fn print(&self) {
+        println!("Capabilities of the {} driver:", self.driver);
+        for line in self.lines() {
+            println!("  {}", line);
+        }
+        let unsupported = self.unsupported().len();
+        if unsupported > 0 {
+            println!("WARNING: {} of {} requirements are not supported", unsupported, self.checks.len());
+        }
+        println!();
+    }
+}
+
+/// Check `scenario` against the capabilities of the `driver` driver
+pub fn check(scenario: &Scenario, capabilities: &Capabilities, driver: &str) -> CompatibilityReport {
+    let mut checks = Vec::new();
+
+    // Effect types in order of first use, with the steps using them
+    let mut types: Vec<(&'static str, Vec<usize>)> = Vec::new();
+    for (index, step) in scenario.steps.iter().enumerate() {
+        let label = effect_label(&step.effect);
+        match types.iter_mut().find(|(l, _)| *l == label) {
+            Some((_, steps)) => steps.push(index + 1),
+            None => types.push((label, vec![index + 1])),
+        }
+    }
+    for (label, steps) in types {
+        let supported = capabilities.effects.contains(&label);
+        checks.push(Check {
+            requirement: label.to_string(),
+            needed: step_list(&steps),
+            available: if supported { "supported" } else { "not supported" }.to_string(),
+            supported: Some(supported),
+        });
+    }
+
+    let longest = |value: fn(&ScenarioStep) -> u32| {
+        scenario
+            .steps
+            .iter()
+            .enumerate()
+            .map(|(index, step)| (value(step), index + 1))
+            .max_by_key(|(ms, index)| (*ms, std::cmp::Reverse(*index)))
+    };
+    let durations = [
+        ("Longest duration", longest(|s| s.effect.duration()), capabilities.max_duration_ms),
+        ("Longest start delay", longest(|s| s.effect.start_delay()), capabilities.max_start_delay_ms),
+    ];
+    for (requirement, needed, limit) in durations {
+        let Some((ms, step)) = needed else { continue };
+        checks.push(Check {
+            requirement: requirement.to_string(),
+            needed: format!("{} ms (step {})", ms, step),
+            available: limit.map_or_else(|| "unlimited".to_string(), |limit| format!("{} ms", limit)),
+            supported: Some(limit.is_none_or(|limit| ms <= limit)),
+        });
+    }
+
+    let playing = max_playing(scenario);
+    checks.push(Check {
+        requirement: "Effects at once".to_string(),
+        needed: playing.to_string(),
+        available: capabilities.max_playing.map_or_else(|| "unknown".to_string(), |max| max.to_string()),
+        supported: capabilities.max_playing.map(|max| playing <= max),
+    });
+
+    CompatibilityReport {
+        driver: driver.to_string(),
+        checks,
+    }
+}
+
+/// Most effects playing at once: each step's own, plus the held effects under it
+fn max_playing(scenario: &Scenario) -> usize {
+    let mut held: Vec<Option<usize>> = Vec::new();
+    let mut max = 0;
+    for (index, step) in scenario.steps.iter().enumerate() {
+        held.retain(|stop_at| *stop_at != Some(index + 1));
+        max = max.max(held.len() + 1);
+        if step.holds() {
+            held.push(step.stop_at);
+        }
+    }
+    max
+}
+
+/// Longest step list written out in full
+const MAX_LISTED_STEPS: usize = 5;
+
+/// "step 3", "steps 1, 4, 7", or "steps 1, 2, 3, ... (40 steps)" for long lists
+fn step_list(steps: &[usize]) -> String {
+    let numbers: Vec<String> = steps.iter().take(MAX_LISTED_STEPS).map(|s| s.to_string()).collect();
+    match steps.len() {
+        1 => format!("step {}", numbers[0]),
+        n if n > MAX_LISTED_STEPS => format!("steps {}, ... ({} steps)", numbers[..3].join(", "), n),
+        _ => format!("steps {}", numbers.join(", ")),
+    }
+}
@@ -0,0 +1,388 @@
+//! Capture files: step outputs as text, one hex packet per line under `# Step` markers
+//!
+//! Packets the decoders know are followed by their decoded command as a comment
+//! (`01 05 01 88 13 ...  # SetConstant slot=1 ...`) for human review; reading a
+//! capture drops these comments, so they never take part in comparisons.
+//!
+//! Long recordings are written step by step with [`CaptureWriter`], so a crash
+//! late in a run keeps the steps completed before it. Large captures (soak runs of
+//! hundreds of MB) are read step by step with [`CaptureReader`], which only keeps
+//! an index of where each step starts in memory.
+
+use crate::compare::parse_hex;
+use crate::normalize::decode_packet;
+use crate::{cadence, health, latency, telemetry, DeviceCapture, StepOutput};
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Prefix of the line starting a device section in capture files
+const DEVICE_PREFIX: &str = "# Device ";
+
+/// Write buffer of capture files, large enough for a step of a multi-kHz capture
+const WRITE_BUFFER_SIZE: usize = 256 * 1024;
+
+/// How long steps may sit in `CaptureWriter`'s buffer before they are flushed
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Write step outputs to a capture file with step markers
+/// Returns total number of packets written
+pub fn write_capture_file(path: &PathBuf, step_outputs: &[StepOutput]) -> anyhow::Result<usize> {
+    let mut file = BufWriter::with_capacity(WRITE_BUFFER_SIZE, fs::File::create(path)?);
+    for step_output in step_outputs {
+        write_step(&mut file, step_output)?;
+    }
+    file.flush()?;
+    Ok(step_outputs.iter().map(|s| s.packets.len()).sum())
+}
+
+/// Capture file written one step at a time as a run plays
+/// Steps go to a spool file next to `path` (`<name>.partial`), which takes the
+/// capture's name once the run is finished. A run that fails leaves the spool
+/// behind with every step completed before; the spool is flushed at least every
+/// `FLUSH_INTERVAL`, so a killed process loses at most the last second of steps.
+pub struct CaptureWriter {
+    path: PathBuf,
+    spool: PathBuf,
+    file: BufWriter<fs::File>,
+    last_flush: Instant,
+    total_steps: usize,
+    total_packets: usize,
+}
+
+impl CaptureWriter {
+    /// Start a capture that becomes `path` once finished, writing to its spool file
+    /// next to it until then
+    pub fn create(path: &Path) -> anyhow::Result<Self> {
+        let spool = spool_path(path);
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: BufWriter::with_capacity(WRITE_BUFFER_SIZE, fs::File::create(&spool)?),
+            spool,
+            last_flush: Instant::now(),
+            total_steps: 0,
+            total_packets: 0,
+        })
+    }
+
+    /// Append `step_output`, flushing the spool file if the last flush was a while ago
+    pub fn write_step(&mut self, step_output: &StepOutput) -> anyhow::Result<()> {
+        write_step(&mut self.file, step_output)?;
+        self.total_steps += 1;
+        self.total_packets += step_output.packets.len();
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write the buffered steps to the spool file now, e.g. once the run is interrupted
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.file.flush()?;
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+
+    /// Steps written so far
+    pub fn total_steps(&self) -> usize {
+        self.total_steps
+    }
+
+    /// Packets of the steps written so far
+    pub fn total_packets(&self) -> usize {
+        self.total_packets
+    }
+
+    /// Give the spool file the capture's name; returns total number of packets written
+    pub fn finish(mut self) -> anyhow::Result<usize> {
+        self.file.flush()?;
+        drop(self.file);
+        fs::rename(&self.spool, &self.path)?;
+        Ok(self.total_packets)
+    }
+}
+
+/// Spool file of a capture written by `CaptureWriter`, e.g. "runs/test.run.partial"
+pub fn spool_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".partial");
+    PathBuf::from(name)
+}
+
+/// Write a step marker and everything captured during the step
+fn write_step(out: &mut impl Write, step_output: &StepOutput) -> std::io::Result<()> {
+    writeln!(out, "# Step {}: {}", step_output.step_index, step_output.step_name)?;
+    for packet in &step_output.packets {
+        write_packet(out, packet)?;
+    }
+    if !step_output.latencies_us.is_empty() {
+        writeln!(out, "{}", latency::format_line(&step_output.latencies_us))?;
+    }
+    if let Some(latency_us) = step_output.wire_latency_us {
+        writeln!(out, "{}", latency::format_wire_line(latency_us))?;
+    }
+    if !step_output.timestamps_us.is_empty() {
+        writeln!(out, "{}", cadence::format_line(&step_output.timestamps_us))?;
+    }
+    if !step_output.axis.is_empty() {
+        writeln!(out, "{}", telemetry::format_line(&step_output.axis))?;
+    }
+    if let Some(health) = &step_output.health {
+        writeln!(out, "{}", health::format_line(health))?;
+    }
+    for capture in &step_output.devices {
+        writeln!(out, "{}{}", DEVICE_PREFIX, capture.device)?;
+        for packet in &capture.packets {
+            write_packet(out, packet)?;
+        }
+    }
+    for variant in &step_output.variants {
+        writeln!(out, "# Variant")?;
+        for packet in variant {
+            write_packet(out, packet)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write a packet line, annotated with its decoded command if a decoder knows it
+fn write_packet(out: &mut impl Write, packet: &str) -> std::io::Result<()> {
+    match parse_hex(packet).and_then(|bytes| decode_packet(&bytes)) {
+        Some(decoded) => writeln!(out, "{}  # {}", packet, decoded),
+        None => writeln!(out, "{}", packet),
+    }
+}
+
+/// Parse a capture file with step markers into StepOutput list
+pub fn parse_capture_file(path: &PathBuf) -> anyhow::Result<Vec<StepOutput>> {
+    parse_capture(&fs::read_to_string(path)?)
+}
+
+/// Where a step of a capture file is, found by `CaptureReader::open`
+#[derive(Debug, Clone)]
+pub struct StepEntry {
+    /// Index from the step's marker, the position in the file if it has none
+    pub step_index: usize,
+    /// Name from the step's marker
+    pub step_name: String,
+    /// Byte range of the step's lines, from its `# Step` marker
+    pub offset: u64,
+    /// Length of the byte range in bytes
+    pub len: u64,
+}
+
+/// Capture file read one step at a time
+/// Opening it reads the file once to find the step markers; each step is then read
+/// and parsed when asked for, so memory holds one step instead of the whole file.
+#[derive(Debug)]
+pub struct CaptureReader {
+    /// Locked for each seek and read, so steps can be read from several threads
+    file: Mutex<fs::File>,
+    entries: Vec<StepEntry>,
+}
+
+impl CaptureReader {
+    /// Open the capture file at `path` and index its steps
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = fs::File::open(path)?;
+        let mut reader = BufReader::new(&file);
+        let mut entries: Vec<StepEntry> = Vec::new();
+        let mut line = Vec::new();
+        let mut offset = 0u64;
+        // Packets before the first marker form an implicit step 1, as in `parse_capture`
+        let mut preamble_packets = false;
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line)? as u64;
+            if read == 0 {
+                break;
+            }
+            let text = String::from_utf8_lossy(&line);
+            let text = text.trim();
+            if let Some(rest) = text.strip_prefix("# Step ") {
+                if let Some((index, name)) = rest.split_once(':') {
+                    if entries.is_empty() && preamble_packets {
+                        entries.push(StepEntry {
+                            step_index: 1,
+                            step_name: "Unknown".to_string(),
+                            offset: 0,
+                            len: offset,
+                        });
+                    }
+                    if let Some(last) = entries.last_mut() {
+                        last.len = offset - last.offset;
+                    }
+                    entries.push(StepEntry {
+                        step_index: index.trim().parse().unwrap_or(entries.len() + 1),
+                        step_name: name.trim().to_string(),
+                        offset,
+                        len: 0,
+                    });
+                }
+            } else if entries.is_empty() && !text.is_empty() && !text.starts_with('#') {
+                preamble_packets = true;
+            }
+            offset += read;
+        }
+        if entries.is_empty() && preamble_packets {
+            entries.push(StepEntry {
+                step_index: 1,
+                step_name: "Unknown".to_string(),
+                offset: 0,
+                len: 0,
+            });
+        }
+        if let Some(last) = entries.last_mut() {
+            last.len = offset - last.offset;
+        }
+        Ok(Self {
+            file: Mutex::new(file),
+            entries,
+        })
+    }
+
+    /// Steps in file order
+    pub fn entries(&self) -> &[StepEntry] {
+        &self.entries
+    }
+
+    /// Number of steps in the file
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// True if the file has no steps
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Read and parse the step at `position` (0-based, in file order); safe to call
+    /// from several threads at once
+    pub fn step(&self, position: usize) -> anyhow::Result<StepOutput> {
+        let entry = self
+            .entries
+            .get(position)
+            .ok_or_else(|| anyhow::anyhow!("capture has no step at position {}", position + 1))?;
+        let mut content = Vec::with_capacity(entry.len as usize);
+        {
+            let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+            file.seek(SeekFrom::Start(entry.offset))?;
+            (&mut *file).take(entry.len).read_to_end(&mut content)?;
+        }
+        parse_capture(&String::from_utf8_lossy(&content))?
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("step {} of the capture is empty", entry.step_index))
+    }
+
+    /// Every step in file order, each read when the iterator reaches it
+    pub fn steps(&self) -> impl Iterator<Item = anyhow::Result<StepOutput>> + '_ {
+        (0..self.entries.len()).map(|position| self.step(position))
+    }
+}
+
+/// Parse capture file contents, e.g. loaded by a browser viewer
+pub fn parse_capture(content: &str) -> anyhow::Result<Vec<StepOutput>> {
+    let mut steps: Vec<StepOutput> = Vec::new();
+    let mut current_step: Option<StepOutput> = None;
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with("# Step ") {
+            // Save previous step if any
+            if let Some(step) = current_step.take() {
+                steps.push(step);
+            }
+
+            // Parse step header: "# Step N: Name"
+            let rest = &line[7..]; // Skip "# Step "
+            if let Some(colon_pos) = rest.find(':') {
+                let step_index = rest[..colon_pos].trim().parse::<usize>().unwrap_or(steps.len() + 1);
+                let step_name = rest[colon_pos + 1..].trim().to_string();
+                current_step = Some(StepOutput {
+                    step_index,
+                    step_name,
+                    packets: Vec::new(),
+                    variants: Vec::new(),
+                    latencies_us: Vec::new(),
+                    wire_latency_us: None,
+                    timestamps_us: Vec::new(),
+                    axis: Vec::new(),
+                    devices: Vec::new(),
+                    health: None,
+                });
+            }
+        } else if let Some(latencies_us) = latency::parse_line(line) {
+            if let Some(ref mut step) = current_step {
+                step.latencies_us = latencies_us;
+            }
+        } else if let Some(latency_us) = latency::parse_wire_line(line) {
+            if let Some(ref mut step) = current_step {
+                step.wire_latency_us = Some(latency_us);
+            }
+        } else if let Some(timestamps_us) = cadence::parse_line(line) {
+            if let Some(ref mut step) = current_step {
+                step.timestamps_us = timestamps_us;
+            }
+        } else if let Some(axis) = telemetry::parse_line(line) {
+            if let Some(ref mut step) = current_step {
+                step.axis = axis;
+            }
+        } else if let Some(health) = health::parse_line(line) {
+            if let Some(ref mut step) = current_step {
+                step.health = Some(health);
+            }
+        } else if let Some(device) = line.strip_prefix(DEVICE_PREFIX) {
+            // Start the section of an additional capture device
+            if let Some(ref mut step) = current_step {
+                step.devices.push(DeviceCapture {
+                    device: device.trim().to_string(),
+                    packets: Vec::new(),
+                });
+            }
+        } else if line == "# Variant" {
+            // Start an alternative packet sequence for the current step
+            if let Some(ref mut step) = current_step {
+                step.variants.push(Vec::new());
+            }
+        } else if !line.starts_with('#') {
+            // Packet data, without its decoded annotation
+            let line = line.split_once('#').map_or(line, |(packet, _)| packet.trim_end());
+            if let Some(ref mut step) = current_step {
+                if let Some(variant) = step.variants.last_mut() {
+                    variant.push(line.to_string());
+                } else if let Some(capture) = step.devices.last_mut() {
+                    capture.packets.push(line.to_string());
+                } else {
+                    step.packets.push(line.to_string());
+                }
+            } else {
+                // No step header yet - create implicit step 1
+                current_step = Some(StepOutput {
+                    step_index: 1,
+                    step_name: "Unknown".to_string(),
+                    packets: vec![line.to_string()],
+                    variants: Vec::new(),
+                    latencies_us: Vec::new(),
+                    wire_latency_us: None,
+                    timestamps_us: Vec::new(),
+                    axis: Vec::new(),
+                    devices: Vec::new(),
+                    health: None,
+                });
+            }
+        }
+    }
+
+    // Don't forget the last step
+    if let Some(step) = current_step {
+        steps.push(step);
+    }
+
+    Ok(steps)
+}
@@ -0,0 +1,784 @@
+//! Step-by-step comparison of captured driver output
+//!
+//! Produces structured results that are printed to the terminal
+//! and consumed by the report writers.
+
+use crate::cadence::TimingThresholds;
+use crate::diff_view;
+use crate::error::{FFBError, FFBResult};
+use crate::{CaptureReader, StepOutput};
+use std::borrow::Cow;
+
+/// Result of comparing a single step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepStatus {
+    /// Packets are identical
+    Match,
+    /// Step present in both runs but packets differ
+    Mismatch,
+    /// Step present in expected capture only
+    Missing,
+    /// Step present in actual run only
+    Extra,
+    /// Packets match but their timing exceeds the thresholds
+    Timing,
+}
+
+/// Difference for a single packet position within a step
+#[derive(Debug, Clone)]
+pub enum PacketDiff {
+    /// Both sides have a packet here, with different bytes
+    Differs {
+        /// 0-based packet position within the step
+        index: usize,
+        /// Expected packet as hex
+        expected: String,
+        /// Actual packet as hex
+        actual: String,
+    },
+    /// Only the expected step has a packet here
+    Missing {
+        /// 0-based packet position within the step
+        index: usize,
+        /// Expected packet as hex
+        expected: String,
+    },
+    /// Only the actual step has a packet here
+    Extra {
+        /// 0-based packet position within the step
+        index: usize,
+        /// Actual packet as hex
+        actual: String,
+    },
+}
+
+/// Comparison of one step between expected and actual output
+#[derive(Debug, Clone)]
+pub struct StepComparison {
+    /// 1-based step index, from the actual step if there is one
+    pub step_index: usize,
+    /// Effect label of the step
+    pub step_name: String,
+    /// Outcome of the comparison
+    pub status: StepStatus,
+    /// Packets in the expected sequence the step was compared against
+    pub expected_packets: usize,
+    /// Packets in the actual step
+    pub actual_packets: usize,
+    /// Packets identical at the same position
+    pub matched_packets: usize,
+    /// Byte-level similarity in percent (0-100)
+    pub similarity: f64,
+    /// Differing packets, empty in counts mode
+    pub packet_diffs: Vec<PacketDiff>,
+    /// Expected packet sequence the step was compared against
+    pub expected: Vec<String>,
+    /// Actual packet sequence
+    pub actual: Vec<String>,
+    /// Timing thresholds the actual step exceeds
+    pub timing_violations: Vec<String>,
+}
+
+impl StepComparison {
+    /// Packets (or counts) match, and their timing is within the thresholds
+    pub fn is_match(&self) -> bool {
+        self.status == StepStatus::Match
+    }
+}
+
+/// Wildcard byte in capture files, matches any value
+pub const WILDCARD_BYTE: &str = "??";
+
+/// Compare two hex bytes, honoring wildcards
+pub fn bytes_match(expected: &str, actual: &str) -> bool {
+    expected == WILDCARD_BYTE || actual == WILDCARD_BYTE || expected.eq_ignore_ascii_case(actual)
+}
+
+/// Compare two hex packets byte by byte, honoring wildcards
+pub fn packets_match(expected: &str, actual: &str) -> bool {
+    let expected: Vec<&str> = expected.split_whitespace().collect();
+    let actual: Vec<&str> = actual.split_whitespace().collect();
+    expected.len() == actual.len() && expected.iter().zip(&actual).all(|(e, a)| bytes_match(e, a))
+}
+
+/// Compare two packet sequences, honoring wildcards
+pub fn sequences_match(expected: &[String], actual: &[String]) -> bool {
+    expected.len() == actual.len() && expected.iter().zip(actual).all(|(e, a)| packets_match(e, a))
+}
+
+/// Compare expected and actual step outputs position by position
+pub fn compare_steps(expected_steps: &[StepOutput], actual_steps: &[StepOutput]) -> Vec<StepComparison> {
+    let max_steps = expected_steps.len().max(actual_steps.len());
+    let mut results = Vec::with_capacity(max_steps);
+
+    for position in 0..max_steps {
+        let expected = expected_steps.get(position);
+        let actual = actual_steps.get(position);
+
+        let result = match (expected, actual) {
+            (Some(exp), Some(act)) => {
+                let expected_packets = best_variant(exp, &act.packets);
+                let packet_diffs = diff_packets(expected_packets, &act.packets);
+                let matched_packets = expected_packets
+                    .iter()
+                    .zip(&act.packets)
+                    .filter(|(e, a)| packets_match(e, a))
+                    .count();
+                StepComparison {
+                    step_index: act.step_index,
+                    step_name: act.step_name.clone(),
+                    status: if sequences_match(expected_packets, &act.packets) {
+                        StepStatus::Match
+                    } else {
+                        StepStatus::Mismatch
+                    },
+                    expected_packets: expected_packets.len(),
+                    actual_packets: act.packets.len(),
+                    matched_packets,
+                    similarity: byte_similarity(expected_packets, &act.packets),
+                    packet_diffs,
+                    expected: expected_packets.to_vec(),
+                    actual: act.packets.clone(),
+                    timing_violations: Vec::new(),
+                }
+            }
+            (Some(exp), None) => StepComparison {
+                step_index: exp.step_index,
+                step_name: exp.step_name.clone(),
+                status: StepStatus::Missing,
+                expected_packets: exp.packets.len(),
+                actual_packets: 0,
+                matched_packets: 0,
+                similarity: 0.0,
+                packet_diffs: Vec::new(),
+                expected: exp.packets.clone(),
+                actual: Vec::new(),
+                timing_violations: Vec::new(),
+            },
+            (None, Some(act)) => StepComparison {
+                step_index: act.step_index,
+                step_name: act.step_name.clone(),
+                status: StepStatus::Extra,
+                expected_packets: 0,
+                actual_packets: act.packets.len(),
+                matched_packets: 0,
+                similarity: 0.0,
+                packet_diffs: Vec::new(),
+                expected: Vec::new(),
+                actual: act.packets.clone(),
+                timing_violations: Vec::new(),
+            },
+            (None, None) => unreachable!(),
+        };
+        results.push(result);
+    }
+
+    results
+}
+
+/// What is compared between expected and actual steps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum CompareMode {
+    /// Packet payloads must match exactly
+    #[default]
+    Packets,
+    /// Only the number of packets per step is compared
+    Counts,
+}
+
+/// Options controlling how runs are compared
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompareOptions {
+    /// Decode both sides to canonical commands first
+    pub normalize: bool,
+    /// Whether payloads or only packet counts are compared
+    pub mode: CompareMode,
+    /// Allowed packet count difference per step in counts mode
+    pub tolerance: usize,
+    /// Limits on the actual steps' packet timing
+    pub timing: TimingThresholds,
+}
+
+/// Compare step outputs according to the given options
+pub fn compare_runs(
+    expected_steps: &[StepOutput],
+    actual_steps: &[StepOutput],
+    options: &CompareOptions,
+) -> Vec<StepComparison> {
+    let mut results = match options.mode {
+        CompareMode::Counts => compare_counts(expected_steps, actual_steps, options.tolerance),
+        CompareMode::Packets if options.normalize => {
+            let (expected_steps, actual_steps) = crate::normalize::normalize_for_compare(expected_steps, actual_steps);
+            compare_steps(&expected_steps, &actual_steps)
+        }
+        CompareMode::Packets => compare_steps(expected_steps, actual_steps),
+    };
+    if options.timing.is_set() {
+        // Results are in step order, so the actual steps line up with the first ones
+        for (result, actual) in results.iter_mut().zip(actual_steps) {
+            result.timing_violations = options.timing.check(actual);
+            if result.status == StepStatus::Match && !result.timing_violations.is_empty() {
+                result.status = StepStatus::Timing;
+            }
+        }
+    }
+    results
+}
+
+/// `compare_runs` over two capture files, reading one pair of steps at a time so
+/// that captures too large to load whole can be compared; steps pair up by
+/// position, and the expected steps are validated as they are read
+pub fn compare_captures(
+    expected: &CaptureReader,
+    actual: &CaptureReader,
+    options: &CompareOptions,
+) -> anyhow::Result<Vec<StepComparison>> {
+    let mut results = Vec::with_capacity(expected.len().max(actual.len()));
+    for position in 0..expected.len().max(actual.len()) {
+        let expected_step: Vec<StepOutput> = (position < expected.len())
+            .then(|| expected.step(position))
+            .transpose()?
+            .into_iter()
+            .collect();
+        validate_expected(&expected_step)?;
+        let actual_step: Vec<StepOutput> = (position < actual.len())
+            .then(|| actual.step(position))
+            .transpose()?
+            .into_iter()
+            .collect();
+        results.extend(compare_runs(&expected_step, &actual_step, options));
+    }
+    Ok(results)
+}
+
+/// Steps a run is compared with: already loaded, or read from a capture file one
+/// step at a time so a large golden capture never has to be in memory whole
+#[derive(Debug, Clone, Copy)]
+pub enum ExpectedSteps<'a> {
+    /// Steps in memory, e.g. from `parse_capture_file`
+    Loaded(&'a [StepOutput]),
+    /// Steps of a capture file, each read when it is compared
+    Capture(&'a CaptureReader),
+}
+
+impl<'a> From<&'a [StepOutput]> for ExpectedSteps<'a> {
+    fn from(steps: &'a [StepOutput]) -> Self {
+        ExpectedSteps::Loaded(steps)
+    }
+}
+
+impl<'a> From<&'a Vec<StepOutput>> for ExpectedSteps<'a> {
+    fn from(steps: &'a Vec<StepOutput>) -> Self {
+        ExpectedSteps::Loaded(steps)
+    }
+}
+
+impl<'a> From<&'a CaptureReader> for ExpectedSteps<'a> {
+    fn from(reader: &'a CaptureReader) -> Self {
+        ExpectedSteps::Capture(reader)
+    }
+}
+
+impl<'a> ExpectedSteps<'a> {
+    /// Number of expected steps
+    pub fn len(&self) -> usize {
+        match self {
+            ExpectedSteps::Loaded(steps) => steps.len(),
+            ExpectedSteps::Capture(reader) => reader.len(),
+        }
+    }
+
+    /// True if there are no expected steps
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Step at `position` (0-based, in file order), None past the last one
+    pub fn step(&self, position: usize) -> anyhow::Result<Option<Cow<'a, StepOutput>>> {
+        match self {
+            ExpectedSteps::Loaded(steps) => Ok(steps.get(position).map(Cow::Borrowed)),
+            ExpectedSteps::Capture(reader) if position < reader.len() => Ok(Some(Cow::Owned(reader.step(position)?))),
+            ExpectedSteps::Capture(_) => Ok(None),
+        }
+    }
+
+    /// `validate_expected` over every step, reading a capture one step at a time
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match self {
+            ExpectedSteps::Loaded(steps) => Ok(validate_expected(steps)?),
+            ExpectedSteps::Capture(reader) => {
+                for step in reader.steps() {
+                    validate_expected(std::slice::from_ref(&step?))?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// `compare_runs` of `actual` against these steps, one pair of steps at a time
+    pub fn compare(&self, actual: &[StepOutput], options: &CompareOptions) -> anyhow::Result<Vec<StepComparison>> {
+        if let ExpectedSteps::Loaded(steps) = self {
+            return Ok(compare_runs(steps, actual, options));
+        }
+        let mut results = Vec::with_capacity(self.len().max(actual.len()));
+        for position in 0..self.len().max(actual.len()) {
+            results.extend(self.compare_step(position, actual.get(position), options)?);
+        }
+        Ok(results)
+    }
+
+    /// `compare_runs` of the actual step at `position` against the expected one, e.g.
+    /// as each step of a run completes; None compares a step the run did not play
+    /// Empty past the last expected step without an actual one.
+    pub fn compare_step(
+        &self,
+        position: usize,
+        actual: Option<&StepOutput>,
+        options: &CompareOptions,
+    ) -> anyhow::Result<Vec<StepComparison>> {
+        let expected = self.step(position)?;
+        let expected = expected.as_deref().map(std::slice::from_ref).unwrap_or_default();
+        Ok(compare_runs(expected, actual.map(std::slice::from_ref).unwrap_or_default(), options))
+    }
+}
+
+/// Compare only the number of packets per step, within `tolerance` packets
+/// Payload bytes are ignored, for noisy devices where packet cadence is the signal
+pub fn compare_counts(expected_steps: &[StepOutput], actual_steps: &[StepOutput], tolerance: usize) -> Vec<StepComparison> {
+    compare_steps(expected_steps, actual_steps)
+        .into_iter()
+        .map(|mut result| {
+            if result.status == StepStatus::Mismatch || result.status == StepStatus::Match {
+                let within = result.expected_packets.abs_diff(result.actual_packets) <= tolerance;
+                result.status = if within { StepStatus::Match } else { StepStatus::Mismatch };
+                result.matched_packets = result.expected_packets.min(result.actual_packets);
+                result.similarity = percent(
+                    result.expected_packets.min(result.actual_packets),
+                    result.expected_packets.max(result.actual_packets),
+                );
+                result.packet_diffs.clear();
+            }
+            result
+        })
+        .collect()
+}
+
+/// Pick the expected packet sequence to compare against
+/// An exactly matching variant wins, otherwise the most similar one
+fn best_variant<'a>(expected: &'a StepOutput, actual: &[String]) -> &'a [String] {
+    let candidates = std::iter::once(&expected.packets).chain(&expected.variants);
+
+    if let Some(exact) = candidates.clone().find(|c| sequences_match(c, actual)) {
+        return exact;
+    }
+
+    // max_by keeps the last of equal elements, reverse so the primary sequence wins ties
+    candidates
+        .rev()
+        .max_by(|a, b| byte_similarity(a, actual).total_cmp(&byte_similarity(b, actual)))
+        .map(|c| c.as_slice())
+        .unwrap_or(&expected.packets)
+}
+
+/// List differing packets between two packet sequences
+fn diff_packets(expected: &[String], actual: &[String]) -> Vec<PacketDiff> {
+    let mut diffs = Vec::new();
+    let max_packets = expected.len().max(actual.len());
+
+    for i in 0..max_packets {
+        match (expected.get(i), actual.get(i)) {
+            (Some(e), Some(a)) if !packets_match(e, a) => diffs.push(PacketDiff::Differs {
+                index: i,
+                expected: e.clone(),
+                actual: a.clone(),
+            }),
+            (Some(e), None) => diffs.push(PacketDiff::Missing {
+                index: i,
+                expected: e.clone(),
+            }),
+            (None, Some(a)) => diffs.push(PacketDiff::Extra {
+                index: i,
+                actual: a.clone(),
+            }),
+            _ => {} // Match, skip
+        }
+    }
+
+    diffs
+}
+
+/// Percentage of bytes that match at the same packet and byte position
+/// Bytes present on only one side count as differing
+fn byte_similarity(expected: &[String], actual: &[String]) -> f64 {
+    let mut total_bytes = 0;
+    let mut matching_bytes = 0;
+    let max_packets = expected.len().max(actual.len());
+
+    for i in 0..max_packets {
+        let e: Vec<&str> = expected.get(i).map(|p| p.split_whitespace().collect()).unwrap_or_default();
+        let a: Vec<&str> = actual.get(i).map(|p| p.split_whitespace().collect()).unwrap_or_default();
+        total_bytes += e.len().max(a.len());
+        matching_bytes += e.iter().zip(&a).filter(|(x, y)| bytes_match(x, y)).count();
+    }
+
+    if total_bytes == 0 {
+        100.0
+    } else {
+        matching_bytes as f64 * 100.0 / total_bytes as f64
+    }
+}
+
+/// Aggregate metrics over all compared steps
+#[derive(Debug, Clone)]
+pub struct ComparisonSummary {
+    /// Compared steps, including missing and extra ones
+    pub total_steps: usize,
+    /// Steps that match
+    pub matched_steps: usize,
+    /// Packets in the larger of expected/actual, summed over steps
+    pub total_packets: usize,
+    /// Packets identical at the same position, summed over steps
+    pub matched_packets: usize,
+    /// Position of the step with the lowest similarity, if any step differs
+    pub largest_divergence: Option<usize>,
+}
+
+impl ComparisonSummary {
+    /// Summary of the results of `compare_runs` or one of its variants
+    pub fn from_results(results: &[StepComparison]) -> Self {
+        let largest_divergence = results
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| !r.is_match())
+            .min_by(|(_, a), (_, b)| a.similarity.total_cmp(&b.similarity))
+            .map(|(i, _)| i);
+
+        ComparisonSummary {
+            total_steps: results.len(),
+            matched_steps: results.iter().filter(|r| r.is_match()).count(),
+            total_packets: results.iter().map(|r| r.expected_packets.max(r.actual_packets)).sum(),
+            matched_packets: results.iter().map(|r| r.matched_packets).sum(),
+            largest_divergence,
+        }
+    }
+}
+
+fn percent(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        100.0
+    } else {
+        part as f64 * 100.0 / total as f64
+    }
+}
+
+/// Print aggregate comparison metrics to stdout
+pub fn print_summary(results: &[StepComparison]) {
+    let summary = ComparisonSummary::from_results(results);
+
+    println!("\n=== Summary ===");
+    println!(
+        "Steps matched:   {}/{} ({:.1}%)",
+        summary.matched_steps,
+        summary.total_steps,
+        percent(summary.matched_steps, summary.total_steps)
+    );
+    println!(
+        "Packets matched: {}/{} ({:.1}%)",
+        summary.matched_packets,
+        summary.total_packets,
+        percent(summary.matched_packets, summary.total_packets)
+    );
+    println!("Byte similarity per step:");
+    for result in results {
+        println!(
+            "  Step {}: {} - {:.1}%",
+            result.step_index, result.step_name, result.similarity
+        );
+    }
+    if let Some(position) = summary.largest_divergence {
+        let step = &results[position];
+        println!(
+            "Largest divergence: Step {}: {} ({:.1}% similar)",
+            step.step_index, step.step_name, step.similarity
+        );
+    }
+}
+
+/// Describe a packet difference in human-readable form
+pub fn describe_packet_diff(diff: &PacketDiff) -> String {
+    match diff {
+        PacketDiff::Differs { index, expected, actual } => format!(
+            "Packet {} differs:\n  Expected: {}\n  Actual:   {}",
+            index + 1,
+            expected,
+            actual
+        ),
+        PacketDiff::Missing { index, expected } => {
+            format!("Packet {} missing in actual:\n  Expected: {}", index + 1, expected)
+        }
+        PacketDiff::Extra { index, actual } => {
+            format!("Packet {} extra in actual:\n  Actual:   {}", index + 1, actual)
+        }
+    }
+}
+
+/// Print comparison results to stdout
+pub fn print_comparison(results: &[StepComparison]) {
+    for result in results {
+        match result.status {
+            StepStatus::Match => {}
+            StepStatus::Mismatch => {
+                println!("MISMATCH Step {}: {}", result.step_index, result.step_name);
+                println!(
+                    "  Expected {} packets, got {} packets",
+                    result.expected_packets, result.actual_packets
+                );
+                if !result.packet_diffs.is_empty() {
+                    diff_view::print_side_by_side(&result.expected, &result.actual);
+                }
+                if !result.timing_violations.is_empty() {
+                    println!("  Timing: {}", result.timing_violations.join(", "));
+                }
+                println!();
+            }
+            StepStatus::Missing => {
+                println!(
+                    "MISSING Step {}: {} (expected {} packets)",
+                    result.step_index, result.step_name, result.expected_packets
+                );
+                println!();
+            }
+            StepStatus::Extra => {
+                println!(
+                    "EXTRA Step {}: {} (got {} packets)",
+                    result.step_index, result.step_name, result.actual_packets
+                );
+                println!();
+            }
+            StepStatus::Timing => {
+                println!("TIMING Step {}: {}", result.step_index, result.step_name);
+                println!("  {}", result.timing_violations.join(", "));
+                println!();
+            }
+        }
+    }
+}
+
+/// Byte offsets at which two hex packets differ
+/// Bytes present in only one of the packets count as differing
+pub fn differing_byte_offsets(expected: &str, actual: &str) -> Vec<usize> {
+    let expected: Vec<&str> = expected.split_whitespace().collect();
+    let actual: Vec<&str> = actual.split_whitespace().collect();
+    let max_len = expected.len().max(actual.len());
+
+    (0..max_len)
+        .filter(|&i| match (expected.get(i), actual.get(i)) {
+            (Some(e), Some(a)) => !bytes_match(e, a),
+            _ => true,
+        })
+        .collect()
+}
+
+/// Check that every packet of the expected steps is hex bytes or wildcards
+/// A typo in a hand-edited golden would otherwise only show up as a mismatch.
+pub fn validate_expected(steps: &[StepOutput]) -> FFBResult<()> {
+    for step in steps {
+        let sequences = std::iter::once(&step.packets)
+            .chain(&step.variants)
+            .chain(step.devices.iter().map(|d| &d.packets));
+        for packets in sequences {
+            for (index, packet) in packets.iter().enumerate() {
+                let bad = packet
+                    .split_whitespace()
+                    .find(|b| *b != WILDCARD_BYTE && (b.len() > 2 || u8::from_str_radix(b, 16).is_err()));
+                if let Some(bad) = bad {
+                    return Err(FFBError::ComparisonFailed {
+                        message: format!("'{}' is not a hex byte", bad),
+                        step: Some(step.step_index),
+                        packet: Some(index + 1),
+                    });
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parse a hex string like "01 0A FF" into bytes
+pub fn parse_hex(packet: &str) -> Option<Vec<u8>> {
+    packet
+        .split_whitespace()
+        .map(|b| u8::from_str_radix(b, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(index: usize, packets: &[&str]) -> StepOutput {
+        StepOutput {
+            step_index: index,
+            step_name: format!("Step {}", index),
+            packets: packets.iter().map(|p| p.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn identical_steps_match() {
+        let expected = [step(1, &["01 02 03", "04 05"]), step(2, &[])];
+        let results = compare_steps(&expected, &expected.clone());
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(StepComparison::is_match));
+        assert_eq!(results[0].matched_packets, 2);
+        assert_eq!(results[0].similarity, 100.0);
+        assert!(results[0].packet_diffs.is_empty());
+    }
+
+    #[test]
+    fn hex_case_is_ignored() {
+        assert!(packets_match("0a ff", "0A FF"));
+    }
+
+    #[test]
+    fn differing_packets_mismatch() {
+        let results = compare_steps(&[step(1, &["01 02 03", "04 05"])], &[step(1, &["01 02 FF", "04 05"])]);
+        let result = &results[0];
+        assert_eq!(result.status, StepStatus::Mismatch);
+        assert_eq!(result.matched_packets, 1);
+        // 4 of the 5 bytes match
+        assert_eq!(result.similarity, 80.0);
+        match result.packet_diffs.as_slice() {
+            [PacketDiff::Differs { index: 0, expected, actual }] => {
+                assert_eq!(expected, "01 02 03");
+                assert_eq!(actual, "01 02 FF");
+            }
+            other => panic!("unexpected diffs {:?}", other),
+        }
+    }
+
+    #[test]
+    fn packet_count_differences_are_listed() {
+        let results = compare_steps(&[step(1, &["01", "02"])], &[step(1, &["01"])]);
+        assert_eq!(results[0].status, StepStatus::Mismatch);
+        assert!(matches!(results[0].packet_diffs.as_slice(), [PacketDiff::Missing { index: 1, .. }]));
+
+        let results = compare_steps(&[step(1, &["01"])], &[step(1, &["01", "02"])]);
+        assert_eq!(results[0].status, StepStatus::Mismatch);
+        assert!(matches!(results[0].packet_diffs.as_slice(), [PacketDiff::Extra { index: 1, .. }]));
+    }
+
+    #[test]
+    fn packets_of_different_lengths_mismatch() {
+        assert!(!packets_match("01 02", "01 02 03"));
+        assert!(!packets_match("01 ?? ??", "01 02"));
+    }
+
+    #[test]
+    fn missing_and_extra_steps() {
+        let results = compare_steps(&[step(1, &["01"]), step(2, &["02"])], &[step(1, &["01"])]);
+        assert_eq!(results[1].status, StepStatus::Missing);
+        assert_eq!(results[1].step_index, 2);
+        assert_eq!(results[1].expected_packets, 1);
+        assert_eq!(results[1].actual_packets, 0);
+
+        let results = compare_steps(&[step(1, &["01"])], &[step(1, &["01"]), step(2, &["02", "03"])]);
+        assert_eq!(results[1].status, StepStatus::Extra);
+        assert_eq!(results[1].step_index, 2);
+        assert_eq!(results[1].actual_packets, 2);
+        assert!(!results[1].is_match());
+    }
+
+    #[test]
+    fn wildcards_match_any_byte() {
+        assert!(bytes_match("??", "7F"));
+        assert!(packets_match("01 ?? 03", "01 FF 03"));
+        assert!(!packets_match("01 ?? 03", "01 FF 04"));
+
+        let results = compare_steps(&[step(1, &["01 ?? ?? 04"])], &[step(1, &["01 AA BB 04"])]);
+        assert!(results[0].is_match());
+        assert_eq!(results[0].similarity, 100.0);
+        assert_eq!(differing_byte_offsets("01 ?? 03", "01 02 04 05"), vec![2, 3]);
+    }
+
+    #[test]
+    fn matching_variant_is_accepted() {
+        let mut expected = step(1, &["01 01"]);
+        expected.variants = vec![vec!["01 02".to_string()], vec!["01 03".to_string()]];
+
+        let results = compare_steps(&[expected.clone()], &[step(1, &["01 03"])]);
+        assert!(results[0].is_match());
+        assert_eq!(results[0].expected, vec!["01 03".to_string()]);
+
+        // Without a matching variant the primary sequence is reported on ties
+        let results = compare_steps(&[expected], &[step(1, &["01 04"])]);
+        assert_eq!(results[0].status, StepStatus::Mismatch);
+        assert_eq!(results[0].expected, vec!["01 01".to_string()]);
+    }
+
+    #[test]
+    fn counts_mode_compares_packet_counts_within_tolerance() {
+        let expected = [step(1, &["01", "02", "03"])];
+        let actual = [step(1, &["FF", "FF"])];
+        assert!(compare_counts(&expected, &actual, 1)[0].is_match());
+
+        let result = &compare_counts(&expected, &actual, 0)[0];
+        assert_eq!(result.status, StepStatus::Mismatch);
+        assert!(result.packet_diffs.is_empty());
+
+        let options = CompareOptions {
+            mode: CompareMode::Counts,
+            tolerance: 1,
+            ..Default::default()
+        };
+        assert!(compare_runs(&expected, &actual, &options)[0].is_match());
+    }
+
+    #[test]
+    fn invalid_expected_bytes_are_rejected() {
+        assert!(validate_expected(&[step(1, &["01 ?? FF"])]).is_ok());
+        assert!(validate_expected(&[step(1, &["01 0G"])]).is_err());
+        assert!(validate_expected(&[step(1, &["01 100"])]).is_err());
+    }
+
+    #[test]
+    fn wildcard_packets_match_when_normalized() {
+        let report = |byte: &str| format!("01 05 01 {} E0 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00", byte);
+        let expected = [step(1, &[&report("??"), &report("10")])];
+        let actual = [step(1, &[&report("C1"), &report("10")])];
+        let options = CompareOptions {
+            normalize: true,
+            ..Default::default()
+        };
+        let results = compare_runs(&expected, &actual, &options);
+        assert!(results[0].is_match(), "{:?}", results[0]);
+        // The packet without a wildcard is still compared decoded
+        assert_ne!(results[0].actual[1], report("10"));
+
+        let actual = [step(1, &[&report("C1"), &report("11")])];
+        assert_eq!(compare_runs(&expected, &actual, &options)[0].status, StepStatus::Mismatch);
+    }
+
+    #[test]
+    fn capture_steps_compare_like_loaded_steps() {
+        let expected = [step(1, &["01 02", "03"]), step(2, &["04 ??"]), step(3, &["05"])];
+        let actual = [step(1, &["01 02", "03"]), step(2, &["04 FF", "06"])];
+        let path = std::env::temp_dir().join(format!("ffb_replay_expected_{}.txt", std::process::id()));
+        crate::write_capture_file(&path, &expected).unwrap();
+        let reader = CaptureReader::open(&path).unwrap();
+
+        let options = CompareOptions::default();
+        let loaded = ExpectedSteps::from(&expected[..]).compare(&actual, &options).unwrap();
+        let streamed = ExpectedSteps::from(&reader).compare(&actual, &options).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(ExpectedSteps::from(&reader).validate().is_ok());
+        assert_eq!(streamed.len(), 3);
+        for (loaded, streamed) in loaded.iter().zip(&streamed) {
+            assert_eq!(loaded.status, streamed.status);
+            assert_eq!(loaded.step_index, streamed.step_index);
+            assert_eq!(loaded.packet_diffs.len(), streamed.packet_diffs.len());
+        }
+    }
+}
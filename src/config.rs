@@ -0,0 +1,64 @@
+//! Default options from `ffb_replay.yaml` in the current directory
+//!
+//! The file maps global option names (without the leading `--`) to values.
+//! They are passed before the command-line arguments, skipping any option the
+//! command line gives itself, so the command line always wins.
+
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+
+/// Config file looked up in the current directory
+pub const CONFIG_FILE: &str = "ffb_replay.yaml";
+
+#[derive(Debug, Default, Deserialize)]
+struct Config {
+    #[serde(default)]
+    options: BTreeMap<String, Value>,
+}
+
+/// Command-line arguments with the config defaults inserted after the program name
+pub fn with_defaults(args: Vec<OsString>, path: &Path) -> anyhow::Result<Vec<OsString>> {
+    if !path.exists() {
+        return Ok(args);
+    }
+    let content = fs::read_to_string(path)?;
+    let config: Config = serde_yaml::from_str(&content)
+        .map_err(|e| anyhow::anyhow!("Invalid config {}: {}", path.display(), e))?;
+
+    let mut defaults = Vec::new();
+    for (name, value) in &config.options {
+        let flag = format!("--{}", name);
+        let given = args.iter().skip(1).any(|arg| {
+            let arg = arg.to_string_lossy();
+            arg == flag || arg.starts_with(&format!("{}=", flag))
+        });
+        if given {
+            continue;
+        }
+        match value {
+            Value::Null | Value::Bool(false) => {}
+            Value::Bool(true) => defaults.push(OsString::from(flag)),
+            Value::Sequence(items) => {
+                let items: Vec<String> = items.iter().map(scalar).collect::<anyhow::Result<_>>()?;
+                defaults.push(OsString::from(format!("{}={}", flag, items.join(","))));
+            }
+            value => defaults.push(OsString::from(format!("{}={}", flag, scalar(value)?))),
+        }
+    }
+
+    let mut args = args.into_iter();
+    Ok(args.next().into_iter().chain(defaults).chain(args).collect())
+}
+
+fn scalar(value: &Value) -> anyhow::Result<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        other => anyhow::bail!("Unsupported config value: {:?}", other),
+    }
+}
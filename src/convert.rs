@@ -0,0 +1,385 @@
+//! Scenarios converted from other force feedback tools' test definitions
+//!
+//! The `convert` command reads effects defined for another tool and writes them
+//! as a scenario, one step per effect in the order they are defined, so existing
+//! test suites can be replayed and compared without re-authoring them.
+//!
+//! `fftest` reads Linux `struct ff_effect` definitions (linux/input.h) written as
+//! C assignments, the way fftest and the tools derived from it define their
+//! effects:
+//!
+//! ```c
+//! effects[1].type = FF_CONSTANT;
+//! effects[1].direction = 0x6000;  /* 135 degrees */
+//! effects[1].u.constant.level = 0x2000;
+//! effects[1].u.constant.envelope.attack_length = 1000;
+//! effects[1].replay.length = 5000;
+//! ```
+//!
+//! Each variable (`effects[1]`, `effect`) is one effect; assigning `type` again
+//! to a variable that already has one starts a new effect. Other statements are
+//! skipped, so a whole C file can be converted. Levels are rescaled from the
+//! kernel's 16-bit ranges to the scenario's -10000 to 10000, directions and
+//! phases from 0..0xFFFF to degrees and hundredths of a degree. Condition
+//! parameters are taken from the first axis. Rumble effects and custom
+//! waveforms have no scenario equivalent and are skipped with a warning.
+
+use crate::effects::{
+    ConditionEffect, ConditionParams, ConditionType, ConstantForce, Direction, Effect, EffectParams, Envelope,
+    PeriodicEffect, RampEffect, WaveType,
+};
+use crate::schedule::TimingMode;
+use crate::{Scenario, ScenarioStep};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Step length of effects that play until stopped (replay.length 0)
+pub const INFINITE_LENGTH_MS: u32 = 1000;
+/// Full scale of scenario forces and levels
+const FULL_SCALE: f64 = 10000.0;
+
+/// Effect types and waveforms of linux/input.h
+const FF_RUMBLE: i64 = 0x50;
+const FF_PERIODIC: i64 = 0x51;
+const FF_CONSTANT: i64 = 0x52;
+const FF_SPRING: i64 = 0x53;
+const FF_FRICTION: i64 = 0x54;
+const FF_DAMPER: i64 = 0x55;
+const FF_INERTIA: i64 = 0x56;
+const FF_RAMP: i64 = 0x57;
+const FF_SQUARE: i64 = 0x58;
+const FF_TRIANGLE: i64 = 0x59;
+const FF_SINE: i64 = 0x5A;
+const FF_SAW_UP: i64 = 0x5B;
+const FF_SAW_DOWN: i64 = 0x5C;
+const FF_CUSTOM: i64 = 0x5D;
+const FF_CONSTANTS: [(&str, i64); 14] = [
+    ("FF_RUMBLE", FF_RUMBLE),
+    ("FF_PERIODIC", FF_PERIODIC),
+    ("FF_CONSTANT", FF_CONSTANT),
+    ("FF_SPRING", FF_SPRING),
+    ("FF_FRICTION", FF_FRICTION),
+    ("FF_DAMPER", FF_DAMPER),
+    ("FF_INERTIA", FF_INERTIA),
+    ("FF_RAMP", FF_RAMP),
+    ("FF_SQUARE", FF_SQUARE),
+    ("FF_TRIANGLE", FF_TRIANGLE),
+    ("FF_SINE", FF_SINE),
+    ("FF_SAW_UP", FF_SAW_UP),
+    ("FF_SAW_DOWN", FF_SAW_DOWN),
+    ("FF_CUSTOM", FF_CUSTOM),
+];
+
+/// Format of the test definitions to convert
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ScriptFormat {
+    /// Linux `struct ff_effect` assignments, as fftest defines its effects
+    Fftest,
+}
+
+/// Numbers of a conversion, for `--output json`
+#[derive(Debug, Serialize)]
+pub struct ConvertSummary {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub steps: usize,
+    /// What was skipped or approximated
+    pub warnings: Vec<String>,
+}
+
+/// Convert the `format` definitions in `input` into the scenario `output`
+pub fn run(input: &Path, format: ScriptFormat, output: &Path) -> anyhow::Result<ConvertSummary> {
+    let source = fs::read_to_string(input).map_err(|e| anyhow::anyhow!("Failed to read {}: {}", input.display(), e))?;
+    let (steps, warnings) = convert(&source, format);
+    for warning in &warnings {
+        println!("WARNING: {}", warning);
+    }
+    if steps.is_empty() {
+        anyhow::bail!("{} defines no effects that convert to scenario steps", input.display());
+    }
+
+    let scenario = Scenario {
+        name: output.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+        description: format!("Converted from {}", input.display()),
+        loop_forever: false,
+        repeat_count: 1,
+        timing: TimingMode::default(),
+        health_check: false,
+        steps,
+    };
+    let header = format!(
+        "# Converted from the effect definitions in {}, one step per effect.\n",
+        input.display()
+    );
+    fs::write(output, header + &serde_yaml::to_string(&scenario)?)?;
+    println!(
+        "Converted {} effects from {} to {}",
+        scenario.steps.len(),
+        input.display(),
+        output.display()
+    );
+    Ok(ConvertSummary {
+        input: input.to_path_buf(),
+        output: output.to_path_buf(),
+        steps: scenario.steps.len(),
+        warnings,
+    })
+}
+
+/// Steps converted from `source`, and a warning for everything skipped
+pub fn convert(source: &str, format: ScriptFormat) -> (Vec<ScenarioStep>, Vec<String>) {
+    match format {
+        ScriptFormat::Fftest => convert_fftest(source),
+    }
+}
+
+/// A `struct ff_effect` being assigned: its variable and fields by path
+/// relative to the union, e.g. "constant.level" or "replay.length"
+#[derive(Debug, Default)]
+struct FfEffect {
+    variable: String,
+    fields: BTreeMap<String, i64>,
+    /// Assignments whose value is not a number
+    ignored: Vec<String>,
+}
+
+impl FfEffect {
+    fn get(&self, field: &str) -> i64 {
+        self.fields.get(field).copied().unwrap_or(0)
+    }
+}
+
+fn convert_fftest(source: &str) -> (Vec<ScenarioStep>, Vec<String>) {
+    let mut effects: Vec<FfEffect> = Vec::new();
+    let mut warnings = Vec::new();
+    for statement in strip_comments(source).split([';', '{', '}']) {
+        let Some((target, value)) = assignment(statement) else {
+            continue;
+        };
+        let Some((variable, path)) = target.split_once('.') else {
+            continue;
+        };
+        let Some(path) = field_path(path) else {
+            continue;
+        };
+        let number = parse_value(value);
+
+        // The variable's latest effect, or a new one when it is given a type again
+        let current = effects.iter().rposition(|effect| effect.variable == variable);
+        let index = match current {
+            Some(index) if !(path == "type" && effects[index].fields.contains_key("type")) => index,
+            _ => {
+                effects.push(FfEffect {
+                    variable: variable.to_string(),
+                    ..Default::default()
+                });
+                effects.len() - 1
+            }
+        };
+        match number {
+            Some(number) => {
+                effects[index].fields.insert(path, number);
+            }
+            None => effects[index].ignored.push(format!("{} = {}", target, value.trim())),
+        }
+    }
+
+    let mut steps = Vec::new();
+    // Other structs assigned alongside, such as the input events playing the
+    // effects, have no force feedback effect type
+    let ff_types = FF_RUMBLE..=FF_RAMP;
+    for effect in effects.iter().filter(|effect| effect.fields.get("type").is_some_and(|t| ff_types.contains(t))) {
+        for assignment in &effect.ignored {
+            warnings.push(format!("{}: value not understood, ignored", assignment));
+        }
+        match scenario_effect(effect) {
+            Ok(scenario_effect) => {
+                if effect.get("replay.length") == 0 {
+                    warnings.push(format!(
+                        "{}: plays until stopped, converted to a {} ms step",
+                        effect.variable, INFINITE_LENGTH_MS
+                    ));
+                }
+                steps.push(ScenarioStep {
+                    effect: scenario_effect,
+                    expect: None,
+                    peripherals: Vec::new(),
+                    settings: Vec::new(),
+                    hold: false,
+                    stop_at: None,
+                });
+            }
+            Err(e) => warnings.push(format!("{}: {}, skipped", effect.variable, e)),
+        }
+    }
+    (steps, warnings)
+}
+
+/// The source without C comments
+fn strip_comments(source: &str) -> String {
+    let mut stripped = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(start) = rest.find("/*").into_iter().chain(rest.find("//")).min() {
+        stripped.push_str(&rest[..start]);
+        let end = if rest[start..].starts_with("/*") {
+            rest[start..].find("*/").map_or(rest.len(), |end| start + end + 2)
+        } else {
+            rest[start..].find('\n').map_or(rest.len(), |end| start + end)
+        };
+        stripped.push(' ');
+        rest = &rest[end..];
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+/// Target and value of an assignment statement, the target being the last
+/// expression before `=`, e.g. `effects[2].u.condition[i].center` in `i++) effects...`
+fn assignment(statement: &str) -> Option<(&str, &str)> {
+    let (left, value) = statement.split_once('=')?;
+    let left = left.trim_end();
+    if value.starts_with('=') || left.ends_with(['!', '<', '>', '+', '-', '*', '/', '|', '&']) {
+        return None;
+    }
+    let start = left
+        .rfind(|c: char| !(c.is_ascii_alphanumeric() || "_.[]".contains(c)))
+        .map_or(0, |position| position + 1);
+    Some((&left[start..], value))
+}
+
+/// Field path relative to the union, with conditions reduced to the first axis;
+/// None for the other axis
+fn field_path(path: &str) -> Option<String> {
+    let path = path.strip_prefix("u.").unwrap_or(path);
+    match path.strip_prefix("condition[") {
+        Some(rest) => {
+            let (axis, field) = rest.split_once("].")?;
+            // fftest sets both axes in a loop, condition[i]; the wheel's axis is the first
+            let first = axis.trim().parse::<usize>().map_or(true, |axis| axis == 0);
+            first.then(|| format!("condition.{}", field))
+        }
+        None => Some(path.to_string()),
+    }
+}
+
+/// An integer literal, FF_ constant or their negation, with any cast or suffix
+fn parse_value(value: &str) -> Option<i64> {
+    let mut value = value.trim();
+    // Casts such as (__u16) or (__s16)
+    while let Some(rest) = value.strip_prefix('(').and_then(|rest| rest.split_once(')')) {
+        if rest.0.trim_start_matches("__").starts_with(['u', 's']) && !rest.0.contains(' ') {
+            value = rest.1.trim();
+        } else {
+            break;
+        }
+    }
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value.trim()),
+        None => (false, value),
+    };
+    let value = value.trim_end_matches(['u', 'U', 'l', 'L']);
+    let number = if let Some(hex) = value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some((_, number)) = FF_CONSTANTS.iter().find(|(name, _)| *name == value) {
+        *number
+    } else {
+        value.parse().ok()?
+    };
+    Some(if negative { -number } else { number })
+}
+
+/// `value` of a signed 16-bit level, in scenario units
+fn level(value: i64) -> i16 {
+    (value as f64 * FULL_SCALE / 32767.0).round().clamp(-FULL_SCALE, FULL_SCALE) as i16
+}
+
+/// `value` of an unsigned 16-bit range (saturation, dead band), in scenario units
+fn unsigned_level(value: i64, max: f64) -> u16 {
+    (value as f64 * FULL_SCALE / max).round().clamp(0.0, FULL_SCALE) as u16
+}
+
+fn scenario_effect(effect: &FfEffect) -> Result<Effect, String> {
+    let length = effect.get("replay.length").clamp(0, u16::MAX as i64) as u32;
+    let params = EffectParams {
+        duration: if length == 0 { INFINITE_LENGTH_MS } else { length },
+        start_delay: effect.get("replay.delay").clamp(0, u16::MAX as i64) as u32,
+        gain: FULL_SCALE as u16,
+    };
+    // 0..0xFFFF for a full turn
+    let direction = Direction(((effect.get("direction") & 0xFFFF) * 360 / 0x10000) as u16);
+    let envelope = |kind: &str| Envelope {
+        attack_time: effect.get(&format!("{}.envelope.attack_length", kind)).clamp(0, u16::MAX as i64) as u32,
+        attack_level: unsigned_level(effect.get(&format!("{}.envelope.attack_level", kind)), 32767.0),
+        fade_time: effect.get(&format!("{}.envelope.fade_length", kind)).clamp(0, u16::MAX as i64) as u32,
+        fade_level: unsigned_level(effect.get(&format!("{}.envelope.fade_level", kind)), 32767.0),
+    };
+
+    let condition_type = match effect.get("type") {
+        FF_CONSTANT => {
+            return Ok(Effect::Constant {
+                params,
+                force: ConstantForce {
+                    magnitude: level(effect.get("constant.level")),
+                    direction,
+                    envelope: envelope("constant"),
+                },
+            })
+        }
+        FF_PERIODIC => {
+            let wave_type = match effect.get("periodic.waveform") {
+                FF_SQUARE => WaveType::Square,
+                FF_TRIANGLE => WaveType::Triangle,
+                FF_SINE => WaveType::Sine,
+                FF_SAW_UP => WaveType::SawtoothUp,
+                FF_SAW_DOWN => WaveType::SawtoothDown,
+                FF_CUSTOM => return Err("custom waveforms have no scenario equivalent".to_string()),
+                other => return Err(format!("unknown waveform 0x{:X}", other)),
+            };
+            return Ok(Effect::Periodic {
+                params,
+                effect: PeriodicEffect {
+                    wave_type,
+                    magnitude: level(effect.get("periodic.magnitude")).unsigned_abs(),
+                    offset: level(effect.get("periodic.offset")),
+                    phase: ((effect.get("periodic.phase") & 0xFFFF) * 36000 / 0x10000) as u16,
+                    period: effect.get("periodic.period").clamp(0, u16::MAX as i64) as u32,
+                    direction,
+                    envelope: envelope("periodic"),
+                },
+            });
+        }
+        FF_RAMP => {
+            return Ok(Effect::Ramp {
+                params,
+                effect: RampEffect {
+                    start_magnitude: level(effect.get("ramp.start_level")),
+                    end_magnitude: level(effect.get("ramp.end_level")),
+                    direction,
+                    envelope: envelope("ramp"),
+                },
+            })
+        }
+        FF_SPRING => ConditionType::Spring,
+        FF_DAMPER => ConditionType::Damper,
+        FF_FRICTION => ConditionType::Friction,
+        FF_INERTIA => ConditionType::Inertia,
+        FF_RUMBLE => return Err("rumble effects have no scenario equivalent".to_string()),
+        other => return Err(format!("unknown effect type 0x{:X}", other)),
+    };
+    Ok(Effect::Condition {
+        params,
+        effect: ConditionEffect {
+            condition_type,
+            x_axis: ConditionParams {
+                offset: level(effect.get("condition.center")),
+                positive_coefficient: level(effect.get("condition.right_coeff")),
+                negative_coefficient: level(effect.get("condition.left_coeff")),
+                positive_saturation: unsigned_level(effect.get("condition.right_saturation"), 65535.0),
+                negative_saturation: unsigned_level(effect.get("condition.left_saturation"), 65535.0),
+                dead_band: unsigned_level(effect.get("condition.deadband"), 65535.0),
+            },
+        },
+    })
+}
@@ -0,0 +1,170 @@
+//! Offline decoding of packets, capture files and pcap files
+//!
+//! The `decode` command prints every packet together with the command a
+//! decoder recognizes in it, for captures collected elsewhere. With a HID report
+//! descriptor, reports of PID devices are decoded field by field.
+
+use crate::compare::parse_hex;
+use crate::hid_descriptor::ReportDescriptor;
+use crate::monitor::format_packet;
+use crate::normalize::decode_packet_with;
+use crate::CaptureReader;
+use crate::usb_monitor::{self, format_hex, PacketFilter};
+use serde::Serialize;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// A decoded packet, as listed in JSON output
+#[derive(Debug, Serialize)]
+pub struct DecodedPacket {
+    /// Input the packet was read from
+    pub input: String,
+    /// Step of a capture file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub step: Option<usize>,
+    pub packet: String,
+    /// None for unknown packets and patterns with wildcards
+    pub decoded: Option<String>,
+}
+
+/// Decode each input: a pcap file, a capture file or a hex packet
+/// Packets of pcap files are selected by `filter`, like a live capture
+pub fn run(
+    inputs: &[String],
+    filter: &PacketFilter,
+    descriptor: Option<&ReportDescriptor>,
+) -> anyhow::Result<Vec<DecodedPacket>> {
+    let mut decoded = Vec::new();
+    for input in inputs {
+        let Some(path) = resolve_file(input) else {
+            let bytes = parse_hex_input(input)
+                .ok_or_else(|| anyhow::anyhow!("'{}' is neither a file nor a hex packet", input))?;
+            decoded.push(DecodedPacket {
+                input: input.clone(),
+                step: None,
+                packet: format_hex(&bytes),
+                decoded: print_packet(&format_hex(&bytes), Some(&bytes), descriptor),
+            });
+            continue;
+        };
+
+        println!("# {}", path.display());
+        if is_pcap(&path)? {
+            decode_pcap(&path, input, filter, descriptor, &mut decoded)?;
+        } else {
+            decode_capture_file(&path, input, descriptor, &mut decoded)?;
+        }
+    }
+    Ok(decoded)
+}
+
+/// Input as a path, or as a capture name in `runs/` like the other commands
+pub fn resolve_file(input: &str) -> Option<PathBuf> {
+    [PathBuf::from(input), PathBuf::from("runs").join(input)]
+        .into_iter()
+        .find(|path| path.is_file())
+}
+
+/// Check the file for a pcap magic number (either byte order, micro- or nanosecond)
+/// or a pcapng section header
+pub fn is_pcap(path: &Path) -> anyhow::Result<bool> {
+    let mut magic = [0u8; 4];
+    let read = fs::File::open(path)?.read(&mut magic)?;
+    Ok(read == 4
+        && matches!(
+            magic,
+            [0xd4, 0xc3, 0xb2, 0xa1]
+                | [0x4d, 0x3c, 0xb2, 0xa1]
+                | [0xa1, 0xb2, 0xc3, 0xd4]
+                | [0xa1, 0xb2, 0x3c, 0x4d]
+                | [0x0a, 0x0d, 0x0d, 0x0a]
+        ))
+}
+
+/// Parse a hex packet given with or without spaces ("01 05 01" or "010501")
+fn parse_hex_input(input: &str) -> Option<Vec<u8>> {
+    let digits: String = input.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn decode_pcap(
+    path: &Path,
+    input: &str,
+    filter: &PacketFilter,
+    descriptor: Option<&ReportDescriptor>,
+    decoded: &mut Vec<DecodedPacket>,
+) -> anyhow::Result<()> {
+    let packets = usb_monitor::read_pcap_file(path, filter).map_err(anyhow::Error::msg)?;
+    println!("# {} packets", packets.len());
+    let start = packets.first().map(|p| p.timestamp).unwrap_or_default();
+    for packet in &packets {
+        println!("{}", format_packet(packet, start));
+        decoded.push(DecodedPacket {
+            input: input.to_string(),
+            step: None,
+            packet: format_hex(&packet.data),
+            decoded: print_decoded(&packet.data, descriptor),
+        });
+    }
+    Ok(())
+}
+
+fn decode_capture_file(
+    path: &Path,
+    input: &str,
+    descriptor: Option<&ReportDescriptor>,
+    decoded: &mut Vec<DecodedPacket>,
+) -> anyhow::Result<()> {
+    for step in CaptureReader::open(path)?.steps() {
+        let step = step?;
+        println!("# Step {}: {}", step.step_index, step.step_name);
+        let mut decode_lines = |packets: &[String]| {
+            for packet in packets {
+                decoded.push(DecodedPacket {
+                    input: input.to_string(),
+                    step: Some(step.step_index),
+                    packet: packet.clone(),
+                    decoded: print_packet(packet, parse_hex(packet).as_deref(), descriptor),
+                });
+            }
+        };
+        decode_lines(&step.packets);
+        for capture in &step.devices {
+            println!("# Device {}", capture.device);
+            decode_lines(&capture.packets);
+        }
+        for variant in &step.variants {
+            println!("# Variant");
+            decode_lines(variant);
+        }
+    }
+    Ok(())
+}
+
+/// Print a packet line and its decoding; `bytes` is None for lines with wildcards
+fn print_packet(line: &str, bytes: Option<&[u8]>, descriptor: Option<&ReportDescriptor>) -> Option<String> {
+    println!("{}", line);
+    match bytes {
+        Some(bytes) => print_decoded(bytes, descriptor),
+        None => {
+            println!("    -> (pattern, not decoded)");
+            None
+        }
+    }
+}
+
+fn print_decoded(bytes: &[u8], descriptor: Option<&ReportDescriptor>) -> Option<String> {
+    let decoded = decode_packet_with(bytes, descriptor);
+    match &decoded {
+        Some(decoded) => println!("    -> {}", decoded),
+        None => println!("    -> (unknown)"),
+    }
+    decoded
+}
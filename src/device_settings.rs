@@ -0,0 +1,45 @@
+//! Device settings steps: rotation range and torque limit
+//!
+//! Games configure the wheel before and between their effects. A step's settings
+//! are sent as its effect starts, so the configuration reports are captured and
+//! compared like the force feedback. How a setting reaches the wheel is up to the
+//! driver (see `FfbDriver::send_settings`).
+
+use serde::{Deserialize, Serialize};
+
+/// Largest rotation range wheels accept, in degrees
+pub const MAX_ROTATION: u16 = 2520;
+
+/// Device setting of a scenario step
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DeviceSetting {
+    /// Wheel rotation range, lock to lock, in degrees
+    RotationRange { degrees: u16 },
+    /// Cap on the force of all effects, in percent of the wheel's full torque
+    TorqueLimit { percent: u8 },
+}
+
+impl DeviceSetting {
+    /// Short description for logs
+    pub fn label(&self) -> String {
+        match self {
+            DeviceSetting::RotationRange { degrees } => format!("rotation range {}°", degrees),
+            DeviceSetting::TorqueLimit { percent } => format!("torque limit {}%", percent),
+        }
+    }
+
+    /// Check the value against what any wheel accepts
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            DeviceSetting::RotationRange { degrees } if !(40..=MAX_ROTATION).contains(degrees) => Err(format!(
+                "rotation range {}° is outside 40..{}°",
+                degrees, MAX_ROTATION
+            )),
+            DeviceSetting::TorqueLimit { percent } if *percent > 100 => {
+                Err(format!("torque limit {}% is above 100%", percent))
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! Side-by-side terminal diff of packet sequences
+//!
+//! Packets are aligned by position, differing bytes are highlighted and
+//! long runs of matching packets are folded.
+
+use crate::compare::{bytes_match, packets_match};
+use std::io::IsTerminal;
+
+/// Matching packets shown around each difference
+const CONTEXT_PACKETS: usize = 1;
+
+const RED: &str = "\x1b[31;1m";
+const GREEN: &str = "\x1b[32;1m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// Whether to emit ANSI colors (stdout is a terminal and NO_COLOR is unset)
+pub fn use_color() -> bool {
+    std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal()
+}
+
+/// Render a packet's bytes, coloring those that differ from `other`
+/// Returns the rendered text and its visible width
+fn render_bytes(packet: &[&str], other: &[&str], color: &str, colored: bool) -> (String, usize) {
+    let mut text = String::new();
+    for (i, byte) in packet.iter().enumerate() {
+        if i > 0 {
+            text.push(' ');
+        }
+        let differs = other.get(i).map(|o| !bytes_match(o, byte)).unwrap_or(true);
+        if differs && colored {
+            text.push_str(color);
+            text.push_str(byte);
+            text.push_str(RESET);
+        } else {
+            text.push_str(byte);
+        }
+    }
+    let width = packet.iter().map(|b| b.len()).sum::<usize>() + packet.len().saturating_sub(1);
+    (text, width)
+}
+
+/// Caret line marking differing byte positions (used without colors)
+fn caret_line(expected: &[&str], actual: &[&str]) -> String {
+    let max_len = expected.len().max(actual.len());
+    let mut line = String::new();
+    for i in 0..max_len {
+        let differs = match (expected.get(i), actual.get(i)) {
+            (Some(e), Some(a)) => !bytes_match(e, a),
+            _ => true,
+        };
+        line.push_str(if differs { "^^ " } else { "   " });
+    }
+    line.trim_end().to_string()
+}
+
+fn split_bytes(packet: Option<&String>) -> Vec<&str> {
+    packet.map(|p| p.split_whitespace().collect()).unwrap_or_default()
+}
+
+/// Print expected and actual packets side by side
+pub fn print_side_by_side(expected: &[String], actual: &[String]) {
+    let colored = use_color();
+    let max_packets = expected.len().max(actual.len());
+
+    let differs: Vec<bool> = (0..max_packets)
+        .map(|i| match (expected.get(i), actual.get(i)) {
+            (Some(e), Some(a)) => !packets_match(e, a),
+            _ => true,
+        })
+        .collect();
+
+    // Column width fits the longest packet
+    let column = expected
+        .iter()
+        .chain(actual)
+        .map(|p| p.split_whitespace().count() * 3)
+        .max()
+        .unwrap_or(0)
+        .max("Expected".len());
+
+    println!("    {:>4}  {:<column$} | Actual", "#", "Expected", column = column);
+
+    let mut folded = 0;
+    for i in 0..max_packets {
+        let near_diff = (i.saturating_sub(CONTEXT_PACKETS)..=(i + CONTEXT_PACKETS).min(max_packets - 1))
+            .any(|j| differs[j]);
+
+        if !near_diff {
+            folded += 1;
+            continue;
+        }
+        if folded > 0 {
+            print_fold(folded, colored);
+            folded = 0;
+        }
+
+        let exp = split_bytes(expected.get(i));
+        let act = split_bytes(actual.get(i));
+        let marker = match (expected.get(i), actual.get(i)) {
+            (Some(_), None) => '-',
+            (None, Some(_)) => '+',
+            _ if differs[i] => '!',
+            _ => ' ',
+        };
+
+        let (exp_text, exp_width) = if expected.get(i).is_some() {
+            render_bytes(&exp, &act, RED, colored)
+        } else {
+            ("(missing)".to_string(), "(missing)".len())
+        };
+        let (act_text, _) = if actual.get(i).is_some() {
+            render_bytes(&act, &exp, GREEN, colored)
+        } else {
+            ("(missing)".to_string(), 0)
+        };
+        let padding = " ".repeat(column.saturating_sub(exp_width));
+
+        println!("  {} {:>4}  {}{} | {}", marker, i + 1, exp_text, padding, act_text);
+
+        if differs[i] && !colored && !exp.is_empty() && !act.is_empty() {
+            println!("    {:>4}  {:<column$} | {}", "", "", caret_line(&exp, &act), column = column);
+        }
+    }
+    if folded > 0 {
+        print_fold(folded, colored);
+    }
+}
+
+fn print_fold(count: usize, colored: bool) {
+    let text = format!("... {} matching packet(s) ...", count);
+    if colored {
+        println!("          {}{}{}", DIM, text, RESET);
+    } else {
+        println!("          {}", text);
+    }
+}
@@ -1,5 +1,36 @@
-use crate::{effects::Effect, error::FFBResult};
+//! The driver interface: what scenario playback needs from a device
+//!
+//! `FfbDriver` is implemented by the SDL and SIMAGIC drivers in `drivers` and can be
+//! implemented by embedders for their own devices.
+
+use crate::{
+    cancel::CancellationToken,
+    capabilities::Capabilities,
+    device_settings::DeviceSetting,
+    effects::Effect,
+    error::{FFBError, FFBResult},
+    health::Health,
+    peripheral::PeripheralCommand,
+    telemetry::AxisSample,
+    usb_monitor::UsbPacket,
+    DeviceCapture,
+};
 use std::any::Any;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+/// Result of `FfbDriver::begin_effect`
+#[derive(Debug, Clone)]
+pub enum EffectProgress {
+    /// The effect's output is complete
+    Done(Vec<String>),
+    /// Wait this long, then collect the output with `finish_effect`
+    Wait(Duration),
+}
+
+/// Effect kept playing past its step by `FfbDriver::hold_effect`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeldEffect(pub i32);
 
 /// Trait for Force Feedback device drivers
 pub trait FfbDriver {
@@ -8,10 +39,126 @@ pub trait FfbDriver {
     
     /// Apply (create and start) an effect
     /// Returns captured/generated command packets as hex strings
-    /// For real drivers (SDL), this waits for effect duration and captures USB traffic
+    /// For real drivers (SDL), this waits for effect start delay and duration and captures USB traffic
     /// For simulation drivers (Simagic), this returns generated reports immediately
     fn apply_effect(&mut self, effect: &Effect) -> FFBResult<Vec<String>>;
+
+    /// Start an effect without waiting for it, for callers that wait on their own (scenario playback)
+    /// `apply_effect` is `begin_effect`, the wait it asks for and `finish_effect`
+    fn begin_effect(&mut self, effect: &Effect) -> FFBResult<EffectProgress> {
+        self.apply_effect(effect).map(EffectProgress::Done)
+    }
+
+    /// Collect the output of the effect started by `begin_effect` once its wait is over
+    fn finish_effect(&mut self) -> FFBResult<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    /// Notes on how the driver changed effects since the last call, e.g. forces cut
+    /// by a force limit; playback reports them to its observer
+    fn take_notes(&mut self) -> Vec<String> {
+        Vec::new()
+    }
     
+    /// Keep the effect of the last step playing past the step, until `release_effect`
+    /// None if the driver has no effect that outlives its step
+    fn hold_effect(&mut self) -> Option<HeldEffect> {
+        None
+    }
+
+    /// Stop an effect kept by `hold_effect`
+    fn release_effect(&mut self, _effect: HeldEffect) -> FFBResult<()> {
+        Ok(())
+    }
+
+    /// Stop the effect of the last step, leaving held effects playing
+    fn stop_step_effect(&mut self) -> FFBResult<()> {
+        self.stop_all_effects()
+    }
+
+    /// Send a step's peripheral commands (rev LEDs, displays) while its effect plays
+    /// Returns the generated reports of drivers that don't capture; capturing drivers
+    /// return nothing, the reports show up in the capture
+    fn send_peripherals(&mut self, commands: &[PeripheralCommand]) -> FFBResult<Vec<String>> {
+        match commands.first() {
+            None => Ok(Vec::new()),
+            Some(command) => Err(FFBError::InvalidParameter(format!(
+                "The {} driver cannot send {}",
+                self.name(),
+                command.label()
+            ))),
+        }
+    }
+
+    /// Apply a step's device settings (rotation range, torque limit) as its effect starts
+    /// Returns generated reports like `send_peripherals`
+    fn send_settings(&mut self, settings: &[DeviceSetting]) -> FFBResult<Vec<String>> {
+        match settings.first() {
+            None => Ok(Vec::new()),
+            Some(setting) => Err(FFBError::InvalidParameter(format!(
+                "The {} driver cannot set the {}",
+                self.name(),
+                setting.label()
+            ))),
+        }
+    }
+
+    /// What the initialized device can play, for the check before a run
+    /// None if the driver doesn't know
+    fn capabilities(&self) -> Option<Capabilities> {
+        None
+    }
+
+    /// Poll the device between steps, e.g. its connection state or a HID read
+    /// None if the driver has no device to poll
+    fn check_health(&mut self) -> Option<Health> {
+        None
+    }
+
+    /// Submit-to-complete latency of the packets returned by the last `apply_effect`
+    /// Empty if the driver does not measure latency
+    fn packet_latencies(&self) -> Vec<Option<Duration>> {
+        Vec::new()
+    }
+
+    /// Capture timestamps of the packets returned by the last `apply_effect`
+    /// Empty if the driver does not capture
+    fn packet_timestamps(&self) -> Vec<Duration> {
+        Vec::new()
+    }
+
+    /// Time from submitting the last `apply_effect`'s effect to its first OUT packet on
+    /// the wire, None if the driver does not measure it or nothing was captured
+    fn wire_latency(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Steering axis positions sampled during the last `apply_effect`
+    /// Empty if the driver does not sample the axis
+    fn axis_samples(&self) -> Vec<AxisSample> {
+        Vec::new()
+    }
+
+    /// Packets of the additional capture devices from the last `apply_effect`
+    /// Empty unless capturing several devices
+    fn device_captures(&self) -> Vec<DeviceCapture> {
+        Vec::new()
+    }
+
+    /// Capture statistics of the last `apply_effect`, None if the driver does not capture
+    fn capture_summary(&self) -> Option<String> {
+        None
+    }
+
+    /// Stream of captured packets as they arrive, None if the driver does not capture
+    fn live_packets(&self) -> Option<Receiver<UsbPacket>> {
+        None
+    }
+
+    /// Token that cuts the driver's own waits short (`apply_effect`, capture start)
+    /// Drivers that never wait ignore it
+    fn set_cancellation(&mut self, _cancel: CancellationToken) {}
+
     /// Stop all effects
     fn stop_all_effects(&mut self) -> FFBResult<()>;
     
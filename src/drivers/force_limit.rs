@@ -0,0 +1,287 @@
+//! Safety limit on the force of every effect before it reaches a driver
+//!
+//! A direct drive base turns a full-scale effect into full torque, which is dangerous
+//! while iterating on unknown protocol bytes. [`ForceLimit`] wraps a driver and limits
+//! the magnitudes, offsets, envelope levels and condition saturations of each effect
+//! to a share of full scale (10000), either clamping the values above it or scaling
+//! all of them. Each limited effect is noted through `FfbDriver::take_notes`, which
+//! playback reports to its observer, so the run log shows which steps did not play
+//! as written.
+
+use crate::{
+    cancel::CancellationToken,
+    capabilities::Capabilities,
+    device_settings::DeviceSetting,
+    driver::{EffectProgress, FfbDriver, HeldEffect},
+    effects::*,
+    error::FFBResult,
+    health::Health,
+    peripheral::PeripheralCommand,
+    telemetry::AxisSample,
+    usb_monitor::UsbPacket,
+    DeviceCapture,
+};
+use std::any::Any;
+use std::collections::VecDeque;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+/// Unread notes kept; older ones are dropped when nobody takes them
+const MAX_NOTES: usize = 1000;
+
+/// How forces above the limit are brought under it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum LimitMode {
+    /// Cut values above the limit, leave the others as written
+    #[default]
+    Clamp,
+    /// Scale all values by the limit, keeping their proportions
+    Scale,
+}
+
+impl LimitMode {
+    /// The mode as `--max-force-mode` names it
+    pub fn name(self) -> &'static str {
+        match self {
+            LimitMode::Clamp => "clamp",
+            LimitMode::Scale => "scale",
+        }
+    }
+}
+
+/// Force limit of a run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxForce {
+    /// Share of full scale, 1-100
+    pub percent: u8,
+    /// How values above the limit are brought under it
+    pub mode: LimitMode,
+}
+
+impl MaxForce {
+    /// `effect` within the limit, and a description of each value that changed
+    pub fn limit(&self, effect: &Effect) -> (Effect, Vec<String>) {
+        let mut limited = effect.clone();
+        let mut limiter = Limiter {
+            max: self,
+            changes: Vec::new(),
+        };
+        match &mut limited {
+            Effect::Constant { force, .. } => {
+                limiter.signed("magnitude", &mut force.magnitude);
+                limiter.envelope(&mut force.envelope);
+            }
+            Effect::Periodic { effect, .. } => {
+                limiter.unsigned("magnitude", &mut effect.magnitude);
+                limiter.signed("offset", &mut effect.offset);
+                limiter.envelope(&mut effect.envelope);
+            }
+            Effect::Ramp { effect, .. } => {
+                limiter.signed("start_magnitude", &mut effect.start_magnitude);
+                limiter.signed("end_magnitude", &mut effect.end_magnitude);
+                limiter.envelope(&mut effect.envelope);
+            }
+            Effect::Condition { effect, .. } => {
+                let axis = &mut effect.x_axis;
+                // The saturations cap a condition's force; scaling also softens its slope
+                if self.mode == LimitMode::Scale {
+                    limiter.signed("positive_coefficient", &mut axis.positive_coefficient);
+                    limiter.signed("negative_coefficient", &mut axis.negative_coefficient);
+                }
+                limiter.unsigned("positive_saturation", &mut axis.positive_saturation);
+                limiter.unsigned("negative_saturation", &mut axis.negative_saturation);
+            }
+        }
+        (limited, limiter.changes)
+    }
+
+    /// A single signed level within the limit, for callers that build their own effects
+    pub fn limit_magnitude(&self, magnitude: i16) -> i16 {
+        let mut limited = magnitude;
+        Limiter {
+            max: self,
+            changes: Vec::new(),
+        }
+        .signed("magnitude", &mut limited);
+        limited
+    }
+}
+
+/// Applies a `MaxForce` to one effect's values, recording what changed
+struct Limiter<'a> {
+    max: &'a MaxForce,
+    changes: Vec<String>,
+}
+
+impl Limiter<'_> {
+    fn limited(&self, value: i32) -> i32 {
+        let percent = self.max.percent as i32;
+        match self.max.mode {
+            LimitMode::Clamp => value.clamp(-100 * percent, 100 * percent),
+            LimitMode::Scale => value * percent / 100,
+        }
+    }
+
+    fn signed(&mut self, name: &str, value: &mut i16) {
+        let limited = self.limited(*value as i32) as i16;
+        if limited != *value {
+            self.changes.push(format!("{} {} -> {}", name, value, limited));
+            *value = limited;
+        }
+    }
+
+    fn unsigned(&mut self, name: &str, value: &mut u16) {
+        let limited = self.limited(*value as i32) as u16;
+        if limited != *value {
+            self.changes.push(format!("{} {} -> {}", name, value, limited));
+            *value = limited;
+        }
+    }
+
+    fn envelope(&mut self, envelope: &mut Envelope) {
+        self.unsigned("attack_level", &mut envelope.attack_level);
+        self.unsigned("fade_level", &mut envelope.fade_level);
+    }
+}
+
+/// Driver that limits every effect before passing it on
+pub struct ForceLimit {
+    inner: Box<dyn FfbDriver>,
+    max: MaxForce,
+    notes: VecDeque<String>,
+}
+
+impl ForceLimit {
+    /// Limit every effect played on `inner` to `max`
+    pub fn new(inner: Box<dyn FfbDriver>, max: MaxForce) -> Self {
+        Self {
+            inner,
+            max,
+            notes: VecDeque::new(),
+        }
+    }
+
+    fn note(&mut self, note: String) {
+        if self.notes.len() == MAX_NOTES {
+            self.notes.pop_front();
+        }
+        self.notes.push_back(note);
+    }
+
+    fn limit(&mut self, effect: &Effect) -> Effect {
+        let (limited, changes) = self.max.limit(effect);
+        if !changes.is_empty() {
+            self.note(format!(
+                "Force limit {}% ({}): {}",
+                self.max.percent,
+                self.max.mode.name(),
+                changes.join(", ")
+            ));
+        }
+        limited
+    }
+}
+
+impl FfbDriver for ForceLimit {
+    fn initialize(&mut self) -> FFBResult<()> {
+        self.note(format!("Forces limited to {}% of full scale ({})", self.max.percent, self.max.mode.name()));
+        self.inner.initialize()
+    }
+
+    fn apply_effect(&mut self, effect: &Effect) -> FFBResult<Vec<String>> {
+        let effect = self.limit(effect);
+        self.inner.apply_effect(&effect)
+    }
+
+    fn begin_effect(&mut self, effect: &Effect) -> FFBResult<EffectProgress> {
+        let effect = self.limit(effect);
+        self.inner.begin_effect(&effect)
+    }
+
+    fn finish_effect(&mut self) -> FFBResult<Vec<String>> {
+        self.inner.finish_effect()
+    }
+
+    fn take_notes(&mut self) -> Vec<String> {
+        let mut notes: Vec<String> = self.notes.drain(..).collect();
+        notes.extend(self.inner.take_notes());
+        notes
+    }
+
+    fn hold_effect(&mut self) -> Option<HeldEffect> {
+        self.inner.hold_effect()
+    }
+
+    fn release_effect(&mut self, effect: HeldEffect) -> FFBResult<()> {
+        self.inner.release_effect(effect)
+    }
+
+    fn stop_step_effect(&mut self) -> FFBResult<()> {
+        self.inner.stop_step_effect()
+    }
+
+    fn send_peripherals(&mut self, commands: &[PeripheralCommand]) -> FFBResult<Vec<String>> {
+        self.inner.send_peripherals(commands)
+    }
+
+    fn send_settings(&mut self, settings: &[DeviceSetting]) -> FFBResult<Vec<String>> {
+        self.inner.send_settings(settings)
+    }
+
+    fn capabilities(&self) -> Option<Capabilities> {
+        self.inner.capabilities()
+    }
+
+    fn check_health(&mut self) -> Option<Health> {
+        self.inner.check_health()
+    }
+
+    fn packet_latencies(&self) -> Vec<Option<Duration>> {
+        self.inner.packet_latencies()
+    }
+
+    fn packet_timestamps(&self) -> Vec<Duration> {
+        self.inner.packet_timestamps()
+    }
+
+    fn wire_latency(&self) -> Option<Duration> {
+        self.inner.wire_latency()
+    }
+
+    fn axis_samples(&self) -> Vec<AxisSample> {
+        self.inner.axis_samples()
+    }
+
+    fn device_captures(&self) -> Vec<DeviceCapture> {
+        self.inner.device_captures()
+    }
+
+    fn capture_summary(&self) -> Option<String> {
+        self.inner.capture_summary()
+    }
+
+    fn live_packets(&self) -> Option<Receiver<UsbPacket>> {
+        self.inner.live_packets()
+    }
+
+    fn set_cancellation(&mut self, cancel: CancellationToken) {
+        self.inner.set_cancellation(cancel)
+    }
+
+    fn stop_all_effects(&mut self) -> FFBResult<()> {
+        self.inner.stop_all_effects()
+    }
+
+    fn shutdown(&mut self) -> FFBResult<()> {
+        self.inner.shutdown()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    /// The wrapped driver, so downcasts see through the limit
+    fn as_any(&self) -> &dyn Any {
+        self.inner.as_any()
+    }
+}
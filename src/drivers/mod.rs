@@ -1,2 +1,43 @@
+//! Driver implementations: SDL haptics with USB capture, and SIMAGIC report generation
+
+pub mod force_limit;
+#[cfg(feature = "device")]
 pub mod sdl_driver;
 pub mod simagic_driver;
+
+use crate::driver::FfbDriver;
+use crate::usb_monitor::CaptureConfig;
+use simagic_driver::SimagicDriver;
+
+/// Create a driver by name; without a capture config the SDL driver only plays effects
+/// The SDL driver needs the `device` feature. The config's `max_force` wraps the driver
+/// in a [`force_limit::ForceLimit`].
+pub fn create_driver(
+    driver_name: &str,
+    capture: Option<&CaptureConfig>,
+) -> anyhow::Result<Box<dyn FfbDriver>> {
+    let driver = create_unlimited_driver(driver_name, capture)?;
+    Ok(match capture.and_then(|capture| capture.max_force) {
+        Some(max) => Box::new(force_limit::ForceLimit::new(driver, max)),
+        None => driver,
+    })
+}
+
+#[cfg_attr(not(feature = "device"), allow(unused_variables))]
+fn create_unlimited_driver(
+    driver_name: &str,
+    capture: Option<&CaptureConfig>,
+) -> anyhow::Result<Box<dyn FfbDriver>> {
+    match driver_name.to_lowercase().as_str() {
+        #[cfg(feature = "device")]
+        "sdl" => Ok(Box::new(match capture {
+            Some(capture) => sdl_driver::SdlDriver::with_capture_config(capture.clone()),
+            None => sdl_driver::SdlDriver::without_capture(),
+        })),
+        "simagic" => Ok(Box::new(SimagicDriver::new())),
+        _ => Err(anyhow::anyhow!(
+            "Unknown driver: {}. Available drivers: sdl, simagic",
+            driver_name
+        )),
+    }
+}
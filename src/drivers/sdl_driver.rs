@@ -1,18 +1,31 @@
 use crate::{
-    driver::FfbDriver,
+    cancel::CancellationToken,
+    capabilities::Capabilities,
+    driver::{EffectProgress, FfbDriver, HeldEffect},
     effects::*,
+    device_settings::DeviceSetting,
     error::{FFBError, FFBResult},
-    usb_monitor::{format_hex, UsbMonitor},
+    health::Health,
+    peripheral::{PeripheralCommand, Protocol},
+    scenario::EFFECT_LABELS,
+    telemetry::AxisSample,
+    watchdog,
+    DeviceCapture,
+    usb_monitor::{capture_clock, format_hex, CaptureConfig, CaptureStats, PacketDirection, UsbMonitor, UsbPacket},
 };
 use sdl3_sys::error::SDL_GetError;
 use sdl3_sys::haptic::*;
+use sdl3_sys::hidapi::{SDL_hid_close, SDL_hid_device, SDL_hid_open, SDL_hid_read_timeout, SDL_hid_write};
 use sdl3_sys::init::*;
 use sdl3_sys::joystick::*;
 use sdl3_sys::stdinc::SDL_free;
 use std::ffi::CStr;
 use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 // SDL uses range -32767..32767, our config uses -10000..10000
 const SCALE_FACTOR: f32 = 32767.0 / 10000.0;
@@ -27,21 +40,150 @@ fn scale_magnitude_u16(value: u16) -> i16 {
 
 pub struct SdlDriver {
     haptic: *mut SDL_Haptic,
+    /// The wheel's joystick, None if the haptic device was opened without one
+    joystick: *mut SDL_Joystick,
+    /// The wheel's HID device for vendor reports, opened by the first peripheral command or setting
+    hid: *mut SDL_hid_device,
+    /// Steering axis sampling rate (Hz), None to not sample
+    axis_rate_hz: Option<u32>,
     current_effect_id: Option<SDL_HapticEffectID>,
+    /// Effects uploaded to the device keyed by their SDL parameters, least recently used first,
+    /// so repeated iterations restart an effect instead of uploading it again
+    uploaded: Vec<(Vec<u8>, SDL_HapticEffectID)>,
+    /// How many effects the device stores at once
+    max_effects: usize,
+    /// Effects kept playing past their step; never evicted or restarted by later steps
+    held: Vec<SDL_HapticEffectID>,
     initialized: bool,
     usb_monitor: UsbMonitor,
+    /// False for playback only: no USB capture is started and no packets are returned
+    capture: bool,
+    /// When the USB capture started, for run-level capture statistics
+    capture_started: Option<Instant>,
+    /// Latency of the packets captured by the last step
+    last_latencies: Vec<Option<Duration>>,
+    /// Effect submit to first OUT packet of the last step
+    last_wire_latency: Option<Duration>,
+    /// Capture timestamps of the packets of the last step
+    last_timestamps: Vec<Duration>,
+    /// Steering axis samples of the last step
+    last_axis_samples: Vec<AxisSample>,
+    /// Packets of the additional capture devices in the last step
+    last_device_captures: Vec<DeviceCapture>,
+    /// Capture statistics of the last step
+    last_capture_summary: Option<String>,
+    /// Step started by `begin_effect`, collected by `finish_effect`
+    pending_step: Option<PendingStep>,
+    /// Cuts effect waits and force streaming short
+    cancel: CancellationToken,
+    /// Stops the effects on a panic or forced exit while the haptic device is open
+    watchdog: Option<watchdog::StopGuard>,
+}
+
+/// Capture state of a step between `begin_effect` and `finish_effect`
+struct PendingStep {
+    started: Instant,
+    /// Capture clock when the effect was submitted to SDL
+    effect_start: Duration,
+    /// Start delay plus duration: the effect plays until `effect_start + effect_end`
+    effect_end: Duration,
+    stats: CaptureStats,
+    /// Packets delivered during the step, without a ring buffer
+    packets: Option<Receiver<UsbPacket>>,
+    axis: Option<AxisSampler>,
+}
+
+/// Samples the steering axis on its own thread while a step plays
+struct AxisSampler {
+    stop: Arc<AtomicBool>,
+    thread: thread::JoinHandle<Vec<AxisSample>>,
+}
+
+/// Joystick handle moved to the sampling thread; SDL locks its joystick state
+struct JoystickHandle(*mut SDL_Joystick);
+
+unsafe impl Send for JoystickHandle {}
+
+/// Haptic handle of the watchdog's stop action; SDL locks its haptic state
+struct HapticHandle(*mut SDL_Haptic);
+
+unsafe impl Send for HapticHandle {}
+unsafe impl Sync for HapticHandle {}
+
+impl AxisSampler {
+    /// Sample axis 0 of `joystick` every `interval`, timed from `submitted`
+    fn start(joystick: *mut SDL_Joystick, interval: Duration, submitted: Instant) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let handle = JoystickHandle(joystick);
+        let thread = thread::spawn(move || {
+            let handle = handle;
+            let mut samples = Vec::new();
+            let mut next = Instant::now();
+            while !stopped.load(Ordering::Relaxed) {
+                let position = unsafe {
+                    SDL_UpdateJoysticks();
+                    SDL_GetJoystickAxis(handle.0, 0)
+                };
+                samples.push(AxisSample {
+                    time_us: submitted.elapsed().as_micros() as u64,
+                    position,
+                });
+                next += interval;
+                if let Some(wait) = next.checked_duration_since(Instant::now()) {
+                    thread::sleep(wait);
+                }
+            }
+            samples
+        });
+        Self { stop, thread }
+    }
+
+    fn finish(self) -> Vec<AxisSample> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread.join().unwrap_or_default()
+    }
 }
 
 impl SdlDriver {
     pub fn new() -> Self {
+        Self::with_capture_config(CaptureConfig::default())
+    }
+
+    pub fn with_capture_config(capture: CaptureConfig) -> Self {
+        let axis_rate_hz = capture.axis_rate_hz;
         SdlDriver {
             haptic: ptr::null_mut(),
+            joystick: ptr::null_mut(),
+            hid: ptr::null_mut(),
+            axis_rate_hz,
             current_effect_id: None,
+            held: Vec::new(),
+            uploaded: Vec::new(),
+            max_effects: 1,
             initialized: false,
-            usb_monitor: UsbMonitor::new(),
+            usb_monitor: UsbMonitor::with_config(capture),
+            capture: true,
+            capture_started: None,
+            last_latencies: Vec::new(),
+            last_wire_latency: None,
+            last_timestamps: Vec::new(),
+            last_axis_samples: Vec::new(),
+            last_device_captures: Vec::new(),
+            last_capture_summary: None,
+            pending_step: None,
+            cancel: CancellationToken::new(),
+            watchdog: None,
         }
     }
 
+    /// Playback only, e.g. for feel-testing: effects run without USB capture
+    pub fn without_capture() -> Self {
+        let mut driver = Self::new();
+        driver.capture = false;
+        driver
+    }
+
     fn map_direction(direction: &Direction) -> SDL_HapticDirection {
         SDL_HapticDirection {
             r#type: SDL_HAPTIC_CARTESIAN,
@@ -162,6 +304,50 @@ impl SdlDriver {
         effect
     }
     
+    /// Capture failure on the primary capture device
+    fn capture_error(&self, message: String) -> FFBError {
+        match self.usb_monitor.devices().first() {
+            Some(device) => FFBError::capture(message).on_device(device.to_string()),
+            None => FFBError::capture(message),
+        }
+    }
+
+    /// Report builder for the wheel's vendor, opening its HID device on first use
+    fn open_vendor_device(&mut self) -> FFBResult<Protocol> {
+        if self.joystick.is_null() {
+            return Err(FFBError::DeviceError(
+                "Vendor reports need the wheel's joystick, the haptic device was opened without one".to_string(),
+            ));
+        }
+        let vendor = unsafe { SDL_GetJoystickVendor(self.joystick) };
+        let product = unsafe { SDL_GetJoystickProduct(self.joystick) };
+        let protocol = Protocol::for_vendor(vendor).ok_or_else(|| {
+            FFBError::DeviceError(format!("No vendor reports known for {:04x}:{:04x}", vendor, product))
+        })?;
+        if self.hid.is_null() {
+            self.hid = unsafe { SDL_hid_open(vendor, product, ptr::null()) };
+            if self.hid.is_null() {
+                return Err(FFBError::DeviceError(format!(
+                    "SDL_hid_open {:04x}:{:04x} failed: {}",
+                    vendor,
+                    product,
+                    Self::get_sdl_error()
+                )));
+            }
+        }
+        Ok(protocol)
+    }
+
+    /// Write `reports` to the HID device opened by `open_vendor_device`
+    fn write_vendor_reports(&mut self, label: &str, reports: &[Vec<u8>]) -> FFBResult<()> {
+        for report in reports {
+            if unsafe { SDL_hid_write(self.hid, report.as_ptr(), report.len()) } < 0 {
+                return Err(FFBError::DeviceError(format!("Sending {} failed: {}", label, Self::get_sdl_error())));
+            }
+        }
+        Ok(())
+    }
+
     fn get_sdl_error() -> String {
         unsafe {
             let error = SDL_GetError();
@@ -172,8 +358,194 @@ impl SdlDriver {
             }
         }
     }
+
+    /// Cache key of an SDL effect: its raw bytes
+    /// The create_* functions start from a zeroed effect, so padding never differs
+    fn effect_key(effect: &SDL_HapticEffect) -> Vec<u8> {
+        // SAFETY: the effect is plain old data, read as bytes within its size
+        unsafe {
+            std::slice::from_raw_parts(
+                effect as *const SDL_HapticEffect as *const u8,
+                std::mem::size_of::<SDL_HapticEffect>(),
+            )
+        }
+        .to_vec()
+    }
+
+    /// The uploaded effect with these parameters, uploading it if it is not cached yet
+    /// The least recently used effect is evicted when the device is full
+    /// Held effects are neither reused nor evicted, they are still playing.
+    fn upload_effect(&mut self, sdl_effect: &SDL_HapticEffect) -> FFBResult<SDL_HapticEffectID> {
+        let key = Self::effect_key(sdl_effect);
+        if let Some(index) = self
+            .uploaded
+            .iter()
+            .position(|(k, id)| *k == key && !self.held.contains(id))
+        {
+            let entry = self.uploaded.remove(index);
+            let effect_id = entry.1;
+            self.uploaded.push(entry);
+            return Ok(effect_id);
+        }
+
+        if self.uploaded.len() >= self.max_effects {
+            if let Some(index) = self.uploaded.iter().position(|(_, id)| !self.held.contains(id)) {
+                let (_, oldest) = self.uploaded.remove(index);
+                unsafe {
+                    SDL_DestroyHapticEffect(self.haptic, oldest);
+                }
+            }
+        }
+        let mut effect_id = unsafe { SDL_CreateHapticEffect(self.haptic, sdl_effect) };
+        if effect_id.0 < 0 && self.uploaded.len() > self.held.len() {
+            // The device may hold fewer effects than it reports; make room and retry
+            let idle: Vec<_> = self
+                .uploaded
+                .iter()
+                .map(|(_, id)| *id)
+                .filter(|id| !self.held.contains(id))
+                .collect();
+            for id in idle {
+                self.destroy_effect(id);
+            }
+            effect_id = unsafe { SDL_CreateHapticEffect(self.haptic, sdl_effect) };
+        }
+        if effect_id.0 < 0 {
+            return Err(FFBError::EffectCreationFailed(Self::get_sdl_error()));
+        }
+        self.uploaded.push((key, effect_id));
+        Ok(effect_id)
+    }
+
+    /// Destroy an uploaded effect and drop it from the cache
+    fn destroy_effect(&mut self, effect_id: SDL_HapticEffectID) {
+        self.uploaded.retain(|(_, id)| *id != effect_id);
+        if self.current_effect_id == Some(effect_id) {
+            self.current_effect_id = None;
+        }
+        unsafe {
+            SDL_DestroyHapticEffect(self.haptic, effect_id);
+        }
+    }
+
+    /// Destroy every uploaded effect
+    fn destroy_effects(&mut self) {
+        self.current_effect_id = None;
+        self.held.clear();
+        for (_, effect_id) in self.uploaded.drain(..) {
+            unsafe {
+                SDL_DestroyHapticEffect(self.haptic, effect_id);
+            }
+        }
+    }
+
+    /// Stop the previous effect, then run this one, uploading it only if it is not cached
+    fn start_effect(&mut self, effect: &Effect) -> FFBResult<()> {
+        if let Some(id) = self.current_effect_id.take() {
+            unsafe {
+                SDL_StopHapticEffect(self.haptic, id);
+            }
+        }
+
+        let sdl_effect = match effect {
+            Effect::Constant { params, force } => self.create_constant_effect(params, force),
+            Effect::Periodic { params, effect } => self.create_periodic_effect(params, effect),
+            Effect::Ramp { params, effect } => self.create_ramp_effect(params, effect),
+            Effect::Condition { params, effect } => self.create_condition_effect(params, effect),
+        };
+
+        let effect_id = self.upload_effect(&sdl_effect)?;
+        unsafe {
+            if !SDL_RunHapticEffect(self.haptic, effect_id, 1) {
+                let error = Self::get_sdl_error();
+                self.destroy_effect(effect_id);
+                return Err(FFBError::EffectPlaybackFailed(error));
+            }
+        }
+        self.current_effect_id = Some(effect_id);
+        Ok(())
+    }
+
+    /// Block for the effect's duration
+    /// Run an infinite constant force and update its level as fast as SDL accepts,
+    /// sweeping between -`magnitude` and `magnitude` so every update differs
+    /// Returns the number of accepted updates and the primary device's packets captured meanwhile
+    /// Streaming stops early once the driver's cancellation token is cancelled
+    pub fn stream_constant_force(&mut self, duration: Duration, magnitude: i16) -> FFBResult<(usize, Vec<UsbPacket>)> {
+        if !self.initialized || self.haptic.is_null() {
+            return Err(FFBError::NotInitialized);
+        }
+        if !self.capture {
+            return Err(FFBError::capture("the driver was created without capture"));
+        }
+
+        let params = EffectParams {
+            duration: 0,
+            ..EffectParams::default()
+        };
+        let mut force = ConstantForce {
+            magnitude: 0,
+            direction: Direction::default(),
+            envelope: Envelope::default(),
+        };
+
+        self.usb_monitor.get_packets().map_err(|e| self.capture_error(e))?;
+        let receiver = self.usb_monitor.subscribe();
+        self.start_effect(&Effect::Constant {
+            params: params.clone(),
+            force: force.clone(),
+        })?;
+        let Some(effect_id) = self.current_effect_id else {
+            return Err(FFBError::EffectCreationFailed("No effect running".to_string()));
+        };
+
+        let magnitude = magnitude.clamp(0, 10000);
+        let started = Instant::now();
+        let mut updates = 0;
+        while started.elapsed() < duration && !self.cancel.is_cancelled() {
+            // Triangle sweep in 100 updates per edge
+            let phase = (updates % 200) as i32;
+            let ramp = if phase < 100 { phase } else { 200 - phase };
+            force.magnitude = (magnitude as i32 * (ramp * 2 - 100) / 100) as i16;
+            let sdl_effect = self.create_constant_effect(&params, &force);
+            unsafe {
+                if !SDL_UpdateHapticEffect(self.haptic, effect_id, &sdl_effect) {
+                    return Err(FFBError::EffectPlaybackFailed(Self::get_sdl_error()));
+                }
+            }
+            updates += 1;
+        }
+        self.stop_all_effects()?;
+        // Updated in place, so its parameters no longer match its cache key
+        self.destroy_effect(effect_id);
+
+        // Let in-flight packets arrive before collecting
+        thread::sleep(Duration::from_millis(100));
+        self.usb_monitor.get_packets().map_err(|e| self.capture_error(e))?;
+        let primary = self.usb_monitor.devices().first().copied();
+        let packets = receiver
+            .try_iter()
+            .filter(|p| primary.is_none() || p.source == primary)
+            .collect();
+        Ok((updates, packets))
+    }
 }
 
+/// SDL haptic feature of each effect type in `EFFECT_LABELS`
+const EFFECT_FEATURES: [SDL_HapticEffectType; 11] = [
+    SDL_HAPTIC_CONSTANT,
+    SDL_HAPTIC_SINE,
+    SDL_HAPTIC_SQUARE,
+    SDL_HAPTIC_TRIANGLE,
+    SDL_HAPTIC_SAWTOOTHUP,
+    SDL_HAPTIC_SAWTOOTHDOWN,
+    SDL_HAPTIC_RAMP,
+    SDL_HAPTIC_SPRING,
+    SDL_HAPTIC_DAMPER,
+    SDL_HAPTIC_FRICTION,
+    SDL_HAPTIC_INERTIA,
+];
+
 impl Default for SdlDriver {
     fn default() -> Self {
         Self::new()
@@ -182,14 +554,17 @@ impl Default for SdlDriver {
 
 impl FfbDriver for SdlDriver {
     fn initialize(&mut self) -> FFBResult<()> {
-        // Start USB capture first - this is required
-        println!("Starting USB capture...");
-        self.usb_monitor.start_capture().map_err(|e| {
-            FFBError::InitializationFailed(format!(
-                "Failed to start USB capture: {}. Install USBPcap (Windows) or tcpdump (Linux).",
-                e
-            ))
-        })?;
+        // Start USB capture first - this is required unless only playing
+        if self.capture {
+            println!("Starting USB capture...");
+            self.usb_monitor.start_capture().map_err(|e| {
+                FFBError::InitializationFailed(format!(
+                    "Failed to start USB capture: {}. Install USBPcap (Windows) or libpcap/tcpdump (Linux).",
+                    e
+                ))
+            })?;
+            self.capture_started = Some(Instant::now());
+        }
 
         unsafe {
             // Initialize SDL with joystick and haptic support
@@ -206,6 +581,9 @@ impl FfbDriver for SdlDriver {
                 return Err(FFBError::DeviceNotFound);
             }
 
+            // With --device, the primary device's joystick, so that several drivers can
+            // each play on their own device
+            let wanted = self.usb_monitor.devices().first().copied();
             let mut found_joystick: *mut SDL_Joystick = ptr::null_mut();
             let mut idx = 0;
             
@@ -214,6 +592,14 @@ impl FfbDriver for SdlDriver {
                 if joy_id == 0 {
                     break;
                 }
+                if let Some(wanted) = wanted {
+                    if SDL_GetJoystickVendorForID(joy_id) != wanted.vendor_id
+                        || SDL_GetJoystickProductForID(joy_id) != wanted.product_id
+                    {
+                        idx += 1;
+                        continue;
+                    }
+                }
                 
                 let joystick = SDL_OpenJoystick(joy_id);
                 if !joystick.is_null() {
@@ -234,6 +620,12 @@ impl FfbDriver for SdlDriver {
             SDL_free(joysticks as *mut _);
 
             if found_joystick.is_null() {
+                if let Some(wanted) = wanted {
+                    return Err(FFBError::InitializationFailed(format!(
+                        "no force feedback joystick {} found",
+                        wanted
+                    )));
+                }
                 // Try to open haptic device directly
                 let haptics = SDL_GetHaptics(ptr::null_mut());
                 if !haptics.is_null() {
@@ -252,6 +644,7 @@ impl FfbDriver for SdlDriver {
                     return Err(FFBError::DeviceNotFound);
                 }
             } else {
+                self.joystick = found_joystick;
                 self.haptic = SDL_OpenHapticFromJoystick(found_joystick);
                 if self.haptic.is_null() {
                     return Err(FFBError::InitializationFailed(format!(
@@ -270,6 +663,9 @@ impl FfbDriver for SdlDriver {
             
             let num_axes = SDL_GetNumHapticAxes(self.haptic);
             println!("  Axes: {}", num_axes);
+
+            self.max_effects = SDL_GetMaxHapticEffects(self.haptic).max(1) as usize;
+            println!("  Effect slots: {}", self.max_effects);
             
             let features = SDL_GetHapticFeatures(self.haptic);
             println!("  Supported effects:");
@@ -295,74 +691,206 @@ impl FfbDriver for SdlDriver {
                 println!("    - Inertia");
             }
 
+            let handle = HapticHandle(self.haptic);
+            self.watchdog = Some(watchdog::register(move || {
+                let handle = &handle;
+                SDL_StopHapticEffects(handle.0);
+            }));
             self.initialized = true;
             Ok(())
         }
     }
 
     fn apply_effect(&mut self, effect: &Effect) -> FFBResult<Vec<String>> {
-        if !self.initialized || self.haptic.is_null() {
-            return Err(FFBError::DeviceError("Device not initialized".to_string()));
+        match self.begin_effect(effect)? {
+            EffectProgress::Done(packets) => Ok(packets),
+            EffectProgress::Wait(duration) => {
+                self.cancel.sleep(duration);
+                self.finish_effect()
+            }
         }
+    }
 
-        // Clear any pending captured packets before applying effect
-        let _ = self.usb_monitor.get_packets();
-
-        // Stop previous effect
-        if let Some(id) = self.current_effect_id.take() {
-            unsafe {
-                SDL_StopHapticEffect(self.haptic, id);
-                SDL_DestroyHapticEffect(self.haptic, id);
-            }
+    fn begin_effect(&mut self, effect: &Effect) -> FFBResult<EffectProgress> {
+        if !self.initialized || self.haptic.is_null() {
+            return Err(FFBError::NotInitialized);
         }
+        // A delayed effect only starts playing after its start delay
+        let effect_end = Duration::from_millis(effect.end_time() as u64);
+        self.last_wire_latency = None;
+        self.last_timestamps.clear();
+        self.last_axis_samples.clear();
 
-        let sdl_effect = match effect {
-            Effect::Constant { params, force } => self.create_constant_effect(params, force),
-            Effect::Periodic { params, effect } => self.create_periodic_effect(params, effect),
-            Effect::Ramp { params, effect } => self.create_ramp_effect(params, effect),
-            Effect::Condition { params, effect } => self.create_condition_effect(params, effect),
-        };
+        if !self.capture {
+            self.start_effect(effect)?;
+            self.pending_step = None;
+            return Ok(EffectProgress::Wait(effect_end));
+        }
 
-        unsafe {
-            let effect_id = SDL_CreateHapticEffect(self.haptic, &sdl_effect);
-            if effect_id.0 < 0 {
-                return Err(FFBError::EffectCreationFailed(Self::get_sdl_error()));
+        // Without a ring buffer: clear any pending captured packets before applying effect,
+        // then collect only the packets delivered during this step
+        let ring_buffer = self.usb_monitor.ring_buffer();
+        let packets = match ring_buffer {
+            Some(_) => None,
+            None => {
+                self.usb_monitor.get_packets().map_err(|e| self.capture_error(e))?;
+                Some(self.usb_monitor.subscribe())
             }
+        };
+        let axis = match (self.axis_rate_hz, self.joystick.is_null()) {
+            (Some(rate), false) => Some(AxisSampler::start(self.joystick, Duration::from_secs(1) / rate, Instant::now())),
+            _ => None,
+        };
+        let step = PendingStep {
+            started: Instant::now(),
+            effect_start: capture_clock(),
+            effect_end,
+            stats: self.usb_monitor.stats(),
+            packets,
+            axis,
+        };
 
-            if !SDL_RunHapticEffect(self.haptic, effect_id, 1) {
-                SDL_DestroyHapticEffect(self.haptic, effect_id);
-                return Err(FFBError::EffectPlaybackFailed(Self::get_sdl_error()));
-            }
+        self.start_effect(effect)?;
+        self.pending_step = Some(step);
 
-            self.current_effect_id = Some(effect_id);
-        }
+        // Wait for effect start delay and duration to allow USB capture, plus the
+        // post-roll packets of a ring buffer before the window is extracted
+        let post_roll = ring_buffer.map(|ring| ring.post_roll).unwrap_or_default();
+        Ok(EffectProgress::Wait(effect_end + post_roll))
+    }
 
-        // Wait for effect duration to allow USB capture
-        let duration = effect.duration();
-        if duration > 0 {
-            thread::sleep(Duration::from_millis(duration as u64));
-        }
+    fn finish_effect(&mut self) -> FFBResult<Vec<String>> {
+        // Playback only
+        let Some(step) = self.pending_step.take() else {
+            return Ok(Vec::new());
+        };
+        self.last_axis_samples = step.axis.map(AxisSampler::finish).unwrap_or_default();
 
         // Capture USB packets that were generated during effect playback
-        let packets = self.usb_monitor.get_packets();
-        let captured_packets = packets
+        let packets = match (self.usb_monitor.ring_buffer(), step.packets) {
+            (Some(ring), _) => {
+                let effect_end = step.effect_start + step.effect_end;
+                self.usb_monitor
+                    .packets_between(step.effect_start.saturating_sub(ring.pre_roll), effect_end + ring.post_roll)
+                    .map_err(|e| self.capture_error(e))?
+            }
+            (None, step_packets) => {
+                self.usb_monitor.get_packets().map_err(|e| self.capture_error(e))?;
+                step_packets.map(|r| r.try_iter().collect()).unwrap_or_default()
+            }
+        };
+
+        // With several capture devices the step output is the primary device's traffic,
+        // the others get their own sections
+        let devices = self.usb_monitor.devices();
+        let (packets, others): (Vec<_>, Vec<_>) = match devices {
+            [primary, _, ..] => packets.into_iter().partition(|p| p.source == Some(*primary)),
+            _ => (packets, Vec::new()),
+        };
+        self.last_device_captures = devices
             .iter()
-            .filter(|p| UsbMonitor::is_ffb_command(p))
-            .map(|p| format_hex(&p.data))
+            .skip(1)
+            .map(|device| DeviceCapture {
+                device: device.to_string(),
+                packets: others
+                    .iter()
+                    .filter(|p| p.source == Some(*device))
+                    .map(|p| format_hex(&p.data))
+                    .collect(),
+            })
             .collect();
 
+        self.last_latencies = self.usb_monitor.latencies(&packets);
+        // Packets share the capture clock; pre-roll packets of a ring buffer predate the submit
+        self.last_wire_latency = packets
+            .iter()
+            .filter(|p| p.direction == PacketDirection::HostToDevice && p.timestamp >= step.effect_start)
+            .map(|p| p.timestamp - step.effect_start)
+            .min();
+        self.last_timestamps = packets.iter().map(|p| p.timestamp).collect();
+        let captured_packets = packets.iter().map(|p| format_hex(&p.data)).collect();
+        self.last_capture_summary = Some(
+            self.usb_monitor
+                .stats()
+                .since(&step.stats)
+                .describe(step.started.elapsed()),
+        );
+
         Ok(captured_packets)
     }
 
+    fn packet_latencies(&self) -> Vec<Option<Duration>> {
+        self.last_latencies.clone()
+    }
+
+    fn packet_timestamps(&self) -> Vec<Duration> {
+        self.last_timestamps.clone()
+    }
+
+    fn wire_latency(&self) -> Option<Duration> {
+        self.last_wire_latency
+    }
+
+    fn axis_samples(&self) -> Vec<AxisSample> {
+        self.last_axis_samples.clone()
+    }
+
+    fn device_captures(&self) -> Vec<DeviceCapture> {
+        self.last_device_captures.clone()
+    }
+
+    fn capture_summary(&self) -> Option<String> {
+        self.last_capture_summary.clone()
+    }
+
+    fn live_packets(&self) -> Option<Receiver<UsbPacket>> {
+        self.capture.then(|| self.usb_monitor.subscribe())
+    }
+
+    fn set_cancellation(&mut self, cancel: CancellationToken) {
+        self.usb_monitor.set_cancellation(cancel.clone());
+        self.cancel = cancel;
+    }
+
+    /// The current effect is handed over as held; the next step starts without stopping it
+    fn hold_effect(&mut self) -> Option<HeldEffect> {
+        let effect_id = self.current_effect_id.take()?;
+        self.held.push(effect_id);
+        Some(HeldEffect(effect_id.0))
+    }
+
+    fn release_effect(&mut self, effect: HeldEffect) -> FFBResult<()> {
+        let effect_id = SDL_HapticEffectID(effect.0);
+        if self.haptic.is_null() || !self.held.contains(&effect_id) {
+            return Ok(());
+        }
+        self.held.retain(|id| *id != effect_id);
+        // Stays uploaded, to be restarted by later iterations
+        if !unsafe { SDL_StopHapticEffect(self.haptic, effect_id) } {
+            return Err(FFBError::EffectStopFailed(Self::get_sdl_error()));
+        }
+        Ok(())
+    }
+
+    fn stop_step_effect(&mut self) -> FFBResult<()> {
+        if let Some(id) = self.current_effect_id.take() {
+            if !unsafe { SDL_StopHapticEffect(self.haptic, id) } {
+                return Err(FFBError::EffectStopFailed(Self::get_sdl_error()));
+            }
+        }
+        Ok(())
+    }
+
     fn stop_all_effects(&mut self) -> FFBResult<()> {
         if self.haptic.is_null() {
             return Ok(());
         }
+        self.held.clear();
 
+        // Uploaded effects stay on the device to be restarted by later iterations
         if let Some(id) = self.current_effect_id.take() {
             unsafe {
                 SDL_StopHapticEffect(self.haptic, id);
-                SDL_DestroyHapticEffect(self.haptic, id);
             }
         }
 
@@ -378,22 +906,126 @@ impl FfbDriver for SdlDriver {
 
         // Stop USB capture
         self.usb_monitor.stop_capture();
+        if let Some(started) = self.capture_started.take() {
+            println!("Capture total: {}", self.usb_monitor.stats().describe(started.elapsed()));
+        }
 
+        self.watchdog = None;
         if !self.haptic.is_null() {
+            self.destroy_effects();
             unsafe {
                 SDL_CloseHaptic(self.haptic);
             }
             self.haptic = ptr::null_mut();
         }
+        if !self.hid.is_null() {
+            unsafe {
+                SDL_hid_close(self.hid);
+            }
+            self.hid = ptr::null_mut();
+        }
+        if !self.joystick.is_null() {
+            unsafe {
+                SDL_CloseJoystick(self.joystick);
+            }
+            self.joystick = ptr::null_mut();
+        }
 
+        // Other drivers may still use SDL; the subsystems shut down with the last one
         unsafe {
-            SDL_Quit();
+            SDL_QuitSubSystem(SDL_INIT_JOYSTICK | SDL_INIT_HAPTIC);
         }
 
         self.initialized = false;
         Ok(())
     }
     
+    /// Writes the reports of the wheel's vendor to its HID device; they reach the
+    /// capture like the FFB reports, so none are returned
+    fn send_peripherals(&mut self, commands: &[PeripheralCommand]) -> FFBResult<Vec<String>> {
+        if commands.is_empty() {
+            return Ok(Vec::new());
+        }
+        let protocol = self.open_vendor_device()?;
+        for command in commands {
+            let reports = protocol.reports(command).map_err(FFBError::InvalidParameter)?;
+            self.write_vendor_reports(&command.label(), &reports)?;
+        }
+        Ok(Vec::new())
+    }
+
+    /// The torque limit is SDL's device gain; the rotation range is a vendor report
+    /// like the peripheral commands
+    fn send_settings(&mut self, settings: &[DeviceSetting]) -> FFBResult<Vec<String>> {
+        for setting in settings {
+            match setting {
+                DeviceSetting::TorqueLimit { percent } => unsafe {
+                    if (SDL_GetHapticFeatures(self.haptic) & SDL_HAPTIC_GAIN.0 as u32) == 0 {
+                        return Err(FFBError::DeviceError("The device has no gain control".to_string()));
+                    }
+                    if !SDL_SetHapticGain(self.haptic, *percent as i32) {
+                        return Err(FFBError::DeviceError(format!(
+                            "Setting {} failed: {}",
+                            setting.label(),
+                            Self::get_sdl_error()
+                        )));
+                    }
+                },
+                DeviceSetting::RotationRange { degrees } => {
+                    let protocol = self.open_vendor_device()?;
+                    self.write_vendor_reports(&setting.label(), &protocol.rotation_reports(*degrees))?;
+                }
+            }
+        }
+        Ok(Vec::new())
+    }
+
+    /// Effect types from the device's haptic features; durations as SDL's effect
+    /// fields hold them (start delays are 16-bit)
+    fn capabilities(&self) -> Option<Capabilities> {
+        if !self.initialized || self.haptic.is_null() {
+            return None;
+        }
+        let features = unsafe { SDL_GetHapticFeatures(self.haptic) };
+        let playing = unsafe { SDL_GetMaxHapticEffectsPlaying(self.haptic) };
+        Some(Capabilities {
+            effects: EFFECT_LABELS
+                .iter()
+                .zip(EFFECT_FEATURES)
+                .filter(|(_, feature)| features & feature.0 as u32 != 0)
+                .map(|(label, _)| *label)
+                .collect(),
+            max_duration_ms: Some(SDL_HAPTIC_INFINITY - 1),
+            max_start_delay_ms: Some(u16::MAX as u32),
+            max_playing: (playing > 0).then_some(playing as usize),
+        })
+    }
+
+    /// The joystick's connection state, and a read from the vendor HID device if one is open
+    fn check_health(&mut self) -> Option<Health> {
+        if !self.initialized {
+            return None;
+        }
+        let mut checked = Vec::new();
+        if !self.joystick.is_null() {
+            // Picks up a removed device
+            unsafe { SDL_UpdateJoysticks() };
+            if !unsafe { SDL_JoystickConnected(self.joystick) } {
+                return Some(Health::not_responding("joystick disconnected"));
+            }
+            checked.push("joystick connected");
+        }
+        if !self.hid.is_null() {
+            // Doesn't wait for an input report; only an error tells something
+            let mut report = [0u8; 64];
+            if unsafe { SDL_hid_read_timeout(self.hid, report.as_mut_ptr(), report.len(), 0) } < 0 {
+                return Some(Health::not_responding(format!("HID read failed: {}", Self::get_sdl_error())));
+            }
+            checked.push("HID device readable");
+        }
+        (!checked.is_empty()).then(|| Health::ok(checked.join(", ")))
+    }
+
     fn name(&self) -> &str {
         "SDL"
     }
@@ -75,6 +75,25 @@ pub enum SimagicEffectType {
     Inertia = 0x09,      // Confirmed from captures (not 0x08)
 }
 
+impl SimagicEffectType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x01 => Some(SimagicEffectType::Constant),
+            0x02 => Some(SimagicEffectType::Sine),
+            0x05 => Some(SimagicEffectType::Damper),
+            0x06 => Some(SimagicEffectType::Spring),
+            0x07 => Some(SimagicEffectType::Friction),
+            0x09 => Some(SimagicEffectType::Inertia),
+            0x0E => Some(SimagicEffectType::Ramp),
+            0x0F => Some(SimagicEffectType::Square),
+            0x10 => Some(SimagicEffectType::Triangle),
+            0x11 => Some(SimagicEffectType::SawtoothUp),
+            0x12 => Some(SimagicEffectType::SawtoothDown),
+            _ => None,
+        }
+    }
+}
+
 impl From<&Effect> for SimagicEffectType {
     fn from(effect: &Effect) -> Self {
         match effect {
@@ -285,7 +304,7 @@ impl FfbDriver for SimagicDriver {
 
     fn apply_effect(&mut self, effect: &Effect) -> FFBResult<Vec<String>> {
         if !self.initialized {
-            return Err(FFBError::DeviceError("Device not initialized".to_string()));
+            return Err(FFBError::NotInitialized);
         }
 
         let effect_type = SimagicEffectType::from(effect);
@@ -362,6 +381,16 @@ impl FfbDriver for SimagicDriver {
         Ok(generated_reports.iter().map(Self::format_report).collect())
     }
 
+    /// Every effect type has a report; durations are 16-bit and start delays are not sent
+    fn capabilities(&self) -> Option<crate::capabilities::Capabilities> {
+        Some(crate::capabilities::Capabilities {
+            effects: crate::scenario::EFFECT_LABELS.to_vec(),
+            max_duration_ms: Some(0xFFFF),
+            max_start_delay_ms: Some(0),
+            max_playing: None,
+        })
+    }
+
     fn stop_all_effects(&mut self) -> FFBResult<()> {
         // Send stop commands for common effect types
         // In practice, we'd track which effects are active
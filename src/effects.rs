@@ -1,3 +1,6 @@
+//! Force feedback effects as scenario steps describe them, modelled on the USB HID
+//! PID effect types: constant, periodic, ramp and condition effects
+
 use serde::{Deserialize, Serialize};
 
 /// Effect direction (in degrees, 0-360)
@@ -236,4 +239,92 @@ impl Effect {
             Effect::Condition { params, .. } => params.start_delay,
         }
     }
+
+    /// Time from starting the effect until it ends (ms): its start delay plus its duration
+    pub fn end_time(&self) -> u32 {
+        self.start_delay().saturating_add(self.duration())
+    }
+}
+
+impl Envelope {
+    /// Level (0-10000) of an effect with `magnitude` (0-10000) at `t` ms after its
+    /// start, for a `duration` in ms (0 = infinite, which never fades)
+    pub fn level_at(&self, magnitude: f64, t: f64, duration: u32) -> f64 {
+        if self.attack_time > 0 && t < self.attack_time as f64 {
+            let progress = t / self.attack_time as f64;
+            return self.attack_level as f64 + (magnitude - self.attack_level as f64) * progress;
+        }
+        let fade_start = duration as f64 - self.fade_time as f64;
+        if duration > 0 && self.fade_time > 0 && t > fade_start {
+            let progress = ((t - fade_start) / self.fade_time as f64).min(1.0);
+            return magnitude + (self.fade_level as f64 - magnitude) * progress;
+        }
+        magnitude
+    }
+}
+
+impl WaveType {
+    /// Value (-1 to 1) at `position` (0-1) in the period, rising through zero at 0
+    /// like a sine
+    pub fn value_at(&self, position: f64) -> f64 {
+        match self {
+            WaveType::Sine => (position * std::f64::consts::TAU).sin(),
+            WaveType::Square => {
+                if position < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            WaveType::Triangle => {
+                if position < 0.25 {
+                    4.0 * position
+                } else if position < 0.75 {
+                    2.0 - 4.0 * position
+                } else {
+                    4.0 * position - 4.0
+                }
+            }
+            WaveType::SawtoothUp => 2.0 * position - 1.0,
+            WaveType::SawtoothDown => 1.0 - 2.0 * position,
+        }
+    }
+}
+
+impl Effect {
+    /// Force (-10000 to 10000) the effect asks for `t` ms after it was started,
+    /// with its start delay, envelope and gain applied
+    /// None for condition effects, whose force depends on the wheel position
+    pub fn force_at(&self, t: f64) -> Option<f64> {
+        let (params, envelope) = match self {
+            Effect::Constant { params, force } => (params, &force.envelope),
+            Effect::Periodic { params, effect } => (params, &effect.envelope),
+            Effect::Ramp { params, effect } => (params, &effect.envelope),
+            Effect::Condition { .. } => return None,
+        };
+        let t = t - params.start_delay as f64;
+        if t < 0.0 || (params.duration > 0 && t > params.duration as f64) {
+            return Some(0.0);
+        }
+        let force = match self {
+            Effect::Constant { force, .. } => {
+                let magnitude = force.magnitude as f64;
+                magnitude.signum() * envelope.level_at(magnitude.abs(), t, params.duration)
+            }
+            Effect::Periodic { effect, .. } => {
+                let amplitude = envelope.level_at(effect.magnitude as f64, t, params.duration);
+                let cycles = if effect.period > 0 { t / effect.period as f64 } else { 0.0 };
+                let position = (cycles + effect.phase as f64 / 36000.0).rem_euclid(1.0);
+                effect.offset as f64 + amplitude * effect.wave_type.value_at(position)
+            }
+            Effect::Ramp { effect, .. } => {
+                let progress = if params.duration > 0 { t / params.duration as f64 } else { 0.0 };
+                let start = effect.start_magnitude as f64;
+                let magnitude = start + (effect.end_magnitude as f64 - start) * progress;
+                magnitude.signum() * envelope.level_at(magnitude.abs(), t, params.duration)
+            }
+            Effect::Condition { .. } => return None,
+        };
+        Some(force * params.gain as f64 / 10000.0)
+    }
 }
@@ -0,0 +1,378 @@
+//! Environment variables in scenario files
+//!
+//! `${VAR}` is replaced by the variable's value when a scenario is loaded, and
+//! `${VAR:-fallback}` by the fallback when the variable is unset or empty, so a CI
+//! pipeline can inject magnitudes, durations or device identifiers without
+//! templating the YAML itself. Values are inserted before the YAML is parsed:
+//! `magnitude: ${MAG}` reads a number, and a placeholder in quotes
+//! (`name: "Run ${BUILD}"`) stays text. A value YAML would read differently, one
+//! with `: `, ` #`, quotes or line breaks, is escaped inside quotes and written as
+//! a double-quoted string where the placeholder is a whole value; inside other
+//! unquoted text it is an error, as the placeholder needs quotes then. Quoted
+//! strings may continue over several lines. In block scalars (`|`, `>`) values
+//! are text as written, their further lines indented like the placeholder's line.
+//! `$${` writes a literal `${`. Comments are left alone, so commented-out
+//! placeholders need no variable.
+
+/// Expand the placeholders of a YAML document with `lookup`
+/// Fails on a variable that is neither set nor has a fallback, naming its line.
+pub fn expand(text: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    if !text.contains("${") {
+        return Ok(text.to_string());
+    }
+    let mut expanded = String::with_capacity(text.len());
+    // Quoted scalars may continue over several lines, so the state carries over
+    let mut quoting = Quoting::default();
+    let mut block = None;
+    for (index, line) in text.split_inclusive('\n').enumerate() {
+        let error = |e| format!("line {}: {}", index + 1, e);
+        let indent = line.len() - line.trim_start_matches(' ').len();
+        match block {
+            Some(parent) if line.trim().is_empty() || indent > parent => {
+                // Quotes and `#` in a block scalar are text
+                let mut text = Quoting::default();
+                expand_line(line, &lookup, Insert::Block(&line[..indent]), &mut text, &mut expanded).map_err(error)?;
+                continue;
+            }
+            _ => block = None,
+        }
+        let (content, comment) = line.split_at(comment_start(line, &quoting));
+        expand_line(content, &lookup, Insert::Yaml, &mut quoting, &mut expanded).map_err(error)?;
+        expanded.push_str(comment);
+        if !comment.is_empty() {
+            quoting.previous = '\n';
+        }
+        if quoting.quote.is_none() {
+            block = block_scalar_parent(content);
+        }
+    }
+    Ok(expanded)
+}
+
+/// Expand the placeholders of a YAML document with the process environment
+pub fn expand_env(text: &str) -> Result<String, String> {
+    expand(text, |name| std::env::var(name).ok())
+}
+
+/// Byte offset of the line's comment, the line's length if it has none
+/// A `#` starts a comment at the line start or after whitespace, outside quoted
+/// scalars; a quote only opens one where a scalar can start.
+fn comment_start(line: &str, quoting: &Quoting) -> usize {
+    let mut quoting = quoting.clone();
+    for (offset, c) in line.char_indices() {
+        if quoting.quote.is_none() && c == '#' && quoting.previous.is_whitespace() {
+            return offset;
+        }
+        quoting.push(c);
+    }
+    line.len()
+}
+
+/// Column a block scalar's lines must be indented past, if `content` ends with
+/// a block scalar indicator (`|`, `>`, with chomping or indentation indicators)
+/// That is the column of its key, or of its `-` in a sequence entry.
+fn block_scalar_parent(content: &str) -> Option<usize> {
+    let trimmed = content.trim_end();
+    let token = &trimmed[trimmed.rfind(char::is_whitespace).map_or(0, |i| i + 1)..];
+    let mut chars = token.chars();
+    let is_indicator = matches!(chars.next(), Some('|' | '>')) && chars.all(|c| "+-123456789".contains(c));
+    if !is_indicator {
+        return None;
+    }
+    let mut rest = trimmed.trim_start();
+    let mut column = trimmed.len() - rest.len();
+    let mut parent = column;
+    while let Some(entry) = rest.strip_prefix('-').filter(|entry| entry.starts_with(char::is_whitespace)) {
+        parent = column;
+        let stripped = entry.trim_start();
+        column += rest.len() - stripped.len();
+        rest = stripped;
+    }
+    Some(if rest == token { parent } else { column })
+}
+
+/// Whether the text so far is inside a quoted scalar, scanned one character at a time
+#[derive(Clone)]
+struct Quoting {
+    quote: Option<char>,
+    previous: char,
+    /// The previous character was a `\` escaping this one in a double-quoted scalar
+    escaped: bool,
+    /// The previous character closed a single-quoted scalar, unless a quote follows: `''`
+    closed: bool,
+}
+
+impl Default for Quoting {
+    fn default() -> Self {
+        Self {
+            quote: None,
+            previous: ' ',
+            escaped: false,
+            closed: false,
+        }
+    }
+}
+
+impl Quoting {
+    fn push(&mut self, c: char) {
+        let (escaped, closed) = (self.escaped, self.closed);
+        self.escaped = false;
+        self.closed = false;
+        match (self.quote, c) {
+            (Some('"'), _) if escaped => {}
+            (Some('"'), '\\') => self.escaped = true,
+            (None, '\'') if closed => self.quote = Some(c),
+            (None, '"' | '\'') if self.previous.is_whitespace() || "[{,".contains(self.previous) => {
+                self.quote = Some(c)
+            }
+            (Some(open), _) if c == open => {
+                self.quote = None;
+                self.closed = c == '\'';
+            }
+            _ => {}
+        }
+        self.previous = c;
+    }
+
+    fn push_str(&mut self, text: &str) {
+        text.chars().for_each(|c| self.push(c));
+    }
+}
+
+/// How values are written into a line
+enum Insert<'a> {
+    /// As YAML reads them back: escaped or quoted as needed
+    Yaml,
+    /// As written inside a block scalar, further lines indented like this one
+    Block(&'a str),
+}
+
+fn expand_line(
+    line: &str,
+    lookup: &impl Fn(&str) -> Option<String>,
+    insert: Insert,
+    quoting: &mut Quoting,
+    out: &mut String,
+) -> Result<(), String> {
+    let line_start = out.len();
+    let mut rest = line;
+    while let Some(start) = rest.find("${") {
+        // `$${` escapes the placeholder
+        if rest[..start].ends_with('$') {
+            out.push_str(&rest[..start - 1]);
+            out.push_str("${");
+            quoting.push_str(&rest[..start + 2]);
+            rest = &rest[start + 2..];
+            continue;
+        }
+        out.push_str(&rest[..start]);
+        quoting.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or("unterminated ${")? + start;
+        let placeholder = &rest[start + 2..end];
+        let (name, fallback) = match placeholder.split_once(":-") {
+            Some((name, fallback)) => (name, Some(fallback)),
+            None => (placeholder, None),
+        };
+        if !is_variable_name(name) {
+            return Err(format!("'{}' is not an environment variable name", name));
+        }
+        let value = match (lookup(name), fallback) {
+            (Some(value), Some(fallback)) if value.is_empty() => fallback.to_string(),
+            (Some(value), _) => value,
+            (None, Some(fallback)) => fallback.to_string(),
+            (None, None) => {
+                return Err(format!(
+                    "environment variable {} is not set (use ${{{}:-fallback}} for a default)",
+                    name, name
+                ))
+            }
+        };
+        let after = &rest[end + 1..];
+        match (&insert, quoting.quote) {
+            (Insert::Block(indent), _) => out.push_str(&value.replace('\n', &format!("\n{}", indent))),
+            (Insert::Yaml, Some('"')) => out.push_str(&escape_double_quoted(&value)),
+            (Insert::Yaml, Some(_)) if value.contains(['\n', '\r']) => {
+                return Err(format!(
+                    "the value of {} has a line break, which a single-quoted string cannot hold",
+                    name
+                ))
+            }
+            (Insert::Yaml, Some(_)) => out.push_str(&value.replace('\'', "''")),
+            (Insert::Yaml, None) if !needs_quotes(&value) => out.push_str(&value),
+            (Insert::Yaml, None) if is_whole_value(&out[line_start..], after) => {
+                out.push('"');
+                out.push_str(&escape_double_quoted(&value));
+                out.push('"');
+            }
+            (Insert::Yaml, None) => {
+                return Err(format!(
+                    "the value of {} needs quotes in YAML; put the placeholder in double quotes",
+                    name
+                ))
+            }
+        }
+        if let Some(last) = out[line_start..].chars().next_back() {
+            quoting.previous = last;
+        }
+        rest = after;
+    }
+    out.push_str(rest);
+    quoting.push_str(rest);
+    Ok(())
+}
+
+/// Whether YAML would read `value`, written as a plain scalar, as anything but
+/// that text: an indicator at its start, `: ` or ` #` inside, surrounding
+/// whitespace or control characters like line breaks
+fn needs_quotes(value: &str) -> bool {
+    let mut chars = value.chars();
+    let starts_with_indicator = match (chars.next(), chars.next()) {
+        (Some('-' | '?' | ':'), next) => next.is_none_or(char::is_whitespace),
+        (Some(first), _) => ",[]{}#&*!|>'\"%@`".contains(first),
+        (None, _) => false,
+    };
+    starts_with_indicator
+        || value.trim() != value
+        || value.contains(": ")
+        || value.ends_with(':')
+        || value.contains(" #")
+        || value.contains(char::is_control)
+}
+
+/// Whether a placeholder between `before` and `after` on its line is a whole
+/// scalar, so that quoting its value quotes nothing else
+fn is_whole_value(before: &str, after: &str) -> bool {
+    let trimmed = before.trim_end();
+    let spaced = trimmed.len() < before.len();
+    let starts = trimmed.is_empty()
+        || trimmed.ends_with(['[', '{', ','])
+        || (spaced && trimmed.ends_with([':', '-', '?']));
+    let rest = after.trim_start();
+    let ends = rest.is_empty()
+        || rest.starts_with([',', ']', '}'])
+        || after.strip_prefix(':').is_some_and(|key| key.is_empty() || key.starts_with(char::is_whitespace));
+    starts && ends
+}
+
+/// `value` escaped for the inside of a double-quoted YAML string
+fn escape_double_quoted(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_with(text: &str, vars: &[(&str, &str)]) -> Result<String, String> {
+        expand(text, |name| {
+            vars.iter().find(|(var, _)| *var == name).map(|(_, value)| value.to_string())
+        })
+    }
+
+    /// The `value` key of the expanded document, as YAML reads it
+    fn parsed(text: &str, value: &str) -> serde_yaml::Value {
+        let expanded = expand_with(text, &[("VALUE", value)]).unwrap();
+        serde_yaml::from_str::<serde_yaml::Mapping>(&expanded).unwrap()["value"].clone()
+    }
+
+    #[test]
+    fn fallback_is_used_when_unset_or_empty() {
+        assert_eq!(expand_with("a: ${MAG:-0.5}\n", &[]).unwrap(), "a: 0.5\n");
+        assert_eq!(expand_with("a: ${MAG:-0.5}\n", &[("MAG", "")]).unwrap(), "a: 0.5\n");
+        assert_eq!(expand_with("a: ${MAG:-0.5}\n", &[("MAG", "0.8")]).unwrap(), "a: 0.8\n");
+        let error = expand_with("a: 1\nb: ${MAG}\n", &[]).unwrap_err();
+        assert!(error.starts_with("line 2: environment variable MAG is not set"), "{}", error);
+    }
+
+    #[test]
+    fn double_dollar_writes_a_literal_placeholder() {
+        assert_eq!(expand_with("a: $${MAG} ${MAG}\n", &[("MAG", "1")]).unwrap(), "a: ${MAG} 1\n");
+        assert_eq!(expand_with("a: $${UNSET}\n", &[]).unwrap(), "a: ${UNSET}\n");
+    }
+
+    #[test]
+    fn comments_are_not_expanded() {
+        assert_eq!(expand_with("a: 1 # ${UNSET}\n# ${UNSET}\n", &[]).unwrap(), "a: 1 # ${UNSET}\n# ${UNSET}\n");
+        assert_eq!(
+            expand_with("a: \"${B} # not a comment\" # ${UNSET}\n", &[("B", "x")]).unwrap(),
+            "a: \"x # not a comment\" # ${UNSET}\n"
+        );
+    }
+
+    #[test]
+    fn malformed_placeholders_are_rejected() {
+        assert_eq!(expand_with("a: ${MAG\n", &[]).unwrap_err(), "line 1: unterminated ${");
+        assert!(expand_with("a: ${1MAG}\n", &[]).unwrap_err().contains("not an environment variable name"));
+    }
+
+    #[test]
+    fn plain_values_keep_their_type() {
+        assert_eq!(parsed("value: ${VALUE}", "-0.5"), serde_yaml::Value::from(-0.5));
+        assert_eq!(parsed("value: ${VALUE}", "true"), serde_yaml::Value::from(true));
+        assert_eq!(parsed("value: Run ${VALUE}", "42"), serde_yaml::Value::from("Run 42"));
+    }
+
+    #[test]
+    fn special_values_stay_text() {
+        for value in ["a: b", "a #b", "\"quoted\"", "it's", "line\nbreak", "- item", " padded ", "back\\slash"] {
+            assert_eq!(parsed("value: ${VALUE}", value), serde_yaml::Value::from(value), "{:?}", value);
+            assert_eq!(parsed("value: \"${VALUE}\"", value), serde_yaml::Value::from(value), "{:?}", value);
+            assert_eq!(parsed("value: [${VALUE}]", value)[0], serde_yaml::Value::from(value), "{:?}", value);
+        }
+        assert_eq!(parsed("value: 'Run ${VALUE}'", "it's: #1"), serde_yaml::Value::from("Run it's: #1"));
+    }
+
+    #[test]
+    fn quoted_values_continue_over_lines() {
+        let value = "a\"b # c";
+        let expected = serde_yaml::Value::from(format!("first {}", value));
+        assert_eq!(parsed("value: \"first\n  ${VALUE}\"\n", value), expected);
+        assert_eq!(parsed("value: 'it''s\n  ${VALUE}'\n", "it's"), serde_yaml::Value::from("it's it's"));
+        assert_eq!(parsed("value: \"a \\\" #\n  ${VALUE}\"\n", "b"), serde_yaml::Value::from("a \" # b"));
+        // The quote closed on the line before, so this `#` starts a comment
+        assert_eq!(expand_with("a: \"x\n  y\" # ${UNSET}\n", &[]).unwrap(), "a: \"x\n  y\" # ${UNSET}\n");
+    }
+
+    #[test]
+    fn block_scalar_values_are_inserted_as_written() {
+        let value = "Torque: 30% # max \"peak\"\nsecond line";
+        let expected = serde_yaml::Value::from(format!("{}\n", value));
+        assert_eq!(parsed("value: |\n  ${VALUE}\nnext: 1\n", value), expected);
+        assert_eq!(parsed("value: |-\n  # ${VALUE}\n", "x"), serde_yaml::Value::from("# x"));
+        let text = "list:\n- |\n  ${VALUE}\nvalue: ${VALUE}\n";
+        let expanded = expand_with(text, &[("VALUE", value)]).unwrap();
+        let parsed: serde_yaml::Mapping = serde_yaml::from_str(&expanded).unwrap();
+        assert_eq!(parsed["list"][0], expected);
+        assert_eq!(parsed["value"], serde_yaml::Value::from(value));
+        // Lines indented no further than the key end the block scalar
+        let text = "steps:\n  - description: >\n      ${VALUE}\n    name: ${VALUE}\n";
+        let expanded = expand_with(text, &[("VALUE", "a: b")]).unwrap();
+        let parsed: serde_yaml::Mapping = serde_yaml::from_str(&expanded).unwrap();
+        assert_eq!(parsed["steps"][0]["description"], serde_yaml::Value::from("a: b\n"));
+        assert_eq!(parsed["steps"][0]["name"], serde_yaml::Value::from("a: b"));
+    }
+
+    #[test]
+    fn special_values_inside_plain_text_need_quotes() {
+        assert!(expand_with("value: Run ${VALUE}\n", &[("VALUE", "a: b")]).unwrap_err().contains("needs quotes"));
+        assert!(expand_with("value: '${VALUE}'\n", &[("VALUE", "a\nb")]).unwrap_err().contains("line break"));
+    }
+}
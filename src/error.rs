@@ -1,3 +1,5 @@
+//! Error types and the CLI's exit codes
+
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -22,6 +24,175 @@ pub enum FFBError {
     
     #[error("Invalid parameter: {0}")]
     InvalidParameter(String),
+
+    /// The device cannot play what the scenario needs (see `capabilities`)
+    #[error("Unsupported by the device: {0}")]
+    Unsupported(String),
+
+    /// A driver call before `initialize`
+    #[error("Device not initialized")]
+    NotInitialized,
+
+    /// Stopped through a `CancellationToken`
+    #[error("Cancelled")]
+    Cancelled,
+
+    /// USB capture died; the run cannot produce a meaningful capture
+    #[error("USB capture failed{}: {message}", capture_context(.step, .device))]
+    CaptureFailed {
+        message: String,
+        /// 1-based scenario step that was playing
+        step: Option<usize>,
+        /// VID:PID of the capture device
+        device: Option<String>,
+    },
+
+    /// The device failed a health check between steps
+    #[error("Device stopped responding{}: {message}", step_context(.step))]
+    NotResponding {
+        message: String,
+        /// 1-based scenario step after which the check failed
+        step: Option<usize>,
+    },
+
+    #[error(transparent)]
+    Scenario(#[from] ScenarioError),
+
+    /// The expected side of a comparison is missing or unusable
+    #[error("Comparison failed{}: {message}", comparison_context(.step, .packet))]
+    ComparisonFailed {
+        message: String,
+        /// 1-based step index from the capture file
+        step: Option<usize>,
+        /// 1-based packet position within the step
+        packet: Option<usize>,
+    },
+}
+
+/// " (step 3, device 16d0:0d5a)", with the parts that are known
+fn capture_context(step: &Option<usize>, device: &Option<String>) -> String {
+    context(&[step.map(|s| format!("step {}", s)), device.as_ref().map(|d| format!("device {}", d))])
+}
+
+/// " (after step 3)", if the step is known
+fn step_context(step: &Option<usize>) -> String {
+    context(&[step.map(|s| format!("after step {}", s))])
+}
+
+/// " (step 3, packet 2)", with the parts that are known
+fn comparison_context(step: &Option<usize>, packet: &Option<usize>) -> String {
+    context(&[step.map(|s| format!("step {}", s)), packet.map(|p| format!("packet {}", p))])
+}
+
+fn context(parts: &[Option<String>]) -> String {
+    let known: Vec<&str> = parts.iter().flatten().map(String::as_str).collect();
+    if known.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", known.join(", "))
+    }
+}
+
+impl FFBError {
+    /// Capture failure without context yet
+    pub fn capture(message: impl Into<String>) -> Self {
+        FFBError::CaptureFailed {
+            message: message.into(),
+            step: None,
+            device: None,
+        }
+    }
+
+    /// Comparison failure without context yet
+    pub fn comparison(message: impl Into<String>) -> Self {
+        FFBError::ComparisonFailed {
+            message: message.into(),
+            step: None,
+            packet: None,
+        }
+    }
+
+    /// Failed health check without context yet
+    pub fn not_responding(message: impl Into<String>) -> Self {
+        FFBError::NotResponding {
+            message: message.into(),
+            step: None,
+        }
+    }
+
+    /// Attach the step the error happened in, unless already known
+    pub fn at_step(mut self, index: usize) -> Self {
+        match &mut self {
+            FFBError::CaptureFailed { step, .. }
+            | FFBError::ComparisonFailed { step, .. }
+            | FFBError::NotResponding { step, .. } => {
+                step.get_or_insert(index);
+            }
+            _ => {}
+        }
+        self
+    }
+
+    /// Attach the capture device of a capture failure, unless already known
+    pub fn on_device(mut self, name: impl Into<String>) -> Self {
+        if let FFBError::CaptureFailed { device, .. } = &mut self {
+            device.get_or_insert_with(|| name.into());
+        }
+        self
+    }
 }
 
 pub type FFBResult<T> = Result<T, FFBError>;
+
+/// Errors caused by a scenario file rather than the device or environment
+#[derive(Error, Debug)]
+pub enum ScenarioError {
+    #[error("Scenario file not found: {0}")]
+    NotFound(String),
+
+    #[error("Failed to read scenario {path}: {source}")]
+    Read {
+        path: String,
+        source: std::io::Error,
+    },
+
+    #[error("Invalid scenario {path}: {message}")]
+    Invalid { path: String, message: String },
+}
+
+/// Process exit codes, used consistently across all subcommands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// Command succeeded, all compared steps match
+    Success = 0,
+    /// Driver output differs from the expected capture
+    Mismatch = 1,
+    /// Device, driver or environment setup failed
+    SetupError = 2,
+    /// Scenario file, or the capture it is compared with, is missing or invalid
+    BadScenario = 3,
+}
+
+impl ExitStatus {
+    /// Classify an error into the matching exit status
+    pub fn from_error(error: &anyhow::Error) -> Self {
+        let bad_input = error.chain().any(|cause| {
+            cause.downcast_ref::<ScenarioError>().is_some()
+                || matches!(
+                    cause.downcast_ref::<FFBError>(),
+                    Some(FFBError::Scenario(_) | FFBError::ComparisonFailed { .. })
+                )
+        });
+        if bad_input {
+            ExitStatus::BadScenario
+        } else {
+            ExitStatus::SetupError
+        }
+    }
+}
+
+impl From<ExitStatus> for std::process::ExitCode {
+    fn from(status: ExitStatus) -> Self {
+        std::process::ExitCode::from(status as u8)
+    }
+}
@@ -0,0 +1,132 @@
+//! Inline packet expectations for scenario steps
+//!
+//! Lets a step declare simple protocol invariants that are checked
+//! against its output without maintaining a separate capture file.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Rule matched against a single packet
+/// All given fields must match; a rule is satisfied if any packet in the step matches it
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PacketRule {
+    /// Exact hex string, e.g. "01 0A 01 01 01 00"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exact: Option<String>,
+    /// Hex prefix, e.g. "01 05"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    /// Regular expression over the uppercase, space-separated hex string
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+}
+
+/// Expectations for the output of one step
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StepExpectations {
+    /// Minimum number of packets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_packets: Option<usize>,
+    /// Maximum number of packets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_packets: Option<usize>,
+    /// Rules that must each be matched by at least one packet
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub packets: Vec<PacketRule>,
+}
+
+/// Normalize a hex string to uppercase bytes separated by single spaces
+pub fn normalize_hex(hex: &str) -> String {
+    hex.split_whitespace()
+        .map(|b| b.to_uppercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl PacketRule {
+    /// Check that the rule is well-formed
+    pub fn validate(&self) -> Result<(), String> {
+        if self.exact.is_none() && self.prefix.is_none() && self.regex.is_none() {
+            return Err("packet rule needs at least one of exact, prefix or regex".to_string());
+        }
+        if let Some(pattern) = &self.regex {
+            Regex::new(pattern).map_err(|e| format!("invalid regex '{}': {}", pattern, e))?;
+        }
+        Ok(())
+    }
+
+    pub fn matches(&self, packet: &str) -> bool {
+        let packet = normalize_hex(packet);
+
+        if let Some(exact) = &self.exact {
+            if packet != normalize_hex(exact) {
+                return false;
+            }
+        }
+        if let Some(prefix) = &self.prefix {
+            if !packet.starts_with(&normalize_hex(prefix)) {
+                return false;
+            }
+        }
+        if let Some(pattern) = &self.regex {
+            match Regex::new(pattern) {
+                Ok(re) if re.is_match(&packet) => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(exact) = &self.exact {
+            parts.push(format!("exact \"{}\"", exact));
+        }
+        if let Some(prefix) = &self.prefix {
+            parts.push(format!("prefix \"{}\"", prefix));
+        }
+        if let Some(pattern) = &self.regex {
+            parts.push(format!("regex /{}/", pattern));
+        }
+        parts.join(", ")
+    }
+}
+
+impl StepExpectations {
+    /// Check that all rules are well-formed
+    pub fn validate(&self) -> Result<(), String> {
+        if let (Some(min), Some(max)) = (self.min_packets, self.max_packets) {
+            if min > max {
+                return Err(format!("min_packets ({}) is greater than max_packets ({})", min, max));
+            }
+        }
+        for rule in &self.packets {
+            rule.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Check step output against the expectations
+    /// Returns a list of failure messages, empty if all expectations hold
+    pub fn check(&self, packets: &[String]) -> Vec<String> {
+        let mut failures = Vec::new();
+
+        if let Some(min) = self.min_packets {
+            if packets.len() < min {
+                failures.push(format!("expected at least {} packets, got {}", min, packets.len()));
+            }
+        }
+        if let Some(max) = self.max_packets {
+            if packets.len() > max {
+                failures.push(format!("expected at most {} packets, got {}", max, packets.len()));
+            }
+        }
+        for rule in &self.packets {
+            if !packets.iter().any(|p| rule.matches(p)) {
+                failures.push(format!("no packet matches {}", rule.describe()));
+            }
+        }
+
+        failures
+    }
+}
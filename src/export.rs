@@ -0,0 +1,538 @@
+//! Scenarios as standalone C programs
+//!
+//! The `export` command turns a scenario into one C source file that plays its
+//! steps through SDL3's haptic API (`c-sdl`) or DirectInput 8 (`c-dinput`), so a
+//! wheel vendor can be handed a minimal reproducer that needs nothing but the
+//! API's SDK. The SDL program sets up each effect exactly as the `sdl` driver
+//! does, down to the scaling of levels to SDL's -32767..32767. The DirectInput
+//! program uses DirectInput's own units, which match the scenario's (-10000 to
+//! 10000, durations in microseconds), as a Windows game would.
+//!
+//! Both play the steps one after the other for their start delay and duration and
+//! stop each effect before starting the next, repeating as the scenario says.
+
+use crate::effects::{ConditionType, Effect, Envelope, WaveType};
+use crate::{effect_label, Scenario, ScenarioStep};
+use std::fmt::Write as _;
+
+/// SDL uses range -32767..32767, scenarios -10000..10000
+const SDL_SCALE: f32 = 32767.0 / 10000.0;
+
+/// API of the generated program
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Language {
+    /// C program using SDL3's haptic API (Linux, Windows, macOS)
+    CSdl,
+    /// C program using DirectInput 8 (Windows)
+    CDinput,
+}
+
+impl Language {
+    /// Suffix of the default output file name, e.g. "sdl" for `<scenario>_sdl.c`
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Language::CSdl => "sdl",
+            Language::CDinput => "dinput",
+        }
+    }
+}
+
+/// C source of a program playing `scenario`, loaded from `source`
+pub fn c_source(scenario: &Scenario, source: &str, language: Language) -> String {
+    let mut c = String::new();
+    let _ = writeln!(c, "/*");
+    let _ = writeln!(c, " * {}", comment(&scenario.name));
+    for line in scenario.description.lines().filter(|line| !line.trim().is_empty()) {
+        let _ = writeln!(c, " * {}", comment(line.trim()));
+    }
+    let _ = writeln!(c, " *");
+    let _ = writeln!(c, " * Generated by ffb_replay from {}. Plays the scenario's", comment(source));
+    match language {
+        Language::CSdl => {
+            let _ = writeln!(c, " * force feedback through SDL3's haptic API on the first FFB device, with");
+            let _ = writeln!(c, " * the same effect parameters as `ffb_replay play --driver sdl`.");
+            let _ = writeln!(c, " *");
+            let _ = writeln!(c, " * Build: cc this_file.c $(pkg-config --cflags --libs sdl3)");
+        }
+        Language::CDinput => {
+            let _ = writeln!(c, " * force feedback through DirectInput 8 on the first FFB game controller.");
+            let _ = writeln!(c, " *");
+            let _ = writeln!(c, " * Build: cl this_file.c dinput8.lib dxguid.lib user32.lib");
+            let _ = writeln!(c, " *    or: x86_64-w64-mingw32-gcc this_file.c -ldinput8 -ldxguid");
+        }
+    }
+    if scenario.steps.iter().any(|step| !step.peripherals.is_empty() || !step.settings.is_empty()) {
+        let _ = writeln!(c, " *");
+        let _ = writeln!(c, " * The steps' device settings and peripheral commands are not exported.");
+    }
+    if scenario.steps.iter().any(ScenarioStep::holds) {
+        let _ = writeln!(c, " *");
+        let _ = writeln!(c, " * Held effects (hold, stop_at) are stopped with their step like the others.");
+    }
+    let _ = writeln!(c, " */");
+    let _ = writeln!(c);
+    match language {
+        Language::CSdl => write_sdl(&mut c, scenario),
+        Language::CDinput => write_dinput(&mut c, scenario),
+    }
+    c
+}
+
+/// Text safe inside a C block comment
+fn comment(text: &str) -> String {
+    text.replace("*/", "* /")
+}
+
+/// Times the steps are played, 0 for forever
+fn repeat_count(scenario: &Scenario) -> u32 {
+    if scenario.loop_forever {
+        0
+    } else {
+        scenario.repeat_count
+    }
+}
+
+fn sdl_level(value: i16) -> i16 {
+    ((value as f32) * SDL_SCALE).clamp(-32767.0, 32767.0) as i16
+}
+
+fn sdl_level_u16(value: u16) -> i16 {
+    ((value as f32) * SDL_SCALE).clamp(0.0, 32767.0) as i16
+}
+
+fn write_sdl(c: &mut String, scenario: &Scenario) {
+    let _ = writeln!(c, "#include <SDL3/SDL.h>");
+    let _ = writeln!(c, "#include <stdio.h>");
+    let _ = writeln!(c);
+    let _ = writeln!(c, "/* Times the steps are played, 0 for forever */");
+    let _ = writeln!(c, "#define REPEAT_COUNT {}", repeat_count(scenario));
+    let _ = writeln!(c);
+    let _ = writeln!(c, "typedef struct {{");
+    let _ = writeln!(c, "    const char *label;");
+    let _ = writeln!(c, "    /* How long the step plays before its effect is stopped */");
+    let _ = writeln!(c, "    Uint32 duration_ms;");
+    let _ = writeln!(c, "    SDL_HapticEffect effect;");
+    let _ = writeln!(c, "}} Step;");
+    let _ = writeln!(c);
+    let _ = writeln!(c, "static const Step steps[] = {{");
+    for (i, step) in scenario.steps.iter().enumerate() {
+        let effect = &step.effect;
+        let _ = writeln!(c, "    /* Step {} */", i + 1);
+        let _ = writeln!(c, "    {{");
+        let _ = writeln!(c, "        \"{}\", {},", effect_label(effect), effect.end_time());
+        let (member, kind, fields) = sdl_fields(effect);
+        let _ = writeln!(c, "        {{ .{} = {{", member);
+        let _ = writeln!(c, "            .type = {},", kind);
+        for (name, value) in fields {
+            let _ = writeln!(c, "            .{} = {},", name, value);
+        }
+        let _ = writeln!(c, "        }} }},");
+        let _ = writeln!(c, "    }},");
+    }
+    let _ = writeln!(c, "}};");
+    c.push_str(SDL_PROGRAM);
+}
+
+/// Union member, effect type and fields of the SDL_HapticEffect the `sdl` driver
+/// creates for `effect`
+fn sdl_fields(effect: &Effect) -> (&'static str, &'static str, Vec<(&'static str, String)>) {
+    let length = match effect.duration() {
+        0 => "SDL_HAPTIC_INFINITY".to_string(),
+        duration => duration.to_string(),
+    };
+    let direction = |degrees: u16| format!("{{ SDL_HAPTIC_CARTESIAN, {{ {}, 0, 0 }} }}", degrees as i32 * 100);
+    let envelope = |envelope: &Envelope| {
+        vec![
+            ("attack_length", (envelope.attack_time as u16).to_string()),
+            ("attack_level", (sdl_level_u16(envelope.attack_level) as u16).to_string()),
+            ("fade_length", (envelope.fade_time as u16).to_string()),
+            ("fade_level", (sdl_level_u16(envelope.fade_level) as u16).to_string()),
+        ]
+    };
+    let delay = (effect.start_delay() as u16).to_string();
+
+    match effect {
+        Effect::Constant { force, .. } => {
+            let mut fields = vec![
+                ("direction", direction(force.direction.0)),
+                ("length", length),
+                ("delay", delay),
+                ("level", sdl_level(force.magnitude).to_string()),
+            ];
+            fields.extend(envelope(&force.envelope));
+            ("constant", "SDL_HAPTIC_CONSTANT", fields)
+        }
+        Effect::Periodic { effect, .. } => {
+            let kind = match effect.wave_type {
+                WaveType::Sine => "SDL_HAPTIC_SINE",
+                WaveType::Square => "SDL_HAPTIC_SQUARE",
+                WaveType::Triangle => "SDL_HAPTIC_TRIANGLE",
+                WaveType::SawtoothUp => "SDL_HAPTIC_SAWTOOTHUP",
+                WaveType::SawtoothDown => "SDL_HAPTIC_SAWTOOTHDOWN",
+            };
+            let mut fields = vec![
+                ("direction", direction(effect.direction.0)),
+                ("length", length),
+                ("delay", delay),
+                ("period", (effect.period as u16).to_string()),
+                ("magnitude", sdl_level_u16(effect.magnitude).to_string()),
+                ("offset", sdl_level(effect.offset).to_string()),
+                ("phase", effect.phase.to_string()),
+            ];
+            fields.extend(envelope(&effect.envelope));
+            ("periodic", kind, fields)
+        }
+        Effect::Ramp { effect, .. } => {
+            let mut fields = vec![
+                ("direction", direction(effect.direction.0)),
+                ("length", length),
+                ("delay", delay),
+                ("start", sdl_level(effect.start_magnitude).to_string()),
+                ("end", sdl_level(effect.end_magnitude).to_string()),
+            ];
+            fields.extend(envelope(&effect.envelope));
+            ("ramp", "SDL_HAPTIC_RAMP", fields)
+        }
+        Effect::Condition { effect, .. } => {
+            let kind = match effect.condition_type {
+                ConditionType::Spring => "SDL_HAPTIC_SPRING",
+                ConditionType::Damper => "SDL_HAPTIC_DAMPER",
+                ConditionType::Friction => "SDL_HAPTIC_FRICTION",
+                ConditionType::Inertia => "SDL_HAPTIC_INERTIA",
+            };
+            let x = &effect.x_axis;
+            let axis = |value: String| format!("{{ {}, 0, 0 }}", value);
+            let fields = vec![
+                ("direction", direction(0)),
+                ("length", length),
+                ("delay", delay),
+                ("right_sat", axis((sdl_level_u16(x.positive_saturation) as u16).to_string())),
+                ("left_sat", axis((sdl_level_u16(x.negative_saturation) as u16).to_string())),
+                ("right_coeff", axis(sdl_level(x.positive_coefficient).to_string())),
+                ("left_coeff", axis(sdl_level(x.negative_coefficient).to_string())),
+                ("deadband", axis(x.dead_band.to_string())),
+                ("center", axis(x.offset.to_string())),
+            ];
+            ("condition", kind, fields)
+        }
+    }
+}
+
+/// Opening the device and playing the `steps` table through SDL
+const SDL_PROGRAM: &str = r#"
+#define STEP_COUNT ((int)(sizeof(steps) / sizeof(steps[0])))
+
+/* The first joystick with force feedback, else the first haptic device */
+static SDL_Haptic *open_haptic(void)
+{
+    SDL_Haptic *haptic = NULL;
+    int count = 0;
+    SDL_JoystickID *joysticks = SDL_GetJoysticks(&count);
+    for (int i = 0; joysticks && i < count && !haptic; i++) {
+        SDL_Joystick *joystick = SDL_OpenJoystick(joysticks[i]);
+        if (!joystick) {
+            continue;
+        }
+        if (SDL_IsJoystickHaptic(joystick)) {
+            printf("Found FFB joystick: %s\n", SDL_GetJoystickName(joystick));
+            haptic = SDL_OpenHapticFromJoystick(joystick);
+        } else {
+            SDL_CloseJoystick(joystick);
+        }
+    }
+    SDL_free(joysticks);
+
+    if (!haptic) {
+        SDL_HapticID *haptics = SDL_GetHaptics(&count);
+        if (haptics && count > 0) {
+            haptic = SDL_OpenHaptic(haptics[0]);
+        }
+        SDL_free(haptics);
+    }
+    return haptic;
+}
+
+int main(int argc, char *argv[])
+{
+    (void)argc;
+    (void)argv;
+    if (!SDL_Init(SDL_INIT_JOYSTICK | SDL_INIT_HAPTIC)) {
+        fprintf(stderr, "SDL_Init failed: %s\n", SDL_GetError());
+        return 1;
+    }
+    SDL_Haptic *haptic = open_haptic();
+    if (!haptic) {
+        fprintf(stderr, "No force feedback device found: %s\n", SDL_GetError());
+        SDL_Quit();
+        return 1;
+    }
+
+    for (int iteration = 0; REPEAT_COUNT == 0 || iteration < REPEAT_COUNT; iteration++) {
+        for (int i = 0; i < STEP_COUNT; i++) {
+            const Step *step = &steps[i];
+            SDL_HapticEffect effect = step->effect;
+            printf("Step %d/%d: %s (%u ms)\n", i + 1, STEP_COUNT, step->label, (unsigned)step->duration_ms);
+            SDL_HapticEffectID id = SDL_CreateHapticEffect(haptic, &effect);
+            if (id < 0) {
+                fprintf(stderr, "  Creating the effect failed: %s\n", SDL_GetError());
+                continue;
+            }
+            if (!SDL_RunHapticEffect(haptic, id, 1)) {
+                fprintf(stderr, "  Running the effect failed: %s\n", SDL_GetError());
+            }
+            SDL_Delay(step->duration_ms);
+            SDL_StopHapticEffects(haptic);
+            SDL_DestroyHapticEffect(haptic, id);
+        }
+    }
+
+    SDL_CloseHaptic(haptic);
+    SDL_Quit();
+    return 0;
+}
+"#;
+
+fn write_dinput(c: &mut String, scenario: &Scenario) {
+    let _ = writeln!(c, "#define DIRECTINPUT_VERSION 0x0800");
+    let _ = writeln!(c, "#include <windows.h>");
+    let _ = writeln!(c, "#include <dinput.h>");
+    let _ = writeln!(c, "#include <stdio.h>");
+    let _ = writeln!(c);
+    let _ = writeln!(c, "/* Times the steps are played, 0 for forever */");
+    let _ = writeln!(c, "#define REPEAT_COUNT {}", repeat_count(scenario));
+    let _ = writeln!(c);
+
+    // Type-specific parameters and envelopes go first, the step table points at them
+    for (i, step) in scenario.steps.iter().enumerate() {
+        let number = i + 1;
+        let (kind, value) = match &step.effect {
+            Effect::Constant { force, .. } => ("DICONSTANTFORCE", format!("{{ {} }}", force.magnitude)),
+            Effect::Periodic { effect, .. } => (
+                "DIPERIODIC",
+                format!(
+                    "{{ {}, {}, {}, {} }}",
+                    effect.magnitude.min(10000),
+                    effect.offset,
+                    effect.phase % 36000,
+                    effect.period as u64 * 1000
+                ),
+            ),
+            Effect::Ramp { effect, .. } => (
+                "DIRAMPFORCE",
+                format!("{{ {}, {} }}", effect.start_magnitude, effect.end_magnitude),
+            ),
+            Effect::Condition { effect, .. } => {
+                let x = &effect.x_axis;
+                (
+                    "DICONDITION",
+                    format!(
+                        "{{ {}, {}, {}, {}, {}, {} }}",
+                        x.offset,
+                        x.positive_coefficient,
+                        x.negative_coefficient,
+                        x.positive_saturation,
+                        x.negative_saturation,
+                        x.dead_band
+                    ),
+                )
+            }
+        };
+        let _ = writeln!(c, "static {} step{}_params = {};", kind, number, value);
+        if let Some(envelope) = dinput_envelope(&step.effect) {
+            let _ = writeln!(
+                c,
+                "static DIENVELOPE step{}_envelope = {{ sizeof(DIENVELOPE), {}, {}, {}, {} }};",
+                number,
+                envelope.attack_level,
+                envelope.attack_time as u64 * 1000,
+                envelope.fade_level,
+                envelope.fade_time as u64 * 1000
+            );
+        }
+    }
+    let _ = writeln!(c);
+
+    let _ = writeln!(c, "typedef struct {{");
+    let _ = writeln!(c, "    const char *label;");
+    let _ = writeln!(c, "    /* How long the step plays before its effect is stopped */");
+    let _ = writeln!(c, "    DWORD duration_ms;");
+    let _ = writeln!(c, "    const GUID *type;");
+    let _ = writeln!(c, "    /* DIEFFECT's dwDuration (INFINITE or microseconds), dwStartDelay and dwGain */");
+    let _ = writeln!(c, "    DWORD duration;");
+    let _ = writeln!(c, "    DWORD start_delay;");
+    let _ = writeln!(c, "    DWORD gain;");
+    let _ = writeln!(c, "    DIENVELOPE *envelope;");
+    let _ = writeln!(c, "    void *params;");
+    let _ = writeln!(c, "    DWORD params_size;");
+    let _ = writeln!(c, "}} Step;");
+    let _ = writeln!(c);
+    let _ = writeln!(c, "static const Step steps[] = {{");
+    for (i, step) in scenario.steps.iter().enumerate() {
+        let number = i + 1;
+        let effect = &step.effect;
+        let (guid, kind, gain) = match effect {
+            Effect::Constant { params, .. } => ("GUID_ConstantForce", "DICONSTANTFORCE", params.gain),
+            Effect::Periodic { params, effect } => (
+                match effect.wave_type {
+                    WaveType::Sine => "GUID_Sine",
+                    WaveType::Square => "GUID_Square",
+                    WaveType::Triangle => "GUID_Triangle",
+                    WaveType::SawtoothUp => "GUID_SawtoothUp",
+                    WaveType::SawtoothDown => "GUID_SawtoothDown",
+                },
+                "DIPERIODIC",
+                params.gain,
+            ),
+            Effect::Ramp { params, .. } => ("GUID_RampForce", "DIRAMPFORCE", params.gain),
+            Effect::Condition { params, effect } => (
+                match effect.condition_type {
+                    ConditionType::Spring => "GUID_Spring",
+                    ConditionType::Damper => "GUID_Damper",
+                    ConditionType::Friction => "GUID_Friction",
+                    ConditionType::Inertia => "GUID_Inertia",
+                },
+                "DICONDITION",
+                params.gain,
+            ),
+        };
+        let duration = match effect.duration() {
+            0 => "INFINITE".to_string(),
+            duration => (duration as u64 * 1000).to_string(),
+        };
+        let envelope = match dinput_envelope(effect) {
+            Some(_) => format!("&step{}_envelope", number),
+            None => "NULL".to_string(),
+        };
+        let _ = writeln!(c, "    /* Step {} */", number);
+        let _ = writeln!(
+            c,
+            "    {{ \"{}\", {}, &{}, {}, {}, {}, {}, &step{}_params, sizeof({}) }},",
+            effect_label(effect),
+            effect.end_time(),
+            guid,
+            duration,
+            effect.start_delay() as u64 * 1000,
+            gain.min(10000),
+            envelope,
+            number,
+            kind
+        );
+    }
+    let _ = writeln!(c, "}};");
+    c.push_str(DINPUT_PROGRAM);
+}
+
+/// The envelope of an effect that has one; condition effects take none
+fn dinput_envelope(effect: &Effect) -> Option<&Envelope> {
+    let envelope = match effect {
+        Effect::Constant { force, .. } => &force.envelope,
+        Effect::Periodic { effect, .. } => &effect.envelope,
+        Effect::Ramp { effect, .. } => &effect.envelope,
+        Effect::Condition { .. } => return None,
+    };
+    let empty = envelope.attack_time == 0 && envelope.fade_time == 0;
+    (!empty).then_some(envelope)
+}
+
+/// Opening the device and playing the `steps` table through DirectInput
+const DINPUT_PROGRAM: &str = r#"
+#define STEP_COUNT ((int)(sizeof(steps) / sizeof(steps[0])))
+
+static void fail(const char *call, HRESULT result)
+{
+    fprintf(stderr, "%s failed: 0x%08lX\n", call, (unsigned long)result);
+}
+
+static BOOL found_device = FALSE;
+
+/* Keeps the first force feedback game controller */
+static BOOL CALLBACK on_device(LPCDIDEVICEINSTANCEA instance, LPVOID context)
+{
+    printf("Found FFB device: %s\n", instance->tszProductName);
+    *(GUID *)context = instance->guidInstance;
+    found_device = TRUE;
+    return DIENUM_STOP;
+}
+
+int main(void)
+{
+    IDirectInput8A *input = NULL;
+    IDirectInputDevice8A *device = NULL;
+    GUID instance;
+    HRESULT result;
+
+    result = DirectInput8Create(GetModuleHandle(NULL), DIRECTINPUT_VERSION, &IID_IDirectInput8A, (void **)&input, NULL);
+    if (FAILED(result)) {
+        fail("DirectInput8Create", result);
+        return 1;
+    }
+    IDirectInput8_EnumDevices(input, DI8DEVCLASS_GAMECTRL, on_device, &instance,
+                              DIEDFL_ATTACHEDONLY | DIEDFL_FORCEFEEDBACK);
+    if (!found_device) {
+        fprintf(stderr, "No force feedback game controller found\n");
+        IDirectInput8_Release(input);
+        return 1;
+    }
+    result = IDirectInput8_CreateDevice(input, &instance, &device, NULL);
+    if (FAILED(result)) {
+        fail("CreateDevice", result);
+        IDirectInput8_Release(input);
+        return 1;
+    }
+
+    /* Force feedback needs exclusive access; the console window stands in for a game window */
+    IDirectInputDevice8_SetDataFormat(device, &c_dfDIJoystick2);
+    result = IDirectInputDevice8_SetCooperativeLevel(device, GetConsoleWindow(), DISCL_EXCLUSIVE | DISCL_BACKGROUND);
+    if (FAILED(result)) {
+        fail("SetCooperativeLevel", result);
+    }
+    DIPROPDWORD autocenter = { { sizeof(DIPROPDWORD), sizeof(DIPROPHEADER), 0, DIPH_DEVICE }, DIPROPAUTOCENTER_OFF };
+    IDirectInputDevice8_SetProperty(device, DIPROP_AUTOCENTER, &autocenter.diph);
+    result = IDirectInputDevice8_Acquire(device);
+    if (FAILED(result)) {
+        fail("Acquire", result);
+    }
+
+    for (int iteration = 0; REPEAT_COUNT == 0 || iteration < REPEAT_COUNT; iteration++) {
+        for (int i = 0; i < STEP_COUNT; i++) {
+            const Step *step = &steps[i];
+            /* A wheel has one axis: the sign of the force gives its direction */
+            DWORD axes[1] = { DIJOFS_X };
+            LONG direction[1] = { 0 };
+            DIEFFECT effect = { sizeof(DIEFFECT) };
+            LPDIRECTINPUTEFFECT handle = NULL;
+
+            effect.dwFlags = DIEFF_CARTESIAN | DIEFF_OBJECTOFFSETS;
+            effect.dwDuration = step->duration;
+            effect.dwGain = step->gain;
+            effect.dwTriggerButton = DIEB_NOTRIGGER;
+            effect.cAxes = 1;
+            effect.rgdwAxes = axes;
+            effect.rglDirection = direction;
+            effect.lpEnvelope = step->envelope;
+            effect.cbTypeSpecificParams = step->params_size;
+            effect.lpvTypeSpecificParams = step->params;
+            effect.dwStartDelay = step->start_delay;
+
+            printf("Step %d/%d: %s (%lu ms)\n", i + 1, STEP_COUNT, step->label, (unsigned long)step->duration_ms);
+            result = IDirectInputDevice8_CreateEffect(device, step->type, &effect, &handle, NULL);
+            if (FAILED(result)) {
+                fail("  CreateEffect", result);
+                continue;
+            }
+            result = IDirectInputEffect_Start(handle, 1, 0);
+            if (FAILED(result)) {
+                fail("  Start", result);
+            }
+            Sleep(step->duration_ms);
+            IDirectInputEffect_Stop(handle);
+            IDirectInputEffect_Release(handle);
+        }
+    }
+
+    IDirectInputDevice8_Unacquire(device);
+    IDirectInputDevice8_Release(device);
+    IDirectInput8_Release(input);
+    return 0;
+}
+"#;
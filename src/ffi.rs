@@ -0,0 +1,388 @@
+//! C ABI for embedding the engine (`ffi` feature)
+//!
+//! Handles are opaque pointers created and freed by the library. Functions return an
+//! `FfbStatus`, the CLI's exit codes plus `FFB_STATUS_INVALID_ARGUMENT`; on failure
+//! `ffb_last_error` describes the error of the last call on the calling thread.
+//! Strings returned by the library stay valid until the handle they come from is
+//! freed. Building with the feature generates `include/ffb_replay.h`.
+//!
+//! ```c
+//! FfbDriverHandle *driver;
+//! FfbScenario *scenario;
+//! FfbRun *actual, *expected;
+//! size_t mismatched;
+//!
+//! if (ffb_driver_create("sdl", true, &driver) != FFB_STATUS_OK) {
+//!     fprintf(stderr, "%s\n", ffb_last_error());
+//!     return 1;
+//! }
+//! ffb_scenario_load("scenarios/simple_test.yaml", &scenario);
+//! ffb_play(driver, scenario, &actual);
+//! ffb_run_load("runs/simple_test.txt", &expected);
+//! FfbStatus status = ffb_compare(expected, actual, NULL, &mismatched);
+//! ```
+
+use crate::compare::{self, CompareMode, CompareOptions};
+use crate::driver::FfbDriver;
+use crate::error::ExitStatus;
+use crate::observer::PlaybackObserver;
+use crate::usb_monitor::CaptureConfig;
+use crate::{create_driver, parse_capture_file, write_capture_file, Scenario, StepOutput};
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::ptr;
+
+/// Result of an FFI call
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FfbStatus {
+    Ok = 0,
+    /// Compared runs differ
+    Mismatch = 1,
+    /// Device, driver or environment setup failed
+    SetupError = 2,
+    /// Scenario file is missing or invalid
+    BadScenario = 3,
+    /// A null pointer, bad index or non-UTF-8 string was passed
+    InvalidArgument = 4,
+}
+
+impl From<ExitStatus> for FfbStatus {
+    fn from(status: ExitStatus) -> Self {
+        match status {
+            ExitStatus::Success => FfbStatus::Ok,
+            ExitStatus::Mismatch => FfbStatus::Mismatch,
+            ExitStatus::SetupError => FfbStatus::SetupError,
+            ExitStatus::BadScenario => FfbStatus::BadScenario,
+        }
+    }
+}
+
+/// An initialized driver
+pub struct FfbDriverHandle {
+    driver: Box<dyn FfbDriver>,
+    name: CString,
+}
+
+/// A loaded scenario
+pub struct FfbScenario(Scenario);
+
+/// Step outputs of a played run or a capture file
+pub struct FfbRun {
+    steps: Vec<StepOutput>,
+    /// C copies of the step names and packets, handed out by the accessors
+    names: Vec<CString>,
+    packets: Vec<Vec<CString>>,
+}
+
+/// How `ffb_compare` compares runs; NULL means exact packet comparison
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FfbCompareOptions {
+    /// Decode both sides to canonical commands first
+    pub normalize: bool,
+    /// Compare only the number of packets per step
+    pub counts_only: bool,
+    /// Allowed packet count difference per step with `counts_only`
+    pub tolerance: usize,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid argument: {0}")]
+struct InvalidArgument(String);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Run an FFI call body, turning errors and panics into a status
+fn guard(call: impl FnOnce() -> anyhow::Result<FfbStatus>) -> FfbStatus {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+    match panic::catch_unwind(AssertUnwindSafe(call)) {
+        Ok(Ok(status)) => status,
+        Ok(Err(e)) => {
+            set_last_error(format!("{:#}", e));
+            if e.downcast_ref::<InvalidArgument>().is_some() {
+                FfbStatus::InvalidArgument
+            } else {
+                ExitStatus::from_error(&e).into()
+            }
+        }
+        Err(_) => {
+            set_last_error("internal error (panic)".to_string());
+            FfbStatus::SetupError
+        }
+    }
+}
+
+unsafe fn arg_str<'a>(value: *const c_char, name: &str) -> anyhow::Result<&'a str> {
+    if value.is_null() {
+        return Err(InvalidArgument(format!("{} is NULL", name)).into());
+    }
+    CStr::from_ptr(value)
+        .to_str()
+        .map_err(|_| InvalidArgument(format!("{} is not UTF-8", name)).into())
+}
+
+unsafe fn arg_ref<'a, T>(value: *const T, name: &str) -> anyhow::Result<&'a T> {
+    value
+        .as_ref()
+        .ok_or_else(|| InvalidArgument(format!("{} is NULL", name)).into())
+}
+
+unsafe fn arg_out<'a, T>(value: *mut T, name: &str) -> anyhow::Result<&'a mut T> {
+    value
+        .as_mut()
+        .ok_or_else(|| InvalidArgument(format!("{} is NULL", name)).into())
+}
+
+fn c_string(value: &str) -> CString {
+    CString::new(value.replace('\0', " ")).unwrap_or_default()
+}
+
+impl FfbRun {
+    fn new(steps: Vec<StepOutput>) -> Self {
+        let names = steps.iter().map(|s| c_string(&s.step_name)).collect();
+        let packets = steps
+            .iter()
+            .map(|s| s.packets.iter().map(|p| c_string(p)).collect())
+            .collect();
+        Self { steps, names, packets }
+    }
+}
+
+/// Playback without console output
+struct Silent;
+
+impl PlaybackObserver for Silent {}
+
+/// Message of the last failed call on this thread, NULL if it succeeded
+/// Valid until the next call on this thread.
+#[no_mangle]
+pub extern "C" fn ffb_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// Create and initialize a driver by name ("sdl", "simagic")
+/// With `capture`, the device's USB traffic is captured with default capture settings.
+///
+/// # Safety
+/// `name` must be a NUL-terminated string and `out` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn ffb_driver_create(
+    name: *const c_char,
+    capture: bool,
+    out: *mut *mut FfbDriverHandle,
+) -> FfbStatus {
+    guard(|| {
+        let name = arg_str(name, "name")?;
+        let out = arg_out(out, "out")?;
+        let capture = capture.then(CaptureConfig::default);
+        let mut driver = create_driver(name, capture.as_ref())?;
+        driver.initialize()?;
+        let name = c_string(driver.name());
+        *out = Box::into_raw(Box::new(FfbDriverHandle { driver, name }));
+        Ok(FfbStatus::Ok)
+    })
+}
+
+/// Name of the driver
+///
+/// # Safety
+/// `driver` must come from `ffb_driver_create` and not be freed.
+#[no_mangle]
+pub unsafe extern "C" fn ffb_driver_name(driver: *const FfbDriverHandle) -> *const c_char {
+    driver.as_ref().map_or(ptr::null(), |d| d.name.as_ptr())
+}
+
+/// Shut the driver down and free it; NULL is ignored
+///
+/// # Safety
+/// `driver` must come from `ffb_driver_create` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn ffb_driver_free(driver: *mut FfbDriverHandle) -> FfbStatus {
+    guard(|| {
+        if !driver.is_null() {
+            let mut driver = Box::from_raw(driver);
+            driver.driver.shutdown()?;
+        }
+        Ok(FfbStatus::Ok)
+    })
+}
+
+/// Load a scenario from a YAML file
+///
+/// # Safety
+/// `path` must be a NUL-terminated string and `out` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn ffb_scenario_load(path: *const c_char, out: *mut *mut FfbScenario) -> FfbStatus {
+    guard(|| {
+        let path = PathBuf::from(arg_str(path, "path")?);
+        let out = arg_out(out, "out")?;
+        let scenario = Scenario::load_from_file(&path)?;
+        *out = Box::into_raw(Box::new(FfbScenario(scenario)));
+        Ok(FfbStatus::Ok)
+    })
+}
+
+/// Free a scenario; NULL is ignored
+///
+/// # Safety
+/// `scenario` must come from `ffb_scenario_load` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn ffb_scenario_free(scenario: *mut FfbScenario) {
+    if !scenario.is_null() {
+        drop(Box::from_raw(scenario));
+    }
+}
+
+/// Play `scenario` on `driver`, blocking until it finishes
+/// Steps whose effect fails get no packets, as in the CLI.
+///
+/// # Safety
+/// `driver` and `scenario` must be live handles and `out` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn ffb_play(
+    driver: *mut FfbDriverHandle,
+    scenario: *const FfbScenario,
+    out: *mut *mut FfbRun,
+) -> FfbStatus {
+    guard(|| {
+        let driver = arg_out(driver, "driver")?;
+        let scenario = arg_ref(scenario, "scenario")?;
+        let out = arg_out(out, "out")?;
+        let steps = scenario.0.play_observed(driver.driver.as_mut(), &mut Silent)?;
+        *out = Box::into_raw(Box::new(FfbRun::new(steps)));
+        Ok(FfbStatus::Ok)
+    })
+}
+
+/// Load a capture file written by `ffb_run_save` or the CLI's `record`
+///
+/// # Safety
+/// `path` must be a NUL-terminated string and `out` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn ffb_run_load(path: *const c_char, out: *mut *mut FfbRun) -> FfbStatus {
+    guard(|| {
+        let path = PathBuf::from(arg_str(path, "path")?);
+        let out = arg_out(out, "out")?;
+        let steps = parse_capture_file(&path)?;
+        *out = Box::into_raw(Box::new(FfbRun::new(steps)));
+        Ok(FfbStatus::Ok)
+    })
+}
+
+/// Save a run as a capture file
+///
+/// # Safety
+/// `run` must be a live handle and `path` a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ffb_run_save(run: *const FfbRun, path: *const c_char) -> FfbStatus {
+    guard(|| {
+        let run = arg_ref(run, "run")?;
+        let path = PathBuf::from(arg_str(path, "path")?);
+        write_capture_file(&path, &run.steps)?;
+        Ok(FfbStatus::Ok)
+    })
+}
+
+/// Number of steps in the run, 0 for NULL
+///
+/// # Safety
+/// `run` must be a live handle or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn ffb_run_step_count(run: *const FfbRun) -> usize {
+    run.as_ref().map_or(0, |r| r.steps.len())
+}
+
+/// Effect name of a step, NULL if `step` is out of range
+///
+/// # Safety
+/// `run` must be a live handle or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn ffb_run_step_name(run: *const FfbRun, step: usize) -> *const c_char {
+    run.as_ref()
+        .and_then(|r| r.names.get(step))
+        .map_or(ptr::null(), |name| name.as_ptr())
+}
+
+/// Number of packets captured in a step, 0 if `step` is out of range
+///
+/// # Safety
+/// `run` must be a live handle or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn ffb_run_packet_count(run: *const FfbRun, step: usize) -> usize {
+    run.as_ref().and_then(|r| r.packets.get(step)).map_or(0, Vec::len)
+}
+
+/// A packet of a step as hex bytes ("01 0a ff"), NULL if out of range
+///
+/// # Safety
+/// `run` must be a live handle or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn ffb_run_packet(run: *const FfbRun, step: usize, packet: usize) -> *const c_char {
+    run.as_ref()
+        .and_then(|r| r.packets.get(step))
+        .and_then(|packets| packets.get(packet))
+        .map_or(ptr::null(), |packet| packet.as_ptr())
+}
+
+/// Free a run; NULL is ignored
+///
+/// # Safety
+/// `run` must come from `ffb_play` or `ffb_run_load` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn ffb_run_free(run: *mut FfbRun) {
+    if !run.is_null() {
+        drop(Box::from_raw(run));
+    }
+}
+
+/// Compare `actual` with `expected` step by step
+/// Returns `FFB_STATUS_OK` if all steps match, `FFB_STATUS_MISMATCH` otherwise, with the number of
+/// differing steps in `mismatched` unless it is NULL.
+///
+/// # Safety
+/// `expected` and `actual` must be live handles, `options` and `mismatched` valid or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn ffb_compare(
+    expected: *const FfbRun,
+    actual: *const FfbRun,
+    options: *const FfbCompareOptions,
+    mismatched: *mut usize,
+) -> FfbStatus {
+    guard(|| {
+        let expected = arg_ref(expected, "expected")?;
+        let actual = arg_ref(actual, "actual")?;
+        let options = match options.as_ref() {
+            Some(options) => CompareOptions {
+                normalize: options.normalize,
+                mode: if options.counts_only {
+                    CompareMode::Counts
+                } else {
+                    CompareMode::Packets
+                },
+                tolerance: options.tolerance,
+                ..CompareOptions::default()
+            },
+            None => CompareOptions::default(),
+        };
+        let results = compare::compare_runs(&expected.steps, &actual.steps, &options);
+        let count = results.iter().filter(|r| !r.is_match()).count();
+        if let Some(mismatched) = mismatched.as_mut() {
+            *mismatched = count;
+        }
+        Ok(if count == 0 {
+            FfbStatus::Ok
+        } else {
+            FfbStatus::Mismatch
+        })
+    })
+}
@@ -0,0 +1,122 @@
+//! Force curves: the force a device was commanded over the course of a step
+//!
+//! The magnitude fields of a step's captured packets (decoder fields with
+//! `source: magnitude`) are read in capture order and placed on the step's time
+//! line: a packet goes out its capture timestamp after the step's first packet,
+//! which itself follows the effect submission by the wire latency. Each sample
+//! is paired with the force the scenario effect asks for at that time
+//! ([`Effect::force_at`](crate::effects::Effect::force_at)), so the exported CSV
+//! plots the commanded profile against the intended one. Decoded forces are in
+//! the decoder's units, which match the scenario's only where the protocol does.
+
+use crate::compare::parse_hex;
+use crate::normalize::decode_command;
+use crate::{Scenario, ScenarioStep, StepOutput};
+use serde::Serialize;
+use std::fmt::Write as _;
+
+/// Scenario effect field holding the force
+const MAGNITUDE_SOURCE: &str = "magnitude";
+
+/// A commanded force and the intended one at the same time
+#[derive(Debug, Clone, Serialize)]
+pub struct ForceSample {
+    /// 1-based position of the packet in the step
+    pub packet: usize,
+    /// Time since the effect was submitted (ms), None for captures without timestamps
+    pub time_ms: Option<f64>,
+    /// Decoded command carrying the force
+    pub command: String,
+    /// Decoded magnitude field
+    pub force: f64,
+    /// Force of the scenario effect at `time_ms`, None without a time, a scenario
+    /// or for condition effects
+    pub intended: Option<f64>,
+}
+
+/// The commanded forces of one step
+#[derive(Debug, Clone, Serialize)]
+pub struct ForceCurve {
+    pub step_index: usize,
+    pub step_name: String,
+    pub samples: Vec<ForceSample>,
+}
+
+/// Force curve of the packets of `output`, played from `step` (None if unknown,
+/// e.g. a capture without its scenario)
+pub fn reconstruct(step: Option<&ScenarioStep>, output: &StepOutput) -> ForceCurve {
+    let first_packet_us = output.wire_latency_us.unwrap_or(0);
+    let samples = output
+        .packets
+        .iter()
+        .enumerate()
+        .filter_map(|(i, packet)| {
+            let command = parse_hex(packet).as_deref().and_then(decode_command)?;
+            let force = command
+                .fields
+                .iter()
+                .find(|field| {
+                    field
+                        .source
+                        .as_deref()
+                        .is_some_and(|source| source.split('|').any(|path| path.trim() == MAGNITUDE_SOURCE))
+                })?
+                .number?;
+            let time_ms = output
+                .timestamps_us
+                .get(i)
+                .map(|&t| (first_packet_us + t) as f64 / 1000.0);
+            Some(ForceSample {
+                packet: i + 1,
+                time_ms,
+                command: command.name,
+                force,
+                intended: time_ms.zip(step).and_then(|(t, step)| step.effect.force_at(t)),
+            })
+        })
+        .collect();
+    ForceCurve {
+        step_index: output.step_index,
+        step_name: output.step_name.clone(),
+        samples,
+    }
+}
+
+/// Force curve of every step output, against the scenario step it was played from
+pub fn reconstruct_run(scenario: Option<&Scenario>, outputs: &[StepOutput]) -> Vec<ForceCurve> {
+    outputs
+        .iter()
+        .map(|output| {
+            let step = scenario.and_then(|scenario| scenario.steps.get(output.step_index.saturating_sub(1)));
+            reconstruct(step, output)
+        })
+        .collect()
+}
+
+impl ForceCurve {
+    /// CSV with a header and one row per sample; unknown values are left empty
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("time_ms,packet,command,force,intended\n");
+        for sample in &self.samples {
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{}",
+                sample.time_ms.map(|t| format!("{:.3}", t)).unwrap_or_default(),
+                sample.packet,
+                sample.command,
+                sample.force,
+                sample.intended.map(|f| format!("{:.1}", f)).unwrap_or_default()
+            );
+        }
+        csv
+    }
+
+    /// Largest difference between a commanded and the intended force, None if no
+    /// sample has both
+    pub fn max_deviation(&self) -> Option<f64> {
+        self.samples
+            .iter()
+            .filter_map(|sample| sample.intended.map(|intended| (sample.force - intended).abs()))
+            .reduce(f64::max)
+    }
+}
@@ -0,0 +1,359 @@
+//! Protocol fuzzing with randomized effect parameters
+//!
+//! The `fuzz` command plays random effects generated from a seed, so any run can be
+//! repeated exactly. Every effect's packets go to a capture file; effects that fail
+//! or produce no packets are anomalies, written out as a scenario that replays the
+//! run up to that effect.
+
+use crate::cancel::CancellationToken;
+use crate::driver::FfbDriver;
+use crate::effects::*;
+use crate::error::FFBError;
+use crate::{effect_label, write_capture_file, Scenario, ScenarioStep, StepOutput};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Directory for fuzz packet logs and reproducer scenarios
+const FUZZ_DIR: &str = "runs/fuzz";
+
+/// Settings of a fuzz run
+#[derive(Debug, Clone)]
+pub struct FuzzOptions {
+    pub seed: u64,
+    pub iterations: usize,
+    /// Also generate values outside the documented parameter ranges
+    pub invalid: bool,
+    /// Largest force magnitude of valid values (0-10000)
+    pub max_magnitude: i16,
+    pub stop_on_anomaly: bool,
+    /// Shell command run on each anomaly; a non-zero exit stops the run
+    pub on_anomaly: Option<String>,
+}
+
+/// Something unexpected while playing a fuzzed effect
+#[derive(Debug, Clone)]
+pub enum Anomaly {
+    /// The driver rejected or failed to play the effect
+    EffectFailed(String),
+    /// The effect produced no packets
+    NoPackets,
+}
+
+impl std::fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Anomaly::EffectFailed(message) => write!(f, "effect failed: {}", message),
+            Anomaly::NoPackets => write!(f, "no packets captured"),
+        }
+    }
+}
+
+/// SplitMix64: small, seedable and the same on every platform
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform value in `low..=high`
+    fn range(&mut self, low: i64, high: i64) -> i64 {
+        low + (self.next_u64() % (high - low + 1) as u64) as i64
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        self.next_u64() % 100 < percent
+    }
+}
+
+/// Random effect generator
+struct Generator {
+    rng: Rng,
+    invalid: bool,
+    max_magnitude: i64,
+}
+
+impl Generator {
+    /// Value in the valid range, or with `--invalid` sometimes anywhere in `invalid`
+    fn value(&mut self, valid: (i64, i64), invalid: (i64, i64)) -> i64 {
+        if self.invalid && self.rng.chance(20) {
+            self.rng.range(invalid.0, invalid.1)
+        } else {
+            self.rng.range(valid.0, valid.1)
+        }
+    }
+
+    fn magnitude(&mut self) -> i16 {
+        let max = self.max_magnitude;
+        self.value((-max, max), (i16::MIN as i64, i16::MAX as i64)) as i16
+    }
+
+    fn level(&mut self) -> u16 {
+        self.value((0, self.max_magnitude), (0, u16::MAX as i64)) as u16
+    }
+
+    fn coefficient(&mut self) -> i16 {
+        self.value((-10000, 10000), (i16::MIN as i64, i16::MAX as i64)) as i16
+    }
+
+    fn saturation(&mut self) -> u16 {
+        self.value((0, 10000), (0, u16::MAX as i64)) as u16
+    }
+
+    fn direction(&mut self) -> Direction {
+        Direction(self.value((0, 359), (0, u16::MAX as i64)) as u16)
+    }
+
+    fn envelope(&mut self) -> Envelope {
+        if !self.rng.chance(30) {
+            return Envelope::default();
+        }
+        Envelope {
+            attack_time: self.value((0, 200), (0, 100_000)) as u32,
+            attack_level: self.level(),
+            fade_time: self.value((0, 200), (0, 100_000)) as u32,
+            fade_level: self.level(),
+        }
+    }
+
+    fn params(&mut self) -> EffectParams {
+        EffectParams {
+            // Invalid durations include 0 (infinite); the effect is stopped after each step
+            duration: self.value((50, 500), (0, 2000)) as u32,
+            start_delay: self.value((0, 50), (0, 2000)) as u32,
+            gain: self.value((0, 10000), (0, u16::MAX as i64)) as u16,
+        }
+    }
+
+    fn effect(&mut self) -> Effect {
+        let params = self.params();
+        match self.rng.range(0, 3) {
+            0 => Effect::Constant {
+                params,
+                force: ConstantForce {
+                    magnitude: self.magnitude(),
+                    direction: self.direction(),
+                    envelope: self.envelope(),
+                },
+            },
+            1 => Effect::Periodic {
+                params,
+                effect: PeriodicEffect {
+                    wave_type: match self.rng.range(0, 4) {
+                        0 => WaveType::Sine,
+                        1 => WaveType::Square,
+                        2 => WaveType::Triangle,
+                        3 => WaveType::SawtoothUp,
+                        _ => WaveType::SawtoothDown,
+                    },
+                    magnitude: self.level(),
+                    offset: self.magnitude(),
+                    phase: self.value((0, 35999), (0, u16::MAX as i64)) as u16,
+                    period: self.value((10, 1000), (0, 100_000)) as u32,
+                    direction: self.direction(),
+                    envelope: self.envelope(),
+                },
+            },
+            2 => Effect::Ramp {
+                params,
+                effect: RampEffect {
+                    start_magnitude: self.magnitude(),
+                    end_magnitude: self.magnitude(),
+                    direction: self.direction(),
+                    envelope: self.envelope(),
+                },
+            },
+            _ => Effect::Condition {
+                params,
+                effect: ConditionEffect {
+                    condition_type: match self.rng.range(0, 3) {
+                        0 => ConditionType::Spring,
+                        1 => ConditionType::Damper,
+                        2 => ConditionType::Friction,
+                        _ => ConditionType::Inertia,
+                    },
+                    x_axis: ConditionParams {
+                        offset: self.coefficient(),
+                        positive_coefficient: self.coefficient(),
+                        negative_coefficient: self.coefficient(),
+                        positive_saturation: self.saturation(),
+                        negative_saturation: self.saturation(),
+                        dead_band: self.saturation(),
+                    },
+                },
+            },
+        }
+    }
+}
+
+/// Play `options.iterations` random effects, returning the number of anomalies
+/// An anomaly found while fuzzing
+#[derive(Debug, Serialize)]
+pub struct FoundAnomaly {
+    /// 1-based effect number
+    pub effect: usize,
+    pub anomaly: String,
+    pub reproducer: PathBuf,
+}
+
+/// Outcome of a fuzzing run
+#[derive(Debug, Serialize)]
+pub struct FuzzSummary {
+    pub seed: u64,
+    pub effects: usize,
+    pub packets: usize,
+    pub anomalies: Vec<FoundAnomaly>,
+    pub packet_log: PathBuf,
+}
+
+/// Fuzz until done or `cancel` is cancelled; the driver should share `cancel`
+/// so a cancelled effect ends early too
+pub fn run(driver: &mut dyn FfbDriver, options: &FuzzOptions, cancel: &CancellationToken) -> anyhow::Result<FuzzSummary> {
+    fs::create_dir_all(FUZZ_DIR)?;
+    let mut generator = Generator {
+        rng: Rng(options.seed),
+        invalid: options.invalid,
+        max_magnitude: options.max_magnitude.clamp(0, 10000) as i64,
+    };
+    println!(
+        "Fuzzing {} effects with seed {}{}",
+        options.iterations,
+        options.seed,
+        if options.invalid { " (including invalid values)" } else { "" }
+    );
+
+    let mut steps: Vec<ScenarioStep> = Vec::new();
+    let mut outputs: Vec<StepOutput> = Vec::new();
+    let mut anomalies = Vec::new();
+    for iteration in 1..=options.iterations {
+        if cancel.is_cancelled() {
+            println!("Cancelled after {} effects", iteration - 1);
+            break;
+        }
+        let effect = generator.effect();
+        println!("  Effect {}: {} (duration: {} ms)", iteration, effect_label(&effect), effect.duration());
+
+        let result = driver.apply_effect(&effect);
+        let _ = driver.stop_all_effects();
+        for note in driver.take_notes() {
+            println!("    {}", note);
+        }
+        let (packets, anomaly) = match result {
+            Ok(packets) if packets.is_empty() => (packets, Some(Anomaly::NoPackets)),
+            Ok(packets) => (packets, None),
+            // A dead capture makes every following effect look anomalous
+            Err(e @ FFBError::CaptureFailed { .. }) => return Err(e.at_step(iteration).into()),
+            Err(e) => (Vec::new(), Some(Anomaly::EffectFailed(e.to_string()))),
+        };
+
+        outputs.push(StepOutput {
+            step_index: iteration,
+            step_name: effect_label(&effect).to_string(),
+            packets,
+            variants: Vec::new(),
+            latencies_us: Vec::new(),
+            wire_latency_us: None,
+            timestamps_us: Vec::new(),
+            axis: Vec::new(),
+            devices: Vec::new(),
+            health: None,
+        });
+        steps.push(ScenarioStep {
+            effect,
+            expect: None,
+            peripherals: Vec::new(),
+            settings: Vec::new(),
+            hold: false,
+            stop_at: None,
+        });
+
+        if let Some(anomaly) = anomaly {
+            let path = write_reproducer(&steps, options.seed, iteration, &anomaly)?;
+            println!("    ANOMALY: {}; reproducer written to {}", anomaly, path.display());
+            let hook_stop = options
+                .on_anomaly
+                .as_deref()
+                .is_some_and(|command| !run_hook(command, options.seed, iteration, &anomaly, &path));
+            anomalies.push(FoundAnomaly {
+                effect: iteration,
+                anomaly: anomaly.to_string(),
+                reproducer: path,
+            });
+            if options.stop_on_anomaly || hook_stop {
+                break;
+            }
+        }
+    }
+
+    let log_path = PathBuf::from(FUZZ_DIR).join(format!("fuzz_{}.run", options.seed));
+    let total_packets = write_capture_file(&log_path, &outputs)?;
+    println!(
+        "\n{} effects, {} packets, {} anomalies; packet log saved to {}",
+        outputs.len(),
+        total_packets,
+        anomalies.len(),
+        log_path.display()
+    );
+    Ok(FuzzSummary {
+        seed: options.seed,
+        effects: outputs.len(),
+        packets: total_packets,
+        anomalies,
+        packet_log: log_path,
+    })
+}
+
+/// Save the effects played so far as a scenario ending with the anomalous one,
+/// since firmware state left by earlier effects may be needed to trigger it
+fn write_reproducer(steps: &[ScenarioStep], seed: u64, iteration: usize, anomaly: &Anomaly) -> anyhow::Result<PathBuf> {
+    let scenario = Scenario {
+        name: format!("Fuzz seed {} effect {}", seed, iteration),
+        description: format!("Reproducer for {} at the last step", anomaly),
+        loop_forever: false,
+        repeat_count: 1,
+        timing: Default::default(),
+        health_check: false,
+        steps: steps.to_vec(),
+    };
+    let path = PathBuf::from(FUZZ_DIR).join(format!("fuzz_{}_{}.yaml", seed, iteration));
+    fs::write(&path, serde_yaml::to_string(&scenario)?)?;
+    Ok(path)
+}
+
+/// Run the anomaly hook through the shell with the anomaly in environment variables
+/// Returns false if the hook asks to stop by exiting non-zero
+fn run_hook(command: &str, seed: u64, iteration: usize, anomaly: &Anomaly, scenario: &Path) -> bool {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let status = shell
+        .arg(command)
+        .env("FFB_FUZZ_SEED", seed.to_string())
+        .env("FFB_FUZZ_EFFECT", iteration.to_string())
+        .env("FFB_FUZZ_ANOMALY", anomaly.to_string())
+        .env("FFB_FUZZ_SCENARIO", scenario)
+        .status();
+    match status {
+        Ok(status) if !status.success() => {
+            println!("    Anomaly hook exited with {}, stopping", status);
+            false
+        }
+        Ok(_) => true,
+        Err(e) => {
+            eprintln!("    Failed to run anomaly hook: {}", e);
+            true
+        }
+    }
+}
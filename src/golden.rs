@@ -0,0 +1,289 @@
+//! Golden capture store: one golden per scenario and driver
+//!
+//! Goldens live in `<store>/<scenario>/<driver>.run`, where the scenario is the
+//! scenario file stem. The `golden` command promotes runs into the store, lists
+//! what a scenario has and compares runs against goldens without replaying.
+
+use crate::compare::{self, CompareOptions};
+use crate::error::{ExitStatus, ScenarioError};
+use crate::output::Output;
+use crate::{parse_capture_file, reports, suite, CaptureReader, StepOutput};
+use clap::Subcommand;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Default location of the golden store
+pub const DEFAULT_STORE: &str = "runs/golden";
+
+#[derive(Subcommand)]
+pub enum GoldenCommand {
+    /// Copy a recorded run into the store as the golden for a scenario and driver
+    Promote {
+        /// Capture file to promote (path or name in runs/)
+        run: String,
+
+        /// Scenario the run was recorded from (file path or stem; default: the
+        /// longest scenario name the run file name starts with)
+        #[arg(short, long)]
+        scenario: Option<String>,
+
+        /// Driver the run was recorded with
+        #[arg(short, long, default_value = "sdl")]
+        driver: String,
+
+        /// Directory with scenario YAML files, used to infer the scenario
+        #[arg(long, default_value = "scenarios")]
+        dir: PathBuf,
+    },
+    /// List the goldens of a scenario with their step and packet counts
+    Show {
+        /// Scenario file path or stem
+        scenario: String,
+    },
+    /// Compare a recorded run with the golden of its scenario and driver
+    Diff {
+        /// Scenario file path or stem
+        scenario: String,
+
+        /// Capture file to check (path or name in runs/)
+        run: String,
+
+        /// Driver whose golden is used
+        #[arg(short, long, default_value = "sdl")]
+        driver: String,
+
+        /// Decode both sides to canonical commands before comparing
+        #[arg(long)]
+        normalize: bool,
+
+        /// What to compare: packets (exact payloads) or counts (packets per step)
+        #[arg(long, value_enum, default_value = "packets")]
+        mode: compare::CompareMode,
+
+        /// Allowed packet count difference per step in counts mode
+        #[arg(long, default_value_t = 0)]
+        tolerance: usize,
+    },
+}
+
+/// Where the golden capture of a scenario is looked up
+#[derive(Debug, Clone)]
+pub enum GoldenSource {
+    /// Structured store: `<root>/<scenario>/<driver>.run`
+    Store { root: PathBuf, driver: String },
+    /// Flat directory of `<scenario><suffix>.run` files
+    Flat { dir: PathBuf, suffix: String },
+}
+
+impl GoldenSource {
+    /// Golden capture path for a scenario file
+    pub fn path_for(&self, scenario_path: &Path) -> PathBuf {
+        match self {
+            GoldenSource::Store { root, driver } => golden_path(root, &scenario_key(scenario_path), driver),
+            GoldenSource::Flat { dir, suffix } => suite::golden_path_for(scenario_path, dir, suffix),
+        }
+    }
+}
+
+/// Golden of `scenario` (file stem) recorded with `driver`
+pub fn golden_path(root: &Path, scenario: &str, driver: &str) -> PathBuf {
+    root.join(scenario).join(format!("{}.run", driver.to_lowercase()))
+}
+
+/// Store key of a scenario given as a file path or a bare stem
+pub fn scenario_key(scenario: &Path) -> String {
+    scenario
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+pub fn run(command: GoldenCommand, root: &Path, output: &mut Output) -> anyhow::Result<ExitStatus> {
+    match command {
+        GoldenCommand::Promote {
+            run,
+            scenario,
+            driver,
+            dir,
+        } => {
+            let run_path = resolve_run(&run)?;
+            let scenario = match scenario {
+                Some(scenario) => scenario_key(Path::new(&scenario)),
+                None => infer_scenario(&run_path, &dir)?,
+            };
+            let (golden, steps) = promote(&run_path, root, &scenario, &driver)?;
+            output.emit(&serde_json::json!({
+                "scenario": scenario,
+                "driver": driver.to_lowercase(),
+                "golden": golden,
+                "steps": steps,
+            }))?;
+            Ok(ExitStatus::Success)
+        }
+        GoldenCommand::Show { scenario } => {
+            let goldens = show(root, &scenario_key(Path::new(&scenario)))?;
+            output.emit(&goldens)?;
+            Ok(ExitStatus::Success)
+        }
+        GoldenCommand::Diff {
+            scenario,
+            run,
+            driver,
+            normalize,
+            mode,
+            tolerance,
+        } => {
+            let options = CompareOptions {
+                normalize,
+                mode,
+                tolerance,
+                ..CompareOptions::default()
+            };
+            diff(root, &scenario_key(Path::new(&scenario)), &driver, &resolve_run(&run)?, &options, output)
+        }
+    }
+}
+
+/// Run given as a path, or as a capture name in `runs/` like the other commands
+fn resolve_run(run: &str) -> anyhow::Result<PathBuf> {
+    [PathBuf::from(run), PathBuf::from("runs").join(run)]
+        .into_iter()
+        .find(|path| path.is_file())
+        .ok_or_else(|| anyhow::anyhow!("Capture file not found: {}", run))
+}
+
+/// Pick the longest scenario stem in `dir` that the run file name starts with,
+/// matching the `<scenario><suffix>.run` names used before the store existed
+fn infer_scenario(run_path: &Path, dir: &Path) -> anyhow::Result<String> {
+    let run_name = scenario_key(run_path);
+    suite::find_scenarios(dir)?
+        .iter()
+        .map(|path| scenario_key(path))
+        .filter(|stem| run_name.starts_with(stem.as_str()))
+        .max_by_key(|stem| stem.len())
+        .ok_or_else(|| {
+            ScenarioError::NotFound(format!(
+                "no scenario in {} matches {}, pass --scenario",
+                dir.display(),
+                run_path.display()
+            ))
+            .into()
+        })
+}
+
+/// Copy the run into the store; returns the golden path and its step count
+fn promote(run_path: &Path, root: &Path, scenario: &str, driver: &str) -> anyhow::Result<(PathBuf, usize)> {
+    // Refuse files that are not captures, so the store only holds comparable goldens
+    let steps = parse_capture_file(&run_path.to_path_buf())?;
+    if steps.is_empty() {
+        anyhow::bail!("{} contains no steps", run_path.display());
+    }
+
+    let golden = golden_path(root, scenario, driver);
+    if let Some(parent) = golden.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let replaced = golden.exists();
+    fs::copy(run_path, &golden)?;
+    println!(
+        "{} golden for {} ({}): {} ({} steps)",
+        if replaced { "Replaced" } else { "Added" },
+        scenario,
+        driver.to_lowercase(),
+        golden.display(),
+        steps.len()
+    );
+    Ok((golden, steps.len()))
+}
+
+/// Print the goldens of a scenario and return them for JSON output
+fn show(root: &Path, scenario: &str) -> anyhow::Result<Vec<serde_json::Value>> {
+    let dir = root.join(scenario);
+    let mut goldens: Vec<PathBuf> = match fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.is_file() && path.extension().is_some_and(|e| e == "run"))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    if goldens.is_empty() {
+        println!("No goldens for {} in {}", scenario, dir.display());
+        return Ok(Vec::new());
+    }
+    goldens.sort();
+
+    println!("Goldens for {}:", scenario);
+    println!("{:<12} {:>6} {:>8}  Path", "Driver", "Steps", "Packets");
+    let mut listed = Vec::with_capacity(goldens.len());
+    for golden in &goldens {
+        let steps = parse_capture_file(golden)?;
+        let packets: usize = steps.iter().map(|s| s.packets.len()).sum();
+        println!(
+            "{:<12} {:>6} {:>8}  {}",
+            scenario_key(golden),
+            steps.len(),
+            packets,
+            golden.display()
+        );
+        listed.push(serde_json::json!({
+            "driver": scenario_key(golden),
+            "steps": steps.len(),
+            "packets": packets,
+            "path": golden,
+        }));
+    }
+    Ok(listed)
+}
+
+fn diff(
+    root: &Path,
+    scenario: &str,
+    driver: &str,
+    run_path: &Path,
+    options: &CompareOptions,
+    output: &mut Output,
+) -> anyhow::Result<ExitStatus> {
+    let golden = golden_path(root, scenario, driver);
+    if !golden.exists() {
+        anyhow::bail!("No golden for {} ({}): {} not found", scenario, driver, golden.display());
+    }
+
+    println!("Golden: {}", golden.display());
+    println!("Run: {}\n", run_path.display());
+    // Both captures are read step by step, so soak runs compare without loading them whole
+    let actual = CaptureReader::open(run_path)?;
+    let results = compare::compare_captures(&CaptureReader::open(&golden)?, &actual, options)?;
+    // The JSON document only needs the packet timestamps of the actual steps
+    let actual_steps = actual
+        .steps()
+        .map(|step| {
+            step.map(|step| StepOutput {
+                step_index: step.step_index,
+                timestamps_us: step.timestamps_us,
+                ..StepOutput::default()
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    compare::print_comparison(&results);
+
+    let mismatched = results.iter().filter(|r| !r.is_match()).count();
+    if mismatched == 0 {
+        println!("OK: All {} steps match!", results.len());
+    } else {
+        println!("FAIL: {} of {} steps differ", mismatched, results.len());
+    }
+    compare::print_summary(&results);
+    output.emit(&reports::json::build(
+        scenario,
+        driver,
+        &golden.display().to_string(),
+        &results,
+        &actual_steps,
+    ))?;
+
+    Ok(if mismatched == 0 {
+        ExitStatus::Success
+    } else {
+        ExitStatus::Mismatch
+    })
+}
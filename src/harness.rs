@@ -0,0 +1,213 @@
+//! Scenario fixtures as `cargo test` tests (`harness` feature)
+//!
+//! Fixtures pair a scenario with its goldens in the golden store layout:
+//! `tests/fixtures/<name>.yaml` and `tests/fixtures/<name>/<driver>.run`. By
+//! default they play through the SIMAGIC driver, which generates its reports
+//! without a device, so `cargo test --features harness` covers the packet
+//! generation itself. With `FFB_HIL=1` they play on the real device through the
+//! driver named by `FFB_HIL_DRIVER` (SDL by default), against that driver's
+//! goldens. [`main`] reports each fixture as a test in libtest's format, for a
+//! `harness = false` test target; a fixture without a golden for the driver is
+//! ignored.
+
+use crate::compare::{self, CompareOptions, StepComparison};
+use crate::observer::PlaybackObserver;
+use crate::usb_monitor::CaptureConfig;
+use crate::{create_driver, parse_capture_file, Scenario};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Instant;
+
+/// Set to 1 to play the fixtures on the real device
+pub const HIL_VAR: &str = "FFB_HIL";
+/// Driver of hardware-in-the-loop runs
+pub const HIL_DRIVER_VAR: &str = "FFB_HIL_DRIVER";
+/// Driver of runs without a device
+pub const SIMULATED_DRIVER: &str = "simagic";
+pub const DEFAULT_HIL_DRIVER: &str = "sdl";
+
+/// Packet differences listed per mismatched step
+const MAX_LISTED_DIFFS: usize = 3;
+
+/// A scenario and its golden for one driver
+#[derive(Debug, Clone)]
+pub struct Fixture {
+    /// Scenario file stem, the test name
+    pub name: String,
+    pub scenario: PathBuf,
+    pub golden: PathBuf,
+}
+
+pub fn hil_enabled() -> bool {
+    env::var(HIL_VAR).is_ok_and(|value| value == "1")
+}
+
+/// Driver the fixtures play through in this environment
+pub fn driver_name() -> String {
+    if !hil_enabled() {
+        return SIMULATED_DRIVER.to_string();
+    }
+    env::var(HIL_DRIVER_VAR).unwrap_or_else(|_| DEFAULT_HIL_DRIVER.to_string())
+}
+
+/// Scenario files in `dir` with their `driver` golden paths, sorted by name
+pub fn discover(dir: &Path, driver: &str) -> std::io::Result<Vec<Fixture>> {
+    let mut fixtures: Vec<Fixture> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file() && matches!(path.extension().and_then(|e| e.to_str()), Some("yaml" | "yml")))
+        .filter_map(|scenario| {
+            let name = scenario.file_stem()?.to_string_lossy().into_owned();
+            let golden = dir.join(&name).join(format!("{}.run", driver));
+            Some(Fixture { name, scenario, golden })
+        })
+        .collect();
+    fixtures.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(fixtures)
+}
+
+/// Keeps playback quiet; the test report says how it went
+struct Quiet;
+
+impl PlaybackObserver for Quiet {}
+
+/// Play `fixture` on a fresh `driver` and compare it with its golden
+/// The error describes the failure, e.g. the mismatched steps.
+pub fn run_fixture(fixture: &Fixture, driver: &str) -> Result<(), String> {
+    let scenario = Scenario::load_from_file(&fixture.scenario).map_err(|e| e.to_string())?;
+    let expected = parse_capture_file(&fixture.golden).map_err(|e| format!("{}: {}", fixture.golden.display(), e))?;
+    compare::validate_expected(&expected).map_err(|e| format!("{}: {}", fixture.golden.display(), e))?;
+
+    let capture = CaptureConfig::default();
+    let mut driver = create_driver(driver, Some(&capture)).map_err(|e| e.to_string())?;
+    driver.initialize().map_err(|e| e.to_string())?;
+    let played = scenario.play_observed(driver.as_mut(), &mut Quiet);
+    let _ = driver.shutdown();
+    let actual = played.map_err(|e| e.to_string())?;
+
+    let results = compare::compare_runs(&expected, &actual, &CompareOptions::default());
+    let mismatched: Vec<&StepComparison> = results.iter().filter(|r| !r.is_match()).collect();
+    if mismatched.is_empty() {
+        return Ok(());
+    }
+    let mut lines = vec![format!(
+        "{} of {} steps differ from {}",
+        mismatched.len(),
+        results.len(),
+        fixture.golden.display()
+    )];
+    for result in mismatched {
+        lines.push(format!(
+            "Step {} ({}): {:?}, expected {} packets, got {}",
+            result.step_index, result.step_name, result.status, result.expected_packets, result.actual_packets
+        ));
+        for diff in result.packet_diffs.iter().take(MAX_LISTED_DIFFS) {
+            lines.extend(compare::describe_packet_diff(diff).lines().map(|line| format!("  {}", line)));
+        }
+        if result.packet_diffs.len() > MAX_LISTED_DIFFS {
+            lines.push(format!("  ... {} more", result.packet_diffs.len() - MAX_LISTED_DIFFS));
+        }
+    }
+    Err(lines.join("\n"))
+}
+
+/// libtest options the runner understands; others are accepted and ignored
+#[derive(Default)]
+struct Args {
+    filter: Option<String>,
+    exact: bool,
+    list: bool,
+}
+
+impl Args {
+    fn parse() -> Self {
+        let mut args = Args::default();
+        for arg in env::args().skip(1) {
+            match arg.as_str() {
+                "--exact" => args.exact = true,
+                "--list" => args.list = true,
+                _ if arg.starts_with('-') => {}
+                _ => args.filter = Some(arg),
+            }
+        }
+        args
+    }
+
+    fn selects(&self, name: &str) -> bool {
+        match &self.filter {
+            Some(filter) if self.exact => name == filter,
+            Some(filter) => name.contains(filter.as_str()),
+            None => true,
+        }
+    }
+}
+
+/// Run the fixtures in `dir` as tests and report them like libtest
+pub fn main(dir: impl AsRef<Path>) -> ExitCode {
+    let dir = dir.as_ref();
+    let args = Args::parse();
+    let driver = driver_name();
+    let fixtures = match discover(dir, &driver) {
+        Ok(fixtures) => fixtures,
+        Err(e) => {
+            eprintln!("error: cannot read fixtures in {}: {}", dir.display(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let (selected, filtered): (Vec<Fixture>, Vec<Fixture>) =
+        fixtures.into_iter().partition(|fixture| args.selects(&fixture.name));
+
+    if args.list {
+        for fixture in &selected {
+            println!("{}: test", fixture.name);
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    let started = Instant::now();
+    println!("\nrunning {} tests (driver: {})", selected.len(), driver);
+    let (mut passed, mut ignored) = (0, 0);
+    let mut failures: Vec<(String, String)> = Vec::new();
+    for fixture in &selected {
+        if !fixture.golden.is_file() {
+            println!("test {} ... ignored, no golden at {}", fixture.name, fixture.golden.display());
+            ignored += 1;
+            continue;
+        }
+        match run_fixture(fixture, &driver) {
+            Ok(()) => {
+                println!("test {} ... ok", fixture.name);
+                passed += 1;
+            }
+            Err(message) => {
+                println!("test {} ... FAILED", fixture.name);
+                failures.push((fixture.name.clone(), message));
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        println!("\nfailures:\n");
+        for (name, message) in &failures {
+            println!("---- {} ----\n{}\n", name, message);
+        }
+        println!("failures:");
+        for (name, _) in &failures {
+            println!("    {}", name);
+        }
+    }
+    println!(
+        "\ntest result: {}. {} passed; {} failed; {} ignored; 0 measured; {} filtered out; finished in {:.2}s\n",
+        if failures.is_empty() { "ok" } else { "FAILED" },
+        passed,
+        failures.len(),
+        ignored,
+        filtered.len(),
+        started.elapsed().as_secs_f64()
+    );
+    if failures.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
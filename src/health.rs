@@ -0,0 +1,59 @@
+//! Device health checks between scenario steps
+//!
+//! A wheel that resets, browns out or is unplugged mid-run leaves playback going
+//! with empty captures, step after step. With the scenario's `health_check` set,
+//! the device is polled after every step (see `FfbDriver::check_health`) and the
+//! answer is stored with the step's capture as a comment line:
+//! `# Health: ok (joystick connected, HID device readable)`. A device that doesn't
+//! answer ends the run after the step it failed in.
+
+/// Prefix of the health line in capture files
+pub const HEALTH_PREFIX: &str = "# Health:";
+
+const OK: &str = "ok";
+const NOT_RESPONDING: &str = "not responding";
+
+/// A device's answer to a health check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Health {
+    /// Whether the device answered
+    pub responding: bool,
+    /// What was polled and how it went, e.g. "joystick disconnected"
+    pub detail: String,
+}
+
+impl Health {
+    pub fn ok(detail: impl Into<String>) -> Self {
+        Self {
+            responding: true,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn not_responding(detail: impl Into<String>) -> Self {
+        Self {
+            responding: false,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Format the health line of a step
+pub fn format_line(health: &Health) -> String {
+    let state = if health.responding { OK } else { NOT_RESPONDING };
+    format!("{} {} ({})", HEALTH_PREFIX, state, health.detail)
+}
+
+/// Parse a health line, None if the line is not one
+pub fn parse_line(line: &str) -> Option<Health> {
+    let rest = line.strip_prefix(HEALTH_PREFIX)?.trim();
+    let (state, detail) = match rest.split_once(" (") {
+        Some((state, detail)) => (state, detail.strip_suffix(')').unwrap_or(detail)),
+        None => (rest, ""),
+    };
+    match state {
+        OK => Some(Health::ok(detail)),
+        NOT_RESPONDING => Some(Health::not_responding(detail)),
+        _ => None,
+    }
+}
@@ -0,0 +1,772 @@
+//! HID report descriptors and decoding of reports with them
+//!
+//! A force feedback device that follows the USB HID PID (Physical Interface
+//! Device) specification describes every report it accepts in its report
+//! descriptor: the report ID, and each field's usage, bit size and value range.
+//! `ReportDescriptor` parses the descriptor and decodes captured SET_REPORT and
+//! interrupt OUT payloads field by field, so standards-compliant wheels decode
+//! without a vendor decoder.
+//!
+//! Descriptors come from a connected device (Linux: sysfs, the same descriptor
+//! hidraw reports) or from a dump file, binary or hex as printed by `usbhid-dump`.
+
+use crate::usb_monitor::DeviceFilter;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Usage page of the PID specification
+pub const PID_PAGE: u16 = 0x0F;
+const GENERIC_DESKTOP_PAGE: u16 = 0x01;
+const SIMULATION_PAGE: u16 = 0x02;
+const BUTTON_PAGE: u16 = 0x09;
+const ORDINAL_PAGE: u16 = 0x0A;
+
+/// Collection type of the report-level collections in PID descriptors
+const LOGICAL_COLLECTION: u8 = 0x02;
+/// Upper bound for expanding Usage Minimum..Maximum ranges
+const MAX_USAGE_RANGE: u32 = 1024;
+
+/// Usage page and usage ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Usage {
+    pub page: u16,
+    pub id: u16,
+}
+
+impl Usage {
+    /// Extended usage (page in the upper 16 bits)
+    fn from_extended(value: u32) -> Self {
+        Usage {
+            page: (value >> 16) as u16,
+            id: value as u16,
+        }
+    }
+
+    /// Name from the HID usage tables, or the page and ID in hex
+    pub fn name(&self) -> String {
+        let known = match self.page {
+            PID_PAGE => pid_usage_name(self.id),
+            GENERIC_DESKTOP_PAGE => generic_desktop_usage_name(self.id),
+            SIMULATION_PAGE => simulation_usage_name(self.id),
+            BUTTON_PAGE => return format!("Button {}", self.id),
+            ORDINAL_PAGE => return format!("Instance {}", self.id),
+            _ => None,
+        };
+        match known {
+            Some(name) => name.to_string(),
+            None => format!("Usage {:04X}:{:04X}", self.page, self.id),
+        }
+    }
+}
+
+/// Direction of a report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReportKind {
+    Input,
+    Output,
+    Feature,
+}
+
+/// One Input, Output or Feature main item: `count` values of `size` bits each
+#[derive(Debug, Clone)]
+pub struct Field {
+    /// Bit position in the report, after the report ID
+    pub bit_offset: u32,
+    pub size: u32,
+    pub count: u32,
+    pub logical_min: i32,
+    pub logical_max: i32,
+    /// Usages of the values (variable fields) or the selectable usages (array fields)
+    pub usages: Vec<Usage>,
+    /// Each value is a quantity, rather than an index into `usages`
+    pub variable: bool,
+    /// Padding
+    pub constant: bool,
+    /// Usages of the collections between the report's collection and the field
+    pub path: Vec<Usage>,
+}
+
+/// The fields sharing a report ID and kind, in order
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub kind: ReportKind,
+    /// None if the descriptor uses no report IDs
+    pub id: Option<u8>,
+    /// Usage of the logical collection around the report, e.g. Set Effect Report
+    pub usage: Option<Usage>,
+    pub fields: Vec<Field>,
+    /// Total size of the fields in bits
+    pub bits: u32,
+}
+
+impl Report {
+    /// Name of the report's collection, or its ID
+    pub fn name(&self) -> String {
+        match (self.usage, self.id) {
+            (Some(usage), _) => usage.name(),
+            (None, Some(id)) => format!("Report {}", id),
+            (None, None) => "Report".to_string(),
+        }
+    }
+
+    /// Report bytes, starting with the report ID if it has one, holding `values`
+    /// Variable fields take the value of their usage; array fields select the
+    /// first listed usage they contain. Everything else is zero.
+    pub fn encode(&self, values: &[(Usage, i64)]) -> Vec<u8> {
+        let mut body = vec![0u8; self.bits.div_ceil(8) as usize];
+        for field in self.fields.iter().filter(|f| !f.constant && f.size > 0 && f.size <= 32) {
+            if field.variable {
+                for i in 0..field.count {
+                    let Some(usage) = field.usages.get(i as usize).or(field.usages.last()) else {
+                        continue;
+                    };
+                    if let Some((_, value)) = values.iter().find(|(u, _)| u == usage) {
+                        write_bits(&mut body, field.bit_offset + i * field.size, field.size, *value as u32);
+                    }
+                }
+            } else {
+                let selected = values
+                    .iter()
+                    .find_map(|(usage, _)| field.usages.iter().position(|u| u == usage));
+                if let Some(index) = selected {
+                    let value = field.logical_min as i64 + index as i64;
+                    write_bits(&mut body, field.bit_offset, field.size, value as u32);
+                }
+            }
+        }
+        match self.id {
+            Some(id) => std::iter::once(id).chain(body).collect(),
+            None => body,
+        }
+    }
+
+    /// Value of the variable field with `usage` in the report `body` (without the report ID)
+    pub fn value(&self, body: &[u8], usage: Usage) -> Option<i64> {
+        self.fields
+            .iter()
+            .filter(|f| f.variable && !f.constant && f.size > 0 && f.size <= 32)
+            .find_map(|field| {
+                let i = field.usages.iter().position(|u| *u == usage)?.min(field.count as usize - 1) as u32;
+                let offset = field.bit_offset + i * field.size;
+                (body.len() * 8 >= (offset + field.size) as usize)
+                    .then(|| field_value(field, read_bits(body, offset, field.size)))
+            })
+    }
+
+    /// Usages selected by the array fields in the report `body` (without the report ID)
+    pub fn selections(&self, body: &[u8]) -> Vec<Usage> {
+        let mut selected = Vec::new();
+        for field in self.fields.iter().filter(|f| !f.variable && !f.constant && f.size > 0 && f.size <= 32) {
+            for i in 0..field.count {
+                let value = field_value(field, read_bits(body, field.bit_offset + i * field.size, field.size));
+                if value < field.logical_min as i64 {
+                    continue;
+                }
+                if let Some(usage) = field.usages.get((value - field.logical_min as i64) as usize) {
+                    selected.push(*usage);
+                }
+            }
+        }
+        selected
+    }
+}
+
+/// Parsed report descriptor
+#[derive(Debug, Clone, Default)]
+pub struct ReportDescriptor {
+    pub reports: Vec<Report>,
+}
+
+/// Global item state, saved and restored by Push and Pop
+#[derive(Debug, Clone, Copy, Default)]
+struct Globals {
+    usage_page: u16,
+    logical_min: i32,
+    logical_max: i32,
+    report_size: u32,
+    report_count: u32,
+    report_id: Option<u8>,
+}
+
+#[derive(Debug, Default)]
+struct Locals {
+    usages: Vec<Usage>,
+    usage_min: Option<u32>,
+    usage_max: Option<u32>,
+}
+
+impl Locals {
+    fn usages(&self, page: u16) -> Vec<Usage> {
+        let mut usages = self.usages.clone();
+        if let (Some(min), Some(max)) = (self.usage_min, self.usage_max) {
+            let range_page = |value: u32| if value > 0xFFFF { (value >> 16) as u16 } else { page };
+            let (page, min, max) = (range_page(min), min & 0xFFFF, max & 0xFFFF);
+            usages.extend((min..=max.min(min + MAX_USAGE_RANGE)).map(|id| Usage { page, id: id as u16 }));
+        }
+        usages
+    }
+}
+
+impl ReportDescriptor {
+    /// Parse the raw descriptor bytes
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let mut descriptor = ReportDescriptor::default();
+        let mut index: HashMap<(ReportKind, Option<u8>), usize> = HashMap::new();
+        let mut globals = Globals::default();
+        let mut saved: Vec<Globals> = Vec::new();
+        let mut locals = Locals::default();
+        let mut collections: Vec<(u8, Option<Usage>)> = Vec::new();
+
+        let mut pos = 0;
+        while pos < bytes.len() {
+            let prefix = bytes[pos];
+            // Long items carry no report structure
+            if prefix == 0xFE {
+                let size = *bytes.get(pos + 1).ok_or("truncated long item")? as usize;
+                pos += 3 + size;
+                continue;
+            }
+            let size = match prefix & 0x03 {
+                3 => 4,
+                n => n as usize,
+            };
+            let data = bytes
+                .get(pos + 1..pos + 1 + size)
+                .ok_or_else(|| format!("truncated item at offset {}", pos))?;
+            pos += 1 + size;
+
+            let unsigned = data.iter().rev().fold(0u32, |value, &b| (value << 8) | b as u32);
+            let signed = match size {
+                1 => unsigned as u8 as i8 as i32,
+                2 => unsigned as u16 as i16 as i32,
+                _ => unsigned as i32,
+            };
+            let item_type = (prefix >> 2) & 0x03;
+            let tag = prefix >> 4;
+
+            match (item_type, tag) {
+                // Main items
+                (0, 0x08 | 0x09 | 0x0B) => {
+                    let kind = match tag {
+                        0x08 => ReportKind::Input,
+                        0x09 => ReportKind::Output,
+                        _ => ReportKind::Feature,
+                    };
+                    let key = (kind, globals.report_id);
+                    let report_index = *index.entry(key).or_insert_with(|| {
+                        descriptor.reports.push(Report {
+                            kind,
+                            id: globals.report_id,
+                            usage: None,
+                            fields: Vec::new(),
+                            bits: 0,
+                        });
+                        descriptor.reports.len() - 1
+                    });
+                    let report = &mut descriptor.reports[report_index];
+
+                    // The outermost logical collection names the report
+                    let report_level = collections.iter().position(|(kind, _)| *kind == LOGICAL_COLLECTION);
+                    if report.usage.is_none() {
+                        report.usage = report_level.and_then(|level| collections[level].1);
+                    }
+                    let path = collections
+                        .iter()
+                        .skip(report_level.map_or(collections.len(), |level| level + 1))
+                        .filter_map(|(_, usage)| *usage)
+                        .collect();
+
+                    let field = Field {
+                        bit_offset: report.bits,
+                        size: globals.report_size,
+                        count: globals.report_count,
+                        logical_min: globals.logical_min,
+                        logical_max: globals.logical_max,
+                        usages: locals.usages(globals.usage_page),
+                        variable: unsigned & 0x02 != 0,
+                        constant: unsigned & 0x01 != 0,
+                        path,
+                    };
+                    report.bits += field.size * field.count;
+                    report.fields.push(field);
+                    locals = Locals::default();
+                }
+                (0, 0x0A) => {
+                    let usage = locals.usages(globals.usage_page).first().copied();
+                    collections.push((unsigned as u8, usage));
+                    locals = Locals::default();
+                }
+                (0, 0x0C) => {
+                    collections.pop();
+                    locals = Locals::default();
+                }
+                (0, _) => locals = Locals::default(),
+
+                // Global items
+                (1, 0x00) => globals.usage_page = unsigned as u16,
+                (1, 0x01) => globals.logical_min = signed,
+                // Maximum is unsigned when the minimum is not negative
+                (1, 0x02) => globals.logical_max = if globals.logical_min < 0 { signed } else { unsigned as i32 },
+                (1, 0x07) => globals.report_size = unsigned,
+                (1, 0x08) => {
+                    if unsigned == 0 || unsigned > 0xFF {
+                        return Err(format!("invalid report ID {}", unsigned));
+                    }
+                    globals.report_id = Some(unsigned as u8);
+                }
+                (1, 0x09) => globals.report_count = unsigned,
+                (1, 0x0A) => saved.push(globals),
+                (1, 0x0B) => globals = saved.pop().ok_or("Pop without Push")?,
+
+                // Local items
+                (2, 0x00) => locals.usages.push(if size == 4 {
+                    Usage::from_extended(unsigned)
+                } else {
+                    Usage {
+                        page: globals.usage_page,
+                        id: unsigned as u16,
+                    }
+                }),
+                (2, 0x01) => locals.usage_min = Some(unsigned),
+                (2, 0x02) => locals.usage_max = Some(unsigned),
+
+                _ => {}
+            }
+        }
+
+        if descriptor.reports.is_empty() {
+            return Err("descriptor has no reports".to_string());
+        }
+        Ok(descriptor)
+    }
+
+    /// The descriptor declares PID usages, i.e. the device does force feedback the standard way
+    pub fn has_pid(&self) -> bool {
+        self.reports
+            .iter()
+            .any(|r| r.usage.is_some_and(|u| u.page == PID_PAGE))
+    }
+
+    /// One line per report and field, for reading or committing as ground truth
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for report in &self.reports {
+            let id = report.id.map_or(String::new(), |id| format!(" {}", id));
+            lines.push(format!(
+                "{:?} report{}: {}, {} bytes",
+                report.kind,
+                id,
+                report.name(),
+                report.bits.div_ceil(8)
+            ));
+            for field in &report.fields {
+                let bits = format!("bits {}-{}", field.bit_offset, field.bit_offset + field.size * field.count - 1);
+                if field.constant {
+                    lines.push(format!("  {}: padding", bits));
+                } else if field.variable {
+                    for i in 0..field.count {
+                        let Some(usage) = field.usages.get(i as usize).or(field.usages.last()) else {
+                            continue;
+                        };
+                        let start = field.bit_offset + i * field.size;
+                        lines.push(format!(
+                            "  bits {}-{}: {}, {}..{}",
+                            start,
+                            start + field.size - 1,
+                            qualified_name(&field.path, *usage),
+                            field.logical_min,
+                            field.logical_max
+                        ));
+                    }
+                } else {
+                    let name = field.path.last().map_or("Selection".to_string(), Usage::name);
+                    let usages: Vec<String> = field.usages.iter().map(Usage::name).collect();
+                    lines.push(format!(
+                        "  {}: {} ({} x {} bits), one of {}",
+                        bits,
+                        name,
+                        field.count,
+                        field.size,
+                        usages.join(", ")
+                    ));
+                }
+            }
+        }
+        lines
+    }
+
+    /// Reports start with a report ID
+    pub fn uses_report_ids(&self) -> bool {
+        self.reports.iter().any(|r| r.id.is_some())
+    }
+
+    /// Report of `kind` named by the logical collection `usage`, e.g. PID Block Load
+    pub fn find(&self, kind: ReportKind, usage: Usage) -> Option<&Report> {
+        self.reports.iter().find(|r| r.kind == kind && r.usage == Some(usage))
+    }
+
+    /// Report of `kind` that `payload` is, and its body after the report ID
+    pub fn split<'p>(&self, kind: ReportKind, payload: &'p [u8]) -> Option<(&Report, &'p [u8])> {
+        let (id, body) = if self.uses_report_ids() {
+            (Some(*payload.first()?), &payload[1..])
+        } else {
+            (None, payload)
+        };
+        let report = self.reports.iter().find(|r| r.kind == kind && r.id == id)?;
+        Some((report, body))
+    }
+
+    /// Decode a report sent to the device (Output, or Feature as a fallback)
+    /// `payload` starts with the report ID if the descriptor uses IDs
+    /// Returns None if no report matches
+    pub fn decode(&self, payload: &[u8]) -> Option<String> {
+        let (id, body) = if self.uses_report_ids() {
+            (Some(*payload.first()?), &payload[1..])
+        } else {
+            (None, payload)
+        };
+        let report = [ReportKind::Output, ReportKind::Feature]
+            .iter()
+            .find_map(|kind| self.reports.iter().find(|r| r.kind == *kind && r.id == id))?;
+        if body.len() * 8 < report.bits as usize {
+            return None;
+        }
+
+        let mut values = Vec::new();
+        for field in report.fields.iter().filter(|f| !f.constant && f.size > 0 && f.size <= 32) {
+            for i in 0..field.count {
+                let raw = read_bits(body, field.bit_offset + i * field.size, field.size);
+                if field.variable {
+                    let Some(usage) = field.usages.get(i as usize).or(field.usages.last()) else {
+                        continue;
+                    };
+                    let name = qualified_name(&field.path, *usage);
+                    values.push(format!("{}={}", name, field_value(field, raw)));
+                } else {
+                    // Array: the value selects one of the usages, out of range is no selection
+                    let value = field_value(field, raw);
+                    if value < field.logical_min as i64 || value > field.logical_max as i64 {
+                        continue;
+                    }
+                    let Some(selected) = field.usages.get((value - field.logical_min as i64) as usize) else {
+                        continue;
+                    };
+                    let name = field.path.last().map_or("Selection".to_string(), Usage::name);
+                    values.push(format!("{}={}", name, selected.name()));
+                }
+            }
+        }
+
+        if values.is_empty() {
+            return None;
+        }
+        Some(format!("{}: {}", report.name(), values.join(", ")))
+    }
+}
+
+/// "Direction.Instance 1" for a usage inside named collections
+fn qualified_name(path: &[Usage], usage: Usage) -> String {
+    path.iter()
+        .chain(std::iter::once(&usage))
+        .map(Usage::name)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Raw value, sign-extended when the logical range is signed
+fn field_value(field: &Field, raw: u32) -> i64 {
+    if field.logical_min < 0 && field.size < 32 && raw & (1 << (field.size - 1)) != 0 {
+        raw as i64 - (1i64 << field.size)
+    } else if field.logical_min < 0 {
+        raw as i32 as i64
+    } else {
+        raw as i64
+    }
+}
+
+/// Store the low `size` bits of `value` from bit `offset`, least significant bit first
+fn write_bits(bytes: &mut [u8], offset: u32, size: u32, value: u32) {
+    for bit in 0..size {
+        let position = offset + bit;
+        if let Some(byte) = bytes.get_mut((position / 8) as usize) {
+            if value & (1 << bit) != 0 {
+                *byte |= 1 << (position % 8);
+            } else {
+                *byte &= !(1 << (position % 8));
+            }
+        }
+    }
+}
+
+/// `size` bits starting at bit `offset`, least significant bit first
+fn read_bits(bytes: &[u8], offset: u32, size: u32) -> u32 {
+    (0..size).fold(0u32, |value, bit| {
+        let position = offset + bit;
+        let set = bytes
+            .get((position / 8) as usize)
+            .is_some_and(|byte| byte & (1 << (position % 8)) != 0);
+        value | ((set as u32) << bit)
+    })
+}
+
+/// Descriptor from `source`: a dump file, or VID:PID of a connected device
+pub fn load(source: &str) -> Result<ReportDescriptor, String> {
+    let bytes = load_bytes(source)?;
+    ReportDescriptor::parse(&bytes).map_err(|e| format!("Invalid report descriptor from {}: {}", source, e))
+}
+
+/// Raw descriptor bytes from `source`, as for `load`
+pub fn load_bytes(source: &str) -> Result<Vec<u8>, String> {
+    let path = Path::new(source);
+    if path.is_file() {
+        return read_descriptor_file(path);
+    }
+    let device: DeviceFilter = source
+        .parse()
+        .map_err(|_| format!("'{}' is neither a descriptor file nor a VID:PID", source))?;
+    read_report_descriptor(&device)
+}
+
+/// Text dump of the raw descriptor `bytes` and their parsed `descriptor`: `title`
+/// and the reports as `#` comments around the bytes in hex, which
+/// `read_descriptor_file` reads back
+pub fn format_dump(title: &str, bytes: &[u8], descriptor: &ReportDescriptor) -> String {
+    let mut dump = format!("# {}\n# {} bytes\n", title, bytes.len());
+    for line in bytes.chunks(16) {
+        let hex: Vec<String> = line.iter().map(|b| format!("{:02X}", b)).collect();
+        dump.push_str(&hex.join(" "));
+        dump.push('\n');
+    }
+    dump.push_str("#\n");
+    for line in descriptor.describe() {
+        dump.push_str(&format!("# {}\n", line));
+    }
+    dump
+}
+
+/// Raw descriptor file, or a hex dump; lines that are not hex bytes (e.g. the
+/// headers of `usbhid-dump`) are skipped
+pub fn read_descriptor_file(path: &Path) -> Result<Vec<u8>, String> {
+    let content = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    if !content.iter().all(|b| b.is_ascii_graphic() || b.is_ascii_whitespace()) {
+        return Ok(content);
+    }
+
+    let text = String::from_utf8_lossy(&content);
+    let bytes: Vec<u8> = text
+        .lines()
+        .filter_map(|line| {
+            line.split_whitespace()
+                .map(|token| {
+                    let token = token.trim_start_matches("0x").trim_end_matches(',');
+                    (token.len() == 2).then(|| u8::from_str_radix(token, 16).ok()).flatten()
+                })
+                .collect::<Option<Vec<u8>>>()
+        })
+        .flatten()
+        .collect();
+    if bytes.is_empty() {
+        return Err(format!("{} contains no descriptor bytes", path.display()));
+    }
+    Ok(bytes)
+}
+
+/// sysfs directories of the HID interfaces of a connected device, with their
+/// report descriptor (`report_descriptor`) and hidraw node (`hidraw/hidrawN`)
+#[cfg(target_os = "linux")]
+pub fn hid_interfaces(device: &DeviceFilter) -> Result<Vec<std::path::PathBuf>, String> {
+    const HID_DEVICES: &str = "/sys/bus/hid/devices";
+
+    let entries = fs::read_dir(HID_DEVICES).map_err(|e| format!("Failed to list {}: {}", HID_DEVICES, e))?;
+    let mut interfaces = Vec::new();
+    for entry in entries.flatten() {
+        // Entries are named BUS:VID:PID.INSTANCE, e.g. 0003:046D:C24F.0005
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let mut parts = name.split(['.', ':']).skip(1);
+        let matches = match (parts.next(), parts.next()) {
+            (Some(vid), Some(pid)) => {
+                u16::from_str_radix(vid, 16) == Ok(device.vendor_id)
+                    && u16::from_str_radix(pid, 16) == Ok(device.product_id)
+            }
+            _ => false,
+        };
+        if matches {
+            interfaces.push(entry.path());
+        }
+    }
+    Ok(interfaces)
+}
+
+/// Report descriptor of a connected device
+/// With several HID interfaces, the one with PID usages is preferred
+#[cfg(target_os = "linux")]
+pub fn read_report_descriptor(device: &DeviceFilter) -> Result<Vec<u8>, String> {
+    let mut descriptors: Vec<Vec<u8>> = hid_interfaces(device)?
+        .iter()
+        .filter_map(|interface| fs::read(interface.join("report_descriptor")).ok())
+        .collect();
+
+    let pid = descriptors
+        .iter()
+        .position(|bytes| ReportDescriptor::parse(bytes).is_ok_and(|d| d.has_pid()));
+    match pid {
+        Some(index) => Ok(descriptors.swap_remove(index)),
+        None => descriptors
+            .into_iter()
+            .next()
+            .ok_or_else(|| format!("No HID device {} found", device)),
+    }
+}
+
+/// Report descriptor of a connected device
+#[cfg(not(target_os = "linux"))]
+pub fn read_report_descriptor(device: &DeviceFilter) -> Result<Vec<u8>, String> {
+    Err(format!(
+        "Reading the report descriptor of {} needs Linux; pass a descriptor file instead",
+        device
+    ))
+}
+
+fn pid_usage_name(id: u16) -> Option<&'static str> {
+    Some(match id {
+        0x01 => "Physical Interface Device",
+        0x20 => "Normal",
+        0x21 => "Set Effect Report",
+        0x22 => "Effect Block Index",
+        0x23 => "Parameter Block Offset",
+        0x24 => "ROM Flag",
+        0x25 => "Effect Type",
+        0x26 => "ET Constant Force",
+        0x27 => "ET Ramp",
+        0x28 => "ET Custom Force Data",
+        0x30 => "ET Square",
+        0x31 => "ET Sine",
+        0x32 => "ET Triangle",
+        0x33 => "ET Sawtooth Up",
+        0x34 => "ET Sawtooth Down",
+        0x40 => "ET Spring",
+        0x41 => "ET Damper",
+        0x42 => "ET Inertia",
+        0x43 => "ET Friction",
+        0x50 => "Duration",
+        0x51 => "Sample Period",
+        0x52 => "Gain",
+        0x53 => "Trigger Button",
+        0x54 => "Trigger Repeat Interval",
+        0x55 => "Axes Enable",
+        0x56 => "Direction Enable",
+        0x57 => "Direction",
+        0x58 => "Type Specific Block Offset",
+        0x59 => "Block Type",
+        0x5A => "Set Envelope Report",
+        0x5B => "Attack Level",
+        0x5C => "Attack Time",
+        0x5D => "Fade Level",
+        0x5E => "Fade Time",
+        0x5F => "Set Condition Report",
+        0x60 => "CP Offset",
+        0x61 => "Positive Coefficient",
+        0x62 => "Negative Coefficient",
+        0x63 => "Positive Saturation",
+        0x64 => "Negative Saturation",
+        0x65 => "Dead Band",
+        0x66 => "Download Force Sample",
+        0x67 => "Isoch Custom Force Enable",
+        0x68 => "Custom Force Data Report",
+        0x69 => "Custom Force Data",
+        0x6A => "Custom Force Vendor Defined Data",
+        0x6B => "Set Custom Force Report",
+        0x6C => "Custom Force Data Offset",
+        0x6D => "Sample Count",
+        0x6E => "Set Periodic Report",
+        0x6F => "Offset",
+        0x70 => "Magnitude",
+        0x71 => "Phase",
+        0x72 => "Period",
+        0x73 => "Set Constant Force Report",
+        0x74 => "Set Ramp Force Report",
+        0x75 => "Ramp Start",
+        0x76 => "Ramp End",
+        0x77 => "Effect Operation Report",
+        0x78 => "Effect Operation",
+        0x79 => "Op Effect Start",
+        0x7A => "Op Effect Start Solo",
+        0x7B => "Op Effect Stop",
+        0x7C => "Loop Count",
+        0x7D => "Device Gain Report",
+        0x7E => "Device Gain",
+        0x7F => "PID Pool Report",
+        0x80 => "RAM Pool Size",
+        0x81 => "ROM Pool Size",
+        0x82 => "ROM Effect Block Count",
+        0x83 => "Simultaneous Effects Max",
+        0x84 => "Pool Alignment",
+        0x85 => "PID Pool Move Report",
+        0x86 => "Move Source",
+        0x87 => "Move Destination",
+        0x88 => "Move Length",
+        0x89 => "PID Block Load Report",
+        0x8B => "Block Load Status",
+        0x8C => "Block Load Success",
+        0x8D => "Block Load Full",
+        0x8E => "Block Load Error",
+        0x8F => "Block Handle",
+        0x90 => "PID Block Free Report",
+        0x91 => "Type Specific Block Handle",
+        0x92 => "PID State Report",
+        0x94 => "Effect Playing",
+        0x95 => "PID Device Control Report",
+        0x96 => "PID Device Control",
+        0x97 => "DC Enable Actuators",
+        0x98 => "DC Disable Actuators",
+        0x99 => "DC Stop All Effects",
+        0x9A => "DC Device Reset",
+        0x9B => "DC Device Pause",
+        0x9C => "DC Device Continue",
+        0x9F => "Device Paused",
+        0xA0 => "Actuators Enabled",
+        0xA4 => "Safety Switch",
+        0xA5 => "Actuator Override Switch",
+        0xA6 => "Actuator Power",
+        0xA7 => "Start Delay",
+        0xA8 => "Parameter Block Size",
+        0xA9 => "Device Managed Pool",
+        0xAA => "Shared Parameter Blocks",
+        0xAB => "Create New Effect Report",
+        0xAC => "RAM Pool Available",
+        _ => return None,
+    })
+}
+
+fn generic_desktop_usage_name(id: u16) -> Option<&'static str> {
+    Some(match id {
+        0x01 => "Pointer",
+        0x04 => "Joystick",
+        0x05 => "Game Pad",
+        0x08 => "Multi-axis Controller",
+        0x30 => "X",
+        0x31 => "Y",
+        0x32 => "Z",
+        0x33 => "Rx",
+        0x34 => "Ry",
+        0x35 => "Rz",
+        0x36 => "Slider",
+        0x37 => "Dial",
+        0x38 => "Wheel",
+        0x39 => "Hat Switch",
+        _ => return None,
+    })
+}
+
+fn simulation_usage_name(id: u16) -> Option<&'static str> {
+    Some(match id {
+        0xBA => "Rudder",
+        0xBB => "Throttle",
+        0xC4 => "Accelerator",
+        0xC5 => "Brake",
+        0xC6 => "Clutch",
+        0xC8 => "Steering",
+        _ => return None,
+    })
+}
@@ -0,0 +1,245 @@
+//! Run history storage and trend detection
+//!
+//! Every record/compare/test run appends a one-line JSON summary to
+//! `runs/.history/runs.jsonl`; the `history` command reads it back, per scenario
+//! and driver.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Default location of the history store
+pub fn default_history_path() -> PathBuf {
+    PathBuf::from("runs").join(".history").join("runs.jsonl")
+}
+
+/// Summary of a single run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunRecord {
+    /// Unix timestamp (seconds) when the run finished
+    pub timestamp: u64,
+    /// Subcommand that produced the run: record, compare or test
+    pub command: String,
+    /// Scenario name
+    pub scenario: String,
+    /// Driver name
+    pub driver: String,
+    /// Compared steps, or played steps of a run that was not compared
+    pub total_steps: usize,
+    /// Steps that differ from the expected capture, or failed their `expect` rules
+    pub mismatched_steps: usize,
+    /// Packets of all steps
+    pub total_packets: usize,
+    /// Wall-clock duration of the playback (ms)
+    pub duration_ms: u64,
+}
+
+impl RunRecord {
+    /// No step differed or failed its expectations
+    pub fn passed(&self) -> bool {
+        self.mismatched_steps == 0
+    }
+
+    /// Whether the run was compared with a capture (compare and test runs)
+    /// The mismatched steps of a record run are its failed expectations, so it
+    /// takes no part in pass/fail trends.
+    pub fn is_comparison(&self) -> bool {
+        matches!(self.command.as_str(), "compare" | "test")
+    }
+}
+
+/// Current time as Unix timestamp in seconds
+pub fn now_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Format a Unix timestamp as "YYYY-MM-DD HH:MM:SS" (UTC)
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let secs = timestamp % 86400;
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60
+    )
+}
+
+/// Append a run record to the history store
+pub fn append(path: &Path, record: &RunRecord) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+    Ok(())
+}
+
+/// Append a run record, warning instead of failing the run on errors
+pub fn append_or_warn(record: &RunRecord) {
+    if let Err(e) = append(&default_history_path(), record) {
+        eprintln!("WARNING: Failed to save run history: {}", e);
+    }
+}
+
+/// Load all run records, skipping malformed lines
+pub fn load(path: &Path) -> anyhow::Result<Vec<RunRecord>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let reader = BufReader::new(fs::File::open(path)?);
+    let mut records = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Ok(record) = serde_json::from_str::<RunRecord>(&line) {
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+/// Index of the run where the current failing streak started, in comparison runs
+/// of one scenario and driver
+/// Returns None if the latest run passed or the scenario never passed
+pub fn failing_since(runs: &[&RunRecord]) -> Option<usize> {
+    let last = runs.last()?;
+    if last.passed() {
+        return None;
+    }
+    let last_pass = runs.iter().rposition(|r| r.passed())?;
+    Some(last_pass + 1)
+}
+
+/// Scenario and driver pairs in order of their first run, optionally only those of `scenario`
+/// Runs of one scenario on different drivers are not comparable, so each pair has its own trend.
+fn targets<'a>(records: &'a [RunRecord], scenario: Option<&str>) -> Vec<(&'a str, &'a str)> {
+    let mut targets: Vec<(&str, &str)> = Vec::new();
+    for record in records {
+        let target = (record.scenario.as_str(), record.driver.as_str());
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+    if let Some(filter) = scenario {
+        targets.retain(|(s, _)| *s == filter);
+    }
+    targets
+}
+
+/// Runs of `scenario` on `driver`, and those of them that were compared with a capture
+fn target_runs<'a>(records: &'a [RunRecord], scenario: &str, driver: &str) -> (Vec<&'a RunRecord>, Vec<&'a RunRecord>) {
+    let runs: Vec<&RunRecord> = records
+        .iter()
+        .filter(|r| r.scenario == scenario && r.driver == driver)
+        .collect();
+    let comparisons = runs.iter().copied().filter(|r| r.is_comparison()).collect();
+    (runs, comparisons)
+}
+
+/// Per-scenario and driver history in the form of JSON output: the last `limit`
+/// runs and the timestamp the current failing streak started at
+pub fn to_json(records: &[RunRecord], scenario: Option<&str>, limit: usize) -> serde_json::Value {
+    targets(records, scenario)
+        .into_iter()
+        .map(|(name, driver)| {
+            let (runs, comparisons) = target_runs(records, name, driver);
+            let failing_since = failing_since(&comparisons).map(|idx| comparisons[idx].timestamp);
+            serde_json::json!({
+                "scenario": name,
+                "driver": driver,
+                "total_runs": runs.len(),
+                "failing_since": failing_since,
+                "runs": &runs[runs.len().saturating_sub(limit)..],
+            })
+        })
+        .collect()
+}
+
+/// Print per-scenario and driver trends, optionally filtered by scenario name
+/// The trend and the failing streak only count compare and test runs.
+pub fn print_history(records: &[RunRecord], scenario: Option<&str>, limit: usize) {
+    let targets = targets(records, scenario);
+
+    if targets.is_empty() {
+        println!("No run history found");
+        return;
+    }
+
+    for (name, driver) in targets {
+        let (runs, comparisons) = target_runs(records, name, driver);
+        let shown = &runs[runs.len().saturating_sub(limit)..];
+        let trend: String = comparisons[comparisons.len().saturating_sub(limit)..]
+            .iter()
+            .map(|r| if r.passed() { 'P' } else { 'F' })
+            .collect();
+
+        println!("\n=== {} on {} ({} runs) ===", name, driver, runs.len());
+        if trend.is_empty() {
+            println!("Trend: no compare or test runs");
+        } else {
+            println!("Trend (oldest -> newest): {}", trend);
+        }
+
+        for run in shown {
+            let result = if !run.is_comparison() {
+                format!("{:<5} {} steps", "-", run.total_steps)
+            } else {
+                format!(
+                    "{:<5} {}/{} steps differ",
+                    if run.passed() { "PASS" } else { "FAIL" },
+                    run.mismatched_steps,
+                    run.total_steps
+                )
+            };
+            println!(
+                "  {}  {:<8} {}, {} packets, {} ms",
+                format_timestamp(run.timestamp),
+                run.command,
+                result,
+                run.total_packets,
+                run.duration_ms
+            );
+        }
+
+        if let Some(idx) = failing_since(&comparisons) {
+            println!(
+                "  FLAG: failing since {} ({} consecutive failed runs)",
+                format_timestamp(comparisons[idx].timestamp),
+                comparisons.len() - idx
+            );
+        }
+
+        // Flag a change in packet count between the last two runs
+        if let [.., previous, latest] = runs.as_slice() {
+            if previous.total_packets != latest.total_packets {
+                println!(
+                    "  NOTE: packet count changed from {} to {}",
+                    previous.total_packets, latest.total_packets
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! Emergency stop from the keyboard during Record and Play
+//!
+//! Ctrl+C ends the run, which is too slow a reach when the wheel jerks at the rig.
+//! Space or Esc stop every effect on the device at once through the watchdog and
+//! pause the run before its next step; `r` resumes it and Ctrl+C still ends it. The
+//! console listener reads single keys from the terminal without waiting for Enter,
+//! the dashboard (`--tui`) handles the same keys itself.
+
+use crate::cancel::CancellationToken;
+use crate::watchdog;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How long a key read waits before the listener checks whether to stop
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Stop all effects right away and pause the run of `cancel`
+pub fn emergency_stop(cancel: &CancellationToken) {
+    watchdog::stop_all();
+    cancel.pause();
+}
+
+/// Keyboard listener thread; dropping it stops the thread and restores the terminal
+pub struct Listener {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Put the terminal back in the mode it had before `listen`, for exits that skip
+/// the listener's drop: the forced exit on a second Ctrl+C and panics
+pub fn restore_terminal() {
+    platform::restore();
+}
+
+/// Listen for the emergency stop keys while the returned listener lives
+/// None if stdin is not a terminal or the platform has no key reader.
+pub fn listen(cancel: &CancellationToken) -> Option<Listener> {
+    let mut keys = platform::Keys::open()?;
+    let stop = Arc::new(AtomicBool::new(false));
+    let cancel = cancel.clone();
+    let thread = thread::Builder::new()
+        .name("hotkey".to_string())
+        .spawn({
+            let stop = stop.clone();
+            move || {
+                while !stop.load(Ordering::Relaxed) && !cancel.is_cancelled() {
+                    match keys.read(READ_TIMEOUT) {
+                        Some(' ' | '\u{1b}') if !cancel.is_paused() => {
+                            emergency_stop(&cancel);
+                            eprintln!(
+                                "EMERGENCY STOP: effects stopped, the run pauses before its next step \
+                                 (r: resume, Ctrl+C: stop)"
+                            );
+                        }
+                        Some('r' | 'R') if cancel.is_paused() => {
+                            cancel.resume();
+                            eprintln!("Resuming");
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        })
+        .ok()?;
+    Some(Listener {
+        stop,
+        thread: Some(thread),
+    })
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    //! Non-canonical terminal input: keys arrive one at a time, unechoed, while
+    //! signals like Ctrl+C keep working
+
+    use std::io::IsTerminal;
+    use std::mem::MaybeUninit;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    const STDIN: libc::c_int = 0;
+
+    /// Mode the terminal had before `Keys::open`, kept outside `Keys` so that
+    /// exits which never drop it can still restore the terminal
+    static SAVED: Mutex<Option<libc::termios>> = Mutex::new(None);
+
+    /// Restore the saved mode, once
+    pub fn restore() {
+        let saved = SAVED.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(saved) = saved {
+            // SAFETY: the mode saved in `Keys::open`
+            unsafe { libc::tcsetattr(STDIN, libc::TCSANOW, &saved) };
+        }
+    }
+
+    /// Terminal in key mode; the previous mode is restored on drop
+    pub struct Keys;
+
+    impl Keys {
+        pub fn open() -> Option<Self> {
+            if !std::io::stdin().is_terminal() {
+                return None;
+            }
+            let mut saved = MaybeUninit::<libc::termios>::uninit();
+            // SAFETY: tcgetattr fills the struct on success
+            let saved = unsafe {
+                if libc::tcgetattr(STDIN, saved.as_mut_ptr()) != 0 {
+                    return None;
+                }
+                saved.assume_init()
+            };
+            let mut keys = saved;
+            keys.c_lflag &= !(libc::ICANON | libc::ECHO);
+            keys.c_cc[libc::VMIN] = 0;
+            keys.c_cc[libc::VTIME] = 0;
+            // SAFETY: a valid termios for the terminal on stdin
+            if unsafe { libc::tcsetattr(STDIN, libc::TCSANOW, &keys) } != 0 {
+                return None;
+            }
+            *SAVED.lock().unwrap_or_else(|e| e.into_inner()) = Some(saved);
+            Some(Self)
+        }
+
+        /// Next key, None if none was pressed within `timeout`
+        pub fn read(&mut self, timeout: Duration) -> Option<char> {
+            let mut poll = libc::pollfd {
+                fd: STDIN,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: one valid pollfd
+            if unsafe { libc::poll(&mut poll, 1, timeout.as_millis() as libc::c_int) } <= 0 {
+                return None;
+            }
+            let mut byte = 0u8;
+            // SAFETY: reads at most one byte into `byte`
+            match unsafe { libc::read(STDIN, (&mut byte as *mut u8).cast(), 1) } {
+                1 => Some(byte as char),
+                _ => None,
+            }
+        }
+    }
+
+    impl Drop for Keys {
+        fn drop(&mut self) {
+            restore();
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    //! Console input records: key events arrive without Enter whatever the console
+    //! mode, so it is left as it is
+
+    use std::io::IsTerminal;
+    use std::time::Duration;
+    use windows_sys::Win32::Foundation::{HANDLE, WAIT_OBJECT_0};
+    use windows_sys::Win32::System::Console::{
+        GetStdHandle, ReadConsoleInputW, INPUT_RECORD, KEY_EVENT, STD_INPUT_HANDLE,
+    };
+    use windows_sys::Win32::System::Threading::WaitForSingleObject;
+
+    const VK_ESCAPE: u16 = 0x1b;
+
+    /// The console mode is never changed
+    pub fn restore() {}
+
+    pub struct Keys {
+        input: HANDLE,
+    }
+
+    // The console input handle is process-wide
+    unsafe impl Send for Keys {}
+
+    impl Keys {
+        pub fn open() -> Option<Self> {
+            if !std::io::stdin().is_terminal() {
+                return None;
+            }
+            // SAFETY: no preconditions
+            let input = unsafe { GetStdHandle(STD_INPUT_HANDLE) };
+            (!input.is_null()).then_some(Self { input })
+        }
+
+        /// Next key press, None if none came within `timeout`
+        pub fn read(&mut self, timeout: Duration) -> Option<char> {
+            // SAFETY: a valid console input handle; the record is plain data
+            unsafe {
+                if WaitForSingleObject(self.input, timeout.as_millis() as u32) != WAIT_OBJECT_0 {
+                    return None;
+                }
+                let mut record: INPUT_RECORD = std::mem::zeroed();
+                let mut read = 0;
+                if ReadConsoleInputW(self.input, &mut record, 1, &mut read) == 0 || read == 0 {
+                    return None;
+                }
+                if record.EventType != KEY_EVENT as u16 || record.Event.KeyEvent.bKeyDown == 0 {
+                    return None;
+                }
+                let key = record.Event.KeyEvent;
+                if key.wVirtualKeyCode == VK_ESCAPE {
+                    return Some('\u{1b}');
+                }
+                char::from_u32(key.uChar.UnicodeChar as u32)
+            }
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+mod platform {
+    use std::time::Duration;
+
+    pub struct Keys;
+
+    pub fn restore() {}
+
+    impl Keys {
+        pub fn open() -> Option<Self> {
+            None
+        }
+
+        pub fn read(&mut self, _timeout: Duration) -> Option<char> {
+            None
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Import of USB captures made with Wireshark
+//!
+//! The `import` command reads a pcap or pcapng file (usbmon or USBPcap link type),
+//! splits the selected packets into steps at pauses in the traffic and saves them
+//! as a capture file. Optionally it also writes a scenario candidate with one step
+//! per burst, its duration taken from the capture and the decoded commands listed
+//! as comments, to be completed by hand into a scenario that reproduces the capture.
+//! `virtual-device` saves the reports a game sends the same way.
+
+use crate::cadence;
+use crate::hid_descriptor::ReportDescriptor;
+use crate::normalize::decode_packet_with;
+use crate::usb_monitor::{self, format_hex, PacketFilter, UsbPacket};
+use crate::{write_capture_file, StepOutput};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Duration of the last step of a scenario candidate, which has no following burst
+const LAST_STEP_MS: u64 = 1000;
+
+/// Packets of the capture, split into bursts separated by at least `gap`
+pub fn split_bursts(packets: Vec<UsbPacket>, gap: Duration) -> Vec<Vec<UsbPacket>> {
+    let mut bursts: Vec<Vec<UsbPacket>> = Vec::new();
+    for packet in packets {
+        match bursts.last_mut() {
+            Some(burst) if packet.timestamp.saturating_sub(burst.last().unwrap().timestamp) < gap => burst.push(packet),
+            _ => bursts.push(vec![packet]),
+        }
+    }
+    bursts
+}
+
+/// Import `input` into `output`, and a scenario candidate into `scenario` if given
+/// Returns the steps written
+pub fn run(
+    input: &Path,
+    output: &PathBuf,
+    scenario: Option<&Path>,
+    filter: &PacketFilter,
+    gap: Duration,
+) -> anyhow::Result<Vec<StepOutput>> {
+    let packets = usb_monitor::read_pcap_file(input, filter).map_err(anyhow::Error::msg)?;
+    if packets.is_empty() {
+        anyhow::bail!("{} contains no packets passing the packet filter", input.display());
+    }
+    let bursts = split_bursts(packets, gap);
+    let steps = burst_steps(&bursts);
+    let total_packets = write_capture_file(output, &steps)?;
+    println!(
+        "Imported {} packets in {} steps from {} to {}",
+        total_packets,
+        steps.len(),
+        input.display(),
+        output.display()
+    );
+
+    if let Some(path) = scenario {
+        let name = input.file_stem().unwrap_or_default().to_string_lossy();
+        fs::write(path, scenario_candidate(&name, &input.display().to_string(), &bursts, None))?;
+        println!("Scenario candidate written to {}", path.display());
+    }
+    Ok(steps)
+}
+
+/// One capture step per burst, named by its start relative to the first burst
+pub fn burst_steps(bursts: &[Vec<UsbPacket>]) -> Vec<StepOutput> {
+    let Some(start) = bursts.first().map(|burst| burst[0].timestamp) else {
+        return Vec::new();
+    };
+    bursts
+        .iter()
+        .enumerate()
+        .map(|(i, burst)| StepOutput {
+            step_index: i + 1,
+            step_name: format!("Burst at {:.3} s", (burst[0].timestamp - start).as_secs_f64()),
+            packets: burst.iter().map(|p| format_hex(&p.data)).collect(),
+            variants: Vec::new(),
+            latencies_us: Vec::new(),
+            wire_latency_us: None,
+            timestamps_us: cadence::relative_us(&burst.iter().map(|p| p.timestamp).collect::<Vec<_>>()),
+            axis: Vec::new(),
+            devices: Vec::new(),
+            health: None,
+        })
+        .collect()
+}
+
+/// Scenario YAML with one placeholder step per burst, lasting until the next burst
+/// The packets are listed decoded, with `descriptor` for PID reports if given
+pub fn scenario_candidate(
+    name: &str,
+    source: &str,
+    bursts: &[Vec<UsbPacket>],
+    descriptor: Option<&ReportDescriptor>,
+) -> String {
+    let mut yaml = String::new();
+    let _ = writeln!(yaml, "# Scenario candidate imported from {}: one step per burst of packets.", source);
+    let _ = writeln!(yaml, "# Replace each placeholder effect with the one its decoded commands describe.");
+    let _ = writeln!(yaml, "name: {:?}", name);
+    let _ = writeln!(yaml, "description: {:?}", format!("Imported from {}", source));
+    let _ = writeln!(yaml, "loop_forever: false");
+    let _ = writeln!(yaml, "repeat_count: 1");
+    let _ = writeln!(yaml);
+    let _ = writeln!(yaml, "steps:");
+    for (i, burst) in bursts.iter().enumerate() {
+        let duration_ms = match bursts.get(i + 1) {
+            Some(next) => (next[0].timestamp - burst[0].timestamp).as_millis() as u64,
+            None => LAST_STEP_MS,
+        };
+        let _ = writeln!(yaml, "  # Step {}: {} packets", i + 1, burst.len());
+        for packet in burst {
+            let text = decode_packet_with(&packet.data, descriptor).unwrap_or_else(|| format_hex(&packet.data));
+            let _ = writeln!(yaml, "  #   {}", text);
+        }
+        let _ = writeln!(yaml, "  - effect:");
+        let _ = writeln!(yaml, "      type: constant");
+        let _ = writeln!(yaml, "      duration: {}", duration_ms);
+        let _ = writeln!(yaml, "      magnitude: 0");
+        let _ = writeln!(yaml);
+    }
+    yaml
+}
@@ -0,0 +1,50 @@
+//! Project scaffolding: example scenarios and a default config
+//!
+//! The presets are embedded in the binary, so `init` works from any directory.
+
+use crate::config::CONFIG_FILE;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Files written by `init`: path relative to the target directory and content
+const PRESETS: &[(&str, &str)] = &[
+    ("scenarios/constant_sweep.yaml", include_str!("../presets/constant_sweep.yaml")),
+    ("scenarios/sine_test.yaml", include_str!("../presets/sine_test.yaml")),
+    ("scenarios/spring_damper.yaml", include_str!("../presets/spring_damper.yaml")),
+    (CONFIG_FILE, include_str!("../presets/ffb_replay.yaml")),
+];
+
+/// Files written and kept by `init`
+#[derive(Debug, Default, Serialize)]
+pub struct InitSummary {
+    pub created: Vec<PathBuf>,
+    pub skipped: Vec<PathBuf>,
+}
+
+/// Write the presets into `dir`; existing files are kept unless `force` is set
+pub fn run(dir: &Path, force: bool) -> anyhow::Result<InitSummary> {
+    let mut summary = InitSummary::default();
+    for (name, content) in PRESETS {
+        let path = dir.join(name);
+        if path.exists() && !force {
+            println!("Skipped {} (exists, use --force to overwrite)", path.display());
+            summary.skipped.push(path);
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, content)?;
+        println!("Created {}", path.display());
+        summary.created.push(path);
+    }
+
+    println!(
+        "\n{} file(s) written, {} skipped. Try:\n  ffb_replay play --scenario {}",
+        summary.created.len(),
+        summary.skipped.len(),
+        Path::new("scenarios").join("constant_sweep.yaml").display()
+    );
+    Ok(summary)
+}
@@ -0,0 +1,96 @@
+//! Per-packet completion latency and per-step wire latency stored in capture files
+//!
+//! A step's latencies are written as one comment line after its packets,
+//! in packet order: `# Latency us: 125 130 - 98` (`-` where not measured).
+//!
+//! The wire latency of a step is the time from submitting its effect to the
+//! driver stack to the first OUT packet on the USB bus, the overhead of SDL and
+//! the OS drivers: `# Wire latency us: 850`.
+
+use crate::StepOutput;
+use serde::Serialize;
+
+/// Prefix of the latency line in capture files
+pub const LATENCY_PREFIX: &str = "# Latency us:";
+
+/// Prefix of the wire latency line in capture files
+pub const WIRE_LATENCY_PREFIX: &str = "# Wire latency us:";
+
+/// Format the latency line of a step
+pub fn format_line(latencies: &[Option<u64>]) -> String {
+    let values: Vec<String> = latencies
+        .iter()
+        .map(|l| l.map_or_else(|| "-".to_string(), |us| us.to_string()))
+        .collect();
+    format!("{} {}", LATENCY_PREFIX, values.join(" "))
+}
+
+/// Parse a latency line, None if the line is not one
+pub fn parse_line(line: &str) -> Option<Vec<Option<u64>>> {
+    let values = line.strip_prefix(LATENCY_PREFIX)?;
+    Some(values.split_whitespace().map(|v| v.parse().ok()).collect())
+}
+
+/// Min/avg/max summary of the measured latencies, None if nothing was measured
+pub fn summary(latencies: &[Option<u64>]) -> Option<String> {
+    let measured: Vec<u64> = latencies.iter().flatten().copied().collect();
+    let min = measured.iter().min()?;
+    let max = measured.iter().max()?;
+    let avg = measured.iter().sum::<u64>() as f64 / measured.len() as f64;
+    Some(format!(
+        "min {} us, avg {:.0} us, max {} us ({} of {} packets measured)",
+        min,
+        avg,
+        max,
+        measured.len(),
+        latencies.len()
+    ))
+}
+
+/// Format the wire latency line of a step
+pub fn format_wire_line(latency_us: u64) -> String {
+    format!("{} {}", WIRE_LATENCY_PREFIX, latency_us)
+}
+
+/// Parse a wire latency line, None if the line is not one
+pub fn parse_wire_line(line: &str) -> Option<u64> {
+    line.strip_prefix(WIRE_LATENCY_PREFIX)?.trim().parse().ok()
+}
+
+/// Distribution of the wire latency over the steps of a run
+#[derive(Debug, Clone, Serialize)]
+pub struct WireLatencyDistribution {
+    pub min_us: u64,
+    pub median_us: u64,
+    pub p95_us: u64,
+    pub max_us: u64,
+    pub mean_us: f64,
+    pub measured_steps: usize,
+    pub total_steps: usize,
+}
+
+impl WireLatencyDistribution {
+    /// Distribution over `steps`, None if no step was measured
+    pub fn of(steps: &[StepOutput]) -> Option<Self> {
+        let mut measured: Vec<u64> = steps.iter().filter_map(|s| s.wire_latency_us).collect();
+        measured.sort_unstable();
+        // Nearest-rank percentile
+        let percentile = |p: usize| measured[(measured.len() * p).div_ceil(100).saturating_sub(1)];
+        Some(Self {
+            min_us: *measured.first()?,
+            median_us: percentile(50),
+            p95_us: percentile(95),
+            max_us: *measured.last()?,
+            mean_us: measured.iter().sum::<u64>() as f64 / measured.len() as f64,
+            measured_steps: measured.len(),
+            total_steps: steps.len(),
+        })
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "min {} us, median {} us, p95 {} us, max {} us, mean {:.0} us ({} of {} steps measured)",
+            self.min_us, self.median_us, self.p95_us, self.max_us, self.mean_us, self.measured_steps, self.total_steps
+        )
+    }
+}
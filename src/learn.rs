@@ -0,0 +1,640 @@
+//! Scenarios learned from a game's live FFB traffic
+//!
+//! The `learn` command captures what a game sends to the real wheel, decodes every
+//! packet and follows the effect slots: the effect type and duration from set effect
+//! commands, magnitudes and periodic, ramp, envelope and condition parameters from
+//! their own commands, and which slots play from start and stop commands. Wheels
+//! without effect slots, which stream forces, play whatever was set last. Packets are
+//! decoded with the PID report descriptor when there is one, and otherwise by the
+//! vendor decoders through the `source` fields of their specs.
+//!
+//! Scenarios play one effect at a time, so of several slots playing together the
+//! most recently started one is kept. A step is cut wherever the playing effect
+//! changes, pauses become idle steps of zero force, and steps that are shorter than
+//! `min_step` or within `tolerance` of the one before are merged into it. The result
+//! is a starting point that reproduces the sequence and timing, not an exact replica.
+
+use crate::cancel::CancellationToken;
+use crate::effects::{
+    ConditionEffect, ConditionParams, ConditionType, ConstantForce, Direction, Effect, EffectParams, Envelope,
+    PeriodicEffect, RampEffect, WaveType,
+};
+use crate::error::FFBError;
+use crate::hid_descriptor::{ReportDescriptor, ReportKind, Usage, PID_PAGE};
+use crate::monitor;
+use crate::normalize::{decode_command, strip_setup};
+use crate::schedule::TimingMode;
+use crate::usb_monitor::{capture_clock, CaptureConfig, PacketDirection, UsbMonitor};
+use crate::{Scenario, ScenarioStep};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How often captured packets are collected
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Full scale of scenario forces and levels
+const FULL_SCALE: f64 = 10000.0;
+
+/// PID usages of the reports followed
+const EFFECT_BLOCK_INDEX: u16 = 0x22;
+const PARAMETER_BLOCK_OFFSET: u16 = 0x23;
+const SET_EFFECT_REPORT: u16 = 0x21;
+const DURATION: u16 = 0x50;
+const GAIN: u16 = 0x52;
+const SET_ENVELOPE_REPORT: u16 = 0x5A;
+const ATTACK_LEVEL: u16 = 0x5B;
+const ATTACK_TIME: u16 = 0x5C;
+const FADE_LEVEL: u16 = 0x5D;
+const FADE_TIME: u16 = 0x5E;
+const SET_CONDITION_REPORT: u16 = 0x5F;
+const CP_OFFSET: u16 = 0x60;
+const POSITIVE_COEFFICIENT: u16 = 0x61;
+const NEGATIVE_COEFFICIENT: u16 = 0x62;
+const POSITIVE_SATURATION: u16 = 0x63;
+const NEGATIVE_SATURATION: u16 = 0x64;
+const DEAD_BAND: u16 = 0x65;
+const SET_PERIODIC_REPORT: u16 = 0x6E;
+const OFFSET: u16 = 0x6F;
+const MAGNITUDE: u16 = 0x70;
+const PHASE: u16 = 0x71;
+const PERIOD: u16 = 0x72;
+const SET_CONSTANT_FORCE_REPORT: u16 = 0x73;
+const SET_RAMP_FORCE_REPORT: u16 = 0x74;
+const RAMP_START: u16 = 0x75;
+const RAMP_END: u16 = 0x76;
+const EFFECT_OPERATION_REPORT: u16 = 0x77;
+const OP_EFFECT_START: u16 = 0x79;
+const OP_EFFECT_START_SOLO: u16 = 0x7A;
+const OP_EFFECT_STOP: u16 = 0x7B;
+const BLOCK_FREE_REPORT: u16 = 0x90;
+const DEVICE_CONTROL_REPORT: u16 = 0x96;
+const DC_STOP_ALL_EFFECTS: u16 = 0x99;
+const DC_DEVICE_RESET: u16 = 0x9A;
+
+fn pid(id: u16) -> Usage {
+    Usage { page: PID_PAGE, id }
+}
+
+/// How learned steps are simplified
+#[derive(Debug, Clone)]
+pub struct LearnOptions {
+    /// Steps shorter than this are merged into the step before
+    pub min_step: Duration,
+    /// Largest difference between the values of two steps (scenario units) that
+    /// still merges them
+    pub tolerance: f64,
+    /// Factor from vendor-decoded values to scenario units (PID values are scaled
+    /// by their logical range)
+    pub magnitude_scale: f64,
+}
+
+/// Counts of a learning session
+#[derive(Debug, Clone, Serialize)]
+pub struct LearnSummary {
+    /// Packets sent to the wheel
+    pub packets: usize,
+    /// Packets that decoded to an effect command
+    pub decoded: usize,
+    pub steps: usize,
+    /// Total duration of the steps (ms)
+    pub duration_ms: u64,
+}
+
+/// Learn a scenario from the traffic to the first capture device and write it to
+/// `path`, until `duration` elapses or `cancel` is cancelled
+/// PID reports are decoded with `descriptor`, or else the device's own one
+pub fn run(
+    capture: CaptureConfig,
+    descriptor: Option<ReportDescriptor>,
+    path: &Path,
+    options: &LearnOptions,
+    duration: Option<Duration>,
+    cancel: &CancellationToken,
+) -> anyhow::Result<LearnSummary> {
+    let Some(device) = capture.devices.first().copied() else {
+        anyhow::bail!("learn needs the wheel the game drives: pass --device VID:PID");
+    };
+    let descriptor = descriptor.or_else(|| monitor::device_descriptor(&capture));
+    let mut monitor = UsbMonitor::with_config(capture);
+    monitor.set_cancellation(cancel.clone());
+    monitor
+        .start_capture()
+        .map_err(|e| FFBError::InitializationFailed(format!("Failed to start USB capture: {}", e)))?;
+    let started = Instant::now();
+
+    match duration {
+        Some(duration) => println!("Learning from the traffic to {} for {} s...", device, duration.as_secs()),
+        None => println!("Learning from the traffic to {} (Ctrl+C to stop)...", device),
+    }
+
+    let mut learner = Learner::default();
+    let mut packets = 0;
+    while duration.is_none_or(|duration| started.elapsed() < duration) && cancel.sleep(POLL_INTERVAL) {
+        for packet in monitor.get_packets().map_err(FFBError::capture)? {
+            if packet.direction != PacketDirection::HostToDevice {
+                continue;
+            }
+            packets += 1;
+            if let Some(update) = decode(&packet.data, descriptor.as_ref(), options.magnitude_scale) {
+                learner.apply(packet.timestamp, update);
+            }
+        }
+    }
+    let end = capture_clock();
+    monitor.stop_capture();
+    println!("Capture total: {}", monitor.stats().describe(started.elapsed()));
+
+    let steps = learner.steps(end, options);
+    if steps.is_empty() {
+        anyhow::bail!(
+            "No effects learned from {} packets ({} decoded); PID wheels need a readable report descriptor \
+             (--hid-descriptor), other wheels a decoder (--decoder)",
+            packets,
+            learner.decoded
+        );
+    }
+    let summary = LearnSummary {
+        packets,
+        decoded: learner.decoded,
+        steps: steps.len(),
+        duration_ms: steps.iter().map(|step| step.effect.duration() as u64).sum(),
+    };
+    let scenario = Scenario {
+        name: path.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+        description: format!("Learned from the game traffic to {}", device),
+        loop_forever: false,
+        repeat_count: 1,
+        timing: TimingMode::default(),
+        health_check: false,
+        steps,
+    };
+    let header = format!(
+        "# Learned from {} of {} packets sent to {}: a best-effort reproduction of the\n\
+         # effect sequence and timing, one effect at a time. Review before use.\n",
+        summary.decoded, summary.packets, device
+    );
+    fs::write(path, header + &serde_yaml::to_string(&scenario)?)?;
+    println!(
+        "Learned {} steps ({:.1} s) to {}",
+        summary.steps,
+        summary.duration_ms as f64 / 1000.0,
+        path.display()
+    );
+    Ok(summary)
+}
+
+/// What a decoded packet does to the effect slots
+#[derive(Debug, Default)]
+struct Update {
+    slot: i64,
+    /// Effect type, e.g. "Sine"
+    kind: Option<String>,
+    /// Scenario effect fields by path, e.g. "x_axis.dead_band"
+    values: Vec<(String, f64)>,
+    action: Action,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    #[default]
+    Set,
+    Start,
+    /// Start, stopping all other effects
+    StartSolo,
+    Stop,
+    /// Stop and forget the slot
+    Free,
+    StopAll,
+}
+
+/// Update of a packet sent to the wheel, None if it is not an effect command
+fn decode(data: &[u8], descriptor: Option<&ReportDescriptor>, scale: f64) -> Option<Update> {
+    descriptor
+        .and_then(|descriptor| pid_update(descriptor, strip_setup(data)))
+        .or_else(|| vendor_update(data, scale))
+}
+
+/// Update of a PID report, its values scaled from their logical range
+fn pid_update(descriptor: &ReportDescriptor, payload: &[u8]) -> Option<Update> {
+    let (report, body) = [ReportKind::Output, ReportKind::Feature]
+        .iter()
+        .find_map(|kind| descriptor.split(*kind, payload))?;
+    let usage = report.usage.filter(|usage| usage.page == PID_PAGE)?;
+    let value = |id| report.value(body, pid(id));
+    // Value relative to the field's logical maximum, times `full`
+    let scaled = |id, full: f64| {
+        let field = report.fields.iter().find(|f| f.variable && f.usages.contains(&pid(id)))?;
+        Some(value(id)? as f64 * full / field.logical_max.max(1) as f64)
+    };
+    let selected = |ids: &[u16]| {
+        let selections = report.selections(body);
+        selections.iter().find(|u| u.page == PID_PAGE && ids.contains(&u.id)).map(|u| u.id)
+    };
+
+    let mut kind = None;
+    let mut action = Action::Set;
+    let mut values = Vec::new();
+    let mut set = |key: &str, value: Option<f64>| {
+        if let Some(value) = value {
+            values.push((key.to_string(), value));
+        }
+    };
+    match usage.id {
+        SET_EFFECT_REPORT => {
+            // 0xFFFF (or -1 in a signed field) is infinite
+            set("duration", value(DURATION).map(|d| if (0..0xFFFF).contains(&d) { d as f64 } else { 0.0 }));
+            set("gain", scaled(GAIN, FULL_SCALE));
+            kind = report.selections(body).iter().find_map(|u| pid_effect_kind(*u)).map(str::to_string);
+        }
+        SET_ENVELOPE_REPORT => {
+            set("envelope.attack_level", scaled(ATTACK_LEVEL, FULL_SCALE));
+            set("envelope.attack_time", value(ATTACK_TIME).map(|t| t as f64));
+            set("envelope.fade_level", scaled(FADE_LEVEL, FULL_SCALE));
+            set("envelope.fade_time", value(FADE_TIME).map(|t| t as f64));
+        }
+        // Only the first axis, the wheel's
+        SET_CONDITION_REPORT if value(PARAMETER_BLOCK_OFFSET).unwrap_or(0) == 0 => {
+            set("x_axis.offset", scaled(CP_OFFSET, FULL_SCALE));
+            set("x_axis.positive_coefficient", scaled(POSITIVE_COEFFICIENT, FULL_SCALE));
+            set("x_axis.negative_coefficient", scaled(NEGATIVE_COEFFICIENT, FULL_SCALE));
+            set("x_axis.positive_saturation", scaled(POSITIVE_SATURATION, FULL_SCALE));
+            set("x_axis.negative_saturation", scaled(NEGATIVE_SATURATION, FULL_SCALE));
+            set("x_axis.dead_band", scaled(DEAD_BAND, FULL_SCALE));
+        }
+        SET_PERIODIC_REPORT => {
+            set("magnitude", scaled(MAGNITUDE, FULL_SCALE));
+            set("offset", scaled(OFFSET, FULL_SCALE));
+            set("phase", scaled(PHASE, 36000.0));
+            set("period", value(PERIOD).map(|p| p as f64));
+        }
+        SET_CONSTANT_FORCE_REPORT => set("magnitude", scaled(MAGNITUDE, FULL_SCALE)),
+        SET_RAMP_FORCE_REPORT => {
+            set("start_magnitude", scaled(RAMP_START, FULL_SCALE));
+            set("end_magnitude", scaled(RAMP_END, FULL_SCALE));
+        }
+        EFFECT_OPERATION_REPORT => {
+            action = match selected(&[OP_EFFECT_START, OP_EFFECT_START_SOLO, OP_EFFECT_STOP])? {
+                OP_EFFECT_START => Action::Start,
+                OP_EFFECT_START_SOLO => Action::StartSolo,
+                _ => Action::Stop,
+            };
+        }
+        BLOCK_FREE_REPORT => action = Action::Free,
+        DEVICE_CONTROL_REPORT => {
+            selected(&[DC_STOP_ALL_EFFECTS, DC_DEVICE_RESET])?;
+            action = Action::StopAll;
+        }
+        _ => return None,
+    }
+    Some(Update {
+        slot: value(EFFECT_BLOCK_INDEX).unwrap_or(0),
+        kind,
+        values,
+        action,
+    })
+}
+
+/// Effect type of a PID effect type usage
+fn pid_effect_kind(usage: Usage) -> Option<&'static str> {
+    if usage.page != PID_PAGE {
+        return None;
+    }
+    Some(match usage.id {
+        0x26 => "Constant",
+        0x27 => "Ramp",
+        0x30 => "Square",
+        0x31 => "Sine",
+        0x32 => "Triangle",
+        0x33 => "SawtoothUp",
+        0x34 => "SawtoothDown",
+        0x40 => "Spring",
+        0x41 => "Damper",
+        0x42 => "Inertia",
+        0x43 => "Friction",
+        _ => return None,
+    })
+}
+
+/// Update of a packet a vendor decoder recognizes, from the fields with a scenario
+/// source; the slot is the field named "slot" or "effect"
+fn vendor_update(data: &[u8], scale: f64) -> Option<Update> {
+    let command = decode_command(data)?;
+    let action = match command.operation.as_str() {
+        "start" => Action::Start,
+        "stop" => Action::Stop,
+        "set effect" | "set magnitude" | "set periodic" | "set envelope" | "set condition" => Action::Set,
+        _ => return None,
+    };
+    // Decoders naming a command after its effect, e.g. Spring, carry no type field
+    let mut update = Update {
+        action,
+        kind: known_kind(&command.name),
+        ..Default::default()
+    };
+    for field in &command.fields {
+        match (field.name.as_str(), field.source.as_deref(), field.number) {
+            ("slot" | "effect", None, Some(number)) => update.slot = number as i64,
+            (_, Some(source), _) if source.ends_with("type") => {
+                update.kind = known_kind(&field.value).or(update.kind);
+            }
+            (_, Some(source), Some(number)) => {
+                let number = if is_time(source) { number } else { number * scale };
+                update.values.push((source.to_string(), number));
+            }
+            _ => {}
+        }
+    }
+    Some(update)
+}
+
+/// Effect type named by `name`, ignoring case, spaces and underscores
+fn known_kind(name: &str) -> Option<String> {
+    const KINDS: [&str; 11] = [
+        "Constant", "Ramp", "Sine", "Square", "Triangle", "SawtoothUp", "SawtoothDown", "Spring", "Damper", "Friction",
+        "Inertia",
+    ];
+    let name: String = name.chars().filter(|c| c.is_alphanumeric()).collect();
+    KINDS.iter().find(|kind| kind.eq_ignore_ascii_case(&name)).map(|kind| kind.to_string())
+}
+
+/// Effect fields in milliseconds or degrees, which no magnitude scale applies to
+fn is_time(source: &str) -> bool {
+    matches!(source, "duration" | "period" | "phase") || source.ends_with("_time")
+}
+
+/// Effect set up in one slot
+#[derive(Debug, Default)]
+struct Slot {
+    kind: Option<String>,
+    values: BTreeMap<String, f64>,
+    /// Capture time it was started at, None while stopped
+    started: Option<Duration>,
+}
+
+impl Slot {
+    /// When a started effect of finite duration ends
+    fn end(&self) -> Option<Duration> {
+        let duration = *self.values.get("duration").filter(|d| **d > 0.0)?;
+        Some(self.started? + Duration::from_millis(duration as u64))
+    }
+
+    fn playing_at(&self, at: Duration) -> bool {
+        self.started.is_some() && self.end().is_none_or(|end| at < end)
+    }
+
+    /// Scenario effect of the slot, its duration left 0; None for unknown types
+    fn effect(&self) -> Option<Effect> {
+        let value = |key: &str, default: f64| self.values.get(key).copied().unwrap_or(default);
+        let force = |key: &str| value(key, 0.0).round().clamp(-FULL_SCALE, FULL_SCALE) as i16;
+        let level = |key: &str, default: f64| value(key, default).round().clamp(0.0, FULL_SCALE) as u16;
+        let time = |key: &str| value(key, 0.0).round().max(0.0) as u32;
+        let params = EffectParams {
+            duration: 0,
+            start_delay: 0,
+            gain: level("gain", FULL_SCALE),
+        };
+        let envelope = Envelope {
+            attack_time: time("envelope.attack_time"),
+            attack_level: level("envelope.attack_level", 0.0),
+            fade_time: time("envelope.fade_time"),
+            fade_level: level("envelope.fade_level", 0.0),
+        };
+        let wave_type = match self.kind.as_deref()? {
+            "Constant" => {
+                return Some(Effect::Constant {
+                    params,
+                    force: ConstantForce {
+                        magnitude: force("magnitude"),
+                        direction: Direction::default(),
+                        envelope,
+                    },
+                });
+            }
+            "Ramp" => {
+                return Some(Effect::Ramp {
+                    params,
+                    effect: RampEffect {
+                        start_magnitude: force("start_magnitude"),
+                        end_magnitude: force("end_magnitude"),
+                        direction: Direction::default(),
+                        envelope,
+                    },
+                });
+            }
+            "Sine" => WaveType::Sine,
+            "Square" => WaveType::Square,
+            "Triangle" => WaveType::Triangle,
+            "SawtoothUp" => WaveType::SawtoothUp,
+            "SawtoothDown" => WaveType::SawtoothDown,
+            condition => {
+                let condition_type = match condition {
+                    "Spring" => ConditionType::Spring,
+                    "Damper" => ConditionType::Damper,
+                    "Friction" => ConditionType::Friction,
+                    "Inertia" => ConditionType::Inertia,
+                    _ => return None,
+                };
+                let defaults = ConditionParams::default();
+                let x_axis = ConditionParams {
+                    offset: force("x_axis.offset"),
+                    positive_coefficient: value("x_axis.positive_coefficient", defaults.positive_coefficient as f64)
+                        .round()
+                        .clamp(-FULL_SCALE, FULL_SCALE) as i16,
+                    negative_coefficient: value("x_axis.negative_coefficient", defaults.negative_coefficient as f64)
+                        .round()
+                        .clamp(-FULL_SCALE, FULL_SCALE) as i16,
+                    positive_saturation: level("x_axis.positive_saturation", defaults.positive_saturation as f64),
+                    negative_saturation: level("x_axis.negative_saturation", defaults.negative_saturation as f64),
+                    dead_band: level("x_axis.dead_band", 0.0),
+                };
+                return Some(Effect::Condition {
+                    params,
+                    effect: ConditionEffect { condition_type, x_axis },
+                });
+            }
+        };
+        Some(Effect::Periodic {
+            params,
+            effect: PeriodicEffect {
+                wave_type,
+                magnitude: level("magnitude", 0.0),
+                offset: force("offset"),
+                phase: value("phase", 0.0).round().clamp(0.0, 36000.0) as u16,
+                period: time("period").max(1),
+                direction: Direction::default(),
+                envelope,
+            },
+        })
+    }
+}
+
+/// Effect slot state and the effect playing over time
+#[derive(Debug, Default)]
+struct Learner {
+    slots: BTreeMap<i64, Slot>,
+    /// A start command was seen: slots play once started rather than once set
+    explicit_start: bool,
+    /// The playing effect from each change on, by capture time
+    changes: Vec<(Duration, Option<Effect>)>,
+    decoded: usize,
+}
+
+impl Learner {
+    fn apply(&mut self, at: Duration, update: Update) {
+        self.decoded += 1;
+        self.expire(at);
+        match update.action {
+            Action::Set => {
+                let explicit_start = self.explicit_start;
+                let slot = self.slots.entry(update.slot).or_default();
+                if update.kind.is_some() {
+                    slot.kind = update.kind;
+                } else if slot.kind.is_none() && update.values.iter().any(|(key, _)| key == "magnitude") {
+                    slot.kind = Some("Constant".to_string());
+                }
+                slot.values.extend(update.values);
+                // Streamed forces play as soon as they are set
+                if !explicit_start && slot.started.is_none() {
+                    slot.started = Some(at);
+                }
+            }
+            Action::Start | Action::StartSolo => {
+                self.explicit_start = true;
+                if update.action == Action::StartSolo {
+                    self.slots.values_mut().for_each(|slot| slot.started = None);
+                }
+                self.slots.entry(update.slot).or_default().started = Some(at);
+            }
+            Action::Stop => {
+                if let Some(slot) = self.slots.get_mut(&update.slot) {
+                    slot.started = None;
+                }
+            }
+            Action::Free => {
+                self.slots.remove(&update.slot);
+            }
+            Action::StopAll => self.slots.values_mut().for_each(|slot| slot.started = None),
+        }
+        self.record(at);
+    }
+
+    /// Record the ends of effects whose duration elapsed after the last change
+    /// and before `at`
+    fn expire(&mut self, at: Duration) {
+        let last = self.changes.last().map(|(t, _)| *t).unwrap_or_default();
+        let mut ends: Vec<Duration> = self
+            .slots
+            .values()
+            .filter_map(Slot::end)
+            .filter(|end| *end > last && *end < at)
+            .collect();
+        ends.sort();
+        for end in ends {
+            self.record(end);
+        }
+    }
+
+    /// Record a change if the effect playing at `at` differs from the last one
+    fn record(&mut self, at: Duration) {
+        let effect = self
+            .slots
+            .values()
+            .filter(|slot| slot.playing_at(at))
+            .max_by_key(|slot| slot.started)
+            .and_then(Slot::effect);
+        let changed = match self.changes.last() {
+            Some((_, last)) => !similar(last, &effect, 0.0),
+            None => effect.is_some(),
+        };
+        if changed {
+            self.changes.push((at, effect));
+        }
+    }
+
+    /// Scenario steps up to `end`, without leading or trailing idle time
+    fn steps(&mut self, end: Duration, options: &LearnOptions) -> Vec<ScenarioStep> {
+        self.expire(end);
+        let min_step = options.min_step.as_millis() as u32;
+        let mut segments: Vec<(u32, Option<Effect>)> = Vec::new();
+        for (i, (at, effect)) in self.changes.iter().enumerate() {
+            let until = self.changes.get(i + 1).map_or(end, |(t, _)| *t);
+            let length = until.saturating_sub(*at).as_millis() as u32;
+            match segments.last_mut() {
+                Some((last_length, last)) if length < min_step || similar(last, effect, options.tolerance) => {
+                    *last_length += length;
+                }
+                _ => segments.push((length, effect.clone())),
+            }
+        }
+        while segments.last().is_some_and(|(_, effect)| effect.is_none()) {
+            segments.pop();
+        }
+        segments
+            .into_iter()
+            .filter(|(length, _)| *length > 0)
+            .map(|(length, effect)| ScenarioStep {
+                effect: with_duration(effect.unwrap_or_else(idle), length),
+                expect: None,
+                peripherals: Vec::new(),
+                settings: Vec::new(),
+                hold: false,
+                stop_at: None,
+            })
+            .collect()
+    }
+}
+
+/// Zero force, for the pauses between effects
+fn idle() -> Effect {
+    Effect::Constant {
+        params: EffectParams::default(),
+        force: ConstantForce {
+            magnitude: 0,
+            direction: Direction::default(),
+            envelope: Envelope::default(),
+        },
+    }
+}
+
+fn with_duration(mut effect: Effect, duration: u32) -> Effect {
+    match &mut effect {
+        Effect::Constant { params, .. }
+        | Effect::Periodic { params, .. }
+        | Effect::Ramp { params, .. }
+        | Effect::Condition { params, .. } => params.duration = duration,
+    }
+    effect
+}
+
+/// Both idle, or the same effect type with all values but the duration within `tolerance`
+fn similar(a: &Option<Effect>, b: &Option<Effect>, tolerance: f64) -> bool {
+    let value = |effect: &Effect| {
+        let mut value = serde_json::to_value(effect).unwrap_or_default();
+        if let Some(fields) = value.as_object_mut() {
+            fields.remove("duration");
+        }
+        value
+    };
+    match (a, b) {
+        (None, None) => true,
+        (Some(a), Some(b)) => close(&value(a), &value(b), tolerance),
+        _ => false,
+    }
+}
+
+fn close(a: &Value, b: &Value, tolerance: f64) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => match (a.as_f64(), b.as_f64()) {
+            (Some(a), Some(b)) => (a - b).abs() <= tolerance,
+            _ => false,
+        },
+        (Value::Object(a), Value::Object(b)) => {
+            let matches = |(key, value): (&String, &Value)| b.get(key).is_some_and(|other| close(value, other, tolerance));
+            a.len() == b.len() && a.iter().all(matches)
+        }
+        _ => a == b,
+    }
+}
@@ -0,0 +1,99 @@
+//! Force feedback playback and capture engine behind the `ffb_replay` CLI
+//!
+//! A [`Scenario`] is a sequence of force feedback [`effects`] loaded from YAML. It is
+//! played on an [`driver::FfbDriver`] (see [`create_driver`]): the SDL driver plays
+//! the effects on a real wheel and captures the resulting USB traffic with
+//! [`usb_monitor`], the SIMAGIC driver generates the reports it would send. Each step
+//! yields a [`StepOutput`] with its packets, which can be saved as a capture file
+//! ([`write_capture_file`], [`parse_capture_file`]) and checked against an earlier
+//! capture with [`compare`].
+//!
+//! ```no_run
+//! use ffb_replay::{compare, create_driver, parse_capture_file, Scenario};
+//! use std::path::PathBuf;
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let scenario = Scenario::load_from_file(&PathBuf::from("scenarios/simple_test.yaml"))?;
+//! let mut driver = create_driver("sdl", Some(&Default::default()))?;
+//! driver.initialize()?;
+//! let actual = scenario.play(driver.as_mut())?;
+//! driver.shutdown()?;
+//!
+//! let expected = parse_capture_file(&PathBuf::from("runs/simple_test.txt"))?;
+//! let results = compare::compare_runs(&expected, &actual, &Default::default());
+//! println!("{} steps differ", results.iter().filter(|r| !r.is_match()).count());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Progress during playback can be followed with an [`observer::PlaybackObserver`]
+//! through [`Scenario::play_observed`].
+//!
+//! [`ScenarioRunner`] bundles these stages into one configurable run that returns a
+//! [`RunReport`], as the CLI's `record`, `play` and `compare` commands do. A
+//! [`CancellationToken`] stops a run early, including in the middle of a step.
+//!
+//! With the `async` feature, `async_engine` offers the same playback on tokio. The
+//! `ffi` feature adds a C ABI in `ffi`, for embedding in C and C++ tools.
+//!
+//! Everything that talks to devices (the SDL driver and libpcap live capture) is
+//! behind the `device` feature, enabled by default through `cli`. Without it the
+//! effects model, scenarios, capture files ([`parse_capture`]), pcap parsing
+//! ([`usb_monitor::parse_pcap`]), the decoders in [`normalize`] and [`compare`]
+//! build for `wasm32-unknown-unknown`.
+//!
+//! Devices that follow the HID PID specification decode without a vendor decoder:
+//! [`hid_descriptor`] reads their report descriptor and decodes reports field by
+//! field ([`normalize::decode_packet_with`]).
+//!
+//! [`trace`] links each captured packet's bytes back to the scenario effect field
+//! they were produced from, and [`force_curve`] reconstructs the force they
+//! commanded over time. [`response`] sets that force against the wheel motion
+//! sampled during playback ([`telemetry`]).
+
+#[cfg(feature = "async")]
+pub mod async_engine;
+pub mod cadence;
+pub mod cancel;
+pub mod capabilities;
+pub mod capture_file;
+pub mod compare;
+pub mod device_settings;
+pub mod diff_view;
+pub mod driver;
+pub mod drivers;
+pub mod effects;
+pub mod env_vars;
+pub mod error;
+pub mod expect;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod force_curve;
+#[cfg(feature = "harness")]
+pub mod harness;
+pub mod health;
+pub mod hid_descriptor;
+pub mod history;
+pub mod latency;
+pub mod merge;
+pub mod normalize;
+pub mod observer;
+pub mod peripheral;
+pub mod priority;
+pub mod reports;
+pub mod response;
+pub mod runner;
+pub mod scenario;
+pub mod schedule;
+pub mod telemetry;
+pub mod trace;
+pub mod usb_monitor;
+pub mod variance;
+pub mod watchdog;
+pub mod waveform;
+
+pub use cancel::CancellationToken;
+pub use capture_file::{parse_capture, parse_capture_file, write_capture_file, CaptureReader};
+pub use drivers::create_driver;
+pub use runner::{RunReport, ScenarioRunner};
+pub use scenario::{effect_label, DeviceCapture, Scenario, ScenarioStep, StepOutput};
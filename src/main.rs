@@ -1,155 +1,133 @@
-mod driver;
-mod drivers;
-mod effects;
-mod error;
-mod usb_monitor;
-
-use clap::{Parser, Subcommand};
-use driver::FfbDriver;
-use drivers::sdl_driver::SdlDriver;
-use drivers::simagic_driver::SimagicDriver;
-use effects::Effect;
-use serde::{Deserialize, Serialize};
+mod analyze_bytes;
+mod batch;
+mod bench;
+mod builder;
+mod config;
+mod convert;
+mod decode;
+mod export;
+mod fuzz;
+mod golden;
+mod hotkey;
+mod import;
+mod init;
+mod learn;
+mod monitor;
+mod output;
+mod proxy;
+mod serve;
+mod sim_feed;
+mod suite;
+mod tui;
+mod virtual_device;
+
+// The CLI modules reach the engine through these, as `crate::compare` etc.
+use ffb_replay::{
+    cadence, cancel, capture_file, compare, create_driver, driver, drivers, effect_label, effects, error, force_curve,
+    hid_descriptor, history, latency, merge, normalize, observer, parse_capture_file, priority, reports, response,
+    runner, scenario, schedule, telemetry, trace, usb_monitor, variance, watchdog, write_capture_file, CaptureReader,
+    RunReport, Scenario, ScenarioRunner, ScenarioStep, StepOutput,
+};
+
+use cancel::CancellationToken;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use error::ExitStatus;
 use std::fs;
 use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Mutex;
+use std::time::Duration;
 
-/// Scenario step - effect with delay
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ScenarioStep {
-    /// Effect
-    pub effect: Effect,
-}
+#[derive(Parser)]
+#[command(name = "ffb_replay")]
+#[command(about = "Force Feedback Replay Tool - Play and compare FFB scenarios", long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
 
-/// Playback scenario
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Scenario {
-    /// Scenario name
-    pub name: String,
-    /// Description
-    #[serde(default)]
-    pub description: String,
-    /// Loop forever
-    #[serde(default)]
-    pub loop_forever: bool,
-    /// Repeat count (if not loop_forever)
-    #[serde(default = "default_repeat_count")]
-    pub repeat_count: u32,
-    /// Scenario steps
-    pub steps: Vec<ScenarioStep>,
-}
+    /// USB capture backend for the SDL driver
+    #[arg(long, global = true, value_enum, default_value = "auto")]
+    capture_backend: usb_monitor::CaptureBackend,
 
-fn default_repeat_count() -> u32 {
-    1
-}
+    /// Only capture traffic of these USB devices (VID:PID, e.g. 046D:C24F; comma-separated or repeated)
+    /// The first device is the primary one, the others are recorded in per-device sections
+    #[arg(long = "device", global = true, value_delimiter = ',')]
+    devices: Vec<usb_monitor::DeviceFilter>,
 
-/// Captured output for a single step
-#[derive(Debug, Clone)]
-pub struct StepOutput {
-    pub step_index: usize,
-    pub step_name: String,
-    pub packets: Vec<String>,
-}
+    #[command(flatten)]
+    packet_filter: usb_monitor::PacketFilter,
 
-impl Scenario {
-    /// Load scenario from YAML file
-    pub fn load_from_file(path: &PathBuf) -> anyhow::Result<Self> {
-        let content = fs::read_to_string(path)?;
-        let scenario: Scenario = serde_yaml::from_str(&content)?;
-        Ok(scenario)
-    }
+    /// Kernel capture filter for the pcap/subprocess backends (pcap-filter syntax);
+    /// generated from --device and --filter-* by default, "" disables it
+    #[arg(long, global = true)]
+    capture_filter: Option<String>,
 
-    /// Play scenario with a specific driver
-    /// Returns captured/generated packets organized by step
-    pub fn play<D: FfbDriver + ?Sized>(&self, driver: &mut D) -> anyhow::Result<Vec<StepOutput>> {
-        println!("Starting scenario: {}", self.name);
-        if !self.description.is_empty() {
-            println!("  {}", self.description);
-        }
-        println!();
+    /// Capture continuously into a ring buffer and extract each step's packets by timestamp
+    #[arg(long, global = true)]
+    ring_buffer: bool,
 
-        let iterations = if self.loop_forever {
-            println!("WARNING: Infinite loop mode. Press Ctrl+C to stop.");
-            u32::MAX
-        } else {
-            self.repeat_count
-        };
+    /// Ring buffer: milliseconds before the effect start included in a step
+    #[arg(long, global = true, default_value_t = 50, requires = "ring_buffer")]
+    pre_roll_ms: u64,
 
-        let mut all_outputs: Vec<StepOutput> = Vec::new();
-
-        for iteration in 0..iterations {
-            if iterations != u32::MAX {
-                println!("=== Iteration {}/{} ===", iteration + 1, iterations);
-            }
-
-            for (idx, step) in self.steps.iter().enumerate() {
-                let effect_type = match &step.effect {
-                    Effect::Constant { .. } => "Constant force",
-                    Effect::Periodic { effect, .. } => match effect.wave_type {
-                        effects::WaveType::Sine => "Periodic (sine)",
-                        effects::WaveType::Square => "Periodic (square)",
-                        effects::WaveType::Triangle => "Periodic (triangle)",
-                        effects::WaveType::SawtoothUp => "Periodic (sawtooth up)",
-                        effects::WaveType::SawtoothDown => "Periodic (sawtooth down)",
-                    },
-                    Effect::Ramp { .. } => "Ramp (linear change)",
-                    Effect::Condition { effect, .. } => match effect.condition_type {
-                        effects::ConditionType::Spring => "Condition (spring)",
-                        effects::ConditionType::Damper => "Condition (damper)",
-                        effects::ConditionType::Friction => "Condition (friction)",
-                        effects::ConditionType::Inertia => "Condition (inertia)",
-                    },
-                };
+    /// Ring buffer: milliseconds after the effect end included in a step
+    #[arg(long, global = true, default_value_t = 200, requires = "ring_buffer")]
+    post_roll_ms: u64,
 
-                println!(
-                    "  Step {}: {} (duration: {} ms)",
-                    idx + 1,
-                    effect_type,
-                    step.effect.duration()
-                );
+    /// Sample the wheel's steering axis at this rate (Hz) while effects play and store
+    /// the positions in the capture file (SDL driver)
+    #[arg(long, global = true, value_name = "HZ", value_parser = clap::value_parser!(u32).range(1..=1000))]
+    axis_rate: Option<u32>,
 
-                // apply_effect returns captured packets and handles timing internally
-                // Don't crash on error - just print warning and return empty result
-                let packets = match driver.apply_effect(&step.effect) {
-                    Ok(p) => p,
-                    Err(e) => {
-                        eprintln!("    ERROR: Failed to execute effect: {}", e);
-                        Vec::new()
-                    }
-                };
+    /// Protocol decoders to try before the shipped ones (YAML specs; comma-separated or repeated)
+    #[arg(long = "decoder", global = true, value_delimiter = ',')]
+    decoders: Vec<PathBuf>,
 
-                // Print captured output
-                if !packets.is_empty() {
-                    println!("    Output ({} packets):", packets.len());
-                    for packet in &packets {
-                        println!("      {}", packet);
-                    }
-                } else {
-                    println!("    Output: (no packets captured)");
-                }
+    /// Show a terminal dashboard during playback (record, play, compare and test)
+    #[arg(long, global = true)]
+    tui: bool,
 
-                all_outputs.push(StepOutput {
-                    step_index: idx + 1,
-                    step_name: effect_type.to_string(),
-                    packets,
-                });
+    /// How closely steps are started on time (record, play, compare and test; default: the
+    /// scenario's `timing`, normal unless set)
+    #[arg(long, global = true, value_enum)]
+    timing: Option<schedule::TimingMode>,
 
-                let _ = driver.stop_all_effects();
-            }
+    /// Poll the device after every step and end the run once it stops responding
+    /// (record, play, compare and test; also enabled by the scenario's `health_check`)
+    #[arg(long, global = true)]
+    health_check: bool,
 
-            println!();
-        }
+    /// Fail before the first step when the driver's capabilities rule out part of the
+    /// scenario (record, play, compare and test), instead of failing those steps mid-run
+    #[arg(long, global = true)]
+    strict: bool,
 
-        println!("Scenario completed");
-        Ok(all_outputs)
-    }
+    /// Raise the capture reader and playback threads to real-time priority (SCHED_FIFO
+    /// on Linux, time critical on Windows) where permitted
+    #[arg(long, global = true)]
+    realtime: bool,
+
+    /// Limit the force of every effect to this percentage of full scale before it reaches
+    /// the driver, e.g. to protect a direct drive base while trying unknown protocol bytes
+    #[arg(long, global = true, value_name = "PCT", value_parser = clap::value_parser!(u8).range(1..=100))]
+    max_force: Option<u8>,
+
+    /// How --max-force limits forces: clamp the values above it, or scale all values
+    #[arg(long, global = true, value_enum, default_value = "clamp")]
+    max_force_mode: drivers::force_limit::LimitMode,
+
+    /// Format of command results on stdout; with json, all other text goes to stderr
+    #[arg(long, global = true, value_enum, default_value = "text")]
+    output_format: OutputFormat,
 }
 
-#[derive(Parser)]
-#[command(name = "ffb_replay")]
-#[command(about = "Force Feedback Replay Tool - Play and compare FFB scenarios", long_about = None)]
-struct Cli {
-    #[command(subcommand)]
-    command: Commands,
+/// Format of command results
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Human-readable text
+    Text,
+    /// JSON (for compare, the structured diff document)
+    Json,
 }
 
 #[derive(Subcommand)]
@@ -167,6 +145,26 @@ enum Commands {
         /// Driver to use: sdl or simagic
         #[arg(short, long, default_value = "sdl")]
         driver: String,
+
+        /// Play the scenario this many times and report how much each step varies
+        /// between runs; runs after the first are saved as <output>_2, <output>_3, ...
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        repeats: u32,
+
+        /// Keep no steps in memory, only in the capture file, for long runs; skips the
+        /// summaries over all steps (latency, intervals and variance)
+        #[arg(long)]
+        low_memory: bool,
+    },
+    /// Play a scenario on the device without capturing or saving output (feel-testing)
+    Play {
+        /// Path to scenario YAML file
+        #[arg(short, long)]
+        scenario: PathBuf,
+
+        /// Driver to use: sdl or simagic
+        #[arg(short, long, default_value = "sdl")]
+        driver: String,
     },
     /// Play a scenario and compare driver output with a capture file
     Compare {
@@ -174,232 +172,1617 @@ enum Commands {
         #[arg(short, long)]
         scenario: PathBuf,
 
-        /// Capture file name to compare with (in runs/)
+        /// Capture file name to compare with (in runs/); default: the golden of
+        /// the scenario and driver in the golden store
         #[arg(short, long)]
-        compare: String,
+        compare: Option<String>,
+
+        /// Golden store used when no capture file is given
+        #[arg(long, default_value = golden::DEFAULT_STORE)]
+        store: PathBuf,
 
         /// Driver to use: sdl or simagic
         #[arg(short, long, default_value = "sdl")]
         driver: String,
+
+        /// Overwrite the capture file with the new run if steps differ
+        #[arg(long)]
+        accept: bool,
+
+        /// With --accept, ask before accepting each mismatched step
+        #[arg(long, requires = "accept")]
+        interactive: bool,
+
+        /// Write a JUnit XML report with one test case per step
+        #[arg(long)]
+        junit: Option<PathBuf>,
+
+        /// Write a self-contained HTML report
+        #[arg(long)]
+        html: Option<PathBuf>,
+
+        /// Decode both sides to canonical commands before comparing
+        /// (allows comparing captures from different drivers)
+        #[arg(long)]
+        normalize: bool,
+
+        /// What to compare: packets (exact payloads) or counts (packets per step)
+        #[arg(long, value_enum, default_value = "packets")]
+        mode: compare::CompareMode,
+
+        /// Allowed packet count difference per step in counts mode
+        #[arg(long, default_value_t = 0)]
+        tolerance: usize,
+
+        /// Fail steps whose packet interval jitter (p95 distance from the median) exceeds this
+        #[arg(long, value_name = "US")]
+        max_jitter_us: Option<u64>,
+
+        /// Fail steps whose update rate (from the median packet interval) is below this
+        #[arg(long, value_name = "HZ")]
+        min_update_rate: Option<f64>,
+    },
+    /// Play all scenarios in a directory and compare each with its golden capture
+    Test {
+        /// Directory with scenario YAML files
+        #[arg(long, default_value = "scenarios")]
+        dir: PathBuf,
+
+        /// Flat directory with golden captures named <scenario><suffix>.run
+        /// instead of the golden store
+        #[arg(long)]
+        golden: Option<PathBuf>,
+
+        /// Suffix appended to the scenario name to form the golden file name
+        #[arg(long, default_value = "", requires = "golden")]
+        suffix: String,
+
+        /// Golden store with goldens in <scenario>/<driver>.run
+        #[arg(long, default_value = golden::DEFAULT_STORE)]
+        store: PathBuf,
+
+        /// Driver to use: sdl or simagic
+        #[arg(short, long, default_value = "sdl")]
+        driver: String,
+
+        /// Decode both sides to canonical commands before comparing
+        #[arg(long)]
+        normalize: bool,
+
+        /// What to compare: packets (exact payloads) or counts (packets per step)
+        #[arg(long, value_enum, default_value = "packets")]
+        mode: compare::CompareMode,
+
+        /// Allowed packet count difference per step in counts mode
+        #[arg(long, default_value_t = 0)]
+        tolerance: usize,
+
+        /// Fail steps whose packet interval jitter (p95 distance from the median) exceeds this
+        #[arg(long, value_name = "US")]
+        max_jitter_us: Option<u64>,
+
+        /// Fail steps whose update rate (from the median packet interval) is below this
+        #[arg(long, value_name = "HZ")]
+        min_update_rate: Option<f64>,
+    },
+    /// Merge several captures of the same scenario into a consensus golden
+    Merge {
+        /// Capture file names to merge (in runs/)
+        #[arg(required = true, num_args = 2..)]
+        inputs: Vec<String>,
+
+        /// Output file name (will be saved in runs/)
+        #[arg(short, long)]
+        output: String,
+    },
+    /// Summarize past runs: pass/fail matrix, packet count and latency trends
+    Report {
+        /// Only include runs of the last N days
+        #[arg(long, default_value_t = 30)]
+        days: u64,
+
+        /// Write the Markdown report to this file instead of printing it
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Show run history trends and flag scenarios that started failing
+    History {
+        /// Only show runs of this scenario (scenario name)
+        #[arg(short, long)]
+        scenario: Option<String>,
+
+        /// Number of most recent runs to list per scenario
+        #[arg(short, long, default_value_t = 10)]
+        limit: usize,
+    },
+    /// Decode packets, capture files or pcap files into readable commands
+    Decode {
+        /// Hex packets ("01 05 01 ..."), capture files (path or name in runs/) or pcap/pcapng files (usbmon/USBPcap)
+        #[arg(required = true)]
+        inputs: Vec<String>,
+
+        /// HID report descriptor for decoding PID reports field by field: a descriptor
+        /// file (binary or hex dump) or VID:PID of a connected device (Linux)
+        #[arg(long, value_name = "FILE|VID:PID")]
+        hid_descriptor: Option<String>,
+    },
+    /// Find the bytes that change with a parameter across runs recorded with different values
+    AnalyzeBytes {
+        /// Runs as VALUE=CAPTURE (capture path or name in runs/), e.g. 1000=magnitude_1000.run
+        #[arg(required = true, num_args = 2.., value_name = "VALUE=CAPTURE")]
+        runs: Vec<String>,
+
+        /// Only analyze this step
+        #[arg(long)]
+        step: Option<usize>,
+    },
+    /// Save the HID report descriptor of the --device, raw and parsed, e.g. to commit next to its decoder
+    HidDescriptor {
+        /// Dump file (default: decoders/<vid>_<pid>_descriptor.txt)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Convert a Wireshark pcap/pcapng capture of USB traffic into a capture file
+    Import {
+        /// pcap or pcapng file (usbmon or USBPcap link type)
+        input: PathBuf,
+
+        /// Output file name, saved in runs/ (default: the input name with .run)
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Also write a scenario candidate with one step per burst of packets
+        #[arg(long, value_name = "FILE")]
+        scenario: Option<PathBuf>,
+
+        /// Pause in the traffic that starts a new step, in milliseconds
+        #[arg(long, default_value_t = 500)]
+        gap_ms: u64,
+    },
+    /// Generate a Wireshark Lua dissector from the decoder specs (shipped and --decoder)
+    Dissector {
+        /// Lua file to write
+        #[arg(short, long, default_value = "ffb_replay.lua")]
+        output: PathBuf,
+    },
+    /// Convert another tool's effect definitions (fftest-style ff_effect assignments) into a scenario
+    Convert {
+        /// File with the effect definitions
+        input: PathBuf,
+
+        /// Format of the definitions
+        #[arg(long, value_enum, default_value = "fftest")]
+        from: convert::ScriptFormat,
+
+        /// Scenario file to write (default: <input>.yaml)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Write a standalone C program playing a scenario through SDL3 or DirectInput, as a reproducer
+    Export {
+        /// Scenario file
+        #[arg(short, long)]
+        scenario: PathBuf,
+
+        /// API of the program: c-sdl (SDL3 haptic) or c-dinput (DirectInput 8)
+        #[arg(long, value_enum)]
+        lang: export::Language,
+
+        /// C file to write (default: <scenario>_sdl.c or <scenario>_dinput.c)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Chart the commanded force and packet rate of each step of a capture as SVG or PNG
+    Plot {
+        /// Capture file (path or name in runs/), recorded with packet timestamps
+        capture: String,
+
+        /// Scenario the capture was recorded from, adds its intended force to the force charts
+        #[arg(short, long)]
+        scenario: Option<PathBuf>,
+
+        /// Directory for the charts, named <capture>_step<N>_<force|rate|axis>
+        #[arg(short, long, default_value = "runs/plots")]
+        output: PathBuf,
+
+        /// Image format of the charts
+        #[arg(long, value_enum, default_value = "svg")]
+        format: reports::plot::PlotFormat,
+    },
+    /// Export the force commanded by a capture's packets over time, one CSV per step
+    ForceCurve {
+        /// Capture file (path or name in runs/), recorded with packet timestamps
+        capture: String,
+
+        /// Scenario the capture was recorded from, adds its intended force to each row
+        #[arg(short, long)]
+        scenario: Option<PathBuf>,
+
+        /// Directory for the CSV files, named <capture>_step<N>.csv
+        #[arg(short, long, default_value = "runs/force_curves")]
+        output: PathBuf,
+    },
+    /// Compare the force each step commanded with how the wheel moved (needs --axis-rate captures)
+    Response {
+        /// Capture file (path or name in runs/), recorded with --axis-rate
+        capture: String,
+
+        /// Scenario the capture was recorded from, its force is used for steps whose packets don't decode
+        #[arg(short, long)]
+        scenario: Option<PathBuf>,
+
+        /// Distance from the start position the axis must travel to count as movement
+        #[arg(long, default_value_t = response::DEFAULT_MIN_DEFLECTION)]
+        min_deflection: u16,
+    },
+    /// Watch live USB traffic without running a scenario
+    Monitor {
+        /// Print the decoded command under packets a decoder recognizes
+        #[arg(long)]
+        decode: bool,
+
+        /// HID report descriptor for decoding PID reports field by field: a descriptor
+        /// file (binary or hex dump) or VID:PID (default: the first --device's, if it has PID reports)
+        #[arg(long, value_name = "FILE|VID:PID")]
+        hid_descriptor: Option<String>,
+
+        /// Stop after this many seconds (default: run until interrupted)
+        #[arg(long)]
+        duration: Option<u64>,
+    },
+    /// Pose as a PID force feedback wheel (Linux uhid, Windows vJoy) and record the reports a game sends it
+    VirtualDevice {
+        /// Output file name, saved in runs/
+        #[arg(short, long, default_value = "virtual_device.run")]
+        output: String,
+
+        /// Also write a scenario candidate with one step per burst of reports
+        #[arg(long, value_name = "FILE")]
+        scenario: Option<PathBuf>,
+
+        /// Report descriptor to advertise: a descriptor file or VID:PID of a connected
+        /// wheel to pose as (default: a built-in PID wheel); on Windows only for decoding
+        #[arg(long, value_name = "FILE|VID:PID")]
+        hid_descriptor: Option<String>,
+
+        /// VID:PID to advertise (default: that of the --hid-descriptor wheel, else 1209:0001)
+        #[arg(long, value_name = "VID:PID")]
+        id: Option<usb_monitor::DeviceFilter>,
+
+        /// Device name shown to games
+        #[arg(long, default_value = "ffb_replay virtual wheel")]
+        name: String,
+
+        /// vJoy device receiving the force feedback (Windows)
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..=16))]
+        vjoy_device: u32,
+
+        /// Pause in the reports that starts a new step, in milliseconds
+        #[arg(long, default_value_t = 500)]
+        gap_ms: u64,
+
+        /// Stop after this many seconds (default: run until interrupted)
+        #[arg(long)]
+        duration: Option<u64>,
+    },
+    /// Sit between a game and the --device wheel (Linux): forward its force feedback while recording
+    Proxy {
+        /// Output file name for the reports forwarded to the wheel, saved in runs/
+        #[arg(short, long, default_value = "proxy.run")]
+        output: String,
+
+        /// Log of both directions, saved in runs/
+        #[arg(long, default_value = "proxy.log")]
+        log: String,
+
+        /// YAML rules rewriting or dropping matching reports on their way to the wheel
+        #[arg(long, value_name = "FILE")]
+        mutations: Option<PathBuf>,
+
+        /// VID:PID to advertise (default: the wheel's)
+        #[arg(long, value_name = "VID:PID")]
+        id: Option<usb_monitor::DeviceFilter>,
+
+        /// Device name shown to games
+        #[arg(long, default_value = "ffb_replay proxy wheel")]
+        name: String,
+
+        /// Pause in the reports that starts a new step, in milliseconds
+        #[arg(long, default_value_t = 500)]
+        gap_ms: u64,
+
+        /// Stop after this many seconds (default: run until interrupted)
+        #[arg(long)]
+        duration: Option<u64>,
+    },
+    /// Watch a game drive the --device wheel and write a scenario reproducing its effects
+    Learn {
+        /// Scenario file to write
+        #[arg(short, long, default_value = "learned.yaml")]
+        output: PathBuf,
+
+        /// HID report descriptor for decoding PID reports: a descriptor file (binary or
+        /// hex dump) or VID:PID (default: the --device wheel's, if it has PID reports)
+        #[arg(long, value_name = "FILE|VID:PID")]
+        hid_descriptor: Option<String>,
+
+        /// Steps shorter than this many milliseconds are merged into the step before
+        #[arg(long, default_value_t = 20)]
+        min_step_ms: u64,
+
+        /// Merge consecutive steps whose values differ by at most this much (scenario units)
+        #[arg(long, default_value_t = 100.0)]
+        tolerance: f64,
+
+        /// Factor from decoded vendor values to scenario units (-10000 to 10000)
+        #[arg(long, default_value_t = 1.0)]
+        magnitude_scale: f64,
+
+        /// Stop after this many seconds (default: run until interrupted)
+        #[arg(long)]
+        duration: Option<u64>,
+    },
+    /// Play effects synthesized live from a sim's UDP telemetry through a driver and record them
+    SimFeed {
+        /// Driver to use: sdl or simagic
+        #[arg(short, long, default_value = "sdl")]
+        driver: String,
+
+        /// Telemetry feed: ac (Assetto Corsa remote telemetry) or json (a bridge, e.g. for iRacing or ACC)
+        #[arg(long, value_enum, default_value = "ac")]
+        format: sim_feed::FeedFormat,
+
+        /// The game's address for ac (default: 127.0.0.1:9996), the address to listen on for json
+        /// (default: 127.0.0.1:9997)
+        #[arg(long)]
+        address: Option<std::net::SocketAddr>,
+
+        /// Output file name, saved in runs/
+        #[arg(short, long, default_value = "sim_feed.run")]
+        output: String,
+
+        /// Also write the played effects as a scenario, to replay them through another driver
+        #[arg(long, value_name = "FILE")]
+        scenario: Option<PathBuf>,
+
+        /// Length of each played effect, in milliseconds
+        #[arg(long, default_value_t = 50)]
+        step_ms: u64,
+
+        /// Steering torque (Nm) played at full force
+        #[arg(long, default_value_t = 30.0)]
+        full_torque: f32,
+
+        /// Reverse the direction of the steering torque
+        #[arg(long)]
+        invert: bool,
+
+        /// Tyre slip (1 = limit of grip) above which the rumble starts
+        #[arg(long, default_value_t = 1.0)]
+        slip_threshold: f32,
+
+        /// Rumble magnitude (0-10000) reached one unit of slip above the threshold
+        #[arg(long, default_value_t = 3000)]
+        slip_magnitude: u16,
+
+        /// Period of the rumble, in milliseconds
+        #[arg(long, default_value_t = 40)]
+        slip_period: u32,
+
+        /// Stop after this many seconds (default: run until interrupted)
+        #[arg(long)]
+        duration: Option<u64>,
+    },
+    /// Manage golden captures stored per scenario and driver
+    Golden {
+        #[command(subcommand)]
+        command: golden::GoldenCommand,
+
+        /// Golden store directory
+        #[arg(long, default_value = golden::DEFAULT_STORE, global = true)]
+        store: PathBuf,
+    },
+    /// Run many scenario/driver combinations from a manifest, each on a fresh driver
+    Batch {
+        /// Manifest YAML file listing the runs
+        manifest: PathBuf,
+
+        /// Aggregate JSON result file
+        #[arg(long, default_value = "runs/batch_results.json")]
+        results: PathBuf,
+
+        /// Extra attempts for runs that fail with an error (default: from the manifest)
+        #[arg(long)]
+        retries: Option<u32>,
+
+        /// Run all entries at the same time, each on its own `device` (default: from the manifest)
+        #[arg(long)]
+        parallel: bool,
+    },
+    /// Measure the effective FFB update rate of the device (SDL driver with USB capture)
+    Bench {
+        /// Seconds to stream updates
+        #[arg(long, default_value_t = 5)]
+        duration: u64,
+
+        /// Peak constant-force magnitude of the sweep (0-10000)
+        #[arg(long, default_value_t = 3000, value_parser = clap::value_parser!(i16).range(0..=10000))]
+        magnitude: i16,
+    },
+    /// Play random effect parameters to find firmware edge cases
+    Fuzz {
+        /// Driver to use: sdl or simagic
+        #[arg(short, long, default_value = "sdl")]
+        driver: String,
+
+        /// Number of random effects to play
+        #[arg(short = 'n', long, default_value_t = 100)]
+        iterations: usize,
+
+        /// Seed of the random generator (default: derived from the current time)
+        #[arg(long)]
+        seed: Option<u64>,
+
+        /// Also generate values outside the documented parameter ranges
+        #[arg(long)]
+        invalid: bool,
+
+        /// Largest force magnitude of valid values (0-10000)
+        #[arg(long, default_value_t = 3000, value_parser = clap::value_parser!(i16).range(0..=10000))]
+        max_magnitude: i16,
+
+        /// Stop at the first anomaly
+        #[arg(long)]
+        stop_on_anomaly: bool,
+
+        /// Shell command run on each anomaly (FFB_FUZZ_* environment variables); non-zero exit stops the run
+        #[arg(long)]
+        on_anomaly: Option<String>,
+    },
+    /// Build a scenario from a menu: pick effects, tweak and feel them, keep the ones that fit
+    Build {
+        /// Driver to use: sdl or simagic
+        #[arg(short, long, default_value = "sdl")]
+        driver: String,
+
+        /// Scenario file to write
+        #[arg(short, long, default_value = "built.yaml")]
+        output: PathBuf,
+
+        /// Scenario name (default: the output file name)
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Overwrite an existing output file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Serve an HTTP API to list scenarios, start/stop runs and fetch captures and results
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        bind: String,
+
+        /// Directory with scenario YAML files
+        #[arg(long, default_value = "scenarios")]
+        dir: PathBuf,
+    },
+    /// Write example scenarios and a default config to get started
+    #[command(alias = "new")]
+    Init {
+        /// Directory to write into
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+
+        /// Overwrite existing files
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print a shell completion script
+    Completions {
+        /// Shell to generate completions for
+        shell: clap_complete::Shell,
+    },
+    /// Write man pages for ffb_replay and all subcommands
+    Manpages {
+        /// Directory to write the pages into
+        #[arg(default_value = "man")]
+        dir: PathBuf,
     },
 }
 
-fn create_driver(driver_name: &str) -> anyhow::Result<Box<dyn FfbDriver>> {
-    match driver_name.to_lowercase().as_str() {
-        "sdl" => Ok(Box::new(SdlDriver::new())),
-        "simagic" => Ok(Box::new(SimagicDriver::new())),
-        _ => Err(anyhow::anyhow!(
-            "Unknown driver: {}. Available drivers: sdl, simagic",
-            driver_name
-        )),
-    }
+/// Ask a yes/no question on stdin, defaults to "no"
+fn confirm(prompt: &str) -> anyhow::Result<bool> {
+    use std::io::Write;
+    print!("{} [y/N] ", prompt);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
 }
 
-/// Parse a capture file with step markers into StepOutput list
-fn parse_capture_file(path: &PathBuf) -> anyhow::Result<Vec<StepOutput>> {
-    let content = fs::read_to_string(path)?;
-    let mut steps: Vec<StepOutput> = Vec::new();
-    let mut current_step: Option<StepOutput> = None;
-
-    for line in content.lines() {
-        let line = line.trim();
-        if line.is_empty() {
-            continue;
-        }
-
-        if line.starts_with("# Step ") {
-            // Save previous step if any
-            if let Some(step) = current_step.take() {
-                steps.push(step);
-            }
-
-            // Parse step header: "# Step N: Name"
-            let rest = &line[7..]; // Skip "# Step "
-            if let Some(colon_pos) = rest.find(':') {
-                let step_index = rest[..colon_pos].trim().parse::<usize>().unwrap_or(steps.len() + 1);
-                let step_name = rest[colon_pos + 1..].trim().to_string();
-                current_step = Some(StepOutput {
-                    step_index,
-                    step_name,
-                    packets: Vec::new(),
-                });
-            }
-        } else if !line.starts_with('#') {
-            // Packet data
-            if let Some(ref mut step) = current_step {
-                step.packets.push(line.to_string());
+/// Update the capture file with accepted steps from the new run
+/// Returns number of accepted steps
+fn accept_run(
+    path: &PathBuf,
+    expected_steps: compare::ExpectedSteps<'_>,
+    actual_steps: &[StepOutput],
+    results: &[compare::StepComparison],
+    interactive: bool,
+) -> anyhow::Result<usize> {
+    let mut accepted_steps: Vec<StepOutput> = Vec::new();
+    let mut accepted_count = 0;
+    let max_steps = expected_steps.len().max(actual_steps.len());
+
+    println!();
+    for step_idx in 0..max_steps {
+        let expected = expected_steps.step(step_idx)?;
+        let expected = expected.as_deref();
+        let actual = actual_steps.get(step_idx);
+
+        let mismatched = results.get(step_idx).map(|r| !r.is_match()).unwrap_or(false);
+        let take_actual = if mismatched {
+            let accepted = if interactive {
+                let label = actual.or(expected).map(|s| s.step_name.as_str()).unwrap_or("");
+                confirm(&format!("Accept new output for step {}: {}?", step_idx + 1, label))?
             } else {
-                // No step header yet - create implicit step 1
-                current_step = Some(StepOutput {
-                    step_index: 1,
-                    step_name: "Unknown".to_string(),
-                    packets: vec![line.to_string()],
-                });
+                true
+            };
+            if accepted {
+                accepted_count += 1;
             }
+            accepted
+        } else {
+            false
+        };
+
+        let step = if take_actual { actual } else { expected };
+        if let Some(step) = step {
+            accepted_steps.push(step.clone());
         }
     }
 
-    // Don't forget the last step
-    if let Some(step) = current_step {
-        steps.push(step);
+    if accepted_count == 0 {
+        println!("No steps accepted, {} left unchanged", path.display());
+        return Ok(0);
+    }
+
+    let total_packets = write_capture_file(path, &accepted_steps)?;
+    println!(
+        "Accepted {} step(s), saved {} packets ({} steps) to {}",
+        accepted_count,
+        total_packets,
+        accepted_steps.len(),
+        path.display()
+    );
+    Ok(accepted_count)
+}
+
+/// HID report descriptor from `--hid-descriptor`, if given
+fn load_descriptor(source: Option<&str>) -> anyhow::Result<Option<hid_descriptor::ReportDescriptor>> {
+    let descriptor = source.map(hid_descriptor::load).transpose().map_err(anyhow::Error::msg)?;
+    if let Some(descriptor) = &descriptor {
+        println!("Decoding with a report descriptor of {} reports", descriptor.reports.len());
+    }
+    Ok(descriptor)
+}
+
+/// Run `runner` with console progress, or the dashboard with `--tui`, both with the
+/// emergency stop keys
+fn run_scenario(runner: ScenarioRunner<'_>, scenario: &Scenario, use_tui: bool) -> anyhow::Result<RunReport> {
+    if use_tui {
+        tui::run(runner, scenario)
+    } else {
+        let _keys = hotkey::listen(&runner.cancel());
+        runner.run(scenario)
+    }
+}
+
+/// Capture file name of repeat `run` of a recording, e.g. "test_3.run" for "test.run"
+fn repeat_name(output_name: &str, run: u32) -> String {
+    match output_name.rsplit_once('.') {
+        Some((stem, extension)) => format!("{}_{}.{}", stem, run, extension),
+        None => format!("{}_{}", output_name, run),
+    }
+}
+
+/// Token of the running command that Ctrl+C cancels, None if it has none
+static INTERRUPT: Mutex<Option<CancellationToken>> = Mutex::new(None);
+
+/// Token cancelled by Ctrl+C, so the command stops cleanly and still reports
+/// what it did; a second Ctrl+C exits immediately
+fn interrupt_token() -> CancellationToken {
+    let cancel = CancellationToken::new();
+    *INTERRUPT.lock().unwrap_or_else(|e| e.into_inner()) = Some(cancel.clone());
+    cancel
+}
+
+/// Stop the device's effects on any abnormal exit: panics, and Ctrl+C or termination
+/// signals that exit without the command's own cleanup
+fn install_watchdog() {
+    watchdog::install_panic_hook();
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        hotkey::restore_terminal();
+        previous(info);
+    }));
+    let installed = ctrlc::set_handler(|| {
+        let interrupt = INTERRUPT.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        match interrupt {
+            Some(cancel) if !cancel.is_cancelled() => {
+                eprintln!("\nInterrupted, stopping (Ctrl+C again to exit immediately)");
+                cancel.cancel();
+            }
+            _ => {
+                watchdog::stop_all();
+                hotkey::restore_terminal();
+                std::process::exit(130);
+            }
+        }
+    });
+    if let Err(e) = installed {
+        eprintln!("Warning: Ctrl+C will not stop cleanly: {}", e);
     }
+}
+
+fn main() -> ExitCode {
+    let config_path = std::path::Path::new(config::CONFIG_FILE);
+    let args = match config::with_defaults(std::env::args_os().collect(), config_path) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            return ExitStatus::SetupError.into();
+        }
+    };
+    let cli = Cli::parse_from(args);
+    install_watchdog();
+    let mut output = if cli.output_format == OutputFormat::Json {
+        match output::Output::json() {
+            Ok(output) => output,
+            Err(e) => {
+                eprintln!("Error: Failed to set up JSON output: {}", e);
+                return ExitStatus::SetupError.into();
+            }
+        }
+    } else {
+        output::Output::text()
+    };
 
-    Ok(steps)
+    match run(cli, &mut output) {
+        Ok(status) => status.into(),
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            let status = ExitStatus::from_error(&e);
+            let error = serde_json::json!({ "error": format!("{:#}", e), "exit_code": status as u8 });
+            if let Err(e) = output.emit(&error) {
+                eprintln!("Error: Failed to write JSON output: {}", e);
+            }
+            status.into()
+        }
+    }
 }
 
-fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+fn run(cli: Cli, output: &mut output::Output) -> anyhow::Result<ExitStatus> {
+    for path in &cli.decoders {
+        normalize::spec::register(normalize::spec::DecoderSpec::load(path).map_err(anyhow::Error::msg)?);
+    }
+    let capture = usb_monitor::CaptureConfig {
+        backend: cli.capture_backend,
+        devices: cli.devices,
+        packets: cli.packet_filter,
+        capture_filter: cli.capture_filter,
+        ring_buffer: cli.ring_buffer.then(|| usb_monitor::RingBuffer {
+            pre_roll: Duration::from_millis(cli.pre_roll_ms),
+            post_roll: Duration::from_millis(cli.post_roll_ms),
+        }),
+        axis_rate_hz: cli.axis_rate,
+        realtime: cli.realtime,
+        max_force: cli.max_force.map(|percent| drivers::force_limit::MaxForce {
+            percent,
+            mode: cli.max_force_mode,
+        }),
+    };
+    if cli.realtime {
+        priority::raise_or_warn(priority::ThreadRole::Playback);
+    }
 
-    match cli.command {
+    let use_tui = cli.tui;
+    let timing = cli.timing;
+    let health_check = cli.health_check;
+    let strict = cli.strict;
+    let status = match cli.command {
         Commands::Record {
             scenario,
-            output,
+            output: output_name,
             driver,
+            repeats,
+            low_memory,
         } => {
-            if !scenario.exists() {
-                eprintln!("Error: Scenario file not found: {}", scenario.display());
-                std::process::exit(1);
-            }
-
             println!("Loading scenario: {}", scenario.display());
-            let scenario_data = Scenario::load_from_file(&scenario)?;
+            let mut scenario_data = Scenario::load_from_file(&scenario)?;
+            scenario_data.timing = timing.unwrap_or(scenario_data.timing);
+            scenario_data.health_check |= health_check;
 
             // Create runs directory if it doesn't exist
             fs::create_dir_all("runs")?;
-            let output_path = PathBuf::from("runs").join(&output);
+            let output_path = PathBuf::from("runs").join(&output_name);
 
-            println!("Initializing {} driver...", driver);
-            let mut driver_instance = create_driver(&driver)?;
-            driver_instance.initialize()?;
-            println!("Driver ready\n");
+            let cancel = interrupt_token();
+            let mut reports_by_run = Vec::new();
+            let mut output_paths = Vec::new();
+            for run in 1..=repeats {
+                if repeats > 1 {
+                    println!("\n=== Run {} of {} ===", run, repeats);
+                }
+                // Captured packets are written to the file with step markers as steps complete
+                let run_path = match run {
+                    1 => output_path.clone(),
+                    _ => PathBuf::from("runs").join(repeat_name(&output_name, run)),
+                };
+                println!("Initializing {} driver...", driver);
+                let runner = ScenarioRunner::new(&driver)
+                    .capture(capture.clone())
+                    .cancel_token(cancel.clone())
+                    .stream_to(&run_path)
+                    .retain_steps(!low_memory)
+                    .strict(strict);
+                let report = run_scenario(runner, &scenario_data, use_tui)?;
+                println!(
+                    "\nSaved {} packets ({} steps) to {}",
+                    report.total_packets(),
+                    report.step_count,
+                    run_path.display()
+                );
+                history::append_or_warn(&report.history_record("record"));
 
-            // Play scenario and collect captured packets
-            let step_outputs = scenario_data.play(driver_instance.as_mut())?;
-
-            // Save captured packets to file with step markers
-            let mut file = fs::File::create(&output_path)?;
-            use std::io::Write;
-            let mut total_packets = 0;
-            for step_output in &step_outputs {
-                writeln!(file, "# Step {}: {}", step_output.step_index, step_output.step_name)?;
-                for packet in &step_output.packets {
-                    writeln!(file, "{}", packet)?;
+                let cancelled = report.cancelled;
+                reports_by_run.push(report);
+                output_paths.push(run_path);
+                if cancelled {
+                    break;
                 }
-                total_packets += step_output.packets.len();
             }
+            let report = &reports_by_run[0];
+            let total_packets = report.total_packets();
 
-            println!("\nSaved {} packets ({} steps) to {}", total_packets, step_outputs.len(), output_path.display());
+            let wire_latency = report.wire_latency();
+            if let Some(distribution) = &wire_latency {
+                println!("Submit-to-wire latency: {}", distribution.describe());
+            }
 
-            println!("\nStopping driver...");
-            driver_instance.shutdown()?;
+            let variance = (reports_by_run.len() > 1 && !low_memory).then(|| {
+                let runs: Vec<Vec<StepOutput>> = reports_by_run.iter().map(|r| r.steps.clone()).collect();
+                variance::VarianceReport::of(&runs)
+            });
+            if let Some(variance) = &variance {
+                variance.print();
+            }
+
+            for report in &reports_by_run {
+                report.expectations.print();
+            }
+            let failed_expectations: usize = reports_by_run.iter().map(|r| r.expectations.failures.len()).sum();
+
+            output.emit(&serde_json::json!({
+                "scenario": report.scenario,
+                "driver": report.driver,
+                "output": output_path,
+                "repeat_outputs": &output_paths[1..],
+                "total_steps": report.step_count,
+                "total_packets": total_packets,
+                "failed_expectations": failed_expectations,
+                "duration_ms": report.duration_ms,
+                "wire_latency": wire_latency,
+                "intervals": report
+                    .steps
+                    .iter()
+                    .filter_map(|s| {
+                        let histogram = cadence::IntervalHistogram::of(&s.timestamps_us)?;
+                        Some(serde_json::json!({ "step": s.step_index, "histogram": histogram }))
+                    })
+                    .collect::<Vec<_>>(),
+                "variance": variance,
+            }))?;
             println!("Done");
+
+            if failed_expectations == 0 {
+                ExitStatus::Success
+            } else {
+                ExitStatus::Mismatch
+            }
+        }
+
+        Commands::Play { scenario, driver } => {
+            println!("Loading scenario: {}", scenario.display());
+            let mut scenario_data = Scenario::load_from_file(&scenario)?;
+            scenario_data.timing = timing.unwrap_or(scenario_data.timing);
+            scenario_data.health_check |= health_check;
+
+            println!("Initializing {} driver (no capture)...", driver);
+            let runner = ScenarioRunner::new(&driver)
+                .max_force(capture.max_force)
+                .strict(strict)
+                .cancel_token(interrupt_token());
+            let report = run_scenario(runner, &scenario_data, use_tui)?;
+            output.emit(&serde_json::json!({
+                "scenario": report.scenario,
+                "driver": report.driver,
+                "total_steps": report.steps.len(),
+                "duration_ms": report.duration_ms,
+            }))?;
+            println!("Done");
+            ExitStatus::Success
         }
 
         Commands::Compare {
             scenario,
             compare,
+            store,
             driver,
+            accept,
+            interactive,
+            junit,
+            html,
+            normalize,
+            mode,
+            tolerance,
+            max_jitter_us,
+            min_update_rate,
         } => {
-            if !scenario.exists() {
-                eprintln!("Error: Scenario file not found: {}", scenario.display());
-                std::process::exit(1);
-            }
+            println!("Loading scenario: {}", scenario.display());
+            let mut scenario_data = Scenario::load_from_file(&scenario)?;
+            scenario_data.timing = timing.unwrap_or(scenario_data.timing);
+            scenario_data.health_check |= health_check;
 
-            let compare_path = PathBuf::from("runs").join(&compare);
+            let compare_path = match &compare {
+                Some(compare) => PathBuf::from("runs").join(compare),
+                None => golden::golden_path(&store, &golden::scenario_key(&scenario), &driver),
+            };
+            let compare = compare_path.display().to_string();
             if !compare_path.exists() {
-                eprintln!("Error: Comparison file not found: {}", compare_path.display());
-                std::process::exit(1);
+                return Err(error::FFBError::comparison(format!("comparison file {} not found", compare_path.display())).into());
             }
 
-            println!("Loading scenario: {}", scenario.display());
-            let scenario_data = Scenario::load_from_file(&scenario)?;
-
             println!("Loading comparison data: {}", compare_path.display());
-            let expected_steps = parse_capture_file(&compare_path)?;
+            let expected_steps = CaptureReader::open(&compare_path)?;
 
             println!("Initializing {} driver...", driver);
-            let mut driver_instance = create_driver(&driver)?;
-            driver_instance.initialize()?;
-            println!("Driver ready\n");
+            let options = compare::CompareOptions {
+                normalize,
+                mode,
+                tolerance,
+                timing: cadence::TimingThresholds {
+                    max_jitter_us,
+                    min_update_rate_hz: min_update_rate,
+                },
+            };
+            let runner = ScenarioRunner::new(&driver)
+                .capture(capture)
+                .compare_with(&expected_steps, options)
+                .strict(strict)
+                .cancel_token(interrupt_token());
+            let report = run_scenario(runner, &scenario_data, use_tui)?;
+            let mismatched_steps = report.mismatched_steps();
+            history::append_or_warn(&report.history_record("compare"));
 
-            // Play scenario and collect captured packets
-            let actual_steps = scenario_data.play(driver_instance.as_mut())?;
+            let actual_steps = &report.steps;
+            let results = report.comparison.as_deref().unwrap_or_default();
 
-            // Compare step by step
             println!("\n=== Comparison Results ===");
             println!("Expected: {} steps", expected_steps.len());
             println!("Actual: {} steps\n", actual_steps.len());
 
-            let max_steps = expected_steps.len().max(actual_steps.len());
-            let mut mismatched_steps = 0;
-
-            for step_idx in 0..max_steps {
-                let expected = expected_steps.get(step_idx);
-                let actual = actual_steps.get(step_idx);
-
-                match (expected, actual) {
-                    (Some(exp), Some(act)) => {
-                        // Compare packets in this step
-                        let packets_match = exp.packets == act.packets;
-                        
-                        if !packets_match {
-                            mismatched_steps += 1;
-                            println!("MISMATCH Step {}: {}", act.step_index, act.step_name);
-                            println!("  Expected {} packets, got {} packets", exp.packets.len(), act.packets.len());
-                            
-                            // Show differing packets
-                            let max_packets = exp.packets.len().max(act.packets.len());
-                            for i in 0..max_packets {
-                                let exp_pkt = exp.packets.get(i);
-                                let act_pkt = act.packets.get(i);
-                                
-                                match (exp_pkt, act_pkt) {
-                                    (Some(e), Some(a)) if e != a => {
-                                        println!("    Packet {} differs:", i + 1);
-                                        println!("      Expected: {}", e);
-                                        println!("      Actual:   {}", a);
-                                    }
-                                    (Some(e), None) => {
-                                        println!("    Packet {} missing in actual:", i + 1);
-                                        println!("      Expected: {}", e);
-                                    }
-                                    (None, Some(a)) => {
-                                        println!("    Packet {} extra in actual:", i + 1);
-                                        println!("      Actual:   {}", a);
-                                    }
-                                    _ => {} // Match, skip
-                                }
-                            }
-                            println!();
-                        }
-                    }
-                    (Some(exp), None) => {
-                        mismatched_steps += 1;
-                        println!("MISSING Step {}: {} (expected {} packets)", 
-                            exp.step_index, exp.step_name, exp.packets.len());
-                        println!();
-                    }
-                    (None, Some(act)) => {
-                        mismatched_steps += 1;
-                        println!("EXTRA Step {}: {} (got {} packets)", 
-                            act.step_index, act.step_name, act.packets.len());
-                        println!();
-                    }
-                    (None, None) => unreachable!(),
-                }
+            compare::print_comparison(results);
+            if normalize {
+                trace::print_field_differences(&scenario_data, (&expected_steps).into(), actual_steps, results);
             }
 
             if mismatched_steps == 0 {
                 println!("OK: All {} steps match!", actual_steps.len());
             } else {
-                println!("FAIL: {} of {} steps differ", mismatched_steps, max_steps);
+                println!("FAIL: {} of {} steps differ", mismatched_steps, results.len());
+            }
+
+            compare::print_summary(results);
+            if let Some(distribution) = report.wire_latency() {
+                println!("Submit-to-wire latency: {}", distribution.describe());
+            }
+            output.emit(&reports::json::build(&scenario_data.name, &report.driver, &compare, results, actual_steps))?;
+
+            if let Some(junit_path) = &junit {
+                reports::junit::write(junit_path, &scenario_data.name, results)?;
+                println!("JUnit report written to {}", junit_path.display());
+            }
+
+            if let Some(html_path) = &html {
+                reports::html::write(html_path, &scenario_data, &report.driver, &compare, results, actual_steps)?;
+                println!("HTML report written to {}", html_path.display());
+            }
+
+            let mut remaining_mismatches = mismatched_steps;
+            if accept && mismatched_steps > 0 {
+                let accepted = accept_run(&compare_path, (&expected_steps).into(), actual_steps, results, interactive)?;
+                remaining_mismatches -= accepted;
+            }
+
+            println!("Done");
+
+            if remaining_mismatches == 0 {
+                ExitStatus::Success
+            } else {
+                ExitStatus::Mismatch
+            }
+        }
+
+        Commands::Test {
+            dir,
+            golden,
+            suffix,
+            store,
+            driver,
+            normalize,
+            mode,
+            tolerance,
+            max_jitter_us,
+            min_update_rate,
+        } => {
+            let source = match golden {
+                Some(dir) => golden::GoldenSource::Flat { dir, suffix },
+                None => golden::GoldenSource::Store {
+                    root: store,
+                    driver: driver.clone(),
+                },
+            };
+
+            let cancel = interrupt_token();
+            let options = compare::CompareOptions {
+                normalize,
+                mode,
+                tolerance,
+                timing: cadence::TimingThresholds {
+                    max_jitter_us,
+                    min_update_rate_hz: min_update_rate,
+                },
+            };
+            // Each scenario runs like `compare`, on a freshly initialized driver
+            let play = |scenario: &mut Scenario, golden: &CaptureReader| {
+                scenario.timing = timing.unwrap_or(scenario.timing);
+                scenario.health_check |= health_check;
+                println!("Initializing {} driver...", driver);
+                let runner = ScenarioRunner::new(&driver)
+                    .capture(capture.clone())
+                    .compare_with(golden, options)
+                    .strict(strict)
+                    .cancel_token(cancel.clone());
+                run_scenario(runner, scenario, use_tui)
+            };
+            let results = suite::run_suite(&dir, &source, play, &cancel)?;
+
+            output.emit(&suite::to_json(&results))?;
+            suite::print_results(&results)
+        }
+
+        Commands::Merge {
+            inputs,
+            output: merged_output,
+        } => {
+            let mut captures = Vec::with_capacity(inputs.len());
+            for input in &inputs {
+                let path = PathBuf::from("runs").join(input);
+                if !path.exists() {
+                    anyhow::bail!("Capture file not found: {}", path.display());
+                }
+                println!("Loading capture: {}", path.display());
+                captures.push(parse_capture_file(&path)?);
             }
 
+            let (merged, warnings) = merge::merge_captures(&captures);
+            for warning in &warnings {
+                println!("WARNING: {}", warning);
+            }
+
+            let output_path = PathBuf::from("runs").join(&merged_output);
+            let total_packets = write_capture_file(&output_path, &merged)?;
+            println!(
+                "Saved consensus of {} captures: {} packets ({} steps, {} wildcard bytes) to {}",
+                captures.len(),
+                total_packets,
+                merged.len(),
+                merge::count_wildcards(&merged),
+                output_path.display()
+            );
+            output.emit(&serde_json::json!({
+                "output": output_path,
+                "captures": captures.len(),
+                "total_steps": merged.len(),
+                "total_packets": total_packets,
+                "wildcard_bytes": merge::count_wildcards(&merged),
+                "warnings": warnings,
+            }))?;
+            ExitStatus::Success
+        }
+
+        Commands::History { scenario, limit } => {
+            let records = history::load(&history::default_history_path())?;
+            history::print_history(&records, scenario.as_deref(), limit);
+            output.emit(&history::to_json(&records, scenario.as_deref(), limit))?;
+            ExitStatus::Success
+        }
+
+        Commands::Report {
+            days,
+            output: report_path,
+        } => {
+            let records = history::load(&history::default_history_path())?;
+            let since = history::now_timestamp().saturating_sub(days * 86400);
+            let latencies = reports::summary::scan_latencies(std::path::Path::new("runs"), since)?;
+            let report = reports::summary::render(&records, &latencies, since);
+            match &report_path {
+                Some(path) => {
+                    fs::write(path, &report)?;
+                    println!("Report written to {}", path.display());
+                }
+                None => print!("{}", report),
+            }
+            output.emit(&serde_json::json!({ "output": report_path, "report": report }))?;
+            ExitStatus::Success
+        }
+
+        Commands::Decode {
+            inputs,
+            hid_descriptor: source,
+        } => {
+            let descriptor = load_descriptor(source.as_deref())?;
+            let decoded = decode::run(&inputs, &capture.packets, descriptor.as_ref())?;
+            output.emit(&decoded)?;
+            ExitStatus::Success
+        }
+
+        Commands::AnalyzeBytes { runs, step } => {
+            let analysis = analyze_bytes::run(&runs, step)?;
+            output.emit(&analysis)?;
+            ExitStatus::Success
+        }
+
+        Commands::HidDescriptor { output: dump_path } => {
+            let device = capture
+                .devices
+                .first()
+                .ok_or_else(|| anyhow::anyhow!("hid-descriptor needs --device VID:PID"))?;
+            let bytes = hid_descriptor::read_report_descriptor(device).map_err(anyhow::Error::msg)?;
+            let descriptor = hid_descriptor::ReportDescriptor::parse(&bytes)
+                .map_err(|e| anyhow::anyhow!("Invalid report descriptor of {}: {}", device, e))?;
+            let title = format!("HID report descriptor of {}", device);
+            let dump = hid_descriptor::format_dump(&title, &bytes, &descriptor);
+            let dump_path = dump_path.unwrap_or_else(|| {
+                PathBuf::from("decoders").join(format!(
+                    "{:04x}_{:04x}_descriptor.txt",
+                    device.vendor_id, device.product_id
+                ))
+            });
+            if let Some(dir) = dump_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                fs::create_dir_all(dir)?;
+            }
+            fs::write(&dump_path, &dump)?;
+            print!("{}", dump);
+            println!("Descriptor written to {}", dump_path.display());
+            output.emit(&serde_json::json!({
+                "device": device.to_string(),
+                "output": dump_path,
+                "bytes": bytes.len(),
+                "reports": descriptor.describe(),
+            }))?;
+            ExitStatus::Success
+        }
+
+        Commands::Import {
+            input,
+            output: output_name,
+            scenario: candidate_path,
+            gap_ms,
+        } => {
+            let output_name = output_name.unwrap_or_else(|| {
+                format!("{}.run", input.file_stem().unwrap_or_default().to_string_lossy())
+            });
+            fs::create_dir_all("runs")?;
+            let output_path = PathBuf::from("runs").join(&output_name);
+            let steps = import::run(
+                &input,
+                &output_path,
+                candidate_path.as_deref(),
+                &capture.packets,
+                Duration::from_millis(gap_ms),
+            )?;
+            output.emit(&serde_json::json!({
+                "input": input,
+                "output": output_path,
+                "steps": steps.len(),
+                "total_packets": steps.iter().map(|s| s.packets.len()).sum::<usize>(),
+                "scenario": candidate_path,
+            }))?;
+            ExitStatus::Success
+        }
+
+        Commands::Dissector { output: lua_path } => {
+            let specs = normalize::spec::all();
+            fs::write(&lua_path, normalize::wireshark::lua_dissector(&specs))?;
+            println!("Dissector for {} decoders written to {}", specs.len(), lua_path.display());
+            println!("Copy it into Wireshark's personal plugins directory to decode force feedback packets");
+            output.emit(&serde_json::json!({
+                "output": lua_path,
+                "decoders": specs.iter().map(|s| s.name.clone()).collect::<Vec<_>>(),
+            }))?;
+            ExitStatus::Success
+        }
+
+        Commands::Convert {
+            input,
+            from,
+            output: scenario_path,
+        } => {
+            let scenario_path = scenario_path.unwrap_or_else(|| input.with_extension("yaml"));
+            let summary = convert::run(&input, from, &scenario_path)?;
+            output.emit(&summary)?;
+            ExitStatus::Success
+        }
+
+        Commands::Export {
+            scenario,
+            lang,
+            output: c_path,
+        } => {
+            let scenario_data = Scenario::load_from_file(&scenario)?;
+            let c_path = c_path.unwrap_or_else(|| {
+                let stem = scenario.file_stem().unwrap_or_default().to_string_lossy();
+                PathBuf::from(format!("{}_{}.c", stem, lang.suffix()))
+            });
+            fs::write(&c_path, export::c_source(&scenario_data, &scenario.display().to_string(), lang))?;
+            println!(
+                "{} steps of {} written to {}",
+                scenario_data.steps.len(),
+                scenario_data.name,
+                c_path.display()
+            );
+            output.emit(&serde_json::json!({
+                "scenario": scenario,
+                "lang": lang.suffix(),
+                "output": c_path,
+                "steps": scenario_data.steps.len(),
+            }))?;
+            ExitStatus::Success
+        }
+
+        Commands::Plot {
+            capture: capture_name,
+            scenario: scenario_path,
+            output: plot_dir,
+            format,
+        } => {
+            let capture_path = decode::resolve_file(&capture_name)
+                .ok_or_else(|| anyhow::anyhow!("Capture file not found: {}", capture_name))?;
+            let steps = CaptureReader::open(&capture_path)?;
+            let scenario_data = scenario_path.as_ref().map(Scenario::load_from_file).transpose()?;
+            let stem = capture_path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            fs::create_dir_all(&plot_dir)?;
+
+            let mut written = Vec::new();
+            for step_output in steps.steps() {
+                let step_output = &step_output?;
+                let step = scenario_data
+                    .as_ref()
+                    .and_then(|s| s.steps.get(step_output.step_index.saturating_sub(1)));
+                let charts = [
+                    ("force", reports::plot::force_chart(step, step_output)),
+                    ("rate", reports::plot::rate_chart(step_output)),
+                    ("axis", reports::plot::axis_chart(step_output)),
+                ];
+                for (kind, chart) in charts {
+                    let Some(chart) = chart else {
+                        continue;
+                    };
+                    let path = plot_dir.join(format!(
+                        "{}_step{}_{}.{}",
+                        stem,
+                        step_output.step_index,
+                        kind,
+                        format.extension()
+                    ));
+                    fs::write(&path, chart.render(format)?)?;
+                    println!("{}", path.display());
+                    written.push(serde_json::json!({ "step": step_output.step_index, "chart": kind, "output": path }));
+                }
+            }
+            if written.is_empty() {
+                println!("No charts: the capture has no packet timestamps or decodable forces");
+            } else {
+                println!("Wrote {} charts for {} steps", written.len(), steps.len());
+            }
+            output.emit(&serde_json::json!({ "capture": capture_path, "charts": written }))?;
+            ExitStatus::Success
+        }
+
+        Commands::ForceCurve {
+            capture: capture_name,
+            scenario: scenario_path,
+            output: csv_dir,
+        } => {
+            let capture_path = decode::resolve_file(&capture_name)
+                .ok_or_else(|| anyhow::anyhow!("Capture file not found: {}", capture_name))?;
+            let steps = CaptureReader::open(&capture_path)?;
+            let scenario_data = scenario_path.as_ref().map(Scenario::load_from_file).transpose()?;
+            let stem = capture_path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            fs::create_dir_all(&csv_dir)?;
+
+            let mut exported = Vec::new();
+            for step_output in steps.steps() {
+                let step_output = step_output?;
+                let step = scenario_data
+                    .as_ref()
+                    .and_then(|s| s.steps.get(step_output.step_index.saturating_sub(1)));
+                let curve = force_curve::reconstruct(step, &step_output);
+                if curve.samples.is_empty() {
+                    println!("Step {} ({}): no decoded force", curve.step_index, curve.step_name);
+                    continue;
+                }
+                let csv_path = csv_dir.join(format!("{}_step{}.csv", stem, curve.step_index));
+                fs::write(&csv_path, curve.to_csv())?;
+                let deviation = curve
+                    .max_deviation()
+                    .map(|d| format!(", max deviation from the scenario {:.1}", d))
+                    .unwrap_or_default();
+                println!(
+                    "Step {} ({}): {} force samples{} -> {}",
+                    curve.step_index,
+                    curve.step_name,
+                    curve.samples.len(),
+                    deviation,
+                    csv_path.display()
+                );
+                if curve.samples.iter().any(|s| s.time_ms.is_none()) {
+                    println!("  No packet timestamps in the capture: rows are in packet order only");
+                }
+                exported.push(serde_json::json!({
+                    "step": curve.step_index,
+                    "step_name": curve.step_name,
+                    "output": csv_path,
+                    "samples": curve.samples.len(),
+                    "max_deviation": curve.max_deviation(),
+                }));
+            }
+            output.emit(&serde_json::json!({ "capture": capture_path, "steps": exported }))?;
+            ExitStatus::Success
+        }
+
+        Commands::Response {
+            capture: capture_name,
+            scenario: scenario_path,
+            min_deflection,
+        } => {
+            let capture_path = decode::resolve_file(&capture_name)
+                .ok_or_else(|| anyhow::anyhow!("Capture file not found: {}", capture_name))?;
+            let steps = CaptureReader::open(&capture_path)?;
+            let scenario_data = scenario_path.as_ref().map(Scenario::load_from_file).transpose()?;
+
+            let mut responses = Vec::with_capacity(steps.len());
+            let mut axis_sampled = false;
+            for step_output in steps.steps() {
+                let step_output = step_output?;
+                axis_sampled |= !step_output.axis.is_empty();
+                let step = scenario_data
+                    .as_ref()
+                    .and_then(|s| s.steps.get(step_output.step_index.saturating_sub(1)));
+                let curve = force_curve::reconstruct(step, &step_output);
+                responses.push(response::analyze(step, &step_output, &curve, min_deflection));
+            }
+            for step in &responses {
+                let flag = if step.verdict == response::Verdict::Unresponsive { "  <-- no response" } else { "" };
+                println!("Step {} ({}): {}{}", step.step_index, step.step_name, step.describe(), flag);
+            }
+            let count = |verdict| responses.iter().filter(|s| s.verdict == verdict).count();
+            let unresponsive = count(response::Verdict::Unresponsive);
+            println!(
+                "\n{} steps responded, {} did not, {} without force, {} without axis samples",
+                count(response::Verdict::Responded),
+                unresponsive,
+                count(response::Verdict::NoForce),
+                count(response::Verdict::NoTelemetry)
+            );
+            if !axis_sampled {
+                println!("The capture has no axis samples: record it with --axis-rate");
+            }
+            output.emit(&serde_json::json!({ "capture": capture_path, "steps": responses }))?;
+            if unresponsive == 0 {
+                ExitStatus::Success
+            } else {
+                ExitStatus::Mismatch
+            }
+        }
+
+        Commands::Monitor {
+            decode,
+            hid_descriptor: source,
+            duration,
+        } => {
+            let descriptor = load_descriptor(source.as_deref())?;
+            monitor::run(
+                capture,
+                decode,
+                descriptor,
+                duration.map(Duration::from_secs),
+                &interrupt_token(),
+                output,
+            )?;
+            ExitStatus::Success
+        }
+
+        Commands::VirtualDevice {
+            output: output_name,
+            scenario: candidate_path,
+            hid_descriptor: source,
+            id,
+            name,
+            vjoy_device,
+            gap_ms,
+            duration,
+        } => {
+            let device = virtual_device::VirtualDevice::new(name, id, source.as_deref(), vjoy_device)?;
+            fs::create_dir_all("runs")?;
+            let output_path = PathBuf::from("runs").join(&output_name);
+            let steps = virtual_device::run(
+                &device,
+                &output_path,
+                candidate_path.as_deref(),
+                Duration::from_millis(gap_ms),
+                duration.map(Duration::from_secs),
+                &interrupt_token(),
+            )?;
+            output.emit(&serde_json::json!({
+                "device": device.label(),
+                "output": output_path,
+                "steps": steps.len(),
+                "total_packets": steps.iter().map(|s| s.packets.len()).sum::<usize>(),
+                "scenario": candidate_path,
+            }))?;
+            ExitStatus::Success
+        }
+
+        Commands::Proxy {
+            output: output_name,
+            log,
+            mutations,
+            id,
+            name,
+            gap_ms,
+            duration,
+        } => {
+            let mutations = match mutations {
+                Some(path) => proxy::load_mutations(&path)?,
+                None => Vec::new(),
+            };
+            fs::create_dir_all("runs")?;
+            let output_path = PathBuf::from("runs").join(&output_name);
+            let log_path = PathBuf::from("runs").join(&log);
+            let paths = proxy::ProxyOutput {
+                capture: &output_path,
+                log: &log_path,
+                gap: Duration::from_millis(gap_ms),
+            };
+            let summary = proxy::run(
+                &capture,
+                name,
+                id,
+                &mutations,
+                &paths,
+                duration.map(Duration::from_secs),
+                &interrupt_token(),
+            )?;
+            output.emit(&summary)?;
+            ExitStatus::Success
+        }
+
+        Commands::Learn {
+            output: path,
+            hid_descriptor: source,
+            min_step_ms,
+            tolerance,
+            magnitude_scale,
+            duration,
+        } => {
+            let descriptor = load_descriptor(source.as_deref())?;
+            let options = learn::LearnOptions {
+                min_step: Duration::from_millis(min_step_ms),
+                tolerance,
+                magnitude_scale,
+            };
+            let summary = learn::run(
+                capture,
+                descriptor,
+                &path,
+                &options,
+                duration.map(Duration::from_secs),
+                &interrupt_token(),
+            )?;
+            let mut json = serde_json::to_value(&summary)?;
+            json["output"] = serde_json::json!(path);
+            output.emit(&json)?;
+            ExitStatus::Success
+        }
+
+        Commands::SimFeed {
+            driver,
+            format,
+            address,
+            output: output_name,
+            scenario: scenario_path,
+            step_ms,
+            full_torque,
+            invert,
+            slip_threshold,
+            slip_magnitude,
+            slip_period,
+            duration,
+        } => {
+            let options = sim_feed::SynthesisOptions {
+                step: Duration::from_millis(step_ms.max(1)),
+                full_torque,
+                invert,
+                slip_threshold,
+                slip_magnitude: slip_magnitude.min(10000),
+                slip_period: slip_period.max(1),
+            };
+            let cancel = interrupt_token();
+            let mut feed = sim_feed::Feed::open(format, address.unwrap_or(format.default_address()), &cancel)?;
+
+            println!("Initializing {} driver...", driver);
+            let mut driver_instance = create_driver(&driver, Some(&capture))?;
+            driver_instance.set_cancellation(cancel.clone());
+            driver_instance.initialize()?;
+            for note in driver_instance.take_notes() {
+                println!("{}", note);
+            }
+            fs::create_dir_all("runs")?;
+            let output_path = PathBuf::from("runs").join(&output_name);
+            let result = sim_feed::run(
+                driver_instance.as_mut(),
+                &mut feed,
+                &options,
+                &output_path,
+                scenario_path.as_deref(),
+                duration.map(Duration::from_secs),
+                &cancel,
+            );
+            driver_instance.shutdown()?;
+            output.emit(&result?)?;
+            ExitStatus::Success
+        }
+
+        Commands::Golden { command, store } => golden::run(command, &store, output)?,
+
+        Commands::Batch {
+            manifest,
+            results,
+            retries,
+            parallel,
+        } => {
+            let mut manifest = batch::load_manifest(&manifest)?;
+            if parallel && !manifest.parallel {
+                batch::check_parallel(&manifest.runs).map_err(anyhow::Error::msg)?;
+                manifest.parallel = true;
+            }
+            let results = batch::run(&manifest, retries, &capture, &results, &interrupt_token())?;
+            output.emit(&results)?;
+            results.exit_status()
+        }
+
+        Commands::Bench { duration, magnitude } => {
+            let report = bench::run(capture, Duration::from_secs(duration), magnitude, &interrupt_token())?;
+            report.print();
+            output.emit(&report.to_json())?;
+            ExitStatus::Success
+        }
+
+        Commands::Fuzz {
+            driver,
+            iterations,
+            seed,
+            invalid,
+            max_magnitude,
+            stop_on_anomaly,
+            on_anomaly,
+        } => {
+            let seed = seed.unwrap_or_else(|| {
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos() as u64
+            });
+            println!("Initializing {} driver...", driver);
+            let cancel = interrupt_token();
+            let mut driver_instance = create_driver(&driver, Some(&capture))?;
+            driver_instance.set_cancellation(cancel.clone());
+            driver_instance.initialize()?;
+            for note in driver_instance.take_notes() {
+                println!("{}", note);
+            }
+            println!("Driver ready\n");
+
+            let options = fuzz::FuzzOptions {
+                seed,
+                iterations,
+                invalid,
+                max_magnitude,
+                stop_on_anomaly,
+                on_anomaly,
+            };
+            let summary = fuzz::run(driver_instance.as_mut(), &options, &cancel);
+
             println!("\nStopping driver...");
             driver_instance.shutdown()?;
-            println!("Done");
+
+            let summary = summary?;
+            output.emit(&summary)?;
+            if summary.anomalies.is_empty() {
+                ExitStatus::Success
+            } else {
+                ExitStatus::Mismatch
+            }
         }
-    }
 
-    Ok(())
+        Commands::Build {
+            driver,
+            output: path,
+            name,
+            force,
+        } => {
+            if path.exists() && !force {
+                anyhow::bail!("{} exists, use --force to overwrite", path.display());
+            }
+            let name = name.unwrap_or_else(|| {
+                path.file_stem().map_or_else(|| "Built scenario".to_string(), |s| s.to_string_lossy().into_owned())
+            });
+            println!("Initializing {} driver...", driver);
+            let cancel = interrupt_token();
+            let mut driver_instance = create_driver(&driver, Some(&capture))?;
+            driver_instance.set_cancellation(cancel.clone());
+            driver_instance.initialize()?;
+            for note in driver_instance.take_notes() {
+                println!("{}", note);
+            }
+            println!("Driver ready\n");
+
+            let result = builder::run(driver_instance.as_mut(), std::io::stdin().lock(), &path, &name, &cancel);
+
+            println!("\nStopping driver...");
+            driver_instance.shutdown()?;
+
+            output.emit(&result?)?;
+            ExitStatus::Success
+        }
+
+        Commands::Serve { bind, dir } => {
+            serve::run(&bind, &dir, capture)?;
+            ExitStatus::Success
+        }
+
+        Commands::Init { dir, force } => {
+            let summary = init::run(&dir, force)?;
+            output.emit(&summary)?;
+            ExitStatus::Success
+        }
+
+        Commands::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "ffb_replay", &mut output.stdout());
+            ExitStatus::Success
+        }
+
+        Commands::Manpages { dir } => {
+            fs::create_dir_all(&dir)?;
+            clap_mangen::generate_to(Cli::command(), &dir)?;
+            println!("Wrote man pages to {}", dir.display());
+            output.emit(&serde_json::json!({ "dir": dir }))?;
+            ExitStatus::Success
+        }
+    };
+
+    Ok(status)
 }
@@ -0,0 +1,176 @@
+//! Consensus golden from multiple recordings of the same scenario
+//!
+//! Keeps only the packets and bytes that are stable across all runs;
+//! bytes that vary are replaced with the `??` wildcard. Runs whose packets of a
+//! step differ in number or length can't be lined up byte by byte, so each such
+//! shape is merged on its own and kept as an alternative sequence of the step.
+
+use crate::compare::WILDCARD_BYTE;
+use crate::StepOutput;
+
+/// Merge several captures of the same scenario into a consensus capture
+/// Returns the merged steps and warnings about unstable structure
+pub fn merge_captures(captures: &[Vec<StepOutput>]) -> (Vec<StepOutput>, Vec<String>) {
+    let mut warnings = Vec::new();
+    let Some(first) = captures.first() else {
+        return (Vec::new(), warnings);
+    };
+
+    let step_count = captures.iter().map(|c| c.len()).min().unwrap_or(0);
+    if captures.iter().any(|c| c.len() != first.len()) {
+        warnings.push(format!(
+            "captures have different step counts, keeping the first {} steps",
+            step_count
+        ));
+    }
+
+    let mut merged = Vec::with_capacity(step_count);
+    for position in 0..step_count {
+        let steps: Vec<&StepOutput> = captures.iter().map(|c| &c[position]).collect();
+        let reference = steps[0];
+
+        // Runs with the same packet count and lengths, the first run's shape first
+        let mut shapes: Vec<(Vec<usize>, Vec<&[String]>)> = Vec::new();
+        for step in &steps {
+            let shape = packet_lengths(&step.packets);
+            match shapes.iter_mut().find(|(s, _)| *s == shape) {
+                Some((_, runs)) => runs.push(&step.packets),
+                None => shapes.push((shape, vec![&step.packets])),
+            }
+        }
+        if shapes.len() > 1 {
+            warnings.push(format!(
+                "step {} ({}): packet counts or lengths differ between runs, keeping {} alternative sequences",
+                reference.step_index,
+                reference.step_name,
+                shapes.len() - 1
+            ));
+        }
+
+        let mut sequences = shapes.iter().map(|(_, runs)| merge_sequence(runs));
+        let packets = sequences.next().unwrap_or_default();
+        let mut variants: Vec<Vec<String>> = sequences.collect();
+        // Alternative sequences of the inputs can't be merged with each other, they are kept as they are
+        for variant in steps.iter().flat_map(|s| &s.variants) {
+            if *variant != packets && !variants.contains(variant) {
+                variants.push(variant.clone());
+            }
+        }
+
+        merged.push(StepOutput {
+            step_index: reference.step_index,
+            step_name: reference.step_name.clone(),
+            packets,
+            variants,
+            latencies_us: Vec::new(),
+            wire_latency_us: None,
+            timestamps_us: Vec::new(),
+            axis: Vec::new(),
+            devices: Vec::new(),
+            health: None,
+        });
+    }
+
+    (merged, warnings)
+}
+
+/// Number of bytes of each packet
+fn packet_lengths(packets: &[String]) -> Vec<usize> {
+    packets.iter().map(|p| p.split_whitespace().count()).collect()
+}
+
+/// Merge the packets of a step from several runs with the same packet lengths
+fn merge_sequence(runs: &[&[String]]) -> Vec<String> {
+    (0..runs[0].len())
+        .map(|i| {
+            let packet_bytes: Vec<Vec<&str>> = runs.iter().map(|r| r[i].split_whitespace().collect()).collect();
+            merge_packet(&packet_bytes)
+        })
+        .collect()
+}
+
+/// Merge the same packet from several runs, wildcarding bytes that differ
+/// The packets must have the same length.
+fn merge_packet(runs: &[Vec<&str>]) -> String {
+    (0..runs[0].len())
+        .map(|i| {
+            let first = runs[0][i];
+            if runs.iter().all(|r| r[i].eq_ignore_ascii_case(first)) {
+                first.to_uppercase()
+            } else {
+                WILDCARD_BYTE.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Count wildcard bytes in a merged capture
+pub fn count_wildcards(steps: &[StepOutput]) -> usize {
+    steps
+        .iter()
+        .flat_map(|s| std::iter::once(&s.packets).chain(&s.variants))
+        .flatten()
+        .map(|p| p.split_whitespace().filter(|b| *b == WILDCARD_BYTE).count())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capture(steps: &[&[&str]]) -> Vec<StepOutput> {
+        steps
+            .iter()
+            .enumerate()
+            .map(|(index, packets)| StepOutput {
+                step_index: index + 1,
+                step_name: format!("Step {}", index + 1),
+                packets: packets.iter().map(|p| p.to_string()).collect(),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn varying_bytes_become_wildcards() {
+        let (merged, warnings) = merge_captures(&[capture(&[&["01 02 03"]]), capture(&[&["01 FF 03"]])]);
+        assert!(warnings.is_empty());
+        assert_eq!(merged[0].packets, vec!["01 ?? 03"]);
+        assert!(merged[0].variants.is_empty());
+        assert_eq!(count_wildcards(&merged), 1);
+    }
+
+    #[test]
+    fn packets_of_other_lengths_are_kept_as_variants() {
+        let captures = [
+            capture(&[&["01 02", "03"]]),
+            capture(&[&["01 02 00", "03"]]),
+            capture(&[&["01 05", "03"]]),
+        ];
+        let (merged, warnings) = merge_captures(&captures);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(merged[0].packets, vec!["01 ??", "03"]);
+        assert_eq!(merged[0].variants, vec![vec!["01 02 00".to_string(), "03".to_string()]]);
+
+        // The merged golden matches every run
+        for run in &captures {
+            assert!(crate::compare::compare_steps(&merged, run)[0].is_match());
+        }
+    }
+
+    #[test]
+    fn input_variants_are_kept() {
+        let mut first = capture(&[&["01"]]);
+        first[0].variants = vec![vec!["02".to_string()]];
+        let (merged, _) = merge_captures(&[first, capture(&[&["01"]])]);
+        assert_eq!(merged[0].variants, vec![vec!["02".to_string()]]);
+    }
+
+    #[test]
+    fn extra_steps_are_dropped_with_a_warning() {
+        let (merged, warnings) = merge_captures(&[capture(&[&["01"], &["02"]]), capture(&[&["01"]])]);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(warnings.len(), 1);
+    }
+}
@@ -0,0 +1,96 @@
+//! Live USB sniffing without a scenario
+//!
+//! The `monitor` command runs the capture standalone and prints every kept
+//! packet as it arrives, optionally with its decoded command. Decoding uses the
+//! primary device's HID report descriptor when it can be read, so PID devices
+//! decode field by field.
+
+use crate::cancel::CancellationToken;
+use crate::error::FFBError;
+use crate::hid_descriptor::{self, ReportDescriptor};
+use crate::normalize::decode_packet_with;
+use crate::output::{packet_json, Output};
+use crate::usb_monitor::{capture_clock, format_hex, CaptureConfig, PacketDirection, UsbMonitor, UsbPacket};
+use std::time::{Duration, Instant};
+
+/// How often captured packets are collected and printed
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Capture and print packets until `duration` elapses or `cancel` is cancelled
+/// Packets are decoded with `descriptor`, or else the primary device's own one
+/// In JSON mode every packet is also emitted as one JSON line
+pub fn run(
+    capture: CaptureConfig,
+    decode: bool,
+    descriptor: Option<ReportDescriptor>,
+    duration: Option<Duration>,
+    cancel: &CancellationToken,
+    output: &mut Output,
+) -> anyhow::Result<()> {
+    let descriptor = descriptor.or_else(|| device_descriptor(&capture));
+    let mut monitor = UsbMonitor::with_config(capture);
+    monitor.set_cancellation(cancel.clone());
+    monitor
+        .start_capture()
+        .map_err(|e| FFBError::InitializationFailed(format!("Failed to start USB capture: {}", e)))?;
+    let capture_start = capture_clock();
+    let started = Instant::now();
+
+    match duration {
+        Some(duration) => println!("Monitoring USB traffic for {} s...", duration.as_secs()),
+        None => println!("Monitoring USB traffic (Ctrl+C to stop)..."),
+    }
+
+    while duration.is_none_or(|duration| started.elapsed() < duration) && cancel.sleep(POLL_INTERVAL) {
+        for packet in monitor.get_packets().map_err(FFBError::capture)? {
+            let decoded = decode_packet_with(&packet.data, descriptor.as_ref());
+            let mut json = packet_json(&packet);
+            json["decoded"] = decoded.clone().into();
+            output.emit(&json)?;
+            println!("{}", format_packet(&packet, capture_start));
+            if let Some(decoded) = decoded.filter(|_| decode) {
+                println!("    -> {}", decoded);
+            }
+        }
+    }
+
+    monitor.stop_capture();
+    println!("Capture total: {}", monitor.stats().describe(started.elapsed()));
+    Ok(())
+}
+
+/// Report descriptor of the primary capture device, None if it has no PID
+/// reports or cannot be read
+pub fn device_descriptor(capture: &CaptureConfig) -> Option<ReportDescriptor> {
+    let device = capture.devices.first()?;
+    let bytes = hid_descriptor::read_report_descriptor(device).ok()?;
+    let descriptor = ReportDescriptor::parse(&bytes).ok().filter(ReportDescriptor::has_pid)?;
+    println!("Decoding PID reports with the report descriptor of {}", device);
+    Some(descriptor)
+}
+
+/// One console line per packet: time since `capture_start`, direction, endpoint,
+/// transfer type, source device (with device filters) and payload
+pub fn format_packet(packet: &UsbPacket, capture_start: Duration) -> String {
+    let direction = match packet.direction {
+        PacketDirection::HostToDevice => "OUT",
+        PacketDirection::DeviceToHost => "IN ",
+    };
+    let transfer = match packet.transfer_type {
+        0 => "isoc",
+        1 => "intr",
+        2 => "ctrl",
+        3 => "bulk",
+        _ => "????",
+    };
+    let source = packet.source.map(|device| format!(" {}", device)).unwrap_or_default();
+    format!(
+        "[{:>11.6}] {} ep{} {}{}  {}",
+        packet.timestamp.saturating_sub(capture_start).as_secs_f64(),
+        direction,
+        packet.endpoint,
+        transfer,
+        source,
+        format_hex(&packet.data)
+    )
+}
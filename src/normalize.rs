@@ -0,0 +1,164 @@
+//! Normalization of driver output to a canonical command representation
+//!
+//! USB captures (SDL driver) and generated reports (SIMAGIC driver) differ in
+//! transport framing. Normalizing both sides lets output from different
+//! drivers be compared for the same scenario.
+//!
+//! Known packets are decoded by the declarative decoders in `spec`: the shipped
+//! SIMAGIC, Logitech, Fanatec and Thrustmaster ones, and any loaded with
+//! `--decoder`. `wireshark` turns the same decoders into a Lua dissector.
+
+pub mod spec;
+pub mod wireshark;
+
+use crate::compare::{parse_hex, WILDCARD_BYTE};
+use crate::expect::normalize_hex;
+use crate::hid_descriptor::ReportDescriptor;
+use crate::usb_monitor::format_hex;
+use crate::{DeviceCapture, StepOutput};
+
+/// HID class SET_REPORT request (bmRequestType 0x21, bRequest 0x09)
+const SET_REPORT_SETUP: [u8; 2] = [0x21, 0x09];
+const SETUP_PACKET_LEN: usize = 8;
+/// Size of a SIMAGIC FFB report
+const REPORT_LEN: usize = 21;
+
+/// Strip transport framing from a captured packet
+/// - control transfers carrying a SET_REPORT setup packet lose the 8-byte setup stage
+/// - zero padding beyond the report size is dropped
+pub fn strip_framing(data: &[u8]) -> &[u8] {
+    let data = strip_setup(data);
+    if data.len() > REPORT_LEN && data[REPORT_LEN..].iter().all(|&b| b == 0) {
+        &data[..REPORT_LEN]
+    } else {
+        data
+    }
+}
+
+/// The report of a SET_REPORT control transfer, other packets unchanged
+pub fn strip_setup(data: &[u8]) -> &[u8] {
+    if data.len() > SETUP_PACKET_LEN && data[..2] == SET_REPORT_SETUP {
+        &data[SETUP_PACKET_LEN..]
+    } else {
+        data
+    }
+}
+
+/// Decoded command of a captured packet, None if no decoder knows it
+pub fn decode_packet(data: &[u8]) -> Option<String> {
+    spec::decode(strip_framing(data))
+}
+
+/// Decoded command of a captured packet with its fields; field offsets count from
+/// the start of the captured packet, including any setup stage
+pub fn decode_command(data: &[u8]) -> Option<spec::DecodedCommand> {
+    let setup = data.len() - strip_setup(data).len();
+    let mut command = spec::decode_command(strip_framing(data))?;
+    for field in &mut command.fields {
+        field.offset += setup;
+    }
+    Some(command)
+}
+
+/// Decoded command of a captured packet, field by field from the device's report
+/// descriptor when given, otherwise (or if no report matches) by the vendor decoders
+pub fn decode_packet_with(data: &[u8], descriptor: Option<&ReportDescriptor>) -> Option<String> {
+    descriptor
+        .and_then(|descriptor| descriptor.decode(strip_setup(data)))
+        .or_else(|| decode_packet(data))
+}
+
+/// Convert a single packet to its canonical representation
+/// Decoded command if the packet is a known report, normalized hex otherwise
+pub fn normalize_packet(packet: &str) -> String {
+    match parse_hex(packet) {
+        Some(bytes) => decode_packet(&bytes).unwrap_or_else(|| format_hex(strip_framing(&bytes))),
+        None => normalize_hex(packet),
+    }
+}
+
+/// Normalize all packets of all steps
+pub fn normalize_steps(steps: &[StepOutput]) -> Vec<StepOutput> {
+    steps
+        .iter()
+        .map(|step| StepOutput {
+            step_index: step.step_index,
+            step_name: step.step_name.clone(),
+            packets: step.packets.iter().map(|p| normalize_packet(p)).collect(),
+            variants: step
+                .variants
+                .iter()
+                .map(|v| v.iter().map(|p| normalize_packet(p)).collect())
+                .collect(),
+            latencies_us: step.latencies_us.clone(),
+            wire_latency_us: step.wire_latency_us,
+            timestamps_us: step.timestamps_us.clone(),
+            axis: step.axis.clone(),
+            devices: step
+                .devices
+                .iter()
+                .map(|d| DeviceCapture {
+                    device: d.device.clone(),
+                    packets: d.packets.iter().map(|p| normalize_packet(p)).collect(),
+                })
+                .collect(),
+            health: step.health.clone(),
+        })
+        .collect()
+}
+
+/// Normalize expected and actual steps to be compared with each other
+/// Packets are normalized as by `normalize_steps`, except at the positions of a
+/// step where an expected sequence has a wildcard byte: a wildcard packet can't be
+/// decoded, so there both sides keep their hex with the framing stripped and the
+/// wildcards still match.
+pub fn normalize_for_compare(expected: &[StepOutput], actual: &[StepOutput]) -> (Vec<StepOutput>, Vec<StepOutput>) {
+    let mut normalized_expected = normalize_steps(expected);
+    let mut normalized_actual = normalize_steps(actual);
+    for (position, step) in expected.iter().enumerate() {
+        let sequences = || std::iter::once(&step.packets).chain(&step.variants);
+        let wildcards: Vec<usize> = (0..sequences().map(Vec::len).max().unwrap_or(0))
+            .filter(|&index| {
+                sequences().any(|packets| {
+                    packets
+                        .get(index)
+                        .is_some_and(|packet| packet.split_whitespace().any(|b| b == WILDCARD_BYTE))
+                })
+            })
+            .collect();
+        if wildcards.is_empty() {
+            continue;
+        }
+        let normalized = &mut normalized_expected[position];
+        keep_hex(&step.packets, &mut normalized.packets, &wildcards);
+        for (variant, normalized) in step.variants.iter().zip(&mut normalized.variants) {
+            keep_hex(variant, normalized, &wildcards);
+        }
+        if let (Some(step), Some(normalized)) = (actual.get(position), normalized_actual.get_mut(position)) {
+            keep_hex(&step.packets, &mut normalized.packets, &wildcards);
+        }
+    }
+    (normalized_expected, normalized_actual)
+}
+
+/// Put the framing-stripped hex of the packets at `indices` back in `normalized`
+fn keep_hex(packets: &[String], normalized: &mut [String], indices: &[usize]) {
+    for &index in indices {
+        if let (Some(packet), Some(normalized)) = (packets.get(index), normalized.get_mut(index)) {
+            *normalized = strip_framing_hex(packet);
+        }
+    }
+}
+
+/// `strip_framing` on a hex packet that may hold wildcard bytes, which count as
+/// neither setup nor padding bytes
+fn strip_framing_hex(packet: &str) -> String {
+    let mut bytes: Vec<String> = packet.split_whitespace().map(|b| b.to_uppercase()).collect();
+    if bytes.len() > SETUP_PACKET_LEN && bytes[..2] == ["21", "09"] {
+        bytes.drain(..SETUP_PACKET_LEN);
+    }
+    if bytes.len() > REPORT_LEN && bytes[REPORT_LEN..].iter().all(|b| b == "00") {
+        bytes.truncate(REPORT_LEN);
+    }
+    bytes.join(" ")
+}
@@ -0,0 +1,176 @@
+
+-- Interpreter of the specs above, the same way ffb_replay decodes packets
+
+local ffb = Proto("ffb", "Force feedback commands (ffb_replay)")
+local f_decoder = ProtoField.string("ffb.decoder", "Decoder")
+local f_command = ProtoField.string("ffb.command", "Command")
+local f_operation = ProtoField.string("ffb.operation", "Operation")
+
+-- One filterable field per field name, e.g. ffb.magnitude
+local field_protos = {}
+local all_fields = { f_decoder, f_command, f_operation }
+for _, spec in ipairs(specs) do
+    for _, command in ipairs(spec.commands) do
+        for _, field in ipairs(command.fields) do
+            if not field_protos[field.key] then
+                field_protos[field.key] = ProtoField.string("ffb." .. field.key, field.name)
+                all_fields[#all_fields + 1] = field_protos[field.key]
+            end
+        end
+    end
+end
+ffb.fields = all_fields
+
+-- Bitwise and without a bit library, for any Lua version
+local function band(a, b)
+    local result, bit = 0, 1
+    while a > 0 and b > 0 do
+        if a % 2 == 1 and b % 2 == 1 then
+            result = result + bit
+        end
+        a, b, bit = math.floor(a / 2), math.floor(b / 2), bit * 2
+    end
+    return result
+end
+
+local function bit_length(value)
+    local bits = 0
+    while value > 0 do
+        value = math.floor(value / 2)
+        bits = bits + 1
+    end
+    return bits
+end
+
+-- Shortest text reading back as the same number, like Rust prints floats
+local function shortest(number)
+    for precision = 1, 17 do
+        local text = string.format("%." .. precision .. "g", number)
+        if tonumber(text) == number then
+            return text
+        end
+    end
+    return string.format("%.17g", number)
+end
+
+-- Display value of a field, nil if the packet is too short for it
+local function render(field, bytes, length)
+    if field.offset + field.width > length then
+        return nil
+    end
+    if field.display == "bytes" then
+        local parts = {}
+        for i = 0, field.width - 1 do
+            parts[#parts + 1] = string.format("%02X", bytes[field.offset + i])
+        end
+        return table.concat(parts, " ") .. field.unit
+    end
+
+    local value = 0
+    for i = 0, field.width - 1 do
+        local index = field.big_endian and field.offset + i or field.offset + field.width - 1 - i
+        value = value * 256 + bytes[index]
+    end
+    local bits = field.width * 8
+    if field.mask then
+        value = band(value, field.mask)
+        bits = bit_length(field.mask)
+    end
+    value = math.floor(value / 2 ^ field.shift)
+    bits = math.max(bits - field.shift, 1)
+
+    local number = value
+    if field.signed and value >= 2 ^ (bits - 1) then
+        number = value - 2 ^ bits
+    end
+    local name = field.values[number]
+    if name then
+        return name
+    end
+
+    local text
+    if next(field.values) ~= nil or field.display == "hex" then
+        text = string.format("0x%0" .. math.ceil(bits / 4) .. "X", value)
+    elseif field.display == "bin" then
+        local digits = {}
+        for i = bits - 1, 0, -1 do
+            digits[#digits + 1] = math.floor(value / 2 ^ i) % 2 == 1 and "1" or "0"
+        end
+        text = table.concat(digits)
+    elseif field.scale then
+        text = shortest(number * field.scale)
+    else
+        text = string.format("%d", number)
+    end
+    return text .. field.unit
+end
+
+-- Text and field values of a packet decoded by `command`, nil if it does not match
+local function decode(command, bytes, length)
+    for _, rule in ipairs(command.match) do
+        local byte = bytes[rule[1]]
+        if byte == nil or band(byte, rule[3]) ~= rule[2] then
+            return nil
+        end
+    end
+    local values = {}
+    for i, field in ipairs(command.fields) do
+        values[i] = render(field, bytes, length)
+        if values[i] == nil then
+            return nil
+        end
+    end
+
+    if command.format then
+        local text = command.format:gsub("{([^}]*)}", function(name)
+            for i, field in ipairs(command.fields) do
+                if field.name == name then
+                    return values[i]
+                end
+            end
+        end)
+        return text, values
+    end
+    local parts = { command.name }
+    for i, field in ipairs(command.fields) do
+        parts[#parts + 1] = field.name .. "=" .. values[i]
+    end
+    return table.concat(parts, " "), values
+end
+
+local function dissect(tvb, pinfo, tree)
+    local length = tvb:len()
+    if length == 0 then
+        return false
+    end
+    local bytes = {}
+    for i = 0, length - 1 do
+        bytes[i] = tvb(i, 1):uint()
+    end
+
+    for _, spec in ipairs(specs) do
+        if length >= spec.min_length and (spec.max_length == nil or length <= spec.max_length) then
+            for _, command in ipairs(spec.commands) do
+                local text, values = decode(command, bytes, length)
+                if text then
+                    pinfo.cols.protocol = "FFB"
+                    pinfo.cols.info = text
+                    local subtree = tree:add(ffb, tvb(), "FFB (" .. spec.name .. "): " .. text)
+                    subtree:add(f_decoder, tvb(), spec.name)
+                    subtree:add(f_command, tvb(), command.name)
+                    subtree:add(f_operation, tvb(), command.operation)
+                    for i, field in ipairs(command.fields) do
+                        subtree:add(field_protos[field.key], tvb(field.offset, field.width), values[i])
+                    end
+                    return true
+                end
+            end
+        end
+    end
+    return false
+end
+
+ffb.dissector = dissect
+for _, table_name in ipairs({ "usb.interrupt", "usb.control", "usb.bulk" }) do
+    ffb:register_heuristic(table_name, dissect)
+end
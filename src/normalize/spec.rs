@@ -0,0 +1,369 @@
+//! Declarative protocol decoders
+//!
+//! A decoder spec is a YAML file describing a device's commands: which bytes
+//! identify each command and where its fields are. Reverse engineers can describe
+//! a new wheel's protocol and iterate on it without writing Rust (`--decoder`).
+//! The shipped SIMAGIC, Logitech, Fanatec and Thrustmaster decoders (`decoders/`)
+//! use the same format.
+//!
+//! ```yaml
+//! name: Example wheel
+//! min_length: 8
+//! commands:
+//!   - name: SetForce
+//!     match:
+//!       - { offset: 0, value: 0x10 }
+//!     fields:
+//!       - { name: slot, offset: 1 }
+//!       - { name: force, offset: 2, width: 2, signed: true, scale: 0.01, unit: "%" }
+//! ```
+//!
+//! decodes `10 02 88 13 ...` as `SetForce slot=2 force=50%`.
+//!
+//! Commands may name the logical `operation` they perform (set magnitude, start,
+//! ...) and fields the scenario effect field they carry (`source: magnitude`),
+//! which the traceability tables of `trace` show.
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{LazyLock, RwLock};
+
+/// Shipped decoders, tried after any registered ones
+const BUILTIN: [(&str, &str); 4] = [
+    ("decoders/simagic.yaml", include_str!("../../decoders/simagic.yaml")),
+    ("decoders/logitech.yaml", include_str!("../../decoders/logitech.yaml")),
+    ("decoders/fanatec.yaml", include_str!("../../decoders/fanatec.yaml")),
+    ("decoders/thrustmaster.yaml", include_str!("../../decoders/thrustmaster.yaml")),
+];
+
+static BUILTIN_DECODERS: LazyLock<Vec<DecoderSpec>> = LazyLock::new(|| {
+    BUILTIN
+        .iter()
+        .map(|(name, source)| DecoderSpec::parse(source).unwrap_or_else(|e| panic!("{}: {}", name, e)))
+        .collect()
+});
+
+static REGISTERED: RwLock<Vec<DecoderSpec>> = RwLock::new(Vec::new());
+
+/// A device protocol: its commands, tried in order
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DecoderSpec {
+    pub name: String,
+    /// Packets shorter than this are never decoded by this spec
+    #[serde(default)]
+    pub min_length: usize,
+    #[serde(default)]
+    pub max_length: Option<usize>,
+    pub commands: Vec<CommandSpec>,
+}
+
+/// One command, identified by its `match` rules
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandSpec {
+    pub name: String,
+    /// Every rule must hold for the command to match
+    #[serde(default, rename = "match")]
+    pub matches: Vec<MatchRule>,
+    #[serde(default)]
+    pub fields: Vec<FieldSpec>,
+    /// Output with `{field}` placeholders; default "<name> field=value ..."
+    #[serde(default)]
+    pub format: Option<String>,
+    /// Logical operation, e.g. "set magnitude"; default the command name
+    #[serde(default)]
+    pub operation: Option<String>,
+}
+
+/// Byte `offset` masked with `mask` equals `value`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MatchRule {
+    pub offset: usize,
+    pub value: u8,
+    #[serde(default = "default_mask")]
+    pub mask: u8,
+}
+
+fn default_mask() -> u8 {
+    0xFF
+}
+
+/// A field of `width` bytes at `offset`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldSpec {
+    pub name: String,
+    pub offset: usize,
+    /// Bytes: 1, 2 or 4 for numbers, any for `display: bytes`
+    #[serde(default = "default_width")]
+    pub width: usize,
+    #[serde(default)]
+    pub endian: Endian,
+    /// Two's complement, after `mask` and `shift`
+    #[serde(default)]
+    pub signed: bool,
+    /// Bits of the value to keep, before `shift`
+    #[serde(default)]
+    pub mask: Option<u32>,
+    #[serde(default)]
+    pub shift: u32,
+    /// Multiplier for the displayed value
+    #[serde(default)]
+    pub scale: Option<f64>,
+    /// Appended to the value, e.g. "ms"
+    #[serde(default)]
+    pub unit: String,
+    #[serde(default)]
+    pub display: Display,
+    /// Names of known values; other values are shown in hex
+    #[serde(default)]
+    pub values: BTreeMap<i64, String>,
+    /// Scenario effect field the value is derived from, e.g. "x_axis.offset";
+    /// alternatives separated by `|`, the first the effect has applies
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+fn default_width() -> usize {
+    1
+}
+
+/// A packet decoded by a command
+#[derive(Debug, Clone)]
+pub struct DecodedCommand {
+    pub name: String,
+    pub operation: String,
+    pub fields: Vec<DecodedField>,
+    /// The command as text, e.g. "SetForce slot=2 force=50%"
+    pub text: String,
+}
+
+/// A field of a decoded command
+#[derive(Debug, Clone)]
+pub struct DecodedField {
+    pub name: String,
+    /// Position in the decoded bytes
+    pub offset: usize,
+    pub width: usize,
+    /// Display value
+    pub value: String,
+    /// Numeric value with the scale applied, None for `display: bytes`
+    pub number: Option<f64>,
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Display {
+    #[default]
+    Dec,
+    Hex,
+    Bin,
+    /// The raw bytes in hex
+    Bytes,
+}
+
+impl DecoderSpec {
+    /// Parse and check a spec
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let spec: DecoderSpec = serde_yaml::from_str(source).map_err(|e| e.to_string())?;
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Self::parse(&source).map_err(|e| format!("Invalid decoder {}: {}", path.display(), e))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        for command in &self.commands {
+            for field in &command.fields {
+                let numeric = field.display != Display::Bytes;
+                if field.width == 0 || (numeric && ![1, 2, 4].contains(&field.width)) {
+                    return Err(format!(
+                        "command {}: field {} has width {}, expected 1, 2 or 4 bytes (any for display: bytes)",
+                        command.name, field.name, field.width
+                    ));
+                }
+                if field.shift >= 32 {
+                    return Err(format!("command {}: field {} shifts by {} bits", command.name, field.name, field.shift));
+                }
+            }
+            if let Some(format) = &command.format {
+                for placeholder in placeholders(format) {
+                    if !command.fields.iter().any(|f| f.name == placeholder) {
+                        return Err(format!("command {}: format uses unknown field {{{}}}", command.name, placeholder));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Decode `bytes` with the first matching command
+    /// Returns None if no command matches
+    pub fn decode(&self, bytes: &[u8]) -> Option<String> {
+        self.decode_command(bytes).map(|command| command.text)
+    }
+
+    /// Decode `bytes` field by field with the first matching command
+    pub fn decode_command(&self, bytes: &[u8]) -> Option<DecodedCommand> {
+        if bytes.len() < self.min_length || self.max_length.is_some_and(|max| bytes.len() > max) {
+            return None;
+        }
+        self.commands.iter().find_map(|command| command.decode(bytes))
+    }
+}
+
+impl CommandSpec {
+    fn decode(&self, bytes: &[u8]) -> Option<DecodedCommand> {
+        let matches = self
+            .matches
+            .iter()
+            .all(|rule| bytes.get(rule.offset).is_some_and(|b| b & rule.mask == rule.value));
+        if !matches {
+            return None;
+        }
+
+        let fields = self
+            .fields
+            .iter()
+            .map(|field| {
+                Some(DecodedField {
+                    name: field.name.clone(),
+                    offset: field.offset,
+                    width: field.width,
+                    value: field.render(bytes)?,
+                    number: field.number(bytes),
+                    source: field.source.clone(),
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let text = match &self.format {
+            Some(format) => fields.iter().fold(format.clone(), |text, field| {
+                text.replace(&format!("{{{}}}", field.name), &field.value)
+            }),
+            None => std::iter::once(self.name.clone())
+                .chain(fields.iter().map(|field| format!("{}={}", field.name, field.value)))
+                .collect::<Vec<_>>()
+                .join(" "),
+        };
+        Some(DecodedCommand {
+            name: self.name.clone(),
+            operation: self.operation.clone().unwrap_or_else(|| self.name.clone()),
+            fields,
+            text,
+        })
+    }
+}
+
+impl FieldSpec {
+    /// Display value, None if the packet is too short for the field
+    fn render(&self, bytes: &[u8]) -> Option<String> {
+        if self.display == Display::Bytes {
+            let raw = bytes.get(self.offset..self.offset + self.width)?;
+            let hex: Vec<String> = raw.iter().map(|b| format!("{:02X}", b)).collect();
+            return Some(format!("{}{}", hex.join(" "), self.unit));
+        }
+        let (value, bits, number) = self.extract(bytes)?;
+
+        let text = match self.values.get(&number) {
+            Some(name) => return Some(name.clone()),
+            None if !self.values.is_empty() => format!("0x{:0digits$X}", value, digits = bits.div_ceil(4) as usize),
+            None => match (self.display, self.scale) {
+                (Display::Hex, _) => format!("0x{:0digits$X}", value, digits = bits.div_ceil(4) as usize),
+                (Display::Bin, _) => format!("{:0digits$b}", value, digits = bits as usize),
+                (_, Some(scale)) => format!("{}", number as f64 * scale),
+                _ => number.to_string(),
+            },
+        };
+        Some(format!("{}{}", text, self.unit))
+    }
+
+    /// Numeric value with the scale applied, None for `display: bytes` or a too short packet
+    fn number(&self, bytes: &[u8]) -> Option<f64> {
+        if self.display == Display::Bytes {
+            return None;
+        }
+        let (_, _, number) = self.extract(bytes)?;
+        Some(number as f64 * self.scale.unwrap_or(1.0))
+    }
+
+    /// Masked and shifted value, its width in bits and its (sign-extended) number
+    fn extract(&self, bytes: &[u8]) -> Option<(u32, u32, i64)> {
+        let raw = bytes.get(self.offset..self.offset + self.width)?;
+        let mut value = match self.endian {
+            Endian::Little => raw.iter().rev().fold(0u32, |v, &b| (v << 8) | b as u32),
+            Endian::Big => raw.iter().fold(0u32, |v, &b| (v << 8) | b as u32),
+        };
+        let mut bits = self.width as u32 * 8;
+        if let Some(mask) = self.mask {
+            value &= mask;
+            bits = 32 - mask.leading_zeros();
+        }
+        value >>= self.shift;
+        bits = bits.saturating_sub(self.shift).max(1);
+
+        let number = if self.signed && bits < 32 && value & (1 << (bits - 1)) != 0 {
+            value as i64 - (1i64 << bits)
+        } else if self.signed {
+            value as i32 as i64
+        } else {
+            value as i64
+        };
+        Some((value, bits, number))
+    }
+}
+
+/// Field names used as `{name}` in a format
+fn placeholders(format: &str) -> Vec<&str> {
+    format
+        .split('{')
+        .skip(1)
+        .filter_map(|part| part.split_once('}').map(|(name, _)| name))
+        .collect()
+}
+
+/// Add a decoder, tried after earlier registered ones but before the shipped ones
+pub fn register(spec: DecoderSpec) {
+    REGISTERED.write().unwrap().push(spec);
+}
+
+/// Decode with the registered decoders, then the shipped ones
+pub fn decode(bytes: &[u8]) -> Option<String> {
+    decode_command(bytes).map(|command| command.text)
+}
+
+/// Decode field by field with the registered decoders, then the shipped ones
+pub fn decode_command(bytes: &[u8]) -> Option<DecodedCommand> {
+    let registered = REGISTERED.read().unwrap();
+    registered
+        .iter()
+        .chain(BUILTIN_DECODERS.iter())
+        .find_map(|spec| spec.decode_command(bytes))
+}
+
+/// Every decoder in the order packets are decoded: registered, then shipped
+pub fn all() -> Vec<DecoderSpec> {
+    let registered = REGISTERED.read().unwrap();
+    registered.iter().chain(BUILTIN_DECODERS.iter()).cloned().collect()
+}
+
+/// The shipped decoders
+pub fn builtin() -> &'static [DecoderSpec] {
+    &BUILTIN_DECODERS
+}
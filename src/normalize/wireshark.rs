@@ -0,0 +1,127 @@
+//! Wireshark Lua dissector generated from the decoder specs
+//!
+//! The dissector holds the specs as Lua tables and decodes USB payloads the same
+//! way `spec` does, so a protocol described once in YAML shows up in Wireshark
+//! with the same command names and field values as in `decode`. Every field is
+//! filterable as `ffb.<field name>` (e.g. `ffb.magnitude == "-50%"`).
+//!
+//! Install by copying the file into Wireshark's personal plugins directory
+//! (Help > About Wireshark > Folders).
+
+use super::spec::{CommandSpec, DecoderSpec, Display, Endian, FieldSpec};
+use std::fmt::Write as _;
+
+/// Interpreter of the spec tables
+const INTERPRETER: &str = include_str!("dissector.lua");
+
+/// Lua source of a dissector decoding with `specs`, in order
+pub fn lua_dissector(specs: &[DecoderSpec]) -> String {
+    let mut lua = String::new();
+    let _ = writeln!(lua, "-- Force feedback dissector generated by ffb_replay from its decoder specs:");
+    for spec in specs {
+        let _ = writeln!(lua, "--   {}", spec.name.replace('\n', " "));
+    }
+    let _ = writeln!(lua, "-- Regenerate with `ffb_replay dissector` instead of editing.");
+    let _ = writeln!(lua);
+    let _ = writeln!(lua, "local specs = {{");
+    for spec in specs {
+        let _ = writeln!(lua, "    {{");
+        let _ = writeln!(lua, "        name = {},", string(&spec.name));
+        let _ = writeln!(lua, "        min_length = {},", spec.min_length);
+        let _ = writeln!(lua, "        max_length = {},", optional(spec.max_length));
+        let _ = writeln!(lua, "        commands = {{");
+        for command in &spec.commands {
+            write_command(&mut lua, command);
+        }
+        let _ = writeln!(lua, "        }},");
+        let _ = writeln!(lua, "    }},");
+    }
+    let _ = writeln!(lua, "}}");
+    lua.push_str(INTERPRETER);
+    lua
+}
+
+fn write_command(lua: &mut String, command: &CommandSpec) {
+    let rules: Vec<String> = command
+        .matches
+        .iter()
+        .map(|rule| format!("{{ {}, {}, {} }}", rule.offset, rule.value, rule.mask))
+        .collect();
+    let _ = writeln!(lua, "            {{");
+    let _ = writeln!(lua, "                name = {},", string(&command.name));
+    let _ = writeln!(
+        lua,
+        "                operation = {},",
+        string(command.operation.as_deref().unwrap_or(&command.name))
+    );
+    let _ = writeln!(
+        lua,
+        "                format = {},",
+        command.format.as_deref().map_or("nil".to_string(), string)
+    );
+    let _ = writeln!(lua, "                match = {{ {} }},", rules.join(", "));
+    let _ = writeln!(lua, "                fields = {{");
+    for field in &command.fields {
+        let _ = writeln!(lua, "                    {},", field_table(field));
+    }
+    let _ = writeln!(lua, "                }},");
+    let _ = writeln!(lua, "            }},");
+}
+
+fn field_table(field: &FieldSpec) -> String {
+    let display = match field.display {
+        Display::Dec => "dec",
+        Display::Hex => "hex",
+        Display::Bin => "bin",
+        Display::Bytes => "bytes",
+    };
+    let values: Vec<String> = field
+        .values
+        .iter()
+        .map(|(value, name)| format!("[{}] = {}", value, string(name)))
+        .collect();
+    format!(
+        "{{ name = {}, key = {}, offset = {}, width = {}, big_endian = {}, signed = {}, mask = {}, \
+         shift = {}, scale = {}, unit = {}, display = {}, values = {{ {} }} }}",
+        string(&field.name),
+        string(&field_key(&field.name)),
+        field.offset,
+        field.width,
+        matches!(field.endian, Endian::Big),
+        field.signed,
+        optional(field.mask),
+        field.shift,
+        field.scale.map_or("nil".to_string(), |scale| format!("{:?}", scale)),
+        string(&field.unit),
+        string(display),
+        values.join(", ")
+    )
+}
+
+/// Display filter name of a field: lowercase letters, digits and underscores
+fn field_key(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect()
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map_or("nil".to_string(), |v| v.to_string())
+}
+
+/// Quoted Lua string; non-ASCII text stays as its UTF-8 bytes
+fn string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_ascii_control() => {
+                let _ = write!(quoted, "\\{:03}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
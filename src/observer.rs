@@ -0,0 +1,221 @@
+//! Playback progress reporting
+//!
+//! `Scenario::play_observed` reports each stage of playback to an observer:
+//! the console prints of the plain CLI, the TUI dashboard, or an embedder's own
+//! callbacks.
+
+use crate::cadence::IntervalHistogram;
+use crate::capabilities::CompatibilityReport;
+use crate::driver::FfbDriver;
+use crate::error::FFBError;
+use crate::usb_monitor::UsbPacket;
+use crate::telemetry::AxisSummary;
+use crate::{force_curve, latency, waveform, Scenario, ScenarioStep, StepOutput};
+
+/// Receives playback progress; all methods default to doing nothing
+pub trait PlaybackObserver {
+    /// The scenario was checked against the driver's capabilities before playing
+    /// Not called for drivers that don't know their capabilities
+    fn capabilities_checked(&mut self, _report: &CompatibilityReport) {}
+
+    /// `driver` is initialized and about to play, e.g. to subscribe to its live packets
+    fn scenario_started(&mut self, _scenario: &Scenario, _driver: &dyn FfbDriver) {}
+
+    /// `iterations` is u32::MAX for scenarios that loop forever
+    fn iteration_started(&mut self, _iteration: u32, _iterations: u32) {}
+
+    /// `index` is the 0-based step position in the scenario
+    fn step_started(&mut self, _index: usize, _label: &str, _duration_ms: u32) {}
+
+    /// The driver changed how the step plays, e.g. its force limit cut the effect's
+    /// magnitude; before the first step for notes from the driver's setup
+    fn driver_note(&mut self, _index: Option<usize>, _note: &str) {}
+
+    /// A packet of the driver's capture arrived while a step played
+    /// Not called for drivers that do not capture
+    fn packet_captured(&mut self, _packet: &UsbPacket) {}
+
+    /// The driver failed the step; playback continues with an empty output
+    fn step_failed(&mut self, _index: usize, _error: &FFBError) {}
+
+    /// `capture` is the driver's capture statistics for the step, if any
+    fn step_finished(&mut self, _index: usize, _output: &StepOutput, _capture: Option<&str>) {}
+
+    /// Every step of the iteration was played
+    fn iteration_finished(&mut self) {}
+
+    /// Playback ended, also when it was cancelled; not called when it fails
+    fn scenario_finished(&mut self) {}
+
+    /// Checked before each step; true stops playback with the steps played so far
+    fn cancelled(&self) -> bool {
+        false
+    }
+}
+
+/// Lets a borrowed observer be handed to `ScenarioRunner::observer`
+impl<T: PlaybackObserver + ?Sized> PlaybackObserver for &mut T {
+    fn capabilities_checked(&mut self, report: &CompatibilityReport) {
+        (**self).capabilities_checked(report)
+    }
+
+    fn scenario_started(&mut self, scenario: &Scenario, driver: &dyn FfbDriver) {
+        (**self).scenario_started(scenario, driver)
+    }
+
+    fn iteration_started(&mut self, iteration: u32, iterations: u32) {
+        (**self).iteration_started(iteration, iterations)
+    }
+
+    fn step_started(&mut self, index: usize, label: &str, duration_ms: u32) {
+        (**self).step_started(index, label, duration_ms)
+    }
+
+    fn driver_note(&mut self, index: Option<usize>, note: &str) {
+        (**self).driver_note(index, note)
+    }
+
+    fn packet_captured(&mut self, packet: &UsbPacket) {
+        (**self).packet_captured(packet)
+    }
+
+    fn step_failed(&mut self, index: usize, error: &FFBError) {
+        (**self).step_failed(index, error)
+    }
+
+    fn step_finished(&mut self, index: usize, output: &StepOutput, capture: Option<&str>) {
+        (**self).step_finished(index, output, capture)
+    }
+
+    fn iteration_finished(&mut self) {
+        (**self).iteration_finished()
+    }
+
+    fn scenario_finished(&mut self) {
+        (**self).scenario_finished()
+    }
+
+    fn cancelled(&self) -> bool {
+        (**self).cancelled()
+    }
+}
+
+/// Plain console output
+/// During the first iteration, periodic and ramp steps are previewed as a plot
+/// of their intended force, and captured steps as one of the decoded force
+#[derive(Debug, Default)]
+pub struct ConsoleObserver {
+    steps: Vec<ScenarioStep>,
+    preview: bool,
+}
+
+impl ConsoleObserver {
+    fn print_plot(title: &str, lines: Option<Vec<String>>) {
+        let Some(lines) = lines else {
+            return;
+        };
+        println!("    {}:", title);
+        for line in lines {
+            println!("      {}", line);
+        }
+    }
+}
+
+impl PlaybackObserver for ConsoleObserver {
+    fn capabilities_checked(&mut self, report: &CompatibilityReport) {
+        report.print();
+    }
+
+    fn scenario_started(&mut self, scenario: &Scenario, _driver: &dyn FfbDriver) {
+        self.steps = scenario.steps.clone();
+        println!("Starting scenario: {}", scenario.name);
+        if !scenario.description.is_empty() {
+            println!("  {}", scenario.description);
+        }
+        println!();
+        if scenario.loop_forever {
+            println!("WARNING: Infinite loop mode. Press Ctrl+C to stop.");
+        }
+    }
+
+    fn iteration_started(&mut self, iteration: u32, iterations: u32) {
+        self.preview = iteration == 0;
+        if iterations != u32::MAX {
+            println!("=== Iteration {}/{} ===", iteration + 1, iterations);
+        }
+    }
+
+    fn step_started(&mut self, index: usize, label: &str, duration_ms: u32) {
+        match self.steps.get(index).map(|step| step.effect.start_delay()).filter(|&delay| delay > 0) {
+            Some(delay) => println!(
+                "  Step {}: {} (duration: {} ms, start delay: {} ms)",
+                index + 1,
+                label,
+                duration_ms,
+                delay
+            ),
+            None => println!("  Step {}: {} (duration: {} ms)", index + 1, label, duration_ms),
+        }
+        if let Some(step) = self.steps.get(index).filter(|_| self.preview) {
+            Self::print_plot("Intended force", waveform::intended(&step.effect));
+        }
+    }
+
+    fn driver_note(&mut self, index: Option<usize>, note: &str) {
+        match index {
+            Some(_) => println!("    {}", note),
+            None => println!("{}", note),
+        }
+    }
+
+    fn step_failed(&mut self, _index: usize, error: &FFBError) {
+        eprintln!("    ERROR: Failed to execute effect: {}", error);
+    }
+
+    fn step_finished(&mut self, index: usize, output: &StepOutput, capture: Option<&str>) {
+        if let Some(capture) = capture {
+            println!("    Capture: {}", capture);
+        }
+        if !output.packets.is_empty() {
+            println!("    Output ({} packets):", output.packets.len());
+            for packet in &output.packets {
+                println!("      {}", packet);
+            }
+        } else {
+            println!("    Output: (no packets captured)");
+        }
+        if let Some(summary) = latency::summary(&output.latencies_us) {
+            println!("    Latency: {}", summary);
+        }
+        if let Some(latency_us) = output.wire_latency_us {
+            println!("    Submit-to-wire latency: {} us", latency_us);
+        }
+        if let Some(histogram) = IntervalHistogram::of(&output.timestamps_us) {
+            println!("    Packet intervals: {}", histogram.describe());
+            for line in histogram.render() {
+                println!("      {}", line);
+            }
+        }
+        if let Some(axis) = AxisSummary::of(&output.axis) {
+            println!("    Wheel axis: {}", axis.describe());
+        }
+        if self.preview {
+            let curve = force_curve::reconstruct(self.steps.get(index), output);
+            Self::print_plot("Commanded force (decoded)", waveform::commanded(&curve));
+        }
+        for capture in &output.devices {
+            println!("    Device {} ({} packets):", capture.device, capture.packets.len());
+            for packet in &capture.packets {
+                println!("      {}", packet);
+            }
+        }
+    }
+
+    fn iteration_finished(&mut self) {
+        println!();
+    }
+
+    fn scenario_finished(&mut self) {
+        println!("Scenario completed");
+    }
+}
@@ -0,0 +1,108 @@
+//! Machine-readable results (`--output-format json`)
+//!
+//! Progress and results are printed throughout the tool, and by capture
+//! subprocesses. In JSON mode the process's stdout is pointed at stderr at
+//! startup, so all of that text ends up on stderr unchanged, and each command
+//! writes its results as JSON to the original stdout through `Output::emit`.
+
+use crate::normalize::decode_packet;
+use crate::usb_monitor::{format_hex, PacketDirection, UsbPacket};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, Write};
+
+/// Destination of command results
+pub struct Output {
+    /// The original stdout in JSON mode
+    json: Option<File>,
+}
+
+impl Output {
+    pub fn text() -> Self {
+        Self { json: None }
+    }
+
+    /// Redirect stdout to stderr and keep the original stdout for JSON results
+    pub fn json() -> io::Result<Self> {
+        Ok(Self {
+            json: Some(redirect_stdout()?),
+        })
+    }
+
+    /// Write `value` as one line of JSON in JSON mode; does nothing in text mode
+    pub fn emit<T: Serialize + ?Sized>(&mut self, value: &T) -> anyhow::Result<()> {
+        if let Some(out) = &mut self.json {
+            serde_json::to_writer(&mut *out, value)?;
+            out.write_all(b"\n")?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Where command output that is neither progress nor JSON goes, e.g. completion scripts
+    pub fn stdout(&mut self) -> Box<dyn Write + '_> {
+        match &mut self.json {
+            Some(out) => Box::new(out),
+            None => Box::new(io::stdout()),
+        }
+    }
+}
+
+/// JSON form of a captured packet
+pub fn packet_json(packet: &UsbPacket) -> serde_json::Value {
+    serde_json::json!({
+        "timestamp_us": packet.timestamp.as_micros() as u64,
+        "direction": match packet.direction {
+            PacketDirection::HostToDevice => "out",
+            PacketDirection::DeviceToHost => "in",
+        },
+        "endpoint": packet.endpoint,
+        "data": format_hex(&packet.data),
+        "decoded": decode_packet(&packet.data),
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn redirect_stdout() -> io::Result<File> {
+    use std::os::fd::FromRawFd;
+
+    io::stdout().flush()?;
+    // SAFETY: duplicating the standard descriptors; the saved one is owned by the returned File
+    unsafe {
+        let saved = libc::dup(libc::STDOUT_FILENO);
+        if saved < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            let error = io::Error::last_os_error();
+            libc::close(saved);
+            return Err(error);
+        }
+        Ok(File::from_raw_fd(saved))
+    }
+}
+
+#[cfg(windows)]
+fn redirect_stdout() -> io::Result<File> {
+    use std::os::windows::io::FromRawHandle;
+    use windows_sys::Win32::System::Console::{GetStdHandle, SetStdHandle, STD_ERROR_HANDLE, STD_OUTPUT_HANDLE};
+
+    io::stdout().flush()?;
+    // SAFETY: Rust's stdout looks up the standard handle on every write, so swapping it
+    // takes effect immediately; the original handle is owned by the returned File
+    unsafe {
+        let stdout = GetStdHandle(STD_OUTPUT_HANDLE);
+        if SetStdHandle(STD_OUTPUT_HANDLE, GetStdHandle(STD_ERROR_HANDLE)) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(File::from_raw_handle(stdout as _))
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn redirect_stdout() -> io::Result<File> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "JSON output is only supported on Linux and Windows",
+    ))
+}
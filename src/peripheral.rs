@@ -0,0 +1,176 @@
+//! Wheel peripheral commands: rev LEDs and dash displays
+//!
+//! These are vendor reports on the same OUT pipe as the force feedback, so a step's
+//! peripheral commands are sent while its effect plays and end up in its capture,
+//! where they show how LED and display updates interleave with the FFB reports.
+//! [`Protocol`] builds the reports for a wheel vendor; drivers pick the protocol of
+//! the device they opened (see `FfbDriver::send_peripheral`).
+
+use serde::{Deserialize, Serialize};
+
+/// Peripheral command of a scenario step
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PeripheralCommand {
+    /// Rev LEDs, one character per LED from the first: `x` lit, `.` off, e.g. `xxx..`
+    RevLeds { leds: String },
+    /// Text on the wheel's display
+    Display { text: String },
+}
+
+impl PeripheralCommand {
+    /// Short description for logs
+    pub fn label(&self) -> String {
+        match self {
+            PeripheralCommand::RevLeds { leds } => format!("rev LEDs {}", leds),
+            PeripheralCommand::Display { text } => format!("display \"{}\"", text),
+        }
+    }
+
+    /// Check what doesn't depend on the wheel: the LED pattern's characters
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            PeripheralCommand::RevLeds { leds } => match leds.chars().find(|c| !matches!(c, 'x' | 'X' | '.')) {
+                Some(c) => Err(format!("Invalid LED '{}' in \"{}\", use x for lit and . for off", c, leds)),
+                None => Ok(()),
+            },
+            PeripheralCommand::Display { .. } => Ok(()),
+        }
+    }
+}
+
+/// Vendor report format of the peripheral commands and the rotation range setting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// Logitech G25/G27/G29 rev LEDs, as new-lg4ff sends them; no display
+    Logitech,
+    /// Fanatec wheel rev LEDs and 3-digit display, as hid-fanatecff sends them
+    Fanatec,
+}
+
+/// Logitech vendor ID
+const LOGITECH_VID: u16 = 0x046d;
+/// Fanatec vendor ID
+const FANATEC_VID: u16 = 0x0eb7;
+
+/// Segments (a = bit 0 .. g = bit 6) of the characters a 7-segment display can show
+const SEGMENTS: &[(char, u8)] = &[
+    ('0', 0x3f),
+    ('1', 0x06),
+    ('2', 0x5b),
+    ('3', 0x4f),
+    ('4', 0x66),
+    ('5', 0x6d),
+    ('6', 0x7d),
+    ('7', 0x07),
+    ('8', 0x7f),
+    ('9', 0x6f),
+    ('A', 0x77),
+    ('B', 0x7c),
+    ('C', 0x39),
+    ('D', 0x5e),
+    ('E', 0x79),
+    ('F', 0x71),
+    ('H', 0x76),
+    ('L', 0x38),
+    ('N', 0x54),
+    ('O', 0x5c),
+    ('P', 0x73),
+    ('R', 0x50),
+    ('U', 0x3e),
+    ('-', 0x40),
+    ('_', 0x08),
+    (' ', 0x00),
+];
+
+/// Decimal point segment
+const SEGMENT_DOT: u8 = 0x80;
+
+impl Protocol {
+    /// Protocol of a wheel by its USB vendor ID, None if no report builder knows it
+    pub fn for_vendor(vendor_id: u16) -> Option<Self> {
+        match vendor_id {
+            LOGITECH_VID => Some(Protocol::Logitech),
+            FANATEC_VID => Some(Protocol::Fanatec),
+            _ => None,
+        }
+    }
+
+    /// Number of rev LEDs a command can set
+    pub fn led_count(self) -> usize {
+        match self {
+            Protocol::Logitech => 5,
+            Protocol::Fanatec => 8,
+        }
+    }
+
+    /// Output reports for `command`, each starting with its report ID (0 without IDs),
+    /// as `SDL_hid_write` and hidraw take them
+    pub fn reports(self, command: &PeripheralCommand) -> Result<Vec<Vec<u8>>, String> {
+        match (self, command) {
+            (_, PeripheralCommand::RevLeds { leds }) => {
+                let mask = led_mask(leds, self.led_count())?;
+                Ok(vec![match self {
+                    Protocol::Logitech => vec![0x00, 0xf8, 0x12, mask, 0x00, 0x00, 0x00, 0x00],
+                    Protocol::Fanatec => vec![0x01, 0xf8, 0x13, mask, 0x00, 0x00, 0x00, 0x00],
+                }])
+            }
+            (Protocol::Logitech, PeripheralCommand::Display { .. }) => {
+                Err("Logitech wheels have no display".to_string())
+            }
+            (Protocol::Fanatec, PeripheralCommand::Display { text }) => {
+                let mut report = vec![0x01, 0xf8, 0x09, 0x01, 0x02];
+                report.extend(seven_segment(text, 3)?);
+                report.push(0x00);
+                Ok(vec![report])
+            }
+        }
+    }
+
+    /// Output reports setting the rotation range to `degrees`, as the Linux drivers send them
+    pub fn rotation_reports(self, degrees: u16) -> Vec<Vec<u8>> {
+        let [low, high] = degrees.to_le_bytes();
+        match self {
+            Protocol::Logitech => vec![vec![0x00, 0xf8, 0x81, low, high, 0x00, 0x00, 0x00]],
+            // Range changes are unlocked first
+            Protocol::Fanatec => vec![
+                vec![0x01, 0xf5, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+                vec![0x01, 0xf8, 0x09, 0x01, 0x06, 0x01, 0x00, 0x00],
+                vec![0x01, 0xf8, 0x81, low, high, 0x00, 0x00, 0x00],
+            ],
+        }
+    }
+}
+
+/// LED bit mask of a pattern, the first LED in bit 0
+fn led_mask(leds: &str, count: usize) -> Result<u8, String> {
+    if leds.chars().count() > count {
+        return Err(format!("{} LEDs in \"{}\", the wheel has {}", leds.chars().count(), leds, count));
+    }
+    PeripheralCommand::RevLeds { leds: leds.to_string() }.validate()?;
+    Ok(leds.chars().enumerate().filter(|(_, c)| *c != '.').fold(0, |mask, (i, _)| mask | 1 << i))
+}
+
+/// Segments of `text` on a `digits`-digit display, right-aligned; a `.` lights the
+/// decimal point of the digit before it
+fn seven_segment(text: &str, digits: usize) -> Result<Vec<u8>, String> {
+    let mut segments: Vec<u8> = Vec::new();
+    for c in text.chars() {
+        if c == '.' && segments.last().is_some_and(|s| s & SEGMENT_DOT == 0) {
+            *segments.last_mut().unwrap() |= SEGMENT_DOT;
+            continue;
+        }
+        let upper = c.to_ascii_uppercase();
+        match SEGMENTS.iter().find(|(ch, _)| *ch == upper) {
+            Some((_, bits)) => segments.push(*bits),
+            None if c == '.' => segments.push(SEGMENT_DOT),
+            None => return Err(format!("'{}' in \"{}\" cannot be shown on a 7-segment display", c, text)),
+        }
+    }
+    if segments.len() > digits {
+        return Err(format!("\"{}\" does not fit the {}-digit display", text, digits));
+    }
+    let mut display = vec![0u8; digits - segments.len()];
+    display.extend(segments);
+    Ok(display)
+}
@@ -0,0 +1,88 @@
+//! Real-time thread priorities (`--realtime`)
+//!
+//! On a loaded machine the capture reader can be descheduled long enough for the
+//! kernel buffer to overflow, and the playback thread can wake up late for a step.
+//! With `--realtime` both raise their own priority: `SCHED_FIFO` on Linux, which
+//! needs root, `CAP_SYS_NICE` or an `rtprio` limit, and `THREAD_PRIORITY_TIME_CRITICAL`
+//! / `THREAD_PRIORITY_HIGHEST` on Windows. The capture reader ranks above playback,
+//! since a missed packet is lost while a late step is only late. Where raising is
+//! not permitted, a warning is printed and the thread keeps its priority.
+
+/// What a thread does, deciding how far its priority is raised
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadRole {
+    /// Reads captured packets from the capture backend
+    Capture,
+    /// Plays scenarios and waits for their steps
+    Playback,
+}
+
+impl ThreadRole {
+    fn name(self) -> &'static str {
+        match self {
+            ThreadRole::Capture => "capture",
+            ThreadRole::Playback => "playback",
+        }
+    }
+}
+
+/// Raise the calling thread's priority for `role`
+pub fn raise_current_thread(role: ThreadRole) -> Result<(), String> {
+    platform::raise(role)
+}
+
+/// `raise_current_thread`, printing a warning instead of failing
+pub fn raise_or_warn(role: ThreadRole) {
+    if let Err(e) = raise_current_thread(role) {
+        println!("WARNING: No real-time priority for the {} thread: {}", role.name(), e);
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::ThreadRole;
+
+    pub fn raise(role: ThreadRole) -> Result<(), String> {
+        let priority = match role {
+            ThreadRole::Capture => 50,
+            ThreadRole::Playback => 40,
+        };
+        let param = libc::sched_param { sched_priority: priority };
+        // SAFETY: pthread_self is the calling thread, param is a valid sched_param
+        let result = unsafe { libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param) };
+        match result {
+            0 => Ok(()),
+            libc::EPERM => Err("not permitted (run as root, grant CAP_SYS_NICE or set an rtprio limit)".to_string()),
+            error => Err(std::io::Error::from_raw_os_error(error).to_string()),
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::ThreadRole;
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentThread, SetThreadPriority, THREAD_PRIORITY_HIGHEST, THREAD_PRIORITY_TIME_CRITICAL,
+    };
+
+    pub fn raise(role: ThreadRole) -> Result<(), String> {
+        let priority = match role {
+            ThreadRole::Capture => THREAD_PRIORITY_TIME_CRITICAL,
+            ThreadRole::Playback => THREAD_PRIORITY_HIGHEST,
+        };
+        // SAFETY: GetCurrentThread returns a pseudo handle valid for the calling thread
+        if unsafe { SetThreadPriority(GetCurrentThread(), priority) } == 0 {
+            return Err(std::io::Error::last_os_error().to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+mod platform {
+    use super::ThreadRole;
+
+    pub fn raise(_role: ThreadRole) -> Result<(), String> {
+        Err("not supported on this platform".to_string())
+    }
+}
@@ -0,0 +1,633 @@
+//! Pass-through between a game and the real wheel, recording both directions
+//!
+//! The `proxy` command puts a virtual wheel (uhid) in front of the `--device`
+//! wheel (hidraw): it advertises the real wheel's report descriptor and ID, so
+//! the game drives it as it would the wheel. Every report the game sends is
+//! forwarded to the wheel unmodified, GET_REPORT requests are answered with the
+//! wheel's own reply, and the wheel's input reports (steering, buttons) are passed
+//! back to the game. Unlike `virtual-device`, the device answers nothing itself:
+//! the wheel runs the PID handshake and plays the effects.
+//!
+//! Both directions are logged with timestamps, as `monitor` prints packets, and
+//! the reports sent to the wheel are saved as a capture file split into steps at
+//! pauses, as `virtual-device` does.
+//!
+//! For A/B experiments, `--mutations` rules rewrite or drop matching reports on
+//! their way to the wheel:
+//!
+//! ```yaml
+//! - name: double constant force
+//!   match:
+//!     - { offset: 0, value: 0x05 }
+//!   offset: 2
+//!   width: 2
+//!   signed: true
+//!   scale: 2.0
+//! - name: no damper
+//!   match:
+//!     - { offset: 0, value: 0x03 }
+//!   drop: true
+//! ```
+//!
+//! Offsets count from the first byte of the report, its report ID if numbered.
+
+use crate::cancel::CancellationToken;
+use crate::hid_descriptor::ReportDescriptor;
+use crate::import;
+use crate::monitor::format_packet;
+use crate::normalize::decode_packet_with;
+use crate::normalize::spec::{Endian, MatchRule};
+use crate::usb_monitor::{capture_clock, format_hex, CaptureConfig, DeviceFilter, PacketDirection, UsbPacket};
+use crate::virtual_device::{self, Event, VirtualDevice};
+use crate::write_capture_file;
+use serde::Deserialize;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait for a report from the wheel before checking for the end
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How long to wait for a uhid event before passing on the wheel's input reports
+const EVENT_POLL: Duration = Duration::from_millis(1);
+
+/// A rule rewriting or dropping the reports it matches
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mutation {
+    pub name: String,
+    /// Every rule must hold for the mutation to apply
+    #[serde(default, rename = "match")]
+    pub matches: Vec<MatchRule>,
+    /// Position of the value to change
+    #[serde(default)]
+    pub offset: Option<usize>,
+    /// Bytes: 1, 2 or 4
+    #[serde(default = "default_width")]
+    pub width: usize,
+    #[serde(default)]
+    pub endian: Endian,
+    #[serde(default)]
+    pub signed: bool,
+    /// Replace the value
+    #[serde(default)]
+    pub set: Option<i64>,
+    /// Added to the value, after `scale`
+    #[serde(default)]
+    pub add: Option<i64>,
+    #[serde(default)]
+    pub scale: Option<f64>,
+    /// Do not forward matching reports at all
+    #[serde(default)]
+    pub drop: bool,
+}
+
+fn default_width() -> usize {
+    1
+}
+
+/// What the mutations did to a report
+enum Verdict<'m> {
+    Unchanged,
+    Mutated(Vec<&'m str>),
+    Dropped(&'m str),
+}
+
+impl Mutation {
+    fn validate(&self) -> Result<(), String> {
+        if ![1, 2, 4].contains(&self.width) {
+            return Err(format!("has width {}, expected 1, 2 or 4 bytes", self.width));
+        }
+        if !self.drop {
+            if self.offset.is_none() {
+                return Err("needs an offset, or drop: true".to_string());
+            }
+            if self.set.is_none() && self.add.is_none() && self.scale.is_none() {
+                return Err("changes nothing: give set, add, scale or drop: true".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn matches(&self, report: &[u8]) -> bool {
+        self.matches
+            .iter()
+            .all(|rule| report.get(rule.offset).is_some_and(|byte| byte & rule.mask == rule.value))
+    }
+
+    /// Rewrite the value at `offset`; false if the report is too short to hold it
+    fn apply(&self, report: &mut [u8]) -> bool {
+        let Some(bytes) = self.offset.and_then(|offset| report.get_mut(offset..offset + self.width)) else {
+            return false;
+        };
+        let mut raw = [0u8; 4];
+        match self.endian {
+            Endian::Little => raw[..self.width].copy_from_slice(bytes),
+            Endian::Big => bytes.iter().rev().zip(raw.iter_mut()).for_each(|(byte, raw)| *raw = *byte),
+        }
+        let bits = self.width as u32 * 8;
+        let unsigned = u32::from_le_bytes(raw) as i64;
+        let (value, min, max) = if self.signed {
+            let half = 1i64 << (bits - 1);
+            let value = if unsigned >= half { unsigned - 2 * half } else { unsigned };
+            (value, -half, half - 1)
+        } else {
+            (unsigned, 0, (1i64 << bits) - 1)
+        };
+
+        let mutated = self
+            .set
+            .unwrap_or_else(|| (value as f64 * self.scale.unwrap_or(1.0)).round() as i64 + self.add.unwrap_or(0))
+            .clamp(min, max);
+        let raw = (mutated as u32).to_le_bytes();
+        match self.endian {
+            Endian::Little => bytes.copy_from_slice(&raw[..self.width]),
+            Endian::Big => bytes.iter_mut().rev().zip(raw).for_each(|(byte, raw)| *byte = raw),
+        }
+        true
+    }
+}
+
+/// Mutation rules from a YAML list
+pub fn load_mutations(path: &Path) -> anyhow::Result<Vec<Mutation>> {
+    let source = fs::read_to_string(path).map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let mutations: Vec<Mutation> =
+        serde_yaml::from_str(&source).map_err(|e| anyhow::anyhow!("Invalid mutations {}: {}", path.display(), e))?;
+    for mutation in &mutations {
+        mutation
+            .validate()
+            .map_err(|e| anyhow::anyhow!("{}: mutation {} {}", path.display(), mutation.name, e))?;
+    }
+    Ok(mutations)
+}
+
+/// Apply the matching mutations to `report`, in order, until one drops it
+fn mutate<'m>(mutations: &'m [Mutation], report: &mut [u8]) -> Verdict<'m> {
+    let mut applied = Vec::new();
+    for mutation in mutations {
+        if !mutation.matches(report) {
+            continue;
+        }
+        if mutation.drop {
+            return Verdict::Dropped(&mutation.name);
+        }
+        if mutation.apply(report) {
+            applied.push(mutation.name.as_str());
+        }
+    }
+    match applied.is_empty() {
+        true => Verdict::Unchanged,
+        false => Verdict::Mutated(applied),
+    }
+}
+
+/// Where the proxy sends its logs and captures
+pub struct ProxyOutput<'a> {
+    /// Capture file of the reports forwarded to the wheel
+    pub capture: &'a PathBuf,
+    /// Log of both directions
+    pub log: &'a Path,
+    /// Pause in the reports that starts a new capture step
+    pub gap: Duration,
+}
+
+/// Numbers of a proxy session, for `--output json`
+#[derive(Debug, serde::Serialize)]
+pub struct ProxySummary {
+    pub wheel: String,
+    pub device: String,
+    /// Reports from the game, including dropped ones
+    pub output_reports: usize,
+    pub mutated: usize,
+    pub dropped: usize,
+    /// Input reports passed back to the game
+    pub input_reports: usize,
+    pub get_reports: usize,
+    pub steps: usize,
+    pub output: PathBuf,
+    pub log: PathBuf,
+}
+
+/// Pose as the `--device` wheel under `name` (and `id` if given), forward what the
+/// game sends to the wheel through `mutations`, and log and record it until
+/// `duration` elapses or `cancel` is cancelled
+pub fn run(
+    capture: &CaptureConfig,
+    name: String,
+    id: Option<DeviceFilter>,
+    mutations: &[Mutation],
+    output: &ProxyOutput,
+    duration: Option<Duration>,
+    cancel: &CancellationToken,
+) -> anyhow::Result<ProxySummary> {
+    let Some(wheel_id) = capture.devices.first().copied() else {
+        anyhow::bail!("proxy needs the wheel to forward to: pass --device VID:PID");
+    };
+    let (mut wheel, descriptor_bytes) = platform::Wheel::open(&wheel_id).map_err(anyhow::Error::msg)?;
+    let descriptor = ReportDescriptor::parse(&descriptor_bytes)
+        .map_err(|e| anyhow::anyhow!("Invalid report descriptor of {}: {}", wheel_id, e))?;
+    let numbered = descriptor.uses_report_ids();
+    let device = VirtualDevice {
+        name,
+        id: id.unwrap_or(wheel_id),
+        descriptor: Some(descriptor_bytes),
+        vjoy_device: 1,
+    };
+    let mut virtual_device = virtual_device::Device::create(&device).map_err(anyhow::Error::msg)?;
+    let mut log = BufWriter::new(
+        File::create(output.log).map_err(|e| anyhow::anyhow!("Failed to create {}: {}", output.log.display(), e))?,
+    );
+
+    // The wheel's input reports are read on their own thread, so they reach the
+    // game while the main loop waits for the game's reports
+    let (sender, inputs) = mpsc::channel();
+    let stop = CancellationToken::new();
+    let reader = {
+        let mut wheel = wheel.try_clone().map_err(anyhow::Error::msg)?;
+        let stop = stop.clone();
+        thread::spawn(move || {
+            while !stop.is_cancelled() {
+                match wheel.read_input(POLL_INTERVAL) {
+                    Ok(None) => {}
+                    Ok(Some(report)) => {
+                        if sender.send(Ok(report)).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        let _ = sender.send(Err(e));
+                        break;
+                    }
+                }
+            }
+        })
+    };
+
+    let capture_start = capture_clock();
+    let started = Instant::now();
+    let mut summary = ProxySummary {
+        wheel: wheel_id.to_string(),
+        device: device.label(),
+        output_reports: 0,
+        mutated: 0,
+        dropped: 0,
+        input_reports: 0,
+        get_reports: 0,
+        steps: 0,
+        output: output.capture.clone(),
+        log: output.log.to_path_buf(),
+    };
+    let mut sequence = 0;
+    let mut forwarded = Vec::new();
+    writeln!(log, "# {} in front of {}", device.label(), wheel_id)?;
+    match duration {
+        Some(duration) => println!(
+            "Forwarding the {} to {} for {} s...",
+            device.label(),
+            wheel_id,
+            duration.as_secs()
+        ),
+        None => println!("Forwarding the {} to {} until Ctrl+C...", device.label(), wheel_id),
+    }
+
+    while duration.is_none_or(|duration| started.elapsed() < duration) && !cancel.is_cancelled() {
+        while let Ok(input) = inputs.try_recv() {
+            let report = input.map_err(anyhow::Error::msg)?;
+            virtual_device.send_input(&report).map_err(anyhow::Error::msg)?;
+            let packet = device_packet(report, 1, 1, sequence);
+            sequence += 1;
+            summary.input_reports += 1;
+            writeln!(log, "{}", format_packet(&packet, capture_start))?;
+        }
+
+        let Some(event) = virtual_device.next_event(EVENT_POLL).map_err(anyhow::Error::msg)? else {
+            continue;
+        };
+        let (mut report, request) = match event {
+            Event::Open => {
+                println!("A game opened the device");
+                continue;
+            }
+            Event::Close => {
+                println!("The game closed the device");
+                continue;
+            }
+            Event::Output(data) => (data, None),
+            Event::SetReport {
+                id,
+                number,
+                kind,
+                data,
+            } => {
+                virtual_device.reply_set_report(id).map_err(anyhow::Error::msg)?;
+                (data, Some((number, kind)))
+            }
+            Event::GetReport { id, number, kind } => {
+                let reply = wheel.get_report(kind, number, numbered);
+                virtual_device
+                    .reply_get_report(id, reply.as_deref().ok())
+                    .map_err(anyhow::Error::msg)?;
+                summary.get_reports += 1;
+                let line = match reply {
+                    Ok(reply) => format_packet(&device_packet(reply, 0, 2, sequence), capture_start),
+                    Err(e) => format!("GET_REPORT {:?} {} failed: {}", kind, number, e),
+                };
+                sequence += 1;
+                println!("{}", line);
+                writeln!(log, "{}", line)?;
+                continue;
+            }
+            Event::Other => continue,
+        };
+
+        let received = virtual_device::report_packet(report.clone(), request, sequence);
+        sequence += 1;
+        summary.output_reports += 1;
+        let line = format_packet(&received, capture_start);
+        println!("{}", line);
+        writeln!(log, "{}", line)?;
+        let (note, dropped) = match mutate(mutations, &mut report) {
+            Verdict::Unchanged => (None, false),
+            Verdict::Mutated(names) => {
+                summary.mutated += 1;
+                (Some(format!("mutated by {}: {}", names.join(", "), format_hex(&report))), false)
+            }
+            Verdict::Dropped(name) => {
+                summary.dropped += 1;
+                (Some(format!("dropped by {}", name)), true)
+            }
+        };
+        if let Some(note) = &note {
+            println!("    -> {}", note);
+            writeln!(log, "    -> {}", note)?;
+        }
+        if dropped {
+            continue;
+        }
+        if let Some(decoded) = decode_packet_with(&report, Some(&descriptor)) {
+            println!("    -> {}", decoded);
+        }
+
+        let sent = match request {
+            Some((number, kind)) => wheel.set_report(kind, number, &report, numbered),
+            None => wheel.write_output(&report, numbered),
+        };
+        if let Err(e) = sent {
+            println!("WARNING: {}", e);
+            writeln!(log, "    -> not forwarded: {}", e)?;
+            continue;
+        }
+        let mut packet = virtual_device::report_packet(report, request, received.sequence as usize);
+        packet.timestamp = received.timestamp;
+        forwarded.push(packet);
+    }
+    stop.cancel();
+    let _ = reader.join();
+    drop(virtual_device);
+    log.flush()?;
+
+    println!(
+        "Forwarded {} reports to the wheel ({} mutated, {} dropped) and {} input reports to the game",
+        summary.output_reports - summary.dropped,
+        summary.mutated,
+        summary.dropped,
+        summary.input_reports
+    );
+    println!("Both directions logged to {}", output.log.display());
+    if forwarded.is_empty() {
+        println!("The game sent no force feedback, no capture written");
+        return Ok(summary);
+    }
+    let bursts = import::split_bursts(forwarded, output.gap);
+    let steps = import::burst_steps(&bursts);
+    let total_packets = write_capture_file(output.capture, &steps)?;
+    summary.steps = steps.len();
+    println!(
+        "Recorded {} reports in {} steps to {}",
+        total_packets,
+        steps.len(),
+        output.capture.display()
+    );
+    Ok(summary)
+}
+
+/// A report from the wheel as a captured packet: input reports on the interrupt
+/// endpoint (`transfer_type` 1), GET_REPORT replies on the control endpoint (2)
+fn device_packet(data: Vec<u8>, endpoint: u8, transfer_type: u8, sequence: usize) -> UsbPacket {
+    UsbPacket {
+        timestamp: capture_clock(),
+        direction: PacketDirection::DeviceToHost,
+        endpoint,
+        data,
+        urb_id: 0,
+        bus: 0,
+        device: 0,
+        transfer_type,
+        sequence: sequence as u64,
+        source: None,
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    //! The real wheel through `/dev/hidrawN`: reads return input reports and
+    //! writes send output reports, both starting with the report number (0 if the
+    //! device does not number its reports); feature and output reports are set and
+    //! got with the HIDIOC ioctls (linux/hidraw.h)
+
+    use crate::hid_descriptor::{self, ReportDescriptor, ReportKind};
+    use crate::usb_monitor::DeviceFilter;
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{Read, Write};
+    use std::os::fd::AsRawFd;
+    use std::time::Duration;
+
+    /// Largest report exchanged
+    const REPORT_MAX: usize = 4096;
+    const HIDIOCSFEATURE: u8 = 0x06;
+    const HIDIOCGFEATURE: u8 = 0x07;
+    const HIDIOCGINPUT: u8 = 0x0A;
+    const HIDIOCSOUTPUT: u8 = 0x0B;
+    const HIDIOCGOUTPUT: u8 = 0x0C;
+
+    pub struct Wheel {
+        file: File,
+        path: String,
+    }
+
+    /// _IOC(_IOC_READ | _IOC_WRITE, 'H', nr, len)
+    fn request(nr: u8, len: usize) -> u64 {
+        (3 << 30) | ((len as u64) << 16) | ((b'H' as u64) << 8) | nr as u64
+    }
+
+    impl Wheel {
+        /// The hidraw node of the wheel's HID interface with PID reports (else its
+        /// first), and that interface's report descriptor
+        pub fn open(device: &DeviceFilter) -> Result<(Self, Vec<u8>), String> {
+            let mut candidates = Vec::new();
+            for interface in hid_descriptor::hid_interfaces(device)? {
+                let Ok(descriptor) = fs::read(interface.join("report_descriptor")) else {
+                    continue;
+                };
+                let node = fs::read_dir(interface.join("hidraw"))
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                    .find(|name| name.starts_with("hidraw"));
+                if let Some(node) = node {
+                    candidates.push((format!("/dev/{}", node), descriptor));
+                }
+            }
+            let pid = candidates
+                .iter()
+                .position(|(_, bytes)| ReportDescriptor::parse(bytes).is_ok_and(|d| d.has_pid()));
+            let Some((path, descriptor)) = pid
+                .map(|index| candidates.swap_remove(index))
+                .or_else(|| candidates.into_iter().next())
+            else {
+                return Err(format!("No hidraw device for {} found", device));
+            };
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .map_err(|e| format!("Failed to open {}: {} (run as root or grant access with a udev rule)", path, e))?;
+            Ok((Self { file, path }, descriptor))
+        }
+
+        pub fn try_clone(&self) -> Result<Self, String> {
+            let file = self.file.try_clone().map_err(|e| format!("Failed to reopen {}: {}", self.path, e))?;
+            Ok(Self {
+                file,
+                path: self.path.clone(),
+            })
+        }
+
+        /// The next input report, None if none arrives within `timeout`
+        pub fn read_input(&mut self, timeout: Duration) -> Result<Option<Vec<u8>>, String> {
+            let mut pfd = libc::pollfd {
+                fd: self.file.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: pfd is a valid pollfd for the duration of the call
+            let ready = unsafe { libc::poll(&mut pfd, 1, timeout.as_millis() as libc::c_int) };
+            if ready <= 0 {
+                return Ok(None);
+            }
+            let mut report = vec![0u8; REPORT_MAX];
+            let len = self
+                .file
+                .read(&mut report)
+                .map_err(|e| format!("Failed to read {}: {}", self.path, e))?;
+            report.truncate(len);
+            Ok(Some(report))
+        }
+
+        /// Send output report `report`, which starts with its report ID if `numbered`
+        pub fn write_output(&mut self, report: &[u8], numbered: bool) -> Result<(), String> {
+            let buffer = with_number(0, report, numbered);
+            self.file
+                .write_all(&buffer)
+                .map_err(|e| format!("Failed to write {}: {}", self.path, e))
+        }
+
+        /// SET_REPORT of report `number`
+        pub fn set_report(
+            &mut self,
+            kind: ReportKind,
+            number: u8,
+            report: &[u8],
+            numbered: bool,
+        ) -> Result<(), String> {
+            let nr = match kind {
+                ReportKind::Feature => HIDIOCSFEATURE,
+                ReportKind::Output => HIDIOCSOUTPUT,
+                ReportKind::Input => return Err(format!("{}: input reports cannot be set", self.path)),
+            };
+            let mut buffer = with_number(number, report, numbered);
+            self.ioctl(nr, &mut buffer).map(|_| ())
+        }
+
+        /// GET_REPORT of report `number`, starting with the report ID if `numbered`
+        pub fn get_report(&mut self, kind: ReportKind, number: u8, numbered: bool) -> Result<Vec<u8>, String> {
+            let nr = match kind {
+                ReportKind::Feature => HIDIOCGFEATURE,
+                ReportKind::Input => HIDIOCGINPUT,
+                ReportKind::Output => HIDIOCGOUTPUT,
+            };
+            let mut buffer = vec![0u8; REPORT_MAX];
+            buffer[0] = number;
+            let len = self.ioctl(nr, &mut buffer)?;
+            buffer.truncate(len);
+            if !numbered && !buffer.is_empty() {
+                buffer.remove(0);
+            }
+            Ok(buffer)
+        }
+
+        fn ioctl(&mut self, nr: u8, buffer: &mut [u8]) -> Result<usize, String> {
+            let request = request(nr, buffer.len());
+            // SAFETY: the request's size is the buffer's, which outlives the call
+            let result = unsafe { libc::ioctl(self.file.as_raw_fd(), request as _, buffer.as_mut_ptr()) };
+            if result < 0 {
+                return Err(format!("{}: {}", self.path, std::io::Error::last_os_error()));
+            }
+            Ok(result as usize)
+        }
+    }
+
+    /// hidraw wants the report number first, also for devices without report IDs
+    fn with_number(number: u8, report: &[u8], numbered: bool) -> Vec<u8> {
+        match numbered {
+            true => report.to_vec(),
+            false => std::iter::once(number).chain(report.iter().copied()).collect(),
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod platform {
+    use crate::hid_descriptor::ReportKind;
+    use crate::usb_monitor::DeviceFilter;
+    use std::time::Duration;
+
+    const UNSUPPORTED: &str = "The proxy needs Linux: uhid for the virtual wheel and hidraw for the real one";
+
+    pub struct Wheel;
+
+    impl Wheel {
+        pub fn open(_device: &DeviceFilter) -> Result<(Self, Vec<u8>), String> {
+            Err(UNSUPPORTED.to_string())
+        }
+
+        pub fn try_clone(&self) -> Result<Self, String> {
+            Err(UNSUPPORTED.to_string())
+        }
+
+        pub fn read_input(&mut self, _timeout: Duration) -> Result<Option<Vec<u8>>, String> {
+            Err(UNSUPPORTED.to_string())
+        }
+
+        pub fn write_output(&mut self, _report: &[u8], _numbered: bool) -> Result<(), String> {
+            Err(UNSUPPORTED.to_string())
+        }
+
+        pub fn set_report(
+            &mut self,
+            _kind: ReportKind,
+            _number: u8,
+            _report: &[u8],
+            _numbered: bool,
+        ) -> Result<(), String> {
+            Err(UNSUPPORTED.to_string())
+        }
+
+        pub fn get_report(&mut self, _kind: ReportKind, _number: u8, _numbered: bool) -> Result<Vec<u8>, String> {
+            Err(UNSUPPORTED.to_string())
+        }
+    }
+}
@@ -0,0 +1,325 @@
+//! Self-contained HTML report writer
+//!
+//! Renders per-step status, colored hex diffs, decoded fields, a timing
+//! chart, force, packet rate and wheel axis charts and the traceability table
+//! of each step (which bytes carry which scenario field) into a single file
+//! that can be attached to bug reports for wheel vendors.
+
+use crate::compare::{differing_byte_offsets, parse_hex, PacketDiff, StepComparison, StepStatus};
+use crate::normalize::decode_packet;
+use crate::reports::json::status_name;
+use crate::reports::plot;
+use crate::trace::{self, StepTrace};
+use crate::{Scenario, StepOutput};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 1.5em; }
+th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; vertical-align: top; }
+.hex { font-family: monospace; white-space: pre; }
+.diff { background: #f8c6c6; font-weight: bold; }
+.decoded { color: #555; font-size: 0.9em; }
+.match { color: #1a7f37; }
+.mismatch, .missing, .extra, .timing { color: #c62828; }
+.summary-pass { color: #1a7f37; }
+.summary-fail { color: #c62828; }
+"#;
+
+/// Escape text for HTML content
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Render a packet as hex, highlighting bytes at the given offsets
+fn render_hex(packet: &str, highlight: &[usize]) -> String {
+    packet
+        .split_whitespace()
+        .enumerate()
+        .map(|(i, byte)| {
+            if highlight.contains(&i) {
+                format!(r#"<span class="diff">{}</span>"#, escape_html(byte))
+            } else {
+                escape_html(byte)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Decoded field view of a packet, if it can be decoded
+fn render_decoded(packet: &str) -> String {
+    match parse_hex(packet).as_deref().and_then(decode_packet) {
+        Some(decoded) => format!(r#"<div class="decoded">{}</div>"#, escape_html(&decoded)),
+        None => String::new(),
+    }
+}
+
+/// Render a packet cell with hex and decoded views
+fn render_packet(packet: Option<&str>, highlight: &[usize]) -> String {
+    match packet {
+        Some(p) => format!(
+            r#"<div class="hex">{}</div>{}"#,
+            render_hex(p, highlight),
+            render_decoded(p)
+        ),
+        None => "&mdash;".to_string(),
+    }
+}
+
+/// Table of a step's packets: operation, then each field's bytes, value and scenario source
+fn render_trace(trace: &StepTrace) -> String {
+    let mut html = String::new();
+    let _ = writeln!(
+        html,
+        "<details><summary>Step {}: {}</summary>",
+        trace.step_index,
+        escape_html(&trace.step_name)
+    );
+    let _ = writeln!(html, "<table><tr><th>Packet</th><th>Operation</th><th>Bytes</th><th>Field</th><th>Value</th><th>Scenario field</th><th>Scenario value</th></tr>");
+    for packet in &trace.packets {
+        let operation = packet.operation.as_deref().unwrap_or("unknown");
+        if packet.fields.is_empty() {
+            let _ = writeln!(
+                html,
+                r#"<tr><td>{}</td><td>{}</td><td colspan="5" class="hex">{}</td></tr>"#,
+                packet.index,
+                escape_html(operation),
+                escape_html(&packet.packet)
+            );
+        }
+        for (i, field) in packet.fields.iter().enumerate() {
+            let (packet_cell, operation_cell) = if i == 0 {
+                let rows = packet.fields.len();
+                (
+                    format!(r#"<td rowspan="{}">{}</td>"#, rows, packet.index),
+                    format!(r#"<td rowspan="{}">{}</td>"#, rows, escape_html(operation)),
+                )
+            } else {
+                (String::new(), String::new())
+            };
+            let bytes = if field.width == 1 {
+                field.offset.to_string()
+            } else {
+                format!("{}-{}", field.offset, field.offset + field.width - 1)
+            };
+            let _ = writeln!(
+                html,
+                "<tr>{}{}<td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                packet_cell,
+                operation_cell,
+                bytes,
+                escape_html(&field.name),
+                escape_html(&field.value),
+                escape_html(field.source.as_deref().unwrap_or("")),
+                escape_html(field.scenario_value.as_deref().unwrap_or(""))
+            );
+        }
+    }
+    let _ = writeln!(html, "</table></details>");
+    html
+}
+
+/// SVG bar chart of step durations and packet counts
+fn render_timing_chart(scenario: &Scenario, results: &[StepComparison]) -> String {
+    const BAR_HEIGHT: usize = 18;
+    const LABEL_WIDTH: usize = 220;
+    const CHART_WIDTH: usize = 500;
+
+    let durations: Vec<u32> = results
+        .iter()
+        .map(|r| {
+            scenario
+                .steps
+                .get(r.step_index.saturating_sub(1))
+                .map(|s| s.effect.duration())
+                .unwrap_or(0)
+        })
+        .collect();
+    let max_duration = durations.iter().copied().max().unwrap_or(0).max(1);
+
+    let height = results.len() * (BAR_HEIGHT + 6) + 10;
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-size="12">"#,
+        LABEL_WIDTH + CHART_WIDTH + 200,
+        height
+    );
+
+    for (i, (result, duration)) in results.iter().zip(&durations).enumerate() {
+        let y = i * (BAR_HEIGHT + 6) + 5;
+        let width = (*duration as usize * CHART_WIDTH) / max_duration as usize;
+        let color = if result.is_match() { "#66bb6a" } else { "#ef5350" };
+        let _ = writeln!(
+            svg,
+            r#"  <text x="0" y="{}">Step {}: {}</text>"#,
+            y + BAR_HEIGHT - 5,
+            result.step_index,
+            escape_html(&result.step_name)
+        );
+        let _ = writeln!(
+            svg,
+            r#"  <rect x="{}" y="{}" width="{}" height="{}" fill="{}" />"#,
+            LABEL_WIDTH,
+            y,
+            width.max(1),
+            BAR_HEIGHT,
+            color
+        );
+        let _ = writeln!(
+            svg,
+            r#"  <text x="{}" y="{}">{} ms, {}/{} packets</text>"#,
+            LABEL_WIDTH + width.max(1) + 6,
+            y + BAR_HEIGHT - 5,
+            duration,
+            result.actual_packets,
+            result.expected_packets
+        );
+    }
+
+    let _ = writeln!(svg, "</svg>");
+    svg
+}
+
+/// Render comparison results as a self-contained HTML document
+/// `steps` is the captured output, traced back to the scenario
+pub fn render(
+    scenario: &Scenario,
+    driver: &str,
+    capture_file: &str,
+    results: &[StepComparison],
+    steps: &[StepOutput],
+) -> String {
+    let mismatched = results.iter().filter(|r| !r.is_match()).count();
+    let title = format!("FFB comparison: {}", scenario.name);
+
+    let mut html = String::new();
+    let _ = writeln!(html, "<!DOCTYPE html>");
+    let _ = writeln!(html, r#"<html><head><meta charset="utf-8"><title>{}</title>"#, escape_html(&title));
+    let _ = writeln!(html, "<style>{}</style></head><body>", STYLE);
+    let _ = writeln!(html, "<h1>{}</h1>", escape_html(&title));
+    if !scenario.description.is_empty() {
+        let _ = writeln!(html, "<p>{}</p>", escape_html(&scenario.description));
+    }
+    let _ = writeln!(
+        html,
+        "<p>Driver: <b>{}</b><br>Capture file: <b>{}</b></p>",
+        escape_html(driver),
+        escape_html(capture_file)
+    );
+    if mismatched == 0 {
+        let _ = writeln!(html, r#"<h2 class="summary-pass">OK: All {} steps match</h2>"#, results.len());
+    } else {
+        let _ = writeln!(
+            html,
+            r#"<h2 class="summary-fail">FAIL: {} of {} steps differ</h2>"#,
+            mismatched,
+            results.len()
+        );
+    }
+
+    // Per-step status table
+    let _ = writeln!(html, "<h2>Steps</h2>");
+    let _ = writeln!(html, "<table><tr><th>Step</th><th>Name</th><th>Status</th><th>Expected packets</th><th>Actual packets</th></tr>");
+    for result in results {
+        let status = status_name(result.status);
+        let mut label = status.to_uppercase();
+        if !result.timing_violations.is_empty() {
+            label = format!("{} ({})", label, result.timing_violations.join(", "));
+        }
+        let _ = writeln!(
+            html,
+            r#"<tr><td>{}</td><td>{}</td><td class="{}">{}</td><td>{}</td><td>{}</td></tr>"#,
+            result.step_index,
+            escape_html(&result.step_name),
+            status,
+            escape_html(&label),
+            result.expected_packets,
+            result.actual_packets
+        );
+    }
+    let _ = writeln!(html, "</table>");
+
+    // Timing chart
+    let _ = writeln!(html, "<h2>Timing</h2>");
+    html.push_str(&render_timing_chart(scenario, results));
+
+    // Packet diffs
+    for result in results.iter().filter(|r| r.status == StepStatus::Mismatch) {
+        let _ = writeln!(
+            html,
+            "<h3>Step {}: {}</h3>",
+            result.step_index,
+            escape_html(&result.step_name)
+        );
+        let _ = writeln!(html, "<table><tr><th>Packet</th><th>Expected</th><th>Actual</th></tr>");
+        for diff in &result.packet_diffs {
+            let (index, expected, actual) = match diff {
+                PacketDiff::Differs { index, expected, actual } => (index, Some(expected.as_str()), Some(actual.as_str())),
+                PacketDiff::Missing { index, expected } => (index, Some(expected.as_str()), None),
+                PacketDiff::Extra { index, actual } => (index, None, Some(actual.as_str())),
+            };
+            let offsets = match (expected, actual) {
+                (Some(e), Some(a)) => differing_byte_offsets(e, a),
+                _ => Vec::new(),
+            };
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                index + 1,
+                render_packet(expected, &offsets),
+                render_packet(actual, &offsets)
+            );
+        }
+        let _ = writeln!(html, "</table>");
+    }
+
+    // Force and packet rate of the captured steps
+    let charts: Vec<plot::Chart> = steps
+        .iter()
+        .flat_map(|output| {
+            let step = scenario.steps.get(output.step_index.saturating_sub(1));
+            [plot::force_chart(step, output), plot::rate_chart(output), plot::axis_chart(output)]
+        })
+        .flatten()
+        .collect();
+    if !charts.is_empty() {
+        let _ = writeln!(html, "<h2>Force and packet rate</h2>");
+        for chart in &charts {
+            match chart.svg() {
+                Ok(svg) => html.push_str(&svg),
+                Err(e) => {
+                    let _ = writeln!(html, "<p>{}: {}</p>", escape_html(&chart.title), escape_html(&e.to_string()));
+                }
+            }
+        }
+    }
+
+    // Traceability of the captured packets
+    let _ = writeln!(html, "<h2>Traceability</h2>");
+    for step in trace::trace_run(scenario, steps) {
+        html.push_str(&render_trace(&step));
+    }
+
+    let _ = writeln!(html, "</body></html>");
+    html
+}
+
+/// Write comparison results to an HTML file
+pub fn write(
+    path: &Path,
+    scenario: &Scenario,
+    driver: &str,
+    capture_file: &str,
+    results: &[StepComparison],
+    steps: &[StepOutput],
+) -> anyhow::Result<()> {
+    fs::write(path, render(scenario, driver, capture_file, results, steps))?;
+    Ok(())
+}
@@ -0,0 +1,157 @@
+//! JSON diff document writer
+//!
+//! Structured form of the comparison results for downstream
+//! tooling and dashboards.
+
+use crate::cadence::IntervalHistogram;
+use crate::compare::{differing_byte_offsets, ComparisonSummary, PacketDiff, StepComparison, StepStatus};
+use crate::StepOutput;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct DiffDocument {
+    pub scenario: String,
+    pub driver: String,
+    pub capture_file: String,
+    pub passed: bool,
+    pub total_steps: usize,
+    pub mismatched_steps: usize,
+    pub summary: SummaryEntry,
+    pub steps: Vec<StepDiff>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SummaryEntry {
+    pub matched_steps: usize,
+    pub total_steps: usize,
+    pub matched_packets: usize,
+    pub total_packets: usize,
+    /// Step index of the step with the lowest byte similarity
+    pub largest_divergence: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StepDiff {
+    pub step_index: usize,
+    pub step_name: String,
+    pub status: &'static str,
+    pub expected_packets: usize,
+    pub actual_packets: usize,
+    pub matched_packets: usize,
+    /// Byte-level similarity in percent
+    pub similarity: f64,
+    pub packets: Vec<PacketDiffEntry>,
+    /// Intervals between the actual packets, if their timestamps were captured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub intervals: Option<IntervalHistogram>,
+    /// Timing thresholds the actual step exceeds
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub timing_violations: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PacketDiffEntry {
+    /// Packet position within the step (1-based)
+    pub packet: usize,
+    pub kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actual: Option<String>,
+    /// Byte offsets that differ (only for "differs")
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub byte_offsets: Vec<usize>,
+}
+
+pub fn status_name(status: StepStatus) -> &'static str {
+    match status {
+        StepStatus::Match => "match",
+        StepStatus::Mismatch => "mismatch",
+        StepStatus::Missing => "missing",
+        StepStatus::Extra => "extra",
+        StepStatus::Timing => "timing",
+    }
+}
+
+fn packet_entry(diff: &PacketDiff) -> PacketDiffEntry {
+    match diff {
+        PacketDiff::Differs { index, expected, actual } => PacketDiffEntry {
+            packet: index + 1,
+            kind: "differs",
+            byte_offsets: differing_byte_offsets(expected, actual),
+            expected: Some(expected.clone()),
+            actual: Some(actual.clone()),
+        },
+        PacketDiff::Missing { index, expected } => PacketDiffEntry {
+            packet: index + 1,
+            kind: "missing",
+            expected: Some(expected.clone()),
+            actual: None,
+            byte_offsets: Vec::new(),
+        },
+        PacketDiff::Extra { index, actual } => PacketDiffEntry {
+            packet: index + 1,
+            kind: "extra",
+            expected: None,
+            actual: Some(actual.clone()),
+            byte_offsets: Vec::new(),
+        },
+    }
+}
+
+/// Build the JSON diff document from comparison results and the actual steps
+/// they were compared from
+pub fn build(
+    scenario: &str,
+    driver: &str,
+    capture_file: &str,
+    results: &[StepComparison],
+    actual: &[StepOutput],
+) -> DiffDocument {
+    let mismatched_steps = results.iter().filter(|r| !r.is_match()).count();
+    let summary = ComparisonSummary::from_results(results);
+    DiffDocument {
+        scenario: scenario.to_string(),
+        driver: driver.to_string(),
+        capture_file: capture_file.to_string(),
+        passed: mismatched_steps == 0,
+        total_steps: results.len(),
+        mismatched_steps,
+        summary: SummaryEntry {
+            matched_steps: summary.matched_steps,
+            total_steps: summary.total_steps,
+            matched_packets: summary.matched_packets,
+            total_packets: summary.total_packets,
+            largest_divergence: summary.largest_divergence.map(|i| results[i].step_index),
+        },
+        steps: results
+            .iter()
+            .map(|r| StepDiff {
+                step_index: r.step_index,
+                step_name: r.step_name.clone(),
+                status: status_name(r.status),
+                expected_packets: r.expected_packets,
+                actual_packets: r.actual_packets,
+                matched_packets: r.matched_packets,
+                similarity: r.similarity,
+                packets: r.packet_diffs.iter().map(packet_entry).collect(),
+                intervals: actual
+                    .iter()
+                    .find(|s| s.step_index == r.step_index)
+                    .and_then(|s| IntervalHistogram::of(&s.timestamps_us)),
+                timing_violations: r.timing_violations.clone(),
+            })
+            .collect(),
+    }
+}
+
+/// Render comparison results as a pretty-printed JSON document
+pub fn render(
+    scenario: &str,
+    driver: &str,
+    capture_file: &str,
+    results: &[StepComparison],
+    actual: &[StepOutput],
+) -> anyhow::Result<String> {
+    Ok(serde_json::to_string_pretty(&build(scenario, driver, capture_file, results, actual))?)
+}
@@ -0,0 +1,105 @@
+//! JUnit XML report writer
+//!
+//! One test case per scenario step, so CI systems (Jenkins, GitLab)
+//! can display FFB regressions as regular test results.
+
+use crate::compare::{describe_packet_diff, StepComparison, StepStatus};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+/// Escape text for use in XML attributes and content
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Render comparison results as a JUnit XML document
+pub fn render(suite_name: &str, results: &[StepComparison]) -> String {
+    let failures = results.iter().filter(|r| !r.is_match()).count();
+    let suite = escape_xml(suite_name);
+
+    let mut xml = String::new();
+    let _ = writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        xml,
+        r#"<testsuites name="ffb_replay" tests="{}" failures="{}">"#,
+        results.len(),
+        failures
+    );
+    let _ = writeln!(
+        xml,
+        r#"  <testsuite name="{}" tests="{}" failures="{}" errors="0" skipped="0">"#,
+        suite,
+        results.len(),
+        failures
+    );
+
+    for result in results {
+        let name = escape_xml(&format!("Step {}: {}", result.step_index, result.step_name));
+        let _ = write!(xml, r#"    <testcase name="{}" classname="{}""#, name, suite);
+
+        let (message, details) = match result.status {
+            StepStatus::Match => {
+                let _ = writeln!(xml, " />");
+                continue;
+            }
+            StepStatus::Mismatch => {
+                let details = result
+                    .packet_diffs
+                    .iter()
+                    .map(describe_packet_diff)
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                (
+                    format!(
+                        "Packets differ: expected {} packets, got {} packets",
+                        result.expected_packets, result.actual_packets
+                    ),
+                    details,
+                )
+            }
+            StepStatus::Missing => (
+                format!("Step missing in actual run (expected {} packets)", result.expected_packets),
+                String::new(),
+            ),
+            StepStatus::Extra => (
+                format!("Step not present in capture file (got {} packets)", result.actual_packets),
+                String::new(),
+            ),
+            StepStatus::Timing => (
+                format!("Packet timing out of bounds: {}", result.timing_violations.join(", ")),
+                String::new(),
+            ),
+        };
+
+        let _ = writeln!(xml, ">");
+        let _ = writeln!(
+            xml,
+            r#"      <failure message="{}" type="PacketMismatch">{}</failure>"#,
+            escape_xml(&message),
+            escape_xml(&details)
+        );
+        let _ = writeln!(xml, "    </testcase>");
+    }
+
+    let _ = writeln!(xml, "  </testsuite>");
+    let _ = writeln!(xml, "</testsuites>");
+    xml
+}
+
+/// Write comparison results to a JUnit XML file
+pub fn write(path: &Path, suite_name: &str, results: &[StepComparison]) -> anyhow::Result<()> {
+    fs::write(path, render(suite_name, results))?;
+    Ok(())
+}
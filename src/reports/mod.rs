@@ -0,0 +1,7 @@
+//! Comparison reports: HTML, JSON, JUnit XML, charts and the run summary
+
+pub mod html;
+pub mod json;
+pub mod junit;
+pub mod plot;
+pub mod summary;
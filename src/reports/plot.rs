@@ -0,0 +1,298 @@
+//! Force and packet rate charts of captured steps, as SVG or PNG
+//!
+//! Each captured step gets up to three charts: the force its packets commanded
+//! over time (decoded by [`force_curve`]) against the force the scenario asks
+//! for, the packet rate over time from the packet timestamps, and the steering
+//! axis position when it was sampled (`--axis-rate`).
+//! Charts are drawn with plotters, as SVG (embedded in the HTML report) or PNG.
+//! SVG leaves the text to the viewer and builds everywhere, wasm included; PNG
+//! rasterizes it with a system font and comes with the `cli` feature.
+
+use crate::force_curve;
+use crate::{ScenarioStep, StepOutput};
+use plotters::coord::Shift;
+use plotters::prelude::*;
+
+const WIDTH: u32 = 640;
+const HEIGHT: u32 = 260;
+/// Ticks on each axis, including both ends
+const TICKS: usize = 5;
+/// Points the intended force is sampled at
+const INTENDED_SAMPLES: usize = 200;
+
+const TEXT: RGBColor = RGBColor(0x22, 0x22, 0x22);
+const GRID: RGBColor = RGBColor(0xDD, 0xDD, 0xDD);
+const COMMANDED: RGBColor = RGBColor(0x1E, 0x88, 0xE5);
+const INTENDED: RGBColor = RGBColor(0xEF, 0x6C, 0x00);
+const RATE: RGBColor = RGBColor(0x2E, 0x7D, 0x32);
+const AXIS: RGBColor = RGBColor(0x8E, 0x24, 0xAA);
+
+/// A chart could not be drawn or encoded
+#[derive(Debug, thiserror::Error)]
+#[error("Failed to draw chart: {0}")]
+pub struct PlotError(String);
+
+/// Output format of a chart
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum PlotFormat {
+    #[default]
+    Svg,
+    Png,
+}
+
+impl PlotFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PlotFormat::Svg => "svg",
+            PlotFormat::Png => "png",
+        }
+    }
+}
+
+/// A line of a chart
+#[derive(Debug, Clone)]
+pub struct Series {
+    pub label: String,
+    pub color: RGBColor,
+    /// (x, y), sorted by x
+    pub points: Vec<(f64, f64)>,
+    /// Hold each value until the next point, as a device holds a commanded force
+    pub step: bool,
+}
+
+/// A line chart with a title, axis labels and one or more series
+#[derive(Debug, Clone)]
+pub struct Chart {
+    pub title: String,
+    pub x_label: String,
+    pub y_label: String,
+    pub series: Vec<Series>,
+}
+
+/// Force commanded by the packets of `output` over time, with the force of the
+/// scenario step it was played from; None without timed, decoded forces
+pub fn force_chart(step: Option<&ScenarioStep>, output: &StepOutput) -> Option<Chart> {
+    let curve = force_curve::reconstruct(step, output);
+    let commanded: Vec<(f64, f64)> = curve
+        .samples
+        .iter()
+        .filter_map(|sample| Some((sample.time_ms?, sample.force)))
+        .collect();
+    let last = commanded.last()?.0;
+
+    let mut series = vec![Series {
+        label: "commanded".to_string(),
+        color: COMMANDED,
+        points: commanded,
+        step: true,
+    }];
+    if let Some(effect) = step.map(|step| &step.effect).filter(|e| e.force_at(0.0).is_some()) {
+        let end = match effect.duration() {
+            0 => last,
+            duration => last.max((effect.start_delay() + duration) as f64),
+        };
+        let points = (0..INTENDED_SAMPLES)
+            .filter_map(|i| {
+                let t = end * i as f64 / (INTENDED_SAMPLES - 1) as f64;
+                Some((t, effect.force_at(t)?))
+            })
+            .collect();
+        series.push(Series {
+            label: "scenario".to_string(),
+            color: INTENDED,
+            points,
+            step: false,
+        });
+    }
+    Some(Chart {
+        title: format!("Step {}: {} - force", output.step_index, output.step_name),
+        x_label: "time since submit (ms)".to_string(),
+        y_label: "force".to_string(),
+        series,
+    })
+}
+
+/// Packet rate of `output` over time, from the interval before each packet;
+/// None with fewer than two timed intervals
+pub fn rate_chart(output: &StepOutput) -> Option<Chart> {
+    let points: Vec<(f64, f64)> = output
+        .timestamps_us
+        .windows(2)
+        .filter(|pair| pair[1] > pair[0])
+        .map(|pair| (pair[1] as f64 / 1000.0, 1_000_000.0 / (pair[1] - pair[0]) as f64))
+        .collect();
+    if points.len() < 2 {
+        return None;
+    }
+    Some(Chart {
+        title: format!("Step {}: {} - packet rate", output.step_index, output.step_name),
+        x_label: "time since first packet (ms)".to_string(),
+        y_label: "packets/s".to_string(),
+        series: vec![Series {
+            label: "rate".to_string(),
+            color: RATE,
+            points,
+            step: false,
+        }],
+    })
+}
+
+/// Steering axis position of `output` over time, None with fewer than two samples
+pub fn axis_chart(output: &StepOutput) -> Option<Chart> {
+    if output.axis.len() < 2 {
+        return None;
+    }
+    Some(Chart {
+        title: format!("Step {}: {} - wheel axis", output.step_index, output.step_name),
+        x_label: "time since submit (ms)".to_string(),
+        y_label: "position".to_string(),
+        series: vec![Series {
+            label: "axis".to_string(),
+            color: AXIS,
+            points: output
+                .axis
+                .iter()
+                .map(|s| (s.time_us as f64 / 1000.0, s.position as f64))
+                .collect(),
+            step: false,
+        }],
+    })
+}
+
+/// Value range of an axis, widened to include 0 for the y axis
+fn range(values: impl Iterator<Item = f64>, include_zero: bool) -> (f64, f64) {
+    let (mut min, mut max) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), v| (min.min(v), max.max(v)));
+    if include_zero {
+        min = min.min(0.0);
+        max = max.max(0.0);
+    }
+    if !min.is_finite() || !max.is_finite() {
+        return (0.0, 1.0);
+    }
+    if max <= min {
+        return (min - 1.0, max + 1.0);
+    }
+    (min, max)
+}
+
+/// Tick label with as many decimals as the axis span needs
+fn tick_label(value: f64, span: f64) -> String {
+    let decimals = if span >= 10.0 {
+        0
+    } else if span >= 1.0 {
+        1
+    } else {
+        2
+    };
+    format!("{:.*}", decimals, value)
+}
+
+impl Series {
+    /// Points of the drawn line; a step series holds each value until the next point, and the last one until `x_max`
+    fn line(&self, x_max: f64) -> Vec<(f64, f64)> {
+        if !self.step {
+            return self.points.clone();
+        }
+        let mut line = Vec::with_capacity(self.points.len() * 2 + 1);
+        for (i, &(x, y)) in self.points.iter().enumerate() {
+            if i > 0 {
+                line.push((x, self.points[i - 1].1));
+            }
+            line.push((x, y));
+        }
+        if let Some(&(_, y)) = self.points.last() {
+            line.push((x_max, y));
+        }
+        line
+    }
+}
+
+impl Chart {
+    /// Draw the chart on `root`, whatever the backend
+    fn draw<DB: DrawingBackend>(&self, root: &DrawingArea<DB, Shift>) -> Result<(), DrawingAreaErrorKind<DB::ErrorType>> {
+        let (x_min, x_max) = range(self.series.iter().flat_map(|s| s.points.iter().map(|p| p.0)), false);
+        let (y_min, y_max) = range(self.series.iter().flat_map(|s| s.points.iter().map(|p| p.1)), true);
+        let x_label = |x: &f64| tick_label(*x, x_max - x_min);
+        let y_label = |y: &f64| tick_label(*y, y_max - y_min);
+
+        root.fill(&WHITE)?;
+        let mut chart = ChartBuilder::on(root)
+            .caption(&self.title, ("sans-serif", 14))
+            .margin(10)
+            .x_label_area_size(36)
+            .y_label_area_size(56)
+            .build_cartesian_2d(x_min..x_max, y_min..y_max)?;
+        chart
+            .configure_mesh()
+            .x_labels(TICKS)
+            .y_labels(TICKS)
+            .x_label_formatter(&x_label)
+            .y_label_formatter(&y_label)
+            .x_desc(self.x_label.as_str())
+            .y_desc(self.y_label.as_str())
+            .bold_line_style(GRID)
+            .light_line_style(TRANSPARENT)
+            .draw()?;
+        if y_min < 0.0 && y_max > 0.0 {
+            chart.draw_series(LineSeries::new([(x_min, 0.0), (x_max, 0.0)], &TEXT))?;
+        }
+
+        for series in &self.series {
+            let color = series.color;
+            chart
+                .draw_series(LineSeries::new(series.line(x_max), color.stroke_width(2)))?
+                .label(series.label.as_str())
+                .legend(move |(x, y)| PathElement::new([(x, y), (x + 16, y)], color.stroke_width(2)));
+        }
+        chart
+            .configure_series_labels()
+            .position(SeriesLabelPosition::UpperRight)
+            .background_style(WHITE.mix(0.8))
+            .border_style(GRID)
+            .draw()?;
+        root.present()
+    }
+
+    /// The chart as a standalone SVG document, also embeddable in HTML
+    pub fn svg(&self) -> Result<String, PlotError> {
+        let mut svg = String::new();
+        {
+            let root = SVGBackend::with_string(&mut svg, (WIDTH, HEIGHT)).into_drawing_area();
+            self.draw(&root).map_err(|e| PlotError(e.to_string()))?;
+        }
+        Ok(svg)
+    }
+
+    /// The chart as a PNG image, drawn with the system's sans-serif font
+    #[cfg(feature = "cli")]
+    pub fn png(&self) -> Result<Vec<u8>, PlotError> {
+        let mut pixels = vec![0u8; (WIDTH * HEIGHT * 3) as usize];
+        {
+            let root = BitMapBackend::with_buffer(&mut pixels, (WIDTH, HEIGHT)).into_drawing_area();
+            self.draw(&root).map_err(|e| PlotError(e.to_string()))?;
+        }
+
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, WIDTH, HEIGHT);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(|e| PlotError(e.to_string()))?;
+        writer.write_image_data(&pixels).map_err(|e| PlotError(e.to_string()))?;
+        writer.finish().map_err(|e| PlotError(e.to_string()))?;
+        Ok(bytes)
+    }
+
+    /// PNG needs plotters' font rendering, which the `cli` feature brings
+    #[cfg(not(feature = "cli"))]
+    pub fn png(&self) -> Result<Vec<u8>, PlotError> {
+        Err(PlotError("PNG charts need the cli feature".to_string()))
+    }
+
+    /// The chart in `format`
+    pub fn render(&self, format: PlotFormat) -> Result<Vec<u8>, PlotError> {
+        match format {
+            PlotFormat::Svg => Ok(self.svg()?.into_bytes()),
+            PlotFormat::Png => self.png(),
+        }
+    }
+}
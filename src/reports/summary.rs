@@ -0,0 +1,195 @@
+//! Summary report across past runs
+//!
+//! Combines the run history (pass/fail and packet counts per scenario, driver
+//! and day) with the latency lines of the capture files in `runs/` into one
+//! Markdown document for release sign-off.
+
+use crate::history::{format_timestamp, RunRecord};
+use crate::CaptureReader;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Latency figures of one capture file
+#[derive(Debug, Clone)]
+pub struct CaptureLatency {
+    pub path: PathBuf,
+    /// Modification date (YYYY-MM-DD)
+    pub date: String,
+    pub packets: usize,
+    pub measured: usize,
+    pub avg_us: f64,
+    pub max_us: u64,
+}
+
+/// Day (YYYY-MM-DD, UTC) of a Unix timestamp
+fn day(timestamp: u64) -> String {
+    format_timestamp(timestamp)[..10].to_string()
+}
+
+/// Latency of the capture files directly in `dir` that have latency lines and were
+/// modified since `since` (Unix timestamp), oldest first
+pub fn scan_latencies(dir: &Path, since: u64) -> anyhow::Result<Vec<CaptureLatency>> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(Vec::new());
+    };
+    let mut captures = Vec::new();
+    for path in entries.filter_map(|entry| entry.ok().map(|e| e.path())) {
+        if !path.is_file() {
+            continue;
+        }
+        let modified = fs::metadata(&path)?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        if modified < since {
+            continue;
+        }
+        // Skip files that are not captures rather than failing the report; large
+        // captures are read one step at a time
+        let Ok(reader) = CaptureReader::open(&path) else {
+            continue;
+        };
+        let (mut packets, mut measured, mut total_us, mut max_us) = (0, 0, 0, None);
+        for step in reader.steps() {
+            let Ok(step) = step else {
+                break;
+            };
+            packets += step.packets.len();
+            for latency_us in step.latencies_us.iter().flatten().copied() {
+                measured += 1;
+                total_us += latency_us;
+                max_us = max_us.max(Some(latency_us));
+            }
+        }
+        let Some(max_us) = max_us else {
+            continue;
+        };
+        captures.push((
+            modified,
+            CaptureLatency {
+                date: day(modified),
+                packets,
+                measured,
+                avg_us: total_us as f64 / measured as f64,
+                max_us,
+                path,
+            },
+        ));
+    }
+    captures.sort_by_key(|(modified, _)| *modified);
+    Ok(captures.into_iter().map(|(_, capture)| capture).collect())
+}
+
+/// Render the report for runs since `since` (Unix timestamp)
+pub fn render(records: &[RunRecord], latencies: &[CaptureLatency], since: u64) -> String {
+    let records: Vec<&RunRecord> = records.iter().filter(|r| r.timestamp >= since).collect();
+    let mut out = String::new();
+    let _ = writeln!(out, "# FFB Replay Run Report\n");
+    let _ = writeln!(
+        out,
+        "{} runs since {} (UTC), {} capture files with latency data\n",
+        records.len(),
+        day(since),
+        latencies.len()
+    );
+
+    // (scenario, driver) -> day -> runs of that day
+    let mut by_target: BTreeMap<(&str, &str), BTreeMap<String, Vec<&RunRecord>>> = BTreeMap::new();
+    for record in &records {
+        by_target
+            .entry((record.scenario.as_str(), record.driver.as_str()))
+            .or_default()
+            .entry(day(record.timestamp))
+            .or_default()
+            .push(record);
+    }
+    let mut days: Vec<String> = records.iter().map(|r| day(r.timestamp)).collect();
+    days.sort();
+    days.dedup();
+
+    let _ = writeln!(out, "## Pass/fail matrix\n");
+    if by_target.is_empty() {
+        let _ = writeln!(out, "No runs in the history.\n");
+    } else {
+        let _ = writeln!(out, "Passed compare and test runs out of all of them per scenario, driver and day.\n");
+        let _ = writeln!(out, "| Scenario | Driver | {} |", days.join(" | "));
+        let _ = writeln!(out, "|---|---|{}", "---|".repeat(days.len()));
+        for ((scenario, driver), runs_by_day) in &by_target {
+            let cells: Vec<String> = days
+                .iter()
+                .map(|d| {
+                    // Record runs have nothing to pass or fail
+                    let runs: Vec<&RunRecord> = runs_by_day
+                        .get(d)
+                        .map(|runs| runs.iter().copied().filter(|r| r.is_comparison()).collect())
+                        .unwrap_or_default();
+                    if runs.is_empty() {
+                        return "-".to_string();
+                    }
+                    let passed = runs.iter().filter(|r| r.passed()).count();
+                    let mark = if passed == runs.len() { "PASS" } else { "FAIL" };
+                    format!("{} {}/{}", mark, passed, runs.len())
+                })
+                .collect();
+            let _ = writeln!(out, "| {} | {} | {} |", scenario, driver, cells.join(" | "));
+        }
+        let _ = writeln!(out);
+
+        let _ = writeln!(out, "## Packet count trends\n");
+        let _ = writeln!(out, "Packets of the last run of each day, oldest to newest.\n");
+        let _ = writeln!(out, "| Scenario | Driver | Packets per day | Min | Max | Change |");
+        let _ = writeln!(out, "|---|---|---|---|---|---|");
+        for ((scenario, driver), runs_by_day) in &by_target {
+            let counts: Vec<usize> = runs_by_day
+                .values()
+                .filter_map(|runs| runs.last().map(|r| r.total_packets))
+                .collect();
+            let (Some(first), Some(last)) = (counts.first(), counts.last()) else {
+                continue;
+            };
+            let change = if *first == 0 {
+                "-".to_string()
+            } else {
+                format!("{:+.1}%", (*last as f64 - *first as f64) * 100.0 / *first as f64)
+            };
+            let series: Vec<String> = counts.iter().map(|c| c.to_string()).collect();
+            let _ = writeln!(
+                out,
+                "| {} | {} | {} | {} | {} | {} |",
+                scenario,
+                driver,
+                series.join(" -> "),
+                counts.iter().min().unwrap_or(&0),
+                counts.iter().max().unwrap_or(&0),
+                change
+            );
+        }
+        let _ = writeln!(out);
+    }
+
+    let _ = writeln!(out, "## Latency trends\n");
+    if latencies.is_empty() {
+        let _ = writeln!(out, "No capture files with latency data.");
+    } else {
+        let _ = writeln!(out, "Submit-to-complete latency per capture file, oldest to newest.\n");
+        let _ = writeln!(out, "| Date | Capture | Packets | Measured | Avg (us) | Max (us) |");
+        let _ = writeln!(out, "|---|---|---|---|---|---|");
+        for capture in latencies {
+            let _ = writeln!(
+                out,
+                "| {} | {} | {} | {} | {:.0} | {} |",
+                capture.date,
+                capture.path.display(),
+                capture.packets,
+                capture.measured,
+                capture.avg_us,
+                capture.max_us
+            );
+        }
+    }
+    out
+}
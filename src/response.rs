@@ -0,0 +1,166 @@
+//! Commanded force against measured wheel motion
+//!
+//! A capture recorded with `--axis-rate` holds both sides of each step: the force
+//! its packets commanded ([`force_curve`]) and where the steering axis went
+//! ([`telemetry`](crate::telemetry)). Lining them up on the step's time line
+//! gives the wheel's peak deflection from where it started and the delay from
+//! the force's onset to the first movement. A step whose packets
+//! went out and commanded a force, but whose wheel did not move, points at the
+//! device (disabled force feedback, a firmware that ignored the effect, gain at
+//! zero) rather than at the driver.
+
+use crate::force_curve::{self, ForceCurve};
+use crate::{Scenario, ScenarioStep, StepOutput};
+use serde::Serialize;
+
+/// Default deflection from the start position that counts as movement, about 1%
+/// of the axis travel
+pub const DEFAULT_MIN_DEFLECTION: u16 = 328;
+
+/// Share of the peak force at which the force counts as applied
+const ONSET_FRACTION: f64 = 0.1;
+
+/// How the wheel reacted to a step
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    /// The wheel moved after the force was commanded
+    Responded,
+    /// Packets commanded a force but the wheel did not move
+    Unresponsive,
+    /// Nothing to respond to: no packets or no non-zero force (e.g. condition effects)
+    NoForce,
+    /// The step has no axis samples
+    NoTelemetry,
+}
+
+/// Commanded force and wheel motion of one step
+#[derive(Debug, Clone, Serialize)]
+pub struct StepResponse {
+    pub step_index: usize,
+    pub step_name: String,
+    /// Largest commanded force by magnitude, with its sign
+    pub peak_force: Option<f64>,
+    /// Forces come from the scenario because no packet decoded to one
+    pub force_from_scenario: bool,
+    /// Time the force first reached a tenth of its peak (ms since submission)
+    pub onset_ms: Option<f64>,
+    /// Largest distance of the axis from its start position, with its sign
+    pub peak_deflection: Option<i32>,
+    /// Time from the force's onset to the first movement (ms)
+    pub delay_ms: Option<f64>,
+    pub verdict: Verdict,
+}
+
+impl StepResponse {
+    /// One line summary, e.g. "peak force 8000 from 12.0 ms, wheel moved 4410 after 38.5 ms"
+    pub fn describe(&self) -> String {
+        let force = match (self.peak_force, self.onset_ms) {
+            (Some(peak), Some(onset)) => format!("peak force {:.0} from {:.1} ms", peak, onset),
+            (Some(peak), None) => format!("peak force {:.0}", peak),
+            (None, _) => "no decoded force".to_string(),
+        };
+        let source = if self.force_from_scenario { " (scenario)" } else { "" };
+        let motion = match (self.verdict, self.peak_deflection, self.delay_ms) {
+            (Verdict::NoTelemetry, _, _) => "no axis samples".to_string(),
+            (Verdict::Unresponsive, Some(deflection), _) => {
+                format!("wheel did not move (peak deflection {})", deflection)
+            }
+            (_, Some(deflection), Some(delay)) => format!("wheel moved {} after {:.1} ms", deflection, delay),
+            (_, Some(deflection), None) => format!("peak deflection {}", deflection),
+            (_, None, _) => "no axis samples".to_string(),
+        };
+        format!("{}{}, {}", force, source, motion)
+    }
+}
+
+/// Compare the force commanded during `output` (its decoded `curve`, or `step`'s
+/// intended force if no packet decoded to one) with the wheel's axis samples
+pub fn analyze(
+    step: Option<&ScenarioStep>,
+    output: &StepOutput,
+    curve: &ForceCurve,
+    min_deflection: u16,
+) -> StepResponse {
+    let mut force_from_scenario = false;
+    let mut forces: Vec<(f64, f64)> = curve
+        .samples
+        .iter()
+        .filter_map(|sample| Some((sample.time_ms?, sample.force)))
+        .collect();
+    if curve.samples.is_empty() && !output.packets.is_empty() {
+        if let Some(step) = step {
+            forces = output
+                .axis
+                .iter()
+                .filter_map(|sample| {
+                    let t = sample.time_us as f64 / 1000.0;
+                    Some((t, step.effect.force_at(t)?))
+                })
+                .collect();
+            force_from_scenario = !forces.is_empty();
+        }
+    }
+
+    let peak_force = curve
+        .samples
+        .iter()
+        .map(|sample| sample.force)
+        .chain(forces.iter().map(|&(_, force)| force))
+        .reduce(|a, b| if b.abs() > a.abs() { b } else { a });
+    let onset_ms = peak_force.and_then(|peak| {
+        forces
+            .iter()
+            .find(|&&(_, force)| peak != 0.0 && force.abs() >= peak.abs() * ONSET_FRACTION)
+            .map(|&(t, _)| t)
+    });
+
+    let start = output.axis.first().map(|sample| i32::from(sample.position));
+    let peak_deflection = start.and_then(|start| {
+        output
+            .axis
+            .iter()
+            .map(|sample| i32::from(sample.position) - start)
+            .max_by_key(|deflection| deflection.abs())
+    });
+    let moved_ms = start.and_then(|start| {
+        output
+            .axis
+            .iter()
+            .find(|sample| (i32::from(sample.position) - start).unsigned_abs() >= u32::from(min_deflection))
+            .map(|sample| sample.time_us as f64 / 1000.0)
+    });
+
+    let commanded = !output.packets.is_empty() && peak_force.is_some_and(|peak| peak != 0.0);
+    let verdict = if !commanded {
+        Verdict::NoForce
+    } else if output.axis.is_empty() {
+        Verdict::NoTelemetry
+    } else if moved_ms.is_none() {
+        Verdict::Unresponsive
+    } else {
+        Verdict::Responded
+    };
+
+    StepResponse {
+        step_index: output.step_index,
+        step_name: output.step_name.clone(),
+        peak_force,
+        force_from_scenario,
+        onset_ms,
+        peak_deflection,
+        delay_ms: onset_ms.zip(moved_ms).map(|(onset, moved)| (moved - onset).max(0.0)),
+        verdict,
+    }
+}
+
+/// Response of every step output, against the scenario step it was played from
+pub fn analyze_run(scenario: Option<&Scenario>, outputs: &[StepOutput], min_deflection: u16) -> Vec<StepResponse> {
+    outputs
+        .iter()
+        .map(|output| {
+            let step = scenario.and_then(|scenario| scenario.steps.get(output.step_index.saturating_sub(1)));
+            analyze(step, output, &force_curve::reconstruct(step, output), min_deflection)
+        })
+        .collect()
+}
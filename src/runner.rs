@@ -0,0 +1,309 @@
+//! Scenario runs as a reusable unit
+//!
+//! `ScenarioRunner` is the core of `record`, `play`, `compare` and batch runs: it
+//! creates and initializes the driver, plays the scenario while reporting to the
+//! configured observer, shuts the driver down and returns a `RunReport` with the
+//! captured steps, the comparison against the expected steps and the results of the
+//! inline `expect` rules. Printing, saving captures and run history are up to the
+//! caller.
+//!
+//! Long runs can stream their steps to a capture file as they complete
+//! ([`ScenarioRunner::stream_to`]) and keep none of them in memory
+//! ([`ScenarioRunner::retain_steps`]).
+//!
+//! ```no_run
+//! use ffb_replay::runner::ScenarioRunner;
+//! use ffb_replay::{CaptureReader, Scenario};
+//! use std::path::PathBuf;
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let scenario = Scenario::load_from_file(&PathBuf::from("scenarios/simple_test.yaml"))?;
+//! let expected = CaptureReader::open(&PathBuf::from("runs/simple_test.txt"))?;
+//! let report = ScenarioRunner::new("sdl")
+//!     .capture(Default::default())
+//!     .compare_with(&expected, Default::default())
+//!     .run(&scenario)?;
+//! println!("{} of {} steps differ", report.mismatched_steps(), report.steps.len());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::cancel::CancellationToken;
+use crate::capabilities;
+use crate::compare::{CompareOptions, ExpectedSteps, StepComparison};
+use crate::driver::FfbDriver;
+use crate::drivers::force_limit::{ForceLimit, MaxForce};
+use crate::error::FFBError;
+use crate::history::{self, RunRecord};
+use crate::latency::WireLatencyDistribution;
+use crate::observer::{ConsoleObserver, PlaybackObserver};
+use crate::capture_file::CaptureWriter;
+use crate::scenario::ExpectationReport;
+use crate::usb_monitor::CaptureConfig;
+use crate::{create_driver, Scenario, StepOutput};
+use std::path::PathBuf;
+use std::time::Instant;
+
+enum DriverSource {
+    Name(String),
+    Instance(Box<dyn FfbDriver>),
+}
+
+/// Configuration of one scenario run
+pub struct ScenarioRunner<'a> {
+    driver: DriverSource,
+    capture: Option<CaptureConfig>,
+    expected: Option<ExpectedSteps<'a>>,
+    options: CompareOptions,
+    observer: Box<dyn PlaybackObserver + 'a>,
+    cancel: CancellationToken,
+    stream: Option<PathBuf>,
+    retain_steps: bool,
+    max_force: Option<MaxForce>,
+    strict: bool,
+}
+
+impl<'a> ScenarioRunner<'a> {
+    /// Run on the driver `create_driver` knows as `driver_name`, without capture
+    /// and with console progress until configured otherwise
+    pub fn new(driver_name: &str) -> Self {
+        Self::from_source(DriverSource::Name(driver_name.to_string()))
+    }
+
+    /// Run on an already created driver, e.g. a custom one; `capture` does not apply
+    pub fn with_driver(driver: Box<dyn FfbDriver>) -> Self {
+        Self::from_source(DriverSource::Instance(driver))
+    }
+
+    fn from_source(driver: DriverSource) -> Self {
+        Self {
+            driver,
+            capture: None,
+            expected: None,
+            options: CompareOptions::default(),
+            observer: Box::new(ConsoleObserver::default()),
+            cancel: CancellationToken::new(),
+            stream: None,
+            retain_steps: true,
+            max_force: None,
+            strict: false,
+        }
+    }
+
+    /// Capture the device's USB traffic while playing
+    pub fn capture(mut self, capture: CaptureConfig) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// Compare the captured steps with `expected` using `options`
+    /// A `CaptureReader` is read one step at a time, as each step is compared.
+    pub fn compare_with(mut self, expected: impl Into<ExpectedSteps<'a>>, options: CompareOptions) -> Self {
+        self.expected = Some(expected.into());
+        self.options = options;
+        self
+    }
+
+    /// Report progress to `observer` instead of the console
+    pub fn observer(mut self, observer: impl PlaybackObserver + 'a) -> Self {
+        self.observer = Box::new(observer);
+        self
+    }
+
+    /// Stop playback once `cancel` is cancelled, also in the middle of a step
+    pub fn cancel_token(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Write each step to the capture file `path` as soon as it is complete (see
+    /// [`CaptureWriter`]); the file is complete once `run` returns successfully
+    pub fn stream_to(mut self, path: impl Into<PathBuf>) -> Self {
+        self.stream = Some(path.into());
+        self
+    }
+
+    /// Keep the played steps in the report (the default); without them memory stays
+    /// flat over long runs, and the report only counts steps and packets
+    /// Comparisons still work: each step is compared as it completes.
+    pub fn retain_steps(mut self, retain: bool) -> Self {
+        self.retain_steps = retain;
+        self
+    }
+
+    /// Limit the force of every effect, also without a capture config (whose `max_force`
+    /// `create_driver` applies) or on a driver given to `with_driver`
+    pub fn max_force(mut self, max_force: Option<MaxForce>) -> Self {
+        self.max_force = max_force;
+        self
+    }
+
+    /// Fail before the first step if the driver's capabilities rule out part of the
+    /// scenario, instead of failing those steps as they play
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// The steps set with `compare_with`, e.g. for an observer that compares live
+    pub fn expected(&self) -> Option<ExpectedSteps<'a>> {
+        self.expected
+    }
+
+    /// The options set with `compare_with`, the defaults otherwise
+    pub fn compare_options(&self) -> CompareOptions {
+        self.options
+    }
+
+    /// The token that stops the run, e.g. for a keyboard handler to cancel or pause it
+    pub fn cancel(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Play `scenario` once; the driver is shut down even if playback fails
+    pub fn run(self, scenario: &Scenario) -> anyhow::Result<RunReport> {
+        if let Some(expected) = self.expected {
+            expected.validate()?;
+        }
+        let mut driver = match self.driver {
+            DriverSource::Name(name) => create_driver(&name, self.capture.as_ref())?,
+            DriverSource::Instance(driver) => driver,
+        };
+        if let Some(max) = self.max_force {
+            driver = Box::new(ForceLimit::new(driver, max));
+        }
+        let mut writer = self.stream.as_deref().map(CaptureWriter::create).transpose()?;
+        driver.set_cancellation(self.cancel.clone());
+        driver.initialize()?;
+
+        let mut observer = self.observer;
+        if let Some(capabilities) = driver.capabilities() {
+            let report = capabilities::check(scenario, &capabilities, driver.name());
+            observer.capabilities_checked(&report);
+            if self.strict && !report.is_compatible() {
+                let _ = driver.shutdown();
+                let unsupported: Vec<String> = report
+                    .unsupported()
+                    .iter()
+                    .map(|check| format!("{} ({})", check.requirement, check.needed))
+                    .collect();
+                return Err(FFBError::Unsupported(unsupported.join(", ")).into());
+            }
+        }
+
+        let retain = self.retain_steps;
+        let options = self.options;
+        let mut comparison = self.expected.map(|_| Vec::new());
+        let mut steps = Vec::new();
+        let mut expectations = ExpectationReport::default();
+        let (mut step_count, mut packet_count) = (0, 0);
+        let started = Instant::now();
+        let played = scenario.play_into(driver.as_mut(), observer.as_mut(), &self.cancel, &mut |step| {
+            if let Some(writer) = writer.as_mut() {
+                writer.write_step(&step)?;
+                // A second Ctrl+C exits without unwinding, so nothing may stay buffered
+                if self.cancel.is_cancelled() {
+                    writer.flush()?;
+                }
+            }
+            scenario.check_step_expectations(&step, &mut expectations);
+            if let (Some(expected), Some(results)) = (self.expected, comparison.as_mut()) {
+                results.extend(expected.compare_step(step_count, Some(&step), &options)?);
+            }
+            step_count += 1;
+            packet_count += step.packets.len();
+            if retain {
+                steps.push(step);
+            }
+            Ok(())
+        });
+        let duration_ms = started.elapsed().as_millis() as u64;
+        let shutdown = driver.shutdown();
+        played?;
+        shutdown?;
+        if let Some(writer) = writer {
+            writer.finish()?;
+        }
+
+        // Expected steps the run did not reach
+        if let (Some(expected), Some(results)) = (self.expected, comparison.as_mut()) {
+            for position in step_count..expected.len() {
+                results.extend(expected.compare_step(position, None, &options)?);
+            }
+        }
+        Ok(RunReport {
+            scenario: scenario.name.clone(),
+            driver: driver.name().to_string(),
+            steps,
+            step_count,
+            packet_count,
+            comparison,
+            expectations,
+            duration_ms,
+            cancelled: self.cancel.is_cancelled() || observer.cancelled(),
+        })
+    }
+}
+
+/// Outcome of a scenario run
+#[derive(Debug, Clone)]
+pub struct RunReport {
+    /// Name of the played scenario
+    pub scenario: String,
+    /// Name of the driver that played it
+    pub driver: String,
+    /// Captured output of every played step, empty unless the runner retained them
+    pub steps: Vec<StepOutput>,
+    /// Played steps, also when they were not retained
+    pub step_count: usize,
+    /// Packets of the played steps, also when they were not retained
+    pub packet_count: usize,
+    /// Per-step comparison, None without expected steps
+    pub comparison: Option<Vec<StepComparison>>,
+    /// Results of the scenario's inline `expect` rules
+    pub expectations: ExpectationReport,
+    /// Wall time of the playback
+    pub duration_ms: u64,
+    /// Playback stopped early through the cancel token or the observer
+    pub cancelled: bool,
+}
+
+impl RunReport {
+    /// Packets of all played steps, also when they were not retained
+    pub fn total_packets(&self) -> usize {
+        self.packet_count
+    }
+
+    /// Submit-to-wire latency over the steps, None if the driver did not measure it
+    pub fn wire_latency(&self) -> Option<WireLatencyDistribution> {
+        WireLatencyDistribution::of(&self.steps)
+    }
+
+    /// Steps that differ from the expected steps, or failed their `expect` rules
+    /// when not comparing
+    pub fn mismatched_steps(&self) -> usize {
+        match &self.comparison {
+            Some(results) => results.iter().filter(|r| !r.is_match()).count(),
+            None => self.expectations.failures.len(),
+        }
+    }
+
+    /// No step differs from the expected steps or failed its `expect` rules
+    pub fn passed(&self) -> bool {
+        self.mismatched_steps() == 0
+    }
+
+    /// The run as recorded in the run history (`runs/.history/runs.jsonl`)
+    pub fn history_record(&self, command: &str) -> RunRecord {
+        RunRecord {
+            timestamp: history::now_timestamp(),
+            command: command.to_string(),
+            scenario: self.scenario.clone(),
+            driver: self.driver.clone(),
+            total_steps: self.comparison.as_ref().map_or(self.step_count, Vec::len),
+            mismatched_steps: self.mismatched_steps(),
+            total_packets: self.total_packets(),
+            duration_ms: self.duration_ms,
+        }
+    }
+}
@@ -0,0 +1,544 @@
+//! Scenarios: effect sequences loaded from YAML and played on a driver
+
+use crate::cancel::CancellationToken;
+use crate::driver::{EffectProgress, FfbDriver, HeldEffect};
+use crate::effects::{self, Effect};
+use crate::schedule::{Event, Schedule, TimerResolution, TimingMode};
+use crate::error::{FFBError, FFBResult, ScenarioError};
+use crate::usb_monitor::UsbPacket;
+use crate::device_settings::DeviceSetting;
+use crate::peripheral::PeripheralCommand;
+use crate::{cadence, env_vars, expect, health, observer, telemetry};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+use std::time::Instant;
+
+/// Scenario step - effect with delay
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScenarioStep {
+    /// Effect
+    pub effect: Effect,
+    /// Inline expectations for the captured output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect: Option<expect::StepExpectations>,
+    /// Rev LED and display commands sent while the effect plays
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub peripherals: Vec<PeripheralCommand>,
+    /// Device settings applied as the effect starts, before the peripheral commands
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub settings: Vec<DeviceSetting>,
+    /// Keep the effect playing after the step, e.g. a spring under the following
+    /// steps, until the step `stop_at` starts or the iteration ends
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hold: bool,
+    /// 1-based step whose start stops the held effect; implies `hold`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_at: Option<usize>,
+}
+
+impl ScenarioStep {
+    /// Whether the effect outlives the step
+    pub fn holds(&self) -> bool {
+        self.hold || self.stop_at.is_some()
+    }
+
+    /// Check that `stop_at` names a later step of a scenario of `steps` steps
+    fn validate_stop_at(&self, index: usize, steps: usize) -> Result<(), String> {
+        match self.stop_at {
+            Some(stop_at) if stop_at <= index + 1 || stop_at > steps => Err(format!(
+                "stop_at {} is not a later step (steps {}..{})",
+                stop_at,
+                index + 2,
+                steps
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Playback scenario
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scenario {
+    /// Scenario name
+    pub name: String,
+    /// Description
+    #[serde(default)]
+    pub description: String,
+    /// Loop forever
+    #[serde(default)]
+    pub loop_forever: bool,
+    /// Repeat count (if not loop_forever)
+    #[serde(default = "default_repeat_count")]
+    pub repeat_count: u32,
+    /// How closely steps are started on time
+    #[serde(default, skip_serializing_if = "TimingMode::is_normal")]
+    pub timing: TimingMode,
+    /// Poll the device after every step and end the run once it stops responding
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub health_check: bool,
+    /// Scenario steps
+    pub steps: Vec<ScenarioStep>,
+}
+
+fn default_repeat_count() -> u32 {
+    1
+}
+
+/// Every effect type `effect_label` names, in the order of its match
+pub const EFFECT_LABELS: [&str; 11] = [
+    "Constant force",
+    "Periodic (sine)",
+    "Periodic (square)",
+    "Periodic (triangle)",
+    "Periodic (sawtooth up)",
+    "Periodic (sawtooth down)",
+    "Ramp (linear change)",
+    "Condition (spring)",
+    "Condition (damper)",
+    "Condition (friction)",
+    "Condition (inertia)",
+];
+
+/// Human-readable effect type used as the step name in captures
+pub fn effect_label(effect: &Effect) -> &'static str {
+    let index = match effect {
+        Effect::Constant { .. } => 0,
+        Effect::Periodic { effect, .. } => match effect.wave_type {
+            effects::WaveType::Sine => 1,
+            effects::WaveType::Square => 2,
+            effects::WaveType::Triangle => 3,
+            effects::WaveType::SawtoothUp => 4,
+            effects::WaveType::SawtoothDown => 5,
+        },
+        Effect::Ramp { .. } => 6,
+        Effect::Condition { effect, .. } => match effect.condition_type {
+            effects::ConditionType::Spring => 7,
+            effects::ConditionType::Damper => 8,
+            effects::ConditionType::Friction => 9,
+            effects::ConditionType::Inertia => 10,
+        },
+    };
+    EFFECT_LABELS[index]
+}
+
+/// Captured output for a single step
+#[derive(Debug, Clone, Default)]
+pub struct StepOutput {
+    /// 1-based index of the step in the scenario
+    pub step_index: usize,
+    /// Effect label of the step, e.g. "Constant Force"
+    pub step_name: String,
+    /// Captured or generated packets as hex, e.g. "01 05 01 88 13"
+    pub packets: Vec<String>,
+    /// Alternative acceptable packet sequences (capture files only)
+    pub variants: Vec<Vec<String>>,
+    /// Submit-to-complete latency of each packet (us), empty if not measured
+    pub latencies_us: Vec<Option<u64>>,
+    /// Time from submitting the step's effect to its first OUT packet on the wire
+    /// (us), None if not measured
+    pub wire_latency_us: Option<u64>,
+    /// Capture time of each packet since the step's first packet (us), empty if
+    /// not captured
+    pub timestamps_us: Vec<u64>,
+    /// Steering axis positions sampled while the step played, empty if not sampled
+    pub axis: Vec<telemetry::AxisSample>,
+    /// Packets of the additional devices of a multi-device capture
+    pub devices: Vec<DeviceCapture>,
+    /// Health check of the device after the step, None if not checked
+    pub health: Option<health::Health>,
+}
+
+/// Packets one additional capture device received during a step
+#[derive(Debug, Clone)]
+pub struct DeviceCapture {
+    /// VID:PID of the device
+    pub device: String,
+    /// Packets as hex, like `StepOutput::packets`
+    pub packets: Vec<String>,
+}
+
+/// Results of the inline `expect` rules of a run
+#[derive(Debug, Clone, Default)]
+pub struct ExpectationReport {
+    /// Steps that have rules
+    pub checked_steps: usize,
+    /// Steps whose output broke their rules
+    pub failures: Vec<ExpectationFailure>,
+}
+
+/// A step whose output broke its `expect` rules
+#[derive(Debug, Clone)]
+pub struct ExpectationFailure {
+    /// 1-based index of the step
+    pub step_index: usize,
+    /// Effect label of the step
+    pub step_name: String,
+    /// Each broken rule, with what the step sent instead
+    pub failures: Vec<String>,
+}
+
+impl ExpectationReport {
+    /// Print each failed step with its broken rules
+    pub fn print(&self) {
+        for step in &self.failures {
+            println!("EXPECT FAIL Step {}: {}", step.step_index, step.step_name);
+            for failure in &step.failures {
+                println!("  {}", failure);
+            }
+        }
+
+        if self.checked_steps > 0 {
+            if self.failures.is_empty() {
+                println!("Expectations: all {} checked steps passed", self.checked_steps);
+            } else {
+                println!(
+                    "Expectations: {} of {} checked steps failed",
+                    self.failures.len(),
+                    self.checked_steps
+                );
+            }
+        }
+    }
+}
+
+impl Scenario {
+    /// Load scenario from YAML file
+    pub fn load_from_file(path: &PathBuf) -> Result<Self, ScenarioError> {
+        let display = path.display().to_string();
+        if !path.exists() {
+            return Err(ScenarioError::NotFound(display));
+        }
+
+        let content = fs::read_to_string(path).map_err(|source| ScenarioError::Read {
+            path: display.clone(),
+            source,
+        })?;
+        let content = env_vars::expand_env(&content).map_err(|message| ScenarioError::Invalid {
+            path: display.clone(),
+            message,
+        })?;
+        let scenario: Scenario = serde_yaml::from_str(&content).map_err(|e| ScenarioError::Invalid {
+            path: display.clone(),
+            message: e.to_string(),
+        })?;
+
+        if scenario.steps.is_empty() {
+            return Err(ScenarioError::Invalid {
+                path: display,
+                message: "scenario has no steps".to_string(),
+            });
+        }
+
+        for (idx, step) in scenario.steps.iter().enumerate() {
+            if let Some(expect) = &step.expect {
+                expect.validate().map_err(|message| ScenarioError::Invalid {
+                    path: display.clone(),
+                    message: format!("step {}: {}", idx + 1, message),
+                })?;
+            }
+            for command in &step.peripherals {
+                command.validate().map_err(|message| ScenarioError::Invalid {
+                    path: display.clone(),
+                    message: format!("step {}: {}", idx + 1, message),
+                })?;
+            }
+            for setting in &step.settings {
+                setting.validate().map_err(|message| ScenarioError::Invalid {
+                    path: display.clone(),
+                    message: format!("step {}: {}", idx + 1, message),
+                })?;
+            }
+            step.validate_stop_at(idx, scenario.steps.len()).map_err(|message| ScenarioError::Invalid {
+                path: display.clone(),
+                message: format!("step {}: {}", idx + 1, message),
+            })?;
+        }
+
+        Ok(scenario)
+    }
+
+    /// Check step outputs against inline `expect` rules
+    /// Prints failures and returns the number of steps that failed
+    pub fn check_expectations(&self, outputs: &[StepOutput]) -> usize {
+        let report = self.evaluate_expectations(outputs);
+        report.print();
+        report.failures.len()
+    }
+
+    /// Check step outputs against inline `expect` rules without printing
+    pub fn evaluate_expectations(&self, outputs: &[StepOutput]) -> ExpectationReport {
+        let mut report = ExpectationReport::default();
+        for output in outputs {
+            self.check_step_expectations(output, &mut report);
+        }
+        report
+    }
+
+    /// Check one step output against its step's `expect` rules, adding to `report`
+    pub fn check_step_expectations(&self, output: &StepOutput, report: &mut ExpectationReport) {
+        let expect = match self
+            .steps
+            .get(output.step_index.saturating_sub(1))
+            .and_then(|s| s.expect.as_ref())
+        {
+            Some(expect) => expect,
+            None => return,
+        };
+
+        report.checked_steps += 1;
+        let failures = expect.check(&output.packets);
+        if !failures.is_empty() {
+            report.failures.push(ExpectationFailure {
+                step_index: output.step_index,
+                step_name: output.step_name.clone(),
+                failures,
+            });
+        }
+    }
+
+    /// Play scenario with a specific driver
+    /// Returns captured/generated packets organized by step
+    pub fn play(&self, driver: &mut dyn FfbDriver) -> anyhow::Result<Vec<StepOutput>> {
+        self.play_observed(driver, &mut observer::ConsoleObserver::default())
+    }
+
+    /// Play scenario, reporting progress to `observer` instead of the console
+    pub fn play_observed(
+        &self,
+        driver: &mut dyn FfbDriver,
+        observer: &mut dyn observer::PlaybackObserver,
+    ) -> anyhow::Result<Vec<StepOutput>> {
+        self.play_until(driver, observer, &CancellationToken::new())
+    }
+
+    /// Play scenario until done or `cancel` is cancelled
+    /// A cancelled step ends early and keeps the output captured so far.
+    /// Steps are started and collected by a [`Schedule`] on the scenario's clock.
+    pub fn play_until(
+        &self,
+        driver: &mut dyn FfbDriver,
+        observer: &mut dyn observer::PlaybackObserver,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<Vec<StepOutput>> {
+        let mut all_outputs = Vec::new();
+        self.play_into(driver, observer, cancel, &mut |output| {
+            all_outputs.push(output);
+            Ok(())
+        })?;
+        Ok(all_outputs)
+    }
+
+    /// `play_until`, handing each step's output to `sink` as soon as the step is
+    /// complete instead of collecting them, e.g. to write long runs to disk as they
+    /// play; an error from `sink` stops playback
+    pub fn play_into(
+        &self,
+        driver: &mut dyn FfbDriver,
+        observer: &mut dyn observer::PlaybackObserver,
+        cancel: &CancellationToken,
+        sink: &mut dyn FnMut(StepOutput) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        observer.scenario_started(self, &*driver);
+        for note in driver.take_notes() {
+            observer.driver_note(None, &note);
+        }
+        let live = driver.live_packets();
+
+        let iterations = if self.loop_forever {
+            u32::MAX
+        } else {
+            self.repeat_count
+        };
+
+        let _resolution = (self.timing == TimingMode::Precise).then(TimerResolution::raise);
+        let mut schedule = Schedule::new();
+        if iterations > 0 {
+            schedule.at(schedule.origin(), Event::Start { iteration: 0, step: 0 });
+        }
+        // Settings and peripheral reports of the step in flight, added to its packets when it finishes
+        let mut sent_packets = Ok(Vec::new());
+        let mut held = HeldEffects::default();
+
+        while let Some((mut due, event)) = schedule.pop() {
+            wait_until(due, self.timing, live.as_ref(), observer, cancel);
+
+            // Don't crash on effect errors - just report them and return empty result
+            // A dead capture aborts the run instead of producing an empty capture
+            let (iteration, idx, result) = match event {
+                Event::Start { iteration, step: idx } => {
+                    if idx == 0 {
+                        observer.iteration_started(iteration, iterations);
+                    }
+                    let Some(step) = self.steps.get(idx) else {
+                        held.release(driver, None);
+                        observer.iteration_finished();
+                        if iteration + 1 < iterations {
+                            schedule.at(due, Event::Start { iteration: iteration + 1, step: 0 });
+                        }
+                        continue;
+                    };
+                    if observer.cancelled() || cancel.is_cancelled() {
+                        break;
+                    }
+                    // Held by an emergency stop; the scenario's clock restarts on resume
+                    if cancel.is_paused() {
+                        if !cancel.wait_while_paused() {
+                            break;
+                        }
+                        due = Instant::now();
+                    }
+                    held.release(driver, Some(idx + 1));
+                    observer.step_started(idx, effect_label(&step.effect), step.effect.duration());
+                    let progress = driver.begin_effect(&step.effect);
+                    for note in driver.take_notes() {
+                        observer.driver_note(Some(idx), &note);
+                    }
+                    match progress {
+                        Ok(EffectProgress::Wait(wait)) => {
+                            sent_packets = send_step_commands(driver, step);
+                            // Closes on the schedule even if this step started late
+                            schedule.at(due + wait, Event::Finish { iteration, step: idx });
+                            continue;
+                        }
+                        Ok(EffectProgress::Done(packets)) => {
+                            let sent = send_step_commands(driver, step);
+                            (iteration, idx, with_sent(Ok(packets), sent))
+                        }
+                        Err(e) => (iteration, idx, Err(e)),
+                    }
+                }
+                Event::Finish { iteration, step: idx } => {
+                    let packets = driver.finish_effect();
+                    for packet in live.iter().flat_map(|live| live.try_iter()) {
+                        observer.packet_captured(&packet);
+                    }
+                    let sent = std::mem::replace(&mut sent_packets, Ok(Vec::new()));
+                    (iteration, idx, with_sent(packets, sent))
+                }
+            };
+
+            let packets = match result {
+                Ok(p) => p,
+                Err(e @ FFBError::CaptureFailed { .. }) => {
+                    let _ = driver.stop_all_effects();
+                    return Err(e.at_step(idx + 1).into());
+                }
+                Err(e) => {
+                    observer.step_failed(idx, &e);
+                    Vec::new()
+                }
+            };
+
+            let latencies_us: Vec<Option<u64>> = driver
+                .packet_latencies()
+                .iter()
+                .map(|l| l.map(|d| d.as_micros() as u64))
+                .collect();
+            let mut output = StepOutput {
+                step_index: idx + 1,
+                step_name: effect_label(&self.steps[idx].effect).to_string(),
+                packets,
+                variants: Vec::new(),
+                latencies_us,
+                wire_latency_us: driver.wire_latency().map(|d| d.as_micros() as u64),
+                timestamps_us: cadence::relative_us(&driver.packet_timestamps()),
+                axis: driver.axis_samples(),
+                devices: driver.device_captures(),
+                health: None,
+            };
+            observer.step_finished(idx, &output, driver.capture_summary().as_deref());
+            held.finish_step(driver, &self.steps[idx]);
+            if self.health_check {
+                output.health = driver.check_health();
+            }
+            let failed_health = output.health.clone().filter(|health| !health.responding);
+            sink(output)?;
+            // A device that stopped responding would only add empty steps
+            if let Some(health) = failed_health {
+                return Err(FFBError::not_responding(health.detail).at_step(idx + 1).into());
+            }
+
+            // The next step is due when this one was due to end
+            schedule.at(due, Event::Start { iteration, step: idx + 1 });
+        }
+
+        // Playback stopped in the middle of an iteration
+        held.release(driver, None);
+        observer.scenario_finished();
+        Ok(())
+    }
+}
+
+/// Send a step's settings, then its peripheral commands, right after its effect started
+pub fn send_step_commands(driver: &mut dyn FfbDriver, step: &ScenarioStep) -> FFBResult<Vec<String>> {
+    let mut sent = driver.send_settings(&step.settings)?;
+    sent.extend(driver.send_peripherals(&step.peripherals)?);
+    Ok(sent)
+}
+
+/// A step's packets followed by the reports its settings and peripheral commands
+/// generated; a failed command fails the step like a failed effect
+pub fn with_sent(packets: FFBResult<Vec<String>>, sent: FFBResult<Vec<String>>) -> FFBResult<Vec<String>> {
+    let mut packets = packets?;
+    packets.extend(sent?);
+    Ok(packets)
+}
+
+/// Effects of `hold` steps that are still playing, with the 1-based step that stops each
+#[derive(Debug, Clone, Default)]
+pub struct HeldEffects(Vec<(Option<usize>, HeldEffect)>);
+
+impl HeldEffects {
+    /// True if no effect outlives its step
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Stop a finished step's effect, or hold it if the step asks for it
+    /// Without held effects this stops everything, as after any step.
+    pub fn finish_step(&mut self, driver: &mut dyn FfbDriver, step: &ScenarioStep) {
+        if step.holds() {
+            self.0.extend(driver.hold_effect().map(|effect| (step.stop_at, effect)));
+        }
+        if self.0.is_empty() {
+            let _ = driver.stop_all_effects();
+        } else {
+            let _ = driver.stop_step_effect();
+        }
+    }
+
+    /// Stop the effects held until the 1-based step `step` starts, or all of them with None
+    pub fn release(&mut self, driver: &mut dyn FfbDriver, step: Option<usize>) {
+        self.0.retain(|(stop_at, effect)| {
+            let due = step.is_none() || *stop_at == step;
+            if due {
+                let _ = driver.release_effect(*effect);
+            }
+            !due
+        });
+    }
+}
+
+/// Wait until `deadline`, passing live packets to the observer in the meantime
+/// and returning early once `cancel` is cancelled
+fn wait_until(
+    deadline: Instant,
+    timing: TimingMode,
+    live: Option<&Receiver<UsbPacket>>,
+    observer: &mut dyn observer::PlaybackObserver,
+    cancel: &CancellationToken,
+) {
+    if let Some(live) = live {
+        let receive_until = deadline.checked_sub(timing.margin()).unwrap_or(deadline);
+        while let Some(remaining) = receive_until.checked_duration_since(Instant::now()) {
+            match cancel.recv_timeout(live, remaining) {
+                Ok(packet) => observer.packet_captured(&packet),
+                Err(_) => break,
+            }
+        }
+    }
+    // Without live packets, or when the capture went away
+    timing.wait_until(deadline, cancel);
+}
@@ -0,0 +1,145 @@
+//! Event schedule of scenario playback
+//!
+//! Playback runs on the scenario's own clock. Starting a step's effect and closing
+//! its capture window are events due at instants measured from the start of
+//! playback, and the player waits for the next due event instead of sleeping for an
+//! effect's duration after starting it. A step's window closes its effect's wait
+//! (start delay and duration plus ring buffer post-roll) after the step was due to
+//! start, and the next step is due at that same instant, so the time spent
+//! collecting captures and stopping effects does not push every following step
+//! later. Waits for an event forward live packets and wake up on cancellation.
+//!
+//! Sleeping only wakes up within the OS timer resolution, about a millisecond on
+//! Linux and up to 15.6 ms on Windows. Scenarios that depend on precise spacing use
+//! [`TimingMode::Precise`]: waits sleep until shortly before the event and spin for
+//! the rest, with the Windows timer resolution raised to 1 ms while playing.
+
+use crate::cancel::CancellationToken;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::time::{Duration, Instant};
+
+/// Time before a due event at which precise waits stop sleeping and spin; covers
+/// the oversleep of the OS timer
+#[cfg(windows)]
+const SPIN_MARGIN: Duration = Duration::from_millis(2);
+#[cfg(not(windows))]
+const SPIN_MARGIN: Duration = Duration::from_micros(200);
+
+/// How closely playback waits for due events
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TimingMode {
+    /// Sleep until the event is due, within the OS timer resolution
+    #[default]
+    Normal,
+    /// Sleep until shortly before the event and spin for the rest, for
+    /// sub-millisecond timing at the cost of a busy core while waiting
+    Precise,
+}
+
+impl TimingMode {
+    pub fn is_normal(&self) -> bool {
+        *self == TimingMode::Normal
+    }
+
+    /// Time before a due event at which waits stop sleeping
+    pub fn margin(self) -> Duration {
+        match self {
+            TimingMode::Normal => Duration::ZERO,
+            TimingMode::Precise => SPIN_MARGIN,
+        }
+    }
+
+    /// Wait until `deadline` or until `cancel` is cancelled
+    /// Returns false if the wait was cut short
+    pub fn wait_until(self, deadline: Instant, cancel: &CancellationToken) -> bool {
+        let Some(sleep_until) = deadline.checked_sub(self.margin()) else {
+            return cancel.sleep_until(deadline);
+        };
+        if !cancel.sleep_until(sleep_until) {
+            return false;
+        }
+        while Instant::now() < deadline {
+            if cancel.is_cancelled() {
+                return false;
+            }
+            std::hint::spin_loop();
+        }
+        true
+    }
+}
+
+/// Raises the OS timer resolution while alive, for precise playback; only
+/// Windows needs it, where sleeps otherwise round up to 15.6 ms
+pub struct TimerResolution(());
+
+impl TimerResolution {
+    pub fn raise() -> Self {
+        // SAFETY: paired with timeEndPeriod in drop
+        #[cfg(windows)]
+        unsafe {
+            windows_sys::Win32::Media::timeBeginPeriod(1);
+        }
+        TimerResolution(())
+    }
+}
+
+impl Drop for TimerResolution {
+    fn drop(&mut self) {
+        // SAFETY: ends the period begun in raise
+        #[cfg(windows)]
+        unsafe {
+            windows_sys::Win32::Media::timeEndPeriod(1);
+        }
+    }
+}
+
+/// Something playback does at a point in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Event {
+    /// Close the capture window of a step and collect its output; comes before a
+    /// start due at the same instant
+    Finish { iteration: u32, step: usize },
+    /// Start the effect of a step; `step` past the last one ends the iteration
+    Start { iteration: u32, step: usize },
+}
+
+/// Pending events in the order they are due
+#[derive(Debug)]
+pub struct Schedule {
+    origin: Instant,
+    events: BinaryHeap<Reverse<(Instant, Event)>>,
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Schedule {
+    /// Empty schedule whose clock starts now
+    pub fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+            events: BinaryHeap::new(),
+        }
+    }
+
+    /// Instant the schedule's clock started
+    pub fn origin(&self) -> Instant {
+        self.origin
+    }
+
+    /// Add `event`, due at `due`
+    pub fn at(&mut self, due: Instant, event: Event) {
+        self.events.push(Reverse((due, event)));
+    }
+
+    /// Remove the earliest event, None once the schedule is empty
+    pub fn pop(&mut self) -> Option<(Instant, Event)> {
+        self.events.pop().map(|Reverse(entry)| entry)
+    }
+}
@@ -0,0 +1,483 @@
+//! HTTP API for driving a lab machine remotely
+//!
+//! `serve` accepts runs over HTTP and plays them one at a time on a worker
+//! thread, since they share one device. Each run is a batch manifest entry
+//! (record or compare on a fresh driver). Scenarios and captures are addressed by
+//! file name only, so requests cannot reach files outside `scenarios/` and `runs/`.
+//!
+//! Endpoints (JSON unless noted):
+//! - `GET /scenarios`: scenario files with name, description and step count
+//! - `GET /runs`, `GET /runs/<id>`: run status and counts
+//! - `POST /runs`: queue a run, body as a manifest entry; returns its id
+//! - `POST /runs/<id>/stop`: cancel a queued run, or stop a running one mid-step
+//! - `GET /runs/<id>/result`: comparison document of a finished compare run
+//! - `GET /captures`, `GET /captures/<name>`: capture files in `runs/` (content as text)
+//! - `GET /events`: WebSocket stream of run events and live packets, one JSON text message each
+
+use crate::batch::{self, ManifestRun};
+use crate::cadence::IntervalHistogram;
+use crate::cancel::CancellationToken;
+use crate::driver::FfbDriver;
+use crate::error::FFBError;
+use crate::observer::{ConsoleObserver, PlaybackObserver};
+use crate::priority;
+use crate::telemetry::AxisSummary;
+use crate::usb_monitor::{CaptureConfig, UsbPacket};
+use crate::{latency, output, reports, suite, Scenario, StepOutput};
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tiny_http::{Header, Method, Request, Response, Server};
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+/// Largest accepted request body
+const MAX_BODY: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum RunState {
+    Queued,
+    Running,
+    Passed,
+    Failed,
+    Error,
+    Stopped,
+}
+
+/// A run submitted over the API
+#[derive(Debug, Serialize)]
+struct RunInfo {
+    id: usize,
+    state: RunState,
+    scenario: String,
+    driver: String,
+    /// record or compare
+    mode: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compare: Option<String>,
+    total_steps: usize,
+    mismatched_steps: usize,
+    total_packets: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// JSON comparison document of a finished compare run
+    #[serde(skip)]
+    result: Option<String>,
+    #[serde(skip)]
+    entry: ManifestRun,
+    #[serde(skip)]
+    stop: CancellationToken,
+}
+
+type Runs = Arc<Mutex<Vec<RunInfo>>>;
+
+/// Connected `/events` clients
+#[derive(Clone, Default)]
+struct Events {
+    clients: Arc<Mutex<Vec<Sender<String>>>>,
+}
+
+impl Events {
+    fn subscribe(&self) -> Receiver<String> {
+        let (sender, receiver) = mpsc::channel();
+        self.clients.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Send `event` to every client, dropping clients that disconnected
+    fn publish(&self, event: Value) {
+        let mut clients = self.clients.lock().unwrap();
+        if clients.is_empty() {
+            return;
+        }
+        let text = event.to_string();
+        clients.retain(|client| client.send(text.clone()).is_ok());
+    }
+}
+
+/// Console output and run events
+struct ServeObserver {
+    run: usize,
+    events: Events,
+    console: ConsoleObserver,
+}
+
+impl PlaybackObserver for ServeObserver {
+    fn scenario_started(&mut self, scenario: &Scenario, driver: &dyn FfbDriver) {
+        self.console.scenario_started(scenario, driver);
+        self.events.publish(serde_json::json!({
+            "event": "scenario_started",
+            "run": self.run,
+            "scenario": scenario.name,
+            "driver": driver.name(),
+            "steps": scenario.steps.len(),
+        }));
+    }
+
+    fn iteration_started(&mut self, iteration: u32, iterations: u32) {
+        self.console.iteration_started(iteration, iterations);
+        self.events.publish(serde_json::json!({
+            "event": "iteration_started",
+            "run": self.run,
+            "iteration": iteration + 1,
+            "iterations": (iterations != u32::MAX).then_some(iterations),
+        }));
+    }
+
+    fn step_started(&mut self, index: usize, label: &str, duration_ms: u32) {
+        self.console.step_started(index, label, duration_ms);
+        self.events.publish(serde_json::json!({
+            "event": "step_started",
+            "run": self.run,
+            "step": index + 1,
+            "effect": label,
+            "duration_ms": duration_ms,
+        }));
+    }
+
+    fn driver_note(&mut self, index: Option<usize>, note: &str) {
+        self.console.driver_note(index, note);
+        self.events.publish(serde_json::json!({
+            "event": "driver_note",
+            "run": self.run,
+            "step": index.map(|index| index + 1),
+            "note": note,
+        }));
+    }
+
+    fn packet_captured(&mut self, packet: &UsbPacket) {
+        let mut event = output::packet_json(packet);
+        event["event"] = "packet".into();
+        event["run"] = self.run.into();
+        self.events.publish(event);
+    }
+
+    fn step_failed(&mut self, index: usize, error: &FFBError) {
+        self.console.step_failed(index, error);
+        self.events.publish(serde_json::json!({
+            "event": "step_failed",
+            "run": self.run,
+            "step": index + 1,
+            "error": error.to_string(),
+        }));
+    }
+
+    fn step_finished(&mut self, index: usize, output: &StepOutput, capture: Option<&str>) {
+        self.console.step_finished(index, output, capture);
+        self.events.publish(serde_json::json!({
+            "event": "step_finished",
+            "run": self.run,
+            "step": index + 1,
+            "packets": output.packets,
+            "capture": capture,
+            "latency": latency::summary(&output.latencies_us),
+            "wire_latency_us": output.wire_latency_us,
+            "intervals": IntervalHistogram::of(&output.timestamps_us),
+            "axis": AxisSummary::of(&output.axis),
+        }));
+    }
+
+    fn iteration_finished(&mut self) {
+        self.console.iteration_finished();
+    }
+
+    fn scenario_finished(&mut self) {
+        self.console.scenario_finished();
+    }
+}
+
+/// Serve the API on `bind` until the process is stopped
+pub fn run(bind: &str, scenarios_dir: &Path, capture: CaptureConfig) -> anyhow::Result<()> {
+    let server = Server::http(bind).map_err(|e| anyhow::anyhow!("Failed to listen on {}: {}", bind, e))?;
+    println!("Serving the HTTP API on http://{}", bind);
+
+    let runs: Runs = Arc::new(Mutex::new(Vec::new()));
+    let events = Events::default();
+    let (queue, queued) = mpsc::channel();
+    let worker_runs = Arc::clone(&runs);
+    let worker_events = events.clone();
+    thread::spawn(move || worker(worker_runs, queued, capture, worker_events));
+
+    for mut request in server.incoming_requests() {
+        if request.url() == "/events" {
+            stream_events(request, &events);
+            continue;
+        }
+        let (status, body, json) = match read_incoming(&mut request)
+            .and_then(|incoming| handle(incoming, &runs, scenarios_dir, &queue))
+        {
+            Ok(response) => response,
+            Err(e) => (400, error_json(&format!("{:#}", e)), true),
+        };
+        respond(request, status, body, json);
+    }
+    Ok(())
+}
+
+/// Method, path and body of a request
+struct Incoming {
+    method: Method,
+    path: String,
+    body: String,
+}
+
+fn read_incoming(request: &mut Request) -> anyhow::Result<Incoming> {
+    let mut body = String::new();
+    request.as_reader().take(MAX_BODY).read_to_string(&mut body)?;
+    Ok(Incoming {
+        method: request.method().clone(),
+        path: request.url().split('?').next().unwrap_or_default().to_string(),
+        body,
+    })
+}
+
+fn respond(request: Request, status: u16, body: String, json: bool) {
+    let content_type = if json { "application/json" } else { "text/plain; charset=utf-8" };
+    let header = Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).expect("valid header");
+    let response = Response::from_string(body).with_status_code(status).with_header(header);
+    if let Err(e) = request.respond(response) {
+        eprintln!("Failed to send response: {}", e);
+    }
+}
+
+fn header_value<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+/// Upgrade an `/events` request to a WebSocket and forward events to it on its own thread
+fn stream_events(request: Request, events: &Events) {
+    let upgrade = header_value(&request, "Upgrade").is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    let key = header_value(&request, "Sec-WebSocket-Key").map(|key| derive_accept_key(key.trim().as_bytes()));
+    let (true, Some(accept)) = (upgrade, key) else {
+        respond(request, 426, error_json("Expected a WebSocket upgrade"), true);
+        return;
+    };
+
+    let response = Response::empty(101)
+        .with_header(Header::from_bytes(&b"Upgrade"[..], &b"websocket"[..]).expect("valid header"))
+        .with_header(Header::from_bytes(&b"Connection"[..], &b"Upgrade"[..]).expect("valid header"))
+        .with_header(Header::from_bytes(&b"Sec-WebSocket-Accept"[..], accept.as_bytes()).expect("valid header"));
+    let stream = request.upgrade("websocket", response);
+    let received = events.subscribe();
+    thread::spawn(move || {
+        let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+        for event in received {
+            if socket.send(Message::text(event)).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+fn error_json(message: &str) -> String {
+    serde_json::json!({ "error": message }).to_string()
+}
+
+/// File name without directories, so requests stay inside the served directories
+fn plain_name(name: &str) -> anyhow::Result<&str> {
+    match Path::new(name).file_name() {
+        Some(file_name) if file_name == name && !name.starts_with('.') => Ok(name),
+        _ => anyhow::bail!("Invalid file name: {}", name),
+    }
+}
+
+fn handle(
+    incoming: Incoming,
+    runs: &Runs,
+    scenarios_dir: &Path,
+    queue: &mpsc::Sender<usize>,
+) -> anyhow::Result<(u16, String, bool)> {
+    let segments: Vec<&str> = incoming.path.trim_matches('/').split('/').collect();
+    let not_found = || Ok((404, error_json("Not found"), true));
+
+    match (&incoming.method, segments.as_slice()) {
+        (Method::Get, ["scenarios"]) => {
+            let mut scenarios = Vec::new();
+            for path in suite::find_scenarios(scenarios_dir)? {
+                let file = path.file_name().map(|n| n.to_string_lossy().into_owned());
+                scenarios.push(match Scenario::load_from_file(&path) {
+                    Ok(scenario) => serde_json::json!({
+                        "file": file,
+                        "name": scenario.name,
+                        "description": scenario.description,
+                        "steps": scenario.steps.len(),
+                    }),
+                    Err(e) => serde_json::json!({ "file": file, "error": e.to_string() }),
+                });
+            }
+            Ok((200, serde_json::to_string_pretty(&scenarios)?, true))
+        }
+        (Method::Get, ["runs"]) => {
+            let runs = runs.lock().unwrap();
+            Ok((200, serde_json::to_string_pretty(&*runs)?, true))
+        }
+        (Method::Post, ["runs"]) => {
+            let mut entry: ManifestRun = serde_json::from_str(&incoming.body)?;
+            if entry.output.is_none() == entry.compare.is_none() {
+                anyhow::bail!("A run needs exactly one of output or compare");
+            }
+            for name in entry.output.iter().chain(&entry.compare) {
+                plain_name(name)?;
+            }
+            let scenario = entry.scenario.to_string_lossy().into_owned();
+            entry.scenario = scenarios_dir.join(plain_name(&scenario)?);
+            if !entry.scenario.is_file() {
+                return Ok((404, error_json(&format!("Scenario not found: {}", scenario)), true));
+            }
+
+            let mut runs = runs.lock().unwrap();
+            let id = runs.len() + 1;
+            runs.push(RunInfo {
+                id,
+                state: RunState::Queued,
+                scenario,
+                driver: entry.driver.clone(),
+                mode: if entry.compare.is_some() { "compare" } else { "record" },
+                output: entry.output.clone(),
+                compare: entry.compare.clone(),
+                total_steps: 0,
+                mismatched_steps: 0,
+                total_packets: 0,
+                error: None,
+                result: None,
+                entry,
+                stop: CancellationToken::new(),
+            });
+            queue.send(id)?;
+            Ok((202, serde_json::json!({ "id": id }).to_string(), true))
+        }
+        (Method::Get, ["runs", id]) => {
+            let runs = runs.lock().unwrap();
+            match find_run(&runs, id) {
+                Some(run) => Ok((200, serde_json::to_string_pretty(run)?, true)),
+                None => not_found(),
+            }
+        }
+        (Method::Post, ["runs", id, "stop"]) => {
+            let mut runs = runs.lock().unwrap();
+            let Some(run) = find_run_mut(&mut runs, id) else {
+                return not_found();
+            };
+            run.stop.cancel();
+            if run.state == RunState::Queued {
+                run.state = RunState::Stopped;
+            }
+            Ok((200, serde_json::to_string_pretty(&*run)?, true))
+        }
+        (Method::Get, ["runs", id, "result"]) => {
+            let runs = runs.lock().unwrap();
+            match find_run(&runs, id).and_then(|run| run.result.clone()) {
+                Some(result) => Ok((200, result, true)),
+                None => Ok((404, error_json("No comparison result for this run"), true)),
+            }
+        }
+        (Method::Get, ["captures"]) => {
+            let mut captures: Vec<String> = match fs::read_dir("runs") {
+                Ok(entries) => entries
+                    .filter_map(|entry| entry.ok().map(|e| e.path()))
+                    .filter(|path| path.is_file())
+                    .filter_map(|path| path.file_name().map(|n| n.to_string_lossy().into_owned()))
+                    .filter(|name| !name.starts_with('.'))
+                    .collect(),
+                Err(_) => Vec::new(),
+            };
+            captures.sort();
+            Ok((200, serde_json::to_string_pretty(&captures)?, true))
+        }
+        (Method::Get, ["captures", name]) => {
+            let path = PathBuf::from("runs").join(plain_name(name)?);
+            match fs::read_to_string(&path) {
+                Ok(content) => Ok((200, content, false)),
+                Err(_) => not_found(),
+            }
+        }
+        _ => not_found(),
+    }
+}
+
+fn find_run<'a>(runs: &'a [RunInfo], id: &str) -> Option<&'a RunInfo> {
+    let id: usize = id.parse().ok()?;
+    runs.iter().find(|run| run.id == id)
+}
+
+fn find_run_mut<'a>(runs: &'a mut [RunInfo], id: &str) -> Option<&'a mut RunInfo> {
+    let id: usize = id.parse().ok()?;
+    runs.iter_mut().find(|run| run.id == id)
+}
+
+/// Play queued runs one after another
+fn worker(runs: Runs, queued: Receiver<usize>, capture: CaptureConfig, events: Events) {
+    if capture.realtime {
+        priority::raise_or_warn(priority::ThreadRole::Playback);
+    }
+    for id in queued {
+        let (entry, stop) = {
+            let mut runs = runs.lock().unwrap();
+            let Some(run) = runs.iter_mut().find(|run| run.id == id) else {
+                continue;
+            };
+            if run.state != RunState::Queued {
+                continue;
+            }
+            run.state = RunState::Running;
+            (run.entry.clone(), run.stop.clone())
+        };
+
+        println!("\n##### Run {}: {} ({}) #####", id, entry.scenario.display(), entry.driver);
+        events.publish(serde_json::json!({ "event": "run_started", "run": id }));
+        let mut observer = ServeObserver {
+            run: id,
+            events: events.clone(),
+            console: ConsoleObserver::default(),
+        };
+        let outcome = batch::run_entry(&entry, &capture, &mut observer, &stop);
+
+        let mut runs = runs.lock().unwrap();
+        let Some(run) = runs.iter_mut().find(|run| run.id == id) else {
+            continue;
+        };
+        match outcome {
+            Ok(attempt) => {
+                run.state = if stop.is_cancelled() {
+                    RunState::Stopped
+                } else if attempt.mismatched_steps == 0 {
+                    RunState::Passed
+                } else {
+                    RunState::Failed
+                };
+                run.total_steps = attempt.total_steps;
+                run.mismatched_steps = attempt.mismatched_steps;
+                run.total_packets = attempt.total_packets;
+                if let Some(compare) = &entry.compare {
+                    run.result = reports::json::render(
+                        &attempt.scenario_name,
+                        &attempt.driver_name,
+                        compare,
+                        &attempt.results,
+                        &attempt.steps,
+                    )
+                    .ok();
+                }
+            }
+            Err(e) => {
+                eprintln!("Run {} failed: {:#}", id, e);
+                run.state = RunState::Error;
+                run.error = Some(format!("{:#}", e));
+            }
+        }
+        events.publish(serde_json::json!({ "event": "run_finished", "run": id, "status": &*run }));
+    }
+}
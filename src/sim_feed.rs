@@ -0,0 +1,396 @@
+//! Effects synthesized live from sim racing telemetry
+//!
+//! The `sim-feed` command listens to a game's telemetry over UDP and turns it into
+//! force feedback on the fly: the steering torque becomes a constant force, and tyre
+//! slip beyond a threshold adds a sine rumble on top (a periodic effect with the
+//! torque as its offset). Every `step` the latest telemetry is played through the
+//! selected driver as one effect, and the packets it produces are recorded as one
+//! capture step, so the captures of several drivers fed the same telemetry show how
+//! each translates it. The played effects can be saved as a scenario, which replays
+//! exactly the same sequence through another driver.
+//!
+//! Assetto Corsa's remote telemetry (port 9996) is read directly: the tool performs
+//! the handshake and subscribes to car updates, taking the torque from the front
+//! tyres' self-aligning torque and the slip from their normalized slip. iRacing and
+//! Assetto Corsa Competizione publish telemetry through shared memory rather than
+//! UDP; a bridge forwarding their values as JSON datagrams (`{"torque": 4.2,
+//! "slip": 0.3}`) feeds them in.
+
+use crate::cancel::CancellationToken;
+use crate::capture_file::CaptureWriter;
+use crate::driver::{EffectProgress, FfbDriver};
+use crate::effects::{ConstantForce, Direction, Effect, EffectParams, Envelope, PeriodicEffect, WaveType};
+use crate::error::FFBError;
+use crate::{cadence, effect_label, Scenario, ScenarioStep, StepOutput};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Telemetry older than this is stale (game paused or closed) and plays no force
+const STALE_AFTER: Duration = Duration::from_millis(500);
+/// How often the Assetto Corsa handshake is repeated until the game answers
+const HANDSHAKE_RETRY: Duration = Duration::from_secs(1);
+/// Errors of a read while the game is not sending: a timeout, or a refused port
+/// on some systems
+const NO_ANSWER: [ErrorKind; 3] = [ErrorKind::WouldBlock, ErrorKind::TimedOut, ErrorKind::ConnectionRefused];
+/// Largest telemetry datagram read
+const MAX_DATAGRAM: usize = 2048;
+
+/// Assetto Corsa remote telemetry: handshake operations and packet layouts
+const AC_HANDSHAKE: i32 = 0;
+const AC_SUBSCRIBE_UPDATE: i32 = 1;
+const AC_DISMISS: i32 = 3;
+const AC_HANDSHAKE_RESPONSE_LEN: usize = 408;
+const AC_CAR_INFO_LEN: usize = 328;
+/// Offsets in RTCarInfo of the per-wheel arrays (FL, FR, RL, RR) of 4-byte floats
+const AC_ND_SLIP: usize = 164;
+const AC_MZ: usize = 212;
+
+/// Telemetry protocol of the feed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum FeedFormat {
+    /// Assetto Corsa remote telemetry, requested from the game's address
+    Ac,
+    /// One JSON object per datagram with `torque` and `slip`, received on the address
+    Json,
+}
+
+impl FeedFormat {
+    /// The game's address for `ac`, the address to listen on for `json`
+    pub fn default_address(self) -> SocketAddr {
+        match self {
+            FeedFormat::Ac => SocketAddr::from(([127, 0, 0, 1], 9996)),
+            FeedFormat::Json => SocketAddr::from(([127, 0, 0, 1], 9997)),
+        }
+    }
+}
+
+/// Telemetry values effects are synthesized from
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct Sample {
+    /// Steering torque (Nm), positive pulling the wheel right
+    #[serde(default)]
+    pub torque: f32,
+    /// Tyre slip, normalized so that 1 is the limit of grip
+    #[serde(default)]
+    pub slip: f32,
+}
+
+/// How telemetry becomes effects
+#[derive(Debug, Clone)]
+pub struct SynthesisOptions {
+    /// Length of each played effect
+    pub step: Duration,
+    /// Torque (Nm) played at full force
+    pub full_torque: f32,
+    /// Reverse the torque's sign, for feeds with the opposite convention
+    pub invert: bool,
+    /// Slip above which the rumble starts
+    pub slip_threshold: f32,
+    /// Rumble magnitude (0-10000) one unit of slip above the threshold reaches
+    pub slip_magnitude: u16,
+    /// Period of the rumble (ms)
+    pub slip_period: u32,
+}
+
+/// Outcome of a sim-feed run
+#[derive(Debug, Serialize)]
+pub struct FeedSummary {
+    /// Telemetry datagrams received
+    pub samples: usize,
+    pub steps: usize,
+    pub packets: usize,
+    pub output: PathBuf,
+    pub scenario: Option<PathBuf>,
+}
+
+/// UDP telemetry source
+pub struct Feed {
+    socket: UdpSocket,
+    format: FeedFormat,
+    /// Latest sample and when it arrived
+    latest: Option<(Instant, Sample)>,
+    received: usize,
+}
+
+impl Feed {
+    /// Listen for `format` telemetry at `address`, for Assetto Corsa after the game
+    /// answered the handshake (retried until `cancel` is cancelled)
+    pub fn open(format: FeedFormat, address: SocketAddr, cancel: &CancellationToken) -> anyhow::Result<Self> {
+        let socket = match format {
+            FeedFormat::Json => UdpSocket::bind(address)?,
+            FeedFormat::Ac => {
+                let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0)))?;
+                socket.connect(address)?;
+                socket
+            }
+        };
+        let feed = Feed {
+            socket,
+            format,
+            latest: None,
+            received: 0,
+        };
+        match format {
+            FeedFormat::Json => println!("Listening for JSON telemetry on {}", address),
+            FeedFormat::Ac => feed.ac_handshake(address, cancel)?,
+        }
+        feed.socket.set_nonblocking(true)?;
+        Ok(feed)
+    }
+
+    fn ac_handshake(&self, address: SocketAddr, cancel: &CancellationToken) -> anyhow::Result<()> {
+        println!("Waiting for Assetto Corsa at {} (Ctrl+C to stop)...", address);
+        self.socket.set_read_timeout(Some(HANDSHAKE_RETRY))?;
+        let mut buffer = [0u8; MAX_DATAGRAM];
+        loop {
+            if cancel.is_cancelled() {
+                anyhow::bail!("Cancelled before Assetto Corsa answered the handshake");
+            }
+            self.socket.send(&ac_request(AC_HANDSHAKE))?;
+            match self.socket.recv(&mut buffer) {
+                Ok(len) if len >= AC_HANDSHAKE_RESPONSE_LEN => {
+                    let car = utf16_string(&buffer[..100]);
+                    let track = utf16_string(&buffer[208..308]);
+                    println!("Connected to Assetto Corsa: {} at {}", car, track);
+                    break;
+                }
+                Ok(_) => {}
+                Err(e) if NO_ANSWER.contains(&e.kind()) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.socket.send(&ac_request(AC_SUBSCRIBE_UPDATE))?;
+        Ok(())
+    }
+
+    /// Take every datagram received so far, keeping the latest sample
+    fn poll(&mut self) -> anyhow::Result<()> {
+        let mut buffer = [0u8; MAX_DATAGRAM];
+        loop {
+            match self.socket.recv(&mut buffer) {
+                Ok(len) => {
+                    let sample = match self.format {
+                        FeedFormat::Ac => parse_ac(&buffer[..len]),
+                        FeedFormat::Json => serde_json::from_slice(&buffer[..len]).ok(),
+                    };
+                    if let Some(sample) = sample {
+                        self.latest = Some((Instant::now(), sample));
+                        self.received += 1;
+                    }
+                }
+                Err(e) if NO_ANSWER.contains(&e.kind()) => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Latest sample, None once it is stale
+    fn current(&self) -> Option<Sample> {
+        self.latest
+            .filter(|(received, _)| received.elapsed() < STALE_AFTER)
+            .map(|(_, sample)| sample)
+    }
+}
+
+impl Drop for Feed {
+    fn drop(&mut self) {
+        if self.format == FeedFormat::Ac {
+            let _ = self.socket.send(&ac_request(AC_DISMISS));
+        }
+    }
+}
+
+/// Assetto Corsa handshaker: identifier, version and operation as little-endian i32
+fn ac_request(operation: i32) -> Vec<u8> {
+    [1i32, 1, operation].iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+/// NUL-terminated UTF-16LE string
+fn utf16_string(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .take_while(|unit| *unit != 0)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// Sample of an Assetto Corsa RTCarInfo update, None for other packets
+fn parse_ac(data: &[u8]) -> Option<Sample> {
+    if data.len() < AC_CAR_INFO_LEN || data[0] != b'a' {
+        return None;
+    }
+    let float = |offset: usize| f32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+    let wheel = |array: usize, wheel: usize| float(array + 4 * wheel);
+    Some(Sample {
+        // The front tyres' aligning torque turns the steering against their slip angle
+        torque: -(wheel(AC_MZ, 0) + wheel(AC_MZ, 1)),
+        slip: (0..4).map(|i| wheel(AC_ND_SLIP, i)).fold(0.0, f32::max),
+    })
+}
+
+/// Effect playing `sample` for one step: the torque as a constant force, as the
+/// offset of a sine rumble while the slip is above the threshold
+pub fn synthesize(sample: Sample, options: &SynthesisOptions) -> Effect {
+    let sign = if options.invert { -1.0 } else { 1.0 };
+    let force = (sign * sample.torque / options.full_torque * 10000.0).round().clamp(-10000.0, 10000.0) as i16;
+    let excess_slip = (sample.slip - options.slip_threshold).clamp(0.0, 1.0);
+    let rumble = (excess_slip * options.slip_magnitude as f32).round() as u16;
+    let params = EffectParams {
+        duration: options.step.as_millis() as u32,
+        ..EffectParams::default()
+    };
+    if rumble == 0 {
+        return Effect::Constant {
+            params,
+            force: ConstantForce {
+                magnitude: force,
+                direction: Direction::default(),
+                envelope: Envelope::default(),
+            },
+        };
+    }
+    Effect::Periodic {
+        params,
+        effect: PeriodicEffect {
+            wave_type: WaveType::Sine,
+            magnitude: rumble.min(10000),
+            offset: force,
+            phase: 0,
+            period: options.slip_period,
+            direction: Direction::default(),
+            envelope: Envelope::default(),
+        },
+    }
+}
+
+/// Play effects synthesized from `feed` through `driver` until `duration` elapses
+/// or `cancel` is cancelled, recording each step to `output` and, if given, the
+/// played effects to the scenario `scenario`
+pub fn run(
+    driver: &mut dyn FfbDriver,
+    feed: &mut Feed,
+    options: &SynthesisOptions,
+    output: &Path,
+    scenario: Option<&Path>,
+    duration: Option<Duration>,
+    cancel: &CancellationToken,
+) -> anyhow::Result<FeedSummary> {
+    let mut writer = CaptureWriter::create(output)?;
+    let mut steps: Vec<ScenarioStep> = Vec::new();
+    let started = Instant::now();
+    println!(
+        "Playing telemetry through the {} driver in {} ms steps (Ctrl+C to stop)...",
+        driver.name(),
+        options.step.as_millis()
+    );
+
+    while duration.is_none_or(|duration| started.elapsed() < duration) && !cancel.is_cancelled() {
+        feed.poll()?;
+        let effect = synthesize(feed.current().unwrap_or_default(), options);
+        let step_start = Instant::now();
+        let index = steps.len() + 1;
+
+        let result = match driver.begin_effect(&effect) {
+            Ok(EffectProgress::Wait(wait)) => {
+                cancel.sleep(wait);
+                driver.finish_effect()
+            }
+            Ok(EffectProgress::Done(packets)) => {
+                // Drivers that generate packets without playing keep the feed's pace
+                cancel.sleep_until(step_start + options.step);
+                Ok(packets)
+            }
+            Err(e) => Err(e),
+        };
+        for note in driver.take_notes() {
+            println!("  Step {}: {}", index, note);
+        }
+        let packets = match result {
+            Ok(packets) => packets,
+            Err(e @ FFBError::CaptureFailed { .. }) => {
+                let _ = driver.stop_all_effects();
+                writer.flush()?;
+                return Err(e.at_step(index).into());
+            }
+            Err(e) => {
+                println!("  Step {}: {}", index, e);
+                Vec::new()
+            }
+        };
+
+        writer.write_step(&StepOutput {
+            step_index: index,
+            step_name: format!("{:.3} s: {}", (step_start - started).as_secs_f64(), describe(&effect)),
+            packets,
+            variants: Vec::new(),
+            latencies_us: driver
+                .packet_latencies()
+                .iter()
+                .map(|l| l.map(|d| d.as_micros() as u64))
+                .collect(),
+            wire_latency_us: driver.wire_latency().map(|d| d.as_micros() as u64),
+            timestamps_us: cadence::relative_us(&driver.packet_timestamps()),
+            axis: driver.axis_samples(),
+            devices: driver.device_captures(),
+            health: None,
+        })?;
+        steps.push(ScenarioStep {
+            effect,
+            expect: None,
+            peripherals: Vec::new(),
+            settings: Vec::new(),
+            hold: false,
+            stop_at: None,
+        });
+    }
+    let _ = driver.stop_all_effects();
+
+    let step_count = writer.total_steps();
+    let packets = writer.finish()?;
+    println!(
+        "\n{} telemetry samples played as {} steps, {} packets saved to {}",
+        feed.received,
+        step_count,
+        packets,
+        output.display()
+    );
+    if let Some(path) = scenario {
+        let scenario = Scenario {
+            name: path.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+            description: format!("Effects synthesized from telemetry, recorded with the {} driver", driver.name()),
+            loop_forever: false,
+            repeat_count: 1,
+            timing: Default::default(),
+            health_check: false,
+            steps,
+        };
+        fs::write(path, serde_yaml::to_string(&scenario)?)?;
+        println!("Played effects written to {}", path.display());
+    }
+    Ok(FeedSummary {
+        samples: feed.received,
+        steps: step_count,
+        packets,
+        output: output.to_path_buf(),
+        scenario: scenario.map(Path::to_path_buf),
+    })
+}
+
+/// Effect label with its force and rumble, e.g. "Constant force 4200"
+fn describe(effect: &Effect) -> String {
+    match effect {
+        Effect::Constant { force, .. } => format!("{} {}", effect_label(effect), force.magnitude),
+        Effect::Periodic { effect: periodic, .. } => format!(
+            "{} offset {}, rumble {}",
+            effect_label(effect),
+            periodic.offset,
+            periodic.magnitude
+        ),
+        _ => effect_label(effect).to_string(),
+    }
+}
@@ -0,0 +1,224 @@
+//! Regression suite: play every scenario in a directory against its golden capture
+
+use crate::cancel::CancellationToken;
+use crate::compare::{self, ExpectedSteps};
+use crate::error::{ExitStatus, ScenarioError};
+use crate::golden::GoldenSource;
+use crate::history;
+use crate::{CaptureReader, RunReport, Scenario};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Outcome of a single scenario in the suite
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SuiteOutcome {
+    /// All steps match the golden capture
+    Pass { steps: usize },
+    /// Some steps differ from the golden capture
+    Fail { mismatched: usize, steps: usize },
+    /// No golden capture found for the scenario
+    NoGolden,
+    /// Scenario file could not be loaded, or its golden is invalid
+    BadScenario(String),
+    /// The driver failed while playing the scenario
+    PlaybackFailed(String),
+}
+
+/// Result for one scenario file
+#[derive(Debug, Clone)]
+pub struct SuiteResult {
+    pub scenario_path: PathBuf,
+    pub golden_path: PathBuf,
+    pub outcome: SuiteOutcome,
+}
+
+/// Find all scenario files in a directory, sorted by name
+pub fn find_scenarios(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut scenarios: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.is_file()
+                && matches!(
+                    path.extension().and_then(|e| e.to_str()),
+                    Some("yaml") | Some("yml")
+                )
+        })
+        .collect();
+    scenarios.sort();
+    Ok(scenarios)
+}
+
+/// Golden capture path for a scenario: `<golden_dir>/<scenario stem><suffix>.run`
+pub fn golden_path_for(scenario_path: &Path, golden_dir: &Path, suffix: &str) -> PathBuf {
+    let stem = scenario_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    golden_dir.join(format!("{}{}.run", stem, suffix))
+}
+
+/// Play a single scenario and compare it with its golden capture
+/// `play` runs the scenario with the golden as the expected steps.
+fn run_one(
+    scenario_path: &Path,
+    golden_path: &Path,
+    play: &mut impl FnMut(&mut Scenario, &CaptureReader) -> anyhow::Result<RunReport>,
+) -> SuiteOutcome {
+    let mut scenario = match Scenario::load_from_file(&scenario_path.to_path_buf()) {
+        Ok(scenario) => scenario,
+        Err(e) => return SuiteOutcome::BadScenario(e.to_string()),
+    };
+
+    if !golden_path.exists() {
+        return SuiteOutcome::NoGolden;
+    }
+    let bad_golden = |e: anyhow::Error| SuiteOutcome::BadScenario(format!("{}: {:#}", golden_path.display(), e));
+    let golden = match CaptureReader::open(golden_path) {
+        Ok(golden) => golden,
+        Err(e) => return bad_golden(e),
+    };
+    if let Err(e) = ExpectedSteps::from(&golden).validate() {
+        return bad_golden(e);
+    }
+
+    let report = match play(&mut scenario, &golden) {
+        Ok(report) => report,
+        Err(e) if ExitStatus::from_error(&e) == ExitStatus::BadScenario => {
+            return SuiteOutcome::BadScenario(format!("{:#}", e))
+        }
+        Err(e) => return SuiteOutcome::PlaybackFailed(format!("{:#}", e)),
+    };
+    let results = report.comparison.as_deref().unwrap_or_default();
+    compare::print_comparison(results);
+    history::append_or_warn(&report.history_record("test"));
+
+    let mismatched = report.mismatched_steps();
+    if mismatched == 0 {
+        SuiteOutcome::Pass { steps: results.len() }
+    } else {
+        SuiteOutcome::Fail {
+            mismatched,
+            steps: results.len(),
+        }
+    }
+}
+
+/// Play every scenario in `dir` and compare against goldens from `goldens`
+/// `play` runs one scenario, e.g. through a `ScenarioRunner` comparing with the
+/// golden; it may adjust the scenario first. Once `cancel` is cancelled the rest
+/// are skipped. A scenario that fails to load, has a bad golden or fails to play
+/// is reported in its result, and the suite goes on with the next one.
+pub fn run_suite(
+    dir: &Path,
+    goldens: &GoldenSource,
+    mut play: impl FnMut(&mut Scenario, &CaptureReader) -> anyhow::Result<RunReport>,
+    cancel: &CancellationToken,
+) -> anyhow::Result<Vec<SuiteResult>> {
+    let scenarios = find_scenarios(dir)?;
+    if scenarios.is_empty() {
+        return Err(ScenarioError::NotFound(format!("no scenario files in {}", dir.display())).into());
+    }
+
+    let mut results = Vec::with_capacity(scenarios.len());
+    for scenario_path in scenarios {
+        if cancel.is_cancelled() {
+            println!("\nCancelled, skipping the remaining scenarios");
+            break;
+        }
+        let golden_path = goldens.path_for(&scenario_path);
+        println!("\n##### {} #####", scenario_path.display());
+        let outcome = run_one(&scenario_path, &golden_path, &mut play);
+        results.push(SuiteResult {
+            scenario_path,
+            golden_path,
+            outcome,
+        });
+    }
+    Ok(results)
+}
+
+/// Results in the form of JSON output
+pub fn to_json(results: &[SuiteResult]) -> serde_json::Value {
+    results
+        .iter()
+        .map(|result| {
+            let (status, steps, mismatched, error) = match &result.outcome {
+                SuiteOutcome::Pass { steps } => ("pass", *steps, 0, None),
+                SuiteOutcome::Fail { mismatched, steps } => ("fail", *steps, *mismatched, None),
+                SuiteOutcome::NoGolden => ("no_golden", 0, 0, None),
+                SuiteOutcome::BadScenario(message) | SuiteOutcome::PlaybackFailed(message) => {
+                    ("error", 0, 0, Some(message.as_str()))
+                }
+            };
+            serde_json::json!({
+                "scenario": result.scenario_path,
+                "golden": result.golden_path,
+                "status": status,
+                "total_steps": steps,
+                "mismatched_steps": mismatched,
+                "error": error,
+            })
+        })
+        .collect()
+}
+
+/// Print a pass/fail table and return the overall exit status
+pub fn print_results(results: &[SuiteResult]) -> ExitStatus {
+    println!("\n=== Test Results ===");
+    println!("{:<40} {:<12} Details", "Scenario", "Result");
+
+    let mut passed = 0;
+    let mut failed = 0;
+    let mut bad_scenarios = 0;
+    let mut playback_errors = 0;
+
+    for result in results {
+        let name = result
+            .scenario_path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let (label, details) = match &result.outcome {
+            SuiteOutcome::Pass { steps } => {
+                passed += 1;
+                ("PASS", format!("{}/{} steps match", steps, steps))
+            }
+            SuiteOutcome::Fail { mismatched, steps } => {
+                failed += 1;
+                ("FAIL", format!("{} of {} steps differ", mismatched, steps))
+            }
+            SuiteOutcome::NoGolden => {
+                failed += 1;
+                ("NO GOLDEN", format!("missing {}", result.golden_path.display()))
+            }
+            SuiteOutcome::BadScenario(message) => {
+                bad_scenarios += 1;
+                ("ERROR", message.clone())
+            }
+            SuiteOutcome::PlaybackFailed(message) => {
+                playback_errors += 1;
+                ("ERROR", message.clone())
+            }
+        };
+        println!("{:<40} {:<12} {}", name, label, details);
+    }
+
+    println!(
+        "\n{} passed, {} failed, {} invalid, {} errors ({} total)",
+        passed,
+        failed,
+        bad_scenarios,
+        playback_errors,
+        results.len()
+    );
+
+    if bad_scenarios > 0 {
+        ExitStatus::BadScenario
+    } else if playback_errors > 0 {
+        ExitStatus::SetupError
+    } else if failed > 0 {
+        ExitStatus::Mismatch
+    } else {
+        ExitStatus::Success
+    }
+}
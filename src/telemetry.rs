@@ -0,0 +1,79 @@
+//! Wheel axis telemetry: how the wheel physically moved while a step played
+//!
+//! With `--axis-rate`, the SDL driver samples the steering axis of the wheel's
+//! input reports at a fixed rate while each effect plays. The samples are stored
+//! in capture files as one comment line after the step's packets, each as the
+//! time since the effect was submitted and the axis position (-32768 to 32767,
+//! as SDL reports it): `# Axis: 0:-120 10000:-95 20000:310`. Comparisons ignore
+//! the line.
+
+use serde::Serialize;
+
+/// Prefix of the axis line in capture files
+pub const AXIS_PREFIX: &str = "# Axis:";
+
+/// Steering axis position at a point of a step
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct AxisSample {
+    /// Time since the step's effect was submitted (us)
+    pub time_us: u64,
+    /// -32768 (full left) to 32767 (full right)
+    pub position: i16,
+}
+
+/// Format the axis line of a step
+pub fn format_line(samples: &[AxisSample]) -> String {
+    let values: Vec<String> = samples
+        .iter()
+        .map(|s| format!("{}:{}", s.time_us, s.position))
+        .collect();
+    format!("{} {}", AXIS_PREFIX, values.join(" "))
+}
+
+/// Parse an axis line, None if the line is not one
+pub fn parse_line(line: &str) -> Option<Vec<AxisSample>> {
+    let values = line.strip_prefix(AXIS_PREFIX)?;
+    values
+        .split_whitespace()
+        .map(|value| {
+            let (time_us, position) = value.split_once(':')?;
+            Some(AxisSample {
+                time_us: time_us.parse().ok()?,
+                position: position.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// How far the wheel moved during a step
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct AxisSummary {
+    pub samples: usize,
+    pub start: i16,
+    pub end: i16,
+    pub min: i16,
+    pub max: i16,
+}
+
+impl AxisSummary {
+    /// Summary of `samples`, None without samples
+    pub fn of(samples: &[AxisSample]) -> Option<Self> {
+        let first = samples.first()?;
+        let last = samples.last()?;
+        Some(Self {
+            samples: samples.len(),
+            start: first.position,
+            end: last.position,
+            min: samples.iter().map(|s| s.position).min()?,
+            max: samples.iter().map(|s| s.position).max()?,
+        })
+    }
+
+    /// One line summary, e.g. "50 samples, -120 -> 4300 (range -120..4410)"
+    pub fn describe(&self) -> String {
+        format!(
+            "{} samples, {} -> {} (range {}..{})",
+            self.samples, self.start, self.end, self.min, self.max
+        )
+    }
+}
@@ -0,0 +1,201 @@
+//! Traceability from captured packets back to the scenario
+//!
+//! Each captured packet is decoded into its logical operation (set effect, set
+//! magnitude, start, stop, ...) and fields, and every field is linked to the
+//! scenario effect field it carries, through the `operation` and `source`
+//! entries of the decoder specs. The resulting tables explain a packet byte by
+//! byte: which bytes hold which value, and which line of the scenario produced it.
+//! Semantic comparison (`--normalize`) uses them to name the scenario field behind
+//! each differing value, and the HTML report lists them per step.
+
+use crate::compare::{parse_hex, ExpectedSteps, StepComparison, StepStatus};
+use crate::normalize::decode_command;
+use crate::{Scenario, ScenarioStep, StepOutput};
+use serde::Serialize;
+use serde_json::Value;
+
+/// A decoded field and where its value comes from
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldTrace {
+    pub name: String,
+    /// Position of the field in the captured packet
+    pub offset: usize,
+    pub width: usize,
+    /// Display value in the packet
+    pub value: String,
+    /// Scenario effect field the value is derived from, e.g. "x_axis.dead_band"
+    pub source: Option<String>,
+    /// Value of `source` in the scenario step
+    pub scenario_value: Option<String>,
+}
+
+/// A captured packet with its operation and fields; undecoded packets have none
+#[derive(Debug, Clone, Serialize)]
+pub struct PacketTrace {
+    /// 1-based position in the step
+    pub index: usize,
+    pub packet: String,
+    pub command: Option<String>,
+    pub operation: Option<String>,
+    pub fields: Vec<FieldTrace>,
+}
+
+/// Traceability table of one step
+#[derive(Debug, Clone, Serialize)]
+pub struct StepTrace {
+    pub step_index: usize,
+    pub step_name: String,
+    pub packets: Vec<PacketTrace>,
+}
+
+/// A field whose value differs between two captures of a step
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldDifference {
+    /// 1-based position of the packet in the step
+    pub packet: usize,
+    pub operation: String,
+    pub field: String,
+    pub expected: String,
+    pub actual: String,
+    pub source: Option<String>,
+    pub scenario_value: Option<String>,
+}
+
+/// Trace the packets of `output`, played from `step` (None if unknown, e.g. a
+/// capture without its scenario)
+pub fn trace_step(step: Option<&ScenarioStep>, output: &StepOutput) -> StepTrace {
+    let effect = step.and_then(|step| serde_json::to_value(&step.effect).ok());
+    let packets = output
+        .packets
+        .iter()
+        .enumerate()
+        .map(|(i, packet)| {
+            let command = parse_hex(packet).as_deref().and_then(decode_command);
+            let fields = command
+                .iter()
+                .flat_map(|command| &command.fields)
+                .map(|field| {
+                    let resolved = field
+                        .source
+                        .as_deref()
+                        .and_then(|source| resolve_source(effect.as_ref(), source));
+                    FieldTrace {
+                        name: field.name.clone(),
+                        offset: field.offset,
+                        width: field.width,
+                        value: field.value.clone(),
+                        source: resolved.as_ref().map(|(path, _)| path.clone()).or(field.source.clone()),
+                        scenario_value: resolved.map(|(_, value)| value),
+                    }
+                })
+                .collect();
+            PacketTrace {
+                index: i + 1,
+                packet: packet.clone(),
+                command: command.as_ref().map(|c| c.name.clone()),
+                operation: command.map(|c| c.operation),
+                fields,
+            }
+        })
+        .collect();
+    StepTrace {
+        step_index: output.step_index,
+        step_name: output.step_name.clone(),
+        packets,
+    }
+}
+
+/// Trace every step output against the scenario step it was played from
+pub fn trace_run(scenario: &Scenario, outputs: &[StepOutput]) -> Vec<StepTrace> {
+    outputs
+        .iter()
+        .map(|output| trace_step(scenario.steps.get(output.step_index.saturating_sub(1)), output))
+        .collect()
+}
+
+/// The first alternative of `source` ("a|b.c") the effect has, with its value
+fn resolve_source(effect: Option<&Value>, source: &str) -> Option<(String, String)> {
+    let effect = effect?;
+    source.split('|').map(str::trim).find_map(|path| {
+        let value = path.split('.').try_fold(effect, |value, key| value.get(key))?;
+        let text = match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        Some((path.to_string(), text))
+    })
+}
+
+/// Fields of packets at the same position that decode to the same command but
+/// differ in value; a different command counts as a difference of the command
+pub fn field_differences(expected: &StepTrace, actual: &StepTrace) -> Vec<FieldDifference> {
+    let mut differences = Vec::new();
+    for (exp, act) in expected.packets.iter().zip(&actual.packets) {
+        let (Some(exp_command), Some(act_command)) = (&exp.command, &act.command) else {
+            continue;
+        };
+        let operation = act.operation.clone().unwrap_or_else(|| act_command.clone());
+        if exp_command != act_command {
+            differences.push(FieldDifference {
+                packet: act.index,
+                operation,
+                field: "command".to_string(),
+                expected: exp_command.clone(),
+                actual: act_command.clone(),
+                source: None,
+                scenario_value: None,
+            });
+            continue;
+        }
+        for field in &act.fields {
+            let Some(exp_field) = exp.fields.iter().find(|f| f.name == field.name) else {
+                continue;
+            };
+            if exp_field.value != field.value {
+                differences.push(FieldDifference {
+                    packet: act.index,
+                    operation: operation.clone(),
+                    field: field.name.clone(),
+                    expected: exp_field.value.clone(),
+                    actual: field.value.clone(),
+                    source: field.source.clone(),
+                    scenario_value: field.scenario_value.clone(),
+                });
+            }
+        }
+    }
+    differences
+}
+
+/// Print the differing fields of each mismatched step with the scenario field behind them
+pub fn print_field_differences(
+    scenario: &Scenario,
+    expected_steps: ExpectedSteps<'_>,
+    actual_steps: &[StepOutput],
+    results: &[StepComparison],
+) {
+    // Results are in step order, one per position of the two runs
+    for (position, result) in results.iter().enumerate().filter(|(_, r)| r.status == StepStatus::Mismatch) {
+        let expected = expected_steps.step(position).ok().flatten();
+        let (Some(expected), Some(actual)) = (expected.as_deref(), actual_steps.get(position)) else {
+            continue;
+        };
+        let step = scenario.steps.get(result.step_index.saturating_sub(1));
+        let differences = field_differences(&trace_step(step, expected), &trace_step(step, actual));
+        if differences.is_empty() {
+            continue;
+        }
+        println!("Step {}: {} fields:", result.step_index, result.step_name);
+        for difference in &differences {
+            let source = match (&difference.source, &difference.scenario_value) {
+                (Some(source), Some(value)) => format!(" (scenario {} = {})", source, value),
+                (Some(source), None) => format!(" (scenario {})", source),
+                _ => String::new(),
+            };
+            println!(
+                "  packet {} {}: {} expected {}, got {}{}",
+                difference.packet, difference.operation, difference.field, difference.expected, difference.actual, source
+            );
+        }
+    }
+}
@@ -0,0 +1,448 @@
+//! Terminal dashboard for playback (`--tui`)
+//!
+//! Playback runs on the calling thread through a `ScenarioRunner` that reports to a
+//! `TuiObserver`, which forwards progress to a UI thread. The UI thread draws the step list, the live
+//! packet stream with decoded commands, a packet rate sparkline and the compare
+//! status, and handles the keyboard: `q` or Ctrl+C stop playback right away, also
+//! mid-step, Space or Esc are the emergency stop of `hotkey` and `r` resumes from it,
+//! and any key closes the dashboard once playback finished.
+
+use crate::cancel::CancellationToken;
+use crate::compare::{self, CompareOptions, ExpectedSteps, StepStatus};
+use crate::driver::FfbDriver;
+use crate::error::FFBError;
+use crate::runner::{RunReport, ScenarioRunner};
+use crate::monitor::format_packet;
+use crate::normalize::decode_packet;
+use crate::observer::PlaybackObserver;
+use crate::usb_monitor::{capture_clock, UsbPacket};
+use crate::{effect_label, Scenario, StepOutput};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, Paragraph, Sparkline};
+use ratatui::Frame;
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Redraw interval, also the keyboard poll timeout
+const FRAME_INTERVAL: Duration = Duration::from_millis(100);
+/// Packet lines kept for the stream view
+const MAX_PACKET_LINES: usize = 500;
+/// Width of one packet rate sample
+const RATE_BUCKET: Duration = Duration::from_millis(250);
+/// Packet rate samples kept for the sparkline
+const MAX_RATE_BUCKETS: usize = 240;
+
+/// Run `runner` with the dashboard instead of console prints
+/// When the runner compares, every step is compared as soon as it finishes
+pub fn run(runner: ScenarioRunner<'_>, scenario: &Scenario) -> anyhow::Result<RunReport> {
+    let expected = runner.expected().map(|steps| (steps, runner.compare_options()));
+    let (observer, dashboard) = TuiObserver::start(expected, runner.cancel())?;
+    let result = runner.observer(observer).run(scenario);
+    dashboard.finish(result.as_ref().err().map(|e| format!("{:#}", e)))?;
+    result
+}
+
+/// Step progress shown in the step list
+#[derive(Debug, Clone)]
+enum StepState {
+    Pending,
+    Running,
+    /// Finished, not compared
+    Done,
+    Match,
+    Mismatch(String),
+    Failed(String),
+}
+
+enum Message {
+    Started { scenario: String, driver: String, steps: Vec<(String, u32)> },
+    Iteration(String),
+    StepStarted(usize),
+    Note(String),
+    Packet(UsbPacket),
+    StepFinished { index: usize, packets: Vec<String>, state: StepState },
+    Finished(Option<String>),
+}
+
+/// Observer that forwards playback progress to the UI thread
+struct TuiObserver<'a> {
+    messages: Sender<Message>,
+    expected: Option<(ExpectedSteps<'a>, CompareOptions)>,
+    /// Position of the next step among all played steps, for comparison
+    position: usize,
+}
+
+/// The UI thread, kept until playback is over
+struct Dashboard {
+    messages: Sender<Message>,
+    ui: JoinHandle<anyhow::Result<()>>,
+}
+
+impl<'a> TuiObserver<'a> {
+    /// Start the UI thread; stopping from the keyboard cancels `cancel`
+    fn start(
+        expected: Option<(ExpectedSteps<'a>, CompareOptions)>,
+        cancel: CancellationToken,
+    ) -> anyhow::Result<(Self, Dashboard)> {
+        let (messages, receiver) = mpsc::channel();
+        let state = State::new(expected.is_some());
+        let ui = thread::Builder::new()
+            .name("tui".to_string())
+            .spawn(move || run_ui(state, receiver, cancel))?;
+        let observer = Self {
+            messages: messages.clone(),
+            expected,
+            position: 0,
+        };
+        Ok((observer, Dashboard { messages, ui }))
+    }
+
+    fn send(&self, message: Message) {
+        let _ = self.messages.send(message);
+    }
+}
+
+impl Dashboard {
+    /// Report the end of playback and wait until the dashboard is closed
+    fn finish(self, error: Option<String>) -> anyhow::Result<()> {
+        let _ = self.messages.send(Message::Finished(error));
+        match self.ui.join() {
+            Ok(result) => result,
+            Err(_) => anyhow::bail!("TUI thread panicked"),
+        }
+    }
+}
+
+impl PlaybackObserver for TuiObserver<'_> {
+    fn scenario_started(&mut self, scenario: &Scenario, driver: &dyn FfbDriver) {
+        let steps = scenario
+            .steps
+            .iter()
+            .map(|step| (effect_label(&step.effect).to_string(), step.effect.duration()))
+            .collect();
+        self.send(Message::Started {
+            scenario: scenario.name.clone(),
+            driver: driver.name().to_string(),
+            steps,
+        });
+    }
+
+    fn iteration_started(&mut self, iteration: u32, iterations: u32) {
+        let label = if iterations == u32::MAX {
+            format!("iteration {}", iteration + 1)
+        } else {
+            format!("iteration {}/{}", iteration + 1, iterations)
+        };
+        self.send(Message::Iteration(label));
+    }
+
+    fn step_started(&mut self, index: usize, _label: &str, _duration_ms: u32) {
+        self.send(Message::StepStarted(index));
+    }
+
+    fn driver_note(&mut self, index: Option<usize>, note: &str) {
+        let note = match index {
+            Some(index) => format!("Step {}: {}", index + 1, note),
+            None => note.to_string(),
+        };
+        self.send(Message::Note(note));
+    }
+
+    fn packet_captured(&mut self, packet: &UsbPacket) {
+        self.send(Message::Packet(packet.clone()));
+    }
+
+    fn step_failed(&mut self, index: usize, error: &FFBError) {
+        self.send(Message::StepFinished {
+            index,
+            packets: Vec::new(),
+            state: StepState::Failed(error.to_string()),
+        });
+    }
+
+    fn step_finished(&mut self, index: usize, output: &StepOutput, _capture: Option<&str>) {
+        let position = self.position;
+        self.position += 1;
+        let state = match self.expected {
+            Some((expected, options)) => {
+                // An unreadable step shows as extra here; the run's comparison reports the error
+                let expected = expected.step(position).ok().flatten();
+                let expected = expected.as_deref().map(std::slice::from_ref).unwrap_or_default();
+                match compare::compare_runs(expected, std::slice::from_ref(output), &options).first() {
+                    Some(result) if result.status == StepStatus::Match => StepState::Match,
+                    Some(result) if result.status == StepStatus::Timing => {
+                        StepState::Mismatch(result.timing_violations.join(", "))
+                    }
+                    Some(result) => StepState::Mismatch(format!(
+                        "{} of {} packets match",
+                        result.matched_packets, result.expected_packets
+                    )),
+                    None => StepState::Done,
+                }
+            }
+            None => StepState::Done,
+        };
+        self.send(Message::StepFinished {
+            index,
+            packets: output.packets.clone(),
+            state,
+        });
+    }
+}
+
+/// Everything the dashboard shows
+struct State {
+    scenario: String,
+    driver: String,
+    iteration: String,
+    steps: Vec<(String, u32, StepState)>,
+    /// Packets arrive from the capture as they are captured; until one does, step
+    /// outputs are shown when steps finish
+    live: bool,
+    compare: bool,
+    matched: usize,
+    mismatched: usize,
+    packet_lines: VecDeque<Line<'static>>,
+    rate: VecDeque<u64>,
+    bucket_started: Instant,
+    bucket_count: u64,
+    /// Capture clock at startup, packet timestamps are shown relative to it
+    capture_start: Duration,
+    started: Instant,
+    finished: bool,
+    status: String,
+}
+
+impl State {
+    fn new(compare: bool) -> Self {
+        Self {
+            scenario: String::new(),
+            driver: String::new(),
+            iteration: String::new(),
+            steps: Vec::new(),
+            live: false,
+            compare,
+            matched: 0,
+            mismatched: 0,
+            packet_lines: VecDeque::new(),
+            rate: VecDeque::new(),
+            bucket_started: Instant::now(),
+            capture_start: capture_clock(),
+            bucket_count: 0,
+            started: Instant::now(),
+            finished: false,
+            status: "Starting".to_string(),
+        }
+    }
+
+    fn apply(&mut self, message: Message) {
+        match message {
+            Message::Started { scenario, driver, steps } => {
+                self.scenario = scenario;
+                self.driver = driver;
+                self.steps = steps
+                    .into_iter()
+                    .map(|(label, duration)| (label, duration, StepState::Pending))
+                    .collect();
+                self.status = "Playing".to_string();
+            }
+            Message::Iteration(label) => {
+                self.iteration = label;
+                for step in &mut self.steps {
+                    step.2 = StepState::Pending;
+                }
+            }
+            Message::StepStarted(index) => {
+                if let Some(step) = self.steps.get_mut(index) {
+                    step.2 = StepState::Running;
+                }
+            }
+            Message::Note(note) => {
+                self.push_line(Line::styled(note, Style::default().fg(Color::Magenta)));
+            }
+            Message::Packet(packet) => {
+                self.live = true;
+                self.push_packet(format_packet(&packet, self.capture_start), decode_packet(&packet.data));
+            }
+            Message::StepFinished { index, packets, state } => {
+                match state {
+                    StepState::Match => self.matched += 1,
+                    StepState::Mismatch(_) => self.mismatched += 1,
+                    _ => {}
+                }
+                if let Some(step) = self.steps.get_mut(index) {
+                    step.2 = state;
+                }
+                if !self.live {
+                    for packet in packets {
+                        let decoded = compare::parse_hex(&packet).and_then(|bytes| decode_packet(&bytes));
+                        self.push_packet(packet, decoded);
+                    }
+                }
+            }
+            Message::Finished(error) => {
+                self.finished = true;
+                self.status = match error {
+                    Some(error) => format!("Failed: {}", error),
+                    None => "Finished".to_string(),
+                };
+            }
+        }
+    }
+
+    fn push_packet(&mut self, line: String, decoded: Option<String>) {
+        let mut spans = vec![Span::raw(line)];
+        if let Some(decoded) = decoded {
+            spans.push(Span::styled(format!("  -> {}", decoded), Style::default().fg(Color::Cyan)));
+        }
+        self.push_line(Line::from(spans));
+        self.bucket_count += 1;
+    }
+
+    /// Add a line to the packet stream view, which also shows the driver's notes
+    fn push_line(&mut self, line: Line<'static>) {
+        self.packet_lines.push_back(line);
+        if self.packet_lines.len() > MAX_PACKET_LINES {
+            self.packet_lines.pop_front();
+        }
+    }
+
+    /// Close packet rate samples that are complete
+    fn tick(&mut self) {
+        while self.bucket_started.elapsed() >= RATE_BUCKET {
+            self.rate.push_back(self.bucket_count);
+            if self.rate.len() > MAX_RATE_BUCKETS {
+                self.rate.pop_front();
+            }
+            self.bucket_count = 0;
+            self.bucket_started += RATE_BUCKET;
+        }
+    }
+}
+
+fn run_ui(mut state: State, messages: Receiver<Message>, cancel: CancellationToken) -> anyhow::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = (|| -> anyhow::Result<()> {
+        loop {
+            while let Ok(message) = messages.try_recv() {
+                state.apply(message);
+            }
+            state.tick();
+            terminal.draw(|frame| draw(frame, &state))?;
+
+            if !event::poll(FRAME_INTERVAL)? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if state.finished {
+                return Ok(());
+            }
+            let stop = key.code == KeyCode::Char('q')
+                || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL));
+            if stop {
+                cancel.cancel();
+                state.status = "Stopping".to_string();
+            } else if matches!(key.code, KeyCode::Char(' ') | KeyCode::Esc) && !cancel.is_paused() {
+                crate::hotkey::emergency_stop(&cancel);
+                state.status = "EMERGENCY STOP, paused before the next step".to_string();
+            } else if key.code == KeyCode::Char('r') && cancel.is_paused() {
+                cancel.resume();
+                state.status = "Playing".to_string();
+            }
+        }
+    })();
+    ratatui::restore();
+    result
+}
+
+fn draw(frame: &mut Frame, state: &State) {
+    let [header, body, footer] =
+        Layout::vertical([Constraint::Length(3), Constraint::Min(0), Constraint::Length(3)]).areas(frame.area());
+    let [steps, right] = Layout::horizontal([Constraint::Percentage(35), Constraint::Percentage(65)]).areas(body);
+    let [packets, rate] = Layout::vertical([Constraint::Min(0), Constraint::Length(7)]).areas(right);
+
+    let title = format!(
+        "{} | {} | {} | {:.1} s | {}",
+        state.scenario,
+        state.driver,
+        state.iteration,
+        state.started.elapsed().as_secs_f64(),
+        state.status
+    );
+    frame.render_widget(Paragraph::new(title).block(Block::bordered().title("ffb_replay")), header);
+
+    draw_steps(frame, state, steps);
+    draw_packets(frame, state, packets);
+
+    let samples: Vec<u64> = state.rate.iter().copied().collect();
+    let per_second = samples.last().copied().unwrap_or(0) * (1000 / RATE_BUCKET.as_millis() as u64);
+    // Newest samples on the right, as many as fit
+    let visible = samples.len().saturating_sub(rate.width.saturating_sub(2) as usize);
+    frame.render_widget(
+        Sparkline::default()
+            .block(Block::bordered().title(format!("Packet rate: {} packets/s", per_second)))
+            .data(&samples[visible..])
+            .style(Style::default().fg(Color::Yellow)),
+        rate,
+    );
+
+    let compare_status = if state.compare {
+        format!("Compare: {} match, {} differ", state.matched, state.mismatched)
+    } else {
+        "Compare: off".to_string()
+    };
+    let keys = if state.finished {
+        "any key: close"
+    } else {
+        "Space/Esc: emergency stop    r: resume    q: quit"
+    };
+    frame.render_widget(
+        Paragraph::new(format!("{}    {}", compare_status, keys)).block(Block::bordered()),
+        footer,
+    );
+}
+
+fn draw_steps(frame: &mut Frame, state: &State, area: Rect) {
+    let items: Vec<ListItem> = state
+        .steps
+        .iter()
+        .enumerate()
+        .map(|(index, (label, duration, step_state))| {
+            let (marker, color, detail) = match step_state {
+                StepState::Pending => ("   ", Color::DarkGray, String::new()),
+                StepState::Running => (" > ", Color::Yellow, String::new()),
+                StepState::Done => (" * ", Color::White, String::new()),
+                StepState::Match => ("OK ", Color::Green, String::new()),
+                StepState::Mismatch(detail) => ("XX ", Color::Red, format!(" ({})", detail)),
+                StepState::Failed(error) => ("!! ", Color::Red, format!(" ({})", error)),
+            };
+            let mut style = Style::default().fg(color);
+            if matches!(step_state, StepState::Running) {
+                style = style.add_modifier(Modifier::BOLD);
+            }
+            ListItem::new(Line::styled(
+                format!("{}{:>2}. {} ({} ms){}", marker, index + 1, label, duration, detail),
+                style,
+            ))
+        })
+        .collect();
+    frame.render_widget(List::new(items).block(Block::bordered().title("Steps")), area);
+}
+
+fn draw_packets(frame: &mut Frame, state: &State, area: Rect) {
+    let height = area.height.saturating_sub(2) as usize;
+    let skip = state.packet_lines.len().saturating_sub(height);
+    let lines: Vec<Line> = state.packet_lines.iter().skip(skip).cloned().collect();
+    let title = if state.live { "Packets (live)" } else { "Packets" };
+    frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(title)), area);
+}
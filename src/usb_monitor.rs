@@ -1,8 +1,31 @@
-use std::io::{BufReader, Read};
-use std::process::{Child, Command, Stdio};
+//! USB traffic capture while effects play: usbmon and libpcap on Linux, USBPcap
+//! and ETW on Windows, and the packet filter that keeps the FFB reports
+
+#[cfg(target_os = "linux")]
+mod usbmon;
+#[cfg(target_os = "windows")]
+mod usbpcap;
+mod pcap_stream;
+mod pcapng;
+mod hotplug;
+#[cfg(target_os = "windows")]
+mod etw;
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+mod preflight;
+
+use crate::cancel::CancellationToken;
+use crate::drivers::force_limit::MaxForce;
+use crate::priority::{self, ThreadRole};
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Child, Stdio};
+#[cfg(target_os = "windows")]
+use std::process::Command;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 /// USB packet information
 #[derive(Debug, Clone)]
@@ -12,6 +35,16 @@ pub struct UsbPacket {
     pub direction: PacketDirection,
     pub endpoint: u8,
     pub data: Vec<u8>,
+    /// URB id (usbmon) or IRP id (USBPcap)
+    pub urb_id: u64,
+    pub bus: u16,
+    pub device: u16,
+    /// 0=Isochronous, 1=Interrupt, 2=Control, 3=Bulk
+    pub transfer_type: u8,
+    /// Capture order of kept packets, assigned by the monitor
+    pub sequence: u64,
+    /// Filtered device the packet belongs to, None without device filters
+    pub source: Option<DeviceFilter>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -20,99 +53,698 @@ pub enum PacketDirection {
     DeviceToHost,
 }
 
+/// How USB traffic is captured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum CaptureBackend {
+    /// Best available: usbmon device, then libpcap, then tcpdump (Linux); USBPcap driver, then ETW (Windows)
+    #[default]
+    Auto,
+    /// Direct reader of the usbmon binary interface /dev/usbmonN (Linux)
+    Usbmon,
+    /// In-process capture: libpcap on usbmon (Linux), the USBPcap driver's control device (Windows)
+    Pcap,
+    /// USBPcapCMD (Windows) or tcpdump (Linux) subprocess
+    Subprocess,
+    /// Event Tracing for Windows USB providers, no USBPcap needed (Windows)
+    Etw,
+}
+
+/// USB capture configuration
+#[derive(Debug, Clone, Default)]
+pub struct CaptureConfig {
+    pub backend: CaptureBackend,
+    /// Only keep traffic of these devices, the first one is the primary device
+    pub devices: Vec<DeviceFilter>,
+    /// Which captured packets count as FFB commands
+    pub packets: PacketFilter,
+    /// Kernel capture filter (pcap-filter syntax) for the libpcap and tcpdump backends
+    /// None generates one from the device and packet filters, an empty string disables it
+    pub capture_filter: Option<String>,
+    /// Capture continuously and extract each step's window by timestamp
+    pub ring_buffer: Option<RingBuffer>,
+    /// Also sample the wheel's steering axis at this rate (Hz) while effects play,
+    /// None to not sample it (SDL driver)
+    pub axis_rate_hz: Option<u32>,
+    /// Run the capture reader, and the playback of whoever owns this configuration,
+    /// at real-time priority
+    pub realtime: bool,
+    /// Limit the force of every effect played by drivers created with this configuration
+    pub max_force: Option<MaxForce>,
+}
+
+/// Ring-buffer capture: packets are kept for a while instead of being cleared per step,
+/// and a step takes the packets timestamped within [start - pre_roll, end + post_roll]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RingBuffer {
+    pub pre_roll: Duration,
+    pub post_roll: Duration,
+}
+
+/// How long a capture subprocess may take until its pcap stream starts
+const CAPTURE_START_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the ring buffer keeps packets that were never extracted
+const RING_BUFFER_RETENTION: Duration = Duration::from_secs(600);
+
+/// Current time on the clock of packet timestamps (time since the Unix epoch)
+/// usbmon, USBPcap and ETW all stamp packets with system time
+pub fn capture_clock() -> Duration {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// Transfer directions kept by the packet filter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum DirectionFilter {
+    /// Host-to-device only (FFB commands)
+    #[default]
+    Out,
+    /// Device-to-host only
+    In,
+    Both,
+}
+
+/// USB transfer type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TransferType {
+    Isochronous,
+    Interrupt,
+    Control,
+    Bulk,
+}
+
+impl TransferType {
+    /// Transfer type code used by usbmon and USBPcap headers
+    pub fn code(self) -> u8 {
+        match self {
+            TransferType::Isochronous => 0,
+            TransferType::Interrupt => 1,
+            TransferType::Control => 2,
+            TransferType::Bulk => 3,
+        }
+    }
+}
+
+/// Report IDs of common FFB commands, kept by the default packet filter
+/// Generic HID PID reports (0x01-0x0F), Logitech commands (0x11-0x15, 0xF3, 0xF5) and SET_REPORT (0x21)
+const FFB_REPORT_IDS: &[u8] = &[
+    0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F, 0x11, 0x12, 0x13, 0x14,
+    0x15, 0x21, 0xF3, 0xF5,
+];
+
+/// Rules selecting which captured packets are FFB commands
+#[derive(Debug, Clone, clap::Args)]
+pub struct PacketFilter {
+    /// Transfer direction of captured packets
+    #[arg(long = "filter-direction", global = true, value_enum, default_value = "out")]
+    pub direction: DirectionFilter,
+
+    /// Transfer types to capture (comma-separated)
+    #[arg(
+        long = "filter-transfer-types",
+        global = true,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "interrupt,control"
+    )]
+    pub transfer_types: Vec<TransferType>,
+
+    /// Only capture these endpoint numbers (comma-separated, default: all)
+    #[arg(long = "filter-endpoints", global = true, value_delimiter = ',')]
+    pub endpoints: Vec<u8>,
+
+    /// Only capture packets whose first byte is one of these report IDs (hex, comma-separated)
+    /// The default is the report IDs of common FFB commands (generic HID PID, Logitech)
+    #[arg(
+        long = "filter-report-ids",
+        global = true,
+        value_delimiter = ',',
+        value_parser = parse_report_id,
+        default_value = "01,02,03,04,05,06,07,08,09,0a,0b,0c,0d,0e,0f,11,12,13,14,15,21,f3,f5"
+    )]
+    pub report_ids: Vec<u8>,
+
+    /// Packets at least this long are captured whatever their report ID (0 ignores report IDs)
+    #[arg(long = "filter-any-report-length", global = true, default_value_t = 7)]
+    pub any_report_length: usize,
+
+    /// Minimum payload length in bytes
+    #[arg(long = "filter-min-length", global = true, default_value_t = 2)]
+    pub min_length: usize,
+
+    /// Maximum payload length in bytes (default: unlimited)
+    #[arg(long = "filter-max-length", global = true)]
+    pub max_length: Option<usize>,
+}
+
+impl Default for PacketFilter {
+    fn default() -> Self {
+        Self {
+            direction: DirectionFilter::Out,
+            transfer_types: vec![TransferType::Interrupt, TransferType::Control],
+            endpoints: Vec::new(),
+            report_ids: FFB_REPORT_IDS.to_vec(),
+            any_report_length: 7,
+            min_length: 2,
+            max_length: None,
+        }
+    }
+}
+
+impl PacketFilter {
+    /// Check whether a captured packet passes all rules
+    pub fn matches(&self, packet: &UsbPacket) -> bool {
+        let direction_ok = match self.direction {
+            DirectionFilter::Out => packet.direction == PacketDirection::HostToDevice,
+            DirectionFilter::In => packet.direction == PacketDirection::DeviceToHost,
+            DirectionFilter::Both => true,
+        };
+
+        direction_ok
+            && self.transfer_types.iter().any(|t| t.code() == packet.transfer_type)
+            && (self.endpoints.is_empty() || self.endpoints.contains(&packet.endpoint))
+            && packet.data.len() >= self.min_length
+            && self.max_length.is_none_or(|max| packet.data.len() <= max)
+            && (self.report_ids.is_empty()
+                || packet.data.len() >= self.any_report_length
+                || packet.data.first().is_some_and(|id| self.report_ids.contains(id)))
+    }
+}
+
+/// Build a pcap-filter expression for usbmon captures that pre-selects packets in the kernel
+/// Covers the device address, direction, transfer types, endpoints and payload length;
+/// report IDs are left to `PacketFilter::matches` since the payload offset depends on the link type.
+/// OUT completions are always let through so submit-to-complete latency can still be measured.
+#[cfg(target_os = "linux")]
+fn usbmon_filter_expression(filter: &PacketFilter, addresses: &[DeviceAddress]) -> String {
+    // Same usbmon header layout as parse_usbmon_frame; multi-byte fields are little-endian
+    // while BPF loads are big-endian, so they are assembled byte by byte
+    let len_cap = "link[36] + link[37] * 256 + link[38] * 65536 + link[39] * 16777216";
+    let any_of = |clauses: Vec<String>| format!("({})", clauses.join(" or "));
+
+    let mut clauses = Vec::new();
+    if !addresses.is_empty() {
+        clauses.push(any_of(
+            addresses
+                .iter()
+                .map(|address| match address.bus {
+                    Some(bus) => format!("(link[11] = {} and link[12] + link[13] * 256 = {})", address.device, bus),
+                    None => format!("link[11] = {}", address.device),
+                })
+                .collect(),
+        ));
+    }
+    if !filter.transfer_types.is_empty() {
+        clauses.push(any_of(
+            filter.transfer_types.iter().map(|t| format!("link[9] = {}", t.code())).collect(),
+        ));
+    }
+    if !filter.endpoints.is_empty() {
+        clauses.push(any_of(
+            filter.endpoints.iter().map(|ep| format!("link[10] & 0x7f = {}", ep)).collect(),
+        ));
+    }
+
+    // Data-carrying events: OUT submits ('S') and IN completions ('C')
+    let mut payload = vec![format!("{} >= {}", len_cap, filter.min_length)];
+    if let Some(max) = filter.max_length {
+        payload.push(format!("{} <= {}", len_cap, max));
+    }
+    let payload = payload.join(" and ");
+    let outbound = format!("(link[10] & 0x80 = 0 and (link[8] = 0x43 or (link[8] = 0x53 and {})))", payload);
+    let inbound = format!("(link[10] & 0x80 != 0 and link[8] = 0x43 and {})", payload);
+    clauses.push(match filter.direction {
+        DirectionFilter::Out => outbound,
+        DirectionFilter::In => inbound,
+        DirectionFilter::Both => any_of(vec![outbound, inbound]),
+    });
+
+    clauses.join(" and ")
+}
+
+/// Parse a report ID given as hex ("11", "0x11")
+fn parse_report_id(value: &str) -> Result<u8, String> {
+    let digits = value.trim().trim_start_matches("0x").trim_start_matches("0X");
+    u8::from_str_radix(digits, 16).map_err(|_| format!("Invalid report ID '{}', expected a hex byte", value))
+}
+
+/// USB device selected by vendor and product id (VID:PID)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceFilter {
+    pub vendor_id: u16,
+    pub product_id: u16,
+}
+
+impl std::str::FromStr for DeviceFilter {
+    type Err = String;
+
+    /// Parse "046D:C24F" (hex, case-insensitive)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (vid, pid) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid device filter '{}', expected VID:PID (e.g. 046D:C24F)", s))?;
+        let parse = |part: &str| {
+            u16::from_str_radix(part.trim(), 16)
+                .map_err(|_| format!("Invalid device filter '{}', expected VID:PID (e.g. 046D:C24F)", s))
+        };
+        Ok(Self {
+            vendor_id: parse(vid)?,
+            product_id: parse(pid)?,
+        })
+    }
+}
+
+impl std::fmt::Display for DeviceFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04X}:{:04X}", self.vendor_id, self.product_id)
+    }
+}
+
+/// Bus/device address of the filtered device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DeviceAddress {
+    /// None when the capture is already limited to the device's bus (USBPcap hub)
+    bus: Option<u16>,
+    device: u16,
+}
+
+impl DeviceAddress {
+    fn matches(&self, packet: &UsbPacket) -> bool {
+        self.bus.is_none_or(|bus| bus == packet.bus) && self.device == packet.device
+    }
+}
+
+/// How often capture threads poll kernel/driver drop counters
+#[cfg(target_os = "linux")]
+const DROP_STATS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Capture counters, cumulative since the monitor was created
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CaptureStats {
+    /// Packets read from the capture source
+    pub seen_packets: u64,
+    pub seen_bytes: u64,
+    /// Packets passing the device and packet filters
+    pub kept_packets: u64,
+    pub kept_bytes: u64,
+    /// Events lost by the kernel or capture driver, None if the backend does not report drops
+    pub dropped: Option<u64>,
+}
+
+/// Capture counters updated by the reader thread without locking
+#[derive(Debug, Default)]
+struct StatsCounters {
+    seen_packets: AtomicU64,
+    seen_bytes: AtomicU64,
+    kept_packets: AtomicU64,
+    kept_bytes: AtomicU64,
+    dropped: AtomicU64,
+    /// The backend reports drops, so `dropped` is meaningful
+    reports_drops: AtomicBool,
+}
+
+impl StatsCounters {
+    fn snapshot(&self) -> CaptureStats {
+        CaptureStats {
+            seen_packets: self.seen_packets.load(Ordering::Relaxed),
+            seen_bytes: self.seen_bytes.load(Ordering::Relaxed),
+            kept_packets: self.kept_packets.load(Ordering::Relaxed),
+            kept_bytes: self.kept_bytes.load(Ordering::Relaxed),
+            dropped: self
+                .reports_drops
+                .load(Ordering::Relaxed)
+                .then(|| self.dropped.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl CaptureStats {
+    /// Packets rejected by the device or packet filter
+    pub fn filtered_packets(&self) -> u64 {
+        self.seen_packets - self.kept_packets
+    }
+
+    /// Counters accumulated since an earlier snapshot
+    pub fn since(&self, earlier: &CaptureStats) -> CaptureStats {
+        CaptureStats {
+            seen_packets: self.seen_packets.saturating_sub(earlier.seen_packets),
+            seen_bytes: self.seen_bytes.saturating_sub(earlier.seen_bytes),
+            kept_packets: self.kept_packets.saturating_sub(earlier.kept_packets),
+            kept_bytes: self.kept_bytes.saturating_sub(earlier.kept_bytes),
+            dropped: self
+                .dropped
+                .map(|dropped| dropped.saturating_sub(earlier.dropped.unwrap_or(0))),
+        }
+    }
+
+    /// One-line summary with rates over `elapsed`
+    pub fn describe(&self, elapsed: Duration) -> String {
+        let seconds = elapsed.as_secs_f64().max(0.001);
+        format!(
+            "{} packets seen ({:.1} packets/s, {:.1} bytes/s), {} kept, {} filtered, {} dropped",
+            self.seen_packets,
+            self.seen_packets as f64 / seconds,
+            self.seen_bytes as f64 / seconds,
+            self.kept_packets,
+            self.filtered_packets(),
+            self.dropped.map_or_else(|| "n/a".to_string(), |d| d.to_string())
+        )
+    }
+}
+
+/// Live packet subscribers, shared by the monitor and its reader threads
+/// Readers work on their own copy of `senders`, refreshed when `generation` changes,
+/// so forwarding a packet takes no lock.
+#[derive(Default)]
+struct Subscribers {
+    senders: Mutex<Vec<(u64, Sender<UsbPacket>)>>,
+    generation: AtomicU64,
+}
+
+/// Destination of packets read by a capture thread
+/// Applies the device and packet filters, then sends the packet to the monitor for
+/// `get_packets()` and forwards it to every subscriber. Nothing on this path locks:
+/// packets go through channels and the counters are atomic.
+struct PacketSink {
+    store: Sender<UsbPacket>,
+    subscribers: Arc<Subscribers>,
+    /// This reader's copy of the subscribers, and the generation it was taken at
+    local_subscribers: Vec<(u64, Sender<UsbPacket>)>,
+    subscribers_generation: u64,
+    stats: Arc<StatsCounters>,
+    next_sequence: Arc<AtomicU64>,
+    /// Completion latency by packet sequence number
+    latencies: Arc<Mutex<BTreeMap<u64, Duration>>>,
+    /// Submitted OUT URBs waiting for completion: URB id -> (sequence, submit time)
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pending: HashMap<u64, (u64, Duration)>,
+    /// Filtered devices and their addresses, empty to keep all devices
+    devices: Vec<(DeviceFilter, DeviceAddress)>,
+    filter: PacketFilter,
+    /// Signalled once the capture source delivers data, see `UsbMonitor::wait_until_ready()`
+    ready: Option<Sender<()>>,
+}
+
+/// Bound on tracked latencies and pending submits (URBs that never complete are forgotten)
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+const MAX_TRACKED_URBS: usize = 4096;
+
+impl PacketSink {
+    /// Report that the capture is running (e.g. the pcap global header arrived)
+    fn mark_ready(&mut self) {
+        if let Some(ready) = self.ready.take() {
+            let _ = ready.send(());
+        }
+    }
+
+    /// Store and forward a packet that passes the filters
+    /// Returns the sequence number assigned to a kept packet
+    fn push(&mut self, mut packet: UsbPacket) -> Option<u64> {
+        let source = self.devices.iter().find(|(_, address)| address.matches(&packet));
+        if let Some((device, _)) = source {
+            packet.source = Some(*device);
+        }
+        let kept = (self.devices.is_empty() || source.is_some()) && self.filter.matches(&packet);
+
+        let bytes = packet.data.len() as u64;
+        self.stats.seen_packets.fetch_add(1, Ordering::Relaxed);
+        self.stats.seen_bytes.fetch_add(bytes, Ordering::Relaxed);
+        if !kept {
+            return None;
+        }
+        self.stats.kept_packets.fetch_add(1, Ordering::Relaxed);
+        self.stats.kept_bytes.fetch_add(bytes, Ordering::Relaxed);
+
+        let sequence = self.next_sequence.fetch_add(1, Ordering::Relaxed);
+        packet.sequence = sequence;
+
+        let generation = self.subscribers.generation.load(Ordering::Acquire);
+        if generation != self.subscribers_generation {
+            self.local_subscribers = self.subscribers.senders.lock().unwrap().clone();
+            self.subscribers_generation = generation;
+        }
+        if !self.local_subscribers.is_empty() {
+            // Drop subscribers whose receiver is gone, here and for later readers
+            let mut gone = Vec::new();
+            self.local_subscribers.retain(|(id, subscriber)| {
+                let alive = subscriber.send(packet.clone()).is_ok();
+                if !alive {
+                    gone.push(*id);
+                }
+                alive
+            });
+            if !gone.is_empty() {
+                self.subscribers.senders.lock().unwrap().retain(|(id, _)| !gone.contains(id));
+            }
+        }
+        // The monitor is gone once the store's receiver is dropped; nothing left to keep
+        let _ = self.store.send(packet);
+        Some(sequence)
+    }
+
+    /// Remember a kept OUT submit until its completion arrives
+    #[cfg(target_os = "linux")]
+    fn track_submit(&mut self, urb_id: u64, sequence: u64, timestamp: Duration) {
+        if self.pending.len() >= MAX_TRACKED_URBS {
+            self.pending.clear();
+        }
+        self.pending.insert(urb_id, (sequence, timestamp));
+    }
+
+    /// Record the latency of a submitted URB when it completes
+    #[cfg(target_os = "linux")]
+    fn complete(&mut self, urb_id: u64, timestamp: Duration) {
+        let Some((sequence, submitted)) = self.pending.remove(&urb_id) else {
+            return;
+        };
+        let mut latencies = self.latencies.lock().unwrap();
+        if latencies.len() >= MAX_TRACKED_URBS {
+            latencies.pop_first();
+        }
+        latencies.insert(sequence, timestamp.saturating_sub(submitted));
+    }
+
+    /// Account events the kernel or capture driver reported as lost
+    #[cfg(target_os = "linux")]
+    fn add_dropped(&self, dropped: u64) {
+        self.stats.dropped.fetch_add(dropped, Ordering::Relaxed);
+        self.stats.reports_drops.store(true, Ordering::Relaxed);
+    }
+}
+
 /// USB packet monitor using platform-specific tools
-/// - Windows: USBPcapCMD subprocess
-/// - Linux: usbmon via tcpdump
+/// - Windows: USBPcap in-process or through USBPcapCMD, or ETW
+/// - Linux: usbmon directly, via in-process libpcap or tcpdump
 pub struct UsbMonitor {
     capture_thread: Option<thread::JoinHandle<()>>,
     capture_process: Option<Child>,
-    packets: Arc<Mutex<Vec<UsbPacket>>>,
+    #[cfg(target_os = "windows")]
+    etw_session: Option<etw::EtwSession>,
+    /// Packets sent by the reader threads, not yet moved to `packets`
+    store: Receiver<UsbPacket>,
+    store_sender: Sender<UsbPacket>,
+    /// Received packets, only touched by the monitor's owner
+    packets: Vec<UsbPacket>,
+    subscribers: Arc<Subscribers>,
+    stats: Arc<StatsCounters>,
+    next_sequence: Arc<AtomicU64>,
+    latencies: Arc<Mutex<BTreeMap<u64, Duration>>>,
     running: Arc<Mutex<bool>>,
-    #[allow(dead_code)]
-    device_filter: Option<String>,
+    /// Why the capture stopped while it should still be running
+    capture_error: Arc<Mutex<Option<String>>>,
+    /// Last stderr lines of the capture subprocess
+    stderr_tail: Arc<Mutex<Vec<String>>>,
+    device_filters: Vec<DeviceFilter>,
+    /// Addresses of the filtered devices, resolved when the capture starts
+    device_addresses: Vec<(DeviceFilter, DeviceAddress)>,
+    /// Hotplug watcher, only running with a device filter
+    hotplug_thread: Option<thread::JoinHandle<()>>,
+    hotplug_running: Arc<Mutex<bool>>,
+    hotplug_events: Option<Receiver<hotplug::HotplugEvent>>,
+    config: CaptureConfig,
+    /// Cuts the wait for the capture to start short
+    cancel: CancellationToken,
+}
+
+impl Default for UsbMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl UsbMonitor {
     /// Create a new USB monitor
     pub fn new() -> Self {
+        Self::with_config(CaptureConfig::default())
+    }
+
+    /// Create a new USB monitor with the given capture configuration
+    pub fn with_config(config: CaptureConfig) -> Self {
+        let (store_sender, store) = mpsc::channel();
         Self {
             capture_thread: None,
             capture_process: None,
-            packets: Arc::new(Mutex::new(Vec::new())),
+            #[cfg(target_os = "windows")]
+            etw_session: None,
+            store,
+            store_sender,
+            packets: Vec::new(),
+            subscribers: Arc::new(Subscribers::default()),
+            stats: Arc::new(StatsCounters::default()),
+            next_sequence: Arc::new(AtomicU64::new(0)),
+            latencies: Arc::new(Mutex::new(BTreeMap::new())),
             running: Arc::new(Mutex::new(false)),
-            device_filter: None,
+            capture_error: Arc::new(Mutex::new(None)),
+            stderr_tail: Arc::new(Mutex::new(Vec::new())),
+            device_filters: config.devices.clone(),
+            device_addresses: Vec::new(),
+            hotplug_thread: None,
+            hotplug_running: Arc::new(Mutex::new(false)),
+            hotplug_events: None,
+            config,
+            cancel: CancellationToken::new(),
         }
     }
 
     /// Set device filter (VID:PID format, e.g., "046D:C24F" for Logitech G29)
+    /// Replaces any devices from the capture configuration
     #[allow(dead_code)]
-    pub fn set_device_filter(&mut self, filter: String) {
-        self.device_filter = Some(filter);
+    pub fn set_device_filter(&mut self, filter: &str) -> Result<(), String> {
+        self.device_filters = vec![filter.parse()?];
+        Ok(())
     }
 
-    /// Find USBPcapCMD executable (Windows only)
+    /// Token that stops waiting for the capture to start once cancelled
+    pub fn set_cancellation(&mut self, cancel: CancellationToken) {
+        self.cancel = cancel;
+    }
+
+    /// Filtered devices, the first one is the primary device
+    pub fn devices(&self) -> &[DeviceFilter] {
+        &self.device_filters
+    }
+
+    /// USBPcap control device to capture on, recording the addresses of the filtered devices (Windows)
+    /// One control device captures a single root hub, so all filtered devices must share it
     #[cfg(target_os = "windows")]
-    fn find_usbpcapcmd() -> Option<String> {
-        let paths = [
-            r"C:\Program Files\USBPcap\USBPcapCMD.exe",
-            r"C:\Program Files (x86)\USBPcap\USBPcapCMD.exe",
-        ];
-        
-        for path in &paths {
-            if std::path::Path::new(path).exists() {
-                return Some(path.to_string());
+    fn usbpcap_hub(&mut self) -> Result<u32, String> {
+        let mut hub = None;
+        self.device_addresses.clear();
+        for &filter in &self.device_filters {
+            let (device_hub, device) = usbpcap::find_device(filter.vendor_id, filter.product_id)
+                .ok_or_else(|| format!("USB device {} not found on any USBPcap hub", filter))?;
+            if hub.is_some_and(|hub| hub != device_hub) {
+                return Err(format!(
+                    "USB device {} is on another USBPcap hub than {}; use --capture-backend etw to capture them together",
+                    filter, self.device_filters[0]
+                ));
             }
+            println!("Filtering capture to device {} (address {})", filter, device);
+            hub = Some(device_hub);
+            self.device_addresses.push((filter, DeviceAddress { bus: None, device }));
         }
-        
-        // Try to find in PATH
-        if let Ok(output) = Command::new("where").arg("USBPcapCMD.exe").output() {
-            if output.status.success() {
-                if let Ok(path) = String::from_utf8(output.stdout) {
-                    let path = path.trim().to_string();
-                    if !path.is_empty() {
-                        return Some(path);
-                    }
-                }
-            }
+        match hub {
+            Some(hub) => Ok(hub),
+            None => usbpcap::hubs().first().copied().ok_or_else(|| {
+                "No USBPcap device found. Please ensure USBPcap is installed and running.".to_string()
+            }),
         }
-        
-        None
     }
 
-    /// Find USBPcap device number (Windows)
+    /// Start capturing USB packets (Windows implementation)
+    #[cfg(target_os = "windows")]
+    fn start_backend(&mut self) -> Result<(), String> {
+        match self.config.backend {
+            CaptureBackend::Usbmon => Err("usbmon capture is only available on Linux".to_string()),
+            CaptureBackend::Pcap => self.start_pcap_capture(),
+            CaptureBackend::Subprocess => self.start_subprocess_capture(),
+            CaptureBackend::Etw => self.start_etw_capture(),
+            CaptureBackend::Auto => self.start_pcap_capture().or_else(|e| {
+                println!("USBPcap capture unavailable ({}), falling back to ETW", e);
+                self.start_etw_capture()
+            }),
+        }
+    }
+
+    /// Start capturing through an ETW real-time session (Windows)
+    /// VID:PID filtering happens on the decoded events, no USBPcap hub is involved
+    #[cfg(target_os = "windows")]
+    fn start_etw_capture(&mut self) -> Result<(), String> {
+        preflight::administrator()?;
+        let session = etw::EtwSession::start()?;
+        println!("Starting USB packet capture on: ETW (USB-UCX, USB-USBPORT)");
+
+        self.etw_session = Some(session);
+        self.device_addresses.clear();
+        let device_filters = self.device_filters.clone();
+        self.spawn_reader(move |sink, _running| etw::consume(sink, device_filters));
+        Ok(())
+    }
+
+    /// Start capturing in-process from the USBPcap control device (Windows)
+    /// No subprocess and no startup delay: packets are captured once filtering has started
     #[cfg(target_os = "windows")]
-    fn find_usbpcap_device() -> Option<u32> {
-        // USBPcap creates devices \\.\USBPcap1, \\.\USBPcap2, etc.
-        // corresponding to USB root hubs. Try to find one that exists.
-        for i in 1..=10 {
-            let _device_path = format!(r"\\.\.USBPcap{}", i);
-            // Check if the device exists by trying to open it briefly
-            // We'll just assume USBPcap1 exists if USBPcap service is running
-            // A proper check would require CreateFile API
-            if i == 1 {
-                return Some(i);
+    fn start_pcap_capture(&mut self) -> Result<(), String> {
+        preflight::administrator()?;
+        preflight::usbpcap_driver()?;
+
+        let hub = self.usbpcap_hub()?;
+        let addresses: Vec<u16> = self.device_addresses.iter().map(|(_, address)| address.device).collect();
+        let capture = usbpcap::Capture::open(hub, &addresses).map_err(|e| format!("USBPcap capture failed: {}", e))?;
+
+        println!(r"Starting USB packet capture on: \\.\USBPcap{}", hub);
+        println!("Using: USBPcap driver (in-process)");
+
+        self.spawn_reader(move |sink, running| Self::usbpcap_reader_loop(capture, sink, running));
+
+        Ok(())
+    }
+
+    /// Read the pcap stream of an in-process USBPcap capture (Windows)
+    #[cfg(target_os = "windows")]
+    fn usbpcap_reader_loop(
+        mut capture: usbpcap::Capture,
+        mut sink: PacketSink,
+        running: Arc<Mutex<bool>>,
+    ) -> Result<(), String> {
+        let mut buffer = vec![0u8; 65536];
+        let mut stream = pcap_stream::PcapStream::new();
+        while *running.lock().unwrap() {
+            let n = capture
+                .read(&mut buffer, Duration::from_millis(100))
+                .map_err(|e| format!("USBPcap read error: {}", e))?;
+            stream.feed(&buffer[..n]);
+            while let Some(record) = stream.next_record()? {
+                if let Some(mut usb_packet) = Self::parse_usbpcap_packet(record.data) {
+                    usb_packet.timestamp = record.timestamp;
+                    sink.push(usb_packet);
+                }
             }
         }
-        None
+        Ok(())
     }
 
-    /// Start capturing USB packets (Windows implementation)
+    /// Start capturing through USBPcapCMD subprocess (Windows)
     #[cfg(target_os = "windows")]
-    pub fn start_capture(&mut self) -> Result<(), String> {
-        // Find USBPcapCMD executable
-        let usbpcapcmd = Self::find_usbpcapcmd().ok_or_else(|| {
-            "USBPcapCMD.exe not found. Please install USBPcap from https://desowin.org/usbpcap/".to_string()
-        })?;
+    fn start_subprocess_capture(&mut self) -> Result<(), String> {
+        // Find USBPcapCMD executable, with the rights and the driver it needs
+        preflight::administrator()?;
+        let usbpcapcmd = preflight::usbpcap()?.display().to_string();
 
-        // Find USBPcap device
-        let device_num = Self::find_usbpcap_device().ok_or_else(|| {
-            "No USBPcap device found. Please ensure USBPcap is installed and running.".to_string()
-        })?;
+        // Find USBPcap device, on the hub of the filtered devices if any
+        let device_num = self.usbpcap_hub()?;
 
         let device_path = format!(r"\\.\USBPcap{}", device_num);
+        // Only capture the filtered devices when known, otherwise all devices on the hub
+        let device_selection = if self.device_addresses.is_empty() {
+            vec!["-A".to_string()]
+        } else {
+            let addresses: Vec<String> = self.device_addresses.iter().map(|(_, a)| a.device.to_string()).collect();
+            vec!["--devices".to_string(), addresses.join(",")]
+        };
         println!("Starting USB packet capture on: {}", device_path);
         println!("Using: {}", usbpcapcmd);
-        println!("NOTE: USB capture requires Administrator privileges");
 
         // Start USBPcapCMD with output to stdout (pipe)
         // Using "-" as output means stdout
@@ -125,10 +757,10 @@ impl UsbMonitor {
             .args([
                 "-d", &device_path,
                 "-o", "-",  // Output to stdout
-                "-A",       // Capture from all devices on this hub
             ])
+            .args(&device_selection)
             .stdout(Stdio::piped())
-            .stderr(Stdio::null())
+            .stderr(Stdio::piped())
             .stdin(Stdio::null());
         
         // Hide the console window for the subprocess
@@ -143,21 +775,11 @@ impl UsbMonitor {
             .map_err(|e| format!("Failed to start USBPcapCMD: {}", e))?;
 
         let stdout = child.stdout.take().ok_or("Failed to get stdout from USBPcapCMD")?;
-        
-        let packets = Arc::clone(&self.packets);
-        let running = Arc::clone(&self.running);
-        
-        *running.lock().unwrap() = true;
-        
+        self.collect_stderr(&mut child);
         self.capture_process = Some(child);
-        
-        self.capture_thread = Some(thread::spawn(move || {
-            Self::pcap_reader_loop(stdout, packets, running);
-        }));
 
-        thread::sleep(Duration::from_millis(5000)); // Give some time to start capturing
-
-        Ok(())
+        let ready = self.spawn_reader(move |sink, running| Self::pcap_reader_loop(stdout, sink, running));
+        self.wait_until_ready(ready, "USBPcapCMD")
     }
 
     /// Find usbmon interface (Linux)
@@ -182,134 +804,314 @@ impl UsbMonitor {
         Some("usbmon0".to_string())
     }
 
+    /// Resolve VID:PID to bus and device number through sysfs (Linux)
+    #[cfg(target_os = "linux")]
+    fn find_sysfs_device(filter: DeviceFilter) -> Option<DeviceAddress> {
+        let read = |dir: &std::path::Path, name: &str| {
+            std::fs::read_to_string(dir.join(name)).ok().map(|s| s.trim().to_string())
+        };
+
+        for entry in std::fs::read_dir("/sys/bus/usb/devices").ok()?.flatten() {
+            let dir = entry.path();
+            let (Some(vid), Some(pid)) = (read(&dir, "idVendor"), read(&dir, "idProduct")) else {
+                continue;
+            };
+            if u16::from_str_radix(&vid, 16).ok() != Some(filter.vendor_id)
+                || u16::from_str_radix(&pid, 16).ok() != Some(filter.product_id)
+            {
+                continue;
+            }
+            let bus = read(&dir, "busnum")?.parse().ok()?;
+            let device = read(&dir, "devnum")?.parse().ok()?;
+            return Some(DeviceAddress { bus: Some(bus), device });
+        }
+        None
+    }
+
+    /// usbmon interface to capture on: the bus of the filtered devices if they share one, all buses otherwise
+    #[cfg(target_os = "linux")]
+    fn usbmon_interface(&self) -> Option<String> {
+        let buses: Vec<Option<u16>> = self.device_addresses.iter().map(|(_, address)| address.bus).collect();
+        match buses.first() {
+            Some(&Some(bus)) if buses.iter().all(|&b| b == Some(bus)) => Some(format!("usbmon{}", bus)),
+            _ => Self::find_usbmon_interface(),
+        }
+    }
+
+    /// Kernel capture filter for libpcap/tcpdump, None when disabled
+    #[cfg(target_os = "linux")]
+    fn kernel_filter(&self) -> Option<String> {
+        match &self.config.capture_filter {
+            Some(expression) if expression.trim().is_empty() => None,
+            Some(expression) => Some(expression.clone()),
+            None => {
+                let addresses: Vec<DeviceAddress> = self.device_addresses.iter().map(|&(_, address)| address).collect();
+                Some(usbmon_filter_expression(&self.config.packets, &addresses))
+            }
+        }
+    }
+
     /// Start capturing USB packets (Linux implementation)
     #[cfg(target_os = "linux")]
-    pub fn start_capture(&mut self) -> Result<(), String> {
-        // Check for tcpdump
-        if Command::new("which").arg("tcpdump").output().map(|o| !o.status.success()).unwrap_or(true) {
-            return Err("tcpdump not found. Please install tcpdump: sudo apt install tcpdump".to_string());
+    fn start_backend(&mut self) -> Result<(), String> {
+        self.device_addresses.clear();
+        for &filter in &self.device_filters {
+            let address = Self::find_sysfs_device(filter)
+                .ok_or_else(|| format!("USB device {} not found in /sys/bus/usb/devices", filter))?;
+            println!(
+                "Filtering capture to device {} (bus {}, device {})",
+                filter,
+                address.bus.unwrap_or(0),
+                address.device
+            );
+            self.device_addresses.push((filter, address));
         }
 
-        // Load usbmon module if not loaded
-        let _ = Command::new("sudo")
-            .args(["modprobe", "usbmon"])
-            .output();
+        match self.config.backend {
+            CaptureBackend::Etw => Err("ETW capture is only available on Windows".to_string()),
+            CaptureBackend::Usbmon => self.start_usbmon_capture(),
+            CaptureBackend::Pcap => self.start_pcap_capture(),
+            CaptureBackend::Subprocess => self.start_subprocess_capture(),
+            CaptureBackend::Auto => self
+                .start_usbmon_capture()
+                .or_else(|e| {
+                    println!("Direct usbmon capture unavailable ({}), trying libpcap", e);
+                    self.start_pcap_capture()
+                })
+                .or_else(|e| {
+                    println!("In-process capture unavailable ({}), falling back to tcpdump", e);
+                    self.start_subprocess_capture()
+                }),
+        }
+    }
+
+    #[cfg(all(target_os = "linux", not(feature = "device")))]
+    fn start_pcap_capture(&mut self) -> Result<(), String> {
+        Err("libpcap capture requires the device feature".to_string())
+    }
+
+    /// Start capturing directly from the usbmon binary interface (Linux)
+    #[cfg(target_os = "linux")]
+    fn start_usbmon_capture(&mut self) -> Result<(), String> {
+        // Loads the usbmon module if not loaded (no-op without privileges)
+        preflight::usbmon_loaded()?;
+
+        let interface = self.usbmon_interface().ok_or_else(|| {
+            "No usbmon interface found. Please ensure usbmon kernel module is loaded: sudo modprobe usbmon".to_string()
+        })?;
+        preflight::usbmon_readable(&interface)?;
+        let bus: u32 = interface.trim_start_matches("usbmon").parse().unwrap_or(0);
+
+        let reader = usbmon::UsbmonReader::open(bus, 65536).map_err(|e| {
+            format!("{}. Capturing usbmon requires root or read access to /dev/usbmon*", e)
+        })?;
+
+        println!("Starting USB packet capture on: /dev/{}", interface);
+        println!("Using: usbmon binary interface (in-process)");
+
+        self.spawn_reader(move |sink, running| Self::usbmon_reader_loop(reader, sink, running));
+
+        Ok(())
+    }
+
+    /// Read URB events from the usbmon binary interface (Linux)
+    #[cfg(target_os = "linux")]
+    fn usbmon_reader_loop(
+        mut reader: usbmon::UsbmonReader,
+        mut sink: PacketSink,
+        running: Arc<Mutex<bool>>,
+    ) -> Result<(), String> {
+        let mut last_stats = Instant::now();
+        while *running.lock().unwrap() {
+            // The kernel resets its lost-event counter on every read
+            if last_stats.elapsed() >= DROP_STATS_INTERVAL {
+                if let Ok(dropped) = reader.take_dropped() {
+                    sink.add_dropped(dropped);
+                }
+                last_stats = Instant::now();
+            }
+
+            match reader.next_event(Duration::from_millis(100)) {
+                Ok(Some(event)) => Self::handle_usbmon_frame(&mut sink, &event, usbmon::USBMON_HEADER_LEN),
+                Ok(None) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
 
-        let interface = Self::find_usbmon_interface().ok_or_else(|| {
+    /// Start capturing in-process through libpcap (Linux)
+    /// No subprocess and no startup delay: packets are captured as soon as the handle is open
+    #[cfg(all(target_os = "linux", feature = "device"))]
+    fn start_pcap_capture(&mut self) -> Result<(), String> {
+        // Loads the usbmon module if not loaded (no-op without privileges)
+        preflight::usbmon_loaded()?;
+
+        let interface = self.usbmon_interface().ok_or_else(|| {
             "No usbmon interface found. Please ensure usbmon kernel module is loaded: sudo modprobe usbmon".to_string()
         })?;
+        preflight::usbmon_readable(&interface)?;
+
+        let mut capture = pcap::Capture::from_device(interface.as_str())
+            .and_then(|capture| capture.snaplen(65535).timeout(100).open())
+            .map_err(|e| format!("{}. Capturing usbmon requires root or read access to /dev/usbmon*", e))?;
+
+        let header_len = match capture.get_datalink().0 as u32 {
+            pcap_stream::DLT_USB_LINUX_MMAPPED => 64,
+            pcap_stream::DLT_USB_LINUX => 48,
+            other => return Err(format!("Unexpected link type {} on {}", other, interface)),
+        };
+
+        if let Some(expression) = self.kernel_filter() {
+            capture
+                .filter(&expression, true)
+                .map_err(|e| format!("Invalid capture filter '{}': {}", expression, e))?;
+            println!("Capture filter: {}", expression);
+        }
 
         println!("Starting USB packet capture on: {}", interface);
-        println!("Using: tcpdump (may require sudo/root)");
+        println!("Using: libpcap (in-process)");
+
+        self.spawn_reader(move |sink, running| {
+            Self::libpcap_reader_loop(capture, header_len, sink, running)
+        });
+
+        Ok(())
+    }
+
+    /// Read frames from an in-process libpcap capture (Linux - usbmon format)
+    #[cfg(all(target_os = "linux", feature = "device"))]
+    fn libpcap_reader_loop(
+        mut capture: pcap::Capture<pcap::Active>,
+        header_len: usize,
+        mut sink: PacketSink,
+        running: Arc<Mutex<bool>>,
+    ) -> Result<(), String> {
+        let mut last_stats = Instant::now();
+        let mut reported_dropped = 0;
+        while *running.lock().unwrap() {
+            // libpcap drop counters are cumulative for the handle
+            if last_stats.elapsed() >= DROP_STATS_INTERVAL {
+                if let Ok(stats) = capture.stats() {
+                    let dropped = u64::from(stats.dropped) + u64::from(stats.if_dropped);
+                    sink.add_dropped(dropped.saturating_sub(reported_dropped));
+                    reported_dropped = dropped;
+                }
+                last_stats = Instant::now();
+            }
+
+            match capture.next_packet() {
+                Ok(packet) => Self::handle_usbmon_frame(&mut sink, packet.data, header_len),
+                Err(pcap::Error::TimeoutExpired) => {}
+                Err(pcap::Error::NoMorePackets) => return Err("libpcap capture ended".to_string()),
+                Err(e) => return Err(format!("libpcap capture failed: {}", e)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Start capturing through tcpdump subprocess (Linux)
+    #[cfg(target_os = "linux")]
+    fn start_subprocess_capture(&mut self) -> Result<(), String> {
+        preflight::usbmon_loaded()?;
+
+        let interface = self.usbmon_interface().ok_or_else(|| {
+            "No usbmon interface found. Please ensure usbmon kernel module is loaded: sudo modprobe usbmon".to_string()
+        })?;
+        // tcpdump must be installed and able to capture without a password prompt
+        let tcpdump = preflight::tcpdump(&interface)?;
+
+        println!("Starting USB packet capture on: {}", interface);
+        println!("Using: {}", tcpdump.describe());
+
+        let filter = self.kernel_filter();
+        if let Some(expression) = &filter {
+            println!("Capture filter: {}", expression);
+        }
 
         // Start tcpdump to capture USB packets in pcap format
         // -i: interface, -w -: write to stdout, -U: unbuffered
-        let mut child = Command::new("sudo")
+        let mut child = tcpdump
+            .command()
             .args([
-                "tcpdump",
                 "-i", &interface,
                 "-w", "-",  // Output to stdout in pcap format
                 "-U",       // Unbuffered output
                 "-q",       // Quiet mode
             ])
+            .args(filter)   // Trailing filter expression, compiled into the kernel by tcpdump
             .stdout(Stdio::piped())
-            .stderr(Stdio::null())
+            .stderr(Stdio::piped())
             .spawn()
-            .map_err(|e| format!("Failed to start tcpdump: {}. Try running with sudo.", e))?;
+            .map_err(|e| format!("Failed to start tcpdump: {}", e))?;
 
         let stdout = child.stdout.take().ok_or("Failed to get stdout from tcpdump")?;
-        
-        let packets = Arc::clone(&self.packets);
-        let running = Arc::clone(&self.running);
-        
-        *running.lock().unwrap() = true;
-        
+        self.collect_stderr(&mut child);
         self.capture_process = Some(child);
-        
-        self.capture_thread = Some(thread::spawn(move || {
-            Self::linux_pcap_reader_loop(stdout, packets, running);
-        }));
-
-        thread::sleep(Duration::from_millis(2000)); // Give some time to start capturing
 
-        Ok(())
+        let ready = self.spawn_reader(move |sink, running| Self::linux_pcap_reader_loop(stdout, sink, running));
+        self.wait_until_ready(ready, "tcpdump")
     }
 
     /// Read pcap data from stdout (Windows - USBPcap format)
     #[cfg(target_os = "windows")]
     fn pcap_reader_loop<R: Read>(
         stdout: R,
-        packets: Arc<Mutex<Vec<UsbPacket>>>,
+        mut sink: PacketSink,
         running: Arc<Mutex<bool>>,
-    ) {
+    ) -> Result<(), String> {
         let mut reader = BufReader::new(stdout);
         let mut buffer = vec![0u8; 65536];
-        let mut pcap_buffer = Vec::new();
+        let mut stream = pcap_stream::PcapStream::new();
         let mut header_read = false;
         
         loop {
             // Check running flag without holding lock during read
             if !*running.lock().unwrap() {
-                break;
+                return Ok(());
             }
             
             match reader.read(&mut buffer) {
                 Ok(0) => {
                     // EOF - process exited (could be permission error)
-                    break;
+                    return Err(match stream.finish() {
+                        Some(leftover) => format!("USBPcapCMD exited, {}", leftover),
+                        None => "USBPcapCMD exited".to_string(),
+                    });
                 }
                 Ok(n) => {
-                    pcap_buffer.extend_from_slice(&buffer[..n]);
+                    stream.feed(&buffer[..n]);
                     
-                    // Skip pcap global header (24 bytes) on first read
-                    if !header_read && pcap_buffer.len() >= 24 {
-                        // Verify pcap magic
-                        if pcap_buffer[0..4] == [0xd4, 0xc3, 0xb2, 0xa1] || 
-                           pcap_buffer[0..4] == [0xa1, 0xb2, 0xc3, 0xd4] {
-                            pcap_buffer = pcap_buffer[24..].to_vec();
-                            header_read = true;
-                        } else {
-                            // Invalid pcap header - could be error message from USBPcapCMD
-                            // Check if it looks like an error message
-                            if let Ok(text) = String::from_utf8(pcap_buffer[..n.min(100)].to_vec()) {
+                    if !header_read {
+                        match stream.header() {
+                            Ok(Some(_)) => {
+                                header_read = true;
+                                // The subprocess writes the header once its capture is open
+                                sink.mark_ready();
+                            }
+                            Ok(None) => continue,
+                            Err(e) => {
+                                // Invalid pcap header - could be error message from USBPcapCMD
+                                let text = String::from_utf8_lossy(&buffer[..n.min(100)]);
                                 if text.contains("Couldn't open") || text.contains("Access") {
-                                    eprintln!("ERROR: USB capture failed. Run as Administrator.");
+                                    return Err("USB capture failed. Run as Administrator.".to_string());
                                 }
+                                return Err(e);
                             }
-                            break;
                         }
                     }
                     
                     // Parse pcap packets from buffer
-                    while pcap_buffer.len() >= 16 {
-                        // pcap packet header: ts_sec(4), ts_usec(4), incl_len(4), orig_len(4)
-                        let incl_len = u32::from_le_bytes([
-                            pcap_buffer[8], pcap_buffer[9], 
-                            pcap_buffer[10], pcap_buffer[11]
-                        ]) as usize;
-                        
-                        let total_packet_len = 16 + incl_len;
-                        
-                        if pcap_buffer.len() < total_packet_len {
-                            // Need more data
-                            break;
-                        }
-                        
-                        // Extract packet data (skip pcap packet header)
-                        let packet_data = &pcap_buffer[16..total_packet_len];
-                        
-                        // Parse USB packet
-                        if let Some(usb_packet) = Self::parse_usbpcap_packet(packet_data) {
-                            packets.lock().unwrap().push(usb_packet);
+                    while let Some(record) = stream.next_record()? {
+                        if let Some(mut usb_packet) = Self::parse_usbpcap_packet(record.data) {
+                            usb_packet.timestamp = record.timestamp;
+                            sink.push(usb_packet);
                         }
-                        
-                        // Remove processed packet from buffer
-                        pcap_buffer = pcap_buffer[total_packet_len..].to_vec();
                     }
                 }
                 Err(e) => {
                     if e.kind() != std::io::ErrorKind::WouldBlock {
-                        break;
+                        return Err(format!("Read error: {}", e));
                     }
                 }
             }
@@ -320,13 +1122,14 @@ impl UsbMonitor {
     #[cfg(target_os = "linux")]
     fn linux_pcap_reader_loop<R: Read>(
         stdout: R,
-        packets: Arc<Mutex<Vec<UsbPacket>>>,
+        mut sink: PacketSink,
         running: Arc<Mutex<bool>>,
-    ) {
+    ) -> Result<(), String> {
         let mut reader = BufReader::new(stdout);
         let mut buffer = vec![0u8; 65536];
-        let mut pcap_buffer = Vec::new();
-        let mut header_read = false;
+        let mut stream = pcap_stream::PcapStream::new();
+        // usbmon header length, known once the pcap header gives the link type
+        let mut header_len = None;
         
         println!("USB capture started (reading from tcpdump/usbmon)");
         
@@ -334,63 +1137,43 @@ impl UsbMonitor {
             match reader.read(&mut buffer) {
                 Ok(0) => {
                     // EOF - process exited
-                    break;
+                    return Err(match stream.finish() {
+                        Some(leftover) => format!("tcpdump exited, {}", leftover),
+                        None => "tcpdump exited".to_string(),
+                    });
                 }
                 Ok(n) => {
-                    pcap_buffer.extend_from_slice(&buffer[..n]);
+                    stream.feed(&buffer[..n]);
                     
-                    // Skip pcap global header (24 bytes) on first read
-                    if !header_read && pcap_buffer.len() >= 24 {
-                        // Verify pcap magic (both endianness)
-                        if pcap_buffer[0..4] == [0xd4, 0xc3, 0xb2, 0xa1] || 
-                           pcap_buffer[0..4] == [0xa1, 0xb2, 0xc3, 0xd4] {
-                            pcap_buffer = pcap_buffer[24..].to_vec();
-                            header_read = true;
-                        } else {
-                            eprintln!("WARNING: Invalid pcap header: {:02X?}", &pcap_buffer[0..4]);
-                            break;
-                        }
+                    if header_len.is_none() {
+                        let Some(header) = stream.header()? else {
+                            continue;
+                        };
+                        header_len = Some(match header.link_type {
+                            pcap_stream::DLT_USB_LINUX_MMAPPED => 64,
+                            pcap_stream::DLT_USB_LINUX => 48,
+                            other => return Err(format!("Unexpected link type {} from tcpdump", other)),
+                        });
+                        // The subprocess writes the header once its capture is open
+                        sink.mark_ready();
                     }
                     
                     // Parse pcap packets from buffer
-                    while pcap_buffer.len() >= 16 {
-                        // pcap packet header: ts_sec(4), ts_usec(4), incl_len(4), orig_len(4)
-                        let incl_len = u32::from_le_bytes([
-                            pcap_buffer[8], pcap_buffer[9], 
-                            pcap_buffer[10], pcap_buffer[11]
-                        ]) as usize;
-                        
-                        let total_packet_len = 16 + incl_len;
-                        
-                        if pcap_buffer.len() < total_packet_len {
-                            // Need more data
-                            break;
-                        }
-                        
-                        // Extract packet data (skip pcap packet header)
-                        let packet_data = &pcap_buffer[16..total_packet_len];
-                        
-                        // Parse usbmon packet
-                        if let Some(usb_packet) = Self::parse_usbmon_packet(packet_data) {
-                            packets.lock().unwrap().push(usb_packet);
-                        }
-                        
-                        // Remove processed packet from buffer
-                        pcap_buffer = pcap_buffer[total_packet_len..].to_vec();
+                    while let Some(record) = stream.next_record()? {
+                        Self::handle_usbmon_frame(&mut sink, record.data, header_len.unwrap_or(64));
                     }
                 }
                 Err(e) => {
                     if e.kind() != std::io::ErrorKind::WouldBlock {
-                        eprintln!("WARNING: Read error: {}", e);
-                        break;
+                        return Err(format!("Read error: {}", e));
                     }
                 }
             }
         }
+        Ok(())
     }
 
-    /// Parse USBPcap packet (Windows)
-    #[cfg(target_os = "windows")]
+    /// Parse USBPcap packet (Windows live capture and saved USBPcap files)
     fn parse_usbpcap_packet(data: &[u8]) -> Option<UsbPacket> {
         // USBPcap header format:
         // Offset 0: headerLen (2 bytes, LE) - usually 27 or 28
@@ -426,13 +1209,8 @@ impl UsbMonitor {
         let endpoint = data[21] & 0x7F;
 
         // Extract transfer type
-        let transfer_type = data[22];
-        
-        // We're interested in Interrupt and Control transfers for FFB
         // Transfer types: 0=Isochronous, 1=Interrupt, 2=Control, 3=Bulk
-        if transfer_type != 1 && transfer_type != 2 {
-            return None;
-        }
+        let transfer_type = data[22];
 
         // Extract payload data
         let payload_data = if data.len() > header_len {
@@ -442,44 +1220,62 @@ impl UsbMonitor {
         };
 
         // Filter out empty packets
+        // Which packets are FFB commands is decided by PacketFilter
         if payload_data.is_empty() {
             return None;
         }
 
-        // Filter for FFB-related packets only:
-        // 1. Only Host-to-Device (OUT) direction - these are FFB commands
-        // 2. Filter out device-to-host (IN) packets which are typically input reports
-        if direction == PacketDirection::DeviceToHost {
-            return None;
-        }
-
-        // Filter for likely FFB commands based on common patterns:
-        // - HID SET_REPORT for FFB typically has specific report IDs
-        // - Logitech wheels use report IDs like 0x11, 0x13, etc.
-        // - Many FFB devices use endpoint 0 (control) or specific interrupt endpoints
-        
-        // For control transfers (type 2), look for SET_REPORT requests
-        // For interrupt transfers (type 1), accept OUT packets to common FFB endpoints
-        
-        // Accept packets that look like FFB commands:
-        // - Minimum size for meaningful FFB data
-        if payload_data.len() < 2 {
-            return None;
-        }
+        let urb_id = u64::from_le_bytes([
+            data[2], data[3], data[4], data[5],
+            data[6], data[7], data[8], data[9],
+        ]);
+        let bus = u16::from_le_bytes([data[17], data[18]]);
+        let device = u16::from_le_bytes([data[19], data[20]]);
 
         Some(UsbPacket {
-            timestamp: Duration::from_micros(0), // Could extract from packet if needed
+            timestamp: Duration::ZERO, // Set from the pcap record header by the reader
             direction,
             endpoint,
             data: payload_data,
+            urb_id,
+            bus,
+            device,
+            transfer_type,
+            sequence: 0,
+            source: None,
         })
     }
 
-    /// Parse usbmon packet (Linux)
-    /// usbmon binary format (64 bytes header for USB packets):
-    /// See: https://www.kernel.org/doc/Documentation/usb/usbmon.txt
+    /// Handle one usbmon frame (Linux)
+    /// OUT submits are stored right away; their completions only record the latency
     #[cfg(target_os = "linux")]
-    fn parse_usbmon_packet(data: &[u8]) -> Option<UsbPacket> {
+    fn handle_usbmon_frame(sink: &mut PacketSink, data: &[u8], header_len: usize) {
+        if let Some((urb_id, timestamp)) = Self::parse_usbmon_out_completion(data, header_len) {
+            sink.complete(urb_id, timestamp);
+        } else if let Some(usb_packet) = Self::parse_usbmon_frame(data, header_len) {
+            let (urb_id, timestamp, direction) = (usb_packet.urb_id, usb_packet.timestamp, usb_packet.direction);
+            if let Some(sequence) = sink.push(usb_packet) {
+                if direction == PacketDirection::HostToDevice {
+                    sink.track_submit(urb_id, sequence, timestamp);
+                }
+            }
+        }
+    }
+
+    /// URB id and timestamp of a Complete ('C') event of an OUT transfer (Linux)
+    #[cfg(target_os = "linux")]
+    fn parse_usbmon_out_completion(data: &[u8], header_len: usize) -> Option<(u64, Duration)> {
+        if data.len() < header_len || data[8] != b'C' || data[10] & 0x80 != 0 {
+            return None;
+        }
+        let urb_id = u64::from_le_bytes(data[0..8].try_into().ok()?);
+        let ts_sec = u64::from_le_bytes(data[16..24].try_into().ok()?);
+        let ts_usec = u32::from_le_bytes(data[24..28].try_into().ok()?);
+        Some((urb_id, Duration::from_secs(ts_sec) + Duration::from_micros(ts_usec as u64)))
+    }
+
+    /// Parse usbmon frame with 48-byte (DLT_USB_LINUX) or 64-byte (DLT_USB_LINUX_MMAPPED) header
+    fn parse_usbmon_frame(data: &[u8], header_len: usize) -> Option<UsbPacket> {
         // usbmon header (mon_bin_hdr) is 64 bytes:
         // Offset 0:  id (8 bytes) - URB id
         // Offset 8:  type (1 byte) - 'S'ubmit, 'C'omplete, 'E'rror
@@ -501,9 +1297,8 @@ impl UsbMonitor {
         // Offset 60: ndesc (4 bytes)
         // After header: payload data
         
-        const USBMON_HEADER_LEN: usize = 64;
-        
-        if data.len() < USBMON_HEADER_LEN {
+        // The 48-byte variant ends after the setup field
+        if data.len() < header_len {
             return None;
         }
 
@@ -519,19 +1314,12 @@ impl UsbMonitor {
         };
         let endpoint = epnum & 0x7F;
 
-        // We're interested in Submit ('S') events for OUT, Complete ('C') for IN
-        // For FFB monitoring, we want OUT packets (host to device)
-        if direction == PacketDirection::DeviceToHost {
-            return None;
-        }
-        
-        // Only process Submit events for OUT transfers
-        if event_type != 'S' {
-            return None;
-        }
-
-        // Filter for Interrupt (1) and Control (2) transfers
-        if xfer_type != 1 && xfer_type != 2 {
+        // OUT data is carried by Submit ('S') events, IN data by Complete ('C') events
+        let expected_event = match direction {
+            PacketDirection::HostToDevice => 'S',
+            PacketDirection::DeviceToHost => 'C',
+        };
+        if event_type != expected_event {
             return None;
         }
 
@@ -539,15 +1327,16 @@ impl UsbMonitor {
         let len_cap = u32::from_le_bytes([data[36], data[37], data[38], data[39]]) as usize;
         
         // Extract payload data
-        let payload_data = if data.len() > USBMON_HEADER_LEN && len_cap > 0 {
-            let payload_end = std::cmp::min(USBMON_HEADER_LEN + len_cap, data.len());
-            data[USBMON_HEADER_LEN..payload_end].to_vec()
+        let payload_data = if data.len() > header_len && len_cap > 0 {
+            let payload_end = std::cmp::min(header_len + len_cap, data.len());
+            data[header_len..payload_end].to_vec()
         } else {
             Vec::new()
         };
 
         // Filter out empty packets
-        if payload_data.is_empty() || payload_data.len() < 2 {
+        // Which packets are FFB commands is decided by PacketFilter
+        if payload_data.is_empty() {
             return None;
         }
 
@@ -559,50 +1348,280 @@ impl UsbMonitor {
         let ts_usec = u32::from_le_bytes([data[24], data[25], data[26], data[27]]);
         let timestamp = Duration::from_secs(ts_sec) + Duration::from_micros(ts_usec as u64);
 
+        let urb_id = u64::from_le_bytes([
+            data[0], data[1], data[2], data[3],
+            data[4], data[5], data[6], data[7],
+        ]);
+        let device = data[11] as u16;
+        let bus = u16::from_le_bytes([data[12], data[13]]);
+
         Some(UsbPacket {
             timestamp,
             direction,
             endpoint,
             data: payload_data,
+            urb_id,
+            bus,
+            device,
+            transfer_type: xfer_type,
+            sequence: 0,
+            source: None,
         })
     }
 
-    /// Check if packet looks like an FFB command
-    pub fn is_ffb_command(packet: &UsbPacket) -> bool {
-        // FFB commands are always Host-to-Device
-        if packet.direction != PacketDirection::HostToDevice {
-            return false;
+    /// Start capturing USB packets
+    /// With device filters, hotplug events are watched so the capture follows a re-enumerated device
+    #[cfg(any(target_os = "linux", target_os = "windows"))]
+    pub fn start_capture(&mut self) -> Result<(), String> {
+        self.start_backend()?;
+        if !self.device_filters.is_empty() {
+            if let Err(e) = self.start_hotplug_watch() {
+                eprintln!("WARNING: Hotplug monitoring unavailable ({}), device resets will not be detected", e);
+            }
         }
-        
-        // Must have some data
-        if packet.data.is_empty() {
-            return false;
+        Ok(())
+    }
+
+    fn start_hotplug_watch(&mut self) -> Result<(), String> {
+        let (sender, receiver) = mpsc::channel();
+        *self.hotplug_running.lock().unwrap() = true;
+        self.hotplug_thread = Some(hotplug::watch(sender, Arc::clone(&self.hotplug_running))?);
+        self.hotplug_events = Some(receiver);
+        Ok(())
+    }
+
+    /// Re-attach the capture if a filtered device re-enumerated since the last call
+    #[cfg(any(target_os = "linux", target_os = "windows"))]
+    fn handle_hotplug(&mut self) -> Result<(), String> {
+        let Some(receiver) = self.hotplug_events.as_ref() else {
+            return Ok(());
+        };
+        let events: Vec<_> = receiver
+            .try_iter()
+            .filter(|e| self.device_filters.contains(&e.device))
+            .collect();
+
+        let mut reattached = Vec::new();
+        for event in events {
+            match event.action {
+                hotplug::HotplugAction::Removed => println!("    DEVICE RESET: {} was removed", event.device),
+                hotplug::HotplugAction::Added if !reattached.contains(&event.device) => reattached.push(event.device),
+                hotplug::HotplugAction::Added => {}
+            }
         }
+        let Some(&first) = reattached.first() else {
+            return Ok(());
+        };
 
-        // Common FFB report IDs and patterns:
-        // Logitech: 0x11, 0x13, 0x14, 0xF3, 0xF5
-        // Generic HID FFB: first byte is often report ID
-        let first_byte = packet.data[0];
-        
-        // Accept common FFB report IDs
-        matches!(first_byte, 
-            0x11 | 0x12 | 0x13 | 0x14 | 0x15 |  // Logitech FFB commands
-            0xF3 | 0xF5 |                         // Logitech extended commands
-            0x01..=0x0F |                         // Generic HID FFB report IDs
-            0x21                                  // SET_REPORT request type
-        ) || packet.data.len() >= 7  // Or any substantial OUT packet
+        self.stop_backend();
+        self.device_addresses.clear();
+        self.start_backend()
+            .map_err(|e| format!("Failed to re-attach capture to {}: {}", first, e))?;
+        for device in reattached {
+            println!("    DEVICE RESET: {} re-enumerated, capture re-attached", device);
+        }
+        Ok(())
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "windows")))]
+    fn handle_hotplug(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Run a reader loop on the capture thread
+    /// A loop that ends while the capture should still be running is recorded as a capture failure
+    /// Returns the receiver of the loop's readiness signal
+    fn spawn_reader<F>(&mut self, reader: F) -> Receiver<()>
+    where
+        F: FnOnce(PacketSink, Arc<Mutex<bool>>) -> Result<(), String> + Send + 'static,
+    {
+        let (ready_sender, ready) = mpsc::channel();
+        let sink = PacketSink {
+            store: self.store_sender.clone(),
+            subscribers: Arc::clone(&self.subscribers),
+            local_subscribers: Vec::new(),
+            subscribers_generation: u64::MAX,
+            stats: Arc::clone(&self.stats),
+            next_sequence: Arc::clone(&self.next_sequence),
+            latencies: Arc::clone(&self.latencies),
+            pending: HashMap::new(),
+            devices: self.device_addresses.clone(),
+            filter: self.config.packets.clone(),
+            ready: Some(ready_sender),
+        };
+        let running = Arc::clone(&self.running);
+        let capture_error = Arc::clone(&self.capture_error);
+        let realtime = self.config.realtime;
+
+        *running.lock().unwrap() = true;
+        *capture_error.lock().unwrap() = None;
+
+        self.capture_thread = Some(thread::spawn(move || {
+            if realtime {
+                priority::raise_or_warn(ThreadRole::Capture);
+            }
+            let result = reader(sink, Arc::clone(&running));
+            if *running.lock().unwrap() {
+                let message = result.err().unwrap_or_else(|| "capture stopped unexpectedly".to_string());
+                *capture_error.lock().unwrap() = Some(message);
+            }
+        }));
+        ready
+    }
+
+    /// Wait for a capture subprocess to start delivering its pcap stream
+    /// On timeout, cancellation or early exit the backend is stopped and the reason returned
+    fn wait_until_ready(&mut self, ready: Receiver<()>, source: &str) -> Result<(), String> {
+        let error = match self.cancel.recv_timeout(&ready, CAPTURE_START_TIMEOUT) {
+            Ok(()) => return Ok(()),
+            Err(RecvTimeoutError::Timeout) if self.cancel.is_cancelled() => {
+                format!("cancelled while waiting for {} to start capturing", source)
+            }
+            Err(RecvTimeoutError::Timeout) => format!(
+                "{} did not start capturing within {} s",
+                source,
+                CAPTURE_START_TIMEOUT.as_secs()
+            ),
+            Err(RecvTimeoutError::Disconnected) => {
+                // The reader ended first; its error is recorded once the thread has exited
+                if let Some(thread) = self.capture_thread.take() {
+                    let _ = thread.join();
+                }
+                self.check_health()
+                    .err()
+                    .unwrap_or_else(|| format!("{} exited before capturing", source))
+            }
+        };
+        self.stop_backend();
+        Err(error)
+    }
+
+    /// Keep the last stderr lines of the capture subprocess for error reports
+    fn collect_stderr(&mut self, child: &mut Child) {
+        const MAX_LINES: usize = 20;
+
+        let Some(stderr) = child.stderr.take() else {
+            return;
+        };
+        let tail = Arc::clone(&self.stderr_tail);
+        tail.lock().unwrap().clear();
+
+        thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                let mut tail = tail.lock().unwrap();
+                if tail.len() == MAX_LINES {
+                    tail.remove(0);
+                }
+                tail.push(line);
+            }
+        });
+    }
+
+    /// Check that the capture is still alive
+    /// Returns a description of the failure if the reader or capture process died
+    pub fn check_health(&mut self) -> Result<(), String> {
+        let mut message = self.capture_error.lock().unwrap().clone();
+
+        if let Some(child) = self.capture_process.as_mut() {
+            if let Ok(Some(status)) = child.try_wait() {
+                let exited = format!("capture process exited with {}", status);
+                message = Some(match message {
+                    Some(error) => format!("{} ({})", error, exited),
+                    None => exited,
+                });
+            }
+        }
+
+        let Some(mut message) = message else {
+            return Ok(());
+        };
+
+        let tail = self.stderr_tail.lock().unwrap();
+        if !tail.is_empty() {
+            message.push_str(": ");
+            message.push_str(&tail.join(" | "));
+        }
+        Err(message)
     }
 
     /// Get and clear captured packets
-    pub fn get_packets(&self) -> Vec<UsbPacket> {
-        let mut packets = self.packets.lock().unwrap();
-        let result = packets.clone();
-        packets.clear();
-        result
+    /// Only packets passing the packet filter (and the device filter, if set) are returned
+    /// Fails if the capture died, instead of returning an empty capture
+    pub fn get_packets(&mut self) -> Result<Vec<UsbPacket>, String> {
+        self.check_health()?;
+        self.receive_packets();
+        let result = std::mem::take(&mut self.packets);
+        self.handle_hotplug()?;
+        Ok(result)
+    }
+
+    /// Captured packets timestamped within [start, end] (ring-buffer mode, see `capture_clock()`)
+    /// Packets before `start` are discarded, later windows may still overlap this one
+    /// Fails if the capture died, instead of returning an empty capture
+    pub fn packets_between(&mut self, start: Duration, end: Duration) -> Result<Vec<UsbPacket>, String> {
+        self.check_health()?;
+        self.receive_packets();
+        self.packets.retain(|p| p.timestamp >= start);
+        let result = self.packets.iter().filter(|p| p.timestamp <= end).cloned().collect();
+        self.handle_hotplug()?;
+        Ok(result)
+    }
+
+    /// Move the packets sent by the reader threads to `packets`
+    /// In ring-buffer mode, packets older than the retention period are dropped.
+    fn receive_packets(&mut self) {
+        self.packets.extend(self.store.try_iter());
+        if self.config.ring_buffer.is_some() {
+            if let Some(latest) = self.packets.last().map(|p| p.timestamp) {
+                let cutoff = latest.saturating_sub(RING_BUFFER_RETENTION);
+                let expired = self.packets.partition_point(|p| p.timestamp < cutoff);
+                self.packets.drain(..expired);
+            }
+        }
+    }
+
+    /// Ring-buffer settings, None when packets are cleared per step
+    pub fn ring_buffer(&self) -> Option<RingBuffer> {
+        self.config.ring_buffer
+    }
+
+    /// Submit-to-complete latency of captured packets, None where not measured
+    /// Latency is only measured for OUT transfers on the Linux usbmon backends
+    pub fn latencies(&self, packets: &[UsbPacket]) -> Vec<Option<Duration>> {
+        let latencies = self.latencies.lock().unwrap();
+        packets.iter().map(|p| latencies.get(&p.sequence).copied()).collect()
+    }
+
+    /// Snapshot of the capture counters
+    pub fn stats(&self) -> CaptureStats {
+        self.stats.snapshot()
+    }
+
+    /// Receive captured packets as they arrive
+    /// Only packets captured after this call are delivered; drop the receiver to unsubscribe.
+    /// Packets are still collected for `get_packets()` as well.
+    pub fn subscribe(&self) -> Receiver<UsbPacket> {
+        let (sender, receiver) = mpsc::channel();
+        let mut senders = self.subscribers.senders.lock().unwrap();
+        // Each subscription starts a new generation, whose number identifies it
+        let id = self.subscribers.generation.fetch_add(1, Ordering::AcqRel);
+        senders.push((id, sender));
+        receiver
     }
 
     /// Stop capturing
     pub fn stop_capture(&mut self) {
+        *self.hotplug_running.lock().unwrap() = false;
+        if let Some(thread) = self.hotplug_thread.take() {
+            let _ = thread.join();
+        }
+        self.hotplug_events = None;
+
+        self.stop_backend();
+    }
+
+    /// Stop the reader thread and capture process
+    fn stop_backend(&mut self) {
         // Set running to false first to stop the reader loop
         *self.running.lock().unwrap() = false;
         
@@ -622,6 +1641,12 @@ impl UsbMonitor {
             // Wait for process to exit
             let _ = child.wait();
         }
+
+        // Stopping the ETW session makes ProcessTrace return on the reader thread
+        #[cfg(target_os = "windows")]
+        {
+            self.etw_session = None;
+        }
         
         if let Some(thread) = self.capture_thread.take() {
             let _ = thread.join();
@@ -657,6 +1682,53 @@ impl Drop for UsbMonitor {
     }
 }
 
+/// Read the packets of a saved pcap or pcapng file (usbmon or USBPcap link type) that pass `filter`
+pub fn read_pcap_file(path: &std::path::Path, filter: &PacketFilter) -> Result<Vec<UsbPacket>, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    parse_pcap(&bytes, filter).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Packets of pcap or pcapng file contents (usbmon or USBPcap link type) that pass `filter`
+pub fn parse_pcap(bytes: &[u8], filter: &PacketFilter) -> Result<Vec<UsbPacket>, String> {
+    let mut packets = Vec::new();
+    if pcapng::is_pcapng(bytes) {
+        for (link_type, record) in pcapng::parse(bytes)? {
+            if let Some(packet) = parse_record(link_type, record)?.filter(|packet| filter.matches(packet)) {
+                packets.push(packet);
+            }
+        }
+        return Ok(packets);
+    }
+
+    let mut stream = pcap_stream::PcapStream::new();
+    stream.feed(bytes);
+    let header = stream
+        .header()?
+        .ok_or_else(|| "too short for a pcap file".to_string())?;
+    while let Some(record) = stream.next_record()? {
+        if let Some(packet) = parse_record(header.link_type, record)?.filter(|packet| filter.matches(packet)) {
+            packets.push(packet);
+        }
+    }
+    if let Some(leftover) = stream.finish() {
+        println!("WARNING: {}", leftover);
+    }
+    Ok(packets)
+}
+
+/// The USB packet of a saved frame, None for frames without one
+fn parse_record(link_type: u32, record: pcap_stream::Record) -> Result<Option<UsbPacket>, String> {
+    Ok(match link_type {
+        pcap_stream::DLT_USBPCAP => UsbMonitor::parse_usbpcap_packet(record.data).map(|mut packet| {
+            packet.timestamp = record.timestamp;
+            packet
+        }),
+        pcap_stream::DLT_USB_LINUX => UsbMonitor::parse_usbmon_frame(record.data, 48),
+        pcap_stream::DLT_USB_LINUX_MMAPPED => UsbMonitor::parse_usbmon_frame(record.data, 64),
+        other => return Err(format!("unsupported link type {}", other)),
+    })
+}
+
 /// Helper function to format packet data as hex string
 pub fn format_hex(data: &[u8]) -> String {
     data.iter()
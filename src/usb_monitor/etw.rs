@@ -0,0 +1,349 @@
+//! USB capture through Event Tracing for Windows (no USBPcap needed)
+//!
+//! A real-time trace session enables the Microsoft-Windows-USB-UCX (USB 3 stack)
+//! and Microsoft-Windows-USB-USBPORT (USB 2 stack) providers. Their URB events
+//! are decoded generically through TDH by property name: the transfer buffer
+//! (`...TransferData`), endpoint address, VID/PID and device address.
+//! Creating the session requires Administrator rights.
+
+use super::{DeviceFilter, PacketDirection, PacketSink, UsbPacket};
+use std::time::Duration;
+use windows_sys::core::GUID;
+use windows_sys::Win32::Foundation::{ERROR_ALREADY_EXISTS, ERROR_INSUFFICIENT_BUFFER, ERROR_SUCCESS};
+use windows_sys::Win32::System::Diagnostics::Etw::*;
+
+/// Microsoft-Windows-USB-UCX
+const USB_UCX_PROVIDER: GUID = GUID::from_u128(0x36DA592D_E43A_4E28_AF6F_4BC57C5A11E8);
+/// Microsoft-Windows-USB-USBPORT
+const USB_USBPORT_PROVIDER: GUID = GUID::from_u128(0xC88A4EF5_D048_4013_9408_E04B7DB2814A);
+
+const SESSION_NAME: &str = "ffb_replay_usb";
+
+/// Seconds between 1601-01-01 (FILETIME epoch) and 1970-01-01
+const FILETIME_UNIX_OFFSET_SECS: u64 = 11_644_473_600;
+
+fn wide(text: &str) -> Vec<u16> {
+    text.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+/// Properties buffer for StartTrace/ControlTrace: the struct followed by the session name
+fn session_properties() -> Vec<u64> {
+    let name = wide(SESSION_NAME);
+    let header = std::mem::size_of::<EVENT_TRACE_PROPERTIES>();
+    let size = header + name.len() * 2;
+    // u64 storage keeps EVENT_TRACE_PROPERTIES aligned
+    let mut buffer = vec![0u64; size.div_ceil(8)];
+
+    // SAFETY: buffer is large enough and aligned for EVENT_TRACE_PROPERTIES
+    let properties = unsafe { &mut *(buffer.as_mut_ptr() as *mut EVENT_TRACE_PROPERTIES) };
+    properties.Wnode.BufferSize = size as u32;
+    properties.Wnode.Flags = WNODE_FLAG_TRACED_GUID;
+    // System time timestamps (FILETIME)
+    properties.Wnode.ClientContext = 2;
+    properties.LogFileMode = EVENT_TRACE_REAL_TIME_MODE;
+    properties.LoggerNameOffset = header as u32;
+    buffer
+}
+
+/// Running real-time trace session; stopped on drop
+pub struct EtwSession {
+    handle: CONTROLTRACE_HANDLE,
+}
+
+// SAFETY: the session handle is a plain value usable from any thread
+unsafe impl Send for EtwSession {}
+
+impl EtwSession {
+    /// Start the session and enable the USB providers
+    pub fn start() -> Result<Self, String> {
+        let name = wide(SESSION_NAME);
+        let mut handle = CONTROLTRACE_HANDLE { Value: 0 };
+
+        // SAFETY: properties buffers are sized and initialized by session_properties
+        unsafe {
+            let mut properties = session_properties();
+            let mut status = StartTraceW(
+                &mut handle,
+                name.as_ptr(),
+                properties.as_mut_ptr() as *mut EVENT_TRACE_PROPERTIES,
+            );
+            if status == ERROR_ALREADY_EXISTS {
+                // Left over from a previous run that did not shut down cleanly
+                let mut stale = session_properties();
+                ControlTraceW(
+                    CONTROLTRACE_HANDLE { Value: 0 },
+                    name.as_ptr(),
+                    stale.as_mut_ptr() as *mut EVENT_TRACE_PROPERTIES,
+                    EVENT_TRACE_CONTROL_STOP,
+                );
+                properties = session_properties();
+                status = StartTraceW(
+                    &mut handle,
+                    name.as_ptr(),
+                    properties.as_mut_ptr() as *mut EVENT_TRACE_PROPERTIES,
+                );
+            }
+            if status != ERROR_SUCCESS {
+                return Err(format!(
+                    "StartTrace failed: {}. ETW capture requires Administrator privileges",
+                    std::io::Error::from_raw_os_error(status as i32)
+                ));
+            }
+        }
+        let session = Self { handle };
+
+        let mut enabled = 0;
+        for provider in [USB_UCX_PROVIDER, USB_USBPORT_PROVIDER] {
+            // SAFETY: handle is a running session, provider is a valid GUID
+            let status = unsafe {
+                EnableTraceEx2(
+                    session.handle,
+                    &provider,
+                    EVENT_CONTROL_CODE_ENABLE_PROVIDER,
+                    TRACE_LEVEL_VERBOSE as u8,
+                    0,
+                    0,
+                    0,
+                    std::ptr::null(),
+                )
+            };
+            if status == ERROR_SUCCESS {
+                enabled += 1;
+            }
+        }
+        if enabled == 0 {
+            return Err("Failed to enable the USB ETW providers".to_string());
+        }
+
+        Ok(session)
+    }
+}
+
+impl Drop for EtwSession {
+    fn drop(&mut self) {
+        let name = wide(SESSION_NAME);
+        let mut properties = session_properties();
+        // SAFETY: stopping our own session; makes ProcessTrace return on the reader thread
+        unsafe {
+            ControlTraceW(
+                self.handle,
+                name.as_ptr(),
+                properties.as_mut_ptr() as *mut EVENT_TRACE_PROPERTIES,
+                EVENT_TRACE_CONTROL_STOP,
+            );
+        }
+    }
+}
+
+/// State handed to the event callback
+struct Consumer {
+    sink: PacketSink,
+    device_filters: Vec<DeviceFilter>,
+}
+
+/// Consume events of the session until it is stopped
+pub fn consume(sink: PacketSink, device_filters: Vec<DeviceFilter>) -> Result<(), String> {
+    let mut name = wide(SESSION_NAME);
+    let consumer = Box::into_raw(Box::new(Consumer { sink, device_filters }));
+
+    // SAFETY: logfile is fully initialized for a real-time EVENT_RECORD consumer; the consumer
+    // box outlives ProcessTrace and is reclaimed once it returns
+    unsafe {
+        let mut logfile: EVENT_TRACE_LOGFILEW = std::mem::zeroed();
+        logfile.LoggerName = name.as_mut_ptr();
+        logfile.Anonymous1.ProcessTraceMode = PROCESS_TRACE_MODE_REAL_TIME | PROCESS_TRACE_MODE_EVENT_RECORD;
+        logfile.Anonymous2.EventRecordCallback = Some(on_event);
+        logfile.Context = consumer as *mut std::ffi::c_void;
+
+        let trace = OpenTraceW(&mut logfile);
+        if trace.Value == u64::MAX || trace.Value == u32::MAX as u64 {
+            drop(Box::from_raw(consumer));
+            return Err(format!("OpenTrace failed: {}", std::io::Error::last_os_error()));
+        }
+
+        let status = ProcessTrace(&trace, 1, std::ptr::null(), std::ptr::null());
+        CloseTrace(trace);
+        drop(Box::from_raw(consumer));
+
+        if status != ERROR_SUCCESS {
+            return Err(format!(
+                "ProcessTrace failed: {}",
+                std::io::Error::from_raw_os_error(status as i32)
+            ));
+        }
+    }
+    Ok(())
+}
+
+unsafe extern "system" fn on_event(record: *mut EVENT_RECORD) {
+    // ProcessTrace delivers events one at a time on its own thread
+    let consumer = &mut *((*record).UserContext as *mut Consumer);
+    if let Some(packet) = decode_event(record, &consumer.device_filters) {
+        consumer.sink.push(packet);
+    }
+}
+
+/// One decoded event property: leaf name and raw value
+struct Field {
+    name: String,
+    value: Vec<u8>,
+}
+
+impl Field {
+    fn as_u64(&self) -> Option<u64> {
+        let mut bytes = [0u8; 8];
+        let len = self.value.len().min(8);
+        if len == 0 {
+            return None;
+        }
+        bytes[..len].copy_from_slice(&self.value[..len]);
+        Some(u64::from_le_bytes(bytes))
+    }
+}
+
+unsafe fn read_wide(base: *const u8, offset: u32) -> String {
+    if offset == 0 {
+        return String::new();
+    }
+    let start = base.add(offset as usize) as *const u16;
+    let mut len = 0;
+    while *start.add(len) != 0 {
+        len += 1;
+    }
+    String::from_utf16_lossy(std::slice::from_raw_parts(start, len))
+}
+
+unsafe fn read_property(record: *const EVENT_RECORD, path: &[PROPERTY_DATA_DESCRIPTOR]) -> Option<Vec<u8>> {
+    let mut size = 0u32;
+    if TdhGetPropertySize(record, 0, std::ptr::null(), path.len() as u32, path.as_ptr(), &mut size) != ERROR_SUCCESS {
+        return None;
+    }
+    let mut value = vec![0u8; size as usize];
+    let status = TdhGetProperty(
+        record,
+        0,
+        std::ptr::null(),
+        path.len() as u32,
+        path.as_ptr(),
+        size,
+        value.as_mut_ptr(),
+    );
+    (status == ERROR_SUCCESS).then_some(value)
+}
+
+/// Decode a USB URB event into a packet
+/// Returns None for events without a transfer buffer or not matching the device filter
+unsafe fn decode_event(record: *const EVENT_RECORD, device_filters: &[DeviceFilter]) -> Option<UsbPacket> {
+    let mut size = 0u32;
+    if TdhGetEventInformation(record, 0, std::ptr::null(), std::ptr::null_mut(), &mut size) != ERROR_INSUFFICIENT_BUFFER {
+        return None;
+    }
+    let mut buffer = vec![0u64; (size as usize).div_ceil(8)];
+    let info = buffer.as_mut_ptr() as *mut TRACE_EVENT_INFO;
+    if TdhGetEventInformation(record, 0, std::ptr::null(), info, &mut size) != ERROR_SUCCESS {
+        return None;
+    }
+    let base = info as *const u8;
+    let properties = std::slice::from_raw_parts((*info).EventPropertyInfoArray.as_ptr(), (*info).PropertyCount as usize);
+    let descriptor = |index: usize| PROPERTY_DATA_DESCRIPTOR {
+        PropertyName: base.add(properties[index].NameOffset as usize) as u64,
+        ArrayIndex: u32::MAX,
+        Reserved: 0,
+    };
+
+    // Flatten top-level properties and one level of struct members
+    let mut fields = Vec::new();
+    for index in 0..(*info).TopLevelPropertyCount as usize {
+        let property = &properties[index];
+        if property.Flags & PropertyStruct != 0 {
+            let members = property.Anonymous1.structType;
+            let first = members.StructStartIndex as usize;
+            for member in first..first + members.NumOfStructMembers as usize {
+                if let Some(value) = read_property(record, &[descriptor(index), descriptor(member)]) {
+                    fields.push(Field {
+                        name: read_wide(base, properties[member].NameOffset),
+                        value,
+                    });
+                }
+            }
+        } else if let Some(value) = read_property(record, &[descriptor(index)]) {
+            fields.push(Field {
+                name: read_wide(base, property.NameOffset),
+                value,
+            });
+        }
+    }
+
+    let field = |suffix: &str| {
+        fields
+            .iter()
+            .find(|f| f.name.to_ascii_lowercase().ends_with(&suffix.to_ascii_lowercase()))
+    };
+
+    let data = field("TransferData")?.value.clone();
+    if data.is_empty() {
+        return None;
+    }
+
+    let device = DeviceFilter {
+        vendor_id: field("idVendor").and_then(Field::as_u64).unwrap_or(0) as u16,
+        product_id: field("idProduct").and_then(Field::as_u64).unwrap_or(0) as u16,
+    };
+    // Events name the device directly, so packets are matched and tagged here instead of by address
+    let source = device_filters.contains(&device).then_some(device);
+    if !device_filters.is_empty() && source.is_none() {
+        return None;
+    }
+
+    let endpoint_address = field("bEndpointAddress").and_then(Field::as_u64).unwrap_or(0) as u8;
+    // The default control endpoint carries the direction in the URB transfer flags
+    let direction_in = if endpoint_address & 0x7F == 0 {
+        field("TransferFlags").and_then(Field::as_u64).unwrap_or(0) & 0x01 != 0
+    } else {
+        endpoint_address & 0x80 != 0
+    };
+    let direction = if direction_in {
+        PacketDirection::DeviceToHost
+    } else {
+        PacketDirection::HostToDevice
+    };
+
+    // OUT data is reported when the URB is dispatched, IN data when it completes
+    let event_name = format!(
+        "{} {}",
+        read_wide(base, (*info).TaskNameOffset),
+        read_wide(base, (*info).OpcodeNameOffset)
+    )
+    .to_ascii_lowercase();
+    let is_completion = event_name.contains("complet");
+    if direction_in != is_completion {
+        return None;
+    }
+
+    let transfer_type = if event_name.contains("control") {
+        2
+    } else if event_name.contains("isoch") {
+        0
+    } else {
+        // Bulk and interrupt share one URB function
+        1
+    };
+
+    // EVENT_HEADER timestamps are FILETIME (100 ns since 1601) with system-time sessions
+    let ticks = (*record).EventHeader.TimeStamp.max(0) as u64;
+    let timestamp = Duration::from_nanos(ticks.saturating_mul(100))
+        .saturating_sub(Duration::from_secs(FILETIME_UNIX_OFFSET_SECS));
+
+    Some(UsbPacket {
+        timestamp,
+        direction,
+        endpoint: endpoint_address & 0x7F,
+        data,
+        urb_id: field("Urb").and_then(Field::as_u64).unwrap_or(0),
+        bus: 0,
+        device: field("DeviceAddress").and_then(Field::as_u64).unwrap_or(0) as u16,
+        transfer_type,
+        sequence: 0,
+        source,
+    })
+}
@@ -0,0 +1,326 @@
+//! USB hotplug notifications
+//!
+//! - Linux: kernel uevents over a NETLINK_KOBJECT_UEVENT socket (what udev listens to)
+//! - Windows: WM_DEVICECHANGE on a message-only window
+//!
+//! Events are sent over a channel; the watcher thread stops when `running` is cleared.
+
+use super::DeviceFilter;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Kind of hotplug event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotplugAction {
+    Added,
+    Removed,
+}
+
+/// A USB device was plugged in or removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HotplugEvent {
+    pub action: HotplugAction,
+    pub device: DeviceFilter,
+}
+
+/// Start watching USB hotplug events on a background thread
+#[cfg(target_os = "linux")]
+pub fn watch(events: Sender<HotplugEvent>, running: Arc<Mutex<bool>>) -> Result<thread::JoinHandle<()>, String> {
+    let socket = linux::UeventSocket::open()?;
+    Ok(thread::spawn(move || {
+        while *running.lock().unwrap() {
+            let Some(message) = socket.receive(std::time::Duration::from_millis(200)) else {
+                continue;
+            };
+            if let Some(event) = linux::parse_uevent(&message) {
+                if events.send(event).is_err() {
+                    break;
+                }
+            }
+        }
+    }))
+}
+
+/// Start watching USB hotplug events on a background thread
+#[cfg(target_os = "windows")]
+pub fn watch(events: Sender<HotplugEvent>, running: Arc<Mutex<bool>>) -> Result<thread::JoinHandle<()>, String> {
+    let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+    let handle = thread::spawn(move || windows::message_loop(events, running, ready_tx));
+    ready_rx
+        .recv()
+        .map_err(|_| "Hotplug watcher thread exited".to_string())??;
+    Ok(handle)
+}
+
+/// Start watching USB hotplug events on a background thread
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+pub fn watch(_events: Sender<HotplugEvent>, _running: Arc<Mutex<bool>>) -> Result<thread::JoinHandle<()>, String> {
+    Err("USB hotplug monitoring is not supported on this platform".to_string())
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{HotplugAction, HotplugEvent};
+    use crate::usb_monitor::DeviceFilter;
+    use std::time::Duration;
+
+    /// Multicast group of kernel uevents
+    const KERNEL_UEVENT_GROUP: u32 = 1;
+
+    pub struct UeventSocket {
+        fd: libc::c_int,
+    }
+
+    impl UeventSocket {
+        pub fn open() -> Result<Self, String> {
+            // SAFETY: plain socket creation, the fd is owned by the returned value
+            let fd = unsafe {
+                libc::socket(
+                    libc::AF_NETLINK,
+                    libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                    libc::NETLINK_KOBJECT_UEVENT,
+                )
+            };
+            if fd < 0 {
+                return Err(format!("Failed to open uevent socket: {}", std::io::Error::last_os_error()));
+            }
+            let socket = Self { fd };
+
+            // SAFETY: sockaddr_nl is plain data, all-zero is a valid value
+            let mut address: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+            address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            address.nl_groups = KERNEL_UEVENT_GROUP;
+
+            // SAFETY: address is a valid sockaddr_nl of the given size
+            let result = unsafe {
+                libc::bind(
+                    socket.fd,
+                    &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                    std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+                )
+            };
+            if result < 0 {
+                return Err(format!("Failed to bind uevent socket: {}", std::io::Error::last_os_error()));
+            }
+            Ok(socket)
+        }
+
+        /// Wait up to `timeout` for one uevent message
+        pub fn receive(&self, timeout: Duration) -> Option<Vec<u8>> {
+            let mut pfd = libc::pollfd {
+                fd: self.fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: pfd is a valid pollfd for one descriptor
+            let ready = unsafe { libc::poll(&mut pfd, 1, timeout.as_millis() as libc::c_int) };
+            if ready <= 0 {
+                return None;
+            }
+
+            let mut buffer = vec![0u8; 8192];
+            // SAFETY: buffer is valid for its length
+            let length = unsafe { libc::recv(self.fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len(), 0) };
+            if length <= 0 {
+                return None;
+            }
+            buffer.truncate(length as usize);
+            Some(buffer)
+        }
+    }
+
+    impl Drop for UeventSocket {
+        fn drop(&mut self) {
+            // SAFETY: fd was opened by us and is closed once
+            unsafe {
+                libc::close(self.fd);
+            }
+        }
+    }
+
+    /// Parse a kernel uevent ("add@/devices/...\0ACTION=add\0SUBSYSTEM=usb\0...")
+    /// Only whole USB devices are reported, not their interfaces
+    pub fn parse_uevent(message: &[u8]) -> Option<HotplugEvent> {
+        let mut action = None;
+        let mut subsystem = None;
+        let mut devtype = None;
+        let mut product = None;
+
+        for field in message.split(|&b| b == 0) {
+            let Ok(field) = std::str::from_utf8(field) else {
+                continue;
+            };
+            match field.split_once('=') {
+                Some(("ACTION", value)) => action = Some(value),
+                Some(("SUBSYSTEM", value)) => subsystem = Some(value),
+                Some(("DEVTYPE", value)) => devtype = Some(value),
+                Some(("PRODUCT", value)) => product = Some(value),
+                _ => {}
+            }
+        }
+
+        if subsystem != Some("usb") || devtype != Some("usb_device") {
+            return None;
+        }
+        let action = match action? {
+            "add" => HotplugAction::Added,
+            "remove" => HotplugAction::Removed,
+            _ => return None,
+        };
+
+        // PRODUCT=46d/c24f/111 (vendor/product/bcdDevice in hex, no padding)
+        let mut parts = product?.split('/');
+        let vendor_id = u16::from_str_radix(parts.next()?, 16).ok()?;
+        let product_id = u16::from_str_radix(parts.next()?, 16).ok()?;
+
+        Some(HotplugEvent {
+            action,
+            device: DeviceFilter { vendor_id, product_id },
+        })
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod windows {
+    use super::{HotplugAction, HotplugEvent};
+    use crate::usb_monitor::DeviceFilter;
+    use std::cell::RefCell;
+    use std::sync::mpsc::Sender;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use windows_sys::core::GUID;
+    use windows_sys::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+    use windows_sys::Win32::System::LibraryLoader::GetModuleHandleW;
+    use windows_sys::Win32::UI::WindowsAndMessaging::*;
+
+    /// GUID_DEVINTERFACE_USB_DEVICE
+    const USB_DEVICE_INTERFACE: GUID = GUID::from_u128(0xA5DCBF10_6530_11D2_901F_00C04FB951ED);
+
+    thread_local! {
+        // Window procedures cannot capture state; the watcher thread owns its sender here
+        static EVENTS: RefCell<Option<Sender<HotplugEvent>>> = const { RefCell::new(None) };
+    }
+
+    fn wide(text: &str) -> Vec<u16> {
+        text.encode_utf16().chain(std::iter::once(0)).collect()
+    }
+
+    /// Create a message-only window, register for USB device notifications and pump messages
+    pub fn message_loop(
+        events: Sender<HotplugEvent>,
+        running: Arc<Mutex<bool>>,
+        ready: Sender<Result<(), String>>,
+    ) {
+        EVENTS.with(|slot| *slot.borrow_mut() = Some(events));
+        let class_name = wide("ffb_replay_hotplug");
+
+        // SAFETY: Win32 calls with valid, NUL-terminated strings and zero-initialized structs;
+        // the window and notification are destroyed on this thread before returning
+        unsafe {
+            let instance = GetModuleHandleW(std::ptr::null());
+            let mut class: WNDCLASSW = std::mem::zeroed();
+            class.lpfnWndProc = Some(window_proc);
+            class.hInstance = instance;
+            class.lpszClassName = class_name.as_ptr();
+            RegisterClassW(&class);
+
+            let window = CreateWindowExW(
+                0,
+                class_name.as_ptr(),
+                class_name.as_ptr(),
+                0,
+                0,
+                0,
+                0,
+                0,
+                HWND_MESSAGE,
+                std::ptr::null_mut(),
+                instance,
+                std::ptr::null(),
+            );
+            if window.is_null() {
+                let _ = ready.send(Err(format!(
+                    "Failed to create hotplug window: {}",
+                    std::io::Error::last_os_error()
+                )));
+                return;
+            }
+
+            let mut filter: DEV_BROADCAST_DEVICEINTERFACE_W = std::mem::zeroed();
+            filter.dbcc_size = std::mem::size_of::<DEV_BROADCAST_DEVICEINTERFACE_W>() as u32;
+            filter.dbcc_devicetype = DBT_DEVTYP_DEVICEINTERFACE;
+            filter.dbcc_classguid = USB_DEVICE_INTERFACE;
+            let notification = RegisterDeviceNotificationW(
+                window,
+                &filter as *const DEV_BROADCAST_DEVICEINTERFACE_W as *const _,
+                DEVICE_NOTIFY_WINDOW_HANDLE,
+            );
+            if notification.is_null() {
+                let _ = ready.send(Err(format!(
+                    "Failed to register for device notifications: {}",
+                    std::io::Error::last_os_error()
+                )));
+                DestroyWindow(window);
+                return;
+            }
+            let _ = ready.send(Ok(()));
+
+            let mut message: MSG = std::mem::zeroed();
+            while *running.lock().unwrap() {
+                while PeekMessageW(&mut message, window, 0, 0, PM_REMOVE) != 0 {
+                    TranslateMessage(&message);
+                    DispatchMessageW(&message);
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+
+            UnregisterDeviceNotification(notification);
+            DestroyWindow(window);
+            UnregisterClassW(class_name.as_ptr(), instance);
+        }
+    }
+
+    unsafe extern "system" fn window_proc(window: HWND, message: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+        if message == WM_DEVICECHANGE {
+            let action = match wparam as u32 {
+                DBT_DEVICEARRIVAL => Some(HotplugAction::Added),
+                DBT_DEVICEREMOVECOMPLETE => Some(HotplugAction::Removed),
+                _ => None,
+            };
+            if let (Some(action), Some(device)) = (action, device_from_broadcast(lparam)) {
+                EVENTS.with(|slot| {
+                    if let Some(events) = slot.borrow().as_ref() {
+                        let _ = events.send(HotplugEvent { action, device });
+                    }
+                });
+            }
+            return 1;
+        }
+        DefWindowProcW(window, message, wparam, lparam)
+    }
+
+    /// Read VID/PID from the device interface path ("\\?\USB#VID_046D&PID_C24F#...")
+    unsafe fn device_from_broadcast(lparam: LPARAM) -> Option<DeviceFilter> {
+        let header = lparam as *const DEV_BROADCAST_HDR;
+        if header.is_null() || (*header).dbch_devicetype != DBT_DEVTYP_DEVICEINTERFACE {
+            return None;
+        }
+        let interface = lparam as *const DEV_BROADCAST_DEVICEINTERFACE_W;
+        let name_ptr = (*interface).dbcc_name.as_ptr();
+        let mut length = 0;
+        while *name_ptr.add(length) != 0 {
+            length += 1;
+        }
+        let name = String::from_utf16_lossy(std::slice::from_raw_parts(name_ptr, length)).to_uppercase();
+
+        let hex_after = |key: &str| {
+            let start = name.find(key)? + key.len();
+            u16::from_str_radix(name.get(start..start + 4)?, 16).ok()
+        };
+        Some(DeviceFilter {
+            vendor_id: hex_after("VID_")?,
+            product_id: hex_after("PID_")?,
+        })
+    }
+}
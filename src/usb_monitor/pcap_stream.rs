@@ -0,0 +1,310 @@
+//! Incremental parser of pcap streams (tcpdump and USBPcapCMD stdout)
+//!
+//! Bytes are fed as they are read and complete records come out in order. The global
+//! header decides byte order and timestamp precision; record lengths are checked against
+//! the snaplen so a corrupt stream fails instead of stalling or slicing garbage.
+//!
+//! Records borrow their bytes from the stream's buffer, which a cursor walks through;
+//! only the unparsed tail of a read is moved when the next one is fed, so a capture
+//! at several thousand packets per second is not copied packet by packet.
+
+use std::time::Duration;
+
+const GLOBAL_HEADER_LEN: usize = 24;
+const RECORD_HEADER_LEN: usize = 16;
+/// Record size limit when the header's snaplen is 0 or larger (libpcap's maximum snaplen)
+const MAX_SNAPLEN: u32 = 262_144;
+
+/// Link type of usbmon captures with 48-byte header
+pub const DLT_USB_LINUX: u32 = 189;
+/// Link type of usbmon captures with 64-byte header
+pub const DLT_USB_LINUX_MMAPPED: u32 = 220;
+/// Link type of USBPcap captures
+pub const DLT_USBPCAP: u32 = 249;
+
+/// Fields of the pcap global header
+#[derive(Debug, Clone, Copy)]
+pub struct PcapHeader {
+    pub link_type: u32,
+    pub snaplen: u32,
+    big_endian: bool,
+    nanosecond: bool,
+}
+
+impl PcapHeader {
+    fn parse(bytes: &[u8]) -> Result<Self, String> {
+        // Magic as written by the producer: a1b2c3d4 (microseconds) or a1b23c4d (nanoseconds)
+        let (big_endian, nanosecond) = match bytes[0..4] {
+            [0xd4, 0xc3, 0xb2, 0xa1] => (false, false),
+            [0x4d, 0x3c, 0xb2, 0xa1] => (false, true),
+            [0xa1, 0xb2, 0xc3, 0xd4] => (true, false),
+            [0xa1, 0xb2, 0x3c, 0x4d] => (true, true),
+            _ => return Err(format!("Invalid pcap header: {:02X?}", &bytes[0..4])),
+        };
+        let mut header = Self {
+            link_type: 0,
+            snaplen: 0,
+            big_endian,
+            nanosecond,
+        };
+        header.snaplen = header.u32_at(bytes, 16);
+        // The upper bits of the link type field carry FCS information
+        header.link_type = header.u32_at(bytes, 20) & 0x0FFF_FFFF;
+        Ok(header)
+    }
+
+    fn u32_at(&self, bytes: &[u8], offset: usize) -> u32 {
+        let field = [bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]];
+        if self.big_endian {
+            u32::from_be_bytes(field)
+        } else {
+            u32::from_le_bytes(field)
+        }
+    }
+
+    /// Largest record length this stream may contain
+    fn max_record_len(&self) -> usize {
+        match self.snaplen {
+            0 => MAX_SNAPLEN as usize,
+            snaplen => snaplen.min(MAX_SNAPLEN) as usize,
+        }
+    }
+}
+
+/// One captured frame, valid until the stream is read or fed again
+pub struct Record<'a> {
+    /// Capture time since the Unix epoch
+    pub timestamp: Duration,
+    /// Frame bytes, cut at the snaplen
+    pub data: &'a [u8],
+}
+
+/// Pcap stream being parsed
+#[derive(Default)]
+pub struct PcapStream {
+    buffer: Vec<u8>,
+    /// Start of the unparsed bytes in `buffer`
+    position: usize,
+    header: Option<PcapHeader>,
+}
+
+impl PcapStream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append bytes read from the stream
+    pub fn feed(&mut self, bytes: &[u8]) {
+        // Drop parsed bytes before growing, so the buffer stays about one read in size
+        if self.position > 0 {
+            self.buffer.drain(..self.position);
+            self.position = 0;
+        }
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Global header, None until enough bytes arrived
+    pub fn header(&mut self) -> Result<Option<PcapHeader>, String> {
+        if self.header.is_none() && self.buffer.len() - self.position >= GLOBAL_HEADER_LEN {
+            let header = PcapHeader::parse(&self.buffer[self.position..])?;
+            self.position += GLOBAL_HEADER_LEN;
+            self.header = Some(header);
+        }
+        Ok(self.header)
+    }
+
+    /// Next complete record, None until more bytes arrived
+    pub fn next_record(&mut self) -> Result<Option<Record<'_>>, String> {
+        let Some(header) = self.header()? else {
+            return Ok(None);
+        };
+        let pending = &self.buffer[self.position..];
+        if pending.len() < RECORD_HEADER_LEN {
+            return Ok(None);
+        }
+
+        // Record header: ts_sec(4), ts_frac(4), incl_len(4), orig_len(4)
+        let ts_sec = header.u32_at(pending, 0) as u64;
+        let ts_frac = header.u32_at(pending, 4) as u64;
+        let incl_len = header.u32_at(pending, 8) as usize;
+        if incl_len > header.max_record_len() {
+            return Err(format!(
+                "Corrupt pcap stream: record of {} bytes exceeds the snaplen of {}",
+                incl_len,
+                header.max_record_len()
+            ));
+        }
+
+        let end = RECORD_HEADER_LEN + incl_len;
+        if pending.len() < end {
+            return Ok(None);
+        }
+        let start = self.position + RECORD_HEADER_LEN;
+        self.position += end;
+        let data = &self.buffer[start..self.position];
+
+        let fraction = if header.nanosecond {
+            Duration::from_nanos(ts_frac)
+        } else {
+            Duration::from_micros(ts_frac)
+        };
+        Ok(Some(Record {
+            timestamp: Duration::from_secs(ts_sec) + fraction,
+            data,
+        }))
+    }
+
+    /// Describe bytes left over when the stream ended, None if it ended on a record boundary
+    pub fn finish(&self) -> Option<String> {
+        let leftover = self.buffer.len() - self.position;
+        match (leftover, self.header) {
+            (0, _) => None,
+            (_, None) => Some(format!("stream ended inside the pcap header ({} bytes)", leftover)),
+            (_, Some(_)) => Some(format!("stream ended inside a pcap record ({} bytes)", leftover)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_bytes(value: u32, big_endian: bool) -> [u8; 4] {
+        if big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        }
+    }
+
+    /// Global header with `magic` in the given byte order
+    fn global_header(magic: u32, big_endian: bool, snaplen: u32, link_type: u32) -> Vec<u8> {
+        // Version 2.4 (two u16 fields), then timezone and sigfigs
+        let version = if big_endian { [0, 2, 0, 4] } else { [2, 0, 4, 0] };
+        [
+            u32_bytes(magic, big_endian),
+            version,
+            [0; 4],
+            [0; 4],
+            u32_bytes(snaplen, big_endian),
+            u32_bytes(link_type, big_endian),
+        ]
+        .concat()
+    }
+
+    fn record(big_endian: bool, seconds: u32, fraction: u32, data: &[u8]) -> Vec<u8> {
+        let len = data.len() as u32;
+        let mut bytes: Vec<u8> = [seconds, fraction, len, len]
+            .into_iter()
+            .flat_map(|field| u32_bytes(field, big_endian))
+            .collect();
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn all_magic_numbers_are_recognized() {
+        for (magic, big_endian, nanosecond) in [
+            (0xa1b2_c3d4, false, false),
+            (0xa1b2_3c4d, false, true),
+            (0xa1b2_c3d4, true, false),
+            (0xa1b2_3c4d, true, true),
+        ] {
+            let header = PcapHeader::parse(&global_header(magic, big_endian, 65535, DLT_USBPCAP)).unwrap();
+            assert_eq!(header.big_endian, big_endian);
+            assert_eq!(header.nanosecond, nanosecond);
+            assert_eq!(header.snaplen, 65535);
+            assert_eq!(header.link_type, DLT_USBPCAP);
+        }
+    }
+
+    #[test]
+    fn fcs_bits_are_masked_from_the_link_type() {
+        let header = PcapHeader::parse(&global_header(0xa1b2_c3d4, false, 0, 0x1000_0000 | DLT_USB_LINUX)).unwrap();
+        assert_eq!(header.link_type, DLT_USB_LINUX);
+        assert_eq!(header.max_record_len(), MAX_SNAPLEN as usize);
+    }
+
+    #[test]
+    fn invalid_magic_is_rejected() {
+        let mut bytes = global_header(0xa1b2_c3d4, false, 65535, DLT_USBPCAP);
+        bytes[0] = 0;
+        assert!(PcapHeader::parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn records_are_read_in_either_byte_order() {
+        for big_endian in [false, true] {
+            let mut stream = PcapStream::new();
+            stream.feed(&global_header(0xa1b2_c3d4, big_endian, 65535, DLT_USB_LINUX_MMAPPED));
+            stream.feed(&record(big_endian, 10, 250_000, &[1, 2, 3]));
+            stream.feed(&record(big_endian, 11, 0, &[4]));
+
+            let first = stream.next_record().unwrap().unwrap();
+            assert_eq!(first.timestamp, Duration::from_millis(10_250));
+            assert_eq!(first.data, &[1, 2, 3]);
+            let second = stream.next_record().unwrap().unwrap();
+            assert_eq!(second.timestamp, Duration::from_secs(11));
+            assert_eq!(second.data, &[4]);
+            assert!(stream.next_record().unwrap().is_none());
+            assert_eq!(stream.finish(), None);
+        }
+    }
+
+    #[test]
+    fn nanosecond_fraction_is_not_scaled() {
+        let mut stream = PcapStream::new();
+        stream.feed(&global_header(0xa1b2_3c4d, false, 65535, DLT_USBPCAP));
+        stream.feed(&record(false, 2, 123_456_789, &[0xAA]));
+        let record = stream.next_record().unwrap().unwrap();
+        assert_eq!(record.timestamp, Duration::new(2, 123_456_789));
+    }
+
+    #[test]
+    fn records_longer_than_the_snaplen_are_rejected() {
+        let mut stream = PcapStream::new();
+        stream.feed(&global_header(0xa1b2_c3d4, false, 4, DLT_USBPCAP));
+        stream.feed(&record(false, 0, 0, &[0; 5]));
+        let error = stream.next_record().err().unwrap();
+        assert!(error.contains("exceeds the snaplen of 4"), "{}", error);
+    }
+
+    #[test]
+    fn records_split_across_feeds_are_completed() {
+        let mut bytes = global_header(0xa1b2_c3d4, false, 65535, DLT_USBPCAP);
+        bytes.extend(record(false, 1, 0, &[1, 2, 3, 4, 5, 6]));
+        bytes.extend(record(false, 2, 0, &[7, 8]));
+
+        // One byte at a time: nothing comes out before a record is complete
+        let mut stream = PcapStream::new();
+        let mut records = Vec::new();
+        for byte in &bytes {
+            stream.feed(std::slice::from_ref(byte));
+            while let Some(record) = stream.next_record().unwrap() {
+                records.push((record.timestamp, record.data.to_vec()));
+            }
+        }
+        assert_eq!(
+            records,
+            vec![
+                (Duration::from_secs(1), vec![1, 2, 3, 4, 5, 6]),
+                (Duration::from_secs(2), vec![7, 8])
+            ]
+        );
+        assert_eq!(stream.finish(), None);
+    }
+
+    #[test]
+    fn truncated_streams_are_described() {
+        let mut stream = PcapStream::new();
+        stream.feed(&global_header(0xa1b2_c3d4, false, 65535, DLT_USBPCAP)[..10]);
+        assert!(stream.header().unwrap().is_none());
+        assert_eq!(stream.finish().unwrap(), "stream ended inside the pcap header (10 bytes)");
+
+        let mut stream = PcapStream::new();
+        stream.feed(&global_header(0xa1b2_c3d4, false, 65535, DLT_USBPCAP));
+        stream.feed(&record(false, 0, 0, &[1, 2, 3])[..18]);
+        assert!(stream.next_record().unwrap().is_none());
+        assert_eq!(stream.finish().unwrap(), "stream ended inside a pcap record (18 bytes)");
+    }
+}
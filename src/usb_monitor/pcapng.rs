@@ -0,0 +1,148 @@
+//! Parser of pcapng files, the default format Wireshark saves captures in
+//!
+//! A file is a sequence of blocks. Section headers set the byte order, interface
+//! descriptions the link type and timestamp resolution of each interface, and
+//! enhanced or simple packet blocks carry the frames. Other blocks (statistics,
+//! name resolution, comments) are skipped.
+
+use super::pcap_stream::Record;
+use std::time::Duration;
+
+const SECTION_HEADER: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+const SIMPLE_PACKET: u32 = 0x0000_0003;
+const ENHANCED_PACKET: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+/// Option of interface descriptions with the timestamp resolution
+const IF_TSRESOL: u16 = 9;
+
+/// File starts with a section header block
+pub fn is_pcapng(bytes: &[u8]) -> bool {
+    bytes.starts_with(&SECTION_HEADER.to_le_bytes())
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Interface {
+    link_type: u32,
+    snaplen: u32,
+    /// Timestamp units per second as a power: (base, exponent), 10^-6 by default
+    resolution: (u32, u32),
+}
+
+impl Interface {
+    fn timestamp(&self, ticks: u64) -> Duration {
+        let (base, exponent) = self.resolution;
+        let per_second = (base as u128).checked_pow(exponent).unwrap_or(u128::MAX);
+        let nanos = ticks as u128 * 1_000_000_000 / per_second;
+        Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+    }
+}
+
+/// Frames of the file with the link type of their interface
+pub fn parse(bytes: &[u8]) -> Result<Vec<(u32, Record<'_>)>, String> {
+    let mut records = Vec::new();
+    let mut interfaces: Vec<Interface> = Vec::new();
+    let mut big_endian = false;
+    let mut position = 0;
+
+    while position < bytes.len() {
+        let block = &bytes[position..];
+        if block.len() < 12 {
+            return Err(format!("pcapng file ends inside a block header at byte {}", position));
+        }
+        let block_type = u32::from_le_bytes([block[0], block[1], block[2], block[3]]);
+        if block_type == SECTION_HEADER {
+            // The byte order magic decides how this section is read
+            big_endian = match [block[8], block[9], block[10], block[11]] {
+                magic if u32::from_le_bytes(magic) == BYTE_ORDER_MAGIC => false,
+                magic if u32::from_be_bytes(magic) == BYTE_ORDER_MAGIC => true,
+                magic => return Err(format!("Invalid pcapng byte order magic: {:02X?}", magic)),
+            };
+            interfaces.clear();
+        }
+        let u32_at = |data: &[u8], offset: usize| -> Option<u32> {
+            let field: [u8; 4] = data.get(offset..offset + 4)?.try_into().ok()?;
+            Some(if big_endian {
+                u32::from_be_bytes(field)
+            } else {
+                u32::from_le_bytes(field)
+            })
+        };
+        let block_type = u32_at(block, 0).unwrap_or_default();
+        let length = u32_at(block, 4).unwrap_or_default() as usize;
+        if length < 12 || !length.is_multiple_of(4) || length > block.len() {
+            return Err(format!("Corrupt pcapng block of {} bytes at byte {}", length, position));
+        }
+        let body = &block[8..length - 4];
+        position += length;
+
+        match block_type {
+            INTERFACE_DESCRIPTION => {
+                let link_type = u32_at(body, 0).ok_or("Truncated interface description block")?;
+                let link_type = if big_endian { link_type >> 16 } else { link_type & 0xFFFF };
+                let snaplen = u32_at(body, 4).ok_or("Truncated interface description block")?;
+                interfaces.push(Interface {
+                    link_type,
+                    snaplen,
+                    resolution: timestamp_resolution(body.get(8..).unwrap_or_default(), big_endian),
+                });
+            }
+            ENHANCED_PACKET => {
+                let field = |offset| u32_at(body, offset).ok_or("Truncated enhanced packet block");
+                let interface = *interfaces
+                    .get(field(0)? as usize)
+                    .ok_or("Enhanced packet block of an undescribed interface")?;
+                let ticks = ((field(4)? as u64) << 32) | field(8)? as u64;
+                let captured = field(12)? as usize;
+                let data = body
+                    .get(20..20 + captured)
+                    .ok_or("Enhanced packet block shorter than its packet")?;
+                records.push((
+                    interface.link_type,
+                    Record {
+                        timestamp: interface.timestamp(ticks),
+                        data,
+                    },
+                ));
+            }
+            SIMPLE_PACKET => {
+                // Simple packets belong to the first interface and carry no timestamp
+                let interface = *interfaces.first().ok_or("Simple packet block without an interface")?;
+                let original = u32_at(body, 0).ok_or("Truncated simple packet block")? as usize;
+                let mut captured = original.min(body.len() - 4);
+                if interface.snaplen > 0 {
+                    captured = captured.min(interface.snaplen as usize);
+                }
+                records.push((
+                    interface.link_type,
+                    Record {
+                        timestamp: Duration::ZERO,
+                        data: &body[4..4 + captured],
+                    },
+                ));
+            }
+            _ => {}
+        }
+    }
+    Ok(records)
+}
+
+/// `if_tsresol` of an interface's options: 10^-n, or 2^-n with the top bit set
+fn timestamp_resolution(mut options: &[u8], big_endian: bool) -> (u32, u32) {
+    while options.len() >= 4 {
+        let read = |bytes: [u8; 2]| if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) };
+        let code = read([options[0], options[1]]);
+        let length = read([options[2], options[3]]) as usize;
+        if code == 0 {
+            break;
+        }
+        if code == IF_TSRESOL && length == 1 {
+            if let Some(&value) = options.get(4) {
+                return if value & 0x80 != 0 { (2, (value & 0x7F) as u32) } else { (10, value as u32) };
+            }
+        }
+        // Option values are padded to 32 bits
+        options = options.get(4 + length.div_ceil(4) * 4..).unwrap_or_default();
+    }
+    (10, 6)
+}
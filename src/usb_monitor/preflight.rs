@@ -0,0 +1,302 @@
+//! Probes of what each capture backend needs, run before the backend starts
+//!
+//! A capture backend without its privileges or tools used to fail late and vaguely:
+//! tcpdump waiting on a sudo password until the start timeout, or "failed to start
+//! capture" with no hint why. Each backend now probes its requirements first (the
+//! usbmon module, read access to `/dev/usbmonN`, tcpdump and its capture rights on
+//! Linux; Administrator rights and the USBPcap install on Windows) and a missing one
+//! is reported with the command that fixes it on this system.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A missing capture requirement and how to provide it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub what: String,
+    pub fix: String,
+}
+
+impl Problem {
+    fn new(what: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self {
+            what: what.into(),
+            fix: fix.into(),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}. To fix: {}", self.what, self.fix)
+    }
+}
+
+impl From<Problem> for String {
+    fn from(problem: Problem) -> Self {
+        problem.to_string()
+    }
+}
+
+/// Path of `program` on PATH, or in the admin directories a user's PATH often lacks
+fn find_program(program: &str, extra_dirs: &[&str]) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path)
+        .chain(extra_dirs.iter().map(PathBuf::from))
+        .map(|dir| dir.join(program))
+        .find(|candidate| candidate.is_file())
+}
+
+/// How tcpdump gets the rights to capture
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TcpdumpLaunch {
+    /// Run as is: this process is root, or tcpdump can capture on its own
+    Direct(PathBuf),
+    /// Run through non-interactive sudo
+    Sudo(PathBuf),
+}
+
+#[cfg(target_os = "linux")]
+impl TcpdumpLaunch {
+    pub fn command(&self) -> Command {
+        match self {
+            TcpdumpLaunch::Direct(path) => Command::new(path),
+            TcpdumpLaunch::Sudo(path) => {
+                let mut command = Command::new("sudo");
+                command.arg("-n").arg(path);
+                command
+            }
+        }
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            TcpdumpLaunch::Direct(_) => "tcpdump",
+            TcpdumpLaunch::Sudo(_) => "tcpdump (through sudo)",
+        }
+    }
+}
+
+/// Bit of CAP_NET_RAW in capability sets
+#[cfg(target_os = "linux")]
+const CAP_NET_RAW: u32 = 13;
+
+#[cfg(target_os = "linux")]
+fn is_root() -> bool {
+    // SAFETY: no preconditions
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(target_os = "linux")]
+fn is_readable(path: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: a valid NUL-terminated path
+    unsafe { libc::access(path.as_ptr(), libc::R_OK) == 0 }
+}
+
+/// Whether the file capabilities of `program` permit CAP_NET_RAW (as `setcap` grants it)
+#[cfg(target_os = "linux")]
+fn has_net_raw(program: &Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(path) = std::ffi::CString::new(program.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // vfs_cap_data: magic and flags, then the permitted set's low word first
+    let mut data = [0u8; 24];
+    // SAFETY: the buffer outlives the call and its length is passed
+    let len = unsafe {
+        libc::getxattr(
+            path.as_ptr(),
+            c"security.capability".as_ptr(),
+            data.as_mut_ptr().cast(),
+            data.len(),
+        )
+    };
+    if len < 8 {
+        return false;
+    }
+    let permitted = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+    permitted & (1 << CAP_NET_RAW) != 0
+}
+
+/// Command installing `package` with the package manager found on this system
+#[cfg(target_os = "linux")]
+fn install_command(package: &str) -> String {
+    let managers = [
+        ("apt-get", "sudo apt install"),
+        ("dnf", "sudo dnf install"),
+        ("pacman", "sudo pacman -S"),
+        ("zypper", "sudo zypper install"),
+    ];
+    managers
+        .iter()
+        .find(|(manager, _)| find_program(manager, &[]).is_some())
+        .map(|(_, install)| format!("{} {}", install, package))
+        .unwrap_or_else(|| format!("install the {} package of your distribution", package))
+}
+
+/// Way to give the current user read access to a usbmon device
+#[cfg(target_os = "linux")]
+fn usbmon_access_fix(device: &Path) -> String {
+    let user = std::env::var("USER").unwrap_or_else(|_| "$USER".to_string());
+    format!(
+        "sudo setfacl -m u:{}:r {} (until the next reboot), or run this command with sudo",
+        user,
+        device.display()
+    )
+}
+
+/// The usbmon kernel module is loaded, loading it when running as root
+#[cfg(target_os = "linux")]
+pub fn usbmon_loaded() -> Result<(), Problem> {
+    let loaded = || Path::new("/sys/module/usbmon").exists() || Path::new("/dev/usbmon0").exists();
+    if !loaded() && is_root() {
+        let _ = Command::new("modprobe").arg("usbmon").output();
+    }
+    if loaded() {
+        Ok(())
+    } else {
+        Err(Problem::new("The usbmon kernel module is not loaded", "sudo modprobe usbmon"))
+    }
+}
+
+/// This process can read the usbmon device of `interface` (e.g. "usbmon0")
+#[cfg(target_os = "linux")]
+pub fn usbmon_readable(interface: &str) -> Result<(), Problem> {
+    let device = Path::new("/dev").join(interface);
+    if !device.exists() {
+        return Err(Problem::new(
+            format!("{} does not exist", device.display()),
+            "sudo modprobe usbmon, and check the device's bus with lsusb",
+        ));
+    }
+    if is_root() || is_readable(&device) {
+        Ok(())
+    } else {
+        Err(Problem::new(
+            format!("No read access to {}", device.display()),
+            usbmon_access_fix(&device),
+        ))
+    }
+}
+
+/// How to start tcpdump on `interface`: it must be installed, and this process must be
+/// root, tcpdump must have CAP_NET_RAW and read access to the device, or sudo must not
+/// need a password
+#[cfg(target_os = "linux")]
+pub fn tcpdump(interface: &str) -> Result<TcpdumpLaunch, Problem> {
+    let path = find_program("tcpdump", &["/usr/sbin", "/sbin"])
+        .ok_or_else(|| Problem::new("tcpdump is not installed", install_command("tcpdump")))?;
+    if is_root() {
+        return Ok(TcpdumpLaunch::Direct(path));
+    }
+
+    let device = Path::new("/dev").join(interface);
+    let net_raw = has_net_raw(&path);
+    if net_raw && is_readable(&device) {
+        return Ok(TcpdumpLaunch::Direct(path));
+    }
+    let sudo = Command::new("sudo").args(["-n", "true"]).output();
+    if sudo.is_ok_and(|output| output.status.success()) {
+        return Ok(TcpdumpLaunch::Sudo(path));
+    }
+
+    if net_raw {
+        Err(Problem::new(
+            format!(
+                "tcpdump has CAP_NET_RAW but no read access to {}, and sudo needs a password",
+                device.display()
+            ),
+            usbmon_access_fix(&device),
+        ))
+    } else {
+        Err(Problem::new(
+            "Not root, tcpdump has no CAP_NET_RAW, and sudo needs a password to start it",
+            format!(
+                "sudo -v to cache the sudo password for this run, or sudo setcap cap_net_raw,cap_net_admin=eip {} \
+                 and give read access to {}",
+                path.display(),
+                device.display()
+            ),
+        ))
+    }
+}
+
+/// This process runs elevated, as USBPcap and ETW capture need
+#[cfg(target_os = "windows")]
+pub fn administrator() -> Result<(), Problem> {
+    use windows_sys::Win32::Foundation::{CloseHandle, HANDLE};
+    use windows_sys::Win32::Security::{GetTokenInformation, TokenElevation, TOKEN_ELEVATION, TOKEN_QUERY};
+    use windows_sys::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+
+    let mut elevation = TOKEN_ELEVATION { TokenIsElevated: 0 };
+    // SAFETY: the token is closed after the query; the output buffer has the queried size
+    let elevated = unsafe {
+        let mut token: HANDLE = std::ptr::null_mut();
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) == 0 {
+            return Ok(());
+        }
+        let mut len = 0;
+        let queried = GetTokenInformation(
+            token,
+            TokenElevation,
+            (&mut elevation as *mut TOKEN_ELEVATION).cast(),
+            std::mem::size_of::<TOKEN_ELEVATION>() as u32,
+            &mut len,
+        );
+        CloseHandle(token);
+        // An unanswered query is left to the backend to fail on
+        queried == 0 || elevation.TokenIsElevated != 0
+    };
+    if elevated {
+        Ok(())
+    } else {
+        Err(Problem::new(
+            "USB capture needs Administrator rights",
+            "open an elevated terminal (Start-Process powershell -Verb RunAs) and run this command from it",
+        ))
+    }
+}
+
+/// The USBPcap filter driver is running
+#[cfg(target_os = "windows")]
+pub fn usbpcap_driver() -> Result<(), Problem> {
+    let service = Command::new("sc").args(["query", "USBPcap"]).output();
+    let running = service.is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains("RUNNING"));
+    if running {
+        Ok(())
+    } else {
+        Err(Problem::new(
+            "The USBPcap filter driver is not running",
+            "install USBPcap from https://desowin.org/usbpcap/ and reboot, sc start USBPcap from an elevated \
+             terminal, or use --capture-backend etw",
+        ))
+    }
+}
+
+/// Path of USBPcapCMD, with its filter driver running
+#[cfg(target_os = "windows")]
+pub fn usbpcap() -> Result<PathBuf, Problem> {
+    let installed = [
+        r"C:\Program Files\USBPcap\USBPcapCMD.exe",
+        r"C:\Program Files (x86)\USBPcap\USBPcapCMD.exe",
+    ];
+    let path = installed
+        .iter()
+        .map(PathBuf::from)
+        .find(|path| path.is_file())
+        .or_else(|| find_program("USBPcapCMD.exe", &[]))
+        .ok_or_else(|| {
+            Problem::new(
+                "USBPcap is not installed",
+                "install USBPcap from https://desowin.org/usbpcap/ and reboot, or use --capture-backend etw",
+            )
+        })?;
+    usbpcap_driver()?;
+    Ok(path)
+}
@@ -0,0 +1,143 @@
+//! Direct reader for the Linux usbmon binary interface (/dev/usbmonN)
+//!
+//! Uses the mon_bin ioctl API (MON_IOCX_GETX), which returns the full
+//! 64-byte URB header for every event. No tcpdump or libpcap needed.
+//! See: https://www.kernel.org/doc/Documentation/usb/usbmon.txt
+
+use std::ffi::CString;
+use std::time::Duration;
+
+/// Size of the extended usbmon header returned by MON_IOCX_GETX
+pub const USBMON_HEADER_LEN: usize = 64;
+
+const MON_IOC_MAGIC: u64 = 0x92;
+
+/// Argument of MON_IOCX_GET/GETX
+#[repr(C)]
+struct MonBinGet {
+    hdr: *mut u8,
+    data: *mut u8,
+    alloc: libc::size_t,
+}
+
+/// Result of MON_IOCG_STATS
+#[repr(C)]
+#[derive(Default)]
+struct MonBinStats {
+    queued: u32,
+    dropped: u32,
+}
+
+/// Linux _IOW(type, nr, size)
+const fn iow(ty: u64, nr: u64, size: usize) -> u64 {
+    (1 << 30) | ((size as u64) << 16) | (ty << 8) | nr
+}
+
+/// Linux _IOR(type, nr, size)
+const fn ior(ty: u64, nr: u64, size: usize) -> u64 {
+    (2 << 30) | ((size as u64) << 16) | (ty << 8) | nr
+}
+
+/// MON_IOCX_GETX: fetch one event with the 64-byte header
+const MON_IOCX_GETX: u64 = iow(MON_IOC_MAGIC, 10, std::mem::size_of::<MonBinGet>());
+
+/// MON_IOCG_STATS: queued events and events lost since the last call
+const MON_IOCG_STATS: u64 = ior(MON_IOC_MAGIC, 3, std::mem::size_of::<MonBinStats>());
+
+/// Open usbmon binary device
+pub struct UsbmonReader {
+    fd: libc::c_int,
+    header: [u8; USBMON_HEADER_LEN],
+    data: Vec<u8>,
+}
+
+// SAFETY: the file descriptor is owned and only used by the thread holding the reader
+unsafe impl Send for UsbmonReader {}
+
+impl UsbmonReader {
+    /// Open /dev/usbmonN for reading (0 = all buses)
+    pub fn open(bus: u32, max_payload: usize) -> Result<Self, String> {
+        let path = format!("/dev/usbmon{}", bus);
+        let c_path = CString::new(path.clone()).map_err(|e| e.to_string())?;
+
+        // SAFETY: c_path is a valid NUL-terminated string
+        let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_RDONLY | libc::O_NONBLOCK) };
+        if fd < 0 {
+            return Err(format!("Failed to open {}: {}", path, std::io::Error::last_os_error()));
+        }
+
+        Ok(Self {
+            fd,
+            header: [0u8; USBMON_HEADER_LEN],
+            data: vec![0u8; max_payload],
+        })
+    }
+
+    /// Wait up to `timeout` for the next URB event
+    /// Returns header followed by captured payload, or None on timeout
+    pub fn next_event(&mut self, timeout: Duration) -> Result<Option<Vec<u8>>, String> {
+        let mut pfd = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        // SAFETY: pfd is a valid pollfd for one descriptor
+        let ready = unsafe { libc::poll(&mut pfd, 1, timeout.as_millis() as libc::c_int) };
+        if ready < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() == std::io::ErrorKind::Interrupted {
+                return Ok(None);
+            }
+            return Err(format!("poll failed: {}", error));
+        }
+        if ready == 0 {
+            return Ok(None);
+        }
+
+        let mut get = MonBinGet {
+            hdr: self.header.as_mut_ptr(),
+            data: self.data.as_mut_ptr(),
+            alloc: self.data.len(),
+        };
+
+        // SAFETY: get points to buffers of the advertised sizes owned by self
+        let result = unsafe { libc::ioctl(self.fd, MON_IOCX_GETX as _, &mut get) };
+        if result < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() == std::io::ErrorKind::WouldBlock {
+                return Ok(None);
+            }
+            return Err(format!("MON_IOCX_GETX failed: {}", error));
+        }
+
+        // len_cap (offset 36) is the number of payload bytes copied
+        let len_cap = u32::from_le_bytes([self.header[36], self.header[37], self.header[38], self.header[39]]) as usize;
+        let len_cap = len_cap.min(self.data.len());
+
+        let mut event = Vec::with_capacity(USBMON_HEADER_LEN + len_cap);
+        event.extend_from_slice(&self.header);
+        event.extend_from_slice(&self.data[..len_cap]);
+        Ok(Some(event))
+    }
+
+    /// Events lost because the buffer was full since the previous call
+    pub fn take_dropped(&mut self) -> Result<u64, String> {
+        let mut stats = MonBinStats::default();
+        // SAFETY: stats is a valid mon_bin_stats owned by this frame
+        let result = unsafe { libc::ioctl(self.fd, MON_IOCG_STATS as _, &mut stats) };
+        if result < 0 {
+            return Err(format!("MON_IOCG_STATS failed: {}", std::io::Error::last_os_error()));
+        }
+        Ok(stats.dropped as u64)
+    }
+}
+
+impl Drop for UsbmonReader {
+    fn drop(&mut self) {
+        // SAFETY: fd was opened by us and is closed once
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
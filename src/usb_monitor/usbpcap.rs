@@ -0,0 +1,290 @@
+//! In-process USBPcap capture (Windows)
+//!
+//! USBPcap's filter driver exposes one control device per root hub
+//! (`\\.\USBPcap1`, `\\.\USBPcap2`, ...). Once a capture is set up through the
+//! driver's IOCTLs, reading the control device yields a pcap stream (global
+//! header, then one record per URB), the same stream USBPcapCMD copies to its
+//! output, so no subprocess has to be started and waited for. Filtered devices
+//! are located by walking the ports of each control device's root hub, whose
+//! connection information has the device descriptor and address of every device.
+
+use std::io;
+use std::time::Duration;
+use windows_sys::Win32::Devices::Usb::{
+    DeviceConnected, IOCTL_USB_GET_NODE_CONNECTION_INFORMATION_EX, IOCTL_USB_GET_NODE_CONNECTION_NAME,
+    IOCTL_USB_GET_NODE_INFORMATION, USB_NODE_CONNECTION_INFORMATION_EX, USB_NODE_INFORMATION, USB_PIPE_INFO,
+};
+use windows_sys::Win32::Foundation::{
+    CloseHandle, GetLastError, ERROR_IO_PENDING, ERROR_OPERATION_ABORTED, GENERIC_READ, GENERIC_WRITE, HANDLE,
+    INVALID_HANDLE_VALUE, WAIT_TIMEOUT,
+};
+use windows_sys::Win32::Storage::FileSystem::{
+    CreateFileW, ReadFile, FILE_FLAG_OVERLAPPED, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
+};
+use windows_sys::Win32::System::Threading::{CreateEventW, WaitForSingleObject};
+use windows_sys::Win32::System::IO::{CancelIoEx, DeviceIoControl, GetOverlappedResult, OVERLAPPED};
+
+/// Control devices probed, USBPcap numbers them from 1 per root hub
+const MAX_HUBS: u32 = 16;
+/// Kernel buffer of a capture, USBPcapCMD's default
+const BUFFER_SIZE: u32 = 1024 * 1024;
+const SNAPLEN: u32 = 65535;
+/// Hub ports tried when the hub does not report its port count
+const DEFAULT_PORTS: u32 = 15;
+
+/// CTL_CODE(FILE_DEVICE_UNKNOWN, function, METHOD_BUFFERED, access)
+const fn ctl_code(function: u32, access: u32) -> u32 {
+    (0x22 << 16) | (access << 14) | (function << 2)
+}
+
+const FILE_READ_ACCESS: u32 = 1;
+const FILE_WRITE_ACCESS: u32 = 2;
+const IOCTL_USBPCAP_SETUP_BUFFER: u32 = ctl_code(0x800, FILE_READ_ACCESS);
+const IOCTL_USBPCAP_START_FILTERING: u32 = ctl_code(0x801, FILE_READ_ACCESS | FILE_WRITE_ACCESS);
+const IOCTL_USBPCAP_GET_HUB_SYMLINK: u32 = ctl_code(0x803, 0);
+const IOCTL_USBPCAP_SET_SNAPLEN_SIZE: u32 = ctl_code(0x804, FILE_READ_ACCESS);
+
+/// USBPCAP_IOCTL_SIZE
+#[repr(C, packed(1))]
+struct IoctlSize {
+    size: u32,
+}
+
+/// USBPCAP_ADDRESS_FILTER: one bit per device address, or all devices
+#[repr(C, packed(1))]
+struct AddressFilter {
+    addresses: [u32; 4],
+    filter_all: u8,
+}
+
+/// USB_NODE_CONNECTION_INFORMATION_EX with room for the open pipes the hub driver appends
+#[repr(C)]
+struct ConnectionInformation {
+    info: USB_NODE_CONNECTION_INFORMATION_EX,
+    _pipes: [USB_PIPE_INFO; 30],
+}
+
+/// USB_NODE_CONNECTION_NAME with room for the name
+#[derive(Clone, Copy)]
+#[repr(C, packed(1))]
+struct ConnectionName {
+    connection_index: u32,
+    actual_length: u32,
+    node_name: [u16; 512],
+}
+
+/// An open device handle, closed on drop
+struct Handle(HANDLE);
+
+impl Handle {
+    fn open(path: &str, access: u32, flags: u32) -> io::Result<Self> {
+        let wide: Vec<u16> = path.encode_utf16().chain([0]).collect();
+        // SAFETY: the path is NUL-terminated and outlives the call
+        let handle = unsafe {
+            CreateFileW(
+                wide.as_ptr(),
+                access,
+                FILE_SHARE_READ | FILE_SHARE_WRITE,
+                std::ptr::null(),
+                OPEN_EXISTING,
+                flags,
+                std::ptr::null_mut(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self(handle))
+    }
+
+    /// Synchronous IOCTL with `input` and `output` buffers, returns the bytes written to `output`
+    fn ioctl<I, O>(&self, code: u32, input: Option<&I>, output: Option<&mut O>) -> io::Result<u32> {
+        let (input_ptr, input_len) = match input {
+            Some(input) => ((input as *const I).cast(), std::mem::size_of::<I>() as u32),
+            None => (std::ptr::null(), 0),
+        };
+        let (output_ptr, output_len) = match output {
+            Some(output) => ((output as *mut O).cast(), std::mem::size_of::<O>() as u32),
+            None => (std::ptr::null_mut(), 0),
+        };
+        let mut returned = 0;
+        // SAFETY: the buffers are valid for their sizes for the duration of the call
+        let ok = unsafe {
+            DeviceIoControl(
+                self.0,
+                code,
+                input_ptr,
+                input_len,
+                output_ptr,
+                output_len,
+                &mut returned,
+                std::ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(returned)
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        // SAFETY: the handle was opened by CreateFileW/CreateEventW and is closed once
+        unsafe { CloseHandle(self.0) };
+    }
+}
+
+fn control_device(hub: u32) -> String {
+    format!(r"\\.\USBPcap{}", hub)
+}
+
+/// USBPcap control devices present, in order
+pub fn hubs() -> Vec<u32> {
+    // Opening without access rights is enough to tell the device exists, and does not
+    // conflict with a capture running on it
+    (1..=MAX_HUBS)
+        .filter(|&hub| Handle::open(&control_device(hub), 0, 0).is_ok())
+        .collect()
+}
+
+/// Device path of the root hub a control device filters
+fn root_hub(hub: u32) -> io::Result<String> {
+    let control = Handle::open(&control_device(hub), 0, 0)?;
+    let mut symlink = [0u16; 512];
+    let len = control.ioctl::<(), _>(IOCTL_USBPCAP_GET_HUB_SYMLINK, None, Some(&mut symlink))? as usize / 2;
+    let symlink = String::from_utf16_lossy(&symlink[..len.min(symlink.len())]);
+    let symlink = symlink.trim_end_matches('\0');
+    // The symlink is an NT path (\??\USB#ROOT_HUB...), opened through \\.\ instead
+    Ok(format!(r"\\.\{}", symlink.trim_start_matches(r"\??\")))
+}
+
+/// Address of the device with `vendor_id:product_id` below the hub at `path`, searching downstream hubs too
+fn find_on_hub(path: &str, vendor_id: u16, product_id: u16) -> Option<u16> {
+    let hub = Handle::open(path, GENERIC_WRITE, 0).ok()?;
+    // SAFETY: USB_NODE_INFORMATION is plain data, all zeroes is a valid value
+    let mut node: USB_NODE_INFORMATION = unsafe { std::mem::zeroed() };
+    let request = node;
+    let ports = match hub.ioctl(IOCTL_USB_GET_NODE_INFORMATION, Some(&request), Some(&mut node)) {
+        // SAFETY: the hub driver filled in the hub information of the union
+        Ok(_) => u32::from(unsafe { node.u.HubInformation.HubDescriptor.bNumberOfPorts }),
+        Err(_) => DEFAULT_PORTS,
+    };
+
+    for port in 1..=ports {
+        // SAFETY: plain data, all zeroes is a valid value
+        let mut connection: ConnectionInformation = unsafe { std::mem::zeroed() };
+        connection.info.ConnectionIndex = port;
+        let request = connection.info;
+        if hub.ioctl(IOCTL_USB_GET_NODE_CONNECTION_INFORMATION_EX, Some(&request), Some(&mut connection)).is_err() {
+            continue;
+        }
+        let info = connection.info;
+        if info.ConnectionStatus != DeviceConnected {
+            continue;
+        }
+        let descriptor = info.DeviceDescriptor;
+        if descriptor.idVendor == vendor_id && descriptor.idProduct == product_id {
+            return Some(info.DeviceAddress);
+        }
+        if info.DeviceIsHub != 0 {
+            let mut name = ConnectionName {
+                connection_index: port,
+                actual_length: 0,
+                node_name: [0; 512],
+            };
+            let request = name;
+            if hub.ioctl(IOCTL_USB_GET_NODE_CONNECTION_NAME, Some(&request), Some(&mut name)).is_err() {
+                continue;
+            }
+            let node_name = name.node_name;
+            let len = node_name.iter().position(|&c| c == 0).unwrap_or(node_name.len());
+            let downstream = format!(r"\\.\{}", String::from_utf16_lossy(&node_name[..len]));
+            if let Some(address) = find_on_hub(&downstream, vendor_id, product_id) {
+                return Some(address);
+            }
+        }
+    }
+    None
+}
+
+/// Control device and address of the device with `vendor_id:product_id`
+pub fn find_device(vendor_id: u16, product_id: u16) -> Option<(u32, u16)> {
+    hubs().into_iter().find_map(|hub| {
+        let root = root_hub(hub).ok()?;
+        find_on_hub(&root, vendor_id, product_id).map(|address| (hub, address))
+    })
+}
+
+/// A running capture on a USBPcap control device
+/// Capturing ends when it is dropped.
+pub struct Capture {
+    device: Handle,
+    event: Handle,
+}
+
+// SAFETY: the handles are only used from one thread at a time (moved into the reader thread)
+unsafe impl Send for Capture {}
+
+impl Capture {
+    /// Start capturing the devices at `addresses` on control device `hub`, all its devices if empty
+    pub fn open(hub: u32, addresses: &[u16]) -> io::Result<Self> {
+        let path = control_device(hub);
+        let device = Handle::open(&path, GENERIC_READ | GENERIC_WRITE, FILE_FLAG_OVERLAPPED)
+            .map_err(|e| io::Error::new(e.kind(), format!("cannot open {}: {}", path, e)))?;
+
+        let mut filter = AddressFilter {
+            addresses: [0; 4],
+            filter_all: addresses.is_empty() as u8,
+        };
+        for &address in addresses {
+            let address = usize::from(address.min(127));
+            filter.addresses[address / 32] |= 1 << (address % 32);
+        }
+        // The control device is opened for overlapped I/O, but these requests complete right away
+        device.ioctl::<_, ()>(IOCTL_USBPCAP_SET_SNAPLEN_SIZE, Some(&IoctlSize { size: SNAPLEN }), None)?;
+        device.ioctl::<_, ()>(IOCTL_USBPCAP_SETUP_BUFFER, Some(&IoctlSize { size: BUFFER_SIZE }), None)?;
+        device.ioctl::<_, ()>(IOCTL_USBPCAP_START_FILTERING, Some(&filter), None)?;
+
+        // SAFETY: an unnamed manual-reset event without security attributes
+        let event = unsafe { CreateEventW(std::ptr::null(), 1, 0, std::ptr::null()) };
+        if event.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            device,
+            event: Handle(event),
+        })
+    }
+
+    /// Read the next part of the pcap stream into `buffer`, waiting up to `timeout`
+    /// Returns 0 if nothing was captured in time.
+    pub fn read(&mut self, buffer: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        // SAFETY: OVERLAPPED is plain data, all zeroes is a valid value
+        let mut overlapped: OVERLAPPED = unsafe { std::mem::zeroed() };
+        overlapped.hEvent = self.event.0;
+        let mut read = 0;
+        // SAFETY: the buffer and OVERLAPPED stay valid until the read has completed or
+        // was cancelled and waited for below
+        unsafe {
+            if ReadFile(self.device.0, buffer.as_mut_ptr(), buffer.len() as u32, &mut read, &mut overlapped) != 0 {
+                return Ok(read as usize);
+            }
+            if GetLastError() != ERROR_IO_PENDING {
+                return Err(io::Error::last_os_error());
+            }
+            if WaitForSingleObject(self.event.0, timeout.as_millis() as u32) == WAIT_TIMEOUT {
+                CancelIoEx(self.device.0, &overlapped);
+            }
+            // Waits for the cancellation; a read that completed meanwhile still returns its data
+            if GetOverlappedResult(self.device.0, &overlapped, &mut read, 1) == 0 {
+                let error = io::Error::last_os_error();
+                return match error.raw_os_error() {
+                    Some(code) if code as u32 == ERROR_OPERATION_ABORTED => Ok(0),
+                    _ => Err(error),
+                };
+            }
+        }
+        Ok(read as usize)
+    }
+}
@@ -0,0 +1,156 @@
+//! Run-to-run variance: how deterministic a device is across repeated runs
+//!
+//! `record --repeats N` plays a scenario N times and lines the runs up step by
+//! step: the packet count of each run, the byte offsets that differ between
+//! packets at the same position, and the spread of the step's timing (wire
+//! latency and the time from its first to its last packet). A step whose packets
+//! are identical in every run can be trusted as a golden; one with varying bytes
+//! needs a `--normalize` comparison, variants or a merged golden.
+
+use crate::compare::parse_hex;
+use crate::StepOutput;
+use serde::Serialize;
+
+/// Smallest and largest value across the runs
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Spread {
+    pub min: u64,
+    pub max: u64,
+}
+
+impl Spread {
+    fn of(values: impl Iterator<Item = u64>) -> Option<Self> {
+        values.fold(None, |spread, value| match spread {
+            None => Some(Spread { min: value, max: value }),
+            Some(Spread { min, max }) => Some(Spread {
+                min: min.min(value),
+                max: max.max(value),
+            }),
+        })
+    }
+}
+
+/// Byte offsets of a packet that differ between runs
+#[derive(Debug, Clone, Serialize)]
+pub struct PacketVariation {
+    /// 1-based position of the packet in the step
+    pub packet: usize,
+    /// 0-based offsets; bytes missing from a shorter packet count as differing
+    pub offsets: Vec<usize>,
+}
+
+/// Variability of one step across the runs
+#[derive(Debug, Clone, Serialize)]
+pub struct StepVariance {
+    pub step_index: usize,
+    pub step_name: String,
+    /// Packet count of each run, in run order
+    pub packet_counts: Vec<usize>,
+    /// Packets at positions every run has that differ between runs
+    pub varying_packets: Vec<PacketVariation>,
+    /// Submit-to-wire latency (us), None if no run measured it
+    pub wire_latency_us: Option<Spread>,
+    /// Time from the step's first to its last packet (us), None without timestamps
+    pub span_us: Option<Spread>,
+}
+
+impl StepVariance {
+    /// Same packets in every run; timing may still vary
+    pub fn is_deterministic(&self) -> bool {
+        self.varying_packets.is_empty() && self.packet_counts.windows(2).all(|pair| pair[0] == pair[1])
+    }
+
+    /// One line summary, e.g. "packets 98-103, packet 1 bytes 3, 4 differ, wire latency 610-930 us"
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        let counts = Spread::of(self.packet_counts.iter().map(|&c| c as u64));
+        match counts {
+            Some(Spread { min, max }) if min == max => parts.push(format!("{} packets", min)),
+            Some(Spread { min, max }) => parts.push(format!("packets {}-{}", min, max)),
+            None => {}
+        }
+        if self.is_deterministic() {
+            parts.push("identical in every run".to_string());
+        }
+        for variation in &self.varying_packets {
+            let offsets: Vec<String> = variation.offsets.iter().map(usize::to_string).collect();
+            parts.push(format!("packet {} bytes {} differ", variation.packet, offsets.join(", ")));
+        }
+        if let Some(spread) = self.wire_latency_us {
+            parts.push(format!("wire latency {}-{} us", spread.min, spread.max));
+        }
+        if let Some(spread) = self.span_us {
+            parts.push(format!(
+                "span {:.1}-{:.1} ms",
+                spread.min as f64 / 1000.0,
+                spread.max as f64 / 1000.0
+            ));
+        }
+        parts.join(", ")
+    }
+}
+
+/// Variability of every step across repeated runs of a scenario
+#[derive(Debug, Clone, Serialize)]
+pub struct VarianceReport {
+    pub runs: usize,
+    pub steps: Vec<StepVariance>,
+}
+
+impl VarianceReport {
+    /// Line up the steps of `runs` (each a run's step outputs) by step index
+    pub fn of(runs: &[Vec<StepOutput>]) -> Self {
+        let step_count = runs.iter().map(Vec::len).max().unwrap_or(0);
+        let steps = (0..step_count)
+            .map(|index| {
+                let outputs: Vec<&StepOutput> = runs.iter().filter_map(|run| run.get(index)).collect();
+                step_variance(&outputs)
+            })
+            .collect();
+        Self {
+            runs: runs.len(),
+            steps,
+        }
+    }
+
+    pub fn deterministic_steps(&self) -> usize {
+        self.steps.iter().filter(|s| s.is_deterministic()).count()
+    }
+
+    pub fn print(&self) {
+        println!("\n=== Run-to-run variance ({} runs) ===", self.runs);
+        for step in &self.steps {
+            println!("Step {} ({}): {}", step.step_index, step.step_name, step.describe());
+        }
+        println!("Deterministic steps: {} of {}", self.deterministic_steps(), self.steps.len());
+    }
+}
+
+fn step_variance(outputs: &[&StepOutput]) -> StepVariance {
+    let first = outputs[0];
+    let common = outputs.iter().map(|o| o.packets.len()).min().unwrap_or(0);
+    let varying_packets = (0..common)
+        .filter_map(|i| {
+            let packets: Vec<Vec<u8>> = outputs
+                .iter()
+                .map(|o| parse_hex(&o.packets[i]).unwrap_or_default())
+                .collect();
+            let length = packets.iter().map(Vec::len).max().unwrap_or(0);
+            let offsets: Vec<usize> = (0..length)
+                .filter(|&offset| {
+                    let reference = packets[0].get(offset);
+                    packets.iter().any(|p| p.get(offset) != reference)
+                })
+                .collect();
+            (!offsets.is_empty()).then_some(PacketVariation { packet: i + 1, offsets })
+        })
+        .collect();
+    StepVariance {
+        step_index: first.step_index,
+        step_name: first.step_name.clone(),
+        packet_counts: outputs.iter().map(|o| o.packets.len()).collect(),
+        varying_packets,
+        wire_latency_us: Spread::of(outputs.iter().filter_map(|o| o.wire_latency_us)),
+        span_us: Spread::of(outputs.iter().filter_map(|o| o.timestamps_us.last().copied())),
+    }
+}
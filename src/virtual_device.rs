@@ -0,0 +1,941 @@
+//! Virtual force feedback wheel on Linux uhid or Windows vJoy, to record what games send
+//!
+//! The `virtual-device` command creates a HID device through `/dev/uhid` that
+//! advertises a PID force feedback report descriptor: a built-in wheel, or the
+//! descriptor of a real wheel to pose as. A game that binds to it sends its force
+//! feedback as HID reports, which are recorded as if captured on the wire: output
+//! reports as interrupt OUT packets, SET_REPORT requests as control transfers with
+//! their setup stage. When recording stops, the reports are split into steps at
+//! pauses and saved as a capture file, optionally with a scenario candidate, as
+//! `import` does.
+//!
+//! The device answers the PID handshake itself: a Block Load request gets the next
+//! free effect block index and a PID Pool request the effect capacity, so the
+//! game's driver goes on to upload and play its effects.
+//!
+//! Games under Wine/Proton reach the device through hidraw. Native games use the
+//! kernel's PID driver, which binds to uhid devices only when a driver such as
+//! hid-universal-pidff matches the advertised VID:PID.
+//!
+//! On Windows the device is a vJoy device with force feedback enabled in vJoyConf.
+//! vJoy advertises its own PID descriptor and answers the handshake in its driver;
+//! its FFB receptor (vJoyInterface.dll, loaded at runtime) hands over every report
+//! a game sends, which is recorded the same way. ViGEm is no alternative: it only
+//! emulates gamepads, whose rumble motors are not force feedback.
+
+use crate::cancel::CancellationToken;
+use crate::hid_descriptor::{self, Report, ReportDescriptor, ReportKind, Usage, PID_PAGE};
+use crate::import;
+use crate::monitor::format_packet;
+use crate::normalize::decode_packet_with;
+use crate::usb_monitor::{capture_clock, DeviceFilter, PacketDirection, UsbPacket};
+use crate::{write_capture_file, StepOutput};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// VID:PID advertised by default, the pid.codes test ID
+const DEFAULT_ID: DeviceFilter = DeviceFilter {
+    vendor_id: 0x1209,
+    product_id: 0x0001,
+};
+/// How long to wait for a uhid event before checking for cancellation
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Effect slots when the descriptor does not bound the effect block index
+const DEFAULT_EFFECTS: usize = 40;
+
+/// PID usages of the handshake
+const EFFECT_BLOCK_INDEX: u16 = 0x22;
+const PID_POOL_REPORT: u16 = 0x7F;
+const RAM_POOL_SIZE: u16 = 0x80;
+const SIMULTANEOUS_EFFECTS_MAX: u16 = 0x83;
+const BLOCK_LOAD_REPORT: u16 = 0x89;
+const BLOCK_LOAD_SUCCESS: u16 = 0x8C;
+const BLOCK_LOAD_FULL: u16 = 0x8D;
+const BLOCK_FREE_REPORT: u16 = 0x90;
+const DC_DEVICE_RESET: u16 = 0x9A;
+const DEVICE_MANAGED_POOL: u16 = 0xA9;
+const CREATE_NEW_EFFECT_REPORT: u16 = 0xAB;
+const RAM_POOL_AVAILABLE: u16 = 0xAC;
+
+fn pid(id: u16) -> Usage {
+    Usage { page: PID_PAGE, id }
+}
+
+/// What the virtual device presents itself as
+pub struct VirtualDevice {
+    pub name: String,
+    /// Advertised VID:PID (uhid)
+    pub id: DeviceFilter,
+    /// Raw report descriptor, advertised with uhid and used for decoding; None on
+    /// Windows unless given, since vJoy advertises its own
+    pub descriptor: Option<Vec<u8>>,
+    /// vJoy device receiving the force feedback (Windows)
+    pub vjoy_device: u32,
+}
+
+impl VirtualDevice {
+    /// The built-in wheel, or a device with the report descriptor from `source` (a
+    /// descriptor file or VID:PID of a connected wheel, whose ID it also takes by default)
+    pub fn new(name: String, id: Option<DeviceFilter>, source: Option<&str>, vjoy_device: u32) -> anyhow::Result<Self> {
+        let descriptor = match source {
+            Some(source) => Some(hid_descriptor::load_bytes(source).map_err(anyhow::Error::msg)?),
+            None if cfg!(windows) => None,
+            None => Some(builtin_descriptor()),
+        };
+        let id = id
+            .or_else(|| source.and_then(|source| source.parse().ok()))
+            .unwrap_or(DEFAULT_ID);
+        Ok(Self {
+            name,
+            id,
+            descriptor,
+            vjoy_device,
+        })
+    }
+
+    /// How the device is named in messages, e.g. `virtual device "wheel" (1209:0001)`
+    pub fn label(&self) -> String {
+        if cfg!(windows) {
+            format!("vJoy device {}", self.vjoy_device)
+        } else {
+            format!("virtual device \"{}\" ({})", self.name, self.id)
+        }
+    }
+}
+
+/// Create `device`, record the reports sent to it until `duration` elapses or
+/// `cancel` is cancelled, and save them to `output`, and a scenario candidate
+/// to `scenario` if given. A pause of `gap` starts a new step.
+/// Returns the steps written
+pub fn run(
+    device: &VirtualDevice,
+    output: &PathBuf,
+    scenario: Option<&Path>,
+    gap: Duration,
+    duration: Option<Duration>,
+    cancel: &CancellationToken,
+) -> anyhow::Result<Vec<StepOutput>> {
+    let descriptor = device
+        .descriptor
+        .as_deref()
+        .map(ReportDescriptor::parse)
+        .transpose()
+        .map_err(|e| anyhow::anyhow!("Invalid report descriptor: {}", e))?;
+    if descriptor.as_ref().is_some_and(|descriptor| !descriptor.has_pid()) {
+        println!("WARNING: The report descriptor has no PID reports, games will not send it force feedback");
+    }
+
+    let mut virtual_device = platform::Device::create(device).map_err(anyhow::Error::msg)?;
+    let mut slots = EffectSlots::new(descriptor.as_ref());
+    let capture_start = capture_clock();
+    let started = Instant::now();
+    let mut packets = Vec::new();
+    match duration {
+        Some(duration) => println!(
+            "Recording the force feedback sent to the {} for {} s...",
+            device.label(),
+            duration.as_secs()
+        ),
+        None => println!(
+            "Recording the force feedback sent to the {} until Ctrl+C...",
+            device.label()
+        ),
+    }
+
+    while duration.is_none_or(|duration| started.elapsed() < duration) && !cancel.is_cancelled() {
+        let Some(event) = virtual_device.next_event(POLL_INTERVAL).map_err(anyhow::Error::msg)? else {
+            continue;
+        };
+        let sent = match event {
+            Event::Open => {
+                println!("A game opened the device");
+                None
+            }
+            Event::Close => {
+                println!("The game closed the device");
+                None
+            }
+            Event::Output(data) => Some((ReportKind::Output, report_packet(data, None, packets.len()))),
+            Event::SetReport {
+                id,
+                number,
+                kind,
+                data,
+            } => {
+                virtual_device.reply_set_report(id).map_err(anyhow::Error::msg)?;
+                Some((kind, report_packet(data, Some((number, kind)), packets.len())))
+            }
+            Event::GetReport { id, number, kind } => {
+                let reply = descriptor
+                    .as_ref()
+                    .and_then(|descriptor| slots.reply(descriptor, kind, number));
+                virtual_device
+                    .reply_get_report(id, reply.as_deref())
+                    .map_err(anyhow::Error::msg)?;
+                None
+            }
+            Event::Other => None,
+        };
+        if let Some((kind, packet)) = sent {
+            if let Some(descriptor) = &descriptor {
+                slots.apply(descriptor, kind, report_payload(&packet));
+            }
+            println!("{}", format_packet(&packet, capture_start));
+            if let Some(decoded) = decode_packet_with(&packet.data, descriptor.as_ref()) {
+                println!("    -> {}", decoded);
+            }
+            packets.push(packet);
+        }
+    }
+    drop(virtual_device);
+
+    if packets.is_empty() {
+        anyhow::bail!("No force feedback reports were sent to the virtual device");
+    }
+    let bursts = import::split_bursts(packets, gap);
+    let steps = import::burst_steps(&bursts);
+    let total_packets = write_capture_file(output, &steps)?;
+    println!(
+        "Recorded {} reports in {} steps to {}",
+        total_packets,
+        steps.len(),
+        output.display()
+    );
+
+    if let Some(path) = scenario {
+        let name = output.file_stem().unwrap_or_default().to_string_lossy();
+        let source = format!("the {}", device.label());
+        fs::write(path, import::scenario_candidate(&name, &source, &bursts, descriptor.as_ref()))?;
+        println!("Scenario candidate written to {}", path.display());
+    }
+    Ok(steps)
+}
+
+pub use platform::Device;
+
+/// A uhid event the device reacts to
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub enum Event {
+    Open,
+    Close,
+    /// Output report on the interrupt pipe, starting with the report ID if numbered
+    Output(Vec<u8>),
+    /// SET_REPORT request, answered with `reply_set_report(id)`
+    SetReport {
+        id: u32,
+        number: u8,
+        kind: ReportKind,
+        data: Vec<u8>,
+    },
+    /// GET_REPORT request, answered with `reply_get_report(id, ...)`
+    GetReport { id: u32, number: u8, kind: ReportKind },
+    Other,
+}
+
+/// A report sent to the device as a captured packet
+/// SET_REPORT requests (`request`: report number and kind) get their setup stage
+/// in front, as usbmon shows control transfers
+pub fn report_packet(data: Vec<u8>, request: Option<(u8, ReportKind)>, sequence: usize) -> UsbPacket {
+    let (endpoint, transfer_type, data) = match request {
+        Some((number, kind)) => {
+            let report_type = match kind {
+                ReportKind::Input => 1,
+                ReportKind::Output => 2,
+                ReportKind::Feature => 3,
+            };
+            let length = (data.len() as u16).to_le_bytes();
+            let setup = [0x21, 0x09, number, report_type, 0x00, 0x00, length[0], length[1]];
+            (0, 2, setup.into_iter().chain(data).collect())
+        }
+        None => (0x01, 1, data),
+    };
+    UsbPacket {
+        timestamp: capture_clock(),
+        direction: PacketDirection::HostToDevice,
+        endpoint,
+        data,
+        urb_id: 0,
+        bus: 0,
+        device: 0,
+        transfer_type,
+        sequence: sequence as u64,
+        source: None,
+    }
+}
+
+/// The report of a packet made by `report_packet`, without the setup stage
+fn report_payload(packet: &UsbPacket) -> &[u8] {
+    match packet.transfer_type {
+        2 => &packet.data[8..],
+        _ => &packet.data,
+    }
+}
+
+/// Effect block indexes the game holds, for answering the PID handshake
+struct EffectSlots {
+    used: Vec<bool>,
+    /// Index allocated by the last Create New Effect, reported by the next Block Load
+    pending: Option<usize>,
+}
+
+impl EffectSlots {
+    /// As many slots as the Block Load report's effect block index allows
+    fn new(descriptor: Option<&ReportDescriptor>) -> Self {
+        let capacity = descriptor
+            .and_then(|descriptor| descriptor.find(ReportKind::Feature, pid(BLOCK_LOAD_REPORT)))
+            .and_then(|report| {
+                report
+                    .fields
+                    .iter()
+                    .find(|f| f.variable && f.usages.contains(&pid(EFFECT_BLOCK_INDEX)))
+            })
+            .map_or(DEFAULT_EFFECTS, |field| field.logical_max.clamp(1, 255) as usize);
+        Self {
+            used: vec![false; capacity],
+            pending: None,
+        }
+    }
+
+    /// Track the effects created, freed and reset by a report the game sent
+    fn apply(&mut self, descriptor: &ReportDescriptor, kind: ReportKind, payload: &[u8]) {
+        let Some((report, body)) = descriptor.split(kind, payload) else {
+            return;
+        };
+        if report.usage == Some(pid(CREATE_NEW_EFFECT_REPORT)) {
+            self.pending = self.used.iter().position(|used| !used);
+            if let Some(index) = self.pending {
+                self.used[index] = true;
+            }
+        } else if report.usage == Some(pid(BLOCK_FREE_REPORT)) {
+            if let Some(index) = report.value(body, pid(EFFECT_BLOCK_INDEX)) {
+                if let Some(used) = usize::try_from(index - 1).ok().and_then(|i| self.used.get_mut(i)) {
+                    *used = false;
+                }
+            }
+        } else if report.selections(body).contains(&pid(DC_DEVICE_RESET)) {
+            self.used.fill(false);
+            self.pending = None;
+        }
+    }
+
+    /// Answer to GET_REPORT of report `number`, None if the descriptor has no such report
+    fn reply(&mut self, descriptor: &ReportDescriptor, kind: ReportKind, number: u8) -> Option<Vec<u8>> {
+        let report: &Report = descriptor
+            .reports
+            .iter()
+            .find(|r| r.kind == kind && r.id.is_none_or(|id| id == number))?;
+        let values = if report.usage == Some(pid(BLOCK_LOAD_REPORT)) {
+            match self.pending.take() {
+                Some(index) => vec![
+                    (pid(EFFECT_BLOCK_INDEX), index as i64 + 1),
+                    (pid(BLOCK_LOAD_SUCCESS), 0),
+                    (pid(RAM_POOL_AVAILABLE), 0xFFFF),
+                ],
+                None => vec![(pid(BLOCK_LOAD_FULL), 0)],
+            }
+        } else if report.usage == Some(pid(PID_POOL_REPORT)) {
+            vec![
+                (pid(RAM_POOL_SIZE), 0xFFFF),
+                (pid(SIMULTANEOUS_EFFECTS_MAX), self.used.len() as i64),
+                (pid(DEVICE_MANAGED_POOL), 1),
+            ]
+        } else {
+            Vec::new()
+        };
+        Some(report.encode(&values))
+    }
+}
+
+// Item prefixes of report descriptors (size bits clear)
+const INPUT: u8 = 0x80;
+const OUTPUT: u8 = 0x90;
+const FEATURE: u8 = 0xB0;
+const COLLECTION: u8 = 0xA0;
+const END_COLLECTION: u8 = 0xC0;
+const USAGE_PAGE: u8 = 0x04;
+const LOGICAL_MINIMUM: u8 = 0x14;
+const LOGICAL_MAXIMUM: u8 = 0x24;
+const REPORT_SIZE: u8 = 0x74;
+const REPORT_ID: u8 = 0x84;
+const REPORT_COUNT: u8 = 0x94;
+const USAGE: u8 = 0x08;
+
+const APPLICATION: u32 = 0x01;
+const LOGICAL: u32 = 0x02;
+const GENERIC_DESKTOP_PAGE: u16 = 0x01;
+const BUTTON_PAGE: u16 = 0x09;
+const ORDINAL_PAGE: u16 = 0x0A;
+
+/// Effect types of the built-in wheel: constant, ramp, the periodic waves and the conditions
+const EFFECT_TYPES: [u16; 11] = [0x26, 0x27, 0x30, 0x31, 0x32, 0x33, 0x34, 0x40, 0x41, 0x42, 0x43];
+
+/// Report descriptor writer, each short item with as few data bytes as its value needs
+#[derive(Default)]
+struct DescriptorWriter(Vec<u8>);
+
+impl DescriptorWriter {
+    fn item(&mut self, prefix: u8, data: &[u8]) -> &mut Self {
+        let size = match data.len() {
+            4 => 3,
+            n => n as u8,
+        };
+        self.0.push(prefix | size);
+        self.0.extend_from_slice(data);
+        self
+    }
+
+    fn unsigned(&mut self, prefix: u8, value: u32) -> &mut Self {
+        let len = match value {
+            0..=0xFF => 1,
+            0x100..=0xFFFF => 2,
+            _ => 4,
+        };
+        self.item(prefix, &value.to_le_bytes()[..len])
+    }
+
+    fn signed(&mut self, prefix: u8, value: i32) -> &mut Self {
+        let len = if i8::try_from(value).is_ok() {
+            1
+        } else if i16::try_from(value).is_ok() {
+            2
+        } else {
+            4
+        };
+        self.item(prefix, &value.to_le_bytes()[..len])
+    }
+
+    fn page(&mut self, page: u16) -> &mut Self {
+        self.unsigned(USAGE_PAGE, page as u32)
+    }
+
+    fn report_id(&mut self, id: u8) -> &mut Self {
+        self.unsigned(REPORT_ID, id as u32)
+    }
+
+    /// Open a collection of `kind` named by `usage`
+    fn begin(&mut self, usage: u16, kind: u32) -> &mut Self {
+        self.unsigned(USAGE, usage as u32).unsigned(COLLECTION, kind)
+    }
+
+    fn end(&mut self) -> &mut Self {
+        self.item(END_COLLECTION, &[])
+    }
+
+    /// One variable field of `size` bits per usage, in `min..=max`
+    fn values(&mut self, main: u8, usages: &[u16], min: i32, max: i32, size: u32) -> &mut Self {
+        for usage in usages {
+            self.unsigned(USAGE, *usage as u32);
+        }
+        self.signed(LOGICAL_MINIMUM, min)
+            .signed(LOGICAL_MAXIMUM, max)
+            .unsigned(REPORT_SIZE, size)
+            .unsigned(REPORT_COUNT, usages.len() as u32)
+            .unsigned(main, 0x02)
+    }
+
+    /// A byte selecting one of `usages`, in a logical collection named `usage`
+    fn selection(&mut self, main: u8, usage: u16, usages: &[u16]) -> &mut Self {
+        self.begin(usage, LOGICAL);
+        for usage in usages {
+            self.unsigned(USAGE, *usage as u32);
+        }
+        self.signed(LOGICAL_MINIMUM, 1)
+            .signed(LOGICAL_MAXIMUM, usages.len() as i32)
+            .unsigned(REPORT_SIZE, 8)
+            .unsigned(REPORT_COUNT, 1)
+            .unsigned(main, 0x00)
+            .end()
+    }
+
+    fn padding(&mut self, main: u8, bits: u32) -> &mut Self {
+        self.unsigned(REPORT_SIZE, bits)
+            .unsigned(REPORT_COUNT, 1)
+            .unsigned(main, 0x03)
+    }
+}
+
+/// Report descriptor of the built-in wheel: a steering axis, 8 buttons, and the PID
+/// reports of constant, ramp, periodic and condition effects with envelopes
+fn builtin_descriptor() -> Vec<u8> {
+    let max = DEFAULT_EFFECTS as i32;
+    let mut d = DescriptorWriter::default();
+    d.page(GENERIC_DESKTOP_PAGE).begin(0x04, APPLICATION);
+    d.report_id(1).values(INPUT, &[0x30], -32767, 32767, 16);
+    d.page(BUTTON_PAGE).values(INPUT, &[1, 2, 3, 4, 5, 6, 7, 8], 0, 1, 1);
+
+    d.page(PID_PAGE);
+    // PID State Report
+    d.begin(0x92, LOGICAL)
+        .report_id(2)
+        .values(INPUT, &[0x9F, 0xA0, 0xA4, 0xA5, 0xA6], 0, 1, 1)
+        .padding(INPUT, 3)
+        .values(INPUT, &[0x94], 0, 1, 1)
+        .values(INPUT, &[EFFECT_BLOCK_INDEX], 1, max, 7)
+        .end();
+    // Set Effect Report
+    d.begin(0x21, LOGICAL)
+        .report_id(1)
+        .values(OUTPUT, &[EFFECT_BLOCK_INDEX], 1, max, 8)
+        .selection(OUTPUT, 0x25, &EFFECT_TYPES)
+        .values(OUTPUT, &[0x50, 0x54, 0x51, 0xA7], 0, 32767, 16)
+        .values(OUTPUT, &[0x52], 0, 255, 8)
+        .values(OUTPUT, &[0x53], 0, 8, 8);
+    d.begin(0x55, LOGICAL)
+        .page(GENERIC_DESKTOP_PAGE)
+        .values(OUTPUT, &[0x30], 0, 1, 1)
+        .page(PID_PAGE)
+        .end();
+    d.values(OUTPUT, &[0x56], 0, 1, 1).padding(OUTPUT, 6);
+    d.begin(0x57, LOGICAL)
+        .page(ORDINAL_PAGE)
+        .values(OUTPUT, &[1], 0, 255, 8)
+        .page(PID_PAGE)
+        .end();
+    d.end();
+    // Set Envelope Report
+    d.begin(0x5A, LOGICAL)
+        .report_id(2)
+        .values(OUTPUT, &[EFFECT_BLOCK_INDEX], 1, max, 8)
+        .values(OUTPUT, &[0x5B, 0x5D], 0, 10000, 16)
+        .values(OUTPUT, &[0x5C, 0x5E], 0, 32767, 16)
+        .end();
+    // Set Condition Report
+    d.begin(0x5F, LOGICAL)
+        .report_id(3)
+        .values(OUTPUT, &[EFFECT_BLOCK_INDEX], 1, max, 8)
+        .values(OUTPUT, &[0x23], 0, 1, 4)
+        .padding(OUTPUT, 4)
+        .values(OUTPUT, &[0x60, 0x61, 0x62], -10000, 10000, 16)
+        .values(OUTPUT, &[0x63, 0x64, 0x65], 0, 10000, 16)
+        .end();
+    // Set Periodic Report
+    d.begin(0x6E, LOGICAL)
+        .report_id(4)
+        .values(OUTPUT, &[EFFECT_BLOCK_INDEX], 1, max, 8)
+        .values(OUTPUT, &[0x70], 0, 10000, 16)
+        .values(OUTPUT, &[0x6F], -10000, 10000, 16)
+        .values(OUTPUT, &[0x71], 0, 35999, 16)
+        .values(OUTPUT, &[0x72], 0, 32767, 16)
+        .end();
+    // Set Constant Force Report
+    d.begin(0x73, LOGICAL)
+        .report_id(5)
+        .values(OUTPUT, &[EFFECT_BLOCK_INDEX], 1, max, 8)
+        .values(OUTPUT, &[0x70], -10000, 10000, 16)
+        .end();
+    // Set Ramp Force Report
+    d.begin(0x74, LOGICAL)
+        .report_id(6)
+        .values(OUTPUT, &[EFFECT_BLOCK_INDEX], 1, max, 8)
+        .values(OUTPUT, &[0x75, 0x76], -10000, 10000, 16)
+        .end();
+    // Effect Operation Report: start, start solo, stop
+    d.begin(0x77, LOGICAL)
+        .report_id(10)
+        .values(OUTPUT, &[EFFECT_BLOCK_INDEX], 1, max, 8)
+        .selection(OUTPUT, 0x78, &[0x79, 0x7A, 0x7B])
+        .values(OUTPUT, &[0x7C], 0, 255, 8)
+        .end();
+    // Block Free Report
+    d.begin(BLOCK_FREE_REPORT, LOGICAL)
+        .report_id(11)
+        .values(OUTPUT, &[EFFECT_BLOCK_INDEX], 1, max, 8)
+        .end();
+    // Device Control Report: enable/disable actuators, stop all, reset, pause, continue
+    d.begin(0x95, LOGICAL)
+        .report_id(12)
+        .selection(OUTPUT, 0x96, &[0x97, 0x98, 0x99, DC_DEVICE_RESET, 0x9B, 0x9C])
+        .end();
+    // Device Gain Report
+    d.begin(0x7D, LOGICAL)
+        .report_id(13)
+        .values(OUTPUT, &[0x7E], 0, 255, 8)
+        .end();
+    // Create New Effect Report
+    d.begin(CREATE_NEW_EFFECT_REPORT, LOGICAL)
+        .report_id(0x11)
+        .selection(FEATURE, 0x25, &EFFECT_TYPES)
+        .end();
+    // Block Load Report
+    d.begin(BLOCK_LOAD_REPORT, LOGICAL)
+        .report_id(0x12)
+        .values(FEATURE, &[EFFECT_BLOCK_INDEX], 1, max, 8)
+        .selection(FEATURE, 0x8B, &[BLOCK_LOAD_SUCCESS, BLOCK_LOAD_FULL, 0x8E])
+        .values(FEATURE, &[RAM_POOL_AVAILABLE], 0, 0xFFFF, 16)
+        .end();
+    // PID Pool Report
+    d.begin(PID_POOL_REPORT, LOGICAL)
+        .report_id(0x13)
+        .values(FEATURE, &[RAM_POOL_SIZE], 0, 0xFFFF, 16)
+        .values(FEATURE, &[SIMULTANEOUS_EFFECTS_MAX], 0, 255, 8)
+        .values(FEATURE, &[DEVICE_MANAGED_POOL, 0xAA], 0, 1, 1)
+        .padding(FEATURE, 6)
+        .end();
+    d.end();
+    d.0
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    //! The `/dev/uhid` character device: fixed-size `struct uhid_event` records
+    //! (linux/uhid.h), a 32-bit type followed by the packed request
+
+    use super::{Event, VirtualDevice};
+    use crate::hid_descriptor::ReportKind;
+    use std::fs::{File, OpenOptions};
+    use std::io::{ErrorKind, Read, Write};
+    use std::os::fd::AsRawFd;
+    use std::time::Duration;
+
+    const UHID_PATH: &str = "/dev/uhid";
+    /// sizeof(struct uhid_event): the type and the largest request, UHID_CREATE2
+    const EVENT_SIZE: usize = 4376;
+    /// Capacity of report data and descriptors in events
+    const DATA_MAX: usize = 4096;
+    const BUS_USB: u16 = 0x03;
+
+    const UHID_DESTROY: u32 = 1;
+    const UHID_OPEN: u32 = 4;
+    const UHID_CLOSE: u32 = 5;
+    const UHID_OUTPUT: u32 = 6;
+    const UHID_GET_REPORT: u32 = 9;
+    const UHID_GET_REPORT_REPLY: u32 = 10;
+    const UHID_CREATE2: u32 = 11;
+    const UHID_INPUT2: u32 = 12;
+    const UHID_SET_REPORT: u32 = 13;
+    const UHID_SET_REPORT_REPLY: u32 = 14;
+
+    pub struct Device {
+        file: File,
+    }
+
+    fn event(kind: u32) -> Vec<u8> {
+        let mut event = vec![0u8; EVENT_SIZE];
+        event[..4].copy_from_slice(&kind.to_ne_bytes());
+        event
+    }
+
+    fn u16_at(event: &[u8], offset: usize) -> u16 {
+        u16::from_ne_bytes([event[offset], event[offset + 1]])
+    }
+
+    fn u32_at(event: &[u8], offset: usize) -> u32 {
+        u32::from_ne_bytes([event[offset], event[offset + 1], event[offset + 2], event[offset + 3]])
+    }
+
+    fn report_kind(rtype: u8) -> ReportKind {
+        match rtype {
+            0 => ReportKind::Feature,
+            1 => ReportKind::Output,
+            _ => ReportKind::Input,
+        }
+    }
+
+    impl Device {
+        /// Create the HID device, on the USB bus so it is treated like a wheel
+        pub fn create(device: &VirtualDevice) -> Result<Self, String> {
+            let descriptor = device.descriptor.as_deref().ok_or("uhid devices need a report descriptor")?;
+            if descriptor.len() > DATA_MAX {
+                return Err(format!("The report descriptor exceeds {} bytes", DATA_MAX));
+            }
+            let file = OpenOptions::new().read(true).write(true).open(UHID_PATH).map_err(|e| {
+                let hint = match e.kind() {
+                    ErrorKind::PermissionDenied => "run as root or grant access with a udev rule",
+                    _ => "is the uhid kernel module loaded?",
+                };
+                format!("Failed to open {}: {} ({})", UHID_PATH, e, hint)
+            })?;
+            let mut uhid = Self { file };
+
+            let mut create = event(UHID_CREATE2);
+            // name[128], phys[64] and uniq[64] are left empty except for the name
+            let name = &device.name.as_bytes()[..device.name.len().min(127)];
+            create[4..4 + name.len()].copy_from_slice(name);
+            create[260..262].copy_from_slice(&(descriptor.len() as u16).to_ne_bytes());
+            create[262..264].copy_from_slice(&BUS_USB.to_ne_bytes());
+            create[264..268].copy_from_slice(&(device.id.vendor_id as u32).to_ne_bytes());
+            create[268..272].copy_from_slice(&(device.id.product_id as u32).to_ne_bytes());
+            create[272..276].copy_from_slice(&0x0100u32.to_ne_bytes());
+            create[280..280 + descriptor.len()].copy_from_slice(descriptor);
+            uhid.write(&create)?;
+            Ok(uhid)
+        }
+
+        /// The next event, None if none arrives within `timeout`
+        pub fn next_event(&mut self, timeout: Duration) -> Result<Option<Event>, String> {
+            let mut pfd = libc::pollfd {
+                fd: self.file.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: pfd is a valid pollfd for the duration of the call
+            let ready = unsafe { libc::poll(&mut pfd, 1, timeout.as_millis() as libc::c_int) };
+            if ready <= 0 {
+                return Ok(None);
+            }
+
+            let mut event = vec![0u8; EVENT_SIZE];
+            let len = self
+                .file
+                .read(&mut event)
+                .map_err(|e| format!("Failed to read {}: {}", UHID_PATH, e))?;
+            if len < 4 {
+                return Ok(None);
+            }
+            Ok(Some(match u32_at(&event, 0) {
+                UHID_OPEN => Event::Open,
+                UHID_CLOSE => Event::Close,
+                UHID_OUTPUT => {
+                    let size = (u16_at(&event, 4 + DATA_MAX) as usize).min(DATA_MAX);
+                    Event::Output(event[4..4 + size].to_vec())
+                }
+                UHID_GET_REPORT => Event::GetReport {
+                    id: u32_at(&event, 4),
+                    number: event[8],
+                    kind: report_kind(event[9]),
+                },
+                UHID_SET_REPORT => {
+                    let size = (u16_at(&event, 10) as usize).min(DATA_MAX);
+                    Event::SetReport {
+                        id: u32_at(&event, 4),
+                        number: event[8],
+                        kind: report_kind(event[9]),
+                        data: event[12..12 + size].to_vec(),
+                    }
+                }
+                _ => Event::Other,
+            }))
+        }
+
+        /// Answer GET_REPORT request `id` with `report`, or an I/O error if None
+        pub fn reply_get_report(&mut self, id: u32, report: Option<&[u8]>) -> Result<(), String> {
+            let mut reply = event(UHID_GET_REPORT_REPLY);
+            reply[4..8].copy_from_slice(&id.to_ne_bytes());
+            match report {
+                Some(report) => {
+                    let report = &report[..report.len().min(DATA_MAX)];
+                    reply[10..12].copy_from_slice(&(report.len() as u16).to_ne_bytes());
+                    reply[12..12 + report.len()].copy_from_slice(report);
+                }
+                None => reply[8..10].copy_from_slice(&(libc::EIO as u16).to_ne_bytes()),
+            }
+            self.write(&reply)
+        }
+
+        /// Acknowledge SET_REPORT request `id`
+        pub fn reply_set_report(&mut self, id: u32) -> Result<(), String> {
+            let mut reply = event(UHID_SET_REPORT_REPLY);
+            reply[4..8].copy_from_slice(&id.to_ne_bytes());
+            self.write(&reply)
+        }
+
+        /// Send input report `report`, starting with the report ID if numbered, to the game
+        pub fn send_input(&mut self, report: &[u8]) -> Result<(), String> {
+            let report = &report[..report.len().min(DATA_MAX)];
+            let mut input = event(UHID_INPUT2);
+            input[4..6].copy_from_slice(&(report.len() as u16).to_ne_bytes());
+            input[6..6 + report.len()].copy_from_slice(report);
+            self.write(&input)
+        }
+
+        fn write(&mut self, event: &[u8]) -> Result<(), String> {
+            self.file
+                .write_all(event)
+                .map_err(|e| format!("Failed to write {}: {}", UHID_PATH, e))
+        }
+    }
+
+    impl Drop for Device {
+        fn drop(&mut self) {
+            let _ = self.write(&event(UHID_DESTROY));
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    //! vJoy's force feedback receptor: vJoyInterface.dll calls back with every report
+    //! a game sends to a vJoy device, as FFB_DATA (size, IOCTL and report bytes)
+
+    use super::{Event, VirtualDevice};
+    use crate::hid_descriptor::ReportKind;
+    use libloading::Library;
+    use std::ffi::{c_int, c_uint, c_ulong, c_void};
+    use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    const LIBRARY_NAMES: &[&str] = &["vJoyInterface.dll", r"C:\Program Files\vJoy\x64\vJoyInterface.dll"];
+    /// FFB_DATA's size counts its size and cmd fields too
+    const HEADER_SIZE: usize = 8;
+    const IOCTL_HID_SET_FEATURE: c_ulong = 0xB0191;
+    const IOCTL_HID_WRITE_REPORT: c_ulong = 0xB000F;
+    const VJD_STAT_OWN: c_int = 0;
+    const VJD_STAT_FREE: c_int = 1;
+
+    #[repr(C)]
+    struct FfbData {
+        size: c_ulong,
+        cmd: c_ulong,
+        data: *const u8,
+    }
+
+    type EnabledFn = unsafe extern "C" fn() -> c_int;
+    type StatusFn = unsafe extern "C" fn(c_uint) -> c_int;
+    type AcquireFn = unsafe extern "C" fn(c_uint) -> c_int;
+    type RelinquishFn = unsafe extern "C" fn(c_uint);
+    type IsFfbFn = unsafe extern "C" fn(c_uint) -> c_int;
+    type FfbCallback = extern "system" fn(*const FfbData, *mut c_void);
+    type RegisterFn = unsafe extern "C" fn(FfbCallback, *mut c_void);
+
+    /// Where the callback delivers reports (IOCTL and bytes); vJoy keeps one
+    /// callback per process, so the sink is process-wide
+    static SINK: Mutex<Option<Sender<(c_ulong, Vec<u8>)>>> = Mutex::new(None);
+
+    extern "system" fn on_ffb(packet: *const FfbData, _user: *mut c_void) {
+        // SAFETY: vJoy passes a valid FFB_DATA whose data holds size - 8 bytes
+        let Some(packet) = (unsafe { packet.as_ref() }) else {
+            return;
+        };
+        let len = (packet.size as usize).saturating_sub(HEADER_SIZE);
+        if packet.data.is_null() || len == 0 {
+            return;
+        }
+        let data = unsafe { std::slice::from_raw_parts(packet.data, len) }.to_vec();
+        if let Some(sink) = SINK.lock().unwrap().as_ref() {
+            let _ = sink.send((packet.cmd, data));
+        }
+    }
+
+    pub struct Device {
+        device: c_uint,
+        relinquish: RelinquishFn,
+        reports: Receiver<(c_ulong, Vec<u8>)>,
+        // Keeps the function pointers and the callback valid
+        _library: Library,
+    }
+
+    fn load() -> Result<Library, String> {
+        let mut last_error = String::new();
+        for name in LIBRARY_NAMES {
+            // SAFETY: loading vJoyInterface runs no initialization code with preconditions
+            match unsafe { Library::new(name) } {
+                Ok(library) => return Ok(library),
+                Err(e) => last_error = e.to_string(),
+            }
+        }
+        Err(format!("vJoyInterface.dll not found, is vJoy installed? ({})", last_error))
+    }
+
+    impl Device {
+        /// Acquire the vJoy device and receive the force feedback sent to it
+        pub fn create(device: &VirtualDevice) -> Result<Self, String> {
+            let library = load()?;
+            let id = device.vjoy_device;
+            // SAFETY: symbol types match vJoyInterface.h
+            unsafe {
+                let enabled = *library.get::<EnabledFn>(b"vJoyEnabled\0").map_err(|e| e.to_string())?;
+                let status = *library.get::<StatusFn>(b"GetVJDStatus\0").map_err(|e| e.to_string())?;
+                let acquire = *library.get::<AcquireFn>(b"AcquireVJD\0").map_err(|e| e.to_string())?;
+                let relinquish = *library.get::<RelinquishFn>(b"RelinquishVJD\0").map_err(|e| e.to_string())?;
+                let is_ffb = *library.get::<IsFfbFn>(b"IsDeviceFfb\0").map_err(|e| e.to_string())?;
+                let register = *library.get::<RegisterFn>(b"FfbRegisterGenCB\0").map_err(|e| e.to_string())?;
+
+                if enabled() == 0 {
+                    return Err("vJoy is not enabled".to_string());
+                }
+                match status(id) {
+                    VJD_STAT_OWN | VJD_STAT_FREE => {}
+                    other => {
+                        return Err(format!("vJoy device {} is not available (status {}), check vJoyConf", id, other))
+                    }
+                }
+                if is_ffb(id) == 0 {
+                    return Err(format!("vJoy device {} has no force feedback, enable it in vJoyConf", id));
+                }
+                if acquire(id) == 0 {
+                    return Err(format!("Failed to acquire vJoy device {}", id));
+                }
+
+                let (sender, reports) = mpsc::channel();
+                *SINK.lock().unwrap() = Some(sender);
+                register(on_ffb, std::ptr::null_mut());
+                Ok(Self {
+                    device: id,
+                    relinquish,
+                    reports,
+                    _library: library,
+                })
+            }
+        }
+
+        /// The next report, None if none arrives within `timeout`
+        pub fn next_event(&mut self, timeout: Duration) -> Result<Option<Event>, String> {
+            match self.reports.recv_timeout(timeout) {
+                Ok((IOCTL_HID_WRITE_REPORT, data)) => Ok(Some(Event::Output(data))),
+                Ok((IOCTL_HID_SET_FEATURE, data)) => Ok(Some(Event::SetReport {
+                    id: 0,
+                    number: data[0],
+                    kind: ReportKind::Feature,
+                    data,
+                })),
+                Ok(_) => Ok(Some(Event::Other)),
+                Err(RecvTimeoutError::Timeout) => Ok(None),
+                Err(RecvTimeoutError::Disconnected) => Err("vJoy stopped delivering force feedback".to_string()),
+            }
+        }
+
+        /// vJoy's driver answers requests itself
+        pub fn reply_get_report(&mut self, _id: u32, _report: Option<&[u8]>) -> Result<(), String> {
+            Ok(())
+        }
+
+        pub fn reply_set_report(&mut self, _id: u32) -> Result<(), String> {
+            Ok(())
+        }
+
+        /// vJoy takes axis and button values, not raw input reports
+        pub fn send_input(&mut self, _report: &[u8]) -> Result<(), String> {
+            Err("vJoy devices cannot pass on input reports".to_string())
+        }
+    }
+
+    impl Drop for Device {
+        fn drop(&mut self) {
+            *SINK.lock().unwrap() = None;
+            // SAFETY: the device was acquired in create
+            unsafe { (self.relinquish)(self.device) };
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+mod platform {
+    use super::{Event, VirtualDevice};
+    use std::time::Duration;
+
+    pub struct Device;
+
+    impl Device {
+        pub fn create(_device: &VirtualDevice) -> Result<Self, String> {
+            Err("Virtual devices need Linux (uhid) or Windows (vJoy)".to_string())
+        }
+
+        pub fn next_event(&mut self, _timeout: Duration) -> Result<Option<Event>, String> {
+            Ok(None)
+        }
+
+        pub fn reply_get_report(&mut self, _id: u32, _report: Option<&[u8]>) -> Result<(), String> {
+            Ok(())
+        }
+
+        pub fn reply_set_report(&mut self, _id: u32) -> Result<(), String> {
+            Ok(())
+        }
+
+        pub fn send_input(&mut self, _report: &[u8]) -> Result<(), String> {
+            Err("Virtual devices need Linux (uhid) or Windows (vJoy)".to_string())
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Last-resort stop of the device's effects on an abnormal exit
+//!
+//! A panic or a forced exit in the middle of a step skips `stop_all_effects`, and a
+//! direct drive base keeps playing its last effect, possibly a full constant torque.
+//! Drivers register a stop action for as long as they have a device open; [`stop_all`]
+//! runs them, and [`install_panic_hook`] makes every panic call it before anything
+//! else. The CLI also calls it before exiting on a second Ctrl+C or a termination
+//! signal. Library users that don't unwind to `shutdown` should install the hook too.
+
+use std::panic;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, Once};
+
+type StopAction = Box<dyn Fn() + Send + Sync>;
+
+/// Registered stop actions by guard ID
+static STOPS: Mutex<Vec<(u64, StopAction)>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static PANIC_HOOK: Once = Once::new();
+
+/// Keeps a stop action registered; dropping it unregisters the action, so drop it
+/// before the device handle the action uses is closed
+pub struct StopGuard(u64);
+
+impl Drop for StopGuard {
+    fn drop(&mut self) {
+        let mut stops = STOPS.lock().unwrap_or_else(|e| e.into_inner());
+        stops.retain(|(id, _)| *id != self.0);
+    }
+}
+
+/// Register `stop` to be run by `stop_all` until the guard is dropped
+/// It may run on any thread, also while the driver is in the middle of a call.
+pub fn register(stop: impl Fn() + Send + Sync + 'static) -> StopGuard {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    STOPS.lock().unwrap_or_else(|e| e.into_inner()).push((id, Box::new(stop)));
+    StopGuard(id)
+}
+
+/// Stop the effects of every registered driver
+/// Guards can't be dropped meanwhile, so no device is closed under a running action.
+pub fn stop_all() {
+    let stops = STOPS.lock().unwrap_or_else(|e| e.into_inner());
+    for (_, stop) in stops.iter() {
+        stop();
+    }
+}
+
+/// Make every panic stop all effects before the previous hook reports it
+/// Installing it again does nothing.
+pub fn install_panic_hook() {
+    PANIC_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            stop_all();
+            previous(info);
+        }));
+    });
+}
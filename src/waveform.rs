@@ -0,0 +1,110 @@
+//! Small ASCII plots of force over time for terminal output
+//!
+//! During playback the console shows the force a periodic or ramp step asks for
+//! before it plays, and after capture the force its packets actually commanded
+//! (decoded by [`force_curve`](crate::force_curve)), so a wrong period, sign or
+//! envelope in a scenario stands out without opening a plotting tool.
+
+use crate::effects::Effect;
+use crate::force_curve::ForceCurve;
+
+/// Plot size in characters
+const WIDTH: usize = 60;
+const HEIGHT: usize = 7;
+
+/// Time shown for effects of infinite duration (ms)
+const INFINITE_PREVIEW_MS: f64 = 1000.0;
+
+/// Plot of the force a periodic or ramp effect asks for over its duration,
+/// None for other effects
+pub fn intended(effect: &Effect) -> Option<Vec<String>> {
+    if !matches!(effect, Effect::Periodic { .. } | Effect::Ramp { .. }) {
+        return None;
+    }
+    let end = match effect.duration() {
+        0 => effect.start_delay() as f64 + INFINITE_PREVIEW_MS,
+        duration => (effect.start_delay() + duration) as f64,
+    };
+    let points: Vec<(f64, f64)> = (0..WIDTH)
+        .map(|column| {
+            let t = end * column as f64 / (WIDTH - 1) as f64;
+            (t, effect.force_at(t).unwrap_or(0.0))
+        })
+        .collect();
+    Some(plot(&points))
+}
+
+/// Plot of the commanded forces of a step, None with fewer than two timed samples
+pub fn commanded(curve: &ForceCurve) -> Option<Vec<String>> {
+    let points: Vec<(f64, f64)> = curve
+        .samples
+        .iter()
+        .filter_map(|sample| Some((sample.time_ms?, sample.force)))
+        .collect();
+    (points.len() >= 2).then(|| plot(&points))
+}
+
+/// Plot `points` (time in ms, force), sorted by time; each column shows the
+/// latest value at its time, as a device holds the last commanded force
+pub fn plot(points: &[(f64, f64)]) -> Vec<String> {
+    let (Some(&(start, _)), Some(&(end, _))) = (points.first(), points.last()) else {
+        return Vec::new();
+    };
+    let max = points.iter().map(|&(_, v)| v).fold(0.0, f64::max);
+    let min = points.iter().map(|&(_, v)| v).fold(0.0, f64::min);
+    let row_of = |value: f64| {
+        if max > min {
+            ((max - value) / (max - min) * (HEIGHT - 1) as f64).round() as usize
+        } else {
+            HEIGHT / 2
+        }
+    };
+
+    let mut grid = vec![vec![' '; WIDTH]; HEIGHT];
+    let zero = row_of(0.0);
+    grid[zero].fill('-');
+    let mut previous: Option<usize> = None;
+    let mut next = 0;
+    for column in 0..WIDTH {
+        let t = start + (end - start) * column as f64 / (WIDTH - 1) as f64;
+        while next < points.len() && points[next].0 <= t {
+            next += 1;
+        }
+        let Some(&(_, value)) = points.get(next.wrapping_sub(1)) else {
+            continue;
+        };
+        let row = row_of(value);
+        if let Some(previous) = previous.filter(|previous| previous.abs_diff(row) > 1) {
+            for cells in &mut grid[previous.min(row) + 1..previous.max(row)] {
+                cells[column] = '|';
+            }
+        }
+        grid[row][column] = '*';
+        previous = Some(row);
+    }
+
+    let labels: Vec<String> = (0..HEIGHT)
+        .map(|row| match row {
+            0 => format!("{:.0}", max),
+            _ if row == HEIGHT - 1 => format!("{:.0}", min),
+            _ if row == zero => "0".to_string(),
+            _ => String::new(),
+        })
+        .collect();
+    let label_width = labels.iter().map(String::len).max().unwrap_or(0);
+    let mut lines: Vec<String> = grid
+        .iter()
+        .zip(&labels)
+        .map(|(cells, label)| format!("{:>label_width$} |{}", label, cells.iter().collect::<String>()))
+        .collect();
+    let first = format!("{:.0} ms", start);
+    let last = format!("{:.0} ms", end);
+    lines.push(format!(
+        "{:label_width$}  {}{:>width$}",
+        "",
+        first,
+        last,
+        width = WIDTH.saturating_sub(first.len())
+    ));
+    lines
+}
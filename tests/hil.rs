@@ -0,0 +1,6 @@
+//! Scenario fixtures under `tests/fixtures` as tests: played through the simulated
+//! SIMAGIC driver, or on the device with `FFB_HIL=1` (see `ffb_replay::harness`)
+
+fn main() -> std::process::ExitCode {
+    ffb_replay::harness::main(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures"))
+}